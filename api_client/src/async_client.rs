@@ -0,0 +1,156 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Asynchronous client of the HTTP API, for applications running on tokio.
+//!
+//! The client keeps its connection to the VMM socket open between requests,
+//! bounds every request with a timeout, and retries the requests failing on
+//! the connection with an exponential backoff. Dropping a request future
+//! cancels it, and the connection it was using is closed so that the next
+//! request doesn't read a stale response.
+
+use crate::{get_response_length, parse_http_response, Error};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout};
+
+/// Behavior of the client on slow or failing requests. Only the requests
+/// failing on the socket are retried, as a timed out request may still be
+/// processed by the VMM.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientConfig {
+    /// Time given to each attempt of a request, connection included.
+    pub timeout: Duration,
+    /// Number of attempts after the first one failed on the connection.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every following one.
+    pub backoff: Duration,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+pub struct AsyncApiClient {
+    path: PathBuf,
+    config: ClientConfig,
+    connection: Mutex<Option<UnixStream>>,
+}
+
+impl AsyncApiClient {
+    /// Client of the HTTP API listening on the UNIX socket `path`. The
+    /// connection is only opened by the first request.
+    pub fn new(path: impl Into<PathBuf>, config: ClientConfig) -> Self {
+        AsyncApiClient {
+            path: path.into(),
+            config,
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// Make an API request using the fully qualified command name, such as
+    /// "vm.info" or "vmm.ping", and return the response body.
+    pub async fn request(
+        &self,
+        method: &str,
+        full_command: &str,
+        request_body: Option<&str>,
+    ) -> Result<Option<String>, Error> {
+        let request = http_request(method, full_command, request_body);
+
+        // Requests are serialized on the shared connection.
+        let mut connection = self.connection.lock().await;
+        let mut backoff = self.config.backoff;
+        let mut attempt = 0;
+        loop {
+            // The connection is only given back once the response has been
+            // read entirely, so that a cancelled or failed request doesn't
+            // leave a partial response behind.
+            let stream = connection.take();
+            let result = timeout(self.config.timeout, self.send(stream, &request)).await;
+
+            let error = match result {
+                Ok(Ok((stream, response))) => {
+                    *connection = Some(stream);
+                    return parse_http_response(&mut response.as_bytes());
+                }
+                Ok(Err(e)) => e,
+                Err(_) => Error::Timeout,
+            };
+
+            if attempt == self.config.retries || !error.is_retryable() {
+                return Err(error);
+            }
+            attempt += 1;
+            sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+
+    /// Same as [`AsyncApiClient::request`] for the VM commands, e.g. "info".
+    pub async fn vm_request(
+        &self,
+        method: &str,
+        c: &str,
+        request_body: Option<&str>,
+    ) -> Result<Option<String>, Error> {
+        self.request(method, &format!("vm.{c}"), request_body).await
+    }
+
+    async fn send(
+        &self,
+        stream: Option<UnixStream>,
+        request: &[u8],
+    ) -> Result<(UnixStream, String), Error> {
+        let mut stream = match stream {
+            Some(stream) => stream,
+            None => UnixStream::connect(&self.path)
+                .await
+                .map_err(Error::Socket)?,
+        };
+
+        stream.write_all(request).await.map_err(Error::Socket)?;
+
+        let mut response = String::new();
+        loop {
+            let mut bytes = vec![0; 256];
+            let count = stream.read(&mut bytes).await.map_err(Error::Socket)?;
+            // The VMM closed the connection, possibly an idle one.
+            if count == 0 {
+                return Err(Error::Socket(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            response.push_str(std::str::from_utf8(&bytes[0..count]).unwrap());
+
+            if let Some(length) = get_response_length(&response)? {
+                if response.len() >= length {
+                    return Ok((stream, response));
+                }
+            }
+        }
+    }
+}
+
+fn http_request(method: &str, full_command: &str, request_body: Option<&str>) -> Vec<u8> {
+    let mut request =
+        format!("{method} /api/v1/{full_command} HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n");
+    if let Some(request_body) = request_body {
+        request.push_str(&format!("Content-Length: {}\r\n", request_body.len()));
+    }
+    request.push_str("\r\n");
+    if let Some(request_body) = request_body {
+        request.push_str(request_body);
+    }
+
+    request.into_bytes()
+}
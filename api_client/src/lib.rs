@@ -3,6 +3,9 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+#[cfg(feature = "tokio")]
+pub mod async_client;
+
 use std::io::{Read, Write};
 use std::os::unix::io::RawFd;
 use thiserror::Error;
@@ -22,6 +25,16 @@ pub enum Error {
     ContentLengthParsing(std::num::ParseIntError),
     #[error("Server responded with an error: {0:?}")]
     ServerResponse(StatusCode, Option<String>),
+    #[error("Timed out waiting for the server response")]
+    Timeout,
+}
+
+impl Error {
+    /// Whether the request may be retried on a new connection, the server
+    /// not having processed it.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::Socket(_))
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -80,6 +93,22 @@ fn get_status_code(res: &str) -> Result<StatusCode, Error> {
     }
 }
 
+// Length of the whole response, once all its headers have been received.
+#[cfg(feature = "tokio")]
+fn get_response_length(res: &str) -> Result<Option<usize>, Error> {
+    let Some(o) = res.find("\r\n\r\n") else {
+        return Ok(None);
+    };
+    let body_offset = o + "\r\n\r\n".len();
+
+    let content_length = match get_header(res, "Content-Length") {
+        Some(length) => length.trim().parse().map_err(Error::ContentLengthParsing)?,
+        None => 0,
+    };
+
+    Ok(Some(body_offset + content_length))
+}
+
 fn parse_http_response(socket: &mut dyn Read) -> Result<Option<String>, Error> {
     let mut res = String::new();
     let mut body_offset = None;
@@ -49,6 +49,15 @@ const FIRST_VCPU_PHANDLE: u32 = 8;
 const L2_CACHE_PHANDLE: u32 = 6;
 // This is a value for uniquely identifying the FDT node containing the L3 cache info
 const L3_CACHE_PHANDLE: u32 = 7;
+/// Labels of the nodes of the device tree which device tree overlays can
+/// reference, along with their phandle.
+pub const FDT_SYMBOLS: [(&str, u32); 4] = [
+    ("gic", GIC_PHANDLE),
+    ("msi", MSI_PHANDLE),
+    ("clock", CLOCK_PHANDLE),
+    ("gpio", GPIO_PHANDLE),
+];
+
 // Read the documentation specified when appending the root node to the FDT.
 const ADDRESS_CELLS: u32 = 0x2;
 const SIZE_CELLS: u32 = 0x2;
@@ -293,7 +302,7 @@ fn create_cpu_nodes(
 
     let num_cpus = vcpu_mpidr.len();
     let (threads_per_core, cores_per_package, packages) = vcpu_topology.unwrap_or((1, 1, 1));
-    let max_cpus: u32 = (threads_per_core * cores_per_package * packages).into();
+    let max_cpus = u32::from(threads_per_core) * u32::from(cores_per_package) * u32::from(packages);
 
     // Add cache info.
     // L1 Data Cache Info.
@@ -373,7 +382,7 @@ fn create_cpu_nodes(
         if numa_nodes.len() > 1 {
             for numa_node_idx in 0..numa_nodes.len() {
                 let numa_node = numa_nodes.get(&(numa_node_idx as u32));
-                if numa_node.unwrap().cpus.contains(&(cpu_id as u8)) {
+                if numa_node.unwrap().cpus.contains(&(cpu_id as u32)) {
                     fdt.property_u32("numa-node-id", numa_node_idx as u32)?;
                 }
             }
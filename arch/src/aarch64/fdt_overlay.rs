@@ -0,0 +1,396 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Device tree overlays merged into the generated device tree.
+//!
+//! An overlay is a device tree compiled with `dtc -@`, made of fragments
+//! each holding an `__overlay__` node, which is merged into the node of the
+//! base device tree designated by the `target-path` or `target` property of
+//! the fragment. The phandles defined by the overlay are moved past the ones
+//! of the base device tree, updating the references listed in
+//! `__local_fixups__`, and the references to the base device tree listed in
+//! `__fixups__` are resolved with the labels of [`FDT_SYMBOLS`].
+
+use super::fdt::FDT_SYMBOLS;
+use byteorder::{BigEndian, ByteOrder};
+use std::result;
+use thiserror::Error;
+use vm_fdt::FdtWriter;
+
+/// Errors thrown while applying device tree overlays.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Invalid device tree blob.
+    #[error("Invalid device tree blob: {0}")]
+    Parse(String),
+    /// Invalid fragment in the overlay.
+    #[error("Invalid overlay fragment {0}: {1}")]
+    InvalidFragment(String, &'static str),
+    /// Node targeted by a fragment not found in the device tree.
+    #[error("Target {0} of overlay fragment {1} not found")]
+    TargetNotFound(String, String),
+    /// Label referenced by the overlay unknown to the device tree.
+    #[error("Unknown label {0} referenced by the overlay")]
+    UnknownLabel(String),
+    /// Invalid entry of `__fixups__` or `__local_fixups__`.
+    #[error("Invalid overlay fixup: {0}")]
+    InvalidFixup(String),
+    /// Failure in writing the resulting device tree.
+    #[error("Failure in writing the device tree: {0}")]
+    Write(#[source] vm_fdt::Error),
+}
+type Result<T> = result::Result<T, Error>;
+
+const FIXUPS_NODE: &str = "__fixups__";
+const LOCAL_FIXUPS_NODE: &str = "__local_fixups__";
+const SYMBOLS_NODE: &str = "__symbols__";
+const OVERLAY_NODE: &str = "__overlay__";
+const PHANDLE_PROPERTIES: [&str; 2] = ["phandle", "linux,phandle"];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Node {
+    name: String,
+    properties: Vec<(String, Vec<u8>)>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn from_dtb(dtb: &[u8]) -> Result<Self> {
+        let fdt = fdt_parser::Fdt::new(dtb).map_err(|e| Error::Parse(format!("{e:?}")))?;
+        let root = fdt
+            .find_node("/")
+            .ok_or_else(|| Error::Parse("no root node".to_string()))?;
+        let mut node = Self::from_fdt_node(root);
+        node.name = String::new();
+        Ok(node)
+    }
+
+    fn from_fdt_node(node: fdt_parser::node::FdtNode<'_, '_>) -> Self {
+        Node {
+            name: node.name.to_string(),
+            properties: node
+                .properties()
+                .map(|p| (p.name.to_string(), p.value.to_vec()))
+                .collect(),
+            children: node.children().map(Self::from_fdt_node).collect(),
+        }
+    }
+
+    fn property(&self, name: &str) -> Option<&[u8]> {
+        self.properties
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    fn property_mut(&mut self, name: &str) -> Option<&mut Vec<u8>> {
+        self.properties
+            .iter_mut()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    fn set_property(&mut self, name: &str, value: &[u8]) {
+        if let Some(v) = self.property_mut(name) {
+            *v = value.to_vec();
+        } else {
+            self.properties.push((name.to_string(), value.to_vec()));
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn find_mut(&mut self, path: &str) -> Option<&mut Node> {
+        path.split('/')
+            .filter(|n| !n.is_empty())
+            .try_fold(self, |node, name| {
+                node.children.iter_mut().find(|c| c.name == name)
+            })
+    }
+
+    fn find_phandle_mut(&mut self, phandle: u32) -> Option<&mut Node> {
+        if self.phandle() == Some(phandle) {
+            return Some(self);
+        }
+        self.children
+            .iter_mut()
+            .find_map(|c| c.find_phandle_mut(phandle))
+    }
+
+    fn phandle(&self) -> Option<u32> {
+        PHANDLE_PROPERTIES
+            .iter()
+            .find_map(|p| self.property(p))
+            .filter(|v| v.len() == 4)
+            .map(BigEndian::read_u32)
+    }
+
+    fn max_phandle(&self) -> u32 {
+        self.children
+            .iter()
+            .map(Self::max_phandle)
+            .chain(self.phandle())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn shift_phandles(&mut self, delta: u32) {
+        for (name, value) in self.properties.iter_mut() {
+            if PHANDLE_PROPERTIES.contains(&name.as_str()) && value.len() == 4 {
+                let phandle = BigEndian::read_u32(value) + delta;
+                BigEndian::write_u32(value, phandle);
+            }
+        }
+        for child in self.children.iter_mut() {
+            child.shift_phandles(delta);
+        }
+    }
+
+    // Walks `__local_fixups__` alongside the overlay, each of its properties
+    // listing the offsets of the phandles in the property of the same name.
+    fn apply_local_fixups(&mut self, fixups: &Node, delta: u32) -> Result<()> {
+        for (name, offsets) in fixups.properties.iter() {
+            let value = self.property_mut(name).ok_or_else(|| {
+                Error::InvalidFixup(format!("{}: no property {}", self.name, name))
+            })?;
+            for offset in offsets.chunks_exact(4).map(BigEndian::read_u32) {
+                let offset = offset as usize;
+                let phandle = value.get_mut(offset..offset + 4).ok_or_else(|| {
+                    Error::InvalidFixup(format!("{name}: offset {offset} out of bounds"))
+                })?;
+                let shifted = BigEndian::read_u32(phandle) + delta;
+                BigEndian::write_u32(phandle, shifted);
+            }
+        }
+        for fixups_child in fixups.children.iter() {
+            let child = self
+                .children
+                .iter_mut()
+                .find(|c| c.name == fixups_child.name)
+                .ok_or_else(|| Error::InvalidFixup(format!("no node {}", fixups_child.name)))?;
+            child.apply_local_fixups(fixups_child, delta)?;
+        }
+        Ok(())
+    }
+
+    // Each property of `__fixups__` is named after a label of the base device
+    // tree, and lists the `<path>:<property>:<offset>` locations referencing it.
+    fn apply_fixups(&mut self, fixups: &Node) -> Result<()> {
+        for (label, locations) in fixups.properties.iter() {
+            let phandle = FDT_SYMBOLS
+                .iter()
+                .find(|(l, _)| l == label)
+                .map(|(_, p)| *p)
+                .ok_or_else(|| Error::UnknownLabel(label.clone()))?;
+            for location in locations
+                .split(|c| *c == 0)
+                .filter(|l| !l.is_empty())
+                .map(String::from_utf8_lossy)
+            {
+                let invalid = || Error::InvalidFixup(location.to_string());
+                let mut fields = location.rsplitn(3, ':');
+                let offset = fields
+                    .next()
+                    .and_then(|o| o.parse::<usize>().ok())
+                    .ok_or_else(invalid)?;
+                let property = fields.next().ok_or_else(invalid)?;
+                let path = fields.next().ok_or_else(invalid)?;
+                let value = self
+                    .find_mut(path)
+                    .and_then(|n| n.property_mut(property))
+                    .and_then(|v| v.get_mut(offset..offset + 4))
+                    .ok_or_else(invalid)?;
+                BigEndian::write_u32(value, phandle);
+            }
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, overlay: &Node) {
+        for (name, value) in overlay.properties.iter() {
+            self.set_property(name, value);
+        }
+        for overlay_child in overlay.children.iter() {
+            if let Some(child) = self
+                .children
+                .iter_mut()
+                .find(|c| c.name == overlay_child.name)
+            {
+                child.merge(overlay_child);
+            } else {
+                self.children.push(overlay_child.clone());
+            }
+        }
+    }
+
+    fn write(&self, fdt: &mut FdtWriter) -> vm_fdt::Result<()> {
+        let node = fdt.begin_node(&self.name)?;
+        for (name, value) in self.properties.iter() {
+            fdt.property(name, value)?;
+        }
+        for child in self.children.iter() {
+            child.write(fdt)?;
+        }
+        fdt.end_node(node)
+    }
+}
+
+fn apply_overlay(base: &mut Node, mut overlay: Node) -> Result<()> {
+    let delta = base.max_phandle();
+    overlay.shift_phandles(delta);
+    if let Some(local_fixups) = overlay.child(LOCAL_FIXUPS_NODE).cloned() {
+        overlay.apply_local_fixups(&local_fixups, delta)?;
+    }
+    if let Some(fixups) = overlay.child(FIXUPS_NODE).cloned() {
+        overlay.apply_fixups(&fixups)?;
+    }
+
+    for fragment in overlay
+        .children
+        .iter()
+        .filter(|c| ![FIXUPS_NODE, LOCAL_FIXUPS_NODE, SYMBOLS_NODE].contains(&c.name.as_str()))
+    {
+        let content = fragment
+            .child(OVERLAY_NODE)
+            .ok_or_else(|| Error::InvalidFragment(fragment.name.clone(), "no __overlay__ node"))?;
+
+        let target = if let Some(path) = fragment.property("target-path") {
+            let path = std::str::from_utf8(path)
+                .map(|p| p.trim_end_matches('\0'))
+                .map_err(|_| {
+                    Error::InvalidFragment(fragment.name.clone(), "invalid target-path")
+                })?;
+            base.find_mut(path)
+                .ok_or_else(|| Error::TargetNotFound(path.to_string(), fragment.name.clone()))?
+        } else if let Some(phandle) = fragment.property("target").filter(|t| t.len() == 4) {
+            let phandle = BigEndian::read_u32(phandle);
+            base.find_phandle_mut(phandle).ok_or_else(|| {
+                Error::TargetNotFound(format!("phandle {phandle}"), fragment.name.clone())
+            })?
+        } else {
+            return Err(Error::InvalidFragment(fragment.name.clone(), "no target"));
+        };
+
+        target.merge(content);
+    }
+
+    Ok(())
+}
+
+/// Merges the overlays, in order, into the device tree blob `dtb`.
+pub fn apply_overlays(dtb: &[u8], overlays: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut base = Node::from_dtb(dtb)?;
+    for overlay in overlays.iter() {
+        apply_overlay(&mut base, Node::from_dtb(overlay)?)?;
+    }
+
+    let mut fdt = FdtWriter::new().map_err(Error::Write)?;
+    base.write(&mut fdt).map_err(Error::Write)?;
+    fdt.finish().map_err(Error::Write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base_dtb() -> Vec<u8> {
+        let mut fdt = FdtWriter::new().unwrap();
+        let root = fdt.begin_node("").unwrap();
+        fdt.property_string("compatible", "linux,dummy-virt")
+            .unwrap();
+        let intc = fdt.begin_node("intc").unwrap();
+        fdt.property_u32("phandle", 1).unwrap();
+        fdt.end_node(intc).unwrap();
+        let soc = fdt.begin_node("soc").unwrap();
+        fdt.property_u32("phandle", 2).unwrap();
+        fdt.property_string("status", "disabled").unwrap();
+        fdt.end_node(soc).unwrap();
+        fdt.end_node(root).unwrap();
+        fdt.finish().unwrap()
+    }
+
+    // Equivalent to compiling with `dtc -@`:
+    //
+    // /plugin/;
+    // &{/soc} {
+    //     status = "okay";
+    //     clk: clock { phandle = <1>; };
+    //     dev@0 { clocks = <&clk>; interrupt-parent = <&gic>; };
+    // };
+    fn overlay_dtb() -> Vec<u8> {
+        let mut fdt = FdtWriter::new().unwrap();
+        let root = fdt.begin_node("").unwrap();
+        let fragment = fdt.begin_node("fragment@0").unwrap();
+        fdt.property_string("target-path", "/soc").unwrap();
+        let overlay = fdt.begin_node("__overlay__").unwrap();
+        fdt.property_string("status", "okay").unwrap();
+        let clock = fdt.begin_node("clock").unwrap();
+        fdt.property_u32("phandle", 1).unwrap();
+        fdt.end_node(clock).unwrap();
+        let dev = fdt.begin_node("dev@0").unwrap();
+        fdt.property_u32("clocks", 1).unwrap();
+        fdt.property_u32("interrupt-parent", 0xffff_ffff).unwrap();
+        fdt.end_node(dev).unwrap();
+        fdt.end_node(overlay).unwrap();
+        fdt.end_node(fragment).unwrap();
+        let fixups = fdt.begin_node("__fixups__").unwrap();
+        fdt.property_string_list(
+            "gic",
+            vec!["/fragment@0/__overlay__/dev@0:interrupt-parent:0".to_string()],
+        )
+        .unwrap();
+        fdt.end_node(fixups).unwrap();
+        let local_fixups = fdt.begin_node("__local_fixups__").unwrap();
+        let fragment = fdt.begin_node("fragment@0").unwrap();
+        let overlay = fdt.begin_node("__overlay__").unwrap();
+        let dev = fdt.begin_node("dev@0").unwrap();
+        fdt.property_u32("clocks", 0).unwrap();
+        fdt.end_node(dev).unwrap();
+        fdt.end_node(overlay).unwrap();
+        fdt.end_node(fragment).unwrap();
+        fdt.end_node(local_fixups).unwrap();
+        fdt.end_node(root).unwrap();
+        fdt.finish().unwrap()
+    }
+
+    #[test]
+    fn test_apply_overlays() {
+        let dtb = apply_overlays(&base_dtb(), &[overlay_dtb()]).unwrap();
+        let node = Node::from_dtb(&dtb).unwrap();
+
+        let soc = node.child("soc").unwrap();
+        assert_eq!(soc.property("status"), Some(&b"okay\0"[..]));
+        // The phandle of the overlay is moved past the ones of the base.
+        assert_eq!(soc.child("clock").unwrap().phandle(), Some(3));
+        let dev = soc.child("dev@0").unwrap();
+        assert_eq!(dev.property("clocks"), Some(&3u32.to_be_bytes()[..]));
+        let gic = FDT_SYMBOLS.iter().find(|(l, _)| *l == "gic").unwrap().1;
+        assert_eq!(
+            dev.property("interrupt-parent"),
+            Some(&gic.to_be_bytes()[..])
+        );
+        assert!(node.child(FIXUPS_NODE).is_none());
+        assert!(node.child("fragment@0").is_none());
+    }
+
+    #[test]
+    fn test_apply_overlays_invalid() {
+        let mut fdt = FdtWriter::new().unwrap();
+        let root = fdt.begin_node("").unwrap();
+        let fragment = fdt.begin_node("fragment@0").unwrap();
+        fdt.property_string("target-path", "/missing").unwrap();
+        let overlay = fdt.begin_node("__overlay__").unwrap();
+        fdt.end_node(overlay).unwrap();
+        fdt.end_node(fragment).unwrap();
+        fdt.end_node(root).unwrap();
+        let overlay = fdt.finish().unwrap();
+
+        assert!(matches!(
+            apply_overlays(&base_dtb(), &[overlay]),
+            Err(Error::TargetNotFound(..))
+        ));
+    }
+}
@@ -4,6 +4,8 @@
 
 /// Module for the flattened device tree.
 pub mod fdt;
+/// Module for applying device tree overlays.
+pub mod fdt_overlay;
 /// Layout for this aarch64 system.
 pub mod layout;
 /// Module for system registers definition
@@ -19,7 +21,7 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
-use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryAtomic};
+use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryAtomic, GuestMemoryRegion};
 
 pub const _NSIG: i32 = 65;
 
@@ -34,6 +36,10 @@ pub enum Error {
     #[error("Failed to write FDT to memory: {0}")]
     WriteFdtToMemory(fdt::Error),
 
+    /// Failed to apply a device tree overlay.
+    #[error("Failed to apply a device tree overlay: {0}")]
+    ApplyFdtOverlay(fdt_overlay::Error),
+
     /// Failed to create a GIC.
     #[error("Failed to create a GIC")]
     SetupGic,
@@ -72,7 +78,7 @@ pub struct EntryPoint {
 /// Configure the specified VCPU, and return its MPIDR.
 pub fn configure_vcpu(
     vcpu: &Arc<dyn hypervisor::Vcpu>,
-    id: u8,
+    id: u32,
     boot_setup: Option<(EntryPoint, &GuestMemoryAtomic<GuestMemoryMmap>)>,
 ) -> super::Result<u64> {
     if let Some((kernel_entry_point, _guest_memory)) = boot_setup {
@@ -127,7 +133,31 @@ pub fn arch_memory_regions() -> Vec<(GuestAddress, usize, RegionType)> {
     ]
 }
 
+type RamRange = (u64, u64);
+
+/// Returns the usable physical memory ranges of the guest, as (start, end)
+/// tuples, the contiguous memory regions being merged.
+pub fn generate_ram_ranges(guest_mem: &GuestMemoryMmap) -> super::Result<Vec<RamRange>> {
+    let mut ram_ranges: Vec<RamRange> = Vec::new();
+    for (start, size) in guest_mem
+        .iter()
+        .map(|m| (m.start_addr().raw_value(), m.len()))
+    {
+        match ram_ranges.last_mut() {
+            Some((_, end)) if *end == start => *end += size,
+            _ => ram_ranges.push((start, start + size)),
+        }
+    }
+
+    if ram_ranges.is_empty() {
+        return Err(super::Error::MemmapTableSetup);
+    }
+
+    Ok(ram_ranges)
+}
+
 /// Configures the system and should be called once per vm before starting vcpu threads.
+/// Returns the FDT written to the guest memory.
 #[allow(clippy::too_many_arguments)]
 pub fn configure_system<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHasher>(
     guest_mem: &GuestMemoryMmap,
@@ -141,8 +171,9 @@ pub fn configure_system<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::Bui
     gic_device: &Arc<Mutex<dyn Vgic>>,
     numa_nodes: &NumaNodes,
     pmu_supported: bool,
-) -> super::Result<()> {
-    let fdt_final = fdt::create_fdt(
+    fdt_overlays: &[Vec<u8>],
+) -> super::Result<Vec<u8>> {
+    let mut fdt_final = fdt::create_fdt(
         guest_mem,
         cmdline,
         vcpu_mpidr,
@@ -157,13 +188,18 @@ pub fn configure_system<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::Bui
     )
     .map_err(|_| Error::SetupFdt)?;
 
+    if !fdt_overlays.is_empty() {
+        fdt_final = fdt_overlay::apply_overlays(&fdt_final, fdt_overlays)
+            .map_err(Error::ApplyFdtOverlay)?;
+    }
+
     if log_enabled!(Level::Debug) {
         fdt::print_fdt(&fdt_final);
     }
 
-    fdt::write_fdt_to_memory(fdt_final, guest_mem).map_err(Error::WriteFdtToMemory)?;
+    fdt::write_fdt_to_memory(fdt_final.clone(), guest_mem).map_err(Error::WriteFdtToMemory)?;
 
-    Ok(())
+    Ok(fdt_final)
 }
 
 /// Returns the memory address where the initramfs could be loaded.
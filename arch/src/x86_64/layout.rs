@@ -75,6 +75,14 @@ pub const RSDP_POINTER: GuestAddress = EBDA_START;
 
 pub const SMBIOS_START: u64 = 0xf0000; // First possible location per the spec.
 
+// VM generation ID, read by the guest from the address given by the VMGENID
+// ACPI device, right before the SMBIOS tables.
+pub const VMGENID_START: GuestAddress = GuestAddress(SMBIOS_START - VMGENID_SIZE);
+pub const VMGENID_SIZE: u64 = 0x1000;
+
+// ACPI tables, from the RSDP up to the VM generation ID.
+pub const ACPI_MAX_SIZE: u64 = VMGENID_START.0 - RSDP_POINTER.0;
+
 // == End of "EBDA" range ==
 
 // ** High RAM (start: 1MiB, length: 3071MiB) **
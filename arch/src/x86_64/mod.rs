@@ -15,12 +15,13 @@ pub mod regs;
 use crate::GuestMemoryMmap;
 use crate::InitramfsConfig;
 use crate::RegionType;
-use hypervisor::arch::x86::{CpuIdEntry, CPUID_FLAG_VALID_INDEX};
+use hypervisor::arch::x86::{CpuIdEntry, MsrEntry, CPUID_FLAG_VALID_INDEX};
 use hypervisor::{CpuVendor, HypervisorCpuError, HypervisorError};
 use linux_loader::loader::bootparam::{boot_params, setup_header};
 use linux_loader::loader::elf::start_info::{
     hvm_memmap_table_entry, hvm_modlist_entry, hvm_start_info,
 };
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::mem;
 use thiserror::Error;
@@ -42,6 +43,10 @@ const INVARIANT_TSC_EDX_BIT: u8 = 8; // Invariant TSC bit on 0x8000_0007 EDX
 const AMX_BF16: u8 = 22; // AMX tile computation on bfloat16 numbers
 const AMX_TILE: u8 = 24; // AMX tile load/store instructions
 const AMX_INT8: u8 = 25; // AMX tile computation on 8-bit integers
+const CET_SS_ECX_BIT: u8 = 7; // CET shadow stack on 0x7 ECX
+const CET_IBT_EDX_BIT: u8 = 20; // CET indirect branch tracking on 0x7 EDX
+const XSS_CET_U_BIT: u8 = 11; // CET user state component of XSS
+const XSS_CET_S_BIT: u8 = 12; // CET supervisor state component of XSS
 
 // KVM feature bits
 #[cfg(feature = "tdx")]
@@ -56,6 +61,10 @@ const KVM_FEATURE_ASYNC_PF_BIT: u8 = 4;
 const KVM_FEATURE_ASYNC_PF_VMEXIT_BIT: u8 = 10;
 #[cfg(feature = "tdx")]
 const KVM_FEATURE_STEAL_TIME_BIT: u8 = 5;
+const KVM_FEATURE_MSI_EXT_DEST_ID_BIT: u8 = 15;
+
+/// Largest APIC ID with xAPIC, 0xff being the broadcast ID.
+pub const MAX_XAPIC_ID: u32 = 0xfe;
 
 pub const _NSIG: i32 = 65;
 
@@ -127,6 +136,8 @@ pub struct CpuidConfig {
     #[cfg(feature = "tdx")]
     pub tdx: bool,
     pub amx: bool,
+    pub cet: bool,
+    pub msi_ext_dest_id: bool,
 }
 
 #[derive(Debug, Error)]
@@ -188,8 +199,19 @@ pub enum Error {
     CpuidIdentification(vmm_sys_util::fam::Error),
 
     /// Error checking CPUID compatibility
-    #[error("Error checking CPUID compatibility")]
-    CpuidCheckCompatibility,
+    #[error("Error checking CPUID compatibility: {0}")]
+    CpuidCheckCompatibility(String),
+
+    /// Error getting the MSR-based features through the hypervisor API
+    #[error("Error getting the MSR-based features through the hypervisor API: {0}")]
+    MsrFeaturesGetSupported(HypervisorError),
+
+    /// Error checking the CPU features compatibility
+    #[error("Incompatible CPU features: {0}")]
+    CpuFeaturesCheckCompatibility(String),
+    /// The guest TSC frequency can't be provided
+    #[error("Incompatible TSC: {0}")]
+    TscIncompatible(String),
 
     // Error writing EBDA address
     #[error("Error writing EBDA address: {0}")]
@@ -207,6 +229,10 @@ pub enum Error {
     /// Failed to configure E820 map for bzImage
     #[error("Failed to configure E820 map for bzImage")]
     E820Configuration,
+
+    /// CET is not supported by the host
+    #[error("CET is not supported by the host")]
+    CetNotSupported,
 }
 
 impl From<Error> for super::Error {
@@ -221,10 +247,11 @@ pub fn get_x2apic_id(cpu_id: u32, topology: Option<(u8, u8, u8)>) -> u32 {
         let core_mask_width = u8::BITS - (t.1 - 1).leading_zeros();
         let die_mask_width = u8::BITS - (t.2 - 1).leading_zeros();
 
-        let thread_id = cpu_id % (t.0 as u32);
-        let core_id = cpu_id / (t.0 as u32) % (t.1 as u32);
-        let die_id = cpu_id / ((t.0 * t.1) as u32) % (t.2 as u32);
-        let socket_id = cpu_id / ((t.0 * t.1 * t.2) as u32);
+        let (threads, cores, dies) = (u32::from(t.0), u32::from(t.1), u32::from(t.2));
+        let thread_id = cpu_id % threads;
+        let core_id = cpu_id / threads % cores;
+        let die_id = cpu_id / (threads * cores) % dies;
+        let socket_id = cpu_id / (threads * cores * dies);
 
         return thread_id
             | (core_id << thread_mask_width)
@@ -235,6 +262,12 @@ pub fn get_x2apic_id(cpu_id: u32, topology: Option<(u8, u8, u8)>) -> u32 {
     cpu_id
 }
 
+/// Whether some of the `num_cpus` vCPUs get an APIC ID beyond the xAPIC range,
+/// which requires x2APIC and 32-bit destination IDs for their interrupts.
+pub fn x2apic_ids_required(num_cpus: u32, topology: Option<(u8, u8, u8)>) -> bool {
+    get_x2apic_id(num_cpus.saturating_sub(1), topology) > MAX_XAPIC_ID
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum CpuidReg {
     EAX,
@@ -448,6 +481,13 @@ impl CpuidFeatureEntry {
                 feature_reg: CpuidReg::EAX,
                 compatible_check: CpuidCompatibleCheck::BitwiseSubset,
             },
+            // Leaf 0xd subleaf 0x1, ECX, supervisor state components of XSS
+            CpuidFeatureEntry {
+                function: 0xd,
+                index: 1,
+                feature_reg: CpuidReg::ECX,
+                compatible_check: CpuidCompatibleCheck::BitwiseSubset,
+            },
             // Leaf 0x8000_0001, ECX/EDX, CPUID features bits
             CpuidFeatureEntry {
                 function: 0x8000_0001,
@@ -548,19 +588,19 @@ impl CpuidFeatureEntry {
         features
     }
 
-    // The function returns `Error` (a.k.a. "incompatible"), when the CPUID features from `src_vm_cpuid`
-    // is not a subset of those of the `dest_vm_cpuid`.
-    pub fn check_cpuid_compatibility(
+    // Returns the description of every CPUID feature from `src_vm_cpuid`
+    // which is not provided by `dest_vm_cpuid`.
+    fn cpuid_incompatibilities(
         src_vm_cpuid: &[CpuIdEntry],
         dest_vm_cpuid: &[CpuIdEntry],
-    ) -> Result<(), Error> {
+    ) -> Vec<String> {
         let feature_entry_list = &Self::checked_feature_entry_list();
         let src_vm_features = Self::get_features_from_cpuid(src_vm_cpuid, feature_entry_list);
         let dest_vm_features = Self::get_features_from_cpuid(dest_vm_cpuid, feature_entry_list);
 
         // Loop on feature bit and check if the 'source vm' feature is a subset
         // of those of the 'destination vm' feature
-        let mut compatible = true;
+        let mut incompatibilities = Vec::new();
         for (i, (src_vm_feature, dest_vm_feature)) in src_vm_features
             .iter()
             .zip(dest_vm_features.iter())
@@ -577,22 +617,196 @@ impl CpuidFeatureEntry {
                 CpuidCompatibleCheck::NumNotGreater => src_vm_feature <= dest_vm_feature,
             };
             if !entry_compatible {
-                error!(
-                    "Detected incompatible CPUID entry: leaf={:#02x} (subleaf={:#02x}), register='{:?}', \
-                    compatible_check='{:?}', source VM feature='{:#04x}', destination VM feature'{:#04x}'.",
-                    entry.function, entry.index, entry.feature_reg,
-                    entry.compatible_check, src_vm_feature, dest_vm_feature
-                    );
-
-                compatible = false;
+                let incompatibility = match entry.compatible_check {
+                    CpuidCompatibleCheck::BitwiseSubset => format!(
+                        "CPUID leaf={:#x} subleaf={:#x} {:?} missing bits {:#x}",
+                        entry.function,
+                        entry.index,
+                        entry.feature_reg,
+                        src_vm_feature & !dest_vm_feature
+                    ),
+                    _ => format!(
+                        "CPUID leaf={:#x} subleaf={:#x} {:?} {:?} source {:#x} destination {:#x}",
+                        entry.function,
+                        entry.index,
+                        entry.feature_reg,
+                        entry.compatible_check,
+                        src_vm_feature,
+                        dest_vm_feature
+                    ),
+                };
+                error!("Detected incompatible {}", incompatibility);
+
+                incompatibilities.push(incompatibility);
+            }
+        }
+
+        incompatibilities
+    }
+
+    // The function returns `Error` (a.k.a. "incompatible"), when the CPUID features from `src_vm_cpuid`
+    // is not a subset of those of the `dest_vm_cpuid`.
+    pub fn check_cpuid_compatibility(
+        src_vm_cpuid: &[CpuIdEntry],
+        dest_vm_cpuid: &[CpuIdEntry],
+    ) -> Result<(), Error> {
+        let incompatibilities = Self::cpuid_incompatibilities(src_vm_cpuid, dest_vm_cpuid);
+        if incompatibilities.is_empty() {
+            info!("No CPU incompatibility detected.");
+            Ok(())
+        } else {
+            Err(Error::CpuidCheckCompatibility(incompatibilities.join(", ")))
+        }
+    }
+}
+
+struct MsrFeatureEntry {
+    index: u32,
+    name: &'static str,
+    compatible_check: CpuidCompatibleCheck,
+}
+
+impl MsrFeatureEntry {
+    // MSR-based features which must be preserved across migration. The other
+    // ones reported by the hypervisor (e.g. microcode revision, nested VMX
+    // capabilities) are masked out of the comparison.
+    fn checked_feature_entry_list() -> Vec<MsrFeatureEntry> {
+        vec![
+            MsrFeatureEntry {
+                index: 0x10a,
+                name: "IA32_ARCH_CAPABILITIES",
+                compatible_check: CpuidCompatibleCheck::BitwiseSubset,
+            },
+            MsrFeatureEntry {
+                index: 0x345,
+                name: "IA32_PERF_CAPABILITIES",
+                compatible_check: CpuidCompatibleCheck::Equal,
+            },
+            MsrFeatureEntry {
+                index: 0xc001_1029,
+                name: "DE_CFG",
+                compatible_check: CpuidCompatibleCheck::BitwiseSubset,
+            },
+        ]
+    }
+
+    // Returns the description of every MSR-based feature from `src_msrs`
+    // which is not provided by `dest_msrs`. Only the features known from both
+    // sides are compared, since the source might not have reported any.
+    fn msr_incompatibilities(src_msrs: &[MsrEntry], dest_msrs: &[MsrEntry]) -> Vec<String> {
+        let mut incompatibilities = Vec::new();
+        for entry in Self::checked_feature_entry_list() {
+            let Some(src_msr) = src_msrs.iter().find(|m| m.index == entry.index) else {
+                continue;
+            };
+            let dest_value = dest_msrs
+                .iter()
+                .find(|m| m.index == entry.index)
+                .map_or(0, |m| m.data);
+
+            let entry_compatible = match entry.compatible_check {
+                CpuidCompatibleCheck::BitwiseSubset => src_msr.data & !dest_value == 0,
+                CpuidCompatibleCheck::Equal => src_msr.data == dest_value,
+                CpuidCompatibleCheck::NumNotGreater => src_msr.data <= dest_value,
+            };
+            if !entry_compatible {
+                let incompatibility = format!(
+                    "MSR {} ({:#x}) {:?} source {:#x} destination {:#x}",
+                    entry.name, entry.index, entry.compatible_check, src_msr.data, dest_value
+                );
+                error!("Detected incompatible {}", incompatibility);
+
+                incompatibilities.push(incompatibility);
             }
         }
 
-        if compatible {
+        incompatibilities
+    }
+}
+
+// Difference between two TSC frequencies KVM absorbs without scaling, see
+// the tsc_tolerance_ppm parameter of the kvm module.
+const TSC_TOLERANCE_PPM: u64 = 250;
+
+// Largest TSC scaling ratio supported on both Intel (16.48 fixed point) and
+// AMD (8.32 fixed point).
+const TSC_SCALING_MAX_RATIO: f64 = 255.0;
+
+/// Ratio to apply to the host TSC for the guest to run at `guest_khz`.
+pub fn tsc_scaling_ratio(guest_khz: u32, host_khz: u32) -> f64 {
+    guest_khz as f64 / host_khz as f64
+}
+
+fn tsc_within_tolerance(guest_khz: u32, host_khz: u32) -> bool {
+    u64::from(guest_khz).abs_diff(u64::from(host_khz)) * 1_000_000
+        <= u64::from(host_khz) * TSC_TOLERANCE_PPM
+}
+
+/// Check the TSC of a restored guest, which booted at `guest_khz`, can keep
+/// ticking at the same rate on a vCPU running at `host_khz` by default, for
+/// the invariant TSC the guest relies on.
+pub fn check_tsc_compatibility(
+    guest_khz: u32,
+    host_khz: u32,
+    tsc_scaling: bool,
+) -> Result<(), Error> {
+    if tsc_within_tolerance(guest_khz, host_khz) {
+        return Ok(());
+    }
+
+    let ratio = tsc_scaling_ratio(guest_khz, host_khz);
+    if !tsc_scaling {
+        Err(Error::TscIncompatible(format!(
+            "TSC frequency {guest_khz} kHz (host runs at {host_khz} kHz without TSC scaling)"
+        )))
+    } else if ratio > TSC_SCALING_MAX_RATIO {
+        Err(Error::TscIncompatible(format!(
+            "TSC frequency {guest_khz} kHz (scaling ratio {ratio:.3} from {host_khz} kHz out of range)"
+        )))
+    } else {
+        info!("Guest TSC scaled from {host_khz} kHz to {guest_khz} kHz (ratio {ratio:.6})");
+        Ok(())
+    }
+}
+
+/// CPU features exposed to the guest, made of the CPUID and of the MSR-based
+/// features. It is sent along with the VM configuration when migrating, for
+/// the destination to verify it can provide all of them before the guest
+/// state is transferred.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CpuFeatureManifest {
+    pub cpuid: Vec<CpuIdEntry>,
+    pub msrs: Vec<MsrEntry>,
+}
+
+impl CpuFeatureManifest {
+    pub fn new(
+        hypervisor: &Arc<dyn hypervisor::Hypervisor>,
+        config: &CpuidConfig,
+    ) -> super::Result<Self> {
+        Ok(CpuFeatureManifest {
+            cpuid: generate_common_cpuid(hypervisor, config)?,
+            msrs: hypervisor
+                .get_msr_features()
+                .map_err(Error::MsrFeaturesGetSupported)?,
+        })
+    }
+
+    /// Check the features from `self` are all provided by `dest`, listing
+    /// every missing one otherwise.
+    pub fn check_compatibility(&self, dest: &CpuFeatureManifest) -> Result<(), Error> {
+        let mut incompatibilities =
+            CpuidFeatureEntry::cpuid_incompatibilities(&self.cpuid, &dest.cpuid);
+        let msr_incompatibilities = MsrFeatureEntry::msr_incompatibilities(&self.msrs, &dest.msrs);
+        incompatibilities.extend(msr_incompatibilities);
+
+        if incompatibilities.is_empty() {
             info!("No CPU incompatibility detected.");
             Ok(())
         } else {
-            Err(Error::CpuidCheckCompatibility)
+            Err(Error::CpuFeaturesCheckCompatibility(
+                incompatibilities.join(", "),
+            ))
         }
     }
 }
@@ -692,9 +906,17 @@ pub fn generate_common_cpuid(
                 if !config.amx && entry.index == 0 {
                     entry.edx &= !(1 << AMX_BF16 | 1 << AMX_TILE | 1 << AMX_INT8)
                 }
+                // Clear CET related bits if the CET feature is not enabled
+                if !config.cet && entry.index == 0 {
+                    entry.ecx &= !(1 << CET_SS_ECX_BIT);
+                    entry.edx &= !(1 << CET_IBT_EDX_BIT);
+                }
             }
-            0xd =>
-            {
+            0xd => {
+                // The CET state is only saved by XSAVES with CET enabled
+                if !config.cet && entry.index == 1 {
+                    entry.ecx &= !(1 << XSS_CET_U_BIT | 1 << XSS_CET_S_BIT);
+                }
                 #[cfg(feature = "tdx")]
                 if let Some(caps) = &tdx_capabilities {
                     let xcr0_mask: u64 = 0x82ff;
@@ -745,6 +967,11 @@ pub fn generate_common_cpuid(
                 entry.eax = (entry.eax & 0xffff_ff00) | (config.phys_bits as u32 & 0xff);
             }
             0x4000_0001 => {
+                // Let the guest target APIC IDs beyond 8 bits from the MSI
+                // address and the IOAPIC entries, without interrupt remapping.
+                if config.msi_ext_dest_id {
+                    entry.eax |= 1 << KVM_FEATURE_MSI_EXT_DEST_ID_BIT;
+                }
                 // These features are not supported by TDX
                 #[cfg(feature = "tdx")]
                 if config.tdx {
@@ -760,6 +987,22 @@ pub fn generate_common_cpuid(
         }
     }
 
+    if config.cet {
+        if !cpuid.iter().any(|entry| {
+            entry.function == 0x7
+                && entry.index == 0
+                && (entry.ecx & 1 << CET_SS_ECX_BIT != 0 || entry.edx & 1 << CET_IBT_EDX_BIT != 0)
+        }) {
+            return Err(Error::CetNotSupported.into());
+        }
+    } else {
+        // Drop the size and offset of the CET state components
+        cpuid.retain(|entry| {
+            entry.function != 0xd
+                || (entry.index != XSS_CET_U_BIT as u32 && entry.index != XSS_CET_S_BIT as u32)
+        });
+    }
+
     // Copy CPU identification string
     for i in 0x8000_0002..=0x8000_0004 {
         cpuid.retain(|c| c.function != i);
@@ -827,14 +1070,14 @@ pub fn generate_common_cpuid(
 
 pub fn configure_vcpu(
     vcpu: &Arc<dyn hypervisor::Vcpu>,
-    id: u8,
+    id: u32,
     boot_setup: Option<(EntryPoint, &GuestMemoryAtomic<GuestMemoryMmap>)>,
     cpuid: Vec<CpuIdEntry>,
     kvm_hyperv: bool,
     cpu_vendor: CpuVendor,
     topology: Option<(u8, u8, u8)>,
 ) -> super::Result<()> {
-    let x2apic_id = get_x2apic_id(id as u32, topology);
+    let x2apic_id = get_x2apic_id(id, topology);
 
     // Per vCPU CPUID changes; common are handled via generate_common_cpuid()
     let mut cpuid = cpuid;
@@ -968,7 +1211,7 @@ pub fn configure_system(
     cmdline_addr: GuestAddress,
     cmdline_size: usize,
     initramfs: &Option<InitramfsConfig>,
-    _num_cpus: u8,
+    num_cpus: u32,
     setup_header: Option<setup_header>,
     rsdp_addr: Option<GuestAddress>,
     sgx_epc_region: Option<SgxEpcRegion>,
@@ -988,7 +1231,14 @@ pub fn configure_system(
     // Place the MP table after the SMIOS table aligned to 16 bytes
     let offset = GuestAddress(layout::SMBIOS_START).unchecked_add(size);
     let offset = GuestAddress((offset.0 + 16) & !0xf);
-    mptable::setup_mptable(offset, guest_mem, _num_cpus, topology).map_err(Error::MpTableSetup)?;
+    // The MP table can only describe 8-bit APIC IDs, larger guests rely on the
+    // x2APIC entries from the ACPI MADT instead.
+    if get_x2apic_id(num_cpus.saturating_sub(1), topology) < mptable::MAX_SUPPORTED_CPUS {
+        mptable::setup_mptable(offset, guest_mem, num_cpus, topology)
+            .map_err(Error::MpTableSetup)?;
+    } else {
+        info!("Skipping mptable creation as APIC IDs exceed the xAPIC range");
+    }
 
     // Check that the RAM is not smaller than the RSDP start address
     if let Some(rsdp_addr) = rsdp_addr {
@@ -1387,10 +1637,10 @@ fn update_cpuid_topology(
     cores_per_die: u8,
     dies_per_package: u8,
     cpu_vendor: CpuVendor,
-    id: u8,
+    id: u32,
 ) {
     let x2apic_id = get_x2apic_id(
-        id as u32,
+        id,
         Some((threads_per_core, cores_per_die, dies_per_package)),
     );
 
@@ -1758,5 +2008,119 @@ mod tests {
 
         let x2apic_id = get_x2apic_id(8, Some((2, 3, 1)));
         assert_eq!(x2apic_id, 10);
+
+        // Products of the topology levels beyond 8 bits.
+        let x2apic_id = get_x2apic_id(300, Some((2, 128, 2)));
+        assert_eq!(x2apic_id, 300);
+
+        let x2apic_id = get_x2apic_id(2047, Some((2, 128, 2)));
+        assert_eq!(x2apic_id, 2047);
+    }
+
+    #[test]
+    fn test_x2apic_ids_required() {
+        assert!(!x2apic_ids_required(1, None));
+        assert!(!x2apic_ids_required(255, None));
+        assert!(x2apic_ids_required(256, None));
+        // The topology can leave holes in the APIC IDs.
+        assert!(!x2apic_ids_required(192, Some((2, 3, 32))));
+        assert!(x2apic_ids_required(198, Some((2, 3, 33))));
+    }
+
+    #[test]
+    fn test_cpu_feature_manifest_compatibility() {
+        let src = CpuFeatureManifest {
+            cpuid: vec![CpuIdEntry {
+                function: 7,
+                index: 0,
+                ebx: 0b1011,
+                ..Default::default()
+            }],
+            msrs: vec![MsrEntry {
+                index: 0x10a,
+                data: 0b11,
+            }],
+        };
+
+        let mut dest = src.clone();
+        dest.cpuid[0].ebx = 0b1111;
+        dest.msrs.push(MsrEntry {
+            index: 0x8b,
+            data: 0x1234,
+        });
+        assert!(src.check_compatibility(&dest).is_ok());
+
+        // The features the source doesn't report can't be compared.
+        assert!(CpuFeatureManifest {
+            msrs: Vec::new(),
+            ..src.clone()
+        }
+        .check_compatibility(&dest)
+        .is_ok());
+
+        dest.cpuid[0].ebx = 0b0011;
+        dest.msrs[0].data = 0b01;
+        match src.check_compatibility(&dest) {
+            Err(Error::CpuFeaturesCheckCompatibility(s)) => {
+                assert!(s.contains("leaf=0x7 subleaf=0x0 EBX missing bits 0x8"));
+                assert!(s.contains("MSR IA32_ARCH_CAPABILITIES (0x10a)"));
+            }
+            r => panic!("Unexpected result {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_cpu_feature_manifest_cet_compatibility() {
+        let src = CpuFeatureManifest {
+            cpuid: vec![
+                CpuIdEntry {
+                    function: 7,
+                    index: 0,
+                    ecx: 1 << CET_SS_ECX_BIT,
+                    edx: 1 << CET_IBT_EDX_BIT,
+                    ..Default::default()
+                },
+                CpuIdEntry {
+                    function: 0xd,
+                    index: 1,
+                    ecx: 1 << XSS_CET_U_BIT | 1 << XSS_CET_S_BIT,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let mut dest = src.clone();
+        assert!(src.check_compatibility(&dest).is_ok());
+
+        // The CET state can't be saved by XSAVES on the destination.
+        dest.cpuid[1].ecx = 0;
+        match src.check_compatibility(&dest) {
+            Err(Error::CpuFeaturesCheckCompatibility(s)) => {
+                assert!(s.contains("leaf=0xd subleaf=0x1 ECX missing bits 0x1800"));
+            }
+            r => panic!("Unexpected result {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_tsc_compatibility() {
+        // Within the tolerance of KVM, no scaling needed.
+        assert!(check_tsc_compatibility(2_400_000, 2_400_500, false).is_ok());
+
+        match check_tsc_compatibility(2_400_000, 3_000_000, false) {
+            Err(Error::TscIncompatible(s)) => {
+                assert!(s.contains("TSC frequency 2400000 kHz"));
+            }
+            r => panic!("Unexpected result {r:?}"),
+        }
+
+        assert!(check_tsc_compatibility(2_400_000, 3_000_000, true).is_ok());
+        assert_eq!(tsc_scaling_ratio(2_400_000, 3_000_000), 0.8);
+
+        assert!(matches!(
+            check_tsc_compatibility(u32::MAX, 1_000, true),
+            Err(Error::TscIncompatible(_))
+        ));
     }
 }
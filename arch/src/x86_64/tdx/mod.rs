@@ -32,6 +32,14 @@ pub enum TdvfError {
 const TABLE_FOOTER_GUID: &str = "96b582de-1fb2-45f7-baea-a366c55a082d";
 const TDVF_METADATA_OFFSET_GUID: &str = "e47a6535-984a-4798-865e-4685a7bf8ec2";
 
+// Resource types of the HOB resource descriptors, from the UEFI PI spec.
+const EFI_RESOURCE_SYSTEM_MEMORY: u32 = 0x0;
+const EFI_RESOURCE_MEMORY_MAPPED_IO: u32 = 0x1;
+const EFI_RESOURCE_MEMORY_RESERVED: u32 = 0x5;
+// Memory the guest must accept before use, reported by the firmware to the
+// OS as EFI_UNACCEPTED_MEMORY so that it can be accepted lazily.
+const EFI_RESOURCE_MEMORY_UNACCEPTED: u32 = 0x7;
+
 // TDVF_DESCRIPTOR
 #[repr(packed)]
 #[derive(Default)]
@@ -392,14 +400,14 @@ impl TdHob {
             resource_length,
             if ram {
                 if guid_found {
-                    0x7 /* EFI_RESOURCE_MEMORY_UNACCEPTED */
+                    EFI_RESOURCE_MEMORY_UNACCEPTED
                 } else {
-                    0 /* EFI_RESOURCE_SYSTEM_MEMORY */
+                    EFI_RESOURCE_SYSTEM_MEMORY
                 }
             } else if guid_found {
-                0 /* EFI_RESOURCE_SYSTEM_MEMORY */
+                EFI_RESOURCE_SYSTEM_MEMORY
             } else {
-                0x5 /*EFI_RESOURCE_MEMORY_RESERVED */
+                EFI_RESOURCE_MEMORY_RESERVED
             },
             /* TODO:
              * QEMU currently fills it in like this:
@@ -420,7 +428,7 @@ impl TdHob {
             mem,
             physical_start,
             resource_length,
-            0x1, /* EFI_RESOURCE_MEMORY_MAPPED_IO */
+            EFI_RESOURCE_MEMORY_MAPPED_IO,
             /*
              * EFI_RESOURCE_ATTRIBUTE_PRESENT | EFI_RESOURCE_ATTRIBUTE_INITIALIZED | EFI_RESOURCE_ATTRIBUTE_UNCACHEABLE
              */
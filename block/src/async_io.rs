@@ -24,6 +24,9 @@ pub trait DiskFile: Send {
     fn topology(&mut self) -> DiskTopology {
         DiskTopology::default()
     }
+    fn supports_secure_erase(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Error, Debug)]
@@ -37,6 +40,12 @@ pub enum AsyncIoError {
     /// Failed synchronizing file.
     #[error("Failed synchronizing file: {0}")]
     Fsync(#[source] std::io::Error),
+    /// Failed securely erasing a range of the file.
+    #[error("Failed securely erasing a range of the file: {0}")]
+    SecureErase(#[source] std::io::Error),
+    /// Failed cancelling a request.
+    #[error("Failed cancelling a request: {0}")]
+    Cancel(#[source] std::io::Error),
 }
 
 pub type AsyncIoResult<T> = std::result::Result<T, AsyncIoError>;
@@ -57,4 +66,19 @@ pub trait AsyncIo: Send {
     ) -> AsyncIoResult<()>;
     fn fsync(&mut self, user_data: Option<u64>) -> AsyncIoResult<()>;
     fn next_completed_request(&mut self) -> Option<(u64, i32)>;
+    // Erasing is done synchronously, as neither BLKSECDISCARD nor fallocate
+    // are available through all the asynchronous backends.
+    fn secure_erase(&mut self, _offset: u64, _length: u64) -> AsyncIoResult<()> {
+        Err(AsyncIoError::SecureErase(
+            std::io::Error::from_raw_os_error(libc::EOPNOTSUPP),
+        ))
+    }
+    // Ask for a pending request to be cancelled. The request is still owned
+    // by the backend until it shows up through next_completed_request(),
+    // with -ECANCELED as result if the cancellation succeeded.
+    fn cancel(&mut self, _user_data: u64) -> AsyncIoResult<()> {
+        Err(AsyncIoError::Cancel(std::io::Error::from_raw_os_error(
+            libc::EOPNOTSUPP,
+        )))
+    }
 }
@@ -0,0 +1,339 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transparent encryption of disk images with AES-256-XTS.
+//!
+//! Every 512 bytes sector is encrypted independently, using its index on the
+//! virtual disk as tweak. This is the layout of the dm-crypt `aes-xts-plain64`
+//! cipher, meaning an encrypted raw image can be opened on the host with
+//! `cryptsetup open --type plain --cipher aes-xts-plain64 --key-size 512
+//! --key-file <key_file> <image> <name>`.
+//!
+//! The encryption applies to the guest data only, the metadata of the
+//! underlying image format (e.g. QCOW2) is left in the clear.
+
+use crate::{BlockBackend, SECTOR_SIZE};
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes256, Block};
+use std::fmt::{self, Debug};
+use std::io::{self, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+use thiserror::Error;
+
+/// Size of the key expected from the key file: two AES-256 keys, the first
+/// one for the data and the second one for the tweak.
+pub const KEY_SIZE: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum CryptError {
+    #[error("Invalid key size {0}, expecting {KEY_SIZE} bytes")]
+    InvalidKeySize(usize),
+    #[error("Failed getting the disk size: {0}")]
+    DiskSize(#[source] crate::Error),
+    #[error("Disk size {0} is not a multiple of the sector size")]
+    UnalignedDiskSize(u64),
+}
+
+// AES-256-XTS cipher as defined by IEEE 1619. There's no ciphertext stealing
+// since the data units are whole sectors. The expanded keys are wiped from
+// memory when dropped.
+struct Xts {
+    data: Aes256,
+    tweak: Aes256,
+}
+
+impl Xts {
+    fn new(key: &[u8]) -> Result<Self, CryptError> {
+        if key.len() != KEY_SIZE {
+            return Err(CryptError::InvalidKeySize(key.len()));
+        }
+
+        let (data_key, tweak_key) = key.split_at(KEY_SIZE / 2);
+        Ok(Xts {
+            data: Aes256::new_from_slice(data_key)
+                .map_err(|_| CryptError::InvalidKeySize(key.len()))?,
+            tweak: Aes256::new_from_slice(tweak_key)
+                .map_err(|_| CryptError::InvalidKeySize(key.len()))?,
+        })
+    }
+
+    fn encrypt(&self, buf: &mut [u8], first_sector: u64) {
+        self.process(buf, first_sector, |block| self.data.encrypt_block(block));
+    }
+
+    fn decrypt(&self, buf: &mut [u8], first_sector: u64) {
+        self.process(buf, first_sector, |block| self.data.decrypt_block(block));
+    }
+
+    // Apply `cipher` to every block of the sectors in `buf`, whose length is
+    // a multiple of the sector size. The tweak of the first block of a sector
+    // is its encrypted index, the one of the following blocks is the previous
+    // tweak multiplied by the primitive element of GF(2^128).
+    fn process(&self, buf: &mut [u8], first_sector: u64, cipher: impl Fn(&mut Block)) {
+        for (i, sector) in buf.chunks_exact_mut(SECTOR_SIZE as usize).enumerate() {
+            let mut tweak = Block::from((first_sector as u128 + i as u128).to_le_bytes());
+            self.tweak.encrypt_block(&mut tweak);
+            let mut tweak = u128::from_le_bytes(tweak.into());
+
+            for block in sector.chunks_exact_mut(16) {
+                let value = u128::from_le_bytes(block.try_into().unwrap()) ^ tweak;
+                let mut value = Block::from(value.to_le_bytes());
+                cipher(&mut value);
+                let value = u128::from_le_bytes(value.into()) ^ tweak;
+                block.copy_from_slice(&value.to_le_bytes());
+
+                tweak = (tweak << 1) ^ if tweak >> 127 != 0 { 0x87 } else { 0 };
+            }
+        }
+    }
+}
+
+pub struct CryptFile {
+    inner: Box<dyn BlockBackend>,
+    xts: Xts,
+    size: u64,
+    position: u64,
+}
+
+impl CryptFile {
+    pub fn new(inner: Box<dyn BlockBackend>, key: &[u8]) -> Result<Self, CryptError> {
+        let xts = Xts::new(key)?;
+
+        let size = inner.size().map_err(CryptError::DiskSize)?;
+        if size % SECTOR_SIZE != 0 {
+            return Err(CryptError::UnalignedDiskSize(size));
+        }
+
+        Ok(CryptFile {
+            inner,
+            xts,
+            size,
+            position: 0,
+        })
+    }
+
+    // Read and decrypt the sectors covering `[start, start + buf.len())`,
+    // with `start` and the length of `buf` being sector aligned.
+    fn read_sectors(&mut self, start: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(start))?;
+        self.inner.read_exact(buf)?;
+        self.xts.decrypt(buf, start / SECTOR_SIZE);
+        Ok(())
+    }
+
+    // Encrypt and write the sectors covering `[start, start + buf.len())`,
+    // with `start` and the length of `buf` being sector aligned.
+    fn write_sectors(&mut self, start: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.xts.encrypt(buf, start / SECTOR_SIZE);
+        self.inner.seek(SeekFrom::Start(start))?;
+        self.inner.write_all(buf)
+    }
+
+    // Compute the sector aligned span covering `len` bytes from the current
+    // position.
+    fn aligned_span(&self, len: usize) -> (u64, usize) {
+        let start = self.position - self.position % SECTOR_SIZE;
+        let end = (self.position + len as u64).div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+        (start, (end - start) as usize)
+    }
+}
+
+impl Debug for CryptFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Keep the keys out of the logs.
+        f.debug_struct("CryptFile")
+            .field("inner", &self.inner)
+            .field("size", &self.size)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl Read for CryptFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len() as u64, self.size.saturating_sub(self.position)) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let (start, span_len) = self.aligned_span(len);
+        let mut data = vec![0u8; span_len];
+        self.read_sectors(start, &mut data)?;
+
+        let offset = (self.position - start) as usize;
+        buf[..len].copy_from_slice(&data[offset..offset + len]);
+        self.position += len as u64;
+
+        Ok(len)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        let mut total = 0;
+        for buf in bufs.iter_mut() {
+            let len = self.read(buf)?;
+            total += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+}
+
+impl Write for CryptFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.position + buf.len() as u64 > self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Write beyond the end of the encrypted disk",
+            ));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let (start, span_len) = self.aligned_span(buf.len());
+        let offset = (self.position - start) as usize;
+        let mut data = vec![0u8; span_len];
+        // Partially written sectors must be decrypted first so that the
+        // untouched bytes are preserved.
+        if offset != 0 || buf.len() != span_len {
+            self.read_sectors(start, &mut data)?;
+        }
+        data[offset..offset + buf.len()].copy_from_slice(buf);
+        self.write_sectors(start, &mut data)?;
+        self.position += buf.len() as u64;
+
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let mut total = 0;
+        for buf in bufs.iter() {
+            total += self.write(buf)?;
+        }
+        Ok(total)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for CryptFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        self.position = new_position;
+        Ok(self.position)
+    }
+}
+
+impl BlockBackend for CryptFile {
+    fn size(&self) -> Result<u64, crate::Error> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qcow::RawFile;
+    use vmm_sys_util::tempfile::TempFile;
+
+    const DISK_SIZE: u64 = 0x10_0000;
+
+    fn crypt_file(file: &std::fs::File, key: &[u8]) -> CryptFile {
+        let raw = RawFile::new(file.try_clone().unwrap(), false);
+        CryptFile::new(Box::new(raw), key).unwrap()
+    }
+
+    #[test]
+    fn test_xts() {
+        // Reference values from another AES-256-XTS implementation.
+        let key: Vec<u8> = (0..KEY_SIZE as u8).collect();
+        let plain: Vec<u8> = (0..1024u32).map(|i| (i * 7) as u8).collect();
+        let xts = Xts::new(&key).unwrap();
+
+        let mut buf = plain.clone();
+        xts.encrypt(&mut buf, 5);
+        assert_eq!(
+            buf[..16],
+            0x35af6869_01a5cb6d_f9c180d9_9226dfef_u128.to_be_bytes()
+        );
+        assert_eq!(
+            buf[496..512],
+            0xad77d4e6_33d77747_e0390665_f9c05226_u128.to_be_bytes()
+        );
+        assert_eq!(
+            buf[512..528],
+            0x37373b13_bb335f99_95f64578_0834ce75_u128.to_be_bytes()
+        );
+
+        xts.decrypt(&mut buf, 5);
+        assert_eq!(buf, plain);
+    }
+
+    #[test]
+    fn test_crypt_file() {
+        let key: Vec<u8> = (0..KEY_SIZE as u8).collect();
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(DISK_SIZE).unwrap();
+
+        assert!(matches!(
+            CryptFile::new(
+                Box::new(RawFile::new(file.try_clone().unwrap(), false)),
+                &key[..32]
+            ),
+            Err(CryptError::InvalidKeySize(32))
+        ));
+
+        // Unaligned write spanning two sectors, followed by an aligned one.
+        let mut disk = crypt_file(&file, &key);
+        disk.seek(SeekFrom::Start(500)).unwrap();
+        disk.write_all(&[0xaa; 100]).unwrap();
+        disk.seek(SeekFrom::Start(4096)).unwrap();
+        disk.write_all(&[0x55; 512]).unwrap();
+        disk.seek(SeekFrom::End(0)).unwrap();
+        assert!(disk.write(&[0; 1]).is_err());
+
+        // The data on the underlying file is not in the clear.
+        let mut raw = RawFile::new(file.try_clone().unwrap(), false);
+        let mut encrypted = vec![0u8; 512];
+        raw.seek(SeekFrom::Start(4096)).unwrap();
+        raw.read_exact(&mut encrypted).unwrap();
+        assert_ne!(encrypted, vec![0x55; 512]);
+
+        // But can be read back with the same key.
+        let mut disk = crypt_file(&file, &key);
+        let mut data = vec![0u8; 100];
+        disk.seek(SeekFrom::Start(500)).unwrap();
+        disk.read_exact(&mut data).unwrap();
+        assert_eq!(data, vec![0xaa; 100]);
+        let mut data = vec![0u8; 512];
+        disk.seek(SeekFrom::Start(4096)).unwrap();
+        disk.read_exact(&mut data).unwrap();
+        assert_eq!(data, vec![0x55; 512]);
+
+        // Reading past the end of the disk returns nothing.
+        disk.seek(SeekFrom::End(0)).unwrap();
+        assert_eq!(disk.read(&mut data).unwrap(), 0);
+
+        // While another key gives garbage.
+        let other_key = vec![0x42u8; KEY_SIZE];
+        let mut disk = crypt_file(&file, &other_key);
+        disk.seek(SeekFrom::Start(4096)).unwrap();
+        disk.read_exact(&mut data).unwrap();
+        assert_ne!(data, vec![0x55; 512]);
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::async_io::{AsyncIo, AsyncIoResult, DiskFile, DiskFileResult};
+use crate::crypt::{CryptError, CryptFile};
+use crate::{AsyncAdaptor, BlockBackend};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use vmm_sys_util::eventfd::EventFd;
+
+pub struct CryptDiskSync {
+    crypt_file: Arc<Mutex<CryptFile>>,
+}
+
+impl CryptDiskSync {
+    pub fn new(inner: Box<dyn BlockBackend>, key: &[u8]) -> Result<Self, CryptError> {
+        Ok(CryptDiskSync {
+            crypt_file: Arc::new(Mutex::new(CryptFile::new(inner, key)?)),
+        })
+    }
+}
+
+impl DiskFile for CryptDiskSync {
+    fn size(&mut self) -> DiskFileResult<u64> {
+        // The size of the disk is fixed once the encryption is set up.
+        Ok(self.crypt_file.lock().unwrap().size().unwrap())
+    }
+
+    fn new_async_io(&self, _ring_depth: u32) -> DiskFileResult<Box<dyn AsyncIo>> {
+        Ok(Box::new(CryptSync::new(self.crypt_file.clone())) as Box<dyn AsyncIo>)
+    }
+}
+
+pub struct CryptSync {
+    crypt_file: Arc<Mutex<CryptFile>>,
+    eventfd: EventFd,
+    completion_list: VecDeque<(u64, i32)>,
+}
+
+impl CryptSync {
+    pub fn new(crypt_file: Arc<Mutex<CryptFile>>) -> Self {
+        CryptSync {
+            crypt_file,
+            eventfd: EventFd::new(libc::EFD_NONBLOCK)
+                .expect("Failed creating EventFd for CryptSync"),
+            completion_list: VecDeque::new(),
+        }
+    }
+}
+
+impl AsyncAdaptor<CryptFile> for Arc<Mutex<CryptFile>> {
+    fn file(&mut self) -> MutexGuard<CryptFile> {
+        self.lock().unwrap()
+    }
+}
+
+impl AsyncIo for CryptSync {
+    fn notifier(&self) -> &EventFd {
+        &self.eventfd
+    }
+
+    fn read_vectored(
+        &mut self,
+        offset: libc::off_t,
+        iovecs: &[libc::iovec],
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.crypt_file.read_vectored_sync(
+            offset,
+            iovecs,
+            user_data,
+            &self.eventfd,
+            &mut self.completion_list,
+        )
+    }
+
+    fn write_vectored(
+        &mut self,
+        offset: libc::off_t,
+        iovecs: &[libc::iovec],
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.crypt_file.write_vectored_sync(
+            offset,
+            iovecs,
+            user_data,
+            &self.eventfd,
+            &mut self.completion_list,
+        )
+    }
+
+    fn fsync(&mut self, user_data: Option<u64>) -> AsyncIoResult<()> {
+        self.crypt_file
+            .fsync_sync(user_data, &self.eventfd, &mut self.completion_list)
+    }
+
+    fn next_completed_request(&mut self) -> Option<(u64, i32)> {
+        self.completion_list.pop_front()
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Advisory locking of disk images relying on open file description (OFD)
+//! locks, see fcntl(2).
+//!
+//! OFD locks are tied to the open file description rather than to the
+//! process, which means they are shared by every duplicate of the file
+//! descriptor and only released once all of them are closed.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error("The image is locked by another process")]
+    AlreadyLocked,
+    #[error("Failed acquiring the image lock: {0}")]
+    Acquire(#[source] io::Error),
+    #[error("Failed releasing the image lock: {0}")]
+    Release(#[source] io::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockType {
+    /// Shared lock, can be held concurrently with other read locks.
+    Read,
+    /// Exclusive lock, conflicts with any other lock.
+    Write,
+}
+
+fn set_ofd_lock(file: &File, l_type: libc::c_int) -> io::Result<()> {
+    // SAFETY: all-zero is a valid value for the flock structure
+    let mut flock: libc::flock = unsafe { std::mem::zeroed() };
+    flock.l_type = l_type as libc::c_short;
+    flock.l_whence = libc::SEEK_SET as libc::c_short;
+    // A zero l_start and l_len lock the whole file, no matter its size.
+    flock.l_start = 0;
+    flock.l_len = 0;
+
+    // SAFETY: FFI call with a valid fd and flock structure
+    let ret = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_SETLK, &flock) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Lock guarding a disk image against concurrent writable attachments.
+pub struct ImageLock {
+    file: File,
+    lock_type: LockType,
+    locked: bool,
+}
+
+impl ImageLock {
+    /// Create a lock on the image, the lock is not acquired until
+    /// [`ImageLock::acquire`] is called.
+    pub fn new(file: File, lock_type: LockType) -> Self {
+        ImageLock {
+            file,
+            lock_type,
+            locked: false,
+        }
+    }
+
+    pub fn lock_type(&self) -> LockType {
+        self.lock_type
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Try acquiring the lock without blocking. This is a no-op if the lock
+    /// is already held.
+    pub fn acquire(&mut self) -> Result<(), LockError> {
+        if self.locked {
+            return Ok(());
+        }
+
+        let l_type = match self.lock_type {
+            LockType::Read => libc::F_RDLCK,
+            LockType::Write => libc::F_WRLCK,
+        };
+        set_ofd_lock(&self.file, l_type).map_err(|e| match e.raw_os_error() {
+            Some(libc::EAGAIN) | Some(libc::EACCES) => LockError::AlreadyLocked,
+            _ => LockError::Acquire(e),
+        })?;
+        self.locked = true;
+
+        Ok(())
+    }
+
+    /// Release the lock. This is a no-op if the lock is not held.
+    pub fn release(&mut self) -> Result<(), LockError> {
+        if !self.locked {
+            return Ok(());
+        }
+
+        set_ofd_lock(&self.file, libc::F_UNLCK).map_err(LockError::Release)?;
+        self.locked = false;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use vmm_sys_util::tempfile::TempFile;
+
+    fn open(path: &std::path::Path) -> File {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_image_lock() {
+        let temp_file = TempFile::new().unwrap();
+        let path = temp_file.as_path();
+
+        // Several read locks can be held at the same time.
+        let mut read_lock = ImageLock::new(open(path), LockType::Read);
+        read_lock.acquire().unwrap();
+        let mut other_read_lock = ImageLock::new(open(path), LockType::Read);
+        other_read_lock.acquire().unwrap();
+
+        // A write lock conflicts with existing read locks.
+        let mut write_lock = ImageLock::new(open(path), LockType::Write);
+        assert!(matches!(
+            write_lock.acquire(),
+            Err(LockError::AlreadyLocked)
+        ));
+
+        read_lock.release().unwrap();
+        other_read_lock.release().unwrap();
+        write_lock.acquire().unwrap();
+        assert!(write_lock.locked());
+
+        // And read locks conflict with an existing write lock.
+        assert!(matches!(read_lock.acquire(), Err(LockError::AlreadyLocked)));
+
+        // Dropping the file releases the lock.
+        drop(write_lock);
+        read_lock.acquire().unwrap();
+    }
+}
@@ -100,4 +100,8 @@ impl AsyncIo for FixedVhdAsync {
     fn next_completed_request(&mut self) -> Option<(u64, i32)> {
         self.raw_file_async.next_completed_request()
     }
+
+    fn cancel(&mut self, user_data: u64) -> AsyncIoResult<()> {
+        self.raw_file_async.cancel(user_data)
+    }
 }
@@ -0,0 +1,705 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read-only disk images served over HTTP.
+//!
+//! The image is fetched lazily, by chunks of [`CHUNK_SIZE`] bytes requested
+//! through HTTP range requests, and kept in a local cache file so that every
+//! chunk is only fetched once. The cache file holds the image data at the same
+//! offsets, followed by a bitmap of the chunks already fetched and by the
+//! validator (ETag or Last-Modified) of the image, which lets the cache be
+//! reused by the next boots of the VM as long as the image is unchanged.
+//!
+//! The requests are sent by a dedicated thread, as the virtio-blk threads
+//! aren't allowed to use sockets. TLS is left to a local proxy, so only
+//! `http://` URLs are supported.
+
+use crate::BlockBackend;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::fs::FileExt;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Size of the ranges fetched from the server.
+pub const CHUNK_SIZE: u64 = 1 << 20;
+/// Largest image accepted, which bounds the cache file and the bitmap of the
+/// fetched chunks kept in memory.
+pub const MAX_IMAGE_SIZE: u64 = 16 << 40;
+const TIMEOUT: Duration = Duration::from_secs(30);
+// Room left for the validator at the end of the cache file, prefixed by its
+// 16-bit length.
+const VALIDATOR_LEN: u64 = 256;
+
+#[derive(Error, Debug)]
+pub enum HttpError {
+    #[error("Invalid URL {0}")]
+    InvalidUrl(String),
+    #[error("Unsupported scheme in URL {0}, only http:// is supported")]
+    UnsupportedScheme(String),
+    #[error("Failed resolving {0}: {1}")]
+    Resolve(String, #[source] io::Error),
+    #[error("Failed connecting to the server: {0}")]
+    Connect(#[source] io::Error),
+    #[error("Failed communicating with the server: {0}")]
+    Io(#[source] io::Error),
+    #[error("Invalid HTTP response: {0}")]
+    InvalidResponse(String),
+    #[error("Unexpected HTTP status {0}, the server must support range requests")]
+    Status(u16),
+    #[error("Image size {0} is larger than the supported maximum ({MAX_IMAGE_SIZE})")]
+    ImageTooLarge(u64),
+    #[error("The image changed on the server")]
+    ImageChanged,
+    #[error("Failed setting up the cache file: {0}")]
+    Cache(#[source] io::Error),
+    #[error("Failed spawning the HTTP disk thread: {0}")]
+    SpawnThread(#[source] io::Error),
+}
+
+/// Whether the disk path is an URL rather than a local file.
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Self, HttpError> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            if url.contains("://") {
+                HttpError::UnsupportedScheme(url.to_owned())
+            } else {
+                HttpError::InvalidUrl(url.to_owned())
+            }
+        })?;
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() || authority.contains('@') {
+            return Err(HttpError::InvalidUrl(url.to_owned()));
+        }
+
+        // IPv6 addresses are enclosed in brackets, as they hold colons.
+        let port_index = match authority.rfind(']') {
+            Some(index) => authority[index..].find(':').map(|i| index + i),
+            None => authority.find(':'),
+        };
+        let (host, port) = match port_index {
+            Some(index) => (
+                &authority[..index],
+                authority[index + 1..]
+                    .parse()
+                    .map_err(|_| HttpError::InvalidUrl(url.to_owned()))?,
+            ),
+            None => (authority, 80),
+        };
+
+        Ok(Url {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+
+    fn resolve(&self) -> Result<SocketAddr, HttpError> {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        (host, self.port)
+            .to_socket_addrs()
+            .and_then(|mut addrs| {
+                addrs
+                    .next()
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+            })
+            .map_err(|e| HttpError::Resolve(self.host.clone(), e))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct RangeResponse {
+    data: Vec<u8>,
+    // Size of the whole image.
+    size: u64,
+    // Strong ETag, or Last-Modified date, identifying the image version.
+    validator: Option<String>,
+}
+
+// Parses the response to a range request for [start, end).
+fn read_response<R: BufRead>(
+    reader: &mut R,
+    start: u64,
+    end: u64,
+) -> Result<RangeResponse, HttpError> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(HttpError::Io)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| HttpError::InvalidResponse(line.trim_end().to_owned()))?;
+    if status != 206 {
+        return Err(HttpError::Status(status));
+    }
+
+    let mut content_length = None;
+    let mut content_range = None;
+    let mut etag = None;
+    let mut last_modified = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(HttpError::Io)? == 0 {
+            return Err(HttpError::InvalidResponse("truncated headers".to_owned()));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(HttpError::InvalidResponse(header.to_owned()));
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse::<u64>().ok(),
+            "content-range" => content_range = Some(value.to_owned()),
+            // Weak ETags don't guarantee the bytes are the same.
+            "etag" if !value.starts_with("W/") => etag = Some(value.to_owned()),
+            "last-modified" => last_modified = Some(value.to_owned()),
+            "transfer-encoding" if !value.eq_ignore_ascii_case("identity") => {
+                return Err(HttpError::InvalidResponse(format!(
+                    "unsupported transfer encoding {value}"
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    // Content-Range: bytes <first>-<last>/<size>
+    let content_range =
+        content_range.ok_or_else(|| HttpError::InvalidResponse("no Content-Range".to_owned()))?;
+    let range = content_range
+        .strip_prefix("bytes ")
+        .and_then(|range| range.split_once('/'))
+        .and_then(|(range, size)| {
+            let (first, last) = range.split_once('-')?;
+            Some((
+                first.parse::<u64>().ok()?,
+                last.parse::<u64>().ok()?,
+                size.parse::<u64>().ok()?,
+            ))
+        });
+    let Some((first, last, size)) = range else {
+        return Err(HttpError::InvalidResponse(format!(
+            "invalid Content-Range {content_range}"
+        )));
+    };
+    // The range must lie within the image, which can't end before the
+    // requested range starts.
+    if first > last || last >= size || start >= size {
+        return Err(HttpError::InvalidResponse(format!(
+            "invalid Content-Range {content_range}"
+        )));
+    }
+    let length = last - first + 1;
+    if first != start || last + 1 != end.min(size) {
+        return Err(HttpError::InvalidResponse(format!(
+            "range {content_range} doesn't match the requested {start}-{}",
+            end - 1
+        )));
+    }
+    if content_length.is_some_and(|content_length| content_length != length) {
+        return Err(HttpError::InvalidResponse(
+            "Content-Length doesn't match Content-Range".to_owned(),
+        ));
+    }
+
+    let mut data = vec![0u8; length as usize];
+    reader.read_exact(&mut data).map_err(HttpError::Io)?;
+
+    Ok(RangeResponse {
+        data,
+        size,
+        validator: etag.or(last_modified),
+    })
+}
+
+// Serializes the validator as stored at the end of the cache file, if it fits.
+fn encode_validator(validator: Option<&str>) -> Option<Vec<u8>> {
+    let validator = validator?.as_bytes();
+    if validator.len() + 2 > VALIDATOR_LEN as usize {
+        return None;
+    }
+    let mut data = (validator.len() as u16).to_le_bytes().to_vec();
+    data.extend_from_slice(validator);
+    data.resize(VALIDATOR_LEN as usize, 0);
+    Some(data)
+}
+
+// Fetches [start, end) of the image, over a connection of its own.
+fn get_range(
+    url: &Url,
+    addr: &SocketAddr,
+    start: u64,
+    end: u64,
+) -> Result<RangeResponse, HttpError> {
+    let mut stream = TcpStream::connect_timeout(addr, TIMEOUT).map_err(HttpError::Connect)?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(HttpError::Connect)?;
+
+    let host = if url.port == 80 {
+        url.host.clone()
+    } else {
+        format!("{}:{}", url.host, url.port)
+    };
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {host}\r\nRange: bytes={start}-{}\r\nConnection: close\r\n\r\n",
+        url.path,
+        end - 1
+    )
+    .map_err(HttpError::Io)?;
+
+    read_response(&mut BufReader::new(stream), start, end)
+}
+
+struct FetchRequest {
+    start: u64,
+    end: u64,
+    reply: Sender<Result<Vec<u8>, HttpError>>,
+}
+
+#[derive(Debug)]
+pub struct HttpFile {
+    cache: File,
+    size: u64,
+    // One bit per chunk, set once the chunk is in the cache file.
+    fetched: Vec<u8>,
+    fetcher: Sender<FetchRequest>,
+    position: u64,
+}
+
+impl HttpFile {
+    pub fn new(url: &str, cache: File) -> Result<Self, HttpError> {
+        let url = Url::parse(url)?;
+        let addr = url.resolve()?;
+        let RangeResponse {
+            size, validator, ..
+        } = get_range(&url, &addr, 0, 1)?;
+        if size > MAX_IMAGE_SIZE {
+            return Err(HttpError::ImageTooLarge(size));
+        }
+
+        let bitmap_len = size.div_ceil(CHUNK_SIZE).div_ceil(8);
+        let mut fetched = vec![0u8; bitmap_len as usize];
+        let cache_len = size + bitmap_len + VALIDATOR_LEN;
+        let encoded_validator = encode_validator(validator.as_deref());
+        let mut stored_validator = vec![0u8; VALIDATOR_LEN as usize];
+        let reusable = encoded_validator.is_some()
+            && cache.metadata().map_err(HttpError::Cache)?.len() == cache_len
+            && cache
+                .read_exact_at(&mut stored_validator, size + bitmap_len)
+                .is_ok()
+            && encoded_validator.as_ref() == Some(&stored_validator);
+        if reusable {
+            cache
+                .read_exact_at(&mut fetched, size)
+                .map_err(HttpError::Cache)?;
+        } else {
+            // Not the cache of this version of the image, or no way to tell,
+            // start from scratch.
+            cache
+                .set_len(0)
+                .and_then(|_| cache.set_len(cache_len))
+                .map_err(HttpError::Cache)?;
+            if let Some(encoded_validator) = &encoded_validator {
+                cache
+                    .write_all_at(encoded_validator, size + bitmap_len)
+                    .map_err(HttpError::Cache)?;
+            }
+        }
+
+        let (fetcher, requests) = channel::<FetchRequest>();
+        thread::Builder::new()
+            .name("http_disk".to_string())
+            .spawn(move || {
+                for request in requests {
+                    // Chunks of another version of the image would corrupt
+                    // the disk.
+                    let result =
+                        get_range(&url, &addr, request.start, request.end).and_then(|response| {
+                            if response.size != size || response.validator != validator {
+                                return Err(HttpError::ImageChanged);
+                            }
+                            Ok(response.data)
+                        });
+                    request.reply.send(result).ok();
+                }
+            })
+            .map_err(HttpError::SpawnThread)?;
+
+        Ok(HttpFile {
+            cache,
+            size,
+            fetched,
+            fetcher,
+            position: 0,
+        })
+    }
+
+    fn is_fetched(&self, chunk: u64) -> bool {
+        self.fetched[(chunk / 8) as usize] & (1 << (chunk % 8)) != 0
+    }
+
+    fn fetch_chunks(&mut self, first: u64, last: u64) -> io::Result<()> {
+        let start = first * CHUNK_SIZE;
+        let end = std::cmp::min((last + 1) * CHUNK_SIZE, self.size);
+        let (reply, result) = channel();
+        self.fetcher
+            .send(FetchRequest { start, end, reply })
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "HTTP disk thread exited"))?;
+        let data = result
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "HTTP disk thread exited"))?
+            .map_err(io::Error::other)?;
+        self.cache.write_all_at(&data, start)?;
+
+        for chunk in first..=last {
+            self.fetched[(chunk / 8) as usize] |= 1 << (chunk % 8);
+        }
+        let bitmap = (first / 8) as usize..=(last / 8) as usize;
+        self.cache.write_all_at(
+            &self.fetched[bitmap.clone()],
+            self.size + *bitmap.start() as u64,
+        )
+    }
+
+    // Fetches the chunks covering [offset, offset + len) missing from the
+    // cache, with one request per run of missing chunks.
+    fn fetch(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mut missing: Option<u64> = None;
+        let last = (offset + len - 1) / CHUNK_SIZE;
+        for chunk in offset / CHUNK_SIZE..=last {
+            match (self.is_fetched(chunk), missing) {
+                (false, None) => missing = Some(chunk),
+                (true, Some(first)) => {
+                    self.fetch_chunks(first, chunk - 1)?;
+                    missing = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(first) = missing {
+            self.fetch_chunks(first, last)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for HttpFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len() as u64, self.size.saturating_sub(self.position)) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        self.fetch(self.position, len as u64)?;
+        self.cache.read_exact_at(&mut buf[..len], self.position)?;
+        self.position += len as u64;
+
+        Ok(len)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        // Fetched at once, rather than buffer by buffer.
+        let len = bufs.iter().map(|buf| buf.len() as u64).sum::<u64>();
+        self.fetch(
+            self.position,
+            std::cmp::min(len, self.size.saturating_sub(self.position)),
+        )?;
+
+        let mut total = 0;
+        for buf in bufs.iter_mut() {
+            let len = self.read(buf)?;
+            total += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+}
+
+impl Write for HttpFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(libc::EROFS))
+    }
+
+    fn write_vectored(&mut self, _bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(libc::EROFS))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for HttpFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        self.position = new_position;
+        Ok(self.position)
+    }
+}
+
+impl BlockBackend for HttpFile {
+    fn size(&self) -> Result<u64, crate::Error> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            Url::parse("http://example.com/images/disk.raw").unwrap(),
+            Url {
+                host: "example.com".to_owned(),
+                port: 80,
+                path: "/images/disk.raw".to_owned(),
+            }
+        );
+        assert_eq!(
+            Url::parse("http://[::1]:8080").unwrap(),
+            Url {
+                host: "[::1]".to_owned(),
+                port: 8080,
+                path: "/".to_owned(),
+            }
+        );
+        assert!(matches!(
+            Url::parse("https://example.com/disk.raw"),
+            Err(HttpError::UnsupportedScheme(_))
+        ));
+        assert!(matches!(
+            Url::parse("http://example.com:port/disk.raw"),
+            Err(HttpError::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            Url::parse("http://user@example.com/disk.raw"),
+            Err(HttpError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_response() {
+        let response = "HTTP/1.1 206 Partial Content\r\n\
+                        Content-Length: 4\r\n\
+                        Content-Range: bytes 8-11/4096\r\n\
+                        ETag: \"v1\"\r\n\
+                        \r\n\
+                        data";
+        assert_eq!(
+            read_response(&mut Cursor::new(response), 8, 12).unwrap(),
+            RangeResponse {
+                data: b"data".to_vec(),
+                size: 4096,
+                validator: Some("\"v1\"".to_owned()),
+            }
+        );
+        assert!(matches!(
+            read_response(&mut Cursor::new(response), 0, 4),
+            Err(HttpError::InvalidResponse(_))
+        ));
+
+        // Weak ETags are ignored in favor of Last-Modified.
+        let response = "HTTP/1.1 206 Partial Content\r\n\
+                        Content-Range: bytes 0-3/4096\r\n\
+                        ETag: W/\"v1\"\r\n\
+                        Last-Modified: Tue, 15 Oct 2024 07:28:00 GMT\r\n\
+                        \r\n\
+                        data";
+        assert_eq!(
+            read_response(&mut Cursor::new(response), 0, 4)
+                .unwrap()
+                .validator
+                .as_deref(),
+            Some("Tue, 15 Oct 2024 07:28:00 GMT")
+        );
+
+        // Ranges which don't fit in the image, or would overflow.
+        for content_range in [
+            "bytes 8-11/4",
+            "bytes 8-7/4096",
+            "bytes 0-18446744073709551615/18446744073709551615",
+            "bytes 18446744073709551615-18446744073709551615/18446744073709551615",
+        ] {
+            let response =
+                format!("HTTP/1.1 206 Partial Content\r\nContent-Range: {content_range}\r\n\r\n");
+            assert!(matches!(
+                read_response(&mut Cursor::new(response), 8, 12),
+                Err(HttpError::InvalidResponse(_))
+            ));
+        }
+
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 4096\r\n\r\n";
+        assert!(matches!(
+            read_response(&mut Cursor::new(response), 0, 4),
+            Err(HttpError::Status(200))
+        ));
+    }
+
+    // Serves the ranges of the image, counting the requests.
+    fn serve(image: Vec<u8>, etag: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/disk.raw", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let count = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut range = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(value) = line.trim_end().strip_prefix("Range: bytes=") {
+                        let (first, last) = value.split_once('-').unwrap();
+                        range = Some((first.parse::<usize>().unwrap(), last.parse().unwrap()));
+                    }
+                    line.clear();
+                }
+                count.fetch_add(1, Ordering::SeqCst);
+                let (first, last) = range.unwrap();
+                let last = std::cmp::min(last, image.len() - 1);
+                write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                     Content-Range: bytes {first}-{last}/{}\r\nETag: {etag}\r\n\r\n",
+                    last + 1 - first,
+                    image.len()
+                )
+                .unwrap();
+                stream.write_all(&image[first..=last]).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn test_http_file() {
+        let image: Vec<u8> = (0..3 * CHUNK_SIZE + 512).map(|i| (i / 512) as u8).collect();
+        let (url, requests) = serve(image.clone(), "\"v1\"");
+        let cache = TempFile::new().unwrap();
+
+        let mut disk = HttpFile::new(&url, cache.as_file().try_clone().unwrap()).unwrap();
+        assert_eq!(disk.size().unwrap(), image.len() as u64);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Read across the first two chunks, fetched with a single request.
+        let mut buf = vec![0u8; 4096];
+        disk.seek(SeekFrom::Start(CHUNK_SIZE - 2048)).unwrap();
+        disk.read_exact(&mut buf).unwrap();
+        let offset = (CHUNK_SIZE - 2048) as usize;
+        assert_eq!(buf, image[offset..offset + 4096]);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Already in the cache.
+        disk.seek(SeekFrom::Start(0)).unwrap();
+        disk.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, image[..4096]);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // The last chunk is shorter.
+        let mut buf = vec![0u8; 1024];
+        disk.seek(SeekFrom::End(-512)).unwrap();
+        assert_eq!(disk.read(&mut buf).unwrap(), 512);
+        assert_eq!(buf[..512], image[image.len() - 512..]);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        assert_eq!(
+            disk.write(&buf).unwrap_err().raw_os_error(),
+            Some(libc::EROFS)
+        );
+
+        // The cache is reused by the next attachment.
+        drop(disk);
+        let mut disk = HttpFile::new(&url, cache.as_file().try_clone().unwrap()).unwrap();
+        disk.seek(SeekFrom::Start(CHUNK_SIZE)).unwrap();
+        disk.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, image[CHUNK_SIZE as usize..CHUNK_SIZE as usize + 1024]);
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // But not once the image changed on the server, even with the same
+        // size.
+        drop(disk);
+        let image: Vec<u8> = image.iter().map(|b| !b).collect();
+        let (url, requests) = serve(image.clone(), "\"v2\"");
+        let mut disk = HttpFile::new(&url, cache.as_file().try_clone().unwrap()).unwrap();
+        disk.seek(SeekFrom::Start(CHUNK_SIZE)).unwrap();
+        disk.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, image[CHUNK_SIZE as usize..CHUNK_SIZE as usize + 1024]);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_http_file_too_large() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/disk.raw", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-0/{}\r\n\r\n\0",
+                MAX_IMAGE_SIZE + 1
+            )
+            .unwrap();
+        });
+
+        let cache = TempFile::new().unwrap();
+        assert!(matches!(
+            HttpFile::new(&url, cache.as_file().try_clone().unwrap()),
+            Err(HttpError::ImageTooLarge(size)) if size == MAX_IMAGE_SIZE + 1
+        ));
+        assert_eq!(cache.as_file().metadata().unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::async_io::{AsyncIo, AsyncIoResult, DiskFile, DiskFileResult};
+use crate::http::{HttpError, HttpFile};
+use crate::{AsyncAdaptor, BlockBackend};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::sync::{Arc, Mutex, MutexGuard};
+use vmm_sys_util::eventfd::EventFd;
+
+pub struct HttpDiskSync {
+    http_file: Arc<Mutex<HttpFile>>,
+}
+
+impl HttpDiskSync {
+    pub fn new(url: &str, cache: File) -> Result<Self, HttpError> {
+        Ok(HttpDiskSync {
+            http_file: Arc::new(Mutex::new(HttpFile::new(url, cache)?)),
+        })
+    }
+}
+
+impl DiskFile for HttpDiskSync {
+    fn size(&mut self) -> DiskFileResult<u64> {
+        // The size of the image is retrieved when connecting to the server.
+        Ok(self.http_file.lock().unwrap().size().unwrap())
+    }
+
+    fn new_async_io(&self, _ring_depth: u32) -> DiskFileResult<Box<dyn AsyncIo>> {
+        Ok(Box::new(HttpSync::new(self.http_file.clone())) as Box<dyn AsyncIo>)
+    }
+}
+
+pub struct HttpSync {
+    http_file: Arc<Mutex<HttpFile>>,
+    eventfd: EventFd,
+    completion_list: VecDeque<(u64, i32)>,
+}
+
+impl HttpSync {
+    pub fn new(http_file: Arc<Mutex<HttpFile>>) -> Self {
+        HttpSync {
+            http_file,
+            eventfd: EventFd::new(libc::EFD_NONBLOCK)
+                .expect("Failed creating EventFd for HttpSync"),
+            completion_list: VecDeque::new(),
+        }
+    }
+}
+
+impl AsyncAdaptor<HttpFile> for Arc<Mutex<HttpFile>> {
+    fn file(&mut self) -> MutexGuard<HttpFile> {
+        self.lock().unwrap()
+    }
+}
+
+impl AsyncIo for HttpSync {
+    fn notifier(&self) -> &EventFd {
+        &self.eventfd
+    }
+
+    fn read_vectored(
+        &mut self,
+        offset: libc::off_t,
+        iovecs: &[libc::iovec],
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.http_file.read_vectored_sync(
+            offset,
+            iovecs,
+            user_data,
+            &self.eventfd,
+            &mut self.completion_list,
+        )
+    }
+
+    fn write_vectored(
+        &mut self,
+        offset: libc::off_t,
+        iovecs: &[libc::iovec],
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.http_file.write_vectored_sync(
+            offset,
+            iovecs,
+            user_data,
+            &self.eventfd,
+            &mut self.completion_list,
+        )
+    }
+
+    fn fsync(&mut self, user_data: Option<u64>) -> AsyncIoResult<()> {
+        self.http_file
+            .fsync_sync(user_data, &self.eventfd, &mut self.completion_list)
+    }
+
+    fn next_completed_request(&mut self) -> Option<(u64, i32)> {
+        self.completion_list.pop_front()
+    }
+}
@@ -12,13 +12,19 @@
 extern crate log;
 
 pub mod async_io;
+pub mod crypt;
+pub mod crypt_sync;
+pub mod fcntl;
 pub mod fixed_vhd;
 #[cfg(feature = "io_uring")]
 /// Enabled with the `"io_uring"` feature
 pub mod fixed_vhd_async;
 pub mod fixed_vhd_sync;
+pub mod http;
+pub mod http_sync;
 pub mod qcow;
 pub mod qcow_sync;
+pub mod qos;
 #[cfg(feature = "io_uring")]
 /// Async primitives based on `io-uring`
 ///
@@ -42,13 +48,14 @@ use smallvec::SmallVec;
 use std::alloc::{alloc_zeroed, dealloc, Layout};
 use std::cmp;
 use std::collections::VecDeque;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::fs::File;
 use std::io::{self, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
-use std::os::unix::io::AsRawFd;
-use std::path::Path;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::result;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::MutexGuard;
 use std::time::Instant;
@@ -67,6 +74,12 @@ use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr};
 const SECTOR_SHIFT: u8 = 9;
 pub const SECTOR_SIZE: u64 = 0x01 << SECTOR_SHIFT;
 
+// Added by the virtio 1.3 specification, not part of the bindings yet.
+pub const VIRTIO_BLK_F_LIFETIME: u32 = 15;
+pub const VIRTIO_BLK_F_SECURE_ERASE: u32 = 16;
+pub const VIRTIO_BLK_T_GET_LIFETIME: u32 = 10;
+pub const VIRTIO_BLK_T_SECURE_ERASE: u32 = 14;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Guest gave us bad memory addresses")]
@@ -97,6 +110,10 @@ pub enum Error {
     TooManyDescriptors,
     #[error("Failure in vhdx: {0}")]
     VhdxError(VhdxError),
+    #[error("Conversion to {0} images is not supported")]
+    UnsupportedConversion(ImageType),
+    #[error("Failed converting image: {0}")]
+    ConvertImage(std::io::Error),
 }
 
 fn build_device_id(disk_path: &Path) -> result::Result<String, Error> {
@@ -161,6 +178,8 @@ pub enum ExecuteError {
     AsyncFlush(AsyncIoError),
     #[error("Failed allocating a temporary buffer: {0}")]
     TemporaryBufferAllocation(io::Error),
+    #[error("Failed to secure erase: {0}")]
+    SecureErase(AsyncIoError),
 }
 
 impl ExecuteError {
@@ -180,6 +199,12 @@ impl ExecuteError {
             ExecuteError::AsyncWrite(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::AsyncFlush(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::TemporaryBufferAllocation(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::SecureErase(AsyncIoError::SecureErase(ref e))
+                if e.raw_os_error() == Some(libc::EOPNOTSUPP) =>
+            {
+                VIRTIO_BLK_S_UNSUPP
+            }
+            ExecuteError::SecureErase(_) => VIRTIO_BLK_S_IOERR,
         };
         status as u8
     }
@@ -191,6 +216,8 @@ pub enum RequestType {
     Out,
     Flush,
     GetDeviceId,
+    GetLifetime,
+    SecureErase,
     Unsupported(u32),
 }
 
@@ -204,6 +231,8 @@ pub fn request_type<B: Bitmap + 'static>(
         VIRTIO_BLK_T_OUT => Ok(RequestType::Out),
         VIRTIO_BLK_T_FLUSH => Ok(RequestType::Flush),
         VIRTIO_BLK_T_GET_ID => Ok(RequestType::GetDeviceId),
+        VIRTIO_BLK_T_GET_LIFETIME => Ok(RequestType::GetLifetime),
+        VIRTIO_BLK_T_SECURE_ERASE => Ok(RequestType::SecureErase),
         t => Ok(RequestType::Unsupported(t)),
     }
 }
@@ -289,13 +318,23 @@ impl Request {
         } else {
             req.data_descriptors.reserve_exact(1);
             while desc.has_next() {
-                if desc.is_write_only() && req.request_type == RequestType::Out {
+                if desc.is_write_only()
+                    && matches!(
+                        req.request_type,
+                        RequestType::Out | RequestType::SecureErase
+                    )
+                {
                     return Err(Error::UnexpectedWriteOnlyDescriptor);
                 }
                 if !desc.is_write_only() && req.request_type == RequestType::In {
                     return Err(Error::UnexpectedReadOnlyDescriptor);
                 }
-                if !desc.is_write_only() && req.request_type == RequestType::GetDeviceId {
+                if !desc.is_write_only()
+                    && matches!(
+                        req.request_type,
+                        RequestType::GetDeviceId | RequestType::GetLifetime
+                    )
+                {
                     return Err(Error::UnexpectedReadOnlyDescriptor);
                 }
 
@@ -381,6 +420,12 @@ impl Request {
                     mem.write_slice(serial, *data_addr)
                         .map_err(ExecuteError::Write)?;
                 }
+                RequestType::GetLifetime => {
+                    return Err(ExecuteError::Unsupported(VIRTIO_BLK_T_GET_LIFETIME))
+                }
+                RequestType::SecureErase => {
+                    return Err(ExecuteError::Unsupported(VIRTIO_BLK_T_SECURE_ERASE))
+                }
                 RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
             };
         }
@@ -393,12 +438,36 @@ impl Request {
         disk_nsectors: u64,
         disk_image: &mut dyn AsyncIo,
         serial: &[u8],
+        lifetime: &VirtioBlockLifetime,
         user_data: u64,
     ) -> result::Result<bool, ExecuteError> {
         let sector = self.sector;
         let request_type = self.request_type;
         let offset = (sector << SECTOR_SHIFT) as libc::off_t;
 
+        // The data of these requests doesn't map to the disk sectors, they
+        // are completed synchronously.
+        match request_type {
+            RequestType::GetLifetime => {
+                let (data_addr, data_len) = if self.data_descriptors.len() == 1 {
+                    (self.data_descriptors[0].0, self.data_descriptors[0].1)
+                } else {
+                    return Err(ExecuteError::BadRequest(Error::TooManyDescriptors));
+                };
+                if (data_len as usize) < std::mem::size_of::<VirtioBlockLifetime>() {
+                    return Err(ExecuteError::BadRequest(Error::DescriptorLengthTooSmall));
+                }
+                mem.write_obj(*lifetime, data_addr)
+                    .map_err(ExecuteError::Write)?;
+                return Ok(false);
+            }
+            RequestType::SecureErase => {
+                self.secure_erase(mem, disk_nsectors, disk_image)?;
+                return Ok(false);
+            }
+            _ => {}
+        }
+
         let mut iovecs: SmallVec<[libc::iovec; 1]> =
             SmallVec::with_capacity(self.data_descriptors.len());
         for (data_addr, data_len) in &self.data_descriptors {
@@ -501,12 +570,50 @@ impl Request {
                     .map_err(ExecuteError::Write)?;
                 return Ok(false);
             }
+            RequestType::GetLifetime | RequestType::SecureErase => unreachable!(),
             RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
         }
 
         Ok(true)
     }
 
+    fn secure_erase<B: Bitmap + 'static>(
+        &self,
+        mem: &vm_memory::GuestMemoryMmap<B>,
+        disk_nsectors: u64,
+        disk_image: &mut dyn AsyncIo,
+    ) -> result::Result<(), ExecuteError> {
+        let segment_size = std::mem::size_of::<VirtioBlockDiscardWriteZeroes>();
+        for (data_addr, data_len) in &self.data_descriptors {
+            if *data_len as usize % segment_size != 0 {
+                return Err(ExecuteError::BadRequest(Error::DescriptorLengthTooSmall));
+            }
+
+            for i in 0..(*data_len as usize / segment_size) {
+                let segment_addr = mem.checked_offset(*data_addr, i * segment_size).ok_or(
+                    ExecuteError::BadRequest(Error::CheckedOffset(*data_addr, i * segment_size)),
+                )?;
+                let segment: VirtioBlockDiscardWriteZeroes =
+                    mem.read_obj(segment_addr).map_err(ExecuteError::Read)?;
+
+                let sector = segment.sector;
+                let num_sectors = u64::from(segment.num_sectors);
+                let top = sector
+                    .checked_add(num_sectors)
+                    .ok_or(ExecuteError::BadRequest(Error::InvalidOffset))?;
+                if top > disk_nsectors {
+                    return Err(ExecuteError::BadRequest(Error::InvalidOffset));
+                }
+
+                disk_image
+                    .secure_erase(sector << SECTOR_SHIFT, num_sectors << SECTOR_SHIFT)
+                    .map_err(ExecuteError::SecureErase)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn complete_async(&mut self) -> result::Result<(), Error> {
         for aligned_operation in self.aligned_operations.drain(..) {
             // We need to perform the copy after the data has been read inside
@@ -537,6 +644,21 @@ impl Request {
         Ok(())
     }
 
+    /// Release the resources of an asynchronous request without copying the
+    /// data back, for requests whose result is not relevant anymore.
+    pub fn discard_async(&mut self) {
+        for aligned_operation in self.aligned_operations.drain(..) {
+            // SAFETY: aligned_ptr was allocated by alloc_zeroed with the same
+            // layout
+            unsafe {
+                dealloc(
+                    aligned_operation.aligned_ptr as *mut u8,
+                    aligned_operation.layout,
+                )
+            };
+        }
+    }
+
     pub fn set_writeback(&mut self, writeback: bool) {
         self.writeback = writeback
     }
@@ -564,7 +686,19 @@ pub struct VirtioBlockConfig {
     pub max_write_zeroes_seg: u32,
     pub write_zeroes_may_unmap: u8,
     pub unused1: [u8; 3],
+    #[serde(default)]
+    pub max_secure_erase_sectors: u32,
+    #[serde(default)]
+    pub max_secure_erase_seg: u32,
+    #[serde(default)]
+    pub secure_erase_sector_alignment: u32,
 }
+
+/// Size of the configuration space as defined before the secure erase fields
+/// were added to it, which is what older vhost-user backends expose.
+pub const VIRTIO_BLK_CONFIG_LEGACY_SIZE: usize =
+    std::mem::offset_of!(VirtioBlockConfig, max_secure_erase_sectors);
+
 #[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
 #[repr(C, packed)]
 pub struct VirtioBlockGeometry {
@@ -573,10 +707,82 @@ pub struct VirtioBlockGeometry {
     pub sectors: u8,
 }
 
+/// Segment of a discard, write zeroes or secure erase request.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+pub struct VirtioBlockDiscardWriteZeroes {
+    pub sector: u64,
+    pub num_sectors: u32,
+    pub flags: u32,
+}
+
+/// Answer to a VIRTIO_BLK_T_GET_LIFETIME request, following the eMMC
+/// definitions. Zero values mean the information is not available.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct VirtioBlockLifetime {
+    pub pre_eol_info: u16,
+    pub device_lifetime_est_typ_a: u16,
+    pub device_lifetime_est_typ_b: u16,
+}
+
+impl VirtioBlockLifetime {
+    // Values formatted as hexadecimal such as "0x01 0x02".
+    fn parse_sysfs(value: &str) -> Option<Vec<u16>> {
+        value
+            .split_whitespace()
+            .map(|v| u16::from_str_radix(v.trim_start_matches("0x"), 16).ok())
+            .collect()
+    }
+
+    /// Lifetime of the eMMC device holding the disk image, as reported by
+    /// the MMC driver through sysfs. Undefined for any other kind of storage.
+    pub fn probe(disk_path: &Path) -> Self {
+        let Ok(metadata) = disk_path.metadata() else {
+            return Self::default();
+        };
+        let dev = if metadata.st_mode() & S_IFMT == S_IFBLK {
+            metadata.st_rdev()
+        } else {
+            metadata.st_dev()
+        };
+
+        let mut sysfs = PathBuf::from(format!(
+            "/sys/dev/block/{}:{}",
+            libc::major(dev),
+            libc::minor(dev)
+        ));
+        if sysfs.join("partition").exists() {
+            sysfs.push("..");
+        }
+
+        let read = |name| {
+            std::fs::read_to_string(sysfs.join("device").join(name))
+                .ok()
+                .and_then(|v| Self::parse_sysfs(&v))
+        };
+        match (
+            read("pre_eol_info").as_deref(),
+            read("life_time").as_deref(),
+        ) {
+            (Some([pre_eol_info]), Some([typ_a, typ_b])) => VirtioBlockLifetime {
+                pre_eol_info: *pre_eol_info,
+                device_lifetime_est_typ_a: *typ_a,
+                device_lifetime_est_typ_b: *typ_b,
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
 // SAFETY: data structure only contain a series of integers
 unsafe impl ByteValued for VirtioBlockConfig {}
 // SAFETY: data structure only contain a series of integers
 unsafe impl ByteValued for VirtioBlockGeometry {}
+// SAFETY: data structure only contain a series of integers
+unsafe impl ByteValued for VirtioBlockDiscardWriteZeroes {}
+// SAFETY: data structure only contain a series of integers
+unsafe impl ByteValued for VirtioBlockLifetime {}
 
 /// Check if aio can be used on the current system.
 pub fn block_aio_is_supported() -> bool {
@@ -741,13 +947,49 @@ where
     fn file(&mut self) -> MutexGuard<F>;
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ImageType {
+    #[serde(rename = "vhd")]
     FixedVhd,
+    #[serde(rename = "qcow2")]
     Qcow2,
+    #[serde(rename = "raw")]
     Raw,
+    #[serde(rename = "vhdx")]
     Vhdx,
 }
 
+impl fmt::Display for ImageType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageType::FixedVhd => write!(f, "vhd"),
+            ImageType::Qcow2 => write!(f, "qcow2"),
+            ImageType::Raw => write!(f, "raw"),
+            ImageType::Vhdx => write!(f, "vhdx"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ParseImageTypeError {
+    #[error("Invalid image type: {0}")]
+    InvalidValue(String),
+}
+
+impl FromStr for ImageType {
+    type Err = ParseImageTypeError;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "vhd" => Ok(ImageType::FixedVhd),
+            "qcow2" => Ok(ImageType::Qcow2),
+            "raw" => Ok(ImageType::Raw),
+            "vhdx" => Ok(ImageType::Vhdx),
+            _ => Err(ParseImageTypeError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
 const QCOW_MAGIC: u32 = 0x5146_49fb;
 const VHDX_SIGN: u64 = 0x656C_6966_7864_6876;
 
@@ -809,6 +1051,59 @@ pub fn create_disk_file(mut file: File, direct_io: bool) -> Result<Box<dyn Block
     })
 }
 
+// Copy `size` bytes from `src` into `dst`, skipping the chunks full of zeroes
+// so that the destination image stays sparse.
+fn copy_image_data<W: Write + Seek>(
+    src: &mut dyn BlockBackend,
+    dst: &mut W,
+    size: u64,
+) -> io::Result<()> {
+    const CHUNK_SIZE: usize = 1 << 20;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut offset = 0;
+
+    src.rewind()?;
+    while offset < size {
+        let len = cmp::min(CHUNK_SIZE as u64, size - offset) as usize;
+        src.read_exact(&mut buf[..len])?;
+        if buf[..len].iter().any(|b| *b != 0) {
+            dst.seek(SeekFrom::Start(offset))?;
+            dst.write_all(&buf[..len])?;
+        }
+        offset += len as u64;
+    }
+
+    dst.flush()
+}
+
+/// Copy the contents of the disk image `src_file` into `dst_file`, using the
+/// `dst_type` format. The type of the source image is automatically detected,
+/// while only raw and qcow2 are supported as destination types.
+pub fn convert_image(mut src_file: File, dst_file: File, dst_type: ImageType) -> Result<(), Error> {
+    // The image type is detected from the current position of the file.
+    src_file.rewind().map_err(Error::ConvertImage)?;
+    let mut src = create_disk_file(src_file, false)?;
+    let size = src.size()?;
+
+    // Ensure the destination file is empty before writing to it.
+    dst_file.set_len(0).map_err(Error::ConvertImage)?;
+
+    match dst_type {
+        ImageType::Raw => {
+            let mut dst = dst_file;
+            dst.set_len(size).map_err(Error::ConvertImage)?;
+            copy_image_data(src.as_mut(), &mut dst, size).map_err(Error::ConvertImage)?;
+            dst.sync_all().map_err(Error::ConvertImage)
+        }
+        ImageType::Qcow2 => {
+            let mut dst =
+                QcowFile::new(RawFile::new(dst_file, false), 3, size).map_err(Error::QcowError)?;
+            copy_image_data(src.as_mut(), &mut dst, size).map_err(Error::ConvertImage)
+        }
+        ImageType::FixedVhd | ImageType::Vhdx => Err(Error::UnsupportedConversion(dst_type)),
+    }
+}
+
 #[derive(Debug)]
 pub struct DiskTopology {
     pub logical_block_size: u64,
@@ -832,6 +1127,54 @@ ioctl_io_nr!(BLKSSZGET, 0x12, 104);
 ioctl_io_nr!(BLKPBSZGET, 0x12, 123);
 ioctl_io_nr!(BLKIOMIN, 0x12, 120);
 ioctl_io_nr!(BLKIOOPT, 0x12, 121);
+ioctl_io_nr!(BLKSECDISCARD, 0x12, 125);
+
+/// Erase a range of the disk image behind `fd` so that its content can't be
+/// retrieved anymore: a secure discard for block devices, and releasing the
+/// range from the file system for regular files.
+pub fn secure_erase_fd(fd: RawFd, offset: u64, length: u64) -> io::Result<()> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: FFI call with a valid fd and buffer
+    let ret = unsafe { libc::fstat(fd, stat.as_mut_ptr()) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: stat is valid at this point
+    if unsafe { (*stat.as_ptr()).st_mode & S_IFMT == S_IFBLK } {
+        let range: [u64; 2] = [offset, length];
+        // SAFETY: FFI call with a valid fd and range
+        let ret = unsafe { ioctl(fd, BLKSECDISCARD() as _, &range) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(());
+    }
+
+    let fallocate = |mode: libc::c_int| {
+        // SAFETY: FFI call with a valid fd
+        let ret = unsafe {
+            libc::fallocate64(
+                fd,
+                mode | libc::FALLOC_FL_KEEP_SIZE,
+                offset as libc::off64_t,
+                length as libc::off64_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    };
+
+    // File systems without support for holes can still zero the range.
+    match fallocate(libc::FALLOC_FL_PUNCH_HOLE) {
+        Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+            fallocate(libc::FALLOC_FL_ZERO_RANGE)
+        }
+        r => r,
+    }
+}
 
 enum BlockSize {
     LogicalBlock,
@@ -890,3 +1233,95 @@ impl DiskTopology {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_convert_image() {
+        const IMAGE_SIZE: u64 = 4 << 20;
+
+        let raw = TempFile::new().unwrap();
+        let mut raw_file = raw.into_file();
+        raw_file.set_len(IMAGE_SIZE).unwrap();
+        raw_file.seek(SeekFrom::Start(1 << 20)).unwrap();
+        raw_file.write_all(&[0xaa; 4096]).unwrap();
+
+        // Convert from raw to qcow2 and back to raw.
+        let qcow2 = TempFile::new().unwrap();
+        convert_image(
+            raw_file.try_clone().unwrap(),
+            qcow2.as_file().try_clone().unwrap(),
+            ImageType::Qcow2,
+        )
+        .unwrap();
+        let mut qcow2_file = qcow2.as_file().try_clone().unwrap();
+        qcow2_file.rewind().unwrap();
+        assert_eq!(
+            detect_image_type(&mut qcow2_file).unwrap(),
+            ImageType::Qcow2
+        );
+
+        let converted = TempFile::new().unwrap();
+        convert_image(
+            qcow2.as_file().try_clone().unwrap(),
+            converted.as_file().try_clone().unwrap(),
+            ImageType::Raw,
+        )
+        .unwrap();
+
+        let mut expected = Vec::new();
+        raw_file.rewind().unwrap();
+        raw_file.read_to_end(&mut expected).unwrap();
+        let mut data = Vec::new();
+        let mut converted_file = converted.as_file().try_clone().unwrap();
+        converted_file.rewind().unwrap();
+        converted_file.read_to_end(&mut data).unwrap();
+        assert_eq!(data, expected);
+
+        assert!(matches!(
+            convert_image(
+                raw_file,
+                converted.as_file().try_clone().unwrap(),
+                ImageType::Vhdx
+            ),
+            Err(Error::UnsupportedConversion(ImageType::Vhdx))
+        ));
+    }
+
+    #[test]
+    fn test_image_type_parsing() {
+        assert_eq!("raw".parse::<ImageType>().unwrap(), ImageType::Raw);
+        assert_eq!("qcow2".parse::<ImageType>().unwrap(), ImageType::Qcow2);
+        assert_eq!("vhd".parse::<ImageType>().unwrap(), ImageType::FixedVhd);
+        assert_eq!("vhdx".parse::<ImageType>().unwrap(), ImageType::Vhdx);
+        assert!("vmdk".parse::<ImageType>().is_err());
+        assert_eq!(ImageType::FixedVhd.to_string(), "vhd");
+    }
+
+    #[test]
+    fn test_secure_erase() {
+        let mut file = TempFile::new().unwrap().into_file();
+        file.write_all(&[0xaa; 0x3000]).unwrap();
+        secure_erase_fd(file.as_raw_fd(), 0x1000, 0x1000).unwrap();
+
+        let mut data = Vec::new();
+        file.rewind().unwrap();
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 0x3000);
+        assert!(data[..0x1000].iter().all(|b| *b == 0xaa));
+        assert!(data[0x1000..0x2000].iter().all(|b| *b == 0));
+        assert!(data[0x2000..].iter().all(|b| *b == 0xaa));
+    }
+
+    #[test]
+    fn test_lifetime_parsing() {
+        assert_eq!(
+            VirtioBlockLifetime::parse_sysfs("0x01 0x0b\n"),
+            Some(vec![1, 11])
+        );
+        assert_eq!(VirtioBlockLifetime::parse_sysfs("0xzz\n"), None);
+    }
+}
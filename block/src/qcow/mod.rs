@@ -32,7 +32,7 @@ use vmm_sys_util::{
 pub use crate::qcow::raw_file::RawFile;
 
 /// Nesting depth limit for disk formats that can open other disk files.
-const MAX_NESTING_DEPTH: u32 = 10;
+pub(crate) const MAX_NESTING_DEPTH: u32 = 10;
 
 #[sorted]
 #[derive(Debug, Error)]
@@ -439,7 +439,67 @@ pub struct QcowFile {
     // List of unreferenced clusters available to be used. unref clusters become available once the
     // removal of references to them have been synced to disk.
     avail_clusters: Vec<u64>,
-    backing_file: Option<Box<Self>>,
+    backing_file: Option<BackingFile>,
+}
+
+/// Image read for the clusters a qcow2 file doesn't allocate, either another
+/// qcow2 file or a raw image.
+#[derive(Debug)]
+enum BackingFile {
+    Qcow(Box<QcowFile>),
+    Raw(RawFile),
+}
+
+impl BackingFile {
+    fn open(path: &str, direct_io: bool, max_nesting_depth: u32) -> Result<BackingFile> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .map_err(Error::BackingFileIo)?;
+        let mut raw_file = RawFile::new(file, direct_io);
+
+        Ok(match detect_image_type(&mut raw_file)? {
+            ImageType::Qcow2 => BackingFile::Qcow(Box::new(
+                QcowFile::from_with_nesting_depth(raw_file, max_nesting_depth)
+                    .map_err(|e| Error::BackingFileOpen(Box::new(e)))?,
+            )),
+            ImageType::Raw => BackingFile::Raw(raw_file),
+        })
+    }
+
+    fn virtual_size(&self) -> Result<u64> {
+        match self {
+            BackingFile::Qcow(qcow) => Ok(qcow.virtual_size()),
+            BackingFile::Raw(raw) => Ok(raw.metadata().map_err(Error::GettingFileSize)?.len()),
+        }
+    }
+}
+
+impl Read for BackingFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            BackingFile::Qcow(qcow) => qcow.read(buf),
+            // A raw image reads as zeroes past its end, for the last cluster
+            // to be copied whole when its size isn't a multiple of the
+            // cluster size.
+            BackingFile::Raw(raw) => match raw.read(buf)? {
+                0 => {
+                    buf.fill(0);
+                    Ok(buf.len())
+                }
+                n => Ok(n),
+            },
+        }
+    }
+}
+
+impl Seek for BackingFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            BackingFile::Qcow(qcow) => qcow.seek(pos),
+            BackingFile::Raw(raw) => raw.seek(pos),
+        }
+    }
 }
 
 impl QcowFile {
@@ -482,17 +542,11 @@ impl QcowFile {
             if max_nesting_depth == 0 {
                 return Err(Error::MaxNestingDepthExceeded);
             }
-            let path = backing_file_path.clone();
-            let backing_raw_file = OpenOptions::new()
-                .read(true)
-                .open(path)
-                .map_err(Error::BackingFileIo)?;
-            let backing_file = Self::from_with_nesting_depth(
-                RawFile::new(backing_raw_file, direct_io),
+            Some(BackingFile::open(
+                backing_file_path,
+                direct_io,
                 max_nesting_depth - 1,
-            )
-            .map_err(|e| Error::BackingFileOpen(Box::new(e)))?;
-            Some(Box::new(backing_file))
+            )?)
         } else {
             None
         };
@@ -635,7 +689,8 @@ impl QcowFile {
         QcowFile::new_from_header(file, header)
     }
 
-    /// Creates a new QcowFile at the given path.
+    /// Creates a new QcowFile at the given path, backed by the qcow2 or raw
+    /// image `backing_file_name`.
     pub fn new_from_backing(
         file: RawFile,
         version: u32,
@@ -643,19 +698,12 @@ impl QcowFile {
         backing_file_max_nesting_depth: u32,
     ) -> Result<QcowFile> {
         let direct_io = file.is_direct();
-        let backing_raw_file = OpenOptions::new()
-            .read(true)
-            .open(backing_file_name)
-            .map_err(Error::BackingFileIo)?;
-        let backing_file = Self::from_with_nesting_depth(
-            RawFile::new(backing_raw_file, direct_io),
-            backing_file_max_nesting_depth,
-        )
-        .map_err(|e| Error::BackingFileOpen(Box::new(e)))?;
-        let size = backing_file.virtual_size();
+        let backing_file =
+            BackingFile::open(backing_file_name, direct_io, backing_file_max_nesting_depth)?;
+        let size = backing_file.virtual_size()?;
         let header = QcowHeader::create_for_size_and_path(version, size, Some(backing_file_name))?;
         let mut result = QcowFile::new_from_header(file, header)?;
-        result.backing_file = Some(Box::new(backing_file));
+        result.backing_file = Some(backing_file);
         Ok(result)
     }
 
@@ -684,7 +732,7 @@ impl QcowFile {
     }
 
     pub fn set_backing_file(&mut self, backing: Option<Box<Self>>) {
-        self.backing_file = backing;
+        self.backing_file = backing.map(BackingFile::Qcow);
     }
 
     /// Returns the `QcowHeader` for this file.
@@ -2250,6 +2298,47 @@ mod tests {
         assert_eq!(&buf, b"TEST first");
     }
 
+    #[test]
+    fn write_read_start_raw_backing() {
+        let test_dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let backing_path = test_dir.as_path().join("backing.raw");
+        // The size isn't a multiple of the cluster size, the end of the last
+        // cluster reads as zeroes.
+        let mut backing_data = vec![0x55u8; 0x1_0000 + 0x100];
+        backing_data[..16].copy_from_slice(b"test first bytes");
+        std::fs::write(&backing_path, &backing_data).unwrap();
+
+        let wrapping_disk_file = RawFile::new(TempFile::new().unwrap().into_file(), false);
+        let mut wrapping =
+            QcowFile::new_from_backing(wrapping_disk_file, 3, backing_path.to_str().unwrap(), 1)
+                .unwrap();
+        assert_eq!(wrapping.virtual_size(), backing_data.len() as u64);
+
+        wrapping.seek(SeekFrom::Start(0)).expect("Failed to seek.");
+        wrapping
+            .write_all(b"TEST")
+            .expect("Failed to write second test string.");
+        let mut buf = [0u8; 10];
+        wrapping.seek(SeekFrom::Start(0)).expect("Failed to seek.");
+        wrapping.read_exact(&mut buf).expect("Failed to read.");
+        assert_eq!(&buf, b"TEST first");
+
+        wrapping
+            .seek(SeekFrom::Start(0x1_0000))
+            .expect("Failed to seek.");
+        wrapping.write_all(b"TEST").expect("Failed to write.");
+        let mut buf = [0u8; 0x100];
+        wrapping
+            .seek(SeekFrom::Start(0x1_0000))
+            .expect("Failed to seek.");
+        wrapping.read_exact(&mut buf).expect("Failed to read.");
+        assert_eq!(&buf[..4], b"TEST");
+        assert!(buf[4..].iter().all(|b| *b == 0x55));
+
+        // The base image is left untouched.
+        assert_eq!(std::fs::read(&backing_path).unwrap(), backing_data);
+    }
+
     #[test]
     fn offset_write_read() {
         with_basic_file(&valid_header_v3(), |disk_file: RawFile| {
@@ -2,15 +2,19 @@
 //
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
-use crate::async_io::{AsyncIo, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult};
-use crate::qcow::{QcowFile, RawFile, Result as QcowResult};
+use crate::async_io::{
+    AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult,
+};
+use crate::qcow::{QcowFile, RawFile, Result as QcowResult, MAX_NESTING_DEPTH};
 use crate::AsyncAdaptor;
 use std::collections::VecDeque;
 use std::fs::File;
-use std::io::{Seek, SeekFrom};
+use std::io::{Seek, SeekFrom, Write};
 use std::sync::{Arc, Mutex, MutexGuard};
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::write_zeroes::PunchHole;
 
+#[derive(Clone)]
 pub struct QcowDiskSync {
     qcow_file: Arc<Mutex<QcowFile>>,
 }
@@ -21,6 +25,24 @@ impl QcowDiskSync {
             qcow_file: Arc::new(Mutex::new(QcowFile::from(RawFile::new(file, direct_io))?)),
         })
     }
+
+    /// Creates an empty qcow2 image in `file`, reading the clusters it
+    /// doesn't allocate from the image at `backing_file`.
+    pub fn new_with_backing(file: File, direct_io: bool, backing_file: &str) -> QcowResult<Self> {
+        Ok(QcowDiskSync {
+            qcow_file: Arc::new(Mutex::new(QcowFile::new_from_backing(
+                RawFile::new(file, direct_io),
+                3,
+                backing_file,
+                MAX_NESTING_DEPTH,
+            )?)),
+        })
+    }
+
+    /// Writes the cached metadata out, leaving a consistent image.
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.qcow_file.lock().unwrap().flush()
+    }
 }
 
 impl DiskFile for QcowDiskSync {
@@ -33,6 +55,10 @@ impl DiskFile for QcowDiskSync {
     fn new_async_io(&self, _ring_depth: u32) -> DiskFileResult<Box<dyn AsyncIo>> {
         Ok(Box::new(QcowSync::new(self.qcow_file.clone())) as Box<dyn AsyncIo>)
     }
+
+    fn supports_secure_erase(&self) -> bool {
+        true
+    }
 }
 
 pub struct QcowSync {
@@ -101,4 +127,14 @@ impl AsyncIo for QcowSync {
     fn next_completed_request(&mut self) -> Option<(u64, i32)> {
         self.completion_list.pop_front()
     }
+
+    // Deallocated clusters read back as zeroes, and their storage is released
+    // by punching holes in the underlying file.
+    fn secure_erase(&mut self, offset: u64, length: u64) -> AsyncIoResult<()> {
+        self.qcow_file
+            .lock()
+            .unwrap()
+            .punch_hole(offset, length)
+            .map_err(AsyncIoError::SecureErase)
+    }
 }
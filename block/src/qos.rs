@@ -0,0 +1,263 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host side quality of service of the disk backends: the I/O priority of
+//! the threads processing the requests, and the cgroup v2 `io.max` limits of
+//! the host block device holding the image.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::os::linux::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::str::FromStr;
+use thiserror::Error;
+
+const IOPRIO_CLASS_SHIFT: u16 = 13;
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_MAX_LEVEL: u8 = 7;
+
+const PROC_SELF_CGROUP: &str = "/proc/self/cgroup";
+const CGROUP2_MOUNT: &str = "/sys/fs/cgroup";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IoPriorityClass {
+    #[serde(rename = "rt")]
+    RealTime,
+    #[serde(rename = "be")]
+    BestEffort,
+    #[serde(rename = "idle")]
+    Idle,
+}
+
+impl IoPriorityClass {
+    fn value(&self) -> u16 {
+        match self {
+            IoPriorityClass::RealTime => 1,
+            IoPriorityClass::BestEffort => 2,
+            IoPriorityClass::Idle => 3,
+        }
+    }
+}
+
+/// I/O priority as defined by ioprio_set(2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoPriority {
+    pub class: IoPriorityClass,
+    #[serde(default)]
+    pub level: u8,
+}
+
+impl IoPriority {
+    /// Apply the priority to the I/O issued by the calling thread.
+    pub fn apply_to_current_thread(&self) -> io::Result<()> {
+        let ioprio = (self.class.value() << IOPRIO_CLASS_SHIFT) | self.level as u16;
+        // SAFETY: FFI call with correct arguments, 0 being the calling thread
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                ioprio as libc::c_int,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for IoPriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.class {
+            IoPriorityClass::RealTime => write!(f, "rt:{}", self.level),
+            IoPriorityClass::BestEffort => write!(f, "be:{}", self.level),
+            IoPriorityClass::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ParseIoPriorityError {
+    #[error("Invalid I/O priority class: {0}")]
+    InvalidClass(String),
+    #[error("Invalid I/O priority level: {0}")]
+    InvalidLevel(String),
+}
+
+impl FromStr for IoPriority {
+    type Err = ParseIoPriorityError;
+
+    /// Parse `<class>[:<level>]`, with class one of `rt`, `be` or `idle`.
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (s, None),
+        };
+
+        let class = match class.to_lowercase().as_str() {
+            "rt" => IoPriorityClass::RealTime,
+            "be" => IoPriorityClass::BestEffort,
+            "idle" => IoPriorityClass::Idle,
+            _ => return Err(ParseIoPriorityError::InvalidClass(class.to_owned())),
+        };
+
+        let level = match level {
+            // The idle class has no level.
+            Some(level) if class == IoPriorityClass::Idle => {
+                return Err(ParseIoPriorityError::InvalidLevel(level.to_owned()))
+            }
+            Some(level) => level
+                .parse::<u8>()
+                .ok()
+                .filter(|level| *level <= IOPRIO_MAX_LEVEL)
+                .ok_or_else(|| ParseIoPriorityError::InvalidLevel(level.to_owned()))?,
+            // Same default level as ionice(1).
+            None if class == IoPriorityClass::Idle => 0,
+            None => 4,
+        };
+
+        Ok(IoPriority { class, level })
+    }
+}
+
+/// Limits of the cgroup v2 `io.max` file. A missing limit is left unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoMaxConfig {
+    #[serde(default)]
+    pub rbps: Option<u64>,
+    #[serde(default)]
+    pub wbps: Option<u64>,
+    #[serde(default)]
+    pub riops: Option<u64>,
+    #[serde(default)]
+    pub wiops: Option<u64>,
+}
+
+#[derive(Error, Debug)]
+pub enum IoMaxError {
+    #[error("Error getting the host block device of the disk: {0}")]
+    BlockDevice(#[source] io::Error),
+    #[error("Error finding the cgroup v2 of the VMM: {0}")]
+    Cgroup(#[source] io::Error),
+    #[error("Error writing io.max limits to {0}: {1}")]
+    Write(PathBuf, #[source] io::Error),
+}
+
+// Limits formatted as expected by io.max, for the device `major:minor`.
+fn io_max_line(major: u32, minor: u32, limits: &IoMaxConfig) -> String {
+    let limit = |v: Option<u64>| v.map_or("max".to_string(), |v| v.to_string());
+    format!(
+        "{major}:{minor} rbps={} wbps={} riops={} wiops={}",
+        limit(limits.rbps),
+        limit(limits.wbps),
+        limit(limits.riops),
+        limit(limits.wiops)
+    )
+}
+
+// Path of the cgroup v2 from the content of /proc/self/cgroup.
+fn parse_cgroup2_path(cgroup: &str) -> Option<PathBuf> {
+    cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| Path::new(CGROUP2_MOUNT).join(path.trim_start_matches('/')))
+}
+
+// Whole disk holding `file`, as io.max doesn't accept partitions.
+fn block_device(file: &File) -> io::Result<(u32, u32)> {
+    let metadata = file.metadata()?;
+    let dev = if metadata.st_mode() & libc::S_IFMT == libc::S_IFBLK {
+        metadata.st_rdev()
+    } else {
+        metadata.st_dev()
+    };
+    let (major, minor) = (libc::major(dev), libc::minor(dev));
+
+    let sysfs = PathBuf::from(format!("/sys/dev/block/{major}:{minor}"));
+    if !sysfs.join("partition").exists() {
+        return Ok((major, minor));
+    }
+
+    let parent = fs::read_to_string(sysfs.join("../dev"))?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, parent.trim().to_owned());
+    let (major, minor) = parent.trim().split_once(':').ok_or_else(invalid)?;
+    Ok((
+        major.parse().map_err(|_| invalid())?,
+        minor.parse().map_err(|_| invalid())?,
+    ))
+}
+
+/// Set the `io.max` limits of the host block device holding `file`, in the
+/// cgroup v2 the VMM belongs to. The limits are shared by all the I/O the VMM
+/// issues to that block device.
+pub fn set_io_max(file: &File, limits: &IoMaxConfig) -> result::Result<(), IoMaxError> {
+    let (major, minor) = block_device(file).map_err(IoMaxError::BlockDevice)?;
+
+    let cgroup = fs::read_to_string(PROC_SELF_CGROUP).map_err(IoMaxError::Cgroup)?;
+    let path = parse_cgroup2_path(&cgroup)
+        .ok_or_else(|| {
+            IoMaxError::Cgroup(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no cgroup v2 hierarchy",
+            ))
+        })?
+        .join("io.max");
+
+    let line = io_max_line(major, minor, limits);
+    info!("Setting {:?} to \"{}\"", path, line);
+    fs::write(&path, line).map_err(|e| IoMaxError::Write(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_io_priority() {
+        assert_eq!(
+            IoPriority::from_str("be").unwrap(),
+            IoPriority {
+                class: IoPriorityClass::BestEffort,
+                level: 4
+            }
+        );
+        assert_eq!(
+            IoPriority::from_str("rt:0").unwrap(),
+            IoPriority {
+                class: IoPriorityClass::RealTime,
+                level: 0
+            }
+        );
+        assert_eq!(IoPriority::from_str("idle").unwrap().to_string(), "idle");
+        assert!(IoPriority::from_str("be:8").is_err());
+        assert!(IoPriority::from_str("idle:1").is_err());
+        assert!(IoPriority::from_str("low").is_err());
+    }
+
+    #[test]
+    fn test_io_max() {
+        assert_eq!(
+            io_max_line(
+                8,
+                0,
+                &IoMaxConfig {
+                    wbps: Some(1 << 20),
+                    riops: Some(1000),
+                    ..Default::default()
+                }
+            ),
+            "8:0 rbps=max wbps=1048576 riops=1000 wiops=max"
+        );
+        assert_eq!(
+            parse_cgroup2_path("0::/machine.slice/vm0.scope\n"),
+            Some(PathBuf::from("/sys/fs/cgroup/machine.slice/vm0.scope"))
+        );
+        assert_eq!(parse_cgroup2_path("1:cpu:/\n"), None);
+    }
+}
@@ -5,13 +5,17 @@
 use crate::async_io::{
     AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult,
 };
-use crate::DiskTopology;
+use crate::{secure_erase_fd, DiskTopology};
 use io_uring::{opcode, squeue, types, IoUring};
 use std::fs::File;
 use std::io::{Seek, SeekFrom};
 use std::os::unix::io::{AsRawFd, RawFd};
 use vmm_sys_util::eventfd::EventFd;
 
+// User data of the cancellation requests, out of the range of the ones
+// submitted by the virtio-block device.
+const CANCEL_USER_DATA: u64 = u64::MAX;
+
 pub struct RawFileDisk {
     file: File,
 }
@@ -44,6 +48,10 @@ impl DiskFile for RawFileDisk {
             DiskTopology::default()
         }
     }
+
+    fn supports_secure_erase(&self) -> bool {
+        true
+    }
 }
 
 pub struct RawFileAsync {
@@ -157,9 +165,40 @@ impl AsyncIo for RawFileAsync {
     }
 
     fn next_completed_request(&mut self) -> Option<(u64, i32)> {
+        // The completions of the cancellation requests themselves are of no
+        // interest, the cancelled request gets its own completion.
         self.io_uring
             .completion()
-            .next()
+            .find(|entry| entry.user_data() != CANCEL_USER_DATA)
             .map(|entry| (entry.user_data(), entry.result()))
     }
+
+    fn secure_erase(&mut self, offset: u64, length: u64) -> AsyncIoResult<()> {
+        secure_erase_fd(self.fd, offset, length).map_err(AsyncIoError::SecureErase)
+    }
+
+    fn cancel(&mut self, user_data: u64) -> AsyncIoResult<()> {
+        let (submitter, mut sq, _) = self.io_uring.split();
+        let entry = opcode::AsyncCancel::new(user_data)
+            .build()
+            .user_data(CANCEL_USER_DATA);
+
+        // SAFETY: the cancellation doesn't reference any memory.
+        if unsafe { sq.push(&entry) }.is_err() {
+            // The submission queue is full, the pending entries are handed
+            // over to the kernel to make room for the cancellation.
+            sq.sync();
+            submitter.submit().map_err(AsyncIoError::Cancel)?;
+            sq.sync();
+            // SAFETY: the cancellation doesn't reference any memory.
+            unsafe { sq.push(&entry) }.map_err(|_| {
+                AsyncIoError::Cancel(std::io::Error::from_raw_os_error(libc::EBUSY))
+            })?;
+        }
+
+        sq.sync();
+        submitter.submit().map_err(AsyncIoError::Cancel)?;
+
+        Ok(())
+    }
 }
@@ -8,7 +8,7 @@
 use crate::async_io::{
     AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult,
 };
-use crate::DiskTopology;
+use crate::{secure_erase_fd, DiskTopology};
 use std::fs::File;
 use std::io::{Seek, SeekFrom};
 use std::os::unix::io::{AsRawFd, RawFd};
@@ -47,6 +47,10 @@ impl DiskFile for RawFileDiskAio {
             DiskTopology::default()
         }
     }
+
+    fn supports_secure_erase(&self) -> bool {
+        true
+    }
 }
 
 pub struct RawFileAsyncAio {
@@ -147,4 +151,8 @@ impl AsyncIo for RawFileAsyncAio {
             Some((events[0].data, events[0].res as i32))
         }
     }
+
+    fn secure_erase(&mut self, offset: u64, length: u64) -> AsyncIoResult<()> {
+        secure_erase_fd(self.fd, offset, length).map_err(AsyncIoError::SecureErase)
+    }
 }
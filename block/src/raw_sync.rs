@@ -5,7 +5,7 @@
 use crate::async_io::{
     AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult,
 };
-use crate::DiskTopology;
+use crate::{secure_erase_fd, DiskTopology};
 use std::collections::VecDeque;
 use std::fs::File;
 use std::io::{Seek, SeekFrom};
@@ -41,6 +41,10 @@ impl DiskFile for RawFileDiskSync {
             DiskTopology::default()
         }
     }
+
+    fn supports_secure_erase(&self) -> bool {
+        true
+    }
 }
 
 pub struct RawFileSync {
@@ -132,4 +136,8 @@ impl AsyncIo for RawFileSync {
     fn next_completed_request(&mut self) -> Option<(u64, i32)> {
         self.completion_list.pop_front()
     }
+
+    fn secure_erase(&mut self, offset: u64, length: u64) -> AsyncIoResult<()> {
+        secure_erase_fd(self.fd, offset, length).map_err(AsyncIoError::SecureErase)
+    }
 }
@@ -183,6 +183,22 @@ impl Aml for AcpiGedDevice {
                                 &0x80usize,
                             )],
                         ),
+                        &aml::And::new(&aml::Local(1), &aml::Local(0), &16usize),
+                        &aml::If::new(
+                            &aml::Equal::new(&aml::Local(1), &16usize),
+                            vec![&aml::Notify::new(
+                                &aml::Path::new("\\_SB_.VGEN"),
+                                &0x80usize,
+                            )],
+                        ),
+                        &aml::And::new(&aml::Local(1), &aml::Local(0), &32usize),
+                        &aml::If::new(
+                            &aml::Equal::new(&aml::Local(1), &32usize),
+                            vec![&aml::Notify::new(
+                                &aml::Path::new("\\_SB_.HED_"),
+                                &0x80usize,
+                            )],
+                        ),
                     ],
                 ),
             ],
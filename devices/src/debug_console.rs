@@ -8,12 +8,19 @@
 use std::io;
 use std::io::Write;
 use std::sync::{Arc, Barrier};
+use std::time::Instant;
 use vm_device::BusDevice;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
 
 /// I/O-port.
 pub const DEFAULT_PORT: u64 = 0xe9;
 
+/// I/O-port of the debug output of OVMF and other EDK2 based firmwares.
+pub const FIRMWARE_PORT: u64 = 0x402;
+
+/// Value read from the port, used by guests to detect the device.
+const READBACK_VALUE: u8 = 0xe9;
+
 #[derive(Default)]
 pub struct DebugconState {}
 
@@ -29,19 +36,54 @@ pub struct DebugconState {}
 pub struct DebugConsole {
     id: String,
     out: Box<dyn io::Write + Send>,
+    /// Start of the VM, when each line gets prefixed with a timestamp.
+    timestamp: Option<Instant>,
+    line_start: bool,
 }
 
 impl DebugConsole {
-    pub fn new(id: String, out: Box<dyn io::Write + Send>) -> Self {
-        Self { id, out }
+    pub fn new(id: String, out: Box<dyn io::Write + Send>, timestamp: Option<Instant>) -> Self {
+        Self {
+            id,
+            out,
+            timestamp,
+            line_start: true,
+        }
+    }
+
+    fn write_timestamped(&mut self, data: &[u8]) -> io::Result<()> {
+        let Some(timestamp) = self.timestamp else {
+            return self.out.write_all(data);
+        };
+
+        for line in data.split_inclusive(|b| *b == b'\n') {
+            if self.line_start {
+                let elapsed = timestamp.elapsed();
+                write!(
+                    self.out,
+                    "[{}.{:>06}] ",
+                    elapsed.as_secs(),
+                    elapsed.subsec_micros()
+                )?;
+            }
+            self.out.write_all(line)?;
+            self.line_start = line.ends_with(b"\n");
+        }
+        Ok(())
     }
 }
 
 impl BusDevice for DebugConsole {
-    fn read(&mut self, _base: u64, _offset: u64, _data: &mut [u8]) {}
+    fn read(&mut self, _base: u64, _offset: u64, data: &mut [u8]) {
+        // Firmwares only log to the port once they have read back the
+        // expected value.
+        if data.len() == 1 {
+            data[0] = READBACK_VALUE;
+        }
+    }
 
     fn write(&mut self, _base: u64, _offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
-        if let Err(e) = self.out.write_all(data) {
+        if let Err(e) = self.write_timestamped(data) {
             // unlikely
             error!("debug-console: failed writing data: {e:?}");
         }
@@ -62,3 +104,46 @@ impl Snapshottable for DebugConsole {
 impl Pausable for DebugConsole {}
 impl Transportable for DebugConsole {}
 impl Migratable for DebugConsole {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_debug_console_timestamps() {
+        let out = SharedBuffer::default();
+        let mut console = DebugConsole::new(
+            String::from("debugcon"),
+            Box::new(out.clone()),
+            Some(Instant::now()),
+        );
+        for b in b"Loading\nfirm" {
+            console.write(FIRMWARE_PORT, 0, &[*b]);
+        }
+        console.write(FIRMWARE_PORT, 0, b"ware\n");
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("[0.") && lines[0].ends_with("] Loading"));
+        assert!(lines[1].starts_with("[0.") && lines[1].ends_with("] firmware"));
+
+        let mut data = [0];
+        console.read(FIRMWARE_PORT, 0, &mut data);
+        assert_eq!(data[0], 0xe9);
+    }
+}
@@ -0,0 +1,310 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Generic Hardware Error Source
+//!
+//! Reports hardware errors to the guest through APEI (ACPI Platform Error
+//! Interfaces). The error is recorded as a Common Platform Error Record in an
+//! error status block, which the guest locates through the Error Status
+//! Address register the HEST points at, and reads once notified through the
+//! GED. The guest then clears the block status to acknowledge the error.
+
+use acpi_tables::{aml, Aml, AmlSink};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Barrier};
+use thiserror::Error;
+use vm_device::BusDevice;
+use vm_memory::GuestAddress;
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionedState,
+};
+
+pub const GHES_DEVICE_MMIO_SIZE: u64 = 0x100;
+/// The error status block follows the Error Status Address register, which
+/// is at the start of the MMIO region.
+pub const GHES_ERROR_STATUS_BLOCK_OFFSET: u64 = 0x10;
+pub const GHES_ERROR_STATUS_BLOCK_LENGTH: u32 =
+    (GHES_DEVICE_MMIO_SIZE - GHES_ERROR_STATUS_BLOCK_OFFSET) as u32;
+
+const ERROR_STATUS_BLOCK_HEADER_SIZE: usize = 20;
+const ERROR_DATA_ENTRY_HEADER_SIZE: usize = 72;
+const MEMORY_ERROR_SECTION_SIZE: usize = 80;
+
+// Block Status of the Generic Error Status Block
+const BLOCK_STATUS_UNCORRECTABLE: u32 = 1 << 0;
+const BLOCK_STATUS_CORRECTABLE: u32 = 1 << 1;
+const BLOCK_STATUS_ENTRY_COUNT_SHIFT: u32 = 4;
+
+// Revision of the Generic Error Data Entry, as of ACPI 6.1
+const ERROR_DATA_ENTRY_REVISION: u16 = 0x300;
+
+// Platform Memory Error section type A5BC1114-6F64-4EDE-B863-3E83ED7C83B1
+const PLATFORM_MEMORY_ERROR_SECTION: [u8; 16] = [
+    0x14, 0x11, 0xbc, 0xa5, 0x64, 0x6f, 0xde, 0x4e, 0xb8, 0x63, 0x3e, 0x83, 0xed, 0x7c, 0x83, 0xb1,
+];
+
+// Validation bits of the Platform Memory Error section
+const MEMORY_ERROR_PHYSICAL_ADDRESS_VALID: u64 = 1 << 1;
+const MEMORY_ERROR_PHYSICAL_ADDRESS_MASK_VALID: u64 = 1 << 2;
+const MEMORY_ERROR_TYPE_VALID: u64 = 1 << 14;
+
+const MEMORY_ERROR_TYPE_SINGLE_BIT_ECC: u8 = 2;
+const MEMORY_ERROR_TYPE_MULTI_BIT_ECC: u8 = 3;
+
+// The error is reported for the whole page.
+const MEMORY_ERROR_PHYSICAL_ADDRESS_MASK: u64 = !0xfff;
+
+#[derive(Debug, Error)]
+pub enum GhesError {
+    #[error("The guest did not acknowledge the previous error yet")]
+    Busy,
+}
+
+/// Severity of a hardware error, as defined by UEFI for error records.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorSeverity {
+    /// Uncorrected error the guest can recover from, e.g. by offlining the
+    /// affected page.
+    Recoverable,
+    /// Uncorrected error the guest can't recover from.
+    Fatal,
+    /// Error corrected by the hardware.
+    #[default]
+    Corrected,
+}
+
+impl ErrorSeverity {
+    fn value(self) -> u32 {
+        match self {
+            ErrorSeverity::Recoverable => 0,
+            ErrorSeverity::Fatal => 1,
+            ErrorSeverity::Corrected => 2,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GhesDeviceState {
+    error_status_block: Vec<u8>,
+}
+
+impl VersionedState for GhesDeviceState {
+    const VERSION: u16 = 0;
+}
+
+/// A device holding the hardware error records reported to the guest
+pub struct GhesDevice {
+    id: String,
+    address: GuestAddress,
+    error_status_block: Vec<u8>,
+}
+
+impl GhesDevice {
+    pub fn new(id: String, address: GuestAddress, state: Option<GhesDeviceState>) -> Self {
+        let error_status_block = if let Some(state) = state {
+            state.error_status_block
+        } else {
+            vec![0; GHES_ERROR_STATUS_BLOCK_LENGTH as usize]
+        };
+
+        GhesDevice {
+            id,
+            address,
+            error_status_block,
+        }
+    }
+
+    /// Address of the Error Status Address register, to be described by the
+    /// HEST.
+    pub fn error_status_address(&self) -> u64 {
+        self.address.0
+    }
+
+    fn block_status(&self) -> u32 {
+        u32::from_le_bytes(self.error_status_block[..4].try_into().unwrap())
+    }
+
+    /// Record a memory error at the guest physical address `gpa`. The guest
+    /// must be notified through the GED for the error to be processed.
+    pub fn inject_memory_error(
+        &mut self,
+        gpa: GuestAddress,
+        severity: ErrorSeverity,
+    ) -> Result<(), GhesError> {
+        // The block must not be overwritten before the guest has cleared its
+        // status, acknowledging the previous error.
+        if self.block_status() != 0 {
+            return Err(GhesError::Busy);
+        }
+
+        let (block_status, error_type) = match severity {
+            ErrorSeverity::Corrected => {
+                (BLOCK_STATUS_CORRECTABLE, MEMORY_ERROR_TYPE_SINGLE_BIT_ECC)
+            }
+            _ => (BLOCK_STATUS_UNCORRECTABLE, MEMORY_ERROR_TYPE_MULTI_BIT_ECC),
+        };
+        let data_length = (ERROR_DATA_ENTRY_HEADER_SIZE + MEMORY_ERROR_SECTION_SIZE) as u32;
+
+        let mut block = Vec::with_capacity(GHES_ERROR_STATUS_BLOCK_LENGTH as usize);
+        // Generic Error Status Block
+        block.extend_from_slice(
+            &(block_status | (1 << BLOCK_STATUS_ENTRY_COUNT_SHIFT)).to_le_bytes(),
+        );
+        // Raw Data Offset and Length, no raw data follows the entries
+        block.extend_from_slice(
+            &(ERROR_STATUS_BLOCK_HEADER_SIZE as u32 + data_length).to_le_bytes(),
+        );
+        block.extend_from_slice(&0u32.to_le_bytes());
+        block.extend_from_slice(&data_length.to_le_bytes());
+        block.extend_from_slice(&severity.value().to_le_bytes());
+
+        // Generic Error Data Entry
+        block.extend_from_slice(&PLATFORM_MEMORY_ERROR_SECTION);
+        block.extend_from_slice(&severity.value().to_le_bytes());
+        block.extend_from_slice(&ERROR_DATA_ENTRY_REVISION.to_le_bytes());
+        // Validation Bits and Flags: no FRU identifier nor timestamp
+        block.extend_from_slice(&[0, 0]);
+        block.extend_from_slice(&(MEMORY_ERROR_SECTION_SIZE as u32).to_le_bytes());
+        // FRU Id, FRU Text and Timestamp
+        block.resize(
+            ERROR_STATUS_BLOCK_HEADER_SIZE + ERROR_DATA_ENTRY_HEADER_SIZE,
+            0,
+        );
+
+        // Platform Memory Error section
+        let section_start = block.len();
+        block.extend_from_slice(
+            &(MEMORY_ERROR_PHYSICAL_ADDRESS_VALID
+                | MEMORY_ERROR_PHYSICAL_ADDRESS_MASK_VALID
+                | MEMORY_ERROR_TYPE_VALID)
+                .to_le_bytes(),
+        );
+        // Error Status
+        block.extend_from_slice(&0u64.to_le_bytes());
+        block.extend_from_slice(&gpa.0.to_le_bytes());
+        block.extend_from_slice(&MEMORY_ERROR_PHYSICAL_ADDRESS_MASK.to_le_bytes());
+        // Node, card, module and so on up to the Memory Error Type
+        block.resize(section_start + 72, 0);
+        block.push(error_type);
+        block.resize(GHES_ERROR_STATUS_BLOCK_LENGTH as usize, 0);
+
+        self.error_status_block = block;
+
+        Ok(())
+    }
+
+    fn state(&self) -> GhesDeviceState {
+        GhesDeviceState {
+            error_status_block: self.error_status_block.clone(),
+        }
+    }
+}
+
+impl BusDevice for GhesDevice {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        let end = offset + data.len() as u64;
+        if end <= GHES_ERROR_STATUS_BLOCK_OFFSET {
+            let register = (self.address.0 + GHES_ERROR_STATUS_BLOCK_OFFSET).to_le_bytes();
+            if let Some(bytes) = register.get(offset as usize..end as usize) {
+                data.copy_from_slice(bytes);
+                return;
+            }
+        } else if offset >= GHES_ERROR_STATUS_BLOCK_OFFSET && end <= GHES_DEVICE_MMIO_SIZE {
+            let offset = (offset - GHES_ERROR_STATUS_BLOCK_OFFSET) as usize;
+            data.copy_from_slice(&self.error_status_block[offset..offset + data.len()]);
+            return;
+        }
+
+        warn!("Invalid GHES read: offset {}, len {}", offset, data.len());
+        data.fill(0);
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        // The guest acknowledges the error by clearing the block status.
+        if offset >= GHES_ERROR_STATUS_BLOCK_OFFSET
+            && offset + data.len() as u64 <= GHES_DEVICE_MMIO_SIZE
+        {
+            let offset = (offset - GHES_ERROR_STATUS_BLOCK_OFFSET) as usize;
+            self.error_status_block[offset..offset + data.len()].copy_from_slice(data);
+        } else {
+            warn!("Invalid GHES write: offset {}, len {}", offset, data.len());
+        }
+        None
+    }
+}
+
+// The Hardware Error Device, which the GED notifies for the guest to check
+// the error sources of the HEST.
+impl Aml for GhesDevice {
+    fn to_aml_bytes(&self, sink: &mut dyn AmlSink) {
+        aml::Device::new(
+            "_SB_.HED_".into(),
+            vec![
+                &aml::Name::new("_HID".into(), &aml::EISAName::new("PNP0C33")),
+                &aml::Name::new("_UID".into(), &aml::ZERO),
+            ],
+        )
+        .to_aml_bytes(sink)
+    }
+}
+
+impl Pausable for GhesDevice {}
+
+impl Snapshottable for GhesDevice {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+
+impl Transportable for GhesDevice {}
+impl Migratable for GhesDevice {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject_memory_error() {
+        let mut ghes = GhesDevice::new("ghes".to_string(), GuestAddress(0x1000), None);
+
+        let mut register = [0u8; 8];
+        ghes.read(0, 0, &mut register);
+        assert_eq!(u64::from_le_bytes(register), 0x1010);
+
+        ghes.inject_memory_error(GuestAddress(0x12_3456), ErrorSeverity::Recoverable)
+            .unwrap();
+        let mut header = [0u8; ERROR_STATUS_BLOCK_HEADER_SIZE];
+        ghes.read(0, GHES_ERROR_STATUS_BLOCK_OFFSET, &mut header);
+        // One entry, uncorrectable, of a recoverable error
+        assert_eq!(&header[..4], &[0x11, 0, 0, 0]);
+        assert_eq!(&header[12..16], &152u32.to_le_bytes());
+        assert_eq!(&header[16..], &[0, 0, 0, 0]);
+
+        let mut address = [0u8; 8];
+        ghes.read(
+            0,
+            GHES_ERROR_STATUS_BLOCK_OFFSET + (ERROR_STATUS_BLOCK_HEADER_SIZE + 72 + 16) as u64,
+            &mut address,
+        );
+        assert_eq!(u64::from_le_bytes(address), 0x12_3456);
+
+        // The block is only reused once the guest acknowledged the error.
+        assert!(matches!(
+            ghes.inject_memory_error(GuestAddress(0x1000), ErrorSeverity::Corrected),
+            Err(GhesError::Busy)
+        ));
+        ghes.write(0, GHES_ERROR_STATUS_BLOCK_OFFSET, &[0, 0, 0, 0]);
+        ghes.inject_memory_error(GuestAddress(0x1000), ErrorSeverity::Corrected)
+            .unwrap();
+        ghes.read(0, GHES_ERROR_STATUS_BLOCK_OFFSET, &mut header);
+        assert_eq!(&header[..4], &[0x12, 0, 0, 0]);
+        assert_eq!(&header[16..], &[2, 0, 0, 0]);
+    }
+}
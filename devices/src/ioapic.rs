@@ -20,7 +20,9 @@ use vm_device::interrupt::{
 };
 use vm_device::BusDevice;
 use vm_memory::GuestAddress;
-use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionedState,
+};
 use vmm_sys_util::eventfd::EventFd;
 
 type Result<T> = result::Result<T, Error>;
@@ -32,7 +34,8 @@ type Result<T> = result::Result<T, Error>;
 // split between two 32 bits registers as follow:
 //
 // 63-56: Destination Field - R/W
-// 55-17: Reserved
+// 55-49: Extended Destination Field - R/W
+// 48-17: Reserved
 // 16:    Interrupt Mask - R/W
 // 15:    Trigger Mode - R/W
 // 14:    Remote IRR - RO
@@ -69,6 +72,11 @@ fn destination_field(entry: RedirectionTableEntry) -> u8 {
     // retrieve the destination field based on bits 56-63.
     ((entry >> 56) & 0xffu64) as u8
 }
+fn extended_destination_field(entry: RedirectionTableEntry) -> u8 {
+    // Bits 8-14 of the destination ID, used by guests relying on the KVM
+    // extended destination ID to target APIC IDs beyond 255.
+    ((entry >> 49) & 0x7fu64) as u8
+}
 fn set_delivery_status(entry: &mut RedirectionTableEntry, val: u8) {
     // Clear bit 12
     *entry &= 0xffff_ffff_ffff_efff;
@@ -142,6 +150,10 @@ pub struct IoapicState {
     apic_address: u64,
 }
 
+impl VersionedState for IoapicState {
+    const VERSION: u16 = 0;
+}
+
 impl BusDevice for Ioapic {
     fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
         if data.len() != std::mem::size_of::<u32>() {
@@ -336,6 +348,7 @@ impl Ioapic {
         // Validate Destination Mode value, and retrieve Destination ID
         let destination_mode = destination_mode(entry);
         let destination_id = destination_field(entry);
+        let extended_destination_id = extended_destination_field(entry);
 
         // When this bit is set, the message is directed to the processor with
         // the lowest interrupt priority among processors that can receive the
@@ -345,6 +358,7 @@ impl Ioapic {
         // Generate MSI message address
         let low_addr: u32 = self.apic_address.0 as u32
             | u32::from(destination_id) << 12
+            | u32::from(extended_destination_id) << 5
             | u32::from(redirection_hint) << 3
             | u32::from(destination_mode) << 2;
 
@@ -440,7 +454,7 @@ impl Snapshottable for Ioapic {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
-        Snapshot::new_from_state(&self.state())
+        Snapshot::new_from_versioned_state(&self.state())
     }
 }
 
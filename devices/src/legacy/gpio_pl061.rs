@@ -15,7 +15,9 @@ use std::sync::{Arc, Barrier};
 use thiserror::Error;
 use vm_device::interrupt::InterruptSourceGroup;
 use vm_device::BusDevice;
-use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionedState,
+};
 
 const OFS_DATA: u64 = 0x400; // Data Register
 const GPIODIR: u64 = 0x400; // Direction Register
@@ -89,6 +91,10 @@ pub struct GpioState {
     afsel: u32,
 }
 
+impl VersionedState for GpioState {
+    const VERSION: u16 = 0;
+}
+
 impl Gpio {
     /// Constructs an PL061 GPIO device.
     pub fn new(
@@ -313,7 +319,7 @@ impl Snapshottable for Gpio {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
-        Snapshot::new_from_state(&self.state())
+        Snapshot::new_from_versioned_state(&self.state())
     }
 }
 
@@ -15,7 +15,9 @@ use std::{io, result};
 use thiserror::Error;
 use vm_device::interrupt::InterruptSourceGroup;
 use vm_device::BusDevice;
-use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionedState,
+};
 
 /* Registers */
 const UARTDR: u64 = 0;
@@ -103,6 +105,10 @@ pub struct Pl011State {
     read_trigger: u32,
 }
 
+impl VersionedState for Pl011State {
+    const VERSION: u16 = 0;
+}
+
 impl Pl011 {
     /// Constructs an AMBA PL011 UART device.
     pub fn new(
@@ -442,7 +448,7 @@ impl Snapshottable for Pl011 {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
-        Snapshot::new_from_state(&self.state())
+        Snapshot::new_from_versioned_state(&self.state())
     }
 }
 
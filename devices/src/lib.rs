@@ -17,6 +17,7 @@ extern crate log;
 pub mod acpi;
 #[cfg(target_arch = "x86_64")]
 pub mod debug_console;
+pub mod ghes;
 #[cfg(target_arch = "aarch64")]
 pub mod gic;
 pub mod interrupt_controller;
@@ -27,9 +28,12 @@ pub mod legacy;
 pub mod pvmemcontrol;
 pub mod pvpanic;
 pub mod tpm;
+pub mod vmgenid;
 
 pub use self::acpi::{AcpiGedDevice, AcpiPmTimerDevice, AcpiShutdownDevice};
+pub use self::ghes::{GhesDevice, GHES_DEVICE_MMIO_SIZE};
 pub use self::pvpanic::{PvPanicDevice, PVPANIC_DEVICE_MMIO_SIZE};
+pub use self::vmgenid::VmGenIdDevice;
 
 bitflags! {
     pub struct AcpiNotificationFlags: u8 {
@@ -38,6 +42,8 @@ bitflags! {
         const MEMORY_DEVICES_CHANGED = 0b10;
         const PCI_DEVICES_CHANGED = 0b100;
         const POWER_BUTTON_CHANGED = 0b1000;
+        const VMGENID_CHANGED = 0b10000;
+        const HARDWARE_ERROR = 0b100000;
     }
 }
 
@@ -0,0 +1,239 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Virtual Machine Generation ID device
+//!
+//! Exposes a 128-bit identifier to the guest through ACPI, which is changed
+//! whenever the VM is started again from a previous state (e.g. multiple
+//! clones restored from the same snapshot). The guest kernel uses it to reseed
+//! its random number generator and to let userspace know the VM was cloned.
+
+use acpi_tables::{aml, Aml, AmlSink};
+use serde::{Deserialize, Serialize};
+use std::io;
+use thiserror::Error;
+use vm_memory::{
+    bitmap::AtomicBitmap, Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic,
+    GuestMemoryError, GuestMemoryMmap,
+};
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionedState,
+};
+
+#[derive(Error, Debug)]
+pub enum VmGenIdError {
+    #[error("Failed generating the VM generation ID: {0}")]
+    GenerateId(#[source] io::Error),
+    #[error("Failed writing the VM generation ID to guest memory: {0}")]
+    WriteId(#[source] GuestMemoryError),
+}
+
+type Result<T> = std::result::Result<T, VmGenIdError>;
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VmGenIdDeviceState {
+    generation_id: [u8; 16],
+}
+
+impl VersionedState for VmGenIdDeviceState {
+    const VERSION: u16 = 0;
+}
+
+/// A device exposing the VM generation identifier, which lives in guest
+/// memory at an address the guest finds through the ACPI `ADDR` object.
+pub struct VmGenIdDevice {
+    id: String,
+    address: GuestAddress,
+    generation_id: [u8; 16],
+    memory: GuestMemoryAtomic<GuestMemoryMmap<AtomicBitmap>>,
+}
+
+impl VmGenIdDevice {
+    pub fn new(
+        id: String,
+        address: GuestAddress,
+        memory: GuestMemoryAtomic<GuestMemoryMmap<AtomicBitmap>>,
+        state: Option<VmGenIdDeviceState>,
+    ) -> Result<Self> {
+        // When restoring, the guest memory already holds the generation ID,
+        // and might not even be populated yet.
+        let (generation_id, write) = if let Some(state) = state {
+            (state.generation_id, false)
+        } else {
+            (Self::random_id()?, true)
+        };
+
+        let device = VmGenIdDevice {
+            id,
+            address,
+            generation_id,
+            memory,
+        };
+        if write {
+            device.write_id()?;
+        }
+
+        Ok(device)
+    }
+
+    fn random_id() -> Result<[u8; 16]> {
+        let mut id = [0u8; 16];
+        // SAFETY: FFI call with a valid buffer and matching length
+        let ret = unsafe { libc::getrandom(id.as_mut_ptr() as *mut libc::c_void, id.len(), 0) };
+        if ret < 0 {
+            return Err(VmGenIdError::GenerateId(io::Error::last_os_error()));
+        }
+        if ret as usize != id.len() {
+            return Err(VmGenIdError::GenerateId(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short read generating the VM generation ID",
+            )));
+        }
+
+        Ok(id)
+    }
+
+    fn write_id(&self) -> Result<()> {
+        self.memory
+            .memory()
+            .write_slice(&self.generation_id, self.address)
+            .map_err(VmGenIdError::WriteId)
+    }
+
+    /// Replace the generation ID with a new random one. The guest must be
+    /// notified through the GED for the change to be taken into account.
+    pub fn regenerate(&mut self) -> Result<()> {
+        self.generation_id = Self::random_id()?;
+        self.write_id()
+    }
+
+    fn state(&self) -> VmGenIdDeviceState {
+        VmGenIdDeviceState {
+            generation_id: self.generation_id,
+        }
+    }
+}
+
+impl Aml for VmGenIdDevice {
+    fn to_aml_bytes(&self, sink: &mut dyn AmlSink) {
+        aml::Device::new(
+            "_SB_.VGEN".into(),
+            vec![
+                &aml::Name::new("_HID".into(), &"VMGENCTR"),
+                &aml::Name::new("_CID".into(), &"VM_GEN_COUNTER"),
+                &aml::Name::new("_DDN".into(), &"VM_GEN_COUNTER"),
+                &aml::Name::new("_UID".into(), &aml::ZERO),
+                &aml::Name::new(
+                    "ADDR".into(),
+                    &aml::Package::new(vec![
+                        &(self.address.0 as u32),
+                        &((self.address.0 >> 32) as u32),
+                    ]),
+                ),
+            ],
+        )
+        .to_aml_bytes(sink)
+    }
+}
+
+impl Pausable for VmGenIdDevice {}
+
+impl Snapshottable for VmGenIdDevice {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+
+impl Transportable for VmGenIdDevice {}
+impl Migratable for VmGenIdDevice {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_memory::GuestMemory;
+    use vm_migration::compat::check_state_compatibility;
+
+    const VMGENID_ADDRESS: GuestAddress = GuestAddress(0x1000);
+
+    fn memory() -> GuestMemoryAtomic<GuestMemoryMmap<AtomicBitmap>> {
+        GuestMemoryAtomic::new(GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x2000)]).unwrap())
+    }
+
+    fn guest_id(memory: &GuestMemoryAtomic<GuestMemoryMmap<AtomicBitmap>>) -> [u8; 16] {
+        let mut id = [0u8; 16];
+        memory
+            .memory()
+            .read_slice(&mut id, VMGENID_ADDRESS)
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn test_vmgenid_memory() {
+        let memory = memory();
+        let mut device =
+            VmGenIdDevice::new("vmgenid".to_string(), VMGENID_ADDRESS, memory.clone(), None)
+                .unwrap();
+        let id = guest_id(&memory);
+        assert_ne!(id, [0u8; 16]);
+        assert_eq!(id, device.generation_id);
+
+        device.regenerate().unwrap();
+        assert_ne!(guest_id(&memory), id);
+        assert_eq!(guest_id(&memory), device.generation_id);
+
+        // The ID is already in the restored guest memory
+        let memory = self::memory();
+        let device = VmGenIdDevice::new(
+            "vmgenid".to_string(),
+            VMGENID_ADDRESS,
+            memory.clone(),
+            Some(VmGenIdDeviceState { generation_id: id }),
+        )
+        .unwrap();
+        assert_eq!(device.generation_id, id);
+        assert_eq!(guest_id(&memory), [0u8; 16]);
+
+        // The ID must be within the guest memory
+        let end = memory.memory().last_addr();
+        assert!(matches!(
+            VmGenIdDevice::new("vmgenid".to_string(), end, memory, None),
+            Err(VmGenIdError::WriteId(_))
+        ));
+    }
+
+    #[test]
+    fn test_vmgenid_aml() {
+        let device =
+            VmGenIdDevice::new("vmgenid".to_string(), VMGENID_ADDRESS, memory(), None).unwrap();
+        let mut aml = Vec::new();
+        device.to_aml_bytes(&mut aml);
+
+        let contains = |pattern: &[u8]| aml.windows(pattern.len()).any(|w| w == pattern);
+        assert!(contains(b"VMGENCTR"));
+        assert!(contains(b"ADDR"));
+        // Low half of the address, as a DWord constant
+        let mut address = vec![0x0c];
+        address.extend_from_slice(&(VMGENID_ADDRESS.0 as u32).to_le_bytes());
+        assert!(contains(&address));
+        // No resource is claimed, the ID lives in guest RAM
+        assert!(!contains(b"_CRS"));
+    }
+
+    #[test]
+    fn test_state_compatibility() {
+        check_state_compatibility(&[(
+            0,
+            r#"{"generation_id":[1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16]}"#,
+            VmGenIdDeviceState {
+                generation_id: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+            },
+        )]);
+    }
+}
@@ -3,18 +3,24 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 use serde::Serialize;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::io;
 use std::os::unix::io::AsRawFd;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 static MONITOR: OnceCell<MonitorHandle> = OnceCell::new();
 
+// The latest events are kept, with or without a monitor, for the crash
+// reports to include them.
+const RECENT_EVENTS_LEN: usize = 32;
+static RECENT_EVENTS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+
 #[derive(Serialize)]
 struct Event<'a> {
     timestamp: Duration,
@@ -94,20 +100,38 @@ pub fn event_log(source: &str, event: &str, properties: Option<&HashMap<Cow<str>
     // afterwards. This function only creates immutable references to `MONITOR`.
     // Because `MONITOR.tx` is `Sync`, it's safe to share `MONITOR` across
     // threads, making this function thread-safe.
-    if let Some(monitor_handle) = MONITOR.get().as_ref() {
-        let event = Event {
-            timestamp: monitor_handle.start.elapsed(),
-            source,
-            event,
-            properties,
-        };
+    let monitor_handle = MONITOR.get();
+    let event = Event {
+        timestamp: monitor_handle.map_or_else(|| START.elapsed(), |m| m.start.elapsed()),
+        source,
+        event,
+        properties,
+    };
 
+    if let Ok(event) = serde_json::to_string(&event) {
+        let mut recent_events = RECENT_EVENTS.lock().unwrap();
+        if recent_events.len() == RECENT_EVENTS_LEN {
+            recent_events.pop_front();
+        }
+        recent_events.push_back(event);
+    }
+
+    if let Some(monitor_handle) = monitor_handle {
         if let Ok(event) = serde_json::to_string_pretty(&event) {
             monitor_handle.tx.send(event).ok();
         }
     }
 }
 
+/// The latest events, as JSON objects. None are returned while the events
+/// are being recorded, for a crashing thread not to be waited for.
+pub fn recent_events() -> Vec<String> {
+    RECENT_EVENTS
+        .try_lock()
+        .map(|recent_events| recent_events.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
 /*
     Through the use of Cow<'a, str> it is possible to use String as well as
     &str as the parameters:
@@ -11,12 +11,15 @@ use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 use vm_migration::MigratableError;
 use vmm::api::{
-    http::*, ApiRequest, RequestHandler, VmInfoResponse, VmReceiveMigrationData,
-    VmSendMigrationData, VmmPingResponse,
+    http::*, ApiRequest, RequestHandler, VmInfoResponse, VmInjectErrorData, VmMigrateMemoryData,
+    VmPauseData, VmReceiveMigrationData, VmSendMigrationData, VmSetZonePolicyData, VmSnpReportData,
+    VmTimeAdjustData, VmmPingResponse,
 };
 use vmm::config::RestoreConfig;
+use vmm::log_filter::LogFilterError;
 use vmm::vm::{Error as VmError, VmState};
 use vmm::vm_config::*;
 use vmm::{EpollContext, EpollDispatch};
@@ -89,8 +92,8 @@ impl RequestHandler for StubApiRequestHandler {
         Ok(())
     }
 
-    fn vm_pause(&mut self) -> Result<(), VmError> {
-        Ok(())
+    fn vm_pause(&mut self, _: VmPauseData) -> Result<Option<Vec<u8>>, VmError> {
+        Ok(None)
     }
 
     fn vm_resume(&mut self) -> Result<(), VmError> {
@@ -129,6 +132,9 @@ impl RequestHandler for StubApiRequestHandler {
                     max_phys_bits: 46,
                     affinity: None,
                     features: CpuFeatures::default(),
+                    halt_poll_ns: None,
+                    #[cfg(target_arch = "x86_64")]
+                    idle_poll: false,
                 },
                 memory: MemoryConfig {
                     size: 536_870_912,
@@ -142,6 +148,8 @@ impl RequestHandler for StubApiRequestHandler {
                     prefault: false,
                     zones: None,
                     thp: true,
+                    auto_numa: false,
+                    coredump: false,
                 },
                 payload: Some(PayloadConfig {
                     kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -150,6 +158,8 @@ impl RequestHandler for StubApiRequestHandler {
                     initramfs: None,
                     #[cfg(feature = "igvm")]
                     igvm: None,
+                    #[cfg(feature = "igvm")]
+                    igvm_compatibility_mask: None,
                 }),
                 rate_limit_groups: None,
                 disks: None,
@@ -160,18 +170,23 @@ impl RequestHandler for StubApiRequestHandler {
                 },
                 balloon: None,
                 fs: None,
+                gpu: None,
                 pmem: None,
                 serial: ConsoleConfig {
                     file: None,
                     mode: ConsoleOutputMode::Null,
                     iommu: false,
                     socket: None,
+                    tcp: None,
+                    log_socket: None,
                 },
                 console: ConsoleConfig {
                     file: None,
                     mode: ConsoleOutputMode::Tty,
                     iommu: false,
                     socket: None,
+                    tcp: None,
+                    log_socket: None,
                 },
                 #[cfg(target_arch = "x86_64")]
                 debug_console: DebugConsoleConfig::default(),
@@ -185,12 +200,19 @@ impl RequestHandler for StubApiRequestHandler {
                 iommu: false,
                 #[cfg(target_arch = "x86_64")]
                 sgx_epc: None,
+                #[cfg(target_arch = "aarch64")]
+                fdt_overlays: None,
                 numa: None,
                 watchdog: false,
                 gdb: false,
                 pci_segments: None,
                 platform: None,
+                #[cfg(feature = "sev_snp")]
+                sev_snp: None,
                 tpm: None,
+                restart_policy: None,
+                #[cfg(feature = "mdns")]
+                mdns: None,
                 preserved_fds: None,
                 landlock_enable: false,
                 landlock_rules: None,
@@ -218,7 +240,11 @@ impl RequestHandler for StubApiRequestHandler {
         Ok(())
     }
 
-    fn vm_resize(&mut self, _: Option<u8>, _: Option<u64>, _: Option<u64>) -> Result<(), VmError> {
+    fn vmm_set_log_level(&mut self, _: &str) -> Result<(), LogFilterError> {
+        Ok(())
+    }
+
+    fn vm_resize(&mut self, _: Option<u32>, _: Option<u64>, _: Option<u64>) -> Result<(), VmError> {
         Ok(())
     }
 
@@ -234,10 +260,14 @@ impl RequestHandler for StubApiRequestHandler {
         Ok(None)
     }
 
-    fn vm_remove_device(&mut self, _: String) -> Result<(), VmError> {
+    fn vm_remove_device(&mut self, _: String, _: Option<Duration>, _: bool) -> Result<(), VmError> {
         Ok(())
     }
 
+    fn vm_unplug_status(&mut self) -> Result<Option<Vec<u8>>, VmError> {
+        Ok(None)
+    }
+
     fn vm_add_disk(&mut self, _: DiskConfig) -> Result<Option<Vec<u8>>, VmError> {
         Ok(None)
     }
@@ -254,14 +284,34 @@ impl RequestHandler for StubApiRequestHandler {
         Ok(None)
     }
 
+    fn vm_swap_net(&mut self, _: NetConfig) -> Result<(), VmError> {
+        Ok(())
+    }
+
     fn vm_add_vdpa(&mut self, _: VdpaConfig) -> Result<Option<Vec<u8>>, VmError> {
         Ok(None)
     }
 
+    fn vm_add_console(&mut self, _: ConsolePortConfig) -> Result<Option<Vec<u8>>, VmError> {
+        Ok(None)
+    }
+
     fn vm_add_vsock(&mut self, _: VsockConfig) -> Result<Option<Vec<u8>>, VmError> {
         Ok(None)
     }
 
+    fn vm_counters_shm(&mut self) -> Result<Option<Vec<u8>>, VmError> {
+        Ok(None)
+    }
+
+    fn vm_launch_measurement(&mut self) -> Result<Option<Vec<u8>>, VmError> {
+        Ok(None)
+    }
+
+    fn vm_snp_report(&mut self, _: VmSnpReportData) -> Result<Option<Vec<u8>>, VmError> {
+        Ok(None)
+    }
+
     fn vm_counters(&mut self) -> Result<Option<Vec<u8>>, VmError> {
         Ok(None)
     }
@@ -281,6 +331,26 @@ impl RequestHandler for StubApiRequestHandler {
     fn vm_nmi(&mut self) -> Result<(), VmError> {
         Ok(())
     }
+
+    fn vm_time_info(&mut self) -> Result<Option<Vec<u8>>, VmError> {
+        Ok(None)
+    }
+
+    fn vm_time_adjust(&mut self, _: VmTimeAdjustData) -> Result<(), VmError> {
+        Ok(())
+    }
+
+    fn vm_inject_error(&mut self, _: VmInjectErrorData) -> Result<(), VmError> {
+        Ok(())
+    }
+
+    fn vm_migrate_memory(&mut self, _: VmMigrateMemoryData) -> Result<(), VmError> {
+        Ok(())
+    }
+
+    fn vm_set_zone_policy(&mut self, _: VmSetZonePolicyData) -> Result<(), VmError> {
+        Ok(())
+    }
 }
 
 fn http_receiver_stub(exit_evt: EventFd, api_evt: EventFd, api_receiver: Receiver<ApiRequest>) {
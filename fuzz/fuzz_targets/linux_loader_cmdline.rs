@@ -25,6 +25,8 @@ fuzz_target!(|bytes| {
         initramfs: None,
         #[cfg(feature = "igvm")]
         igvm: None,
+        #[cfg(feature = "igvm")]
+        igvm_compatibility_mask: None,
     };
     let kernel_cmdline = match vmm::vm::Vm::generate_cmdline(&payload_config) {
         Ok(cmdline) => cmdline,
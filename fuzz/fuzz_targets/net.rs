@@ -79,6 +79,7 @@ fuzz_target!(|bytes| {
         true,
         true,
         true,
+        false,
     )
     .unwrap();
 
@@ -0,0 +1,681 @@
+// Copyright © 2019 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! ACPI tables, other than the MADT and PPTT which the VMM builds from its
+//! vCPUs state.
+
+use acpi_tables::sdt::{GenericAddress, Sdt};
+use acpi_tables::Aml;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use core::mem;
+use zerocopy::AsBytes;
+
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Default, AsBytes)]
+struct PciRangeEntry {
+    pub base_address: u64,
+    pub segment: u16,
+    pub start: u8,
+    pub end: u8,
+    _reserved: u32,
+}
+
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Default, AsBytes)]
+struct MemoryAffinity {
+    pub type_: u8,
+    pub length: u8,
+    pub proximity_domain: u32,
+    _reserved1: u16,
+    pub base_addr_lo: u32,
+    pub base_addr_hi: u32,
+    pub length_lo: u32,
+    pub length_hi: u32,
+    _reserved2: u32,
+    pub flags: u32,
+    _reserved3: u64,
+}
+
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Default, AsBytes)]
+struct ProcessorLocalX2ApicAffinity {
+    pub type_: u8,
+    pub length: u8,
+    _reserved1: u16,
+    pub proximity_domain: u32,
+    pub x2apic_id: u32,
+    pub flags: u32,
+    pub clock_domain: u32,
+    _reserved2: u32,
+}
+
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Default, AsBytes)]
+struct ProcessorGiccAffinity {
+    pub type_: u8,
+    pub length: u8,
+    pub proximity_domain: u32,
+    pub acpi_processor_uid: u32,
+    pub flags: u32,
+    pub clock_domain: u32,
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct MemAffinityFlags: u32 {
+        const NOFLAGS = 0;
+        const ENABLE = 0b1;
+        const HOTPLUGGABLE = 0b10;
+        const NON_VOLATILE = 0b100;
+    }
+}
+
+impl MemoryAffinity {
+    fn from_range(range: &MemoryRange, proximity_domain: u32) -> Self {
+        let base_addr_lo = (range.base & 0xffff_ffff) as u32;
+        let base_addr_hi = (range.base >> 32) as u32;
+        let length_lo = (range.size & 0xffff_ffff) as u32;
+        let length_hi = (range.size >> 32) as u32;
+
+        MemoryAffinity {
+            type_: 1,
+            length: 40,
+            proximity_domain,
+            base_addr_lo,
+            base_addr_hi,
+            length_lo,
+            length_hi,
+            flags: range.flags.bits(),
+            ..Default::default()
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Default, AsBytes)]
+struct ViotVirtioPciNode {
+    pub type_: u8,
+    _reserved: u8,
+    pub length: u16,
+    pub pci_segment: u16,
+    pub pci_bdf_number: u16,
+    _reserved2: [u8; 8],
+}
+
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Default, AsBytes)]
+struct ViotPciRangeNode {
+    pub type_: u8,
+    _reserved: u8,
+    pub length: u16,
+    pub endpoint_start: u32,
+    pub pci_segment_start: u16,
+    pub pci_segment_end: u16,
+    pub pci_bdf_start: u16,
+    pub pci_bdf_end: u16,
+    pub output_node: u16,
+    _reserved2: [u8; 6],
+}
+
+/// Registers of the fixed hardware features described by the FADT. A
+/// missing register leaves the matching feature unsupported.
+#[derive(Clone, Copy, Default)]
+pub struct AcpiPlatformAddresses {
+    pub pm_timer_address: Option<GenericAddress>,
+    pub reset_reg_address: Option<GenericAddress>,
+    pub sleep_control_reg_address: Option<GenericAddress>,
+    pub sleep_status_reg_address: Option<GenericAddress>,
+}
+
+/// PCI segment, with its ECAM region.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PciSegmentInfo {
+    pub id: u16,
+    pub mmio_config_address: u64,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// Guest physical memory range of a NUMA node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRange {
+    pub base: u64,
+    pub size: u64,
+    pub flags: MemAffinityFlags,
+}
+
+/// NUMA node, as described by the SRAT and the SLIT.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NumaNode {
+    pub proximity_domain: u32,
+    pub memory_ranges: Vec<MemoryRange>,
+    /// x2APIC id of each processor on x86_64, ACPI processor UID on aarch64.
+    pub processors: Vec<u32>,
+    /// Distance to the other proximity domains. A missing distance defaults
+    /// to 20.
+    pub distances: BTreeMap<u32, u8>,
+}
+
+/// Build the DSDT from the AML of `devices`.
+pub fn create_dsdt_table(devices: &[&dyn Aml]) -> Sdt {
+    let mut dsdt = Sdt::new(*b"DSDT", 36, 6, *b"CLOUDH", *b"CHDSDT  ", 1);
+
+    let mut bytes = Vec::new();
+    for device in devices {
+        device.to_aml_bytes(&mut bytes);
+    }
+    dsdt.append_slice(&bytes);
+
+    dsdt
+}
+
+pub fn create_facp_table(dsdt_address: u64, addresses: &AcpiPlatformAddresses) -> Sdt {
+    // Revision 6 of the ACPI FADT table is 276 bytes long
+    let mut facp = Sdt::new(*b"FACP", 276, 6, *b"CLOUDH", *b"CHFACP  ", 1);
+
+    if let Some(address) = addresses.reset_reg_address {
+        // RESET_REG
+        facp.write(116, address);
+        // RESET_VALUE
+        facp.write(128, 1u8);
+    }
+
+    if let Some(address) = addresses.sleep_control_reg_address {
+        // SLEEP_CONTROL_REG
+        facp.write(244, address);
+    }
+
+    if let Some(address) = addresses.sleep_status_reg_address {
+        // SLEEP_STATUS_REG
+        facp.write(256, address);
+    }
+
+    if let Some(address) = addresses.pm_timer_address {
+        // X_PM_TMR_BLK
+        facp.write(208, address);
+    }
+
+    // aarch64 specific fields
+    #[cfg(target_arch = "aarch64")]
+    // ARM_BOOT_ARCH: enable PSCI with HVC enable-method
+    facp.write(129, 3u16);
+
+    // Architecture common fields
+    // HW_REDUCED_ACPI, RESET_REG_SUP, TMR_VAL_EXT
+    let fadt_flags: u32 = 1 << 20 | 1 << 10 | 1 << 8;
+    facp.write(112, fadt_flags);
+    // FADT minor version
+    facp.write(131, 3u8);
+    // X_DSDT
+    facp.write(140, dsdt_address);
+    // Hypervisor Vendor Identity
+    facp.write_bytes(268, b"CLOUDHYP");
+
+    facp.update_checksum();
+
+    facp
+}
+
+pub fn create_mcfg_table(pci_segments: &[PciSegmentInfo]) -> Sdt {
+    let mut mcfg = Sdt::new(*b"MCFG", 36, 1, *b"CLOUDH", *b"CHMCFG  ", 1);
+
+    // MCFG reserved 8 bytes
+    mcfg.append(0u64);
+
+    for segment in pci_segments {
+        // 32-bit PCI enhanced configuration mechanism
+        mcfg.append(PciRangeEntry {
+            base_address: segment.mmio_config_address,
+            segment: segment.id,
+            start: segment.start_bus,
+            end: segment.end_bus,
+            ..Default::default()
+        });
+    }
+    mcfg
+}
+
+/// Build the TPM2 table of a CRB TPM, whose control area is at
+/// `control_area_address`.
+pub fn create_tpm2_table(control_area_address: u64) -> Sdt {
+    let mut tpm = Sdt::new(*b"TPM2", 52, 3, *b"CLOUDH", *b"CHTPM2  ", 1);
+
+    tpm.write(36, 0_u16); //Platform Class
+    tpm.write(38, 0_u16); // Reserved Space
+    tpm.write(40, control_area_address); // Address of Control Area
+    tpm.write(48, 7_u32); //Start Method
+
+    tpm.update_checksum();
+    tpm
+}
+
+pub fn create_srat_table(numa_nodes: &[NumaNode]) -> Sdt {
+    let mut srat = Sdt::new(*b"SRAT", 36, 3, *b"CLOUDH", *b"CHSRAT  ", 1);
+    // SRAT reserved 12 bytes
+    srat.append_slice(&[0u8; 12]);
+
+    // Check the MemoryAffinity structure is the right size as expected by
+    // the ACPI specification.
+    assert_eq!(mem::size_of::<MemoryAffinity>(), 40);
+
+    for node in numa_nodes {
+        let proximity_domain = node.proximity_domain;
+
+        for range in &node.memory_ranges {
+            srat.append(MemoryAffinity::from_range(range, proximity_domain))
+        }
+
+        for processor in &node.processors {
+            // Flags
+            // - Enabled = 1 (bit 0)
+            // - Reserved bits 1-31
+            let flags = 1;
+
+            #[cfg(target_arch = "x86_64")]
+            srat.append(ProcessorLocalX2ApicAffinity {
+                type_: 2,
+                length: 24,
+                proximity_domain,
+                x2apic_id: *processor,
+                flags,
+                clock_domain: 0,
+                ..Default::default()
+            });
+            #[cfg(target_arch = "aarch64")]
+            srat.append(ProcessorGiccAffinity {
+                type_: 3,
+                length: 18,
+                proximity_domain,
+                acpi_processor_uid: *processor,
+                flags,
+                clock_domain: 0,
+            });
+        }
+    }
+    srat
+}
+
+pub fn create_slit_table(numa_nodes: &[NumaNode]) -> Sdt {
+    let mut slit = Sdt::new(*b"SLIT", 36, 1, *b"CLOUDH", *b"CHSLIT  ", 1);
+    // Number of System Localities on 8 bytes.
+    slit.append(numa_nodes.len() as u64);
+
+    for node in numa_nodes {
+        for other in numa_nodes {
+            let dist: u8 = if node.proximity_domain == other.proximity_domain {
+                10
+            } else if let Some(distance) = node.distances.get(&other.proximity_domain) {
+                *distance
+            } else {
+                20
+            };
+
+            slit.append(dist);
+        }
+    }
+    slit
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn create_gtdt_table() -> Sdt {
+    const ARCH_TIMER_NS_EL2_IRQ: u32 = 10;
+    const ARCH_TIMER_VIRT_IRQ: u32 = 11;
+    const ARCH_TIMER_S_EL1_IRQ: u32 = 13;
+    const ARCH_TIMER_NS_EL1_IRQ: u32 = 14;
+    const ACPI_GTDT_INTERRUPT_MODE_LEVEL: u32 = 0;
+    const ACPI_GTDT_CAP_ALWAYS_ON: u32 = 1 << 2;
+
+    let irqflags: u32 = ACPI_GTDT_INTERRUPT_MODE_LEVEL;
+    // GTDT
+    let mut gtdt = Sdt::new(*b"GTDT", 104, 2, *b"CLOUDH", *b"CHGTDT  ", 1);
+    // Secure EL1 Timer GSIV
+    gtdt.write(48, ARCH_TIMER_S_EL1_IRQ + 16);
+    // Secure EL1 Timer Flags
+    gtdt.write(52, irqflags);
+    // Non-Secure EL1 Timer GSIV
+    gtdt.write(56, ARCH_TIMER_NS_EL1_IRQ + 16);
+    // Non-Secure EL1 Timer Flags
+    gtdt.write(60, irqflags | ACPI_GTDT_CAP_ALWAYS_ON);
+    // Virtual EL1 Timer GSIV
+    gtdt.write(64, ARCH_TIMER_VIRT_IRQ + 16);
+    // Virtual EL1 Timer Flags
+    gtdt.write(68, irqflags);
+    // EL2 Timer GSIV
+    gtdt.write(72, ARCH_TIMER_NS_EL2_IRQ + 16);
+    // EL2 Timer Flags
+    gtdt.write(76, irqflags);
+
+    gtdt.update_checksum();
+
+    gtdt
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn create_spcr_table(base_address: u64, gsi: u32) -> Sdt {
+    // SPCR
+    let mut spcr = Sdt::new(*b"SPCR", 80, 2, *b"CLOUDH", *b"CHSPCR  ", 1);
+    // Interface Type
+    spcr.write(36, 3u8);
+    // Base Address in format ACPI Generic Address Structure
+    spcr.write(40, GenericAddress::mmio_address::<u8>(base_address));
+    // Interrupt Type: Bit[3] ARMH GIC interrupt
+    spcr.write(52, (1 << 3) as u8);
+    // Global System Interrupt used by the UART
+    spcr.write(54, gsi.to_le());
+    // Baud Rate: 3 = 9600
+    spcr.write(58, 3u8);
+    // Stop Bits: 1 Stop bit
+    spcr.write(60, 1u8);
+    // Flow Control: Bit[1] = RTS/CTS hardware flow control
+    spcr.write(61, (1 << 1) as u8);
+    // PCI Device ID: Not a PCI device
+    spcr.write(64, 0xffff_u16);
+    // PCI Vendor ID: Not a PCI device
+    spcr.write(66, 0xffff_u16);
+
+    spcr.update_checksum();
+
+    spcr
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn create_dbg2_table(base_address: u64) -> Sdt {
+    let namespace = "_SB_.COM1";
+    let debug_device_info_offset = 44usize;
+    let debug_device_info_len: u16 = 22 /* BaseAddressRegisterOffset */ +
+                       12 /* BaseAddressRegister */ +
+                       4 /* AddressSize */ +
+                       namespace.len() as u16 + 1 /* zero-terminated */;
+    let tbl_len: u32 = debug_device_info_offset as u32 + debug_device_info_len as u32;
+    let mut dbg2 = Sdt::new(*b"DBG2", tbl_len, 0, *b"CLOUDH", *b"CHDBG2  ", 1);
+
+    /* OffsetDbgDeviceInfo */
+    dbg2.write_u32(36, 44);
+    /* NumberDbgDeviceInfo */
+    dbg2.write_u32(40, 1);
+
+    /* Debug Device Information structure */
+    /* Offsets are calculated from the start of this structure. */
+    let namespace_offset = 38u16;
+    let base_address_register_offset = 22u16;
+    let address_size_offset = 34u16;
+    /* Revision */
+    dbg2.write_u8(debug_device_info_offset, 0);
+    /* Length */
+    dbg2.write_u16(debug_device_info_offset + 1, debug_device_info_len);
+    /* NumberofGenericAddressRegisters */
+    dbg2.write_u8(debug_device_info_offset + 3, 1);
+    /* NameSpaceStringLength */
+    dbg2.write_u16(debug_device_info_offset + 4, namespace.len() as u16 + 1);
+    /* NameSpaceStringOffset */
+    dbg2.write_u16(debug_device_info_offset + 6, namespace_offset);
+    /* OemDataLength */
+    dbg2.write_u16(debug_device_info_offset + 8, 0);
+    /* OemDataOffset */
+    dbg2.write_u16(debug_device_info_offset + 10, 0);
+    /* Port Type */
+    dbg2.write_u16(debug_device_info_offset + 12, 0x8000);
+    /* Port Subtype */
+    dbg2.write_u16(debug_device_info_offset + 14, 0x0003);
+    /* Reserved */
+    dbg2.write_u16(debug_device_info_offset + 16, 0);
+    /* BaseAddressRegisterOffset */
+    dbg2.write_u16(debug_device_info_offset + 18, base_address_register_offset);
+    /* AddressSizeOffset */
+    dbg2.write_u16(debug_device_info_offset + 20, address_size_offset);
+    /* BaseAddressRegister */
+    dbg2.write(
+        debug_device_info_offset + base_address_register_offset as usize,
+        GenericAddress::mmio_address::<u8>(base_address),
+    );
+    /* AddressSize */
+    dbg2.write_u32(
+        debug_device_info_offset + address_size_offset as usize,
+        0x1000,
+    );
+    /* NamespaceString, zero-terminated ASCII */
+    for (k, c) in namespace.chars().enumerate() {
+        dbg2.write_u8(
+            debug_device_info_offset + namespace_offset as usize + k,
+            c as u8,
+        );
+    }
+    dbg2.write_u8(
+        debug_device_info_offset + namespace_offset as usize + namespace.len(),
+        0,
+    );
+
+    dbg2.update_checksum();
+
+    dbg2
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn create_iort_table(pci_segments: &[PciSegmentInfo]) -> Sdt {
+    const ACPI_IORT_NODE_ITS_GROUP: u8 = 0x00;
+    const ACPI_IORT_NODE_PCI_ROOT_COMPLEX: u8 = 0x02;
+    const ACPI_IORT_NODE_ROOT_COMPLEX_OFFSET: usize = 72;
+    const ACPI_IORT_NODE_ROOT_COMPLEX_SIZE: usize = 60;
+
+    // The IORT table contains:
+    // - Header (size = 40)
+    // - 1 x ITS Group Node (size = 24)
+    // - N x Root Complex Node (N = number of pci segments, size = 60 x N)
+    let iort_table_size: u32 = (ACPI_IORT_NODE_ROOT_COMPLEX_OFFSET
+        + ACPI_IORT_NODE_ROOT_COMPLEX_SIZE * pci_segments.len())
+        as u32;
+    let mut iort = Sdt::new(*b"IORT", iort_table_size, 2, *b"CLOUDH", *b"CHIORT  ", 1);
+    iort.write(36, ((1 + pci_segments.len()) as u32).to_le());
+    iort.write(40, (48u32).to_le());
+
+    // ITS group node
+    iort.write(48, ACPI_IORT_NODE_ITS_GROUP);
+    // Length of the ITS group node in bytes
+    iort.write(49, (24u16).to_le());
+    // ITS counts
+    iort.write(64, (1u32).to_le());
+
+    // Root Complex Nodes
+    for (i, segment) in pci_segments.iter().enumerate() {
+        let node_offset: usize =
+            ACPI_IORT_NODE_ROOT_COMPLEX_OFFSET + i * ACPI_IORT_NODE_ROOT_COMPLEX_SIZE;
+        iort.write(node_offset, ACPI_IORT_NODE_PCI_ROOT_COMPLEX);
+        // Length of the root complex node in bytes
+        iort.write(
+            node_offset + 1,
+            (ACPI_IORT_NODE_ROOT_COMPLEX_SIZE as u16).to_le(),
+        );
+        // Revision
+        iort.write(node_offset + 3, (3u8).to_le());
+        // Node ID
+        iort.write(node_offset + 4, (segment.id as u32).to_le());
+        // Mapping counts
+        iort.write(node_offset + 8, (1u32).to_le());
+        // Offset from the start of the RC node to the start of its Array of ID mappings
+        iort.write(node_offset + 12, (36u32).to_le());
+        // Fully coherent device
+        iort.write(node_offset + 16, (1u32).to_le());
+        // CCA = CPM = DCAS = 1
+        iort.write(node_offset + 24, 3u8);
+        // PCI segment number
+        iort.write(node_offset + 28, (segment.id as u32).to_le());
+        // Memory address size limit
+        iort.write(node_offset + 32, (64u8).to_le());
+
+        // From offset 32 onward is the space for ID mappings Array.
+        // Now we have only one mapping.
+        let mapping_offset: usize = node_offset + 36;
+        // The lowest value in the input range
+        iort.write(mapping_offset, (0u32).to_le());
+        // The number of IDs in the range minus one:
+        // This should cover all the devices of a segment:
+        // 1 (bus) x 32 (devices) x 8 (functions) = 256
+        // Note: Currently only 1 bus is supported in a segment.
+        iort.write(mapping_offset + 4, (255_u32).to_le());
+        // The lowest value in the output range
+        iort.write(mapping_offset + 8, ((256 * segment.id) as u32).to_le());
+        // id_mapping_array_output_reference should be
+        // the ITS group node (the first node) if no SMMU
+        iort.write(mapping_offset + 12, (48u32).to_le());
+        // Flags
+        iort.write(mapping_offset + 16, (0u32).to_le());
+    }
+
+    iort.update_checksum();
+
+    iort
+}
+
+/// Build the VIOT of a virtio-iommu and the devices attached to it. The
+/// devices are identified by their segment in the upper 16 bits and their
+/// BDF in the lower 16 bits.
+pub fn create_viot_table(iommu_bdf: u32, devices_bdf: &[u32]) -> Sdt {
+    let segment = |bdf: u32| (bdf >> 16) as u16;
+
+    // VIOT
+    let mut viot = Sdt::new(*b"VIOT", 36, 0, *b"CLOUDH", *b"CHVIOT  ", 0);
+    // Node count
+    viot.append((devices_bdf.len() + 1) as u16);
+    // Node offset
+    viot.append(48u16);
+    // VIOT reserved 8 bytes
+    viot.append_slice(&[0u8; 8]);
+
+    // Virtio-iommu based on virtio-pci node
+    viot.append(ViotVirtioPciNode {
+        type_: 3,
+        length: 16,
+        pci_segment: segment(iommu_bdf),
+        pci_bdf_number: iommu_bdf as u16,
+        ..Default::default()
+    });
+
+    for device_bdf in devices_bdf {
+        viot.append(ViotPciRangeNode {
+            type_: 1,
+            length: 24,
+            endpoint_start: *device_bdf,
+            pci_segment_start: segment(*device_bdf),
+            pci_segment_end: segment(*device_bdf),
+            pci_bdf_start: *device_bdf as u16,
+            pci_bdf_end: *device_bdf as u16,
+            output_node: 48,
+            ..Default::default()
+        });
+    }
+
+    viot
+}
+
+/// Build the HEST describing a single Generic Hardware Error Source, whose
+/// Error Status Address register is at `error_status_address`. The guest is
+/// notified of the errors through the interrupt `gsi`.
+pub fn create_hest_table(
+    error_status_address: u64,
+    error_status_block_length: u32,
+    gsi: u32,
+) -> Sdt {
+    let mut hest = Sdt::new(*b"HEST", 104, 1, *b"CLOUDH", *b"CHHEST  ", 1);
+    // Error Source Count
+    hest.write(36, 1u32);
+
+    // Generic Hardware Error Source
+    hest.write(40, 9u16); // Type
+    hest.write(42, 0u16); // Source Id
+    hest.write(44, 0xffff_u16); // Related Source Id: none
+    hest.write(47, 1u8); // Enabled
+    hest.write(48, 1u32); // Number of Records To Pre-allocate
+    hest.write(52, 1u32); // Max Sections Per Record
+    hest.write(56, 0u32); // Max Raw Data Length
+    hest.write(
+        60,
+        GenericAddress::mmio_address::<u64>(error_status_address),
+    );
+
+    // Hardware Error Notification: GSIV
+    hest.write(72, 10u8); // Type
+    hest.write(73, 28u8); // Length
+    hest.write(80, gsi); // Vector
+
+    hest.write(100, error_status_block_length);
+
+    hest.update_checksum();
+    hest
+}
+
+/// Build the XSDT pointing at the tables located at `table_addresses`.
+pub fn create_xsdt_table(table_addresses: &[u64]) -> Sdt {
+    let mut xsdt = Sdt::new(*b"XSDT", 36, 1, *b"CLOUDH", *b"CHXSDT  ", 1);
+    for address in table_addresses {
+        xsdt.append(*address);
+    }
+    xsdt.update_checksum();
+
+    xsdt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum(table: &Sdt) -> u8 {
+        table
+            .as_slice()
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+    }
+
+    #[test]
+    fn test_slit_default_distances() {
+        let nodes = [
+            NumaNode {
+                proximity_domain: 0,
+                distances: BTreeMap::from([(1, 21)]),
+                ..Default::default()
+            },
+            NumaNode {
+                proximity_domain: 1,
+                ..Default::default()
+            },
+        ];
+        let slit = create_slit_table(&nodes);
+
+        assert_eq!(slit.len(), 36 + 8 + 4);
+        assert_eq!(&slit.as_slice()[44..], &[10, 21, 20, 10]);
+    }
+
+    #[test]
+    fn test_table_checksums() {
+        let srat = create_srat_table(&[NumaNode {
+            proximity_domain: 0,
+            memory_ranges: alloc::vec![MemoryRange {
+                base: 0,
+                size: 1 << 32,
+                flags: MemAffinityFlags::ENABLE,
+            }],
+            processors: alloc::vec![0, 1],
+            ..Default::default()
+        }]);
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(srat.len(), 48 + 40 + 2 * 24);
+
+        let facp = create_facp_table(0x1000, &AcpiPlatformAddresses::default());
+        assert_eq!(checksum(&facp), 0);
+        let hest = create_hest_table(0x1000, 0xf0, 5);
+        assert_eq!(checksum(&hest), 0);
+        assert_eq!(hest.len(), 104);
+        let xsdt = create_xsdt_table(&[0x1000, 0x2000]);
+        assert_eq!(checksum(&xsdt), 0);
+        assert_eq!(xsdt.len(), 36 + 16);
+    }
+}
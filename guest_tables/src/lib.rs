@@ -0,0 +1,20 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Generation of the tables describing the platform to the guest.
+//!
+//! The tables are built from plain descriptions of the platform rather than
+//! from the VMM objects, and the crate only depends on `core` and `alloc`, so
+//! that it can be reused by firmware projects and other VMMs.
+//!
+//! The MP tables and the aarch64 device tree are still generated by the
+//! `arch` crate, as they are written directly into the guest memory.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod acpi;
+pub mod smbios;
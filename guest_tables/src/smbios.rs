@@ -0,0 +1,232 @@
+// Copyright © 2020 Intel Corporation
+//
+// Copyright 2019 The Chromium OS Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+//
+// SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
+
+//! SMBIOS 3.0 tables.
+
+use alloc::vec::Vec;
+use core::mem;
+use zerocopy::AsBytes;
+
+// Constants sourced from SMBIOS Spec 3.2.0.
+const SM3_MAGIC_IDENT: &[u8; 5usize] = b"_SM3_";
+const BIOS_INFORMATION: u8 = 0;
+const SYSTEM_INFORMATION: u8 = 1;
+const OEM_STRINGS: u8 = 11;
+const END_OF_TABLE: u8 = 127;
+const PCI_SUPPORTED: u64 = 1 << 7;
+const IS_VIRTUAL_MACHINE: u8 = 1 << 4;
+
+fn compute_checksum(bytes: &[u8]) -> u8 {
+    let mut checksum: u8 = 0;
+    for i in bytes.iter() {
+        checksum = checksum.wrapping_add(*i);
+    }
+    (!checksum).wrapping_add(1)
+}
+
+#[repr(C, packed)]
+#[derive(Default, Copy, Clone, AsBytes)]
+struct Smbios30Entrypoint {
+    signature: [u8; 5usize],
+    checksum: u8,
+    length: u8,
+    majorver: u8,
+    minorver: u8,
+    docrev: u8,
+    revision: u8,
+    reserved: u8,
+    max_size: u32,
+    physptr: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Default, Copy, Clone, AsBytes)]
+struct SmbiosBiosInfo {
+    r#type: u8,
+    length: u8,
+    handle: u16,
+    vendor: u8,
+    version: u8,
+    start_addr: u16,
+    release_date: u8,
+    rom_size: u8,
+    characteristics: u64,
+    characteristics_ext1: u8,
+    characteristics_ext2: u8,
+}
+
+#[repr(C, packed)]
+#[derive(Default, Copy, Clone, AsBytes)]
+struct SmbiosSysInfo {
+    r#type: u8,
+    length: u8,
+    handle: u16,
+    manufacturer: u8,
+    product_name: u8,
+    version: u8,
+    serial_number: u8,
+    uuid: [u8; 16usize],
+    wake_up_type: u8,
+    sku: u8,
+    family: u8,
+}
+
+#[repr(C, packed)]
+#[derive(Default, Copy, Clone, AsBytes)]
+struct SmbiosOemStrings {
+    r#type: u8,
+    length: u8,
+    handle: u16,
+    count: u8,
+}
+
+#[repr(C, packed)]
+#[derive(Default, Copy, Clone, AsBytes)]
+struct SmbiosEndOfTable {
+    r#type: u8,
+    length: u8,
+    handle: u16,
+}
+
+/// Identity of the machine reported through SMBIOS.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SmbiosInfo<'a> {
+    pub serial_number: Option<&'a str>,
+    /// System UUID, in the mixed endian encoding used by SMBIOS.
+    pub uuid: [u8; 16],
+    pub oem_strings: Option<&'a [&'a str]>,
+}
+
+fn append_string(tables: &mut Vec<u8>, val: &str) {
+    tables.extend_from_slice(val.as_bytes());
+    tables.push(0);
+}
+
+/// Build the SMBIOS entry point followed by the structures it points to, to
+/// be loaded at the guest physical address `address`.
+pub fn create_smbios_tables(address: u64, info: &SmbiosInfo) -> Vec<u8> {
+    let physptr = address + mem::size_of::<Smbios30Entrypoint>() as u64;
+    let mut tables = Vec::new();
+    let mut handle = 0;
+
+    {
+        handle += 1;
+        let smbios_biosinfo = SmbiosBiosInfo {
+            r#type: BIOS_INFORMATION,
+            length: mem::size_of::<SmbiosBiosInfo>() as u8,
+            handle,
+            vendor: 1,  // First string written in this section
+            version: 2, // Second string written in this section
+            characteristics: PCI_SUPPORTED,
+            characteristics_ext2: IS_VIRTUAL_MACHINE,
+            ..Default::default()
+        };
+        tables.extend_from_slice(smbios_biosinfo.as_bytes());
+        append_string(&mut tables, "cloud-hypervisor");
+        append_string(&mut tables, "0");
+        tables.push(0);
+    }
+
+    {
+        handle += 1;
+        let smbios_sysinfo = SmbiosSysInfo {
+            r#type: SYSTEM_INFORMATION,
+            length: mem::size_of::<SmbiosSysInfo>() as u8,
+            handle,
+            manufacturer: 1, // First string written in this section
+            product_name: 2, // Second string written in this section
+            serial_number: info.serial_number.map(|_| 3).unwrap_or_default(), // 3rd string
+            uuid: info.uuid,
+            ..Default::default()
+        };
+        tables.extend_from_slice(smbios_sysinfo.as_bytes());
+        append_string(&mut tables, "Cloud Hypervisor");
+        append_string(&mut tables, "cloud-hypervisor");
+        if let Some(serial_number) = info.serial_number {
+            append_string(&mut tables, serial_number);
+        }
+        tables.push(0);
+    }
+
+    if let Some(oem_strings) = info.oem_strings {
+        handle += 1;
+        let smbios_oemstrings = SmbiosOemStrings {
+            r#type: OEM_STRINGS,
+            length: mem::size_of::<SmbiosOemStrings>() as u8,
+            handle,
+            count: oem_strings.len() as u8,
+        };
+        tables.extend_from_slice(smbios_oemstrings.as_bytes());
+        for s in oem_strings {
+            append_string(&mut tables, s);
+        }
+        tables.push(0);
+    }
+
+    {
+        handle += 1;
+        let smbios_end = SmbiosEndOfTable {
+            r#type: END_OF_TABLE,
+            length: mem::size_of::<SmbiosEndOfTable>() as u8,
+            handle,
+        };
+        tables.extend_from_slice(smbios_end.as_bytes());
+        tables.extend_from_slice(&[0, 0]);
+    }
+
+    let mut smbios_ep = Smbios30Entrypoint {
+        signature: *SM3_MAGIC_IDENT,
+        length: mem::size_of::<Smbios30Entrypoint>() as u8,
+        // SMBIOS rev 3.2.0
+        majorver: 0x03,
+        minorver: 0x02,
+        docrev: 0x00,
+        revision: 0x01, // SMBIOS 3.0
+        max_size: tables.len() as u32,
+        physptr,
+        ..Default::default()
+    };
+    smbios_ep.checksum = compute_checksum(smbios_ep.as_bytes());
+
+    let mut blob = smbios_ep.as_bytes().to_vec();
+    blob.append(&mut tables);
+    blob
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn struct_size() {
+        assert_eq!(
+            mem::size_of::<Smbios30Entrypoint>(),
+            0x18usize,
+            concat!("Size of: ", stringify!(Smbios30Entrypoint))
+        );
+        assert_eq!(
+            mem::size_of::<SmbiosBiosInfo>(),
+            0x14usize,
+            concat!("Size of: ", stringify!(SmbiosBiosInfo))
+        );
+        assert_eq!(
+            mem::size_of::<SmbiosSysInfo>(),
+            0x1busize,
+            concat!("Size of: ", stringify!(SmbiosSysInfo))
+        );
+    }
+
+    #[test]
+    fn entrypoint_checksum() {
+        let tables = create_smbios_tables(0xf0000, &SmbiosInfo::default());
+        let entrypoint = &tables[..mem::size_of::<Smbios30Entrypoint>()];
+
+        assert_eq!(compute_checksum(entrypoint), 0);
+        assert_eq!(&entrypoint[16..24], &(0xf0000u64 + 0x18).to_le_bytes());
+    }
+}
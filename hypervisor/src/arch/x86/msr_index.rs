@@ -302,6 +302,13 @@ pub const MSR_IA32_FEATURE_CONTROL: ::std::os::raw::c_uint = 0x0000003a;
 pub const MSR_IA32_TSC_ADJUST: ::std::os::raw::c_uint = 0x0000003b;
 pub const MSR_IA32_BNDCFGS: ::std::os::raw::c_uint = 0x00000d90;
 pub const MSR_IA32_XSS: ::std::os::raw::c_uint = 0x00000da0;
+pub const MSR_IA32_U_CET: ::std::os::raw::c_uint = 0x000006a0;
+pub const MSR_IA32_S_CET: ::std::os::raw::c_uint = 0x000006a2;
+pub const MSR_IA32_PL0_SSP: ::std::os::raw::c_uint = 0x000006a4;
+pub const MSR_IA32_PL1_SSP: ::std::os::raw::c_uint = 0x000006a5;
+pub const MSR_IA32_PL2_SSP: ::std::os::raw::c_uint = 0x000006a6;
+pub const MSR_IA32_PL3_SSP: ::std::os::raw::c_uint = 0x000006a7;
+pub const MSR_IA32_INT_SSP_TAB: ::std::os::raw::c_uint = 0x000006a8;
 pub const FEATURE_CONTROL_LOCKED: ::std::os::raw::c_uint = 0x00000001;
 pub const FEATURE_CONTROL_VMXON_ENABLED_INSIDE_SMX: ::std::os::raw::c_uint = 0x00000002;
 pub const FEATURE_CONTROL_VMXON_ENABLED_OUTSIDE_SMX: ::std::os::raw::c_uint = 0x00000004;
@@ -276,6 +276,21 @@ pub enum HypervisorCpuError {
     #[error("Failed to set TSC frequency: {0}")]
     SetTscKhz(#[source] anyhow::Error),
     ///
+    /// Error getting TSC offset
+    ///
+    #[error("Failed to get TSC offset: {0}")]
+    GetTscOffset(#[source] anyhow::Error),
+    ///
+    /// Error getting the shadow stack pointer
+    ///
+    #[error("Failed to get the shadow stack pointer: {0}")]
+    GetShadowStackPointer(#[source] anyhow::Error),
+    ///
+    /// Error setting the shadow stack pointer
+    ///
+    #[error("Failed to set the shadow stack pointer: {0}")]
+    SetShadowStackPointer(#[source] anyhow::Error),
+    ///
     /// Error reading value at given GPA
     ///
     #[error("Failed to read from GPA: {0}")]
@@ -303,12 +318,15 @@ pub enum HypervisorCpuError {
     Nmi(#[source] anyhow::Error),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum VmExit {
     #[cfg(target_arch = "x86_64")]
     IoapicEoi(u8 /* vector */),
     Ignore,
     Reset,
+    /// The guest triple faulted, which resets the machine.
+    #[cfg(target_arch = "x86_64")]
+    TripleFault,
     Shutdown,
     Hyperv,
     #[cfg(feature = "tdx")]
@@ -440,7 +458,7 @@ pub trait Vcpu: Send + Sync {
     /// Configure core registers for a given CPU.
     ///
     #[cfg(target_arch = "aarch64")]
-    fn setup_regs(&self, cpu_id: u8, boot_ip: u64, fdt_start: u64) -> Result<()>;
+    fn setup_regs(&self, cpu_id: u32, boot_ip: u64, fdt_start: u64) -> Result<()>;
     ///
     /// Check if the CPU supports PMU
     ///
@@ -517,6 +535,13 @@ pub trait Vcpu: Send + Sync {
     }
     #[cfg(target_arch = "x86_64")]
     ///
+    /// Get the offset applied to the host TSC for this vCPU if available
+    ///
+    fn tsc_offset(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
     /// X86 specific call to retrieve cpuid leaf
     ///
     fn get_cpuid_values(
@@ -526,7 +551,9 @@ pub trait Vcpu: Send + Sync {
         _xfem: u64,
         _xss: u64,
     ) -> Result<[u32; 4]> {
-        unimplemented!()
+        Err(HypervisorCpuError::GetCpuidVales(anyhow::anyhow!(
+            "Not supported by the hypervisor"
+        )))
     }
 
     #[cfg(feature = "sev_snp")]
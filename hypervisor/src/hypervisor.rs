@@ -8,7 +8,7 @@
 //
 //
 #[cfg(target_arch = "x86_64")]
-use crate::arch::x86::CpuIdEntry;
+use crate::arch::x86::{CpuIdEntry, MsrEntry};
 #[cfg(target_arch = "x86_64")]
 use crate::cpu::CpuVendor;
 #[cfg(feature = "tdx")]
@@ -58,6 +58,11 @@ pub enum HypervisorError {
     #[error("Failed to get the list of supported MSRs: {0}")]
     GetMsrList(#[source] anyhow::Error),
     ///
+    /// Failed to retrieve the MSR-based features.
+    ///
+    #[error("Failed to get the MSR-based features: {0}")]
+    GetMsrFeatures(#[source] anyhow::Error),
+    ///
     /// API version is not compatible
     ///
     #[error("Incompatible API version")]
@@ -121,6 +126,27 @@ pub trait Hypervisor: Send + Sync {
     /// Get the supported CpuID
     ///
     fn get_supported_cpuid(&self) -> Result<Vec<CpuIdEntry>>;
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Get the values of the MSR-based features supported by the hypervisor
+    ///
+    fn get_msr_features(&self) -> Result<Vec<MsrEntry>> {
+        Ok(Vec::new())
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Check if the guest TSC can run at a frequency different from the host
+    ///
+    fn tsc_scaling_supported(&self) -> bool {
+        false
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Check if vCPUs can be given APIC IDs beyond the 8-bit xAPIC range
+    ///
+    fn x2apic_api_supported(&self) -> bool {
+        false
+    }
     ///
     /// Check particular extensions if any
     ///
@@ -140,6 +166,20 @@ pub trait Hypervisor: Send + Sync {
         unimplemented!()
     }
     ///
+    /// Check if TDs can be live migrated, through a migration TD
+    ///
+    #[cfg(feature = "tdx")]
+    fn tdx_migration_supported(&self) -> bool {
+        false
+    }
+    ///
+    /// Check if SEV-SNP guests can be live migrated, through a migration agent
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_migration_supported(&self) -> bool {
+        false
+    }
+    ///
     /// Get the number of supported hardware breakpoints
     ///
     fn get_guest_debug_hw_bps(&self) -> usize {
@@ -12,13 +12,32 @@ pub mod gic;
 
 use crate::kvm::{KvmError, KvmResult};
 use kvm_bindings::{
-    kvm_mp_state, kvm_one_reg, kvm_regs, KVM_REG_ARM_COPROC_MASK, KVM_REG_ARM_CORE,
-    KVM_REG_SIZE_MASK, KVM_REG_SIZE_U32, KVM_REG_SIZE_U64,
+    kvm_mp_state, kvm_one_reg, kvm_regs, KVMIO, KVM_REG_ARM64, KVM_REG_ARM_COPROC_MASK,
+    KVM_REG_ARM_CORE, KVM_REG_SIZE_MASK, KVM_REG_SIZE_SHIFT, KVM_REG_SIZE_U512,
 };
 pub use kvm_bindings::{kvm_one_reg as Register, kvm_vcpu_init as VcpuInit, RegList};
+use kvm_ioctls::VmFd;
 use serde::{Deserialize, Serialize};
+use std::io;
+use vmm_sys_util::ioctl::ioctl_with_ref;
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_iow_nr};
 pub use {kvm_ioctls::Cap, kvm_ioctls::Kvm};
 
+pub const KVM_CAP_COUNTER_OFFSET: u32 = 227;
+
+/// Pseudo-register holding the set of SVE vector lengths. It can only be
+/// written before the vCPU is finalized, hence it is not part of the state.
+pub const KVM_REG_ARM64_SVE_VLS: u64 = KVM_REG_ARM64 | KVM_REG_SIZE_U512 | (0x15 << 16) | 0xffff;
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmArmCounterOffset {
+    counter_offset: u64,
+    reserved: u64,
+}
+
+ioctl_iow_nr!(KVM_ARM_SET_COUNTER_OFFSET, KVMIO, 0xb5, KvmArmCounterOffset);
+
 // This macro gets the offset of a structure (i.e `str`) member (i.e `field`) without having
 // an instance of that structure.
 #[macro_export]
@@ -89,18 +108,40 @@ macro_rules! arm64_core_reg_id {
 ///
 /// * `regid` - The index of the register we are checking.
 pub fn is_system_register(regid: u64) -> bool {
-    if (regid & KVM_REG_ARM_COPROC_MASK as u64) == KVM_REG_ARM_CORE as u64 {
-        return false;
-    }
+    (regid & KVM_REG_ARM_COPROC_MASK as u64) != KVM_REG_ARM_CORE as u64
+}
 
-    let size = regid & KVM_REG_SIZE_MASK;
+/// Returns the size in bytes of a register, as encoded in its index.
+pub fn reg_size(regid: u64) -> usize {
+    1 << ((regid & KVM_REG_SIZE_MASK) >> KVM_REG_SIZE_SHIFT)
+}
 
-    assert!(
-        !(size != KVM_REG_SIZE_U32 && size != KVM_REG_SIZE_U64),
-        "Unexpected register size for system register {size}"
-    );
+/// Reads the host virtual counter, which KVM uses as the base for the
+/// guest counter offset.
+pub fn host_counter() -> u64 {
+    let counter: u64;
+    // SAFETY: reading CNTVCT_EL0 is allowed from EL0 and has no side effect.
+    // The ISB prevents the read from being speculated early.
+    unsafe {
+        std::arch::asm!("isb", "mrs {}, cntvct_el0", out(reg) counter, options(nomem, nostack));
+    }
+    counter
+}
+
+/// Sets the offset between the host counter and the virtual and physical
+/// counters of all vCPUs. This is only possible before any vCPU has run.
+pub fn set_counter_offset(vm_fd: &VmFd, offset: u64) -> io::Result<()> {
+    let counter_offset = KvmArmCounterOffset {
+        counter_offset: offset,
+        ..Default::default()
+    };
+    // SAFETY: IOCTL with correct parameters
+    let ret = unsafe { ioctl_with_ref(vm_fd, KVM_ARM_SET_COUNTER_OFFSET(), &counter_offset) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
 
-    true
+    Ok(())
 }
 
 pub fn check_required_kvm_extensions(kvm: &Kvm) -> KvmResult<()> {
@@ -129,4 +170,14 @@ pub struct VcpuKvmState {
     pub mp_state: kvm_mp_state,
     pub core_regs: kvm_regs,
     pub sys_regs: Vec<kvm_one_reg>,
+    #[serde(default)]
+    pub wide_regs: Vec<WideRegister>,
+}
+
+/// A register larger than 64 bits, such as the SVE vector registers, which
+/// doesn't fit in `kvm_one_reg`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct WideRegister {
+    pub id: u64,
+    pub data: Vec<u8>,
 }
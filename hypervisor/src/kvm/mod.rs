@@ -16,6 +16,11 @@ pub use crate::aarch64::{
     VcpuKvmState,
 };
 #[cfg(target_arch = "aarch64")]
+use crate::aarch64::{
+    host_counter, reg_size, set_counter_offset, WideRegister, KVM_CAP_COUNTER_OFFSET,
+    KVM_REG_ARM64_SVE_VLS,
+};
+#[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::gic::{Vgic, VgicConfig};
 use crate::cpu;
 use crate::hypervisor;
@@ -36,7 +41,8 @@ use std::os::unix::io::AsRawFd;
 #[cfg(feature = "tdx")]
 use std::os::unix::io::RawFd;
 use std::result;
-#[cfg(target_arch = "x86_64")]
+#[cfg(target_arch = "aarch64")]
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 use std::sync::{Arc, RwLock};
@@ -61,7 +67,8 @@ use aarch64::{RegList, Register};
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
     kvm_enable_cap, kvm_msr_entry, MsrList, KVM_CAP_HYPERV_SYNIC, KVM_CAP_SPLIT_IRQCHIP,
-    KVM_GUESTDBG_USE_HW_BP,
+    KVM_CAP_X2APIC_API, KVM_GUESTDBG_USE_HW_BP, KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK,
+    KVM_X2APIC_API_USE_32BIT_IDS,
 };
 #[cfg(target_arch = "x86_64")]
 use x86_64::check_required_kvm_extensions;
@@ -108,6 +115,11 @@ pub use {
 
 #[cfg(target_arch = "x86_64")]
 const KVM_CAP_SGX_ATTRIBUTE: u32 = 196;
+const KVM_CAP_HALT_POLL: u32 = 182;
+#[cfg(target_arch = "x86_64")]
+const KVM_CAP_X86_DISABLE_EXITS: u32 = 143;
+#[cfg(target_arch = "x86_64")]
+const KVM_X86_DISABLE_EXITS_HLT: u64 = 1 << 1;
 
 #[cfg(target_arch = "x86_64")]
 use vmm_sys_util::ioctl_io_nr;
@@ -144,7 +156,11 @@ enum TdxCommand {
 
 #[cfg(feature = "tdx")]
 pub enum TdxExitDetails {
-    GetQuote,
+    /// Shared buffer, at `gpa` and of `size` bytes, to generate a quote in.
+    GetQuote {
+        gpa: u64,
+        size: u64,
+    },
     SetupEventNotifyInterrupt,
 }
 
@@ -404,9 +420,16 @@ pub struct KvmVm {
     #[cfg(target_arch = "x86_64")]
     msrs: Vec<MsrEntry>,
     dirty_log_slots: Arc<RwLock<HashMap<u32, KvmDirtyLogSlot>>>,
-    memfd: Arc<OwnedFd>,
+    // Backing of the private memory, SEV-ES guests don't have any.
+    memfd: Option<Arc<OwnedFd>>,
     #[cfg(feature = "sev_snp")]
     snp: Arc<SnpFd>,
+    #[cfg(feature = "sev_snp")]
+    sev_es: bool,
+    // Offset between the host and guest counters, only tracked when KVM lets
+    // the VMM control it.
+    #[cfg(target_arch = "aarch64")]
+    counter_offset: Option<Arc<AtomicU64>>,
 }
 
 impl KvmVm {
@@ -502,14 +525,54 @@ impl vm::Vm for KvmVm {
     }
 
     #[cfg(feature = "sev_snp")]
-    fn sev_snp_init(&self) -> vm::Result<()> {
-        // TODO: get policy from IGVM
+    fn sev_snp_init(&self, policy: Option<u64>) -> vm::Result<()> {
+        if self.sev_es {
+            info!("Calling KVM_SEV_LAUNCH_START");
+            return self
+                .snp
+                .sev_launch_start(&self.fd)
+                .map_err(|e| vm::HypervisorVmError::InitializeSevSnp(e.into()));
+        }
+
         info!("Calling KVM_SEV_SNP_LAUNCH_START");
         self.snp
-            .launch_start(&self.fd)
+            .launch_start(&self.fd, policy)
             .map_err(|e| vm::HypervisorVmError::InitializeSevSnp(e.into()))
     }
 
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_launch_update_data(&self, uaddr: u64, size: u64) -> vm::Result<()> {
+        // The firmware takes a 32-bit length.
+        let mut offset = 0;
+        while offset < size {
+            let len = std::cmp::min(size - offset, u32::MAX as u64 & !0xfff);
+            self.snp
+                .sev_launch_update_data(&self.fd, uaddr + offset, len as u32)
+                .map_err(|e| vm::HypervisorVmError::ImportIsolatedPages(e.into()))?;
+            offset += len;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_launch_finish(&self) -> vm::Result<Vec<u8>> {
+        info!("Calling KVM_SEV_LAUNCH_UPDATE_VMSA");
+        self.snp
+            .sev_launch_update_vmsa(&self.fd)
+            .map_err(|e| vm::HypervisorVmError::CompleteIsolatedImport(e.into()))?;
+        let measurement = self
+            .snp
+            .sev_launch_measure(&self.fd)
+            .map_err(|e| vm::HypervisorVmError::CompleteIsolatedImport(e.into()))?;
+        info!("Calling KVM_SEV_LAUNCH_FINISH");
+        self.snp
+            .sev_launch_finish(&self.fd)
+            .map_err(|e| vm::HypervisorVmError::CompleteIsolatedImport(e.into()))?;
+
+        Ok(measurement.to_vec())
+    }
+
     #[cfg(feature = "sev_snp")]
     fn import_isolated_pages(
         &self,
@@ -534,16 +597,32 @@ impl vm::Vm for KvmVm {
             return Ok(());
         }
 
-        for (pfn, uaddr) in pfns.iter().zip(uaddrs.iter()) {
+        // Pages contiguous in both the guest and the host address spaces are
+        // added by a single command, the PSP still measuring them one after
+        // the other.
+        let page_size = page_size as u64;
+        let mut start = 0;
+        while start < pfns.len() {
+            let mut count = 1;
+            while start + count < pfns.len()
+                && pfns[start + count] == pfns[start] + count as u64 * (page_size >> 12)
+                && uaddrs[start + count] == uaddrs[start] + count as u64 * page_size
+            {
+                count += 1;
+            }
+
+            let len = count as u64 * page_size;
             self.set_memory_attributes(
-                *pfn << 12,
-                page_size as u64,
+                pfns[start] << 12,
+                len,
                 kvm_bindings::KVM_MEMORY_ATTRIBUTE_PRIVATE as u64,
             )
             .map_err(|e| vm::HypervisorVmError::ImportIsolatedPages(e.into()))?;
             self.snp
-                .launch_update(&self.fd, *uaddr, page_size as u64, *pfn, page_type)
+                .launch_update(&self.fd, uaddrs[start], len, pfns[start], page_type)
                 .map_err(|e| vm::HypervisorVmError::ImportIsolatedPages(e.into()))?;
+
+            start += count;
         }
 
         info!("KVM_SEV_SNP_LAUNCH_UPDATE done");
@@ -553,32 +632,44 @@ impl vm::Vm for KvmVm {
     #[cfg(feature = "sev_snp")]
     fn complete_isolated_import(
         &self,
-        _snp_id_block: igvm_defs::IGVM_VHS_SNP_ID_BLOCK,
-        _host_data: [u8; 32],
-        _id_block_enabled: u8,
+        snp_id_block: igvm_defs::IGVM_VHS_SNP_ID_BLOCK,
+        host_data: [u8; 32],
+        policy: Option<u64>,
+        id_block_enabled: u8,
     ) -> vm::Result<()> {
+        // The policy was given to KVM_SEV_SNP_LAUNCH_START, the ID block
+        // being signed along with it. An unsigned ID block is left out.
         info!("Calling KVM_SEV_SNP_LAUNCH_FINISH");
-        // TODO: assign SNP ID block
-        /*let id_key_alg = snp_id_block.id_key_algorithm;
-        let author_key_alg = snp_id_block.author_key_algorithm;
-        let id_block_signature_r = snp_id_block.id_key_signature.r_comp.as_ref();
-        let id_block_signature_s = snp_id_block.id_key_signature.s_comp.as_ref();
-        let id_pubkey_curve = snp_id_block.id_public_key.curve.to_le_bytes().as_ref();
-        let id_pubkey_qx = snp_id_block.id_public_key.qx.as_ref();
-        let id_pubkey_qy = snp_id_block.id_public_key.qy.as_ref();*/
+        let mut snp_id_block = snp_id_block;
+        if id_block_enabled == 0 {
+            snp_id_block.id_key_algorithm = 0;
+        }
 
         self.snp
-            .launch_finish(&self.fd)
+            .launch_finish(&self.fd, &snp_id_block, policy, host_data)
             .map_err(|e| vm::HypervisorVmError::CompleteIsolatedImport(e.into()))?;
         Ok(())
     }
 
+    #[cfg(feature = "sev_snp")]
+    fn release_page_access(&self, gpa: u64, size: u64) -> vm::Result<()> {
+        // The memory of SEV-ES guests is encrypted in place, it has been
+        // pinned when the region was created.
+        if self.sev_es || self.memfd.is_none() {
+            return Ok(());
+        }
+
+        // The range is backed by the guest_memfd, the guest validates the
+        // private pages when onlining them.
+        self.set_memory_attributes(gpa, size, kvm_bindings::KVM_MEMORY_ATTRIBUTE_PRIVATE as u64)
+    }
+
     ///
     /// Creates a VcpuFd object from a vcpu RawFd.
     ///
     fn create_vcpu(
         &self,
-        id: u8,
+        id: u32,
         vm_ops: Option<Arc<dyn VmOps>>,
     ) -> vm::Result<Arc<dyn cpu::Vcpu>> {
         let fd = self
@@ -594,6 +685,8 @@ impl vm::Vm for KvmVm {
             hyperv_synic: AtomicBool::new(false),
             #[cfg(feature = "sev_snp")]
             vm_fd: self.fd.clone(),
+            #[cfg(feature = "sev_snp")]
+            memfd: self.memfd.clone(),
         };
         Ok(Arc::new(vcpu))
     }
@@ -749,14 +842,22 @@ impl vm::Vm for KvmVm {
             } else {
                 0
             }
-            | KVM_MEM_GUEST_MEMFD;
+            | if self.memfd.is_some() {
+                KVM_MEM_GUEST_MEMFD
+            } else {
+                0
+            };
         kvm_userspace_memory_region2 {
             slot,
             guest_phys_addr,
             memory_size,
             userspace_addr,
             flags,
-            guest_memfd: self.memfd.as_raw_fd() as u32,
+            guest_memfd: self
+                .memfd
+                .as_ref()
+                .map(|memfd| memfd.as_raw_fd() as u32)
+                .unwrap_or_default(),
             guest_memfd_offset: guest_phys_addr,
             ..Default::default()
         }
@@ -793,7 +894,9 @@ impl vm::Vm for KvmVm {
             region.flags = 0;
         }
 
-        region.flags |= KVM_MEM_GUEST_MEMFD;
+        if self.memfd.is_some() {
+            region.flags |= KVM_MEM_GUEST_MEMFD;
+        }
 
         // SAFETY: Safe because guest regions are guaranteed not to overlap.
         unsafe {
@@ -803,12 +906,24 @@ impl vm::Vm for KvmVm {
         }
 
         #[cfg(feature = "sev_snp")]
-        // FIXME: check if all pages have to be private for SNP guests, including VFIO
-        self.set_memory_attributes(
-            region.guest_phys_addr,
-            region.memory_size,
-            kvm_bindings::KVM_MEMORY_ATTRIBUTE_PRIVATE as u64,
-        )?;
+        if self.sev_es {
+            // The memory of SEV-ES guests is encrypted in place, it must be
+            // pinned.
+            let enc_region = kvm_bindings::kvm_enc_region {
+                addr: region.userspace_addr,
+                size: region.memory_size,
+            };
+            self.fd
+                .register_enc_memory_region(&enc_region)
+                .map_err(|e| vm::HypervisorVmError::CreateUserMemory(e.into()))?;
+        } else {
+            // FIXME: check if all pages have to be private for SNP guests, including VFIO
+            self.set_memory_attributes(
+                region.guest_phys_addr,
+                region.memory_size,
+                kvm_bindings::KVM_MEMORY_ATTRIBUTE_PRIVATE as u64,
+            )?;
+        }
         Ok(())
     }
 
@@ -841,6 +956,31 @@ impl vm::Vm for KvmVm {
             .map_err(|e| vm::HypervisorVmError::GetPreferredTarget(e.into()))
     }
 
+    /// Retrieve the guest counter.
+    #[cfg(target_arch = "aarch64")]
+    fn get_counter(&self) -> vm::Result<Option<u64>> {
+        Ok(self
+            .counter_offset
+            .as_ref()
+            .map(|offset| host_counter().wrapping_sub(offset.load(Ordering::Acquire))))
+    }
+
+    /// Set the guest counter.
+    #[cfg(target_arch = "aarch64")]
+    fn set_counter(&self, counter: u64) -> vm::Result<()> {
+        let Some(counter_offset) = self.counter_offset.as_ref() else {
+            warn!("Guest counter can't be set, relying on the vCPU timer registers");
+            return Ok(());
+        };
+
+        let offset = host_counter().wrapping_sub(counter);
+        set_counter_offset(&self.fd, offset)
+            .map_err(|e| vm::HypervisorVmError::SetClock(e.into()))?;
+        counter_offset.store(offset, Ordering::Release);
+
+        Ok(())
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn enable_split_irq(&self) -> vm::Result<()> {
         // Create split irqchip
@@ -857,6 +997,22 @@ impl vm::Vm for KvmVm {
         Ok(())
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> vm::Result<()> {
+        // Use the full 32-bit x2APIC IDs in the LAPIC state and in the MSI
+        // routes, with the extended destination ID of the MSI address.
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_X2APIC_API,
+            ..Default::default()
+        };
+        cap.args[0] =
+            (KVM_X2APIC_API_USE_32BIT_IDS | KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK) as u64;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::EnableX2ApicApi(e.into()))?;
+        Ok(())
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, file: File) -> vm::Result<()> {
         let mut cap = kvm_enable_cap {
@@ -870,6 +1026,19 @@ impl vm::Vm for KvmVm {
         Ok(())
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn disable_hlt_exits(&self) -> vm::Result<()> {
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_X86_DISABLE_EXITS,
+            ..Default::default()
+        };
+        cap.args[0] = KVM_X86_DISABLE_EXITS_HLT;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::DisableHltExits(e.into()))?;
+        Ok(())
+    }
+
     /// Retrieve guest clock.
     #[cfg(target_arch = "x86_64")]
     fn get_clock(&self) -> vm::Result<ClockData> {
@@ -889,6 +1058,18 @@ impl vm::Vm for KvmVm {
             .map_err(|e| vm::HypervisorVmError::SetClock(e.into()))
     }
 
+    fn set_halt_poll_ns(&self, ns: u32) -> vm::Result<()> {
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_HALT_POLL,
+            ..Default::default()
+        };
+        cap.args[0] = ns as u64;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::SetHaltPollNs(e.into()))?;
+        Ok(())
+    }
+
     /// Create a device that is used for passthrough
     fn create_passthrough_device(&self) -> vm::Result<VfioDeviceFd> {
         let mut vfio_dev = kvm_create_device {
@@ -1178,10 +1359,10 @@ impl hypervisor::Hypervisor for KvmHypervisor {
     fn create_vm_with_type(&self, vm_type: u64) -> hypervisor::Result<Arc<dyn vm::Vm>> {
         let fd: VmFd;
         #[cfg(feature = "sev_snp")]
-        let vm_type = if vm_type == 0 {
-            0 /* KVM_X86_DEFAULT_VM */
-        } else {
-            4 /* KVM_X86_SNP_VM  */ // TODO: use kvm_bindings when it's updated to match 6.11
+        let vm_type = match vm_type {
+            0 => 0, /* KVM_X86_DEFAULT_VM */
+            2 => 3, /* KVM_X86_SEV_ES_VM */
+            _ => 4, /* KVM_X86_SNP_VM  */ // TODO: use kvm_bindings when it's updated to match 6.11
         };
         #[cfg(feature = "tdx")]
         let vm_type = if vm_type == 0 {
@@ -1209,16 +1390,25 @@ impl hypervisor::Hypervisor for KvmHypervisor {
 
         let vm_fd = Arc::new(fd);
 
-        info!("Creating memfd");
-        let gmem = kvm_create_guest_memfd {
-            size: 1 << 48,
-            ..Default::default()
+        #[cfg(feature = "sev_snp")]
+        let sev_es = vm_type == 3;
+        #[cfg(not(feature = "sev_snp"))]
+        let sev_es = false;
+
+        let memfd = if sev_es {
+            None
+        } else {
+            info!("Creating memfd");
+            let gmem = kvm_create_guest_memfd {
+                size: 1 << 48,
+                ..Default::default()
+            };
+            let memfd = vm_fd
+                .create_guest_memfd(gmem)
+                .context("Failed to create memfd")
+                .map_err(|e| hypervisor::HypervisorError::VmCreate(e.into()))?;
+            Some(Arc::new(unsafe { OwnedFd::from_raw_fd(memfd) }))
         };
-        let memfd = vm_fd
-            .create_guest_memfd(gmem)
-            .context("Failed to create memfd")
-            .map_err(|e| hypervisor::HypervisorError::VmCreate(e.into()))?;
-        let memfd = unsafe { OwnedFd::from_raw_fd(memfd) };
 
         #[cfg(target_arch = "x86_64")]
         {
@@ -1241,7 +1431,7 @@ impl hypervisor::Hypervisor for KvmHypervisor {
                     fd: vm_fd,
                     msrs,
                     dirty_log_slots: Arc::new(RwLock::new(HashMap::new())),
-                    memfd: Some(memfd),
+                    memfd,
                 }))
             }
 
@@ -1261,28 +1451,44 @@ impl hypervisor::Hypervisor for KvmHypervisor {
                 let snp = SnpFd::new()
                     .context("Failed to open SEV device")
                     .map_err(|e| hypervisor::HypervisorError::VmCreate(e.into()))?;
-                if vm_type == 4
-                /* KVM_X86_SNP_VM */
+                if vm_type == 3 || vm_type == 4
+                /* KVM_X86_SEV_ES_VM or KVM_X86_SNP_VM */
                 {
-                    info!("Calling SEV_SNP_INIT2");
-                    snp.init2(&vm_fd)
+                    info!("Calling SEV_INIT2");
+                    snp.init2(&vm_fd, vm_type == 4)
                         .map_err(|e| hypervisor::HypervisorError::VmCreate(e.into()))?;
                 }
                 Ok(Arc::new(KvmVm {
                     fd: vm_fd,
                     msrs,
                     dirty_log_slots: Arc::new(RwLock::new(HashMap::new())),
-                    memfd: Arc::new(memfd),
+                    memfd,
                     snp: Arc::new(snp),
+                    sev_es,
                 }))
             }
         }
 
         #[cfg(target_arch = "aarch64")]
         {
+            // Start the guest counter at zero, as KVM does by default, while
+            // keeping track of the offset so it can be carried across a
+            // snapshot or a migration.
+            let counter_offset = if self.kvm.check_extension_raw(KVM_CAP_COUNTER_OFFSET.into()) > 0
+            {
+                let offset = host_counter();
+                set_counter_offset(&vm_fd, offset)
+                    .map_err(|e| hypervisor::HypervisorError::VmCreate(e.into()))?;
+                Some(Arc::new(AtomicU64::new(offset)))
+            } else {
+                None
+            };
+
             Ok(Arc::new(KvmVm {
                 fd: vm_fd,
                 dirty_log_slots: Arc::new(RwLock::new(HashMap::new())),
+                memfd,
+                counter_offset,
             }))
         }
     }
@@ -1332,6 +1538,53 @@ impl hypervisor::Hypervisor for KvmHypervisor {
         Ok(v)
     }
 
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// X86 specific call to get the values of the MSR-based features
+    /// (e.g. IA32_ARCH_CAPABILITIES) supported by KVM.
+    ///
+    fn get_msr_features(&self) -> hypervisor::Result<Vec<MsrEntry>> {
+        let msr_list = self
+            .kvm
+            .get_msr_feature_index_list()
+            .map_err(|e| hypervisor::HypervisorError::GetMsrFeatures(e.into()))?;
+
+        let kvm_msrs: Vec<kvm_msr_entry> = msr_list
+            .as_slice()
+            .iter()
+            .map(|index| kvm_msr_entry {
+                index: *index,
+                ..Default::default()
+            })
+            .collect();
+        let mut kvm_msrs = MsrEntries::from_entries(&kvm_msrs).unwrap();
+        let succ = self
+            .kvm
+            .get_msrs(&mut kvm_msrs)
+            .map_err(|e| hypervisor::HypervisorError::GetMsrFeatures(e.into()))?;
+
+        Ok(kvm_msrs.as_slice()[..succ]
+            .iter()
+            .map(|e| (*e).into())
+            .collect())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// X86 specific call to check if KVM can scale the guest TSC
+    ///
+    fn tsc_scaling_supported(&self) -> bool {
+        self.kvm.check_extension(Cap::TscControl)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// X86 specific call to check if KVM handles 32-bit APIC IDs
+    ///
+    fn x2apic_api_supported(&self) -> bool {
+        self.kvm.check_extension(Cap::X2ApicApi)
+    }
+
     #[cfg(target_arch = "aarch64")]
     ///
     /// Retrieve AArch64 host maximum IPA size supported by KVM.
@@ -1391,6 +1644,8 @@ pub struct KvmVcpu {
     hyperv_synic: AtomicBool,
     #[cfg(feature = "sev_snp")]
     vm_fd: Arc<VmFd>,
+    #[cfg(feature = "sev_snp")]
+    memfd: Option<Arc<OwnedFd>>,
 }
 
 /// Implementation of Vcpu trait for KVM
@@ -1936,7 +2191,22 @@ impl cpu::Vcpu for KvmVcpu {
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoapicEoi(vector) => Ok(cpu::VmExit::IoapicEoi(vector)),
                 #[cfg(target_arch = "x86_64")]
-                VcpuExit::Shutdown | VcpuExit::Hlt => Ok(cpu::VmExit::Reset),
+                VcpuExit::Shutdown => Ok(cpu::VmExit::TripleFault),
+                #[cfg(target_arch = "x86_64")]
+                VcpuExit::Hlt => Ok(cpu::VmExit::Reset),
+                // A SEV-ES guest gave up through the GHCB MSR protocol, the
+                // request is left in the GHCB MSR value.
+                #[cfg(all(feature = "sev_snp", target_arch = "x86_64"))]
+                VcpuExit::SystemEvent(event_type, flags)
+                    if event_type == x86_64::snp::KVM_SYSTEM_EVENT_SEV_TERM =>
+                {
+                    let (reason_set, reason_code) = x86_64::snp::ghcb_termination_reason(flags[0]);
+                    error!(
+                        "Guest requested termination: reason set {}, reason code {}",
+                        reason_set, reason_code
+                    );
+                    Ok(cpu::VmExit::Shutdown)
+                }
 
                 #[cfg(target_arch = "aarch64")]
                 VcpuExit::SystemEvent(event_type, flags) => {
@@ -1982,34 +2252,72 @@ impl cpu::Vcpu for KvmVcpu {
                 VcpuExit::Debug(_) => Ok(cpu::VmExit::Debug),
                 #[cfg(feature = "sev_snp")]
                 VcpuExit::Hypercall(hypercall) => {
-                    info!("VcpuExit::Hypercall");
-                    const KVM_HC_MAP_GPA_RANGE: u64 = 12;
-                    const PRIVATE_PAGE_MASK: u64 = 1 << 4;
-                    match hypercall.nr {
-                        KVM_HC_MAP_GPA_RANGE => {
-                            info!("Handling KVM_HC_MAP_GPA_RANGE hyper call");
-                            let address = hypercall.args[0];
-                            let size = hypercall.args[1] << 12;
-                            let is_private =
-                                hypercall.args[2] & PRIVATE_PAGE_MASK == PRIVATE_PAGE_MASK;
-                            let attributes: u64 = if is_private {
-                                kvm_bindings::KVM_MEMORY_ATTRIBUTE_PRIVATE as u64
-                            } else {
-                                0
-                            };
-                            let mem_attributes = kvm_bindings::kvm_memory_attributes {
-                                address,
-                                size,
-                                attributes,
-                                ..Default::default()
+                    if hypercall.nr != x86_64::snp::KVM_HC_MAP_GPA_RANGE {
+                        warn!("Unsupported hypercall {}", hypercall.nr);
+                        *hypercall.ret = x86_64::snp::KVM_ENOSYS.wrapping_neg();
+                        return Ok(cpu::VmExit::Ignore);
+                    }
+
+                    // KVM also raises this hypercall for the page state
+                    // changes the guest requests through the GHCB, 2MB pages
+                    // included.
+                    let range = match x86_64::snp::GpaRange::from_hypercall(&hypercall.args) {
+                        Ok(range) => range,
+                        Err(ret) => {
+                            warn!(
+                                "Invalid KVM_HC_MAP_GPA_RANGE hypercall: {:x?}",
+                                hypercall.args
+                            );
+                            *hypercall.ret = ret;
+                            return Ok(cpu::VmExit::Ignore);
+                        }
+                    };
+                    let attributes: u64 = if range.private {
+                        kvm_bindings::KVM_MEMORY_ATTRIBUTE_PRIVATE as u64
+                    } else {
+                        0
+                    };
+                    let mem_attributes = kvm_bindings::kvm_memory_attributes {
+                        address: range.address,
+                        size: range.size,
+                        attributes,
+                        ..Default::default()
+                    };
+                    self.vm_fd
+                        .set_memory_attributes(mem_attributes)
+                        .map_err(|e| cpu::HypervisorCpuError::RunVcpu(e.into()))?;
+
+                    // Pages made shared are released from the
+                    // guest_memfd, at the offset of their GPA.
+                    if !range.private {
+                        if let Some(memfd) = &self.memfd {
+                            // SAFETY: FFI call with valid arguments
+                            let ret = unsafe {
+                                libc::fallocate64(
+                                    memfd.as_raw_fd(),
+                                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                                    range.address as libc::off64_t,
+                                    range.size as libc::off64_t,
+                                )
                             };
-                            self.vm_fd
-                                .set_memory_attributes(mem_attributes)
-                                .map(|_| cpu::VmExit::Ignore)
-                                .map_err(|e| cpu::HypervisorCpuError::RunVcpu(e.into()))
+                            if ret != 0 {
+                                warn!(
+                                    "Failed to release the private memory at 0x{:x}: {}",
+                                    range.address,
+                                    std::io::Error::last_os_error()
+                                );
+                            }
                         }
-                        _ => Ok(cpu::VmExit::Ignore),
                     }
+
+                    if let Some(vm_ops) = &self.vm_ops {
+                        vm_ops
+                            .memory_converted(range.address, range.size, range.private)
+                            .map_err(|e| cpu::HypervisorCpuError::RunVcpu(e.into()))?;
+                    }
+                    *hypercall.ret = 0;
+
+                    Ok(cpu::VmExit::Ignore)
                 }
                 r => Err(cpu::HypervisorCpuError::RunVcpu(anyhow!(
                     "Unexpected exit reason on vcpu run: {:?}",
@@ -2171,7 +2479,7 @@ impl cpu::Vcpu for KvmVcpu {
     /// Configure core registers for a given CPU.
     ///
     #[cfg(target_arch = "aarch64")]
-    fn setup_regs(&self, cpu_id: u8, boot_ip: u64, fdt_start: u64) -> cpu::Result<()> {
+    fn setup_regs(&self, cpu_id: u32, boot_ip: u64, fdt_start: u64) -> cpu::Result<()> {
         #[allow(non_upper_case_globals)]
         // PSR (Processor State Register) bits.
         // Taken from arch/arm64/include/uapi/asm/ptrace.h.
@@ -2300,6 +2608,30 @@ impl cpu::Vcpu for KvmVcpu {
             }
         }
 
+        // Save the CET state when the guest can use it, the CET MSRs not
+        // being listed by the kernels predating its virtualization.
+        let (cet, shadow_stack) = x86_64::cet_enabled(&cpuid);
+        if cet {
+            use crate::arch::x86::msr_index;
+            for index in [
+                msr_index::MSR_IA32_XSS,
+                msr_index::MSR_IA32_U_CET,
+                msr_index::MSR_IA32_S_CET,
+                msr_index::MSR_IA32_PL0_SSP,
+                msr_index::MSR_IA32_PL1_SSP,
+                msr_index::MSR_IA32_PL2_SSP,
+                msr_index::MSR_IA32_PL3_SSP,
+                msr_index::MSR_IA32_INT_SSP_TAB,
+            ] {
+                if !msr_entries.iter().any(|msr| msr.index == index) {
+                    msr_entries.push(MsrEntry {
+                        index,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
         let expected_num_msrs = msr_entries.len();
         let num_msrs = self.get_msrs(&mut msr_entries)?;
         let msrs = if num_msrs != expected_num_msrs {
@@ -2334,6 +2666,14 @@ impl cpu::Vcpu for KvmVcpu {
 
         let vcpu_events = self.get_vcpu_events()?;
         let tsc_khz = self.tsc_khz()?;
+        let ssp = if shadow_stack {
+            Some(
+                x86_64::get_ssp(&self.fd.lock().unwrap())
+                    .map_err(|e| cpu::HypervisorCpuError::GetShadowStackPointer(e.into()))?,
+            )
+        } else {
+            None
+        };
 
         Ok(VcpuKvmState {
             cpuid,
@@ -2347,6 +2687,7 @@ impl cpu::Vcpu for KvmVcpu {
             xcrs,
             mp_state,
             tsc_khz,
+            ssp,
         }
         .into())
     }
@@ -2381,17 +2722,33 @@ impl cpu::Vcpu for KvmVcpu {
         // all of them. We carve out from the list  the core registers which are
         // represented in the kernel by kvm_regs structure and for which we can
         // calculate the id based on the offset in the structure.
-        reg_list.retain(|regid| is_system_register(*regid));
+        // The SVE vector lengths pseudo-register is skipped as well since it
+        // is fixed when the vCPU is finalized.
+        reg_list.retain(|regid| is_system_register(*regid) && *regid != KVM_REG_ARM64_SVE_VLS);
 
         // Now, for the rest of the registers left in the previously fetched
-        // register list, we are simply calling KVM_GET_ONE_REG.
+        // register list, we are simply calling KVM_GET_ONE_REG. The registers
+        // larger than 64 bits (e.g. SVE) are saved separately.
+        let mut wide_regs: Vec<WideRegister> = Vec::new();
         let indices = reg_list.as_slice();
         for index in indices.iter() {
+            let size = reg_size(*index);
+            if size > mem::size_of::<u64>() {
+                let mut data = vec![0_u8; size];
+                self.fd
+                    .lock()
+                    .unwrap()
+                    .get_one_reg(*index, &mut data)
+                    .map_err(|e| cpu::HypervisorCpuError::GetSysRegister(e.into()))?;
+                wide_regs.push(WideRegister { id: *index, data });
+                continue;
+            }
+
             let mut bytes = [0_u8; 8];
             self.fd
                 .lock()
                 .unwrap()
-                .get_one_reg(*index, &mut bytes)
+                .get_one_reg(*index, &mut bytes[..size])
                 .map_err(|e| cpu::HypervisorCpuError::GetSysRegister(e.into()))?;
             sys_regs.push(kvm_bindings::kvm_one_reg {
                 id: *index,
@@ -2400,6 +2757,7 @@ impl cpu::Vcpu for KvmVcpu {
         }
 
         state.sys_regs = sys_regs;
+        state.wide_regs = wide_regs;
 
         Ok(state.into())
     }
@@ -2489,6 +2847,12 @@ impl cpu::Vcpu for KvmVcpu {
             }
         }
 
+        // The shadow stack pointer is only valid once the CET MSRs are set.
+        if let Some(ssp) = state.ssp {
+            x86_64::set_ssp(&self.fd.lock().unwrap(), ssp)
+                .map_err(|e| cpu::HypervisorCpuError::SetShadowStackPointer(e.into()))?;
+        }
+
         self.set_vcpu_events(&state.vcpu_events)?;
 
         Ok(())
@@ -2507,7 +2871,14 @@ impl cpu::Vcpu for KvmVcpu {
             self.fd
                 .lock()
                 .unwrap()
-                .set_one_reg(reg.id, &reg.addr.to_le_bytes())
+                .set_one_reg(reg.id, &reg.addr.to_le_bytes()[..reg_size(reg.id)])
+                .map_err(|e| cpu::HypervisorCpuError::SetSysRegister(e.into()))?;
+        }
+        for reg in &state.wide_regs {
+            self.fd
+                .lock()
+                .unwrap()
+                .set_one_reg(reg.id, &reg.data)
                 .map_err(|e| cpu::HypervisorCpuError::SetSysRegister(e.into()))?;
         }
 
@@ -2559,7 +2930,10 @@ impl cpu::Vcpu for KvmVcpu {
         }
 
         match tdx_vmcall.subfunction {
-            TDG_VP_VMCALL_GET_QUOTE => Ok(TdxExitDetails::GetQuote),
+            TDG_VP_VMCALL_GET_QUOTE => Ok(TdxExitDetails::GetQuote {
+                gpa: tdx_vmcall.in_r12,
+                size: tdx_vmcall.in_r13,
+            }),
             TDG_VP_VMCALL_SETUP_EVENT_NOTIFY_INTERRUPT => {
                 Ok(TdxExitDetails::SetupEventNotifyInterrupt)
             }
@@ -2685,6 +3059,15 @@ impl cpu::Vcpu for KvmVcpu {
         }
     }
 
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Get the offset applied to the host TSC for this vCPU if available
+    ///
+    fn tsc_offset(&self) -> cpu::Result<Option<u64>> {
+        x86_64::get_tsc_offset(&self.fd.lock().unwrap())
+            .map_err(|e| cpu::HypervisorCpuError::GetTscOffset(e.into()))
+    }
+
     #[cfg(target_arch = "x86_64")]
     ///
     /// Trigger NMI interrupt
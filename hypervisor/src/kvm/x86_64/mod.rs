@@ -16,7 +16,25 @@ use crate::arch::x86::{
     XsaveState, CPUID_FLAG_VALID_INDEX,
 };
 use crate::kvm::{Cap, Kvm, KvmError, KvmResult};
+use kvm_bindings::{kvm_device_attr, kvm_one_reg, KVMIO, KVM_VCPU_TSC_CTRL, KVM_VCPU_TSC_OFFSET};
+use kvm_ioctls::VcpuFd;
 use serde::{Deserialize, Serialize};
+use std::io;
+use vmm_sys_util::ioctl::ioctl_with_ref;
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_iow_nr};
+
+ioctl_iow_nr!(KVM_GET_DEVICE_ATTR, KVMIO, 0xe2, kvm_device_attr);
+ioctl_iow_nr!(KVM_GET_ONE_REG, KVMIO, 0xab, kvm_one_reg);
+ioctl_iow_nr!(KVM_SET_ONE_REG, KVMIO, 0xac, kvm_one_reg);
+
+// KVM_X86_REG_KVM(KVM_REG_GUEST_SSP): 64-bit register of the KVM defined
+// type, the shadow stack pointer being the first one.
+const KVM_REG_GUEST_SSP: u64 = 0x2000_0000_0000_0000 | 0x0030_0000_0000_0000 | 3 << 32;
+
+// CET shadow stack on CPUID 0x7 ECX.
+const CET_SS_ECX_BIT: u32 = 7;
+// CET indirect branch tracking on CPUID 0x7 EDX.
+const CET_IBT_EDX_BIT: u32 = 20;
 
 ///
 /// Export generically-named wrappers of kvm-bindings for Unix-based platforms
@@ -31,6 +49,86 @@ pub use {
     kvm_bindings::KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
 };
 
+///
+/// Read the TSC offset of a vCPU. Returns `None` if KVM doesn't expose it.
+///
+pub fn get_tsc_offset(vcpu_fd: &VcpuFd) -> io::Result<Option<u64>> {
+    let mut offset: u64 = 0;
+    let attr = kvm_device_attr {
+        group: KVM_VCPU_TSC_CTRL,
+        attr: KVM_VCPU_TSC_OFFSET as u64,
+        addr: &mut offset as *mut u64 as u64,
+        flags: 0,
+    };
+    if vcpu_fd.has_device_attr(&attr).is_err() {
+        return Ok(None);
+    }
+
+    // SAFETY: IOCTL with correct parameters, attr.addr points to a u64
+    // living for the duration of the call.
+    let ret = unsafe { ioctl_with_ref(vcpu_fd, KVM_GET_DEVICE_ATTR(), &attr) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(Some(offset))
+}
+
+///
+/// Whether the guest can use CET, shadow stacks or indirect branch tracking,
+/// given its CPUID. Returns whether it can use shadow stacks as well.
+///
+pub fn cet_enabled(cpuid: &[CpuIdEntry]) -> (bool, bool) {
+    cpuid
+        .iter()
+        .find(|entry| entry.function == 0x7 && entry.index == 0)
+        .map(|entry| {
+            let shadow_stack = entry.ecx & (1 << CET_SS_ECX_BIT) != 0;
+            let ibt = entry.edx & (1 << CET_IBT_EDX_BIT) != 0;
+            (shadow_stack || ibt, shadow_stack)
+        })
+        .unwrap_or_default()
+}
+
+///
+/// Read the shadow stack pointer of a vCPU.
+///
+pub fn get_ssp(vcpu_fd: &VcpuFd) -> io::Result<u64> {
+    let mut ssp: u64 = 0;
+    let reg = kvm_one_reg {
+        id: KVM_REG_GUEST_SSP,
+        addr: &mut ssp as *mut u64 as u64,
+    };
+
+    // SAFETY: IOCTL with correct parameters, reg.addr points to a u64
+    // living for the duration of the call.
+    let ret = unsafe { ioctl_with_ref(vcpu_fd, KVM_GET_ONE_REG(), &reg) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ssp)
+}
+
+///
+/// Write the shadow stack pointer of a vCPU.
+///
+pub fn set_ssp(vcpu_fd: &VcpuFd, ssp: u64) -> io::Result<()> {
+    let reg = kvm_one_reg {
+        id: KVM_REG_GUEST_SSP,
+        addr: &ssp as *const u64 as u64,
+    };
+
+    // SAFETY: IOCTL with correct parameters, reg.addr points to a u64
+    // living for the duration of the call.
+    let ret = unsafe { ioctl_with_ref(vcpu_fd, KVM_SET_ONE_REG(), &reg) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
 ///
 /// Check KVM extension for Linux
 ///
@@ -79,6 +177,9 @@ pub struct VcpuKvmState {
     pub xcrs: ExtendedControlRegisters,
     pub mp_state: MpState,
     pub tsc_khz: Option<u32>,
+    /// Shadow stack pointer, when the guest can use CET shadow stacks.
+    #[serde(default)]
+    pub ssp: Option<u64>,
 }
 
 impl From<SegmentRegister> for kvm_segment {
@@ -1,3 +1,4 @@
+use igvm_defs::{IGVM_VHS_SNP_ID_BLOCK, IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY};
 use kvm_bindings::kvm_sev_cmd;
 use kvm_ioctls::VmFd;
 use vmm_sys_util::errno;
@@ -8,6 +9,11 @@ use std::os::fd::{AsRawFd, FromRawFd};
 
 pub(crate) type Result<T> = std::result::Result<T, errno::Error>;
 
+const KVM_SEV_LAUNCH_START: u32 = 2;
+const KVM_SEV_LAUNCH_UPDATE_DATA: u32 = 3;
+const KVM_SEV_LAUNCH_UPDATE_VMSA: u32 = 4;
+const KVM_SEV_LAUNCH_MEASURE: u32 = 6;
+const KVM_SEV_LAUNCH_FINISH: u32 = 7;
 const KVM_SEV_INIT2: u32 = 22;
 const KVM_SEV_SNP_LAUNCH_START: u32 = 100;
 const KVM_SEV_SNP_LAUNCH_UPDATE: u32 = 101;
@@ -23,6 +29,124 @@ pub(crate) struct KvmSevInit {
     pub pad2: [u32; 8],
 }
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct KvmSevLaunchStart {
+    pub handle: u32,
+    pub policy: u32,
+    pub dh_uaddr: u64,
+    pub dh_len: u32,
+    pub pad0: u32,
+    pub session_uaddr: u64,
+    pub session_len: u32,
+    pub pad1: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct KvmSevLaunchUpdateData {
+    pub uaddr: u64,
+    pub len: u32,
+    pub pad0: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct KvmSevLaunchMeasure {
+    pub uaddr: u64,
+    pub len: u32,
+    pub pad0: u32,
+}
+
+/// System event raised on a termination request from a SEV-ES guest, through
+/// the GHCB MSR protocol.
+pub(crate) const KVM_SYSTEM_EVENT_SEV_TERM: u32 = 6;
+
+/// Reason set and reason code of a GHCB MSR protocol termination request.
+pub(crate) fn ghcb_termination_reason(ghcb_msr: u64) -> (u8, u8) {
+    (
+        ((ghcb_msr >> 12) & 0xf) as u8,
+        ((ghcb_msr >> 16) & 0xff) as u8,
+    )
+}
+
+/// Hypercall KVM exits to userspace with to convert a range of guest memory,
+/// the page state changes requested through the GHCB included.
+pub(crate) const KVM_HC_MAP_GPA_RANGE: u64 = 12;
+const KVM_MAP_GPA_RANGE_PAGE_SZ_2M: u64 = 1 << 0;
+const KVM_MAP_GPA_RANGE_ENCRYPTED: u64 = 1 << 4;
+
+// Errors returned to the guest by a hypercall, negated.
+pub(crate) const KVM_ENOSYS: u64 = 1000;
+const KVM_EINVAL: u64 = libc::EINVAL as u64;
+
+const PAGE_SIZE_4K: u64 = 0x1000;
+const PAGE_SIZE_2M: u64 = 0x20_0000;
+
+/// Version of the GHCB protocol of the SNP guests. The AP creation, the
+/// extended guest requests and the page state changes through the GHCB came
+/// with the version 2, KVM answering them as unsupported below it.
+const SNP_GHCB_VERSION: u16 = 2;
+
+/// Range of guest memory a KVM_HC_MAP_GPA_RANGE hypercall converts.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct GpaRange {
+    pub address: u64,
+    pub size: u64,
+    pub private: bool,
+}
+
+impl GpaRange {
+    /// Decodes the arguments of the hypercall: the address, the number of
+    /// 4KB pages and the attributes of the range. A range of 2MB pages must
+    /// be made of whole 2MB pages, the error being the return value of the
+    /// hypercall otherwise.
+    pub(crate) fn from_hypercall(args: &[u64; 6]) -> std::result::Result<Self, u64> {
+        let (address, npages, attributes) = (args[0], args[1], args[2]);
+        let page_size = if attributes & KVM_MAP_GPA_RANGE_PAGE_SZ_2M != 0 {
+            PAGE_SIZE_2M
+        } else {
+            PAGE_SIZE_4K
+        };
+        let size = npages
+            .checked_mul(PAGE_SIZE_4K)
+            .filter(|size| *size != 0 && size % page_size == 0)
+            .filter(|size| address % page_size == 0 && address.checked_add(*size).is_some())
+            .ok_or(KVM_EINVAL.wrapping_neg())?;
+
+        Ok(GpaRange {
+            address,
+            size,
+            private: attributes & KVM_MAP_GPA_RANGE_ENCRYPTED != 0,
+        })
+    }
+}
+
+/// Size of the launch measurement of a SEV-ES guest: the HMAC of the
+/// measured data followed by the nonce.
+pub const SEV_LAUNCH_MEASUREMENT_SIZE: usize = 48;
+
+// See AMD Spec Section 4.3 - Guest Policy
+// Bit 17 is reserved and has to be one.
+const DEFAULT_SNP_POLICY: u64 = 0 |  // minor
+    0 << 8 |  // major
+    1 << 16 |  // SMT
+    1 << 17 |  // MB1
+    0 << 18 |  // MIGRATE_MA
+    1 << 19; // DEBUG
+
+// Sizes of the ID_BLOCK and ID_AUTH_INFO structures of SNP_LAUNCH_FINISH,
+// and offsets of the fields of the latter.
+const ID_BLOCK_SIZE: usize = 0x60;
+const ID_AUTH_INFO_SIZE: usize = 0x1000;
+const ID_BLOCK_SIG_OFFSET: usize = 0x40;
+const ID_KEY_OFFSET: usize = 0x240;
+const AUTHOR_KEY_SIG_OFFSET: usize = 0x680;
+const AUTHOR_KEY_OFFSET: usize = 0x880;
+// Size of the r and s components of a signature, and of the coordinates of
+// a public key.
+const ECDSA_COMPONENT_SIZE: usize = 72;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Default)]
 pub(crate) struct KvmSevSnpLaunchStart {
@@ -83,8 +207,12 @@ impl SnpFd {
         }
     }
 
-    pub(crate) fn init2(&self, vm: &VmFd) -> Result<()> {
-        let mut init = KvmSevInit::default();
+    pub(crate) fn init2(&self, vm: &VmFd, snp: bool) -> Result<()> {
+        let mut init = KvmSevInit {
+            // KVM picks the version of the SEV-ES guests.
+            ghcb_version: if snp { SNP_GHCB_VERSION } else { 0 },
+            ..Default::default()
+        };
         let mut sev_cmd = kvm_sev_cmd {
             id: KVM_SEV_INIT2,
             data: &mut init as *mut KvmSevInit as _,
@@ -94,19 +222,84 @@ impl SnpFd {
         vm.encrypt_op_sev(&mut sev_cmd)
     }
 
-    pub(crate) fn launch_start(&self, vm: &VmFd) -> Result<()> {
-        // See AMD Spec Section 4.3 - Guest Policy
-        // Bit 17 is reserved and has to be one.
-        let policy: u64 = 0 |  // minor
-            0 << 8 |  // major
-            1 << 16 |  // SMT
-            1 << 17 |  // MB1
-            0 << 18 |  // MIGRATE_MA
-            1 << 19; // DEBUG
-        let mut start: KvmSevSnpLaunchStart = KvmSevSnpLaunchStart {
+    pub(crate) fn sev_launch_start(&self, vm: &VmFd) -> Result<()> {
+        // See AMD SEV API Section 3 - Guest Policy
+        let policy: u32 = 1 << 0 | // NODBG
+            1 << 1 | // NOKS
+            1 << 2; // ES
+        let mut start = KvmSevLaunchStart {
             policy,
             ..Default::default()
         };
+        let mut sev_cmd = kvm_sev_cmd {
+            id: KVM_SEV_LAUNCH_START,
+            data: &mut start as *mut KvmSevLaunchStart as _,
+            sev_fd: self.sev_fd.as_raw_fd() as _,
+            ..Default::default()
+        };
+        vm.encrypt_op_sev(&mut sev_cmd)
+    }
+
+    pub(crate) fn sev_launch_update_data(&self, vm: &VmFd, hva: u64, size: u32) -> Result<()> {
+        let mut update = KvmSevLaunchUpdateData {
+            uaddr: hva,
+            len: size,
+            ..Default::default()
+        };
+        let mut sev_cmd = kvm_sev_cmd {
+            id: KVM_SEV_LAUNCH_UPDATE_DATA,
+            data: &mut update as *mut KvmSevLaunchUpdateData as _,
+            sev_fd: self.sev_fd.as_raw_fd() as _,
+            ..Default::default()
+        };
+        vm.encrypt_op_sev(&mut sev_cmd)
+    }
+
+    /// Encrypts and measures the VMSA of every vCPU, their registers can't
+    /// be changed afterwards.
+    pub(crate) fn sev_launch_update_vmsa(&self, vm: &VmFd) -> Result<()> {
+        let mut sev_cmd = kvm_sev_cmd {
+            id: KVM_SEV_LAUNCH_UPDATE_VMSA,
+            sev_fd: self.sev_fd.as_raw_fd() as _,
+            ..Default::default()
+        };
+        vm.encrypt_op_sev(&mut sev_cmd)
+    }
+
+    pub(crate) fn sev_launch_measure(
+        &self,
+        vm: &VmFd,
+    ) -> Result<[u8; SEV_LAUNCH_MEASUREMENT_SIZE]> {
+        let mut measurement = [0u8; SEV_LAUNCH_MEASUREMENT_SIZE];
+        let mut measure = KvmSevLaunchMeasure {
+            uaddr: measurement.as_mut_ptr() as u64,
+            len: SEV_LAUNCH_MEASUREMENT_SIZE as u32,
+            ..Default::default()
+        };
+        let mut sev_cmd = kvm_sev_cmd {
+            id: KVM_SEV_LAUNCH_MEASURE,
+            data: &mut measure as *mut KvmSevLaunchMeasure as _,
+            sev_fd: self.sev_fd.as_raw_fd() as _,
+            ..Default::default()
+        };
+        vm.encrypt_op_sev(&mut sev_cmd)?;
+        Ok(measurement)
+    }
+
+    pub(crate) fn sev_launch_finish(&self, vm: &VmFd) -> Result<()> {
+        let mut sev_cmd = kvm_sev_cmd {
+            id: KVM_SEV_LAUNCH_FINISH,
+            sev_fd: self.sev_fd.as_raw_fd() as _,
+            ..Default::default()
+        };
+        vm.encrypt_op_sev(&mut sev_cmd)
+    }
+
+    pub(crate) fn launch_start(&self, vm: &VmFd, policy: Option<u64>) -> Result<()> {
+        let mut start: KvmSevSnpLaunchStart = KvmSevSnpLaunchStart {
+            policy: policy.unwrap_or(DEFAULT_SNP_POLICY),
+            ..Default::default()
+        };
         let mut sev_cmd = kvm_sev_cmd {
             id: KVM_SEV_SNP_LAUNCH_START,
             data: &mut start as *mut KvmSevSnpLaunchStart as _,
@@ -140,8 +333,27 @@ impl SnpFd {
         vm.encrypt_op_sev(&mut sev_cmd)
     }
 
-    pub(crate) fn launch_finish(&self, vm: &VmFd) -> Result<()> {
-        let mut finish = KvmSevSnpLaunchFinish::default();
+    /// Completes the launch, the ID block being checked by the firmware
+    /// when it is signed.
+    pub(crate) fn launch_finish(
+        &self,
+        vm: &VmFd,
+        snp_id_block: &IGVM_VHS_SNP_ID_BLOCK,
+        policy: Option<u64>,
+        host_data: [u8; 32],
+    ) -> Result<()> {
+        let id_block = id_block(snp_id_block, policy.unwrap_or(DEFAULT_SNP_POLICY));
+        let id_auth_info = id_auth_info(snp_id_block);
+        let mut finish = KvmSevSnpLaunchFinish {
+            host_data,
+            ..Default::default()
+        };
+        if snp_id_block.id_key_algorithm != 0 {
+            finish.id_block_uaddr = id_block.as_ptr() as u64;
+            finish.id_auth_uaddr = id_auth_info.as_ptr() as u64;
+            finish.id_block_en = 1;
+            finish.auth_key_en = snp_id_block.author_key_enabled;
+        }
         let mut sev_cmd = kvm_sev_cmd {
             id: KVM_SEV_SNP_LAUNCH_FINISH,
             data: &mut finish as *mut KvmSevSnpLaunchFinish as _,
@@ -151,3 +363,97 @@ impl SnpFd {
         vm.encrypt_op_sev(&mut sev_cmd)
     }
 }
+
+// ID_BLOCK structure, the policy being the one the guest is launched with.
+fn id_block(snp_id_block: &IGVM_VHS_SNP_ID_BLOCK, policy: u64) -> [u8; ID_BLOCK_SIZE] {
+    let mut id_block = [0u8; ID_BLOCK_SIZE];
+    id_block[..0x30].copy_from_slice(&snp_id_block.ld);
+    id_block[0x30..0x40].copy_from_slice(&snp_id_block.family_id);
+    id_block[0x40..0x50].copy_from_slice(&snp_id_block.image_id);
+    id_block[0x50..0x54].copy_from_slice(&snp_id_block.version.to_le_bytes());
+    id_block[0x54..0x58].copy_from_slice(&snp_id_block.guest_svn.to_le_bytes());
+    id_block[0x58..].copy_from_slice(&policy.to_le_bytes());
+    id_block
+}
+
+// Public key as laid out in ID_AUTH_INFO, the IGVM structure having a
+// reserved field after the curve.
+fn write_public_key(dst: &mut [u8], key: &IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY) {
+    dst[..4].copy_from_slice(&key.curve.to_le_bytes());
+    dst[4..4 + ECDSA_COMPONENT_SIZE].copy_from_slice(&key.qx);
+    dst[4 + ECDSA_COMPONENT_SIZE..4 + 2 * ECDSA_COMPONENT_SIZE].copy_from_slice(&key.qy);
+}
+
+// ID_AUTH_INFO structure, holding the signatures and public keys.
+fn id_auth_info(snp_id_block: &IGVM_VHS_SNP_ID_BLOCK) -> Box<[u8; ID_AUTH_INFO_SIZE]> {
+    let mut info = Box::new([0u8; ID_AUTH_INFO_SIZE]);
+    info[..4].copy_from_slice(&snp_id_block.id_key_algorithm.to_le_bytes());
+    info[4..8].copy_from_slice(&snp_id_block.author_key_algorithm.to_le_bytes());
+
+    let signature = &snp_id_block.id_key_signature;
+    let offset = ID_BLOCK_SIG_OFFSET;
+    info[offset..offset + ECDSA_COMPONENT_SIZE].copy_from_slice(&signature.r_comp);
+    info[offset + ECDSA_COMPONENT_SIZE..offset + 2 * ECDSA_COMPONENT_SIZE]
+        .copy_from_slice(&signature.s_comp);
+    write_public_key(&mut info[ID_KEY_OFFSET..], &snp_id_block.id_public_key);
+
+    if snp_id_block.author_key_enabled != 0 {
+        let signature = &snp_id_block.author_key_signature;
+        let offset = AUTHOR_KEY_SIG_OFFSET;
+        info[offset..offset + ECDSA_COMPONENT_SIZE].copy_from_slice(&signature.r_comp);
+        info[offset + ECDSA_COMPONENT_SIZE..offset + 2 * ECDSA_COMPONENT_SIZE]
+            .copy_from_slice(&signature.s_comp);
+        write_public_key(
+            &mut info[AUTHOR_KEY_OFFSET..],
+            &snp_id_block.author_public_key,
+        );
+    }
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ghcb_termination_reason() {
+        // GHCB_MSR_TERM_REQ with the general reason set and the
+        // "unsupported GHCB protocol version" code.
+        assert_eq!(ghcb_termination_reason(0x0001_0100), (0, 1));
+        assert_eq!(ghcb_termination_reason(0x0003_1100), (1, 3));
+    }
+
+    #[test]
+    fn test_gpa_range_from_hypercall() {
+        // A 4KB page made shared.
+        assert_eq!(
+            GpaRange::from_hypercall(&[0x1000, 1, 0, 0, 0, 0]),
+            Ok(GpaRange {
+                address: 0x1000,
+                size: 0x1000,
+                private: false,
+            })
+        );
+        // A 2MB page made private.
+        assert_eq!(
+            GpaRange::from_hypercall(&[0x20_0000, 512, 0x11, 0, 0, 0]),
+            Ok(GpaRange {
+                address: 0x20_0000,
+                size: 0x20_0000,
+                private: true,
+            })
+        );
+        // Misaligned 2MB pages, and an empty range.
+        let einval = Err(KVM_EINVAL.wrapping_neg());
+        assert_eq!(
+            GpaRange::from_hypercall(&[0x1000, 512, 0x11, 0, 0, 0]),
+            einval
+        );
+        assert_eq!(
+            GpaRange::from_hypercall(&[0x20_0000, 256, 0x11, 0, 0, 0]),
+            einval
+        );
+        assert_eq!(GpaRange::from_hypercall(&[0x1000, 0, 0, 0, 0, 0]), einval);
+    }
+}
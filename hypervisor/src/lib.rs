@@ -61,6 +61,8 @@ pub use vm::{
     DataMatch, HypervisorVmError, InterruptSourceConfig, LegacyIrqSourceConfig, MsiIrqSourceConfig,
     Vm, VmOps,
 };
+#[cfg(feature = "tdx")]
+pub use vm::{TdxMigrationSession, TdxMigrationTd};
 
 #[derive(Debug, Copy, Clone)]
 pub enum HypervisorType {
@@ -165,6 +167,19 @@ pub enum CpuState {
     Mshv(mshv::VcpuMshvState),
 }
 
+impl CpuState {
+    /// TSC frequency of the vCPU, when saved along with its state.
+    #[cfg(target_arch = "x86_64")]
+    pub fn tsc_khz(&self) -> Option<u32> {
+        match self {
+            #[cfg(feature = "kvm")]
+            CpuState::Kvm(state) => state.tsc_khz,
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 #[cfg(target_arch = "x86_64")]
 pub enum ClockData {
@@ -184,6 +199,48 @@ impl ClockData {
             _ => {}
         }
     }
+
+    /// Value of the guest clock, in nanoseconds.
+    pub fn clock_ns(&self) -> u64 {
+        match self {
+            #[cfg(feature = "kvm")]
+            ClockData::Kvm(s) => s.clock,
+            #[cfg(feature = "mshv")]
+            ClockData::Mshv(s) => s.ref_time * 100,
+        }
+    }
+
+    /// Host realtime clock when the guest clock was sampled, in nanoseconds.
+    pub fn realtime_ns(&self) -> Option<u64> {
+        match self {
+            #[cfg(feature = "kvm")]
+            ClockData::Kvm(s) if s.flags & kvm_bindings::KVM_CLOCK_REALTIME != 0 => {
+                Some(s.realtime)
+            }
+            _ => None,
+        }
+    }
+
+    /// Host TSC when the guest clock was sampled.
+    pub fn host_tsc(&self) -> Option<u64> {
+        match self {
+            #[cfg(feature = "kvm")]
+            ClockData::Kvm(s) if s.flags & kvm_bindings::KVM_CLOCK_HOST_TSC != 0 => {
+                Some(s.host_tsc)
+            }
+            _ => None,
+        }
+    }
+
+    /// Move the guest clock forward.
+    pub fn advance(&mut self, delta_ns: u64) {
+        match self {
+            #[cfg(feature = "kvm")]
+            ClockData::Kvm(s) => s.clock += delta_ns,
+            #[cfg(feature = "mshv")]
+            ClockData::Mshv(s) => s.ref_time += delta_ns / 100,
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -59,6 +59,9 @@ pub use {
 
 pub const PAGE_SHIFT: usize = 12;
 
+#[cfg(feature = "sev_snp")]
+const MAX_MODIFY_GPA_HOST_ACCESS_PAGES: usize = 1 << 16;
+
 impl From<mshv_user_mem_region> for UserMemoryRegion {
     fn from(region: mshv_user_mem_region) -> Self {
         let mut flags: u32 = 0;
@@ -353,6 +356,8 @@ impl hypervisor::Hypervisor for MshvHypervisor {
                 dirty_log_slots: Arc::new(RwLock::new(HashMap::new())),
                 #[cfg(feature = "sev_snp")]
                 sev_snp_enabled: mshv_vm_type == VmType::Snp,
+                #[cfg(feature = "sev_snp")]
+                snp_certificates: Arc::new(RwLock::new(Vec::new())),
             }))
         }
 
@@ -419,6 +424,8 @@ pub struct MshvVcpu {
     msrs: Vec<MsrEntry>,
     vm_ops: Option<Arc<dyn vm::VmOps>>,
     vm_fd: Arc<VmFd>,
+    #[cfg(feature = "sev_snp")]
+    snp_certificates: Arc<RwLock<Vec<u8>>>,
 }
 
 /// Implementation of Vcpu trait for Microsoft Hypervisor
@@ -565,7 +572,7 @@ impl cpu::Vcpu for MshvVcpu {
                 }
                 hv_message_type_HVMSG_UNRECOVERABLE_EXCEPTION => {
                     warn!("TRIPLE FAULT");
-                    Ok(cpu::VmExit::Shutdown)
+                    Ok(cpu::VmExit::TripleFault)
                 }
                 #[cfg(target_arch = "x86_64")]
                 hv_message_type_HVMSG_X64_IO_PORT_INTERCEPT => {
@@ -751,7 +758,7 @@ impl cpu::Vcpu for MshvVcpu {
                     let gpa = info.guest_physical_address;
 
                     Err(cpu::HypervisorCpuError::RunVcpu(anyhow!(
-                        "Unhandled VCPU exit: Unaccepted GPA({:x}) found at GVA({:x})",
+                        "Unhandled VCPU exit: Unaccepted GPA({:x}) found at GVA({:x}), the guest must accept memory before use",
                         gpa,
                         gva,
                     )))
@@ -1073,10 +1080,6 @@ impl cpu::Vcpu for MshvVcpu {
                                 SVM_EXITCODE_SNP_GUEST_REQUEST
                                 | SVM_EXITCODE_SNP_EXTENDED_GUEST_REQUEST => {
                                     if exit_code == SVM_EXITCODE_SNP_EXTENDED_GUEST_REQUEST {
-                                        info!("Fetching extended guest request is not supported");
-                                        // We don't support extended guest request, so we just write empty data.
-                                        // This matches the behavior of KVM in Linux 6.11.
-
                                         // Read RAX & RBX from the GHCB.
                                         let mut data = [0; 8];
                                         self.gpa_read(ghcb_gpa + GHCB_RAX_OFFSET, &mut data)?;
@@ -1084,9 +1087,32 @@ impl cpu::Vcpu for MshvVcpu {
                                         self.gpa_read(ghcb_gpa + GHCB_RBX_OFFSET, &mut data)?;
                                         let data_npages = u64::from_le_bytes(data);
 
-                                        if data_npages > 0 {
-                                            // The certificates are terminated by 24 zero bytes.
-                                            self.gpa_write(data_gpa, &[0; 24])?;
+                                        let certs = self.snp_certificates.read().unwrap();
+                                        let certs_npages =
+                                            (certs.len() as u64).div_ceil(1 << PAGE_SHIFT);
+                                        if certs.is_empty() {
+                                            // Without certificates, only the terminator of the
+                                            // table is written. This matches the behavior of KVM
+                                            // in Linux 6.11.
+                                            if data_npages > 0 {
+                                                // The certificates are terminated by 24 zero bytes.
+                                                self.gpa_write(data_gpa, &[0; 24])?;
+                                            }
+                                        } else if data_npages < certs_npages {
+                                            // See GHCB specification, Section 4.1.8.2: the
+                                            // guest is told how many pages the certificates
+                                            // need and the request isn't issued.
+                                            self.gpa_write(
+                                                ghcb_gpa + GHCB_RBX_OFFSET,
+                                                &certs_npages.to_le_bytes(),
+                                            )?;
+                                            self.gpa_write(
+                                                ghcb_gpa + GHCB_SW_EXITINFO2_OFFSET,
+                                                &SNP_GUEST_VMM_ERR_INVALID_LEN.to_le_bytes(),
+                                            )?;
+                                            return Ok(cpu::VmExit::Ignore);
+                                        } else {
+                                            self.gpa_write(data_gpa, &certs)?;
                                         }
                                     }
 
@@ -1167,7 +1193,7 @@ impl cpu::Vcpu for MshvVcpu {
     }
 
     #[cfg(target_arch = "aarch64")]
-    fn setup_regs(&self, cpu_id: u8, boot_ip: u64, fdt_start: u64) -> cpu::Result<()> {
+    fn setup_regs(&self, cpu_id: u32, boot_ip: u64, fdt_start: u64) -> cpu::Result<()> {
         unimplemented!()
     }
 
@@ -1568,6 +1594,8 @@ pub struct MshvVm {
     dirty_log_slots: Arc<RwLock<HashMap<u64, MshvDirtyLogSlot>>>,
     #[cfg(feature = "sev_snp")]
     sev_snp_enabled: bool,
+    #[cfg(feature = "sev_snp")]
+    snp_certificates: Arc<RwLock<Vec<u8>>>,
 }
 
 impl MshvVm {
@@ -1582,6 +1610,53 @@ impl MshvVm {
             .map_err(|e| vm::HypervisorVmError::CreateDevice(e.into()))?;
         Ok(VfioDeviceFd::new_from_mshv(device_fd))
     }
+
+    ///
+    /// Changes the host access to the pages of [gpa, gpa + size), acquiring
+    /// them from the guest or releasing them to it.
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn modify_gpa_host_access(
+        &self,
+        gpa: u64,
+        size: u64,
+        host_access: u32,
+        acquire: u32,
+    ) -> vm::Result<()> {
+        if size == 0 {
+            return Ok(());
+        }
+
+        let start_gpfn: u64 = gpa >> PAGE_SHIFT;
+        let end_gpfn: u64 = (gpa + size - 1) >> PAGE_SHIFT;
+
+        let gpas: Vec<u64> = (start_gpfn..=end_gpfn).map(|x| x << PAGE_SHIFT).collect();
+
+        // Hot-added memory can span millions of pages, split the list to
+        // keep each request to a reasonable size.
+        for chunk in gpas.chunks(MAX_MODIFY_GPA_HOST_ACCESS_PAGES) {
+            let mut gpa_list =
+                vec_with_array_field::<mshv_modify_gpa_host_access, u64>(chunk.len());
+            gpa_list[0].gpa_list_size = chunk.len() as u64;
+            gpa_list[0].host_access = host_access;
+            gpa_list[0].acquire = acquire;
+            gpa_list[0].flags = 0;
+
+            // SAFETY: gpa_list initialized with chunk.len() and now it is being turned into
+            // gpas_slice with chunk.len() again. It is guaranteed to be large enough to hold
+            // everything from chunk.
+            unsafe {
+                let gpas_slice: &mut [u64] = gpa_list[0].gpa_list.as_mut_slice(chunk.len());
+                gpas_slice.copy_from_slice(chunk);
+            }
+
+            self.fd
+                .modify_gpa_host_access(&gpa_list[0])
+                .map_err(|e| vm::HypervisorVmError::ModifyGpaHostAccess(e.into()))?;
+        }
+
+        Ok(())
+    }
 }
 
 ///
@@ -1652,9 +1727,10 @@ impl vm::Vm for MshvVm {
     ///
     fn create_vcpu(
         &self,
-        id: u8,
+        id: u32,
         vm_ops: Option<Arc<dyn VmOps>>,
     ) -> vm::Result<Arc<dyn cpu::Vcpu>> {
+        let id = u8::try_from(id).map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
         let vcpu_fd = self
             .fd
             .create_vcpu(id)
@@ -1668,6 +1744,8 @@ impl vm::Vm for MshvVm {
             msrs: self.msrs.clone(),
             vm_ops,
             vm_fd: self.fd.clone(),
+            #[cfg(feature = "sev_snp")]
+            snp_certificates: self.snp_certificates.clone(),
         };
         Ok(Arc::new(vcpu))
     }
@@ -1677,6 +1755,14 @@ impl vm::Vm for MshvVm {
         Ok(())
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> vm::Result<()> {
+        // The vCPU creation ioctl is limited to 8-bit APIC IDs.
+        Err(vm::HypervisorVmError::EnableX2ApicApi(anyhow!(
+            "32-bit APIC IDs are not supported"
+        )))
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, _file: File) -> vm::Result<()> {
         Ok(())
@@ -1918,7 +2004,19 @@ impl vm::Vm for MshvVm {
 
     /// Initialize the SEV-SNP VM
     #[cfg(feature = "sev_snp")]
-    fn sev_snp_init(&self) -> vm::Result<()> {
+    fn sev_snp_init(&self, policy: Option<u64>) -> vm::Result<()> {
+        // The partition was created with the default policy, which can be
+        // replaced as long as the partition isn't secure yet.
+        if let Some(policy) = policy {
+            debug!("Setting the partition isolation policy as: 0x{:x}", policy);
+            self.fd
+                .set_partition_property(
+                    hv_partition_property_code_HV_PARTITION_PROPERTY_ISOLATION_POLICY,
+                    policy,
+                )
+                .map_err(|e| vm::HypervisorVmError::InitializeSevSnp(e.into()))?;
+        }
+
         self.fd
             .set_partition_property(
                 hv_partition_property_code_HV_PARTITION_PROPERTY_ISOLATION_STATE,
@@ -1968,6 +2066,7 @@ impl vm::Vm for MshvVm {
         &self,
         snp_id_block: IGVM_VHS_SNP_ID_BLOCK,
         host_data: [u8; 32],
+        policy: Option<u64>,
         id_block_enabled: u8,
     ) -> vm::Result<()> {
         let mut auth_info = hv_snp_id_auth_info {
@@ -1987,6 +2086,20 @@ impl vm::Vm for MshvVm {
             .copy_from_slice(snp_id_block.id_public_key.qx.as_ref());
         auth_info.id_key[ECDSA_SIG_Y_COMPONENT_START..ECDSA_SIG_Y_COMPONENT_END]
             .copy_from_slice(snp_id_block.id_public_key.qy.as_ref());
+        // The ID key is itself signed by the author key when enabled.
+        if snp_id_block.author_key_enabled != 0 {
+            auth_info.id_key_signature[..SIG_R_COMPONENT_SIZE_IN_BYTES]
+                .copy_from_slice(snp_id_block.author_key_signature.r_comp.as_ref());
+            auth_info.id_key_signature
+                [SIG_R_COMPONENT_SIZE_IN_BYTES..SIG_R_AND_S_COMPONENT_SIZE_IN_BYTES]
+                .copy_from_slice(snp_id_block.author_key_signature.s_comp.as_ref());
+            auth_info.author_key[..ECDSA_CURVE_ID_SIZE_IN_BYTES]
+                .copy_from_slice(snp_id_block.author_public_key.curve.to_le_bytes().as_ref());
+            auth_info.author_key[ECDSA_SIG_X_COMPONENT_START..ECDSA_SIG_X_COMPONENT_END]
+                .copy_from_slice(snp_id_block.author_public_key.qx.as_ref());
+            auth_info.author_key[ECDSA_SIG_Y_COMPONENT_START..ECDSA_SIG_Y_COMPONENT_END]
+                .copy_from_slice(snp_id_block.author_public_key.qy.as_ref());
+        }
 
         let data = mshv_complete_isolated_import {
             import_data: hv_partition_complete_isolated_import_data {
@@ -1997,12 +2110,14 @@ impl vm::Vm for MshvVm {
                         image_id: snp_id_block.image_id,
                         version: snp_id_block.version,
                         guest_svn: snp_id_block.guest_svn,
-                        policy: get_default_snp_guest_policy(),
+                        policy: policy.map_or_else(get_default_snp_guest_policy, |p| {
+                            hv_snp_guest_policy { as_uint64: p }
+                        }),
                     },
                     id_auth_info: auth_info,
                     host_data,
                     id_block_enabled,
-                    author_key_enabled: 0,
+                    author_key_enabled: snp_id_block.author_key_enabled,
                 },
             },
         };
@@ -2021,6 +2136,28 @@ impl vm::Vm for MshvVm {
         unimplemented!()
     }
 
+    #[cfg(target_arch = "aarch64")]
+    fn get_counter(&self) -> vm::Result<Option<u64>> {
+        Err(vm::HypervisorVmError::GetClock(anyhow!(
+            "Guest counter is not supported on MSHV"
+        )))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn set_counter(&self, _counter: u64) -> vm::Result<()> {
+        Err(vm::HypervisorVmError::SetClock(anyhow!(
+            "Guest counter is not supported on MSHV"
+        )))
+    }
+
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_set_certificates(&self, certs: Vec<u8>) -> vm::Result<()> {
+        // The extended guest requests are answered by the VMM, which writes
+        // the certificates to the guest pages.
+        *self.snp_certificates.write().unwrap() = certs;
+        Ok(())
+    }
+
     /// Pause the VM
     fn pause(&self) -> vm::Result<()> {
         // Freeze the partition
@@ -2059,31 +2196,20 @@ impl vm::Vm for MshvVm {
             return Ok(());
         }
 
-        let start_gpfn: u64 = gpa >> PAGE_SHIFT;
-        let end_gpfn: u64 = (gpa + size as u64 - 1) >> PAGE_SHIFT;
-
-        let gpas: Vec<u64> = (start_gpfn..=end_gpfn).map(|x| x << PAGE_SHIFT).collect();
-
-        if !gpas.is_empty() {
-            let mut gpa_list = vec_with_array_field::<mshv_modify_gpa_host_access, u64>(gpas.len());
-            gpa_list[0].gpa_list_size = gpas.len() as u64;
-            gpa_list[0].host_access = HV_MAP_GPA_READABLE | HV_MAP_GPA_WRITABLE;
-            gpa_list[0].acquire = 1;
-            gpa_list[0].flags = 0;
-
-            // SAFETY: gpa_list initialized with gpas.len() and now it is being turned into
-            // gpas_slice with gpas.len() again. It is guaranteed to be large enough to hold
-            // everything from gpas.
-            unsafe {
-                let gpas_slice: &mut [u64] = gpa_list[0].gpa_list.as_mut_slice(gpas.len());
-                gpas_slice.copy_from_slice(gpas.as_slice());
-            }
+        self.modify_gpa_host_access(
+            gpa,
+            size as u64,
+            HV_MAP_GPA_READABLE | HV_MAP_GPA_WRITABLE,
+            1,
+        )
+    }
 
-            self.fd
-                .modify_gpa_host_access(&gpa_list[0])
-                .map_err(|e| vm::HypervisorVmError::ModifyGpaHostAccess(e.into()))?;
+    #[cfg(feature = "sev_snp")]
+    fn release_page_access(&self, gpa: u64, size: u64) -> vm::Result<()> {
+        if !self.sev_snp_enabled {
+            return Ok(());
         }
 
-        Ok(())
+        self.modify_gpa_host_access(gpa, size, 0, 0)
     }
 }
@@ -23,3 +23,7 @@ pub const GHCB_RAX_OFFSET: u64 = 0x01F8;
 pub const GHCB_RBX_OFFSET: u64 = 0x0318;
 pub const GHCB_SW_EXITINFO1_OFFSET: u64 = 0x398;
 pub const GHCB_SW_EXITINFO2_OFFSET: u64 = 0x3A0;
+
+// GHCB spec Sect. 4.1.8: the data pages of an extended guest request are too
+// small for the certificates.
+pub const SNP_GUEST_VMM_ERR_INVALID_LEN: u64 = 1 << 32;
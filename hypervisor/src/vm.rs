@@ -123,6 +123,11 @@ pub enum HypervisorVmError {
     #[error("Failed to enable split Irq: {0}")]
     EnableSplitIrq(#[source] anyhow::Error),
     ///
+    /// Enable x2APIC API error
+    ///
+    #[error("Failed to enable x2APIC API: {0}")]
+    EnableX2ApicApi(#[source] anyhow::Error),
+    ///
     /// Enable SGX attribute error
     ///
     #[error("Failed to enable SGX attribute: {0}")]
@@ -138,6 +143,16 @@ pub enum HypervisorVmError {
     #[error("Failed to set clock: {0}")]
     SetClock(#[source] anyhow::Error),
     ///
+    /// Set halt polling error
+    ///
+    #[error("Failed to set halt polling time: {0}")]
+    SetHaltPollNs(#[source] anyhow::Error),
+    ///
+    /// Disable HLT exits error
+    ///
+    #[error("Failed to disable HLT exits: {0}")]
+    DisableHltExits(#[source] anyhow::Error),
+    ///
     /// Create passthrough device
     ///
     #[error("Failed to create passthrough device: {0}")]
@@ -203,6 +218,19 @@ pub enum HypervisorVmError {
     ///
     #[error("Failed to initialize SEV-SNP: {0}")]
     InitializeSevSnp(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// SEV-ES is not supported by the hypervisor
+    ///
+    #[error("SEV-ES is not supported")]
+    SevEsNotSupported,
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// The hypervisor can't provide the certificates of the extended guest
+    /// requests
+    ///
+    #[error("Setting the SEV-SNP certificates is not supported")]
+    SevSnpCertificatesNotSupported,
 
     #[cfg(feature = "tdx")]
     ///
@@ -222,6 +250,12 @@ pub enum HypervisorVmError {
     ///
     #[error("Failed to initialize memory region TDX: {0}")]
     InitMemRegionTdx(#[source] std::io::Error),
+    #[cfg(feature = "tdx")]
+    ///
+    /// TD live migration is not supported by the hypervisor
+    ///
+    #[error("TD live migration is not supported")]
+    TdxMigrationNotSupported,
     ///
     /// Create Vgic error
     ///
@@ -252,6 +286,25 @@ pub enum HypervisorVmError {
 ///
 pub type Result<T> = std::result::Result<T, HypervisorVmError>;
 
+/// Migration TD (MigTD) bound to a TD, negotiating the migration session key
+/// (MSK) with its peer on the other end of a live migration.
+#[cfg(feature = "tdx")]
+#[derive(Copy, Clone, Debug)]
+pub struct TdxMigrationTd {
+    /// PID of the VMM running the MigTD
+    pub pid: u32,
+}
+
+/// Migration session of a TD, handed over to its MigTD to seed the MSK.
+#[cfg(feature = "tdx")]
+#[derive(Copy, Clone, Debug)]
+pub struct TdxMigrationSession {
+    /// Whether the TD is the source of the migration
+    pub source: bool,
+    /// vsock port the MigTDs reach each other on
+    pub vsock_port: u32,
+}
+
 /// Configuration data for legacy interrupts.
 ///
 /// On x86 platforms, legacy interrupts means those interrupts routed through PICs or IOAPICs.
@@ -304,7 +357,7 @@ pub trait Vm: Send + Sync + Any {
     /// Unregister an event that will, when signaled, trigger the `gsi` IRQ.
     fn unregister_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()>;
     /// Creates a new KVM vCPU file descriptor and maps the memory corresponding
-    fn create_vcpu(&self, id: u8, vm_ops: Option<Arc<dyn VmOps>>) -> Result<Arc<dyn Vcpu>>;
+    fn create_vcpu(&self, id: u32, vm_ops: Option<Arc<dyn VmOps>>) -> Result<Arc<dyn Vcpu>>;
     #[cfg(target_arch = "aarch64")]
     fn create_vgic(&self, config: VgicConfig) -> Result<Arc<Mutex<dyn Vgic>>>;
 
@@ -338,9 +391,19 @@ pub trait Vm: Send + Sync + Any {
     /// Returns the preferred CPU target type which can be emulated by KVM on underlying host.
     #[cfg(target_arch = "aarch64")]
     fn get_preferred_target(&self, kvi: &mut VcpuInit) -> Result<()>;
+    /// Retrieve the guest counter, if the hypervisor lets it be controlled
+    /// for the whole VM.
+    #[cfg(target_arch = "aarch64")]
+    fn get_counter(&self) -> Result<Option<u64>>;
+    /// Set the guest counter for all vCPUs, before any of them has run.
+    #[cfg(target_arch = "aarch64")]
+    fn set_counter(&self, counter: u64) -> Result<()>;
     /// Enable split Irq capability
     #[cfg(target_arch = "x86_64")]
     fn enable_split_irq(&self) -> Result<()>;
+    /// Enable 32-bit APIC IDs, for vCPUs beyond the xAPIC range
+    #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> Result<()>;
     #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, file: File) -> Result<()>;
     /// Retrieve guest clock.
@@ -349,6 +412,20 @@ pub trait Vm: Send + Sync + Any {
     /// Set guest clock.
     #[cfg(target_arch = "x86_64")]
     fn set_clock(&self, data: &ClockData) -> Result<()>;
+    /// Set how long a halted vCPU polls for a wakeup before yielding the host CPU.
+    fn set_halt_poll_ns(&self, _ns: u32) -> Result<()> {
+        Err(HypervisorVmError::SetHaltPollNs(anyhow::anyhow!(
+            "Not supported by the hypervisor"
+        )))
+    }
+    /// Keep the vCPUs in the guest when they execute HLT, must be called before
+    /// any vCPU is created.
+    #[cfg(target_arch = "x86_64")]
+    fn disable_hlt_exits(&self) -> Result<()> {
+        Err(HypervisorVmError::DisableHltExits(anyhow::anyhow!(
+            "Not supported by the hypervisor"
+        )))
+    }
     /// Create a device that is used for passthrough
     fn create_passthrough_device(&self) -> Result<vfio_ioctls::VfioDeviceFd>;
     /// Start logging dirty pages
@@ -358,8 +435,9 @@ pub trait Vm: Send + Sync + Any {
     /// Get dirty pages bitmap
     fn get_dirty_log(&self, slot: u32, base_gpa: u64, memory_size: u64) -> Result<Vec<u64>>;
     #[cfg(feature = "sev_snp")]
-    /// Initialize SEV-SNP on this VM
-    fn sev_snp_init(&self) -> Result<()> {
+    /// Initialize SEV-SNP on this VM, launching the guest with the given
+    /// policy or with the default one of the hypervisor
+    fn sev_snp_init(&self, _policy: Option<u64>) -> Result<()> {
         unimplemented!()
     }
     #[cfg(feature = "tdx")]
@@ -383,6 +461,17 @@ pub trait Vm: Send + Sync + Any {
     ) -> Result<()> {
         unimplemented!()
     }
+    #[cfg(feature = "tdx")]
+    /// Bind a migration TD to this TD
+    fn tdx_bind_migration_td(&self, _migtd: &TdxMigrationTd) -> Result<()> {
+        Err(HypervisorVmError::TdxMigrationNotSupported)
+    }
+    #[cfg(feature = "tdx")]
+    /// Start a migration session, letting the migration TD seed the
+    /// migration session key of this TD
+    fn tdx_seed_migration_key(&self, _session: &TdxMigrationSession) -> Result<()> {
+        Err(HypervisorVmError::TdxMigrationNotSupported)
+    }
     /// Downcast to the underlying hypervisor VM type
     fn as_any(&self) -> &dyn Any;
     /// Import the isolated pages
@@ -402,10 +491,28 @@ pub trait Vm: Send + Sync + Any {
         &self,
         _snp_id_block: IGVM_VHS_SNP_ID_BLOCK,
         _host_data: [u8; 32],
+        _policy: Option<u64>,
         _id_block_enabled: u8,
     ) -> Result<()> {
         unimplemented!()
     }
+    /// Encrypt and measure a range of the memory of a SEV-ES guest
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_launch_update_data(&self, _uaddr: u64, _size: u64) -> Result<()> {
+        Err(HypervisorVmError::SevEsNotSupported)
+    }
+    /// Encrypt the vCPUs state of a SEV-ES guest and finish its launch,
+    /// returning the launch measurement
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_launch_finish(&self) -> Result<Vec<u8>> {
+        Err(HypervisorVmError::SevEsNotSupported)
+    }
+    /// Set the certificate table returned to the guest by the extended
+    /// guest requests, along with its attestation reports
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_set_certificates(&self, _certs: Vec<u8>) -> Result<()> {
+        Err(HypervisorVmError::SevSnpCertificatesNotSupported)
+    }
     /// Pause the VM
     fn pause(&self) -> Result<()> {
         Ok(())
@@ -420,6 +527,13 @@ pub trait Vm: Send + Sync + Any {
     fn gain_page_access(&self, _gpa: u64, _size: u32) -> Result<()> {
         Ok(())
     }
+
+    /// Give up host access to a range of guest memory, leaving the guest to
+    /// validate it before use
+    #[cfg(feature = "sev_snp")]
+    fn release_page_access(&self, _gpa: u64, _size: u64) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub trait VmOps: Send + Sync {
@@ -431,4 +545,10 @@ pub trait VmOps: Send + Sync {
     fn pio_read(&self, port: u64, data: &mut [u8]) -> Result<()>;
     #[cfg(target_arch = "x86_64")]
     fn pio_write(&self, port: u64, data: &[u8]) -> Result<()>;
+    /// The guest converted [gpa, gpa + size) to private or shared memory,
+    /// the backing of the range in its previous state is no longer used.
+    #[cfg(feature = "sev_snp")]
+    fn memory_converted(&self, _gpa: u64, _size: u64, _private: bool) -> Result<()> {
+        Ok(())
+    }
 }
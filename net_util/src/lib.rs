@@ -44,6 +44,11 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Feature of a virtio-net device acting as the standby of a primary device
+/// with the same MAC address, e.g. a VF, which the guest fails over to while
+/// the primary device is gone.
+pub const VIRTIO_NET_F_STANDBY: u32 = 62;
+
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
 pub struct VirtioNetConfig {
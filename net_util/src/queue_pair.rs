@@ -53,6 +53,7 @@ impl TxVirtio {
                 queue.go_to_previous_position();
                 break;
             }
+            tracer::usdt!(virtio_net_tx_pop, desc_chain.head_index());
 
             let mut next_desc = desc_chain.next();
 
@@ -130,6 +131,7 @@ impl TxVirtio {
             queue
                 .add_used(desc_chain.memory(), desc_chain.head_index(), len)
                 .map_err(NetQueuePairError::QueueAddUsed)?;
+            tracer::usdt!(virtio_net_tx_push, desc_chain.head_index(), len);
 
             if !queue
                 .enable_notification(mem)
@@ -182,6 +184,7 @@ impl RxVirtio {
                 queue.go_to_previous_position();
                 break;
             }
+            tracer::usdt!(virtio_net_rx_pop, desc_chain.head_index());
 
             let desc = desc_chain
                 .next()
@@ -279,6 +282,7 @@ impl RxVirtio {
             queue
                 .add_used(desc_chain.memory(), desc_chain.head_index(), len)
                 .map_err(NetQueuePairError::QueueAddUsed)?;
+            tracer::usdt!(virtio_net_rx_push, desc_chain.head_index(), len);
 
             if !queue
                 .enable_notification(mem)
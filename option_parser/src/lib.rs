@@ -5,6 +5,7 @@
 
 use std::collections::HashMap;
 use std::fmt;
+use std::net::SocketAddr;
 use std::num::ParseIntError;
 use std::str::FromStr;
 
@@ -303,6 +304,14 @@ impl TupleValue for Vec<usize> {
     }
 }
 
+impl TupleValue for SocketAddr {
+    fn parse_value(input: &str) -> Result<Self, TupleError> {
+        input
+            .parse::<SocketAddr>()
+            .map_err(|_| TupleError::InvalidValue(input.to_owned()))
+    }
+}
+
 pub struct Tuple<S, T>(pub Vec<(S, T)>);
 
 pub enum TupleError {
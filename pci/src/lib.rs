@@ -14,6 +14,7 @@ mod device;
 mod msi;
 mod msix;
 mod vfio;
+mod vfio_quirks;
 mod vfio_user;
 
 pub use self::bus::{PciBus, PciConfigIo, PciConfigMmio, PciRoot, PciRootError};
@@ -28,7 +29,14 @@ pub use self::device::{
 };
 pub use self::msi::{msi_num_enabled_vectors, MsiCap, MsiConfig};
 pub use self::msix::{MsixCap, MsixConfig, MsixTableEntry, MSIX_CONFIG_ID, MSIX_TABLE_ENTRY_SIZE};
-pub use self::vfio::{MmioRegion, VfioDmaMapping, VfioPciDevice, VfioPciError};
+pub use self::vfio::{
+    vfio_device_ids, vfio_dirty_log, vfio_dirty_log_enable, vfio_select_reset_method, MmioRegion,
+    VfioDmaMapping, VfioPciDevice, VfioPciError, VFIO_DIRTY_PAGE_SIZE,
+};
+pub use self::vfio_quirks::{
+    ParseVfioResetMethodError, VfioBarQuirk, VfioConfigQuirk, VfioDeviceQuirk, VfioQuirks,
+    VfioQuirksError, VfioResetMethod,
+};
 pub use self::vfio_user::{VfioUserDmaMapping, VfioUserPciDevice, VfioUserPciDeviceError};
 use serde::de::Visitor;
 use std::fmt::{self, Display};
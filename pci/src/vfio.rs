@@ -9,7 +9,8 @@ use crate::{
     msi_num_enabled_vectors, BarReprogrammingParams, MsiCap, MsiConfig, MsixCap, MsixConfig,
     PciBarConfiguration, PciBarPrefetchable, PciBarRegionType, PciBdf, PciCapabilityId,
     PciClassCode, PciConfiguration, PciDevice, PciDeviceError, PciExpressCapabilityId,
-    PciHeaderType, PciSubclass, MSIX_CONFIG_ID, MSIX_TABLE_ENTRY_SIZE, PCI_CONFIGURATION_ID,
+    PciHeaderType, PciSubclass, VfioConfigQuirk, VfioDeviceQuirk, VfioResetMethod, MSIX_CONFIG_ID,
+    MSIX_TABLE_ENTRY_SIZE, PCI_CONFIGURATION_ID,
 };
 use anyhow::anyhow;
 use byteorder::{ByteOrder, LittleEndian};
@@ -20,6 +21,7 @@ use std::any::Any;
 use std::collections::{BTreeMap, HashMap};
 use std::io;
 use std::os::unix::io::AsRawFd;
+use std::path::Path;
 use std::ptr::null_mut;
 use std::sync::{Arc, Barrier, Mutex};
 use thiserror::Error;
@@ -42,6 +44,146 @@ use vmm_sys_util::eventfd::EventFd;
 
 pub(crate) const VFIO_COMMON_ID: &str = "vfio_common";
 
+// Power Management Control/Status register, relative to the PM capability.
+const PCI_PM_CTRL_OFFSET: u64 = 4;
+const PCI_PM_CTRL_STATE_MASK: u16 = 0x3;
+const PCI_PM_CTRL_NO_SOFT_RESET: u16 = 0x8;
+const PCI_D0: u16 = 0;
+const PCI_D3HOT: u16 = 3;
+
+// See include/uapi/linux/vfio.h in the kernel code.
+const VFIO_DEVICE_FEATURE: u64 = 0x3b75;
+const VFIO_DEVICE_FEATURE_SET: u32 = 1 << 17;
+const VFIO_DEVICE_FEATURE_LOW_POWER_ENTRY: u32 = 3;
+const VFIO_DEVICE_FEATURE_LOW_POWER_EXIT: u32 = 5;
+
+#[repr(C)]
+struct VfioDeviceFeature {
+    argsz: u32,
+    flags: u32,
+}
+
+// See include/uapi/linux/vfio.h in the kernel code.
+const VFIO_IOMMU_DIRTY_PAGES: u64 = 0x3b75;
+const VFIO_IOMMU_DIRTY_PAGES_FLAG_START: u32 = 1 << 0;
+const VFIO_IOMMU_DIRTY_PAGES_FLAG_STOP: u32 = 1 << 1;
+const VFIO_IOMMU_DIRTY_PAGES_FLAG_GET_BITMAP: u32 = 1 << 2;
+
+/// Granularity of the dirty page tracking of the VFIO containers.
+pub const VFIO_DIRTY_PAGE_SIZE: u64 = 4096;
+
+#[repr(C)]
+struct VfioIommuDirtyBitmap {
+    argsz: u32,
+    flags: u32,
+}
+
+// struct vfio_iommu_type1_dirty_bitmap followed by the
+// struct vfio_iommu_type1_dirty_bitmap_get.
+#[repr(C)]
+struct VfioIommuDirtyBitmapGet {
+    argsz: u32,
+    flags: u32,
+    iova: u64,
+    size: u64,
+    pgsize: u64,
+    bitmap_size: u64,
+    bitmap: *mut u64,
+}
+
+/// Starts or stops tracking the memory written by the DMA of the devices
+/// attached to `container`.
+pub fn vfio_dirty_log_enable(container: &VfioContainer, enable: bool) -> io::Result<()> {
+    let dirty_bitmap = VfioIommuDirtyBitmap {
+        argsz: std::mem::size_of::<VfioIommuDirtyBitmap>() as u32,
+        flags: if enable {
+            VFIO_IOMMU_DIRTY_PAGES_FLAG_START
+        } else {
+            VFIO_IOMMU_DIRTY_PAGES_FLAG_STOP
+        },
+    };
+
+    // SAFETY: FFI call with a valid VFIO container fd and a properly
+    // initialized vfio_iommu_type1_dirty_bitmap structure. The return value
+    // is checked.
+    let ret = unsafe {
+        libc::ioctl(
+            container.as_raw_fd(),
+            VFIO_IOMMU_DIRTY_PAGES as _,
+            &dirty_bitmap as *const VfioIommuDirtyBitmap,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Returns the bitmap of the pages written by DMA since the previous call,
+/// one bit per `VFIO_DIRTY_PAGE_SIZE` page, within `[iova, iova + size)`,
+/// which must not split any DMA mapping of `container`.
+pub fn vfio_dirty_log(container: &VfioContainer, iova: u64, size: u64) -> io::Result<Vec<u64>> {
+    let pages = size.div_ceil(VFIO_DIRTY_PAGE_SIZE);
+    let mut bitmap = vec![0u64; pages.div_ceil(64) as usize];
+
+    let dirty_bitmap = VfioIommuDirtyBitmapGet {
+        argsz: std::mem::size_of::<VfioIommuDirtyBitmapGet>() as u32,
+        flags: VFIO_IOMMU_DIRTY_PAGES_FLAG_GET_BITMAP,
+        iova,
+        size,
+        pgsize: VFIO_DIRTY_PAGE_SIZE,
+        bitmap_size: (bitmap.len() * std::mem::size_of::<u64>()) as u64,
+        bitmap: bitmap.as_mut_ptr(),
+    };
+
+    // SAFETY: FFI call with a valid VFIO container fd and a properly
+    // initialized vfio_iommu_type1_dirty_bitmap structure, whose bitmap is
+    // large enough for the range. The return value is checked.
+    let ret = unsafe {
+        libc::ioctl(
+            container.as_raw_fd(),
+            VFIO_IOMMU_DIRTY_PAGES as _,
+            &dirty_bitmap as *const VfioIommuDirtyBitmapGet,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(bitmap)
+}
+
+/// Returns the vendor and device ids of the PCI device at `sysfs_path`.
+pub fn vfio_device_ids(sysfs_path: &Path) -> io::Result<(u16, u16)> {
+    let read_id = |name: &str| -> io::Result<u16> {
+        let id = std::fs::read_to_string(sysfs_path.join(name))?;
+        u16::from_str_radix(id.trim().trim_start_matches("0x"), 16)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+
+    Ok((read_id("vendor")?, read_id("device")?))
+}
+
+/// Selects the method the host kernel resets the PCI device at `sysfs_path`
+/// with, through its `reset_method` attribute. It applies to the resets of
+/// the device as it is opened, reset and released through VFIO, and stays in
+/// place once the device is released. The kernel refuses the methods the
+/// device doesn't support, such as a bus reset of a device sharing its bus.
+pub fn vfio_select_reset_method(sysfs_path: &Path, method: VfioResetMethod) -> io::Result<()> {
+    let method = match method {
+        // The method selected on the host is kept.
+        VfioResetMethod::Device => return Ok(()),
+        VfioResetMethod::Flr => "flr",
+        VfioResetMethod::Bus => "bus",
+        VfioResetMethod::Acpi => "acpi",
+        // An empty list of methods disables the resets.
+        VfioResetMethod::None => "",
+    };
+
+    std::fs::write(sysfs_path.join("reset_method"), format!("{method}\n"))
+}
+
 #[derive(Debug, Error)]
 pub enum VfioPciError {
     #[error("Failed to create user memory region: {0}")]
@@ -254,6 +396,22 @@ impl Interrupt {
 
         false
     }
+
+    fn msi_in_use(&self) -> bool {
+        if let Some(msi) = &self.msi {
+            return msi.cfg.enabled();
+        }
+
+        false
+    }
+
+    fn msix_in_use(&self) -> bool {
+        if let Some(msix) = &self.msix {
+            return msix.bar.enabled();
+        }
+
+        false
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -320,6 +478,8 @@ pub enum VfioError {
     KernelVfio(#[source] vfio_ioctls::VfioError),
     #[error("VFIO user error: {0}")]
     VfioUser(#[source] vfio_user::Error),
+    #[error("Failed to set the device low power state: {0}")]
+    SetLowPower(#[source] io::Error),
 }
 
 pub(crate) trait Vfio: Send + Sync {
@@ -393,6 +553,11 @@ pub(crate) trait Vfio: Send + Sync {
     fn unmask_irq(&self, _irq_index: u32) -> Result<(), VfioError> {
         unimplemented!()
     }
+
+    // Allow or prevent the host from suspending the device at runtime.
+    fn set_low_power(&self, _enable: bool) -> Result<(), VfioError> {
+        Ok(())
+    }
 }
 
 struct VfioDeviceWrapper {
@@ -435,6 +600,34 @@ impl Vfio for VfioDeviceWrapper {
             .unmask_irq(irq_index)
             .map_err(VfioError::KernelVfio)
     }
+
+    fn set_low_power(&self, enable: bool) -> Result<(), VfioError> {
+        let feature = VfioDeviceFeature {
+            argsz: std::mem::size_of::<VfioDeviceFeature>() as u32,
+            flags: VFIO_DEVICE_FEATURE_SET
+                | if enable {
+                    VFIO_DEVICE_FEATURE_LOW_POWER_ENTRY
+                } else {
+                    VFIO_DEVICE_FEATURE_LOW_POWER_EXIT
+                },
+        };
+
+        // SAFETY: FFI call with a valid VFIO device fd and a properly
+        // initialized vfio_device_feature structure. The return value
+        // is checked.
+        let ret = unsafe {
+            libc::ioctl(
+                self.device.as_raw_fd(),
+                VFIO_DEVICE_FEATURE as _,
+                &feature as *const VfioDeviceFeature,
+            )
+        };
+        if ret < 0 {
+            return Err(VfioError::SetLowPower(io::Error::last_os_error()));
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -442,6 +635,10 @@ struct VfioCommonState {
     intx_state: Option<IntxState>,
     msi_state: Option<MsiState>,
     msix_state: Option<MsixState>,
+    #[serde(default)]
+    pm_cap_offset: Option<u64>,
+    #[serde(default)]
+    power_state: u16,
 }
 
 pub(crate) struct ConfigPatch {
@@ -458,6 +655,8 @@ pub(crate) struct VfioCommon {
     pub(crate) vfio_wrapper: Arc<dyn Vfio>,
     pub(crate) patches: HashMap<usize, ConfigPatch>,
     x_nv_gpudirect_clique: Option<u8>,
+    pm_cap_offset: Option<u64>,
+    power_state: u16,
 }
 
 impl VfioCommon {
@@ -505,6 +704,8 @@ impl VfioCommon {
             vfio_wrapper,
             patches: HashMap::new(),
             x_nv_gpudirect_clique,
+            pm_cap_offset: None,
+            power_state: PCI_D0,
         };
 
         let state: Option<VfioCommonState> = snapshot
@@ -932,7 +1133,10 @@ impl VfioCommon {
                     }
                 }
                 PciCapabilityId::PciExpress => pci_express_cap_found = true,
-                PciCapabilityId::PowerManagement => power_management_cap_found = true,
+                PciCapabilityId::PowerManagement => {
+                    power_management_cap_found = true;
+                    self.pm_cap_offset = Some(u64::from(cap_iter));
+                }
                 _ => {}
             };
 
@@ -953,6 +1157,19 @@ impl VfioCommon {
         }
     }
 
+    // The overrides of the quirks take precedence over the built-in ones.
+    pub(crate) fn apply_config_quirks(&mut self, quirks: &[VfioConfigQuirk]) {
+        for quirk in quirks {
+            self.patches.insert(
+                quirk.offset as usize / 4,
+                ConfigPatch {
+                    mask: quirk.mask,
+                    patch: quirk.value & quirk.mask,
+                },
+            );
+        }
+    }
+
     fn add_nv_gpudirect_clique_cap(&mut self, cap_iter: u8, clique_id: u8) {
         // Turing, Ampere, Hopper, and Lovelace GPUs have dedicated space
         // at 0xD4 for this capability.
@@ -1278,9 +1495,75 @@ impl VfioCommon {
         // to the device region to update the MSI Enable bit.
         self.vfio_wrapper.write_config((reg + offset) as u32, data);
 
+        // The power state change is applied by the device itself, we only
+        // need to follow up on the transition.
+        if self.pm_state_accessed(reg + offset, data.len()) {
+            self.update_power_state();
+        }
+
         None
     }
 
+    fn pm_state_accessed(&self, offset: u64, len: usize) -> bool {
+        if let Some(pm_cap_offset) = self.pm_cap_offset {
+            let pmcsr = pm_cap_offset + PCI_PM_CTRL_OFFSET;
+            return offset <= pmcsr && pmcsr < offset + len as u64;
+        }
+
+        false
+    }
+
+    fn update_power_state(&mut self) {
+        let pmcsr = self
+            .vfio_wrapper
+            .read_config_word((self.pm_cap_offset.unwrap() + PCI_PM_CTRL_OFFSET) as u32);
+        let power_state = pmcsr & PCI_PM_CTRL_STATE_MASK;
+        if power_state == self.power_state {
+            return;
+        }
+
+        info!(
+            "VFIO device power state transition D{} -> D{}",
+            self.power_state, power_state
+        );
+
+        if power_state == PCI_D3HOT {
+            // Let the host suspend the device while the guest isn't using it.
+            if let Err(e) = self.vfio_wrapper.set_low_power(true) {
+                debug!("Could not allow VFIO device runtime suspend: {}", e);
+            }
+        } else if self.power_state == PCI_D3HOT {
+            if let Err(e) = self.vfio_wrapper.set_low_power(false) {
+                debug!("Could not resume VFIO device from runtime suspend: {}", e);
+            }
+
+            // Going from D3hot to D0 resets the internal state of devices
+            // not advertising No_Soft_Reset, interrupts included.
+            if power_state == PCI_D0 && pmcsr & PCI_PM_CTRL_NO_SOFT_RESET == 0 {
+                if let Err(e) = self.restore_interrupts() {
+                    error!("Could not restore interrupts after D3hot: {}", e);
+                }
+            }
+        }
+
+        self.power_state = power_state;
+    }
+
+    fn restore_interrupts(&mut self) -> Result<(), VfioPciError> {
+        if self.interrupt.msix_in_use() {
+            self.disable_msix();
+            self.enable_msix()?;
+        } else if self.interrupt.msi_in_use() {
+            self.disable_msi();
+            self.enable_msi()?;
+        } else if self.interrupt.intx_in_use() {
+            self.disable_intx();
+            self.enable_intx()?;
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn read_config_register(&mut self, reg_idx: usize) -> u32 {
         // When reading the BARs, we trap it and return what comes
         // from our local configuration space. We want the guest to
@@ -1340,6 +1623,8 @@ impl VfioCommon {
             intx_state,
             msi_state,
             msix_state,
+            pm_cap_offset: self.pm_cap_offset,
+            power_state: self.power_state,
         }
     }
 
@@ -1370,6 +1655,14 @@ impl VfioCommon {
             self.initialize_msix(msix.cap, msix.cap_offset, msix.bdf.into(), msix_state);
         }
 
+        self.pm_cap_offset = state.pm_cap_offset;
+        self.power_state = state.power_state;
+        if self.power_state == PCI_D3HOT {
+            if let Err(e) = self.vfio_wrapper.set_low_power(true) {
+                debug!("Could not allow VFIO device runtime suspend: {}", e);
+            }
+        }
+
         Ok(())
     }
 }
@@ -1415,6 +1708,7 @@ pub struct VfioPciDevice {
     common: VfioCommon,
     iommu_attached: bool,
     memory_slot: Arc<dyn Fn() -> u32 + Send + Sync>,
+    quirk: Option<VfioDeviceQuirk>,
 }
 
 impl VfioPciDevice {
@@ -1432,13 +1726,17 @@ impl VfioPciDevice {
         memory_slot: Arc<dyn Fn() -> u32 + Send + Sync>,
         snapshot: Option<Snapshot>,
         x_nv_gpudirect_clique: Option<u8>,
+        quirk: Option<VfioDeviceQuirk>,
+        reset_method: VfioResetMethod,
     ) -> Result<Self, VfioPciError> {
         let device = Arc::new(device);
-        device.reset();
-
         let vfio_wrapper = VfioDeviceWrapper::new(Arc::clone(&device));
 
-        let common = VfioCommon::new(
+        if reset_method != VfioResetMethod::None {
+            device.reset();
+        }
+
+        let mut common = VfioCommon::new(
             msi_interrupt_manager,
             legacy_interrupt_group,
             Arc::new(vfio_wrapper) as Arc<dyn Vfio>,
@@ -1447,6 +1745,9 @@ impl VfioPciDevice {
             vm_migration::snapshot_from_id(snapshot.as_ref(), VFIO_COMMON_ID),
             x_nv_gpudirect_clique,
         )?;
+        if let Some(quirk) = &quirk {
+            common.apply_config_quirks(&quirk.config);
+        }
 
         let vfio_pci_device = VfioPciDevice {
             id,
@@ -1456,6 +1757,7 @@ impl VfioPciDevice {
             common,
             iommu_attached,
             memory_slot,
+            quirk,
         };
 
         Ok(vfio_pci_device)
@@ -1465,6 +1767,10 @@ impl VfioPciDevice {
         self.iommu_attached
     }
 
+    fn trace_config(&self) -> bool {
+        self.quirk.as_ref().is_some_and(|quirk| quirk.trace_config)
+    }
+
     fn generate_sparse_areas(
         caps: &[VfioRegionInfoCap],
         region_index: u32,
@@ -1562,6 +1868,15 @@ impl VfioPciDevice {
 
         for region in self.common.mmio_regions.iter_mut() {
             let region_flags = self.device.get_region_flags(region.index);
+            // The accesses to the BARs the quirks don't let be mapped are
+            // trapped, and forwarded through the VFIO region.
+            if !self
+                .quirk
+                .as_ref()
+                .map_or(true, |quirk| quirk.bar_mmap(region.index))
+            {
+                continue;
+            }
             if region_flags & VFIO_REGION_INFO_FLAG_MMAP != 0 {
                 let mut prot = 0;
                 if region_flags & VFIO_REGION_INFO_FLAG_READ != 0 {
@@ -1823,11 +2138,28 @@ impl PciDevice for VfioPciDevice {
         offset: u64,
         data: &[u8],
     ) -> Option<Arc<Barrier>> {
+        if self.trace_config() {
+            info!(
+                "{}: config write 0x{:03x}: {:02x?}",
+                self.id,
+                reg_idx as u64 * 4 + offset,
+                data
+            );
+        }
         self.common.write_config_register(reg_idx, offset, data)
     }
 
     fn read_config_register(&mut self, reg_idx: usize) -> u32 {
-        self.common.read_config_register(reg_idx)
+        let value = self.common.read_config_register(reg_idx);
+        if self.trace_config() {
+            info!(
+                "{}: config read 0x{:03x}: 0x{:08x}",
+                self.id,
+                reg_idx * 4,
+                value
+            );
+        }
+        value
     }
 
     fn detect_bar_reprogramming(
@@ -1999,3 +2331,139 @@ impl<M: GuestAddressSpace + Sync + Send> ExternalDmaMapping for VfioDmaMapping<M
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Power Management capability right after the standard header
+    const PM_CAP_OFFSET: u32 = 0x40;
+    const PMCSR_REG_IDX: usize = (PM_CAP_OFFSET as usize + PCI_PM_CTRL_OFFSET as usize) / 4;
+
+    struct TestVfio {
+        config: Mutex<[u8; 256]>,
+        low_power: Mutex<Vec<bool>>,
+    }
+
+    impl TestVfio {
+        fn new() -> Self {
+            let mut config = [0u8; 256];
+            config[PCI_CONFIG_CAPABILITY_OFFSET as usize] = PM_CAP_OFFSET as u8;
+            config[PM_CAP_OFFSET as usize] = PciCapabilityId::PowerManagement as u8;
+
+            TestVfio {
+                config: Mutex::new(config),
+                low_power: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Vfio for TestVfio {
+        fn region_read(&self, _index: u32, offset: u64, data: &mut [u8]) {
+            let offset = offset as usize;
+            data.copy_from_slice(&self.config.lock().unwrap()[offset..offset + data.len()]);
+        }
+
+        fn region_write(&self, _index: u32, offset: u64, data: &[u8]) {
+            let offset = offset as usize;
+            self.config.lock().unwrap()[offset..offset + data.len()].copy_from_slice(data);
+        }
+
+        fn get_irq_info(&self, irq_index: u32) -> Option<VfioIrq> {
+            Some(VfioIrq {
+                flags: 0,
+                index: irq_index,
+                count: 0,
+            })
+        }
+
+        fn set_low_power(&self, enable: bool) -> Result<(), VfioError> {
+            self.low_power.lock().unwrap().push(enable);
+            Ok(())
+        }
+    }
+
+    struct TestInterruptManager {}
+
+    impl InterruptManager for TestInterruptManager {
+        type GroupConfig = MsiIrqGroupConfig;
+
+        fn create_group(
+            &self,
+            _config: Self::GroupConfig,
+        ) -> vm_device::interrupt::Result<Arc<dyn InterruptSourceGroup>> {
+            unimplemented!()
+        }
+
+        fn destroy_group(
+            &self,
+            _group: Arc<dyn InterruptSourceGroup>,
+        ) -> vm_device::interrupt::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn vfio_common(vfio: &Arc<TestVfio>, snapshot: Option<Snapshot>) -> VfioCommon {
+        VfioCommon::new(
+            Arc::new(TestInterruptManager {}),
+            None,
+            vfio.clone(),
+            &PciVfioSubclass::VfioSubclass,
+            PciBdf::new(0, 0, 1, 0),
+            snapshot,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_vfio_power_state() {
+        let vfio = Arc::new(TestVfio::new());
+        let mut common = vfio_common(&vfio, None);
+        assert_eq!(common.pm_cap_offset, Some(PM_CAP_OFFSET.into()));
+        assert_eq!(common.power_state, PCI_D0);
+
+        // Entering D3hot lets the host suspend the device
+        common.write_config_register(PMCSR_REG_IDX, 0, &[PCI_D3HOT as u8, 0]);
+        assert_eq!(common.power_state, PCI_D3HOT);
+        assert_eq!(*vfio.low_power.lock().unwrap(), vec![true]);
+
+        // Accesses to other registers don't affect the power state
+        common.write_config_register(PMCSR_REG_IDX + 1, 0, &[0, 0]);
+        assert_eq!(*vfio.low_power.lock().unwrap(), vec![true]);
+
+        // The power state survives a snapshot, and the host is told again
+        // the device can be suspended.
+        let snapshot = common.snapshot().unwrap();
+        let restored_vfio = Arc::new(TestVfio::new());
+        let mut restored = vfio_common(&restored_vfio, Some(snapshot));
+        assert_eq!(restored.pm_cap_offset, Some(PM_CAP_OFFSET.into()));
+        assert_eq!(restored.power_state, PCI_D3HOT);
+        assert_eq!(*restored_vfio.low_power.lock().unwrap(), vec![true]);
+
+        restored.write_config_register(PMCSR_REG_IDX, 0, &[PCI_D0 as u8, 0]);
+        assert_eq!(restored.power_state, PCI_D0);
+        assert_eq!(*restored_vfio.low_power.lock().unwrap(), vec![true, false]);
+    }
+
+    #[test]
+    fn test_vfio_common_state_compatibility() {
+        // Snapshots taken before the power state was saved
+        #[derive(Serialize)]
+        struct OldVfioCommonState {
+            intx_state: Option<IntxState>,
+            msi_state: Option<MsiState>,
+            msix_state: Option<MsixState>,
+        }
+
+        let snapshot = Snapshot::new_from_state(&OldVfioCommonState {
+            intx_state: None,
+            msi_state: None,
+            msix_state: None,
+        })
+        .unwrap();
+        let state: VfioCommonState = snapshot.to_state().unwrap();
+        assert_eq!(state.pm_cap_offset, None);
+        assert_eq!(state.power_state, PCI_D0);
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Per device quirks of the VFIO devices.
+//!
+//! Some devices only behave once assigned to a VM with an adjusted config
+//! space, BAR or reset handling. Rather than being hardcoded, these quirks
+//! are described in a TOML file, each `[[device]]` table applying to the
+//! devices matching its vendor and device ids:
+//!
+//! ```toml
+//! [[device]]
+//! vendor = 0x10de
+//! device = 0x2236
+//! reset = "none"
+//! trace_config = true
+//!
+//! [[device.config]]
+//! offset = 0x40
+//! mask = 0x0000ff00
+//! value = 0x00000000
+//!
+//! [[device.bar]]
+//! index = 1
+//! mmap = false
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use thiserror::Error;
+
+// Size of the PCIe config space
+const PCI_CONFIG_SPACE_SIZE: u16 = 0x1000;
+// Number of BARs, the expansion ROM included
+const BAR_NUMS: u32 = 7;
+
+#[derive(Debug, Error)]
+pub enum VfioQuirksError {
+    #[error("Invalid VFIO quirks: {0}")]
+    Parse(#[source] toml::de::Error),
+    #[error("Config register offset 0x{0:x} of device {1:04x}:{2:04x} is not 4 bytes aligned")]
+    UnalignedConfigOffset(u16, u16, u16),
+    #[error("Config register offset 0x{0:x} of device {1:04x}:{2:04x} is beyond the config space")]
+    InvalidConfigOffset(u16, u16, u16),
+    #[error("BAR index {0} of device {1:04x}:{2:04x} is invalid")]
+    InvalidBarIndex(u32, u16, u16),
+}
+
+/// How the device is reset as it is assigned to the VM, reset with it and
+/// released.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VfioResetMethod {
+    /// Reset through VFIO, with the method the host kernel selects.
+    #[default]
+    Device,
+    /// Function level reset.
+    Flr,
+    /// Secondary bus or slot reset, only possible for a device alone on its
+    /// bus.
+    Bus,
+    /// Reset through the ACPI `_RST` method of the device.
+    Acpi,
+    /// Not reset, for the devices which wedge when reset.
+    None,
+}
+
+#[derive(Error, Debug)]
+pub enum ParseVfioResetMethodError {
+    #[error("Invalid reset method: {0}")]
+    InvalidValue(String),
+}
+
+impl FromStr for VfioResetMethod {
+    type Err = ParseVfioResetMethodError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "device" => Ok(VfioResetMethod::Device),
+            "flr" => Ok(VfioResetMethod::Flr),
+            "bus" => Ok(VfioResetMethod::Bus),
+            "acpi" => Ok(VfioResetMethod::Acpi),
+            "none" => Ok(VfioResetMethod::None),
+            _ => Err(ParseVfioResetMethodError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+/// Override of the bits of a config space register, as seen by the guest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VfioConfigQuirk {
+    /// Offset of the 32 bits register.
+    pub offset: u16,
+    /// Bits of the register which are overridden.
+    pub mask: u32,
+    pub value: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VfioBarQuirk {
+    pub index: u32,
+    /// Whether the BAR is mapped into the guest, rather than having each
+    /// access trapped and forwarded to the device.
+    #[serde(default = "default_true")]
+    pub mmap: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VfioDeviceQuirk {
+    pub vendor: u16,
+    pub device: u16,
+    #[serde(default)]
+    pub reset: VfioResetMethod,
+    /// Whether the config space accesses of the guest are logged.
+    #[serde(default)]
+    pub trace_config: bool,
+    #[serde(default)]
+    pub config: Vec<VfioConfigQuirk>,
+    #[serde(default, rename = "bar")]
+    pub bars: Vec<VfioBarQuirk>,
+}
+
+impl VfioDeviceQuirk {
+    /// Whether the BAR `index` is mapped into the guest.
+    pub fn bar_mmap(&self, index: u32) -> bool {
+        self.bars
+            .iter()
+            .find(|bar| bar.index == index)
+            .map_or(true, |bar| bar.mmap)
+    }
+
+    fn validate(&self) -> Result<(), VfioQuirksError> {
+        for config in &self.config {
+            if config.offset % 4 != 0 {
+                return Err(VfioQuirksError::UnalignedConfigOffset(
+                    config.offset,
+                    self.vendor,
+                    self.device,
+                ));
+            }
+            if config.offset >= PCI_CONFIG_SPACE_SIZE {
+                return Err(VfioQuirksError::InvalidConfigOffset(
+                    config.offset,
+                    self.vendor,
+                    self.device,
+                ));
+            }
+        }
+
+        for bar in &self.bars {
+            if bar.index >= BAR_NUMS {
+                return Err(VfioQuirksError::InvalidBarIndex(
+                    bar.index,
+                    self.vendor,
+                    self.device,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VfioQuirks {
+    #[serde(default, rename = "device")]
+    pub devices: Vec<VfioDeviceQuirk>,
+}
+
+impl VfioQuirks {
+    pub fn parse(quirks: &str) -> Result<Self, VfioQuirksError> {
+        let quirks: VfioQuirks = toml::from_str(quirks).map_err(VfioQuirksError::Parse)?;
+        for device in &quirks.devices {
+            device.validate()?;
+        }
+
+        Ok(quirks)
+    }
+
+    /// Quirks of the device with the given ids, the first matching entry
+    /// winning.
+    pub fn find(&self, vendor: u16, device: u16) -> Option<&VfioDeviceQuirk> {
+        self.devices
+            .iter()
+            .find(|quirk| quirk.vendor == vendor && quirk.device == device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vfio_quirks_parse() {
+        let quirks = VfioQuirks::parse(
+            r#"
+            [[device]]
+            vendor = 0x10de
+            device = 0x2236
+            reset = "bus"
+            trace_config = true
+
+            [[device.config]]
+            offset = 0x40
+            mask = 0x0000ff00
+            value = 0x00001200
+
+            [[device.bar]]
+            index = 1
+            mmap = false
+
+            [[device]]
+            vendor = 0x144d
+            device = 0xa808
+            "#,
+        )
+        .unwrap();
+
+        let quirk = quirks.find(0x10de, 0x2236).unwrap();
+        assert_eq!(quirk.reset, VfioResetMethod::Bus);
+        assert!(quirk.trace_config);
+        assert_eq!(
+            quirk.config,
+            vec![VfioConfigQuirk {
+                offset: 0x40,
+                mask: 0xff00,
+                value: 0x1200,
+            }]
+        );
+        assert!(quirk.bar_mmap(0));
+        assert!(!quirk.bar_mmap(1));
+
+        let quirk = quirks.find(0x144d, 0xa808).unwrap();
+        assert_eq!(quirk.reset, VfioResetMethod::Device);
+        assert!(!quirk.trace_config);
+        assert!(quirks.find(0x8086, 0x1234).is_none());
+
+        assert!(VfioQuirks::parse("").unwrap().devices.is_empty());
+        assert!(VfioQuirks::parse("[[device]]\nvendor = 1\ndevice = 2\nreset = \"warm\"").is_err());
+        assert!(VfioQuirks::parse(
+            "[[device]]\nvendor = 1\ndevice = 2\n[[device.config]]\noffset = 0x41\nmask = 1\nvalue = 1"
+        )
+        .is_err());
+        assert!(
+            VfioQuirks::parse("[[device]]\nvendor = 1\ndevice = 2\n[[device.bar]]\nindex = 7")
+                .is_err()
+        );
+    }
+}
@@ -7,13 +7,18 @@ use api_client::simple_api_command;
 use api_client::simple_api_command_with_fds;
 use api_client::simple_api_full_command;
 use api_client::Error as ApiClientError;
+use block::ImageType;
 use clap::{Arg, ArgAction, ArgMatches, Command};
 use option_parser::{ByteSized, ByteSizedParseError};
 use std::fmt;
-use std::io::Read;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
 use std::process;
+use std::thread;
+use std::time::Duration;
 #[cfg(feature = "dbus_api")]
 use zbus::{proxy, zvariant::Optional};
 
@@ -27,6 +32,13 @@ enum Error {
     InvalidCpuCount(std::num::ParseIntError),
     InvalidMemorySize(ByteSizedParseError),
     InvalidBalloonSize(ByteSizedParseError),
+    InvalidQuiesceTimeout(std::num::ParseIntError),
+    InvalidUnplugTimeout(std::num::ParseIntError),
+    InvalidTimeDelta(std::num::ParseIntError),
+    InvalidTimeStep(std::num::ParseIntError),
+    InvalidTimeInterval(std::num::ParseIntError),
+    InvalidErrorAddress(std::num::ParseIntError),
+    InvalidHostNumaNode(std::num::ParseIntError),
     AddDeviceConfig(vmm::config::Error),
     AddDiskConfig(vmm::config::Error),
     AddFsConfig(vmm::config::Error),
@@ -34,10 +46,21 @@ enum Error {
     AddNetConfig(vmm::config::Error),
     AddUserDeviceConfig(vmm::config::Error),
     AddVdpaConfig(vmm::config::Error),
+    AddConsoleConfig(vmm::config::Error),
     AddVsockConfig(vmm::config::Error),
+    InvalidVsockPort(std::num::ParseIntError),
+    InvalidVsockAddress(std::net::AddrParseError),
     Restore(vmm::config::Error),
     ReadingStdin(std::io::Error),
     ReadingFile(std::io::Error),
+    InvalidImageType(block::ParseImageTypeError),
+    OpeningImage(std::io::Error),
+    SameImage,
+    ConvertImage(block::Error),
+    ConnectAgent(std::io::Error),
+    AgentChannel(vmm::agent_channel::Error),
+    InvalidResolution(String),
+    WritingStdout(std::io::Error),
 }
 
 impl fmt::Display for Error {
@@ -50,6 +73,13 @@ impl fmt::Display for Error {
             InvalidCpuCount(e) => write!(f, "Error parsing CPU count: {e}"),
             InvalidMemorySize(e) => write!(f, "Error parsing memory size: {e:?}"),
             InvalidBalloonSize(e) => write!(f, "Error parsing balloon size: {e:?}"),
+            InvalidQuiesceTimeout(e) => write!(f, "Error parsing quiesce timeout: {e}"),
+            InvalidUnplugTimeout(e) => write!(f, "Error parsing unplug timeout: {e}"),
+            InvalidTimeDelta(e) => write!(f, "Error parsing time delta: {e}"),
+            InvalidTimeStep(e) => write!(f, "Error parsing time adjustment step: {e}"),
+            InvalidTimeInterval(e) => write!(f, "Error parsing time adjustment interval: {e}"),
+            InvalidErrorAddress(e) => write!(f, "Error parsing error address: {e}"),
+            InvalidHostNumaNode(e) => write!(f, "Error parsing host NUMA node: {e}"),
             AddDeviceConfig(e) => write!(f, "Error parsing device syntax: {e}"),
             AddDiskConfig(e) => write!(f, "Error parsing disk syntax: {e}"),
             AddFsConfig(e) => write!(f, "Error parsing filesystem syntax: {e}"),
@@ -57,10 +87,23 @@ impl fmt::Display for Error {
             AddNetConfig(e) => write!(f, "Error parsing network syntax: {e}"),
             AddUserDeviceConfig(e) => write!(f, "Error parsing user device syntax: {e}"),
             AddVdpaConfig(e) => write!(f, "Error parsing vDPA device syntax: {e}"),
+            AddConsoleConfig(e) => write!(f, "Error parsing console port syntax: {e}"),
             AddVsockConfig(e) => write!(f, "Error parsing vsock syntax: {e}"),
+            InvalidVsockPort(e) => write!(f, "Error parsing vsock port: {e}"),
+            InvalidVsockAddress(e) => write!(f, "Error parsing TCP address: {e}"),
             Restore(e) => write!(f, "Error parsing restore syntax: {e}"),
             ReadingStdin(e) => write!(f, "Error reading from stdin: {e}"),
             ReadingFile(e) => write!(f, "Error reading from file: {e}"),
+            InvalidImageType(e) => write!(f, "Error parsing image format: {e}"),
+            OpeningImage(e) => write!(f, "Error opening image: {e}"),
+            SameImage => write!(f, "Source and destination are the same image"),
+            ConvertImage(e) => write!(f, "Error converting image: {e}"),
+            ConnectAgent(e) => write!(f, "Error connecting to the agent socket: {e}"),
+            AgentChannel(e) => write!(f, "Error on the agent channel: {e}"),
+            InvalidResolution(r) => {
+                write!(f, "Invalid resolution '{r}', expected <width>x<height>")
+            }
+            WritingStdout(e) => write!(f, "Error writing to stdout: {e}"),
         }
     }
 }
@@ -76,18 +119,35 @@ enum TargetApi<'a> {
 trait DBusApi1 {
     fn vmm_ping(&self) -> zbus::Result<String>;
     fn vmm_shutdown(&self) -> zbus::Result<()>;
+    fn vmm_set_log_level(&self, log_level_data: &str) -> zbus::Result<()>;
     fn vm_add_device(&self, device_config: &str) -> zbus::Result<Optional<String>>;
     fn vm_add_disk(&self, disk_config: &str) -> zbus::Result<Optional<String>>;
     fn vm_add_fs(&self, fs_config: &str) -> zbus::Result<Optional<String>>;
     fn vm_add_net(&self, net_config: &str) -> zbus::Result<Optional<String>>;
+    fn vm_swap_net(&self, net_config: &str) -> zbus::Result<()>;
     fn vm_add_pmem(&self, pmem_config: &str) -> zbus::Result<Optional<String>>;
     fn vm_add_user_device(&self, vm_add_user_device: &str) -> zbus::Result<Optional<String>>;
     fn vm_add_vdpa(&self, vdpa_config: &str) -> zbus::Result<Optional<String>>;
+    fn vm_add_console(&self, console_port_config: &str) -> zbus::Result<Optional<String>>;
     fn vm_add_vsock(&self, vsock_config: &str) -> zbus::Result<Optional<String>>;
+    fn vm_add_vsock_forward(&self, vsock_forward: &str) -> zbus::Result<()>;
+    fn vm_remove_vsock_forward(&self, vsock_forward: &str) -> zbus::Result<()>;
+    fn vm_block_trace(&self, vm_block_trace: &str) -> zbus::Result<()>;
     fn vm_boot(&self) -> zbus::Result<()>;
     fn vm_coredump(&self, vm_coredump_data: &str) -> zbus::Result<()>;
     fn vm_counters(&self) -> zbus::Result<Optional<String>>;
+    fn vm_counters_shm(&self) -> zbus::Result<Optional<String>>;
+    fn vm_keep_disk(&self, vm_keep_disk: &str) -> zbus::Result<()>;
+    fn vm_launch_measurement(&self) -> zbus::Result<Optional<String>>;
+    fn vm_snp_report(&self, snp_report_data: &str) -> zbus::Result<Optional<String>>;
+    fn vm_unplug_status(&self) -> zbus::Result<Optional<String>>;
+    fn vm_time_info(&self) -> zbus::Result<Optional<String>>;
+    fn vm_time_adjust(&self, time_adjust_data: &str) -> zbus::Result<()>;
+    fn vm_inject_error(&self, inject_error_data: &str) -> zbus::Result<()>;
+    fn vm_migrate_memory(&self, migrate_memory_data: &str) -> zbus::Result<()>;
+    fn vm_set_zone_policy(&self, set_zone_policy_data: &str) -> zbus::Result<()>;
     fn vm_create(&self, vm_config: &str) -> zbus::Result<()>;
+    fn vm_validate_config(&self, vm_config: &str) -> zbus::Result<String>;
     fn vm_delete(&self) -> zbus::Result<()>;
     fn vm_info(&self) -> zbus::Result<String>;
     fn vm_pause(&self) -> zbus::Result<()>;
@@ -139,6 +199,11 @@ impl<'a> DBusApi1ProxyBlocking<'a> {
         self.vmm_shutdown().map_err(Error::DBusApiClient)
     }
 
+    fn api_vmm_set_log_level(&self, log_level_data: &str) -> ApiResult {
+        self.vmm_set_log_level(log_level_data)
+            .map_err(Error::DBusApiClient)
+    }
+
     fn api_vm_add_device(&self, device_config: &str) -> ApiResult {
         self.print_response(self.vm_add_device(device_config))
     }
@@ -155,6 +220,10 @@ impl<'a> DBusApi1ProxyBlocking<'a> {
         self.print_response(self.vm_add_net(net_config))
     }
 
+    fn api_vm_swap_net(&self, net_config: &str) -> ApiResult {
+        self.vm_swap_net(net_config).map_err(Error::DBusApiClient)
+    }
+
     fn api_vm_add_pmem(&self, pmem_config: &str) -> ApiResult {
         self.print_response(self.vm_add_pmem(pmem_config))
     }
@@ -167,10 +236,34 @@ impl<'a> DBusApi1ProxyBlocking<'a> {
         self.print_response(self.vm_add_vdpa(vdpa_config))
     }
 
+    fn api_vm_add_console(&self, console_port_config: &str) -> ApiResult {
+        self.print_response(self.vm_add_console(console_port_config))
+    }
+
     fn api_vm_add_vsock(&self, vsock_config: &str) -> ApiResult {
         self.print_response(self.vm_add_vsock(vsock_config))
     }
 
+    fn api_vm_add_vsock_forward(&self, vsock_forward: &str) -> ApiResult {
+        self.vm_add_vsock_forward(vsock_forward)
+            .map_err(Error::DBusApiClient)
+    }
+
+    fn api_vm_remove_vsock_forward(&self, vsock_forward: &str) -> ApiResult {
+        self.vm_remove_vsock_forward(vsock_forward)
+            .map_err(Error::DBusApiClient)
+    }
+
+    fn api_vm_block_trace(&self, vm_block_trace: &str) -> ApiResult {
+        self.vm_block_trace(vm_block_trace)
+            .map_err(Error::DBusApiClient)
+    }
+
+    fn api_vm_keep_disk(&self, vm_keep_disk: &str) -> ApiResult {
+        self.vm_keep_disk(vm_keep_disk)
+            .map_err(Error::DBusApiClient)
+    }
+
     fn api_vm_boot(&self) -> ApiResult {
         self.vm_boot().map_err(Error::DBusApiClient)
     }
@@ -184,10 +277,56 @@ impl<'a> DBusApi1ProxyBlocking<'a> {
         self.print_response(self.vm_counters())
     }
 
+    fn api_vm_counters_shm(&self) -> ApiResult {
+        self.print_response(self.vm_counters_shm())
+    }
+
+    fn api_vm_launch_measurement(&self) -> ApiResult {
+        self.print_response(self.vm_launch_measurement())
+    }
+
+    fn api_vm_snp_report(&self, snp_report_data: &str) -> ApiResult {
+        self.print_response(self.vm_snp_report(snp_report_data))
+    }
+
+    fn api_vm_unplug_status(&self) -> ApiResult {
+        self.print_response(self.vm_unplug_status())
+    }
+
+    fn api_vm_time_info(&self) -> ApiResult {
+        self.print_response(self.vm_time_info())
+    }
+
+    fn api_vm_time_adjust(&self, time_adjust_data: &str) -> ApiResult {
+        self.vm_time_adjust(time_adjust_data)
+            .map_err(Error::DBusApiClient)
+    }
+
+    fn api_vm_inject_error(&self, inject_error_data: &str) -> ApiResult {
+        self.vm_inject_error(inject_error_data)
+            .map_err(Error::DBusApiClient)
+    }
+
+    fn api_vm_migrate_memory(&self, migrate_memory_data: &str) -> ApiResult {
+        self.vm_migrate_memory(migrate_memory_data)
+            .map_err(Error::DBusApiClient)
+    }
+
+    fn api_vm_set_zone_policy(&self, set_zone_policy_data: &str) -> ApiResult {
+        self.vm_set_zone_policy(set_zone_policy_data)
+            .map_err(Error::DBusApiClient)
+    }
+
     fn api_vm_create(&self, vm_config: &str) -> ApiResult {
         self.vm_create(vm_config).map_err(Error::DBusApiClient)
     }
 
+    fn api_vm_validate_config(&self, vm_config: &str) -> ApiResult {
+        self.vm_validate_config(vm_config)
+            .map(|validation| println!("{validation}"))
+            .map_err(Error::DBusApiClient)
+    }
+
     fn api_vm_delete(&self) -> ApiResult {
         self.vm_delete().map_err(Error::DBusApiClient)
     }
@@ -273,6 +412,12 @@ fn rest_api_do_command(matches: &ArgMatches, socket: &mut UnixStream) -> ApiResu
         }
         Some("shutdown-vmm") => simple_api_full_command(socket, "PUT", "vmm.shutdown", None)
             .map_err(Error::HttpApiClient),
+        Some("set-log-level") => {
+            let log_level_data =
+                set_log_level_config(matches.subcommand_matches("set-log-level").unwrap());
+            simple_api_full_command(socket, "PUT", "vmm.set-log-level", Some(&log_level_data))
+                .map_err(Error::HttpApiClient)
+        }
         Some("resume") => {
             simple_api_command(socket, "PUT", "resume", None).map_err(Error::HttpApiClient)
         }
@@ -283,7 +428,9 @@ fn rest_api_do_command(matches: &ArgMatches, socket: &mut UnixStream) -> ApiResu
             simple_api_command(socket, "PUT", "reboot", None).map_err(Error::HttpApiClient)
         }
         Some("pause") => {
-            simple_api_command(socket, "PUT", "pause", None).map_err(Error::HttpApiClient)
+            let pause_data = pause_config(matches.subcommand_matches("pause").unwrap())?;
+            simple_api_command(socket, "PUT", "pause", Some(&pause_data))
+                .map_err(Error::HttpApiClient)
         }
         Some("info") => {
             simple_api_command(socket, "GET", "info", None).map_err(Error::HttpApiClient)
@@ -291,6 +438,58 @@ fn rest_api_do_command(matches: &ArgMatches, socket: &mut UnixStream) -> ApiResu
         Some("counters") => {
             simple_api_command(socket, "GET", "counters", None).map_err(Error::HttpApiClient)
         }
+        Some("counters-shm") => {
+            simple_api_command(socket, "GET", "counters-shm", None).map_err(Error::HttpApiClient)
+        }
+        Some("launch-measurement") => simple_api_command(socket, "GET", "launch-measurement", None)
+            .map_err(Error::HttpApiClient),
+        Some("snp-report") => {
+            let snp_report_data =
+                snp_report_config(matches.subcommand_matches("snp-report").unwrap());
+            simple_api_command(socket, "PUT", "snp-report", Some(&snp_report_data))
+                .map_err(Error::HttpApiClient)
+        }
+        Some("unplug-status") => {
+            simple_api_command(socket, "GET", "unplug-status", None).map_err(Error::HttpApiClient)
+        }
+        Some("time-info") => {
+            simple_api_command(socket, "GET", "time-info", None).map_err(Error::HttpApiClient)
+        }
+        Some("time-adjust") => {
+            let (steps, interval) =
+                time_adjust_config(matches.subcommand_matches("time-adjust").unwrap())?;
+            for (i, step) in steps.iter().enumerate() {
+                if i > 0 {
+                    thread::sleep(interval);
+                }
+                simple_api_command(socket, "PUT", "time-adjust", Some(step))
+                    .map_err(Error::HttpApiClient)?;
+            }
+            Ok(())
+        }
+        Some("inject-error") => {
+            let inject_error_data =
+                inject_error_config(matches.subcommand_matches("inject-error").unwrap())?;
+            simple_api_command(socket, "PUT", "inject-error", Some(&inject_error_data))
+                .map_err(Error::HttpApiClient)
+        }
+        Some("migrate-memory") => {
+            let migrate_memory_data =
+                migrate_memory_config(matches.subcommand_matches("migrate-memory").unwrap())?;
+            simple_api_command(socket, "PUT", "migrate-memory", Some(&migrate_memory_data))
+                .map_err(Error::HttpApiClient)
+        }
+        Some("set-zone-policy") => {
+            let set_zone_policy_data =
+                set_zone_policy_config(matches.subcommand_matches("set-zone-policy").unwrap())?;
+            simple_api_command(
+                socket,
+                "PUT",
+                "set-zone-policy",
+                Some(&set_zone_policy_data),
+            )
+            .map_err(Error::HttpApiClient)
+        }
         Some("ping") => {
             simple_api_full_command(socket, "GET", "vmm.ping", None).map_err(Error::HttpApiClient)
         }
@@ -346,16 +545,34 @@ fn rest_api_do_command(matches: &ArgMatches, socket: &mut UnixStream) -> ApiResu
                 .map_err(Error::HttpApiClient)
         }
         Some("remove-device") => {
-            let remove_device_data = remove_device_config(
-                matches
-                    .subcommand_matches("remove-device")
-                    .unwrap()
-                    .get_one::<String>("id")
-                    .unwrap(),
-            );
+            let remove_device_data =
+                remove_device_config(matches.subcommand_matches("remove-device").unwrap())?;
             simple_api_command(socket, "PUT", "remove-device", Some(&remove_device_data))
                 .map_err(Error::HttpApiClient)
         }
+        Some("block-trace") => {
+            let block_trace_data =
+                block_trace_config(matches.subcommand_matches("block-trace").unwrap());
+            simple_api_command(socket, "PUT", "block-trace", Some(&block_trace_data))
+                .map_err(Error::HttpApiClient)
+        }
+        Some("keep-disk") => {
+            let keep_disk_data = keep_disk_config(matches.subcommand_matches("keep-disk").unwrap());
+            simple_api_command(socket, "PUT", "keep-disk", Some(&keep_disk_data))
+                .map_err(Error::HttpApiClient)
+        }
+        Some("add-vsock-forward") => {
+            let vsock_forward =
+                vsock_forward_config(matches.subcommand_matches("add-vsock-forward").unwrap())?;
+            simple_api_command(socket, "PUT", "add-vsock-forward", Some(&vsock_forward))
+                .map_err(Error::HttpApiClient)
+        }
+        Some("remove-vsock-forward") => {
+            let vsock_forward =
+                vsock_forward_config(matches.subcommand_matches("remove-vsock-forward").unwrap())?;
+            simple_api_command(socket, "PUT", "remove-vsock-forward", Some(&vsock_forward))
+                .map_err(Error::HttpApiClient)
+        }
         Some("add-disk") => {
             let disk_config = add_disk_config(
                 matches
@@ -422,6 +639,17 @@ fn rest_api_do_command(matches: &ArgMatches, socket: &mut UnixStream) -> ApiResu
             simple_api_command(socket, "PUT", "add-vdpa", Some(&vdpa_config))
                 .map_err(Error::HttpApiClient)
         }
+        Some("add-console") => {
+            let console_port_config = add_console_config(
+                matches
+                    .subcommand_matches("add-console")
+                    .unwrap()
+                    .get_one::<String>("console_port_config")
+                    .unwrap(),
+            )?;
+            simple_api_command(socket, "PUT", "add-console", Some(&console_port_config))
+                .map_err(Error::HttpApiClient)
+        }
         Some("add-vsock") => {
             let vsock_config = add_vsock_config(
                 matches
@@ -433,6 +661,17 @@ fn rest_api_do_command(matches: &ArgMatches, socket: &mut UnixStream) -> ApiResu
             simple_api_command(socket, "PUT", "add-vsock", Some(&vsock_config))
                 .map_err(Error::HttpApiClient)
         }
+        Some("swap-net") => {
+            let (net_config, fds) = add_net_config(
+                matches
+                    .subcommand_matches("swap-net")
+                    .unwrap()
+                    .get_one::<String>("net_config")
+                    .unwrap(),
+            )?;
+            simple_api_command_with_fds(socket, "PUT", "swap-net", Some(&net_config), fds)
+                .map_err(Error::HttpApiClient)
+        }
         Some("snapshot") => {
             let snapshot_config = snapshot_config(
                 matches
@@ -507,6 +746,17 @@ fn rest_api_do_command(matches: &ArgMatches, socket: &mut UnixStream) -> ApiResu
             )?;
             simple_api_command(socket, "PUT", "create", Some(&data)).map_err(Error::HttpApiClient)
         }
+        Some("validate-config") => {
+            let data = create_data(
+                matches
+                    .subcommand_matches("validate-config")
+                    .unwrap()
+                    .get_one::<String>("path")
+                    .unwrap(),
+            )?;
+            simple_api_command(socket, "PUT", "validate-config", Some(&data))
+                .map_err(Error::HttpApiClient)
+        }
         _ => unreachable!(),
     }
 }
@@ -517,12 +767,52 @@ fn dbus_api_do_command(matches: &ArgMatches, proxy: &DBusApi1ProxyBlocking<'_>)
         Some("boot") => proxy.api_vm_boot(),
         Some("delete") => proxy.api_vm_delete(),
         Some("shutdown-vmm") => proxy.api_vmm_shutdown(),
+        Some("set-log-level") => {
+            let log_level_data =
+                set_log_level_config(matches.subcommand_matches("set-log-level").unwrap());
+            proxy.api_vmm_set_log_level(&log_level_data)
+        }
         Some("resume") => proxy.api_vm_resume(),
         Some("power-button") => proxy.api_vm_power_button(),
         Some("reboot") => proxy.api_vm_reboot(),
         Some("pause") => proxy.api_vm_pause(),
         Some("info") => proxy.api_vm_info(),
         Some("counters") => proxy.api_vm_counters(),
+        Some("counters-shm") => proxy.api_vm_counters_shm(),
+        Some("launch-measurement") => proxy.api_vm_launch_measurement(),
+        Some("snp-report") => {
+            let snp_report_data =
+                snp_report_config(matches.subcommand_matches("snp-report").unwrap());
+            proxy.api_vm_snp_report(&snp_report_data)
+        }
+        Some("unplug-status") => proxy.api_vm_unplug_status(),
+        Some("time-info") => proxy.api_vm_time_info(),
+        Some("time-adjust") => {
+            let (steps, interval) =
+                time_adjust_config(matches.subcommand_matches("time-adjust").unwrap())?;
+            for (i, step) in steps.iter().enumerate() {
+                if i > 0 {
+                    thread::sleep(interval);
+                }
+                proxy.api_vm_time_adjust(step)?;
+            }
+            Ok(())
+        }
+        Some("inject-error") => {
+            let inject_error_data =
+                inject_error_config(matches.subcommand_matches("inject-error").unwrap())?;
+            proxy.api_vm_inject_error(&inject_error_data)
+        }
+        Some("migrate-memory") => {
+            let migrate_memory_data =
+                migrate_memory_config(matches.subcommand_matches("migrate-memory").unwrap())?;
+            proxy.api_vm_migrate_memory(&migrate_memory_data)
+        }
+        Some("set-zone-policy") => {
+            let set_zone_policy_data =
+                set_zone_policy_config(matches.subcommand_matches("set-zone-policy").unwrap())?;
+            proxy.api_vm_set_zone_policy(&set_zone_policy_data)
+        }
         Some("ping") => proxy.api_vmm_ping(),
         Some("shutdown") => proxy.api_vm_shutdown(),
         Some("resize") => {
@@ -571,15 +861,29 @@ fn dbus_api_do_command(matches: &ArgMatches, proxy: &DBusApi1ProxyBlocking<'_>)
             proxy.api_vm_add_device(&device_config)
         }
         Some("remove-device") => {
-            let remove_device_data = remove_device_config(
-                matches
-                    .subcommand_matches("remove-device")
-                    .unwrap()
-                    .get_one::<String>("id")
-                    .unwrap(),
-            );
+            let remove_device_data =
+                remove_device_config(matches.subcommand_matches("remove-device").unwrap())?;
             proxy.api_vm_remove_device(&remove_device_data)
         }
+        Some("block-trace") => {
+            let block_trace_data =
+                block_trace_config(matches.subcommand_matches("block-trace").unwrap());
+            proxy.api_vm_block_trace(&block_trace_data)
+        }
+        Some("keep-disk") => {
+            let keep_disk_data = keep_disk_config(matches.subcommand_matches("keep-disk").unwrap());
+            proxy.api_vm_keep_disk(&keep_disk_data)
+        }
+        Some("add-vsock-forward") => {
+            let vsock_forward =
+                vsock_forward_config(matches.subcommand_matches("add-vsock-forward").unwrap())?;
+            proxy.api_vm_add_vsock_forward(&vsock_forward)
+        }
+        Some("remove-vsock-forward") => {
+            let vsock_forward =
+                vsock_forward_config(matches.subcommand_matches("remove-vsock-forward").unwrap())?;
+            proxy.api_vm_remove_vsock_forward(&vsock_forward)
+        }
         Some("add-disk") => {
             let disk_config = add_disk_config(
                 matches
@@ -640,6 +944,16 @@ fn dbus_api_do_command(matches: &ArgMatches, proxy: &DBusApi1ProxyBlocking<'_>)
             )?;
             proxy.api_vm_add_vdpa(&vdpa_config)
         }
+        Some("add-console") => {
+            let console_port_config = add_console_config(
+                matches
+                    .subcommand_matches("add-console")
+                    .unwrap()
+                    .get_one::<String>("console_port_config")
+                    .unwrap(),
+            )?;
+            proxy.api_vm_add_console(&console_port_config)
+        }
         Some("add-vsock") => {
             let vsock_config = add_vsock_config(
                 matches
@@ -650,6 +964,16 @@ fn dbus_api_do_command(matches: &ArgMatches, proxy: &DBusApi1ProxyBlocking<'_>)
             )?;
             proxy.api_vm_add_vsock(&vsock_config)
         }
+        Some("swap-net") => {
+            let (net_config, _fds) = add_net_config(
+                matches
+                    .subcommand_matches("swap-net")
+                    .unwrap()
+                    .get_one::<String>("net_config")
+                    .unwrap(),
+            )?;
+            proxy.api_vm_swap_net(&net_config)
+        }
         Some("snapshot") => {
             let snapshot_config = snapshot_config(
                 matches
@@ -714,6 +1038,16 @@ fn dbus_api_do_command(matches: &ArgMatches, proxy: &DBusApi1ProxyBlocking<'_>)
             )?;
             proxy.api_vm_create(&data)
         }
+        Some("validate-config") => {
+            let data = create_data(
+                matches
+                    .subcommand_matches("validate-config")
+                    .unwrap()
+                    .get_one::<String>("path")
+                    .unwrap(),
+            )?;
+            proxy.api_vm_validate_config(&data)
+        }
         _ => unreachable!(),
     }
 }
@@ -723,7 +1057,7 @@ fn resize_config(
     memory: Option<&str>,
     balloon: Option<&str>,
 ) -> Result<String, Error> {
-    let desired_vcpus: Option<u8> = if let Some(cpus) = cpus {
+    let desired_vcpus: Option<u32> = if let Some(cpus) = cpus {
         Some(cpus.parse().map_err(Error::InvalidCpuCount)?)
     } else {
         None
@@ -787,10 +1121,176 @@ fn add_user_device_config(config: &str) -> Result<String, Error> {
     Ok(device_config)
 }
 
-fn remove_device_config(id: &str) -> String {
-    let remove_device_data = vmm::api::VmRemoveDeviceData { id: id.to_owned() };
+fn remove_device_config(matches: &ArgMatches) -> Result<String, Error> {
+    let remove_device_data = vmm::api::VmRemoveDeviceData {
+        id: matches.get_one::<String>("id").unwrap().to_owned(),
+        timeout: matches
+            .get_one::<String>("timeout")
+            .map(|timeout| timeout.parse::<u64>())
+            .transpose()
+            .map_err(Error::InvalidUnplugTimeout)?,
+        surprise_removal: matches.get_flag("surprise_removal"),
+    };
+
+    Ok(serde_json::to_string(&remove_device_data).unwrap())
+}
+
+/// Split the requested adjustment into the successive requests to send, so
+/// that a large offset can be slewed into the guest rather than applied at
+/// once.
+fn time_adjust_config(matches: &ArgMatches) -> Result<(Vec<String>, Duration), Error> {
+    let delta_ns = matches
+        .get_one::<String>("delta_ns")
+        .unwrap()
+        .parse::<u64>()
+        .map_err(Error::InvalidTimeDelta)?;
+    let step_ns = matches
+        .get_one::<String>("step_ns")
+        .map(|step| step.parse::<u64>())
+        .transpose()
+        .map_err(Error::InvalidTimeStep)?
+        .filter(|step| *step > 0)
+        .unwrap_or(delta_ns);
+    let interval_ms = matches
+        .get_one::<String>("interval_ms")
+        .unwrap()
+        .parse::<u64>()
+        .map_err(Error::InvalidTimeInterval)?;
+
+    let mut steps = Vec::new();
+    let mut remaining = delta_ns;
+    loop {
+        let delta_ns = remaining.min(step_ns);
+        steps.push(serde_json::to_string(&vmm::api::VmTimeAdjustData { delta_ns }).unwrap());
+        remaining -= delta_ns;
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    Ok((steps, Duration::from_millis(interval_ms)))
+}
+
+fn inject_error_config(matches: &ArgMatches) -> Result<String, Error> {
+    let gpa = matches.get_one::<String>("gpa").unwrap();
+    let gpa = match gpa.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => gpa.parse::<u64>(),
+    }
+    .map_err(Error::InvalidErrorAddress)?;
+    let severity = match matches.get_one::<String>("severity").unwrap().as_str() {
+        "recoverable" => vmm::api::ErrorSeverity::Recoverable,
+        "fatal" => vmm::api::ErrorSeverity::Fatal,
+        _ => vmm::api::ErrorSeverity::Corrected,
+    };
+    let inject_error_data = vmm::api::VmInjectErrorData {
+        // Memory errors are the only kind supported.
+        error_type: vmm::api::InjectErrorType::Memory,
+        gpa,
+        severity,
+    };
+
+    Ok(serde_json::to_string(&inject_error_data).unwrap())
+}
+
+fn snp_report_config(matches: &ArgMatches) -> String {
+    let snp_report_data = vmm::api::VmSnpReportData {
+        report_data: matches.get_one::<String>("report_data").cloned(),
+        port: matches.get_one::<String>("port").cloned(),
+    };
+
+    serde_json::to_string(&snp_report_data).unwrap()
+}
+
+fn migrate_memory_config(matches: &ArgMatches) -> Result<String, Error> {
+    let host_numa_node = matches
+        .get_one::<String>("host_numa_node")
+        .unwrap()
+        .parse::<u32>()
+        .map_err(Error::InvalidHostNumaNode)?;
+    let migrate_memory_data = vmm::api::VmMigrateMemoryData { host_numa_node };
 
-    serde_json::to_string(&remove_device_data).unwrap()
+    Ok(serde_json::to_string(&migrate_memory_data).unwrap())
+}
+
+fn set_zone_policy_config(matches: &ArgMatches) -> Result<String, Error> {
+    let id = matches.get_one::<String>("id").unwrap().to_owned();
+    let host_numa_node = matches
+        .get_one::<String>("host_numa_node")
+        .unwrap()
+        .parse::<u32>()
+        .map_err(Error::InvalidHostNumaNode)?;
+    let policy = match matches.get_one::<String>("policy").unwrap().as_str() {
+        "preferred" => vmm::config::HostNumaPolicy::Preferred,
+        _ => vmm::config::HostNumaPolicy::Bind,
+    };
+    let set_zone_policy_data = vmm::api::VmSetZonePolicyData {
+        id,
+        host_numa_node,
+        policy,
+    };
+
+    Ok(serde_json::to_string(&set_zone_policy_data).unwrap())
+}
+
+fn pause_config(matches: &ArgMatches) -> Result<String, Error> {
+    let pause_data = vmm::api::VmPauseData {
+        quiesce_timeout: matches
+            .get_one::<String>("quiesce_timeout")
+            .map(|timeout| timeout.parse::<u64>())
+            .transpose()
+            .map_err(Error::InvalidQuiesceTimeout)?,
+    };
+
+    Ok(serde_json::to_string(&pause_data).unwrap())
+}
+
+fn set_log_level_config(matches: &ArgMatches) -> String {
+    let log_level_data = vmm::api::VmmLogLevelData {
+        filter: matches.get_one::<String>("filter").unwrap().to_owned(),
+    };
+
+    serde_json::to_string(&log_level_data).unwrap()
+}
+
+fn block_trace_config(matches: &ArgMatches) -> String {
+    let block_trace_data = vmm::api::VmBlockTraceData {
+        id: matches.get_one::<String>("id").unwrap().to_owned(),
+        path: matches.get_one::<String>("path").map(PathBuf::from),
+    };
+
+    serde_json::to_string(&block_trace_data).unwrap()
+}
+
+fn keep_disk_config(matches: &ArgMatches) -> String {
+    let keep_disk_data = vmm::api::VmKeepDiskData {
+        id: matches.get_one::<String>("id").unwrap().to_owned(),
+        path: PathBuf::from(matches.get_one::<String>("path").unwrap()),
+    };
+
+    serde_json::to_string(&keep_disk_data).unwrap()
+}
+
+fn vsock_forward_config(matches: &ArgMatches) -> Result<String, Error> {
+    let vsock_forward = vmm::config::VsockForwardConfig {
+        port: matches
+            .get_one::<String>("port")
+            .unwrap()
+            .parse()
+            .map_err(Error::InvalidVsockPort)?,
+        address: matches
+            .get_one::<String>("address")
+            .unwrap()
+            .parse()
+            .map_err(Error::InvalidVsockAddress)?,
+        direction: if matches.get_flag("listen") {
+            vmm::config::VsockForwardDirection::HostToGuest
+        } else {
+            vmm::config::VsockForwardDirection::GuestToHost
+        },
+    };
+
+    Ok(serde_json::to_string(&vsock_forward).unwrap())
 }
 
 fn add_disk_config(config: &str) -> Result<String, Error> {
@@ -834,6 +1334,14 @@ fn add_vdpa_config(config: &str) -> Result<String, Error> {
     Ok(vdpa_config)
 }
 
+fn add_console_config(config: &str) -> Result<String, Error> {
+    let console_port_config =
+        vmm::config::ConsolePortConfig::parse(config).map_err(Error::AddConsoleConfig)?;
+    let console_port_config = serde_json::to_string(&console_port_config).unwrap();
+
+    Ok(console_port_config)
+}
+
 fn add_vsock_config(config: &str) -> Result<String, Error> {
     let vsock_config = vmm::config::VsockConfig::parse(config).map_err(Error::AddVsockConfig)?;
     let vsock_config = serde_json::to_string(&vsock_config).unwrap();
@@ -844,6 +1352,7 @@ fn add_vsock_config(config: &str) -> Result<String, Error> {
 fn snapshot_config(url: &str) -> String {
     let snapshot_config = vmm::api::VmSnapshotConfig {
         destination_url: String::from(url),
+        asynchronous: false,
     };
 
     serde_json::to_string(&snapshot_config).unwrap()
@@ -868,6 +1377,7 @@ fn restore_config(config: &str) -> Result<(String, Vec<i32>), Error> {
 fn coredump_config(destination_url: &str) -> String {
     let coredump_config = vmm::api::VmCoredumpData {
         destination_url: String::from(destination_url),
+        asynchronous: false,
     };
 
     serde_json::to_string(&coredump_config).unwrap()
@@ -876,6 +1386,7 @@ fn coredump_config(destination_url: &str) -> String {
 fn receive_migration_data(url: &str) -> String {
     let receive_migration_data = vmm::api::VmReceiveMigrationData {
         receiver_url: url.to_owned(),
+        asynchronous: false,
     };
 
     serde_json::to_string(&receive_migration_data).unwrap()
@@ -885,6 +1396,7 @@ fn send_migration_data(url: &str, local: bool) -> String {
     let send_migration_data = vmm::api::VmSendMigrationData {
         destination_url: url.to_owned(),
         local,
+        asynchronous: false,
     };
 
     serde_json::to_string(&send_migration_data).unwrap()
@@ -903,6 +1415,110 @@ fn create_data(path: &str) -> Result<String, Error> {
     Ok(data)
 }
 
+// Image conversion only involves local files, hence it does not go through
+// the API.
+fn convert_image(matches: &ArgMatches) -> ApiResult {
+    let source = matches.get_one::<String>("source").unwrap();
+    let destination = matches.get_one::<String>("destination").unwrap();
+    let format = matches
+        .get_one::<String>("format")
+        .unwrap()
+        .parse::<ImageType>()
+        .map_err(Error::InvalidImageType)?;
+
+    // The destination is truncated when opened, which would wipe the source
+    // out before it is read if both are the same file.
+    let source_path = std::fs::canonicalize(source).map_err(Error::OpeningImage)?;
+    if std::fs::canonicalize(destination).is_ok_and(|path| path == source_path) {
+        return Err(Error::SameImage);
+    }
+
+    let src_file = File::open(source_path).map_err(Error::OpeningImage)?;
+    let dst_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(destination)
+        .map_err(Error::OpeningImage)?;
+
+    block::convert_image(src_file, dst_file, format).map_err(Error::ConvertImage)
+}
+
+fn agent_command(matches: &ArgMatches) -> ApiResult {
+    use vmm::agent_channel::{
+        AgentClient, MonitorConfig, FORMAT_TEXT, SELECTION_CLIPBOARD, SELECTION_PRIMARY,
+    };
+
+    let (command, matches) = matches.subcommand().unwrap();
+    let socket = matches.get_one::<String>("socket").unwrap();
+    let stream = UnixStream::connect(socket).map_err(Error::ConnectAgent)?;
+    let mut client = AgentClient::new(stream).map_err(Error::AgentChannel)?;
+
+    let selection = |matches: &ArgMatches| {
+        if matches.get_flag("primary") {
+            SELECTION_PRIMARY
+        } else {
+            SELECTION_CLIPBOARD
+        }
+    };
+
+    match command {
+        "resolution" => {
+            let resolution = matches.get_one::<String>("resolution").unwrap();
+            let (width, height) = resolution
+                .split_once('x')
+                .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                .ok_or_else(|| Error::InvalidResolution(resolution.clone()))?;
+            client
+                .set_resolution(&[MonitorConfig {
+                    width,
+                    height,
+                    x: 0,
+                    y: 0,
+                }])
+                .map_err(Error::AgentChannel)
+        }
+        "clipboard-get" => {
+            let data = client
+                .get_clipboard(selection(matches), FORMAT_TEXT)
+                .map_err(Error::AgentChannel)?;
+            std::io::stdout()
+                .write_all(&data)
+                .map_err(Error::WritingStdout)
+        }
+        "clipboard-set" => {
+            let text = matches.get_one::<String>("text").unwrap();
+            client
+                .set_clipboard(selection(matches), FORMAT_TEXT, text.as_bytes())
+                .map_err(Error::AgentChannel)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn connect_http_api(path: &str, timeout: Option<Duration>) -> std::io::Result<UnixStream> {
+    let socket = UnixStream::connect(path)?;
+    socket.set_read_timeout(timeout)?;
+    socket.set_write_timeout(timeout)?;
+    Ok(socket)
+}
+
+fn agent_socket_arg() -> Arg {
+    Arg::new("socket")
+        .index(1)
+        .required(true)
+        .help("<agent_socket_path>")
+}
+
+fn agent_primary_arg() -> Arg {
+    Arg::new("primary")
+        .long("primary")
+        .help("Use the primary selection instead of the clipboard")
+        .action(ArgAction::SetTrue)
+        .num_args(0)
+}
+
 fn main() {
     let app = Command::new("ch-remote")
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -915,6 +1531,10 @@ fn main() {
                 .long("api-socket")
                 .help("HTTP API socket path (UNIX domain socket).")
                 .num_args(1),
+            Arg::new("timeout")
+                .long("timeout")
+                .help("Timeout in seconds of the HTTP API requests.")
+                .num_args(1),
             #[cfg(feature = "dbus_api")]
             Arg::new("dbus-service-name")
                 .long("dbus-service-name")
@@ -987,6 +1607,15 @@ fn main() {
                     .help(vmm::config::VdpaConfig::SYNTAX),
             ),
         )
+        .subcommand(
+            Command::new("add-console")
+                .about("Add virtio-console port")
+                .arg(
+                    Arg::new("console_port_config")
+                        .index(1)
+                        .help(vmm::config::ConsolePortConfig::SYNTAX),
+                ),
+        )
         .subcommand(
             Command::new("add-vsock").about("Add vsock device").arg(
                 Arg::new("vsock_config")
@@ -997,11 +1626,179 @@ fn main() {
         .subcommand(
             Command::new("remove-device")
                 .about("Remove VFIO and PCI device")
-                .arg(Arg::new("id").index(1).help("<device_id>")),
+                .arg(Arg::new("id").index(1).help("<device_id>"))
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .help("Time given to the guest to eject the device, in milliseconds")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("surprise_removal")
+                        .long("surprise-removal")
+                        .help("Forcefully remove the device if the timeout expires")
+                        .requires("timeout")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("add-vsock-forward")
+                .about("Forward a vsock port to or from a host TCP address")
+                .arg(Arg::new("port").index(1).help("<guest_port>"))
+                .arg(Arg::new("address").index(2).help("<host_tcp_address>"))
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .help("Forward host connections to the address to the guest port")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("remove-vsock-forward")
+                .about("Remove a vsock forwarding rule")
+                .arg(Arg::new("port").index(1).help("<guest_port>"))
+                .arg(Arg::new("address").index(2).help("<host_tcp_address>"))
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .help("Remove a rule added with --listen")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(Command::new("unplug-status").about("Status of the device removals"))
+        .subcommand(Command::new("time-info").about("Guest clock and TSC information"))
+        .subcommand(
+            Command::new("time-adjust")
+                .about("Move the guest clock forward")
+                .arg(
+                    Arg::new("delta_ns")
+                        .long("delta-ns")
+                        .help("Amount of time to add to the guest clock, in nanoseconds")
+                        .num_args(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("step_ns")
+                        .long("step-ns")
+                        .help("Largest adjustment applied at once, in nanoseconds")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("interval_ms")
+                        .long("interval-ms")
+                        .help("Time between two adjustment steps, in milliseconds")
+                        .num_args(1)
+                        .default_value("1000"),
+                ),
+        )
+        .subcommand(
+            Command::new("inject-error")
+                .about("Report a hardware error to the guest")
+                .arg(
+                    Arg::new("type")
+                        .long("type")
+                        .help("Kind of hardware error")
+                        .num_args(1)
+                        .value_parser(["memory"])
+                        .default_value("memory"),
+                )
+                .arg(
+                    Arg::new("gpa")
+                        .long("gpa")
+                        .help("Guest physical address affected by the error")
+                        .num_args(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("severity")
+                        .long("severity")
+                        .help("Severity of the error")
+                        .num_args(1)
+                        .value_parser(["corrected", "recoverable", "fatal"])
+                        .default_value("corrected"),
+                ),
+        )
+        .subcommand(
+            Command::new("migrate-memory")
+                .about("Move the guest memory to another host NUMA node")
+                .arg(
+                    Arg::new("host_numa_node")
+                        .index(1)
+                        .help("<host_numa_node>")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("set-zone-policy")
+                .about("Change the host NUMA policy of a memory zone")
+                .arg(Arg::new("id").index(1).help("<id>").required(true))
+                .arg(
+                    Arg::new("host_numa_node")
+                        .long("host-numa")
+                        .help("Host NUMA node the memory of the zone is moved to")
+                        .num_args(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("policy")
+                        .long("policy")
+                        .help("Policy binding the memory of the zone to the node")
+                        .num_args(1)
+                        .value_parser(["bind", "preferred"])
+                        .default_value("bind"),
+                ),
+        )
+        .subcommand(
+            Command::new("block-trace")
+                .about("Start or stop tracing the requests of a block device")
+                .arg(Arg::new("id").index(1).help("<device_id>"))
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .help("File receiving the events, tracing is stopped if omitted")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("keep-disk")
+                .about("Keep the overlay of a transient disk after the VM shuts down")
+                .arg(Arg::new("id").index(1).help("<device_id>"))
+                .arg(Arg::new("path").index(2).help("<new_image_path>")),
         )
         .subcommand(Command::new("info").about("Info on the VM"))
         .subcommand(Command::new("counters").about("Counters from the VM"))
-        .subcommand(Command::new("pause").about("Pause the VM"))
+        .subcommand(
+            Command::new("counters-shm")
+                .about("Shared memory the counters from the VM are published to"),
+        )
+        .subcommand(
+            Command::new("launch-measurement")
+                .about("Expected SEV-SNP launch measurement of the VM IGVM payload"),
+        )
+        .subcommand(
+            Command::new("snp-report")
+                .about("SEV-SNP attestation report of the VM and its certificates")
+                .arg(
+                    Arg::new("report_data")
+                        .long("report-data")
+                        .help("Hex encoded data bound into the report, up to 64 bytes")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .help("Id of the console port the guest agent listens on")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("pause").about("Pause the VM").arg(
+                Arg::new("quiesce_timeout")
+                    .long("quiesce-timeout")
+                    .help("Time given to the devices to quiesce, in milliseconds")
+                    .num_args(1),
+            ),
+        )
         .subcommand(Command::new("reboot").about("Reboot the VM"))
         .subcommand(Command::new("power-button").about("Trigger a power button in the VM"))
         .subcommand(
@@ -1046,6 +1843,15 @@ fn main() {
         .subcommand(Command::new("boot").about("Boot a created VM"))
         .subcommand(Command::new("delete").about("Delete a VM"))
         .subcommand(Command::new("shutdown").about("Shutdown the VM"))
+        .subcommand(
+            Command::new("swap-net")
+                .about("Switch the backend of a network device")
+                .arg(
+                    Arg::new("net_config")
+                        .index(1)
+                        .help(vmm::config::NetConfig::SYNTAX),
+                ),
+        )
         .subcommand(
             Command::new("snapshot")
                 .about("Create a snapshot from VM")
@@ -1098,12 +1904,111 @@ fn main() {
                 .about("Create VM from a JSON configuration")
                 .arg(Arg::new("path").index(1).default_value("-")),
         )
+        .subcommand(
+            Command::new("validate-config")
+                .about("Check a JSON VM configuration against the host without creating the VM")
+                .arg(Arg::new("path").index(1).default_value("-")),
+        )
         .subcommand(Command::new("ping").about("Ping the VMM to check for API server availability"))
         .subcommand(Command::new("shutdown-vmm").about("Shutdown the VMM"))
-        .subcommand(Command::new("nmi").about("Trigger NMI"));
+        .subcommand(
+            Command::new("set-log-level")
+                .about("Change the log filter of the VMM")
+                .arg(
+                    Arg::new("filter")
+                        .index(1)
+                        .required(true)
+                        .help("<level>,<module>=<level>,..."),
+                ),
+        )
+        .subcommand(Command::new("nmi").about("Trigger NMI"))
+        .subcommand(
+            Command::new("agent")
+                .about("Talk to the guest agent through its channel socket")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("resolution")
+                        .about("Request a new display resolution from the guest")
+                        .arg(agent_socket_arg())
+                        .arg(
+                            Arg::new("resolution")
+                                .index(2)
+                                .required(true)
+                                .help("<width>x<height>"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("clipboard-get")
+                        .about("Print the text copied in the guest")
+                        .arg(agent_socket_arg())
+                        .arg(agent_primary_arg()),
+                )
+                .subcommand(
+                    Command::new("clipboard-set")
+                        .about("Copy text to the guest, until the guest copies something else")
+                        .arg(agent_socket_arg())
+                        .arg(Arg::new("text").index(2).required(true).help("<text>"))
+                        .arg(agent_primary_arg()),
+                ),
+        )
+        .subcommand(
+            Command::new("image")
+                .about("Manage disk images")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("convert")
+                        .about("Convert a disk image to another format")
+                        .arg(
+                            Arg::new("source")
+                                .index(1)
+                                .required(true)
+                                .help("<source_image_path>"),
+                        )
+                        .arg(
+                            Arg::new("destination")
+                                .index(2)
+                                .required(true)
+                                .help("<destination_image_path>"),
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .help("Format of the destination image: raw|qcow2")
+                                .default_value("raw")
+                                .num_args(1),
+                        ),
+                ),
+        );
 
     let matches = app.get_matches();
 
+    if let Some(agent_matches) = matches.subcommand_matches("agent") {
+        if let Err(e) = agent_command(agent_matches) {
+            eprintln!("Error running command: {e}");
+            process::exit(1)
+        }
+        return;
+    }
+
+    if let Some(image_matches) = matches.subcommand_matches("image") {
+        if let Some(convert_matches) = image_matches.subcommand_matches("convert") {
+            if let Err(e) = convert_image(convert_matches) {
+                eprintln!("Error running command: {e}");
+                process::exit(1)
+            }
+        }
+        return;
+    }
+
+    let timeout = matches
+        .get_one::<String>("timeout")
+        .map(|timeout| timeout.parse::<u64>().map(Duration::from_secs))
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("Error parsing timeout: {e}");
+            process::exit(1)
+        });
+
     let mut target_api = match (
         matches.get_one::<String>("api-socket"),
         #[cfg(feature = "dbus_api")]
@@ -1113,7 +2018,7 @@ fn main() {
     ) {
         #[cfg(not(feature = "dbus_api"))]
         (Some(api_sock),) => TargetApi::HttpApi(
-            UnixStream::connect(api_sock).unwrap_or_else(|e| {
+            connect_http_api(api_sock, timeout).unwrap_or_else(|e| {
                 eprintln!("Error opening HTTP socket: {e}");
                 process::exit(1)
             }),
@@ -1121,7 +2026,7 @@ fn main() {
         ),
         #[cfg(feature = "dbus_api")]
         (Some(api_sock), None, None) => TargetApi::HttpApi(
-            UnixStream::connect(api_sock).unwrap_or_else(|e| {
+            connect_http_api(api_sock, timeout).unwrap_or_else(|e| {
                 eprintln!("Error opening HTTP socket: {e}");
                 process::exit(1)
             }),
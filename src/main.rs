@@ -24,6 +24,7 @@ use vmm::api::http::http_api_graceful_shutdown;
 use vmm::api::ApiAction;
 use vmm::config;
 use vmm::landlock::{Landlock, LandlockError};
+use vmm::log_filter::{set_log_filter, LogFilter, LogFilterError};
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::signal::block_signal;
 
@@ -40,6 +41,10 @@ enum Error {
     CreateDebugEventFd(#[source] std::io::Error),
     #[error("Failed to create exit EventFd: {0}")]
     CreateExitEventFd(#[source] std::io::Error),
+    #[error("Failed to set up the reporting of seccomp violations: {0}")]
+    ReportSeccompViolations(#[source] std::io::Error),
+    #[error("Error enabling the crash reports: {0}")]
+    CrashReport(#[source] std::io::Error),
     #[error("Failed to open hypervisor interface (is hypervisor interface available?): {0}")]
     CreateHypervisor(#[source] hypervisor::HypervisorError),
     #[error("Failed to start the VMM thread: {0}")]
@@ -84,12 +89,26 @@ enum Error {
     LogFileCreation(std::io::Error),
     #[error("Error setting up logger: {0}")]
     LoggerSetup(log::SetLoggerError),
+    #[error("Error parsing --log-filter: {0}")]
+    ParsingLogFilter(#[source] LogFilterError),
     #[error("Failed to gracefully shutdown http api: {0}")]
     HttpApiShutdown(#[source] vmm::Error),
     #[error("Failed to create Landlock object: {0}")]
     CreateLandlock(#[source] LandlockError),
     #[error("Failed to apply Landlock: {0}")]
     ApplyLandlock(#[source] LandlockError),
+    #[cfg(feature = "igvm")]
+    #[error("Error opening the igvm file: {0}")]
+    OpenIgvm(#[source] std::io::Error),
+    #[cfg(feature = "igvm")]
+    #[error("Error computing the launch measurement: {0}")]
+    LaunchMeasurement(#[source] vmm::igvm::measurement::Error),
+    #[cfg(feature = "igvm")]
+    #[error("Error validating the igvm file: {0}")]
+    ValidateIgvm(#[source] vmm::igvm::validate::Error),
+    #[cfg(feature = "igvm")]
+    #[error("The igvm file is invalid")]
+    InvalidIgvm,
 }
 
 #[derive(Error, Debug)]
@@ -107,11 +126,12 @@ enum FdTableError {
 struct Logger {
     output: Mutex<Box<dyn std::io::Write + Send>>,
     start: std::time::Instant,
+    json: bool,
 }
 
 impl log::Log for Logger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        vmm::log_filter::log_enabled(metadata)
     }
 
     fn log(&self, record: &log::Record) {
@@ -122,7 +142,18 @@ impl log::Log for Logger {
         let now = std::time::Instant::now();
         let duration = now.duration_since(self.start);
 
-        if record.file().is_some() && record.line().is_some() {
+        if self.json {
+            let line = serde_json::json!({
+                "uptime": duration.as_secs_f64(),
+                "thread": std::thread::current().name().unwrap_or("anonymous"),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "file": record.file(),
+                "line": record.line(),
+                "message": record.args().to_string(),
+            });
+            write!(*(*(self.output.lock().unwrap())), "{line}\r\n")
+        } else if record.file().is_some() && record.line().is_some() {
             write!(
                 *(*(self.output.lock().unwrap())),
                 "cloud-hypervisor: {:.6?}: <{}> {}:{}:{} -- {}\r\n",
@@ -188,7 +219,8 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
                     topology=<threads_per_core>:<cores_per_die>:<dies_per_package>:<packages>,\
                     kvm_hyperv=on|off,max_phys_bits=<maximum_number_of_physical_bits>,\
                     affinity=<list_of_vcpus_with_their_associated_cpuset>,\
-                    features=<list_of_features_to_enable>",
+                    features=<list_of_features_to_enable>,\
+                    halt_poll_ns=<halt_polling_time_in_ns>,idle_poll=on|off",
                 )
                 .default_value(default_vcpus)
                 .group("vm-config"),
@@ -196,7 +228,7 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
         .arg(
             Arg::new("platform")
                 .long("platform")
-                .help("num_pci_segments=<num_pci_segments>,iommu_segments=<list_of_segments>,serial_number=<dmi_device_serial_number>,uuid=<dmi_device_uuid>,oem_strings=<list_of_strings>")
+                .help("num_pci_segments=<num_pci_segments>,iommu_segments=<list_of_segments>,serial_number=<dmi_device_serial_number>,uuid=<dmi_device_uuid>,oem_strings=<list_of_strings>,kbs_uri=<key_broker_service_uri>,kbc=<key_broker_client_socket>,on_reset_loop=report|pause,vfio_quirks=<vfio_quirks_toml_file>,vmgenid=on|off")
                 .num_args(1)
                 .group("vm-config"),
         )
@@ -210,7 +242,8 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
                      hotplug_method=acpi|virtio-mem,\
                      hotplug_size=<hotpluggable_memory_size>,\
                      hotplugged_size=<hotplugged_memory_size>,\
-                     prefault=on|off,thp=on|off\"",
+                     prefault=on|off,thp=on|off,auto_numa=on|off,\
+                     coredump=on|off\"",
                 )
                 .default_value(default_memory)
                 .group("vm-config"),
@@ -223,7 +256,7 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
                      \"size=<guest_memory_region_size>,file=<backing_file>,\
                      shared=on|off,\
                      hugepages=on|off,hugepage_size=<hugepage_size>,\
-                     host_numa_node=<node_id>,\
+                     host_numa_node=<node_id>,host_numa_policy=bind|preferred,\
                      id=<zone_identifier>,hotplug_size=<hotpluggable_memory_size>,\
                      hotplugged_size=<hotplugged_memory_size>,\
                      prefault=on|off\"",
@@ -324,6 +357,13 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
                 .num_args(1..)
                 .group("vm-config"),
         )
+        .arg(
+            Arg::new("gpu")
+                .long("gpu")
+                .help(config::GpuConfig::SYNTAX)
+                .num_args(1)
+                .group("vm-config"),
+        )
         .arg(
             Arg::new("pmem")
                 .long("pmem")
@@ -334,7 +374,7 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
         .arg(
             Arg::new("serial")
                 .long("serial")
-                .help("Control serial port: off|null|pty|tty|file=</path/to/a/file>|socket=</path/to/a/file>")
+                .help("Control serial port: off|null|pty|tty|file=</path/to/a/file>|socket=</path/to/a/file>|tcp=<host:port>|pipe=</path/to/a/device>,log_socket=</path/to/log/shipper/socket>")
                 .default_value("null")
                 .group("vm-config"),
         )
@@ -342,7 +382,7 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
             Arg::new("console")
                 .long("console")
                 .help(
-                    "Control (virtio) console: \"off|null|pty|tty|file=</path/to/a/file>,iommu=on|off\"",
+                    "Control (virtio) console: \"off|null|pty|tty|file=</path/to/a/file>|socket=</path/to/a/file>|tcp=<host:port>|pipe=</path/to/a/device>,iommu=on|off\"",
                 )
                 .default_value("tty")
                 .group("vm-config"),
@@ -419,6 +459,25 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
                 .num_args(1)
                 .group("logging"),
         )
+        .arg(
+            Arg::new("log-filter")
+                .long("log-filter")
+                .help(
+                    "Per module log levels, overriding the one set by -v: \
+                     <level>,<module>=<level>,...",
+                )
+                .num_args(1)
+                .group("logging"),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .help("Format of the log records")
+                .num_args(1)
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .group("logging"),
+        )
         .arg(
             Arg::new("api-socket")
                 .long("api-socket")
@@ -433,6 +492,13 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
                 .num_args(1)
                 .group("vmm-config"),
         )
+        .arg(
+            Arg::new("crash-dir")
+                .long("crash-dir")
+                .help("Directory to write a report to when the VMM crashes")
+                .num_args(1)
+                .group("vmm-config"),
+        )
         .arg(
             Arg::new("restore")
                 .long("restore")
@@ -444,7 +510,7 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
             Arg::new("seccomp")
                 .long("seccomp")
                 .num_args(1)
-                .value_parser(["true", "false", "log"])
+                .value_parser(["true", "false", "log", "report"])
                 .default_value("true"),
         )
         .arg(
@@ -453,6 +519,13 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
                 .num_args(1)
                 .help(config::TpmConfig::SYNTAX)
                 .group("vmm-config"),
+        )
+        .arg(
+            Arg::new("restart-policy")
+                .long("restart-policy")
+                .num_args(1)
+                .help(config::RestartPolicyConfig::SYNTAX)
+                .group("vm-config"),
         );
 
     #[cfg(target_arch = "x86_64")]
@@ -464,11 +537,20 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
             .group("vm-config"),
     );
 
+    #[cfg(target_arch = "aarch64")]
+    let app = app.arg(
+        Arg::new("fdt-overlay")
+            .long("fdt-overlay")
+            .help(config::FdtOverlayConfig::SYNTAX)
+            .num_args(1..)
+            .group("vm-config"),
+    );
+
     #[cfg(target_arch = "x86_64")]
     let app = app.arg(
         Arg::new("debug-console")
             .long("debug-console")
-            .help("Debug console: off|pty|tty|file=</path/to/a/file>,iobase=<port in hex>")
+            .help("Debug console: off|pty|tty|file=</path/to/a/file>|pipe=</path/to/a/device>,iobase=<port in hex>,timestamps=on|off")
             .default_value("off,iobase=0xe9")
             .group("vm-config"),
     );
@@ -507,18 +589,59 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
                 .group("vmm-config"),
         );
     #[cfg(feature = "igvm")]
+    let app = app
+        .arg(
+            Arg::new("igvm")
+                .long("igvm")
+                .help("Path to IGVM file to load.")
+                .num_args(1)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("igvm-compatibility-mask")
+                .long("igvm-compatibility-mask")
+                .help("Compatibility mask of the IGVM platform to load")
+                .num_args(1)
+                .requires("igvm")
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("print-launch-measurement")
+                .long("print-launch-measurement")
+                .help("Print the expected SEV-SNP launch measurement of the IGVM file and exit")
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .requires("igvm"),
+        )
+        .arg(
+            Arg::new("igvm-check")
+                .long("igvm-check")
+                .help("Validate the IGVM file against the memory configuration and exit")
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .requires("igvm"),
+        );
+    #[cfg(feature = "sev_snp")]
     let app = app.arg(
-        Arg::new("igvm")
-            .long("igvm")
-            .help("Path to IGVM file to load.")
+        Arg::new("host-data")
+            .long("host-data")
+            .help("Host specific data to SEV SNP guest")
             .num_args(1)
             .group("vm-config"),
     );
     #[cfg(feature = "sev_snp")]
     let app = app.arg(
-        Arg::new("host-data")
-            .long("host-data")
-            .help("Host specific data to SEV SNP guest")
+        Arg::new("sev-snp")
+            .long("sev-snp")
+            .help(config::SevSnpConfig::SYNTAX)
+            .num_args(1)
+            .group("vm-config"),
+    );
+    #[cfg(feature = "mdns")]
+    let app = app.arg(
+        Arg::new("mdns")
+            .long("mdns")
+            .help(config::MdnsConfig::SYNTAX)
             .num_args(1)
             .group("vm-config"),
     );
@@ -533,6 +656,13 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
     );
 
     app.arg(
+        Arg::new("self-test")
+            .long("self-test")
+            .help("Check the host is able to run VMs, boot a built-in test guest and exit")
+            .num_args(0)
+            .action(ArgAction::SetTrue),
+    )
+    .arg(
         Arg::new("version")
             .short('V')
             .long("version")
@@ -542,6 +672,61 @@ fn create_app(default_vcpus: String, default_memory: String, default_rng: String
     )
 }
 
+#[cfg(feature = "igvm")]
+fn print_launch_measurement(cmd_arguments: &ArgMatches) -> Result<(), Error> {
+    let igvm = cmd_arguments.get_one::<String>("igvm").unwrap();
+    let igvm = std::fs::File::open(igvm).map_err(Error::OpenIgvm)?;
+    // The pages left unaccepted by the launch are not measured.
+    #[cfg(feature = "sev_snp")]
+    let lazy_unmeasured = cmd_arguments
+        .get_one::<String>("sev-snp")
+        .map(|sev_snp| config::SevSnpConfig::parse(sev_snp))
+        .transpose()
+        .map_err(Error::ParsingConfig)?
+        .is_some_and(|sev_snp| sev_snp.lazy_unmeasured);
+    #[cfg(not(feature = "sev_snp"))]
+    let lazy_unmeasured = false;
+    let measurement = vmm::igvm::measurement::snp_launch_measurement(&igvm, lazy_unmeasured)
+        .map_err(Error::LaunchMeasurement)?;
+    println!("{}", serde_json::to_string_pretty(&measurement).unwrap());
+
+    Ok(())
+}
+
+#[cfg(feature = "igvm")]
+fn check_igvm(cmd_arguments: &ArgMatches) -> Result<(), Error> {
+    let igvm = cmd_arguments.get_one::<String>("igvm").unwrap();
+    let igvm = std::fs::File::open(igvm).map_err(Error::OpenIgvm)?;
+
+    // The RAM the VM boots with, the hotpluggable memory left aside.
+    let memory = config::MemoryConfig::parse(
+        cmd_arguments.get_one::<String>("memory").unwrap(),
+        cmd_arguments
+            .get_many::<String>("memory-zone")
+            .map(|zones| zones.map(|zone| zone as &str).collect()),
+    )
+    .map_err(Error::ParsingConfig)?;
+    let ram_size = memory.size
+        + memory
+            .zones
+            .iter()
+            .flatten()
+            .map(|zone| zone.size)
+            .sum::<u64>();
+
+    let errors = vmm::igvm::validate::validate_igvm(&igvm, None, Some(ram_size))
+        .map_err(Error::ValidateIgvm)?;
+    if !errors.is_empty() {
+        for error in errors {
+            eprintln!("{error}");
+        }
+        return Err(Error::InvalidIgvm);
+    }
+    println!("The igvm file is valid");
+
+    Ok(())
+}
+
 fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
     let log_level = match cmd_arguments.get_count("v") {
         0 => LevelFilter::Warn,
@@ -550,6 +735,19 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         _ => LevelFilter::Trace,
     };
 
+    let log_filter = LogFilter::parse(
+        cmd_arguments
+            .get_one::<String>("log-filter")
+            .map(String::as_str)
+            .unwrap_or_default(),
+        log_level,
+    )
+    .map_err(Error::ParsingLogFilter)?;
+
+    let log_json = cmd_arguments
+        .get_one::<String>("log-format")
+        .is_some_and(|format| format == "json");
+
     let log_file: Box<dyn std::io::Write + Send> = if let Some(ref file) =
         cmd_arguments.get_one::<String>("log-file")
     {
@@ -561,8 +759,9 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
     log::set_boxed_logger(Box::new(Logger {
         output: Mutex::new(log_file),
         start: std::time::Instant::now(),
+        json: log_json,
     }))
-    .map(|()| log::set_max_level(log_level))
+    .map(|()| set_log_filter(log_filter))
     .map_err(Error::LoggerSetup)?;
 
     let (api_socket_path, api_socket_fd) =
@@ -599,6 +798,7 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
             "true" => SeccompAction::Trap,
             "false" => SeccompAction::Allow,
             "log" => SeccompAction::Log,
+            "report" => SeccompAction::Trap,
             val => {
                 // The user providing an invalid value will be rejected
                 panic!("Invalid parameter {val} for \"--seccomp\" flag");
@@ -608,7 +808,12 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         SeccompAction::Trap
     };
 
-    if seccomp_action == SeccompAction::Trap {
+    // Reported violations are handled once the VMM can be asked to exit.
+    let report_seccomp_violations = cmd_arguments
+        .get_one::<String>("seccomp")
+        .is_some_and(|seccomp| seccomp == "report");
+
+    if seccomp_action == SeccompAction::Trap && !report_seccomp_violations {
         // SAFETY: We only using signal_hook for managing signals and only execute signal
         // handler safe functions (writing to stderr) and manipulating signals.
         unsafe {
@@ -667,6 +872,12 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
     let vm_debug_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::CreateDebugEventFd)?;
 
     let exit_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::CreateExitEventFd)?;
+    if report_seccomp_violations {
+        vmm::seccomp_report::enable(&exit_evt).map_err(Error::ReportSeccompViolations)?;
+    }
+    if let Some(crash_dir) = cmd_arguments.get_one::<String>("crash-dir") {
+        vmm::crash_report::enable(crash_dir.into()).map_err(Error::CrashReport)?;
+    }
     let landlock_enable = cmd_arguments.get_flag("landlock");
 
     #[allow(unused_mut)]
@@ -934,6 +1145,30 @@ fn main() {
         return;
     }
 
+    if cmd_arguments.get_flag("self-test") {
+        let report = vmm::self_test::run();
+        print!("{report}");
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
+
+    #[cfg(feature = "igvm")]
+    if cmd_arguments.get_flag("print-launch-measurement") {
+        if let Err(e) = print_launch_measurement(&cmd_arguments) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    #[cfg(feature = "igvm")]
+    if cmd_arguments.get_flag("igvm-check") {
+        if let Err(e) = check_igvm(&cmd_arguments) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     if let Err(e) = expand_fdtable() {
         warn!("Error expanding FD table: {e}");
     }
@@ -1011,6 +1246,9 @@ mod unit_tests {
                 max_phys_bits: 46,
                 affinity: None,
                 features: CpuFeatures::default(),
+                halt_poll_ns: None,
+                #[cfg(target_arch = "x86_64")]
+                idle_poll: false,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
@@ -1024,6 +1262,8 @@ mod unit_tests {
                 prefault: false,
                 zones: None,
                 thp: true,
+                auto_numa: false,
+                coredump: false,
             },
             payload: Some(PayloadConfig {
                 kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -1032,6 +1272,8 @@ mod unit_tests {
                 initramfs: None,
                 #[cfg(feature = "igvm")]
                 igvm: None,
+                #[cfg(feature = "igvm")]
+                igvm_compatibility_mask: None,
                 #[cfg(feature = "sev_snp")]
                 host_data: None,
             }),
@@ -1044,19 +1286,25 @@ mod unit_tests {
             },
             balloon: None,
             fs: None,
+            gpu: None,
             pmem: None,
             serial: ConsoleConfig {
                 file: None,
                 mode: ConsoleOutputMode::Null,
                 iommu: false,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             },
             console: ConsoleConfig {
                 file: None,
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             },
+            console_ports: None,
             #[cfg(target_arch = "x86_64")]
             debug_console: DebugConsoleConfig::default(),
             devices: None,
@@ -1069,13 +1317,20 @@ mod unit_tests {
             iommu: false,
             #[cfg(target_arch = "x86_64")]
             sgx_epc: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays: None,
             numa: None,
             watchdog: false,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             pci_segments: None,
             platform: None,
+            #[cfg(feature = "sev_snp")]
+            sev_snp: None,
             tpm: None,
+            restart_policy: None,
+            #[cfg(feature = "mdns")]
+            mdns: None,
             preserved_fds: None,
             landlock_enable: false,
             landlock_rules: None,
@@ -1698,21 +1953,38 @@ mod unit_tests {
     #[cfg(target_arch = "x86_64")]
     #[test]
     fn test_valid_vm_config_debug_console() {
-        [(
-            vec![
-                "cloud-hypervisor",
-                "--kernel",
-                "/path/to/kernel",
-                "--debug-console",
-                "tty,iobase=0xe9",
-            ],
-            // 233 == 0xe9
-            r#"{
-                "payload": {"kernel": "/path/to/kernel" },
-                "debug_console": {"mode": "Tty", "iobase": 233 }
-            }"#,
-            true,
-        )]
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--debug-console",
+                    "tty,iobase=0xe9",
+                ],
+                // 233 == 0xe9
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel" },
+                    "debug_console": {"mode": "Tty", "iobase": 233 }
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--debug-console",
+                    "tty,iobase=0x402,timestamps=on",
+                ],
+                // 1026 == 0x402
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel" },
+                    "debug_console": {"mode": "Tty", "iobase": 1026, "timestamps": true }
+                }"#,
+                true,
+            ),
+        ]
         .iter()
         .for_each(|(cli, openapi, equal)| {
             compare_vm_config_cli_vs_json(cli, openapi, *equal);
@@ -2044,6 +2316,28 @@ mod unit_tests {
         });
     }
 
+    #[test]
+    fn test_valid_vm_config_restart_policy() {
+        [(
+            vec![
+                "cloud-hypervisor",
+                "--kernel",
+                "/path/to/kernel",
+                "--restart-policy",
+                "policy=always,max_reboots=5",
+            ],
+            r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "restart_policy": {"policy": "always", "max_reboots": 5}
+                }"#,
+            true,
+        )]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
     #[test]
     fn test_valid_vm_config_tpm_socket() {
         [(
@@ -16,3 +16,70 @@ pub use tracer_noop::*;
 mod tracer;
 #[cfg(feature = "tracing")]
 pub use tracer::*;
+
+#[cfg(feature = "usdt")]
+mod usdt;
+
+/// Static USDT probe `cloud_hypervisor:<name>`, taking up to 6 integer
+/// arguments. Built with the "usdt" feature, the probe is a single `nop`
+/// instruction until a tracer such as bpftrace attaches to it. Without the
+/// feature it is compiled out, the arguments not being evaluated.
+#[cfg(feature = "usdt")]
+#[macro_export]
+macro_rules! usdt {
+    ($name:ident $(,)?) => {
+        $crate::__usdt_probe!($name, "")
+    };
+    ($name:ident, $a0:expr $(,)?) => {
+        $crate::__usdt_probe!($name, "8@{a0}", a0 = $a0)
+    };
+    ($name:ident, $a0:expr, $a1:expr $(,)?) => {
+        $crate::__usdt_probe!($name, "8@{a0} 8@{a1}", a0 = $a0, a1 = $a1)
+    };
+    ($name:ident, $a0:expr, $a1:expr, $a2:expr $(,)?) => {
+        $crate::__usdt_probe!($name, "8@{a0} 8@{a1} 8@{a2}", a0 = $a0, a1 = $a1, a2 = $a2)
+    };
+    ($name:ident, $a0:expr, $a1:expr, $a2:expr, $a3:expr $(,)?) => {
+        $crate::__usdt_probe!(
+            $name,
+            "8@{a0} 8@{a1} 8@{a2} 8@{a3}",
+            a0 = $a0,
+            a1 = $a1,
+            a2 = $a2,
+            a3 = $a3
+        )
+    };
+    ($name:ident, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr $(,)?) => {
+        $crate::__usdt_probe!(
+            $name,
+            "8@{a0} 8@{a1} 8@{a2} 8@{a3} 8@{a4}",
+            a0 = $a0,
+            a1 = $a1,
+            a2 = $a2,
+            a3 = $a3,
+            a4 = $a4
+        )
+    };
+    ($name:ident, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr $(,)?) => {
+        $crate::__usdt_probe!(
+            $name,
+            "8@{a0} 8@{a1} 8@{a2} 8@{a3} 8@{a4} 8@{a5}",
+            a0 = $a0,
+            a1 = $a1,
+            a2 = $a2,
+            a3 = $a3,
+            a4 = $a4,
+            a5 = $a5
+        )
+    };
+}
+
+#[cfg(not(feature = "usdt"))]
+#[macro_export]
+macro_rules! usdt {
+    ($name:ident $(, $arg:expr)* $(,)?) => {
+        if false {
+            $(let _ = &$arg;)*
+        }
+    };
+}
@@ -0,0 +1,73 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// The probes follow the SystemTap SDT layout: a `nop` at the probe site, and
+// a note in the `.note.stapsdt` section giving its address, the provider and
+// probe names, and where each argument can be found when the probe is hit
+// (e.g. `8@%rax` for a 64 bits value in rax). The `.stapsdt.base` section
+// lets the tracers compute the actual addresses when the binary is
+// relocated. See https://sourceware.org/systemtap/wiki/UserSpaceProbeImplementation
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __usdt_note {
+    ($name:ident, $args:literal) => {
+        concat!(
+            "990: nop\n",
+            ".pushsection .note.stapsdt, \"?\", \"note\"\n",
+            ".balign 4\n",
+            ".4byte 992f-991f, 994f-993f, 3\n",
+            "991: .asciz \"stapsdt\"\n",
+            "992: .balign 4\n",
+            "993: .8byte 990b\n",
+            ".8byte _.stapsdt.base\n",
+            // No semaphore, the probe is always enabled.
+            ".8byte 0\n",
+            ".asciz \"cloud_hypervisor\"\n",
+            ".asciz \"",
+            stringify!($name),
+            "\"\n",
+            ".asciz \"",
+            $args,
+            "\"\n",
+            "994: .balign 4\n",
+            ".popsection\n",
+            ".ifndef _.stapsdt.base\n",
+            ".pushsection .stapsdt.base, \"aG\", \"progbits\", .stapsdt.base, comdat\n",
+            ".weak _.stapsdt.base\n",
+            ".hidden _.stapsdt.base\n",
+            ".set _.stapsdt.base, .\n",
+            ".space 1\n",
+            ".size _.stapsdt.base, 1\n",
+            ".popsection\n",
+            ".endif",
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __usdt_probe {
+    ($name:ident, $args:literal $(, $op:ident = $arg:expr)*) => {
+        // SAFETY: the probe is a nop instruction, the note only describes it
+        // and the operands are only read by the tracers.
+        unsafe {
+            // The AT&T syntax gives the `%reg` operands expected by the
+            // tracers on x86_64.
+            #[cfg(target_arch = "x86_64")]
+            ::core::arch::asm!(
+                $crate::__usdt_note!($name, $args),
+                $($op = in(reg) ($arg) as u64,)*
+                options(att_syntax, nomem, nostack, preserves_flags)
+            );
+            #[cfg(target_arch = "aarch64")]
+            ::core::arch::asm!(
+                $crate::__usdt_note!($name, $args),
+                $($op = in(reg) ($arg) as u64,)*
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+    };
+}
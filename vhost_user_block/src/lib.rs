@@ -29,7 +29,7 @@ use std::result;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
 use std::time::Instant;
-use std::{convert, error, fmt, io};
+use std::{cmp, convert, error, fmt, io};
 use vhost::vhost_user::message::*;
 use vhost::vhost_user::Listener;
 use vhost_user_backend::{
@@ -403,8 +403,10 @@ impl VhostUserBackendMut for VhostUserBlkBackend {
         }
     }
 
-    fn get_config(&self, _offset: u32, _size: u32) -> Vec<u8> {
-        self.config.as_slice().to_vec()
+    fn get_config(&self, _offset: u32, size: u32) -> Vec<u8> {
+        // Frontends unaware of the secure erase fields ask for less.
+        let config = self.config.as_slice();
+        config[..cmp::min(size as usize, config.len())].to_vec()
     }
 
     fn set_config(&mut self, offset: u32, data: &[u8]) -> result::Result<(), io::Error> {
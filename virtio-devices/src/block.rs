@@ -18,25 +18,31 @@ use crate::thread_helper::spawn_virtio_thread;
 use crate::GuestMemoryMmap;
 use crate::VirtioInterrupt;
 use anyhow::anyhow;
+use block::fcntl::{ImageLock, LockError};
+use block::qos::IoPriority;
 use block::{
     async_io::AsyncIo, async_io::AsyncIoError, async_io::DiskFile, build_serial, Request,
-    RequestType, VirtioBlockConfig,
+    RequestType, VirtioBlockConfig, VirtioBlockLifetime, VIRTIO_BLK_F_LIFETIME,
+    VIRTIO_BLK_F_SECURE_ERASE,
 };
 use rate_limiter::group::{RateLimiterGroup, RateLimiterGroupHandle};
 use rate_limiter::TokenType;
 use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::collections::HashMap;
 use std::collections::VecDeque;
-use std::io;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::num::Wrapping;
 use std::ops::Deref;
 use std::os::unix::io::AsRawFd;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Barrier};
+use std::sync::{Arc, Barrier, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use virtio_bindings::virtio_blk::*;
 use virtio_bindings::virtio_config::*;
@@ -46,20 +52,55 @@ use vm_memory::{ByteValued, Bytes, GuestAddressSpace, GuestMemoryAtomic, GuestMe
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
 use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::timerfd::TimerFd;
 
 const SECTOR_SHIFT: u8 = 9;
 pub const SECTOR_SIZE: u64 = 0x01 << SECTOR_SHIFT;
 
+// Interval between two checks of the in-flight requests while quiescing.
+const QUIESCE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
 // New descriptors are pending on the virtio queue.
 const QUEUE_AVAIL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 // New completed tasks are pending on the completion ring.
 const COMPLETION_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 // New 'wake up' event from the rate limiter
 const RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+// Periodic check of the requests exceeding the IO timeout.
+const TIMEOUT_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
+// The device got resumed.
+const RESUME_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
 
 // latency scale, for reduce precision loss in calculate.
 const LATENCY_SCALE: u64 = 10000;
 
+// Upper bounds (in microseconds) of the latency histogram buckets. Requests
+// slower than the last bound are accounted in an extra overflow bucket.
+const LATENCY_BUCKET_BOUNDS: [u64; 8] = [16, 64, 256, 1024, 4096, 16384, 65536, 262144];
+const LATENCY_BUCKETS: usize = LATENCY_BUCKET_BOUNDS.len() + 1;
+const READ_LATENCY_BUCKET_NAMES: [&str; LATENCY_BUCKETS] = [
+    "read_latency_le_16us",
+    "read_latency_le_64us",
+    "read_latency_le_256us",
+    "read_latency_le_1024us",
+    "read_latency_le_4096us",
+    "read_latency_le_16384us",
+    "read_latency_le_65536us",
+    "read_latency_le_262144us",
+    "read_latency_overflow",
+];
+const WRITE_LATENCY_BUCKET_NAMES: [&str; LATENCY_BUCKETS] = [
+    "write_latency_le_16us",
+    "write_latency_le_64us",
+    "write_latency_le_256us",
+    "write_latency_le_1024us",
+    "write_latency_le_4096us",
+    "write_latency_le_16384us",
+    "write_latency_le_65536us",
+    "write_latency_le_262144us",
+    "write_latency_overflow",
+];
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Failed to parse the request: {0}")]
@@ -70,8 +111,6 @@ pub enum Error {
     RequestCompleting(block::Error),
     #[error("Missing the expected entry in the list of requests")]
     MissingEntryRequestList,
-    #[error("The asynchronous request returned with failure")]
-    AsyncRequestFailure,
     #[error("Failed synchronizing the file: {0}")]
     Fsync(AsyncIoError),
     #[error("Failed adding used index: {0}")]
@@ -86,6 +125,37 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Action taken when a request fails or exceeds the IO timeout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnIoError {
+    /// Complete the request with `VIRTIO_BLK_S_IOERR`, letting the guest
+    /// deal with the failure.
+    #[default]
+    #[serde(rename = "report")]
+    Report,
+    /// Pause the VM, the failed requests being submitted again on resume.
+    #[serde(rename = "pause")]
+    Pause,
+}
+
+#[derive(Error, Debug)]
+pub enum ParseOnIoErrorError {
+    #[error("Invalid IO error policy: {0}")]
+    InvalidValue(String),
+}
+
+impl FromStr for OnIoError {
+    type Err = ParseOnIoErrorError;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "report" => Ok(OnIoError::Report),
+            "pause" => Ok(OnIoError::Pause),
+            _ => Err(ParseOnIoErrorError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
 // latency will be records as microseconds, average latency
 // will be save as scaled value.
 #[derive(Clone)]
@@ -100,6 +170,13 @@ pub struct BlockCounters {
     write_latency_min: Arc<AtomicU64>,
     write_latency_max: Arc<AtomicU64>,
     write_latency_avg: Arc<AtomicU64>,
+    read_latency_hist: Arc<[AtomicU64; LATENCY_BUCKETS]>,
+    write_latency_hist: Arc<[AtomicU64; LATENCY_BUCKETS]>,
+    inflight_requests: Arc<AtomicU64>,
+    inflight_requests_max: Arc<AtomicU64>,
+    // Failed requests held until the VM is resumed, not part of the
+    // in-flight ones as they aren't pending on the backend.
+    failed_requests: Arc<AtomicU64>,
 }
 
 impl Default for BlockCounters {
@@ -115,11 +192,128 @@ impl Default for BlockCounters {
             write_latency_min: Arc::new(AtomicU64::new(u64::MAX)),
             write_latency_max: Arc::new(AtomicU64::new(u64::MAX)),
             write_latency_avg: Arc::new(AtomicU64::new(u64::MAX)),
+            read_latency_hist: Arc::new(std::array::from_fn(|_| AtomicU64::new(0))),
+            write_latency_hist: Arc::new(std::array::from_fn(|_| AtomicU64::new(0))),
+            inflight_requests: Arc::new(AtomicU64::new(0)),
+            inflight_requests_max: Arc::new(AtomicU64::new(0)),
+            failed_requests: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+fn latency_bucket(latency: u64) -> usize {
+    LATENCY_BUCKET_BOUNDS
+        .iter()
+        .position(|bound| latency <= *bound)
+        .unwrap_or(LATENCY_BUCKET_BOUNDS.len())
+}
+
+#[derive(Clone, Copy)]
+enum TraceAction {
+    // The request has been fetched from the virtqueue.
+    Queued,
+    // The request has been submitted to the backend.
+    Issued,
+    // The backend completed the request.
+    Completed,
+}
+
+impl TraceAction {
+    fn as_char(self) -> char {
+        match self {
+            TraceAction::Queued => 'Q',
+            TraceAction::Issued => 'D',
+            TraceAction::Completed => 'C',
+        }
+    }
+}
+
+struct TraceSink {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+/// Stream of per-request events in a blktrace-like text format, one event
+/// per line:
+///
+/// `<seconds>.<nanoseconds> <queue> <action> <rwbs> <sector> + <sectors>`
+///
+/// The action is `Q` when the request is fetched from the virtqueue, `D` when
+/// it is submitted to the backend and `C` when the backend completes it. The
+/// time between `Q` and `D` is spent in the VMM, while the time between `D`
+/// and `C` is spent in the host.
+#[derive(Clone, Default)]
+pub struct BlockTracer {
+    enabled: Arc<AtomicBool>,
+    sink: Arc<Mutex<Option<TraceSink>>>,
+}
+
+impl BlockTracer {
+    /// Start streaming the events to the given file.
+    pub fn start(&self, file: File) -> io::Result<()> {
+        let mut sink = self.sink.lock().unwrap();
+        if let Some(mut previous) = sink.take() {
+            previous.writer.flush()?;
+        }
+        *sink = Some(TraceSink {
+            writer: BufWriter::new(file),
+            start: Instant::now(),
+        });
+        self.enabled.store(true, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Stop streaming the events, flushing the pending ones.
+    pub fn stop(&self) -> io::Result<()> {
+        self.enabled.store(false, Ordering::Release);
+        if let Some(mut sink) = self.sink.lock().unwrap().take() {
+            sink.writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn trace(&self, queue_index: u16, action: TraceAction, request: &Request) {
+        if !self.enabled.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut sink = self.sink.lock().unwrap();
+        let Some(sink) = sink.as_mut() else {
+            return;
+        };
+
+        let rwbs = match request.request_type {
+            RequestType::In => 'R',
+            RequestType::Out => 'W',
+            RequestType::Flush => 'F',
+            _ => 'N',
+        };
+        let len: u64 = request
+            .data_descriptors
+            .iter()
+            .map(|(_, len)| *len as u64)
+            .sum();
+        let elapsed = sink.start.elapsed();
+        if let Err(e) = writeln!(
+            sink.writer,
+            "{}.{:09} {} {} {} {} + {}",
+            elapsed.as_secs(),
+            elapsed.subsec_nanos(),
+            queue_index,
+            action.as_char(),
+            rwbs,
+            request.sector,
+            len / SECTOR_SIZE
+        ) {
+            warn!("Failed writing block trace event: {}", e);
         }
     }
 }
 
 struct BlockEpollHandler {
+    id: String,
     queue_index: u16,
     queue: Queue,
     mem: GuestMemoryAtomic<GuestMemoryMmap>,
@@ -127,23 +321,54 @@ struct BlockEpollHandler {
     disk_nsectors: u64,
     interrupt_cb: Arc<dyn VirtioInterrupt>,
     serial: Vec<u8>,
+    lifetime: VirtioBlockLifetime,
     kill_evt: EventFd,
     pause_evt: EventFd,
     writeback: Arc<AtomicBool>,
     counters: BlockCounters,
+    tracer: BlockTracer,
     queue_evt: EventFd,
-    inflight_requests: VecDeque<(u16, Request)>,
+    inflight_requests: VecDeque<(u64, Request)>,
     rate_limiter: Option<RateLimiterGroupHandle>,
     access_platform: Option<Arc<dyn AccessPlatform>>,
     read_only: bool,
     host_cpus: Option<Vec<usize>>,
+    io_priority: Option<IoPriority>,
+    io_timeout: Option<Duration>,
+    timeout_timer: Option<TimerFd>,
+    on_io_error: OnIoError,
+    vm_pause_evt: EventFd,
+    resume_evt: EventFd,
+    // In-flight requests which exceeded the IO timeout and are being
+    // cancelled. They are completed to the guest once the backend gives
+    // them back.
+    timed_out_requests: HashSet<u64>,
+    // Timed out requests the backend failed to cancel, the cancellation
+    // being retried on the next check.
+    uncancelled_requests: HashSet<u64>,
+    // Requests which failed while the policy is to pause the VM, submitted
+    // again once the VM is resumed.
+    failed_requests: Vec<(u64, Request)>,
+    // Set when the VM has been asked to pause because of an IO error, until
+    // it gets resumed.
+    io_error_paused: bool,
+    // Timeouts are not accounted before this instant, as the requests can't
+    // make progress while the VM is paused.
+    timeout_base: Instant,
 }
 
 impl BlockEpollHandler {
     fn process_queue_submit(&mut self) -> Result<()> {
+        // Nothing gets submitted until the VM paused because of an IO error
+        // is resumed.
+        if self.io_error_paused {
+            return Ok(());
+        }
+
         let queue = &mut self.queue;
 
         while let Some(mut desc_chain) = queue.pop_descriptor_chain(self.mem.memory()) {
+            tracer::usdt!(virtio_blk_pop, self.queue_index, desc_chain.head_index());
             let mut request = Request::parse(&mut desc_chain, self.access_platform.as_ref())
                 .map_err(Error::RequestParsing)?;
 
@@ -151,8 +376,10 @@ impl BlockEpollHandler {
             // "A device MUST set the status byte to VIRTIO_BLK_S_IOERR for a write request
             // if the VIRTIO_BLK_F_RO feature if offered, and MUST NOT write any data."
             if self.read_only
-                && (request.request_type == RequestType::Out
-                    || request.request_type == RequestType::Flush)
+                && matches!(
+                    request.request_type,
+                    RequestType::Out | RequestType::Flush | RequestType::SecureErase
+                )
             {
                 desc_chain
                     .memory()
@@ -164,6 +391,12 @@ impl BlockEpollHandler {
                 queue
                     .add_used(desc_chain.memory(), desc_chain.head_index(), 0)
                     .map_err(Error::QueueAddUsed)?;
+                tracer::usdt!(
+                    virtio_blk_push,
+                    self.queue_index,
+                    desc_chain.head_index(),
+                    0
+                );
                 queue
                     .enable_notification(self.mem.memory().deref())
                     .map_err(Error::QueueEnableNotification)?;
@@ -203,19 +436,40 @@ impl BlockEpollHandler {
 
             request.set_writeback(self.writeback.load(Ordering::Acquire));
 
+            self.tracer
+                .trace(self.queue_index, TraceAction::Queued, &request);
+
+            let user_data = desc_chain.head_index() as u64;
+
             if request
                 .execute_async(
                     desc_chain.memory(),
                     self.disk_nsectors,
                     self.disk_image.as_mut(),
                     &self.serial,
-                    desc_chain.head_index() as u64,
+                    &self.lifetime,
+                    user_data,
                 )
                 .map_err(Error::RequestExecuting)?
             {
-                self.inflight_requests
-                    .push_back((desc_chain.head_index(), request));
+                self.tracer
+                    .trace(self.queue_index, TraceAction::Issued, &request);
+                self.inflight_requests.push_back((user_data, request));
+                let inflight = self
+                    .counters
+                    .inflight_requests
+                    .fetch_add(1, Ordering::AcqRel)
+                    + 1;
+                self.counters
+                    .inflight_requests_max
+                    .fetch_max(inflight, Ordering::AcqRel);
             } else {
+                // The request completed synchronously.
+                self.tracer
+                    .trace(self.queue_index, TraceAction::Issued, &request);
+                self.tracer
+                    .trace(self.queue_index, TraceAction::Completed, &request);
+
                 desc_chain
                     .memory()
                     .write_obj(VIRTIO_BLK_S_OK as u8, request.status_addr)
@@ -226,6 +480,12 @@ impl BlockEpollHandler {
                 queue
                     .add_used(desc_chain.memory(), desc_chain.head_index(), 0)
                     .map_err(Error::QueueAddUsed)?;
+                tracer::usdt!(
+                    virtio_blk_push,
+                    self.queue_index,
+                    desc_chain.head_index(),
+                    0
+                );
                 queue
                     .enable_notification(self.mem.memory().deref())
                     .map_err(Error::QueueEnableNotification)?;
@@ -263,7 +523,7 @@ impl BlockEpollHandler {
     }
 
     #[inline]
-    fn find_inflight_request(&mut self, completed_head: u16) -> Result<Request> {
+    fn find_inflight_request(&mut self, completed_user_data: u64) -> Result<Request> {
         // This loop neatly handles the fast path where the completions are
         // in order (it turns into just a pop_front()) and the 1% of the time
         // (analysis during boot) where slight out of ordering has been
@@ -274,8 +534,8 @@ impl BlockEpollHandler {
         // This is a O(1) operation and is prepared for the future as it it likely
         // the next completion would be for the one that was skipped which will
         // now be the new front.
-        for (i, (head, _)) in self.inflight_requests.iter().enumerate() {
-            if head == &completed_head {
+        for (i, (user_data, _)) in self.inflight_requests.iter().enumerate() {
+            if user_data == &completed_user_data {
                 return Ok(self.inflight_requests.swap_remove_front(i).unwrap().1);
             }
         }
@@ -293,10 +553,45 @@ impl BlockEpollHandler {
         while let Some((user_data, result)) = self.disk_image.next_completed_request() {
             let desc_index = user_data as u16;
 
-            let mut request = self.find_inflight_request(desc_index)?;
+            self.timed_out_requests.remove(&user_data);
+            self.uncancelled_requests.remove(&user_data);
+            let mut request = self.find_inflight_request(user_data)?;
+
+            if result < 0 {
+                let e = io::Error::from_raw_os_error(-result);
+                error!("Request failed: {:x?} {:?}", request, e);
+                event!(
+                    "virtio-block",
+                    "io-error",
+                    "id",
+                    &self.id,
+                    "sector",
+                    request.sector.to_string(),
+                    "error",
+                    e.to_string()
+                );
+
+                if self.on_io_error == OnIoError::Pause {
+                    // Keep the request around for submitting it again once
+                    // the VM is resumed.
+                    self.failed_requests.push((user_data, request));
+                    self.counters
+                        .inflight_requests
+                        .fetch_sub(1, Ordering::AcqRel);
+                    self.counters.failed_requests.fetch_add(1, Ordering::AcqRel);
+                    self.pause_vm();
+                    continue;
+                }
+            }
 
             request.complete_async().map_err(Error::RequestCompleting)?;
 
+            self.tracer
+                .trace(self.queue_index, TraceAction::Completed, &request);
+            self.counters
+                .inflight_requests
+                .fetch_sub(1, Ordering::AcqRel);
+
             let latency = request.start.elapsed().as_micros() as u64;
             let read_ops_last = self.counters.read_ops.load(Ordering::Relaxed);
             let write_ops_last = self.counters.write_ops.load(Ordering::Relaxed);
@@ -311,6 +606,8 @@ impl BlockEpollHandler {
                             read_bytes += Wrapping(*data_len as u64);
                         }
                         read_ops += Wrapping(1);
+                        self.counters.read_latency_hist[latency_bucket(latency)]
+                            .fetch_add(1, Ordering::Relaxed);
                         if latency < self.counters.read_latency_min.load(Ordering::Relaxed) {
                             self.counters
                                 .read_latency_min
@@ -343,6 +640,8 @@ impl BlockEpollHandler {
                             write_bytes += Wrapping(*data_len as u64);
                         }
                         write_ops += Wrapping(1);
+                        self.counters.write_latency_hist[latency_bucket(latency)]
+                            .fetch_add(1, Ordering::Relaxed);
                         if latency < self.counters.write_latency_min.load(Ordering::Relaxed) {
                             self.counters
                                 .write_latency_min
@@ -380,12 +679,7 @@ impl BlockEpollHandler {
 
                 (VIRTIO_BLK_S_OK as u8, result as u32)
             } else {
-                error!(
-                    "Request failed: {:x?} {:?}",
-                    request,
-                    io::Error::from_raw_os_error(-result)
-                );
-                return Err(Error::AsyncRequestFailure);
+                (VIRTIO_BLK_S_IOERR as u8, 0)
             };
 
             mem.write_obj(status, request.status_addr)
@@ -396,6 +690,7 @@ impl BlockEpollHandler {
             queue
                 .add_used(mem.deref(), desc_index, len)
                 .map_err(Error::QueueAddUsed)?;
+            tracer::usdt!(virtio_blk_push, self.queue_index, desc_index, len);
             queue
                 .enable_notification(mem.deref())
                 .map_err(Error::QueueEnableNotification)?;
@@ -418,6 +713,128 @@ impl BlockEpollHandler {
         Ok(())
     }
 
+    fn pause_vm(&mut self) {
+        if self.io_error_paused {
+            return;
+        }
+
+        self.io_error_paused = true;
+        if let Err(e) = self.vm_pause_evt.write(1) {
+            error!("Failed requesting the VM to pause: {:?}", e);
+        }
+    }
+
+    fn process_timeouts(&mut self) -> Result<()> {
+        let Some(io_timeout) = self.io_timeout else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let timeout_base = self.timeout_base;
+        let expired =
+            |request: &Request| now.duration_since(request.start.max(timeout_base)) >= io_timeout;
+
+        match self.on_io_error {
+            OnIoError::Pause => {
+                if !self.io_error_paused && self.inflight_requests.iter().any(|(_, r)| expired(r)) {
+                    warn!("Requests exceeded the IO timeout, pausing the VM");
+                    event!("virtio-block", "io-timeout", "id", &self.id);
+                    self.pause_vm();
+                }
+            }
+            OnIoError::Report => {
+                // The requests can't be completed before the backend is done
+                // with the guest memory, they are cancelled instead and fail
+                // once given back.
+                for (user_data, request) in self.inflight_requests.iter() {
+                    if !expired(request) {
+                        continue;
+                    }
+                    if self.timed_out_requests.insert(*user_data) {
+                        error!("Request exceeded the IO timeout: {:x?}", request);
+                        event!(
+                            "virtio-block",
+                            "io-timeout",
+                            "id",
+                            &self.id,
+                            "sector",
+                            request.sector.to_string()
+                        );
+                    } else if !self.uncancelled_requests.remove(user_data) {
+                        continue;
+                    }
+
+                    match self.disk_image.cancel(*user_data) {
+                        Ok(()) => {}
+                        // The request is kept until it completes.
+                        Err(AsyncIoError::Cancel(e))
+                            if e.raw_os_error() == Some(libc::EOPNOTSUPP) => {}
+                        Err(e) => {
+                            warn!("Failed cancelling request: {:x?} {}", request, e);
+                            self.uncancelled_requests.insert(*user_data);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_resume(&mut self) -> Result<()> {
+        // The time spent paused doesn't count towards the IO timeout.
+        self.timeout_base = Instant::now();
+        self.io_error_paused = false;
+
+        let mem = self.mem.memory();
+        for (user_data, mut request) in std::mem::take(&mut self.failed_requests) {
+            info!("Retrying failed request: {:x?}", request);
+            self.counters.failed_requests.fetch_sub(1, Ordering::AcqRel);
+            request.discard_async();
+            if request
+                .execute_async(
+                    mem.deref(),
+                    self.disk_nsectors,
+                    self.disk_image.as_mut(),
+                    &self.serial,
+                    &self.lifetime,
+                    user_data,
+                )
+                .map_err(Error::RequestExecuting)?
+            {
+                self.tracer
+                    .trace(self.queue_index, TraceAction::Issued, &request);
+                self.inflight_requests.push_back((user_data, request));
+                let inflight = self
+                    .counters
+                    .inflight_requests
+                    .fetch_add(1, Ordering::AcqRel)
+                    + 1;
+                self.counters
+                    .inflight_requests_max
+                    .fetch_max(inflight, Ordering::AcqRel);
+            } else {
+                // The request completed synchronously.
+                self.tracer
+                    .trace(self.queue_index, TraceAction::Issued, &request);
+                self.tracer
+                    .trace(self.queue_index, TraceAction::Completed, &request);
+
+                mem.write_obj(VIRTIO_BLK_S_OK as u8, request.status_addr)
+                    .map_err(Error::RequestStatus)?;
+                self.queue
+                    .add_used(mem.deref(), user_data as u16, 0)
+                    .map_err(Error::QueueAddUsed)?;
+                tracer::usdt!(virtio_blk_push, self.queue_index, user_data as u16, 0);
+                self.queue
+                    .enable_notification(mem.deref())
+                    .map_err(Error::QueueEnableNotification)?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
         self.interrupt_cb
             .trigger(VirtioInterruptType::Queue(self.queue_index))
@@ -462,6 +879,17 @@ impl BlockEpollHandler {
         }
     }
 
+    fn set_queue_thread_io_priority(&self) {
+        if let Some(io_priority) = self.io_priority.as_ref() {
+            if let Err(e) = io_priority.apply_to_current_thread() {
+                error!(
+                    "Failed setting the I/O priority of the virtqueue thread {} to {}: {}",
+                    self.queue_index, io_priority, e
+                )
+            }
+        }
+    }
+
     fn run(
         &mut self,
         paused: Arc<AtomicBool>,
@@ -473,13 +901,31 @@ impl BlockEpollHandler {
         if let Some(rate_limiter) = &self.rate_limiter {
             helper.add_event(rate_limiter.as_raw_fd(), RATE_LIMITER_EVENT)?;
         }
+        if let Some(timeout_timer) = &self.timeout_timer {
+            helper.add_event(timeout_timer.as_raw_fd(), TIMEOUT_EVENT)?;
+        }
+        helper.add_event(self.resume_evt.as_raw_fd(), RESUME_EVENT)?;
         self.set_queue_thread_affinity();
+        self.set_queue_thread_io_priority();
         helper.run(paused, paused_sync, self)?;
 
         Ok(())
     }
 }
 
+impl Drop for BlockEpollHandler {
+    fn drop(&mut self) {
+        // The requests still pending when the device is reset are never
+        // completed, they must not be accounted as in-flight anymore.
+        self.counters
+            .inflight_requests
+            .fetch_sub(self.inflight_requests.len() as u64, Ordering::AcqRel);
+        self.counters
+            .failed_requests
+            .fetch_sub(self.failed_requests.len() as u64, Ordering::AcqRel);
+    }
+}
+
 impl EpollHelperHandler for BlockEpollHandler {
     fn handle_event(
         &mut self,
@@ -545,6 +991,44 @@ impl EpollHelperHandler for BlockEpollHandler {
                     )));
                 }
             }
+            TIMEOUT_EVENT => {
+                if let Some(timeout_timer) = &mut self.timeout_timer {
+                    timeout_timer.wait().map_err(|e| {
+                        EpollHelperError::HandleEvent(anyhow!(
+                            "Failed to get timeout event: {:?}",
+                            e
+                        ))
+                    })?;
+                }
+
+                self.process_timeouts().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to process timeouts: {:?}", e))
+                })?;
+                self.try_signal_used_queue()?;
+            }
+            RESUME_EVENT => {
+                self.resume_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get resume event: {:?}", e))
+                })?;
+
+                self.process_resume().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to retry requests: {:?}", e))
+                })?;
+
+                let rate_limit_reached =
+                    self.rate_limiter.as_ref().map_or(false, |r| r.is_blocked());
+
+                // Process the queue only when the rate limit is not reached
+                if !rate_limit_reached {
+                    self.process_queue_submit().map_err(|e| {
+                        EpollHelperError::HandleEvent(anyhow!(
+                            "Failed to process queue (submit): {:?}",
+                            e
+                        ))
+                    })?;
+                }
+                self.try_signal_used_queue()?;
+            }
             _ => {
                 return Err(EpollHelperError::HandleEvent(anyhow!(
                     "Unexpected event: {}",
@@ -556,6 +1040,25 @@ impl EpollHelperHandler for BlockEpollHandler {
     }
 }
 
+// Only a lock held by someone else prevents from using the image, as some
+// filesystems don't support OFD locks.
+fn lock_image(image_lock: &mut ImageLock, disk_path: &Path) -> io::Result<()> {
+    match image_lock.acquire() {
+        Ok(()) => Ok(()),
+        Err(LockError::AlreadyLocked) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Disk image {disk_path:?} is locked by another process"),
+        )),
+        Err(e) => {
+            warn!(
+                "Proceeding without locking disk image {:?}: {}",
+                disk_path, e
+            );
+            Ok(())
+        }
+    }
+}
+
 /// Virtio device for exposing block level read/write operations on a host file.
 pub struct Block {
     common: VirtioCommon,
@@ -566,12 +1069,21 @@ pub struct Block {
     config: VirtioBlockConfig,
     writeback: Arc<AtomicBool>,
     counters: BlockCounters,
+    tracer: BlockTracer,
     seccomp_action: SeccompAction,
     rate_limiter: Option<Arc<RateLimiterGroup>>,
     exit_evt: EventFd,
     read_only: bool,
     serial: Vec<u8>,
+    lifetime: VirtioBlockLifetime,
     queue_affinity: BTreeMap<u16, Vec<usize>>,
+    io_priority: Option<IoPriority>,
+    image_lock: Option<ImageLock>,
+    migrating: bool,
+    io_timeout: Option<Duration>,
+    on_io_error: OnIoError,
+    vm_pause_evt: EventFd,
+    resume_evts: Vec<EventFd>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -600,6 +1112,11 @@ impl Block {
         exit_evt: EventFd,
         state: Option<BlockState>,
         queue_affinity: BTreeMap<u16, Vec<usize>>,
+        io_priority: Option<IoPriority>,
+        mut image_lock: Option<ImageLock>,
+        io_timeout: Option<Duration>,
+        on_io_error: OnIoError,
+        vm_pause_evt: EventFd,
     ) -> io::Result<Self> {
         let (disk_nsectors, avail_features, acked_features, config, paused) =
             if let Some(state) = state {
@@ -631,6 +1148,7 @@ impl Block {
                     | (1u64 << VIRTIO_BLK_F_CONFIG_WCE)
                     | (1u64 << VIRTIO_BLK_F_BLK_SIZE)
                     | (1u64 << VIRTIO_BLK_F_TOPOLOGY)
+                    | (1u64 << VIRTIO_BLK_F_LIFETIME)
                     | (1u64 << VIRTIO_RING_F_EVENT_IDX);
 
                 if iommu {
@@ -674,12 +1192,28 @@ impl Block {
                     config.num_queues = num_queues as u16;
                 }
 
+                if !read_only && disk_image.supports_secure_erase() {
+                    avail_features |= 1u64 << VIRTIO_BLK_F_SECURE_ERASE;
+                    config.max_secure_erase_sectors = u32::MAX;
+                    config.max_secure_erase_seg = 1;
+                    config.secure_erase_sector_alignment = config.blk_size / SECTOR_SIZE as u32;
+                }
+
                 (disk_nsectors, avail_features, 0, config, false)
             };
 
+        // A device restored from a snapshot only acquires the lock once
+        // resumed, as the source VM might still be holding it.
+        if !paused {
+            if let Some(image_lock) = image_lock.as_mut() {
+                lock_image(image_lock, &disk_path)?;
+            }
+        }
+
         let serial = serial
             .map(Vec::from)
             .unwrap_or_else(|| build_serial(&disk_path));
+        let lifetime = VirtioBlockLifetime::probe(&disk_path);
 
         Ok(Block {
             common: VirtioCommon {
@@ -699,15 +1233,29 @@ impl Block {
             config,
             writeback: Arc::new(AtomicBool::new(true)),
             counters: BlockCounters::default(),
+            tracer: BlockTracer::default(),
             seccomp_action,
             rate_limiter,
             exit_evt,
             read_only,
             serial,
+            lifetime,
             queue_affinity,
+            io_priority,
+            image_lock,
+            migrating: false,
+            io_timeout,
+            on_io_error,
+            vm_pause_evt,
+            resume_evts: Vec::new(),
         })
     }
 
+    /// Handle for controlling the request event stream of this device.
+    pub fn tracer(&self) -> BlockTracer {
+        self.tracer.clone()
+    }
+
     fn state(&self) -> BlockState {
         BlockState {
             disk_path: self.disk_path.to_str().unwrap().to_owned(),
@@ -805,6 +1353,7 @@ impl VirtioDevice for Block {
 
         let mut epoll_threads = Vec::new();
         let event_idx = self.common.feature_acked(VIRTIO_RING_F_EVENT_IDX.into());
+        self.resume_evts.clear();
 
         for i in 0..queues.len() {
             let (_, mut queue, queue_evt) = queues.remove(0);
@@ -814,7 +1363,35 @@ impl VirtioDevice for Block {
             let (kill_evt, pause_evt) = self.common.dup_eventfds();
             let queue_idx = i as u16;
 
+            let timeout_timer = self
+                .io_timeout
+                .map(|io_timeout| -> io::Result<TimerFd> {
+                    // Checking twice per period bounds the time a request can
+                    // exceed the IO timeout before being noticed.
+                    let mut timer = TimerFd::new()?;
+                    timer.reset(io_timeout / 2, Some(io_timeout / 2))?;
+                    Ok(timer)
+                })
+                .transpose()
+                .map_err(|e| {
+                    error!("failed to create IO timeout timer: {}", e);
+                    ActivateError::BadActivate
+                })?;
+            let resume_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(|e| {
+                error!("failed to create resume EventFd: {}", e);
+                ActivateError::BadActivate
+            })?;
+            self.resume_evts.push(resume_evt.try_clone().map_err(|e| {
+                error!("failed to clone resume EventFd: {}", e);
+                ActivateError::BadActivate
+            })?);
+            let vm_pause_evt = self.vm_pause_evt.try_clone().map_err(|e| {
+                error!("failed to clone VM pause EventFd: {}", e);
+                ActivateError::BadActivate
+            })?;
+
             let mut handler = BlockEpollHandler {
+                id: self.id.clone(),
                 queue_index: queue_idx,
                 queue,
                 mem: mem.clone(),
@@ -828,10 +1405,12 @@ impl VirtioDevice for Block {
                 disk_nsectors: self.disk_nsectors,
                 interrupt_cb: interrupt_cb.clone(),
                 serial: self.serial.clone(),
+                lifetime: self.lifetime,
                 kill_evt,
                 pause_evt,
                 writeback: self.writeback.clone(),
                 counters: self.counters.clone(),
+                tracer: self.tracer.clone(),
                 queue_evt,
                 // Analysis during boot shows around ~40 maximum requests
                 // This gives head room for systems with slower I/O without
@@ -846,6 +1425,17 @@ impl VirtioDevice for Block {
                 access_platform: self.common.access_platform.clone(),
                 read_only: self.read_only,
                 host_cpus: self.queue_affinity.get(&queue_idx).cloned(),
+                io_priority: self.io_priority,
+                io_timeout: self.io_timeout,
+                timeout_timer,
+                on_io_error: self.on_io_error,
+                vm_pause_evt,
+                resume_evt,
+                timed_out_requests: HashSet::new(),
+                uncancelled_requests: HashSet::new(),
+                failed_requests: Vec::new(),
+                io_error_paused: false,
+                timeout_base: Instant::now(),
             };
 
             let paused = self.common.paused.clone();
@@ -869,6 +1459,8 @@ impl VirtioDevice for Block {
 
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
+        // The cache mode set by the driver doesn't survive a reset.
+        self.config.writeback = 1;
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
@@ -916,6 +1508,26 @@ impl VirtioDevice for Block {
             "read_latency_avg",
             Wrapping(self.counters.read_latency_avg.load(Ordering::Acquire) / LATENCY_SCALE),
         );
+        for (i, name) in READ_LATENCY_BUCKET_NAMES.iter().enumerate() {
+            counters.insert(
+                *name,
+                Wrapping(self.counters.read_latency_hist[i].load(Ordering::Acquire)),
+            );
+        }
+        for (i, name) in WRITE_LATENCY_BUCKET_NAMES.iter().enumerate() {
+            counters.insert(
+                *name,
+                Wrapping(self.counters.write_latency_hist[i].load(Ordering::Acquire)),
+            );
+        }
+        counters.insert(
+            "inflight_requests",
+            Wrapping(self.counters.inflight_requests.load(Ordering::Acquire)),
+        );
+        counters.insert(
+            "inflight_requests_max",
+            Wrapping(self.counters.inflight_requests_max.load(Ordering::Acquire)),
+        );
 
         Some(counters)
     }
@@ -926,11 +1538,57 @@ impl VirtioDevice for Block {
 }
 
 impl Pausable for Block {
+    fn quiesce(&mut self, deadline: Instant) -> result::Result<(), MigratableError> {
+        // The queue handlers are still running, let them complete the
+        // requests already submitted to the backend.
+        loop {
+            let inflight = self.counters.inflight_requests.load(Ordering::Acquire);
+            if inflight == 0 {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                warn!(
+                    "{}: {} request(s) still in flight after quiesce deadline",
+                    self.id, inflight
+                );
+                return Ok(());
+            }
+            std::thread::sleep(QUIESCE_POLL_INTERVAL);
+        }
+    }
+
     fn pause(&mut self) -> result::Result<(), MigratableError> {
-        self.common.pause()
+        self.common.pause()?;
+
+        // Let the destination of the migration take over the image.
+        if self.migrating {
+            if let Some(image_lock) = self.image_lock.as_mut() {
+                image_lock.release().map_err(|e| {
+                    MigratableError::Pause(anyhow!("Error unlocking disk image: {:?}", e))
+                })?;
+            }
+        }
+
+        Ok(())
     }
 
     fn resume(&mut self) -> result::Result<(), MigratableError> {
+        if let Some(image_lock) = self.image_lock.as_mut() {
+            lock_image(image_lock, &self.disk_path).map_err(|e| {
+                MigratableError::Resume(anyhow!("Error locking disk image: {:?}", e))
+            })?;
+        }
+        // Resuming means the migration got cancelled, if any.
+        self.migrating = false;
+
+        // Let the queue handlers retry the requests which failed while the
+        // policy is to pause the VM.
+        for resume_evt in self.resume_evts.iter() {
+            resume_evt.write(1).map_err(|e| {
+                MigratableError::Resume(anyhow!("Error signaling resume event: {:?}", e))
+            })?;
+        }
+
         self.common.resume()
     }
 }
@@ -945,4 +1603,251 @@ impl Snapshottable for Block {
     }
 }
 impl Transportable for Block {}
-impl Migratable for Block {}
+impl Migratable for Block {
+    fn start_migration(&mut self) -> std::result::Result<(), MigratableError> {
+        self.migrating = true;
+        Ok(())
+    }
+
+    fn complete_migration(&mut self) -> std::result::Result<(), MigratableError> {
+        self.migrating = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_memory::GuestAddress;
+    use vm_virtio::queue::testing::VirtQueue as GuestQ;
+    use vmm_sys_util::tempfile::TempFile;
+
+    struct NoopVirtioInterrupt {}
+
+    impl VirtioInterrupt for NoopVirtioInterrupt {
+        fn trigger(&self, _int_type: VirtioInterruptType) -> std::result::Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    // Backend never completing the requests on its own, only giving them
+    // back once cancelled.
+    #[derive(Default)]
+    struct StalledDisk {
+        // Cancellations to reject before accepting them.
+        busy: usize,
+        cancelled: Vec<u64>,
+        completed: VecDeque<(u64, i32)>,
+    }
+
+    struct StalledDiskIo {
+        disk: Arc<Mutex<StalledDisk>>,
+        notifier: EventFd,
+    }
+
+    impl AsyncIo for StalledDiskIo {
+        fn notifier(&self) -> &EventFd {
+            &self.notifier
+        }
+
+        fn read_vectored(
+            &mut self,
+            _offset: libc::off_t,
+            _iovecs: &[libc::iovec],
+            _user_data: u64,
+        ) -> block::async_io::AsyncIoResult<()> {
+            Ok(())
+        }
+
+        fn write_vectored(
+            &mut self,
+            _offset: libc::off_t,
+            _iovecs: &[libc::iovec],
+            _user_data: u64,
+        ) -> block::async_io::AsyncIoResult<()> {
+            Ok(())
+        }
+
+        fn fsync(&mut self, _user_data: Option<u64>) -> block::async_io::AsyncIoResult<()> {
+            Ok(())
+        }
+
+        fn next_completed_request(&mut self) -> Option<(u64, i32)> {
+            self.disk.lock().unwrap().completed.pop_front()
+        }
+
+        fn cancel(&mut self, user_data: u64) -> block::async_io::AsyncIoResult<()> {
+            let mut disk = self.disk.lock().unwrap();
+            if disk.busy > 0 {
+                disk.busy -= 1;
+                return Err(AsyncIoError::Cancel(io::Error::from_raw_os_error(
+                    libc::EBUSY,
+                )));
+            }
+            disk.cancelled.push(user_data);
+            disk.completed.push_back((user_data, -libc::ECANCELED));
+            Ok(())
+        }
+    }
+
+    fn create_handler(
+        mem: &GuestMemoryMmap,
+        guest_q: &GuestQ,
+        disk: &Arc<Mutex<StalledDisk>>,
+        io_timeout: Duration,
+    ) -> BlockEpollHandler {
+        BlockEpollHandler {
+            id: "_disk0".to_string(),
+            queue_index: 0,
+            queue: guest_q.create_queue(),
+            mem: GuestMemoryAtomic::new(mem.clone()),
+            disk_image: Box::new(StalledDiskIo {
+                disk: disk.clone(),
+                notifier: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            }),
+            disk_nsectors: 0x1000,
+            interrupt_cb: Arc::new(NoopVirtioInterrupt {}),
+            serial: Vec::new(),
+            lifetime: VirtioBlockLifetime::default(),
+            kill_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            pause_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            writeback: Arc::new(AtomicBool::new(true)),
+            counters: BlockCounters::default(),
+            tracer: BlockTracer::default(),
+            queue_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            inflight_requests: VecDeque::new(),
+            rate_limiter: None,
+            access_platform: None,
+            read_only: false,
+            host_cpus: None,
+            io_priority: None,
+            io_timeout: Some(io_timeout),
+            timeout_timer: None,
+            on_io_error: OnIoError::Report,
+            vm_pause_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            resume_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            timed_out_requests: HashSet::new(),
+            uncancelled_requests: HashSet::new(),
+            failed_requests: Vec::new(),
+            io_error_paused: false,
+            timeout_base: Instant::now(),
+        }
+    }
+
+    fn request(request_type: RequestType, sector: u64, len: u32) -> Request {
+        Request {
+            request_type,
+            sector,
+            data_descriptors: vec![(GuestAddress(0x1000), len)].into(),
+            status_addr: GuestAddress(0),
+            writeback: true,
+            aligned_operations: Default::default(),
+            start: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_latency_bucket() {
+        assert_eq!(latency_bucket(0), 0);
+        assert_eq!(latency_bucket(16), 0);
+        assert_eq!(latency_bucket(17), 1);
+        assert_eq!(latency_bucket(1024), 3);
+        assert_eq!(latency_bucket(1025), 4);
+        assert_eq!(latency_bucket(262144), LATENCY_BUCKETS - 2);
+        assert_eq!(latency_bucket(262145), LATENCY_BUCKETS - 1);
+        assert_eq!(latency_bucket(u64::MAX), LATENCY_BUCKETS - 1);
+
+        // Every bucket is named after its upper bound.
+        for (i, bound) in LATENCY_BUCKET_BOUNDS.iter().enumerate() {
+            assert_eq!(
+                READ_LATENCY_BUCKET_NAMES[i],
+                format!("read_latency_le_{bound}us")
+            );
+            assert_eq!(
+                WRITE_LATENCY_BUCKET_NAMES[i],
+                format!("write_latency_le_{bound}us")
+            );
+        }
+    }
+
+    #[test]
+    fn test_block_tracer() {
+        let tracer = BlockTracer::default();
+        let read = request(RequestType::In, 8, 4096);
+        let write = request(RequestType::Out, 64, 512);
+
+        // Nothing is traced until the tracer is started.
+        tracer.trace(0, TraceAction::Queued, &read);
+
+        let file = TempFile::new().unwrap();
+        tracer.start(file.as_file().try_clone().unwrap()).unwrap();
+        tracer.trace(0, TraceAction::Queued, &read);
+        tracer.trace(0, TraceAction::Issued, &read);
+        tracer.trace(1, TraceAction::Completed, &write);
+        tracer.stop().unwrap();
+        tracer.trace(0, TraceAction::Completed, &read);
+
+        let trace = std::fs::read_to_string(file.as_path()).unwrap();
+        let events: Vec<Vec<&str>> = trace
+            .lines()
+            .map(|line| line.split(' ').skip(1).collect())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                vec!["0", "Q", "R", "8", "+", "8"],
+                vec!["0", "D", "R", "8", "+", "8"],
+                vec!["1", "C", "W", "64", "+", "1"],
+            ]
+        );
+        for line in trace.lines() {
+            let (secs, nsecs) = line.split(' ').next().unwrap().split_once('.').unwrap();
+            assert!(secs.parse::<u64>().is_ok());
+            assert_eq!(nsecs.len(), 9);
+        }
+    }
+
+    #[test]
+    fn test_io_timeout_cancel() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 1 << 20)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0), &mem, 8);
+        let disk = Arc::new(Mutex::new(StalledDisk {
+            busy: 1,
+            ..Default::default()
+        }));
+        let io_timeout = Duration::from_millis(10);
+        let mut handler = create_handler(&mem, &guest_q, &disk, io_timeout);
+
+        let mut read = request(RequestType::In, 8, 512);
+        read.status_addr = GuestAddress(0x8000);
+        mem.write_obj(0xffu8, read.status_addr).unwrap();
+        handler.inflight_requests.push_back((0, read));
+
+        // Nothing is cancelled before the request exceeds the timeout.
+        handler.process_timeouts().unwrap();
+        assert!(disk.lock().unwrap().cancelled.is_empty());
+
+        // The cancellation rejected by the backend is retried on the next
+        // check, and only submitted once accepted.
+        std::thread::sleep(io_timeout * 2);
+        handler.process_timeouts().unwrap();
+        assert!(disk.lock().unwrap().cancelled.is_empty());
+        handler.process_timeouts().unwrap();
+        handler.process_timeouts().unwrap();
+        assert_eq!(disk.lock().unwrap().cancelled, vec![0]);
+
+        // The request is only completed to the guest once given back by the
+        // backend, failing.
+        assert_eq!(handler.inflight_requests.len(), 1);
+        assert_eq!(guest_q.used.idx.get(), 0);
+        handler.process_queue_complete().unwrap();
+        assert!(handler.inflight_requests.is_empty());
+        assert!(handler.timed_out_requests.is_empty());
+        assert_eq!(guest_q.used.idx.get(), 1);
+        assert_eq!(guest_q.used.ring[0].get().len(), 0);
+        assert_eq!(
+            mem.read_obj::<u8>(GuestAddress(0x8000)).unwrap(),
+            VIRTIO_BLK_S_IOERR as u8
+        );
+    }
+}
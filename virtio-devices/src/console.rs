@@ -21,7 +21,10 @@ use std::collections::VecDeque;
 use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
-use std::os::unix::io::AsRawFd;
+use std::net::TcpListener;
+use std::os::fd::OwnedFd;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
 use std::result;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
@@ -45,6 +48,8 @@ const CONFIG_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
 const FILE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
 // Console resized
 const RESIZE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
+// New connection on the listening socket
+const SOCKET_LISTENER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 6;
 
 //Console size feature bit
 const VIRTIO_CONSOLE_F_SIZE: u64 = 0;
@@ -106,12 +111,62 @@ struct ConsoleEpollHandler {
     out: Option<Box<dyn Write + Send>>,
     write_out: Option<Arc<AtomicBool>>,
     file_event_registered: bool,
+    socket_stream: Option<File>,
+}
+
+/// Listening socket of a host character device.
+pub enum SocketListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl SocketListener {
+    /// Accepts the next client, the stream being read and written as a file.
+    pub fn accept(&self) -> io::Result<File> {
+        Ok(match self {
+            Self::Unix(l) => File::from(OwnedFd::from(l.accept()?.0)),
+            Self::Tcp(l) => File::from(OwnedFd::from(l.accept()?.0)),
+        })
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Self::Unix(l) => Self::Unix(l.try_clone()?),
+            Self::Tcp(l) => Self::Tcp(l.try_clone()?),
+        })
+    }
+
+    /// Removes the socket file, for the path to be bound again.
+    pub fn remove_socket_file(&self) {
+        if let Self::Unix(l) = self {
+            if let Some(path) = l
+                .local_addr()
+                .ok()
+                .and_then(|a| a.as_pathname().map(|p| p.to_path_buf()))
+            {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+impl AsRawFd for SocketListener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Unix(l) => l.as_raw_fd(),
+            Self::Tcp(l) => l.as_raw_fd(),
+        }
+    }
 }
 
 pub enum Endpoint {
     File(File),
     FilePair(File, File),
     PtyPair(File, File),
+    /// Listening Unix or TCP socket, a single client at a time is bridged to
+    /// the port. Output is dropped while no client is connected, and a new
+    /// client can connect once the previous one went away.
+    Socket(SocketListener),
     Null,
 }
 
@@ -121,6 +176,7 @@ impl Endpoint {
             Self::File(f) => Some(f),
             Self::FilePair(f, _) => Some(f),
             Self::PtyPair(f, _) => Some(f),
+            Self::Socket(_) => None,
             Self::Null => None,
         }
     }
@@ -130,6 +186,7 @@ impl Endpoint {
             Self::File(_) => None,
             Self::FilePair(_, f) => Some(f),
             Self::PtyPair(_, f) => Some(f),
+            Self::Socket(_) => None,
             Self::Null => None,
         }
     }
@@ -137,6 +194,13 @@ impl Endpoint {
     fn is_pty(&self) -> bool {
         matches!(self, Self::PtyPair(_, _))
     }
+
+    fn listener(&self) -> Option<&SocketListener> {
+        match self {
+            Self::Socket(l) => Some(l),
+            _ => None,
+        }
+    }
 }
 
 impl Clone for Endpoint {
@@ -149,6 +213,7 @@ impl Clone for Endpoint {
             Self::PtyPair(f_out, f_in) => {
                 Self::PtyPair(f_out.try_clone().unwrap(), f_in.try_clone().unwrap())
             }
+            Self::Socket(l) => Self::Socket(l.try_clone().unwrap()),
             Self::Null => Self::Null,
         }
     }
@@ -205,9 +270,14 @@ impl ConsoleEpollHandler {
             out,
             write_out,
             file_event_registered: false,
+            socket_stream: None,
         }
     }
 
+    fn in_file(&self) -> Option<&File> {
+        self.socket_stream.as_ref().or(self.endpoint.in_file())
+    }
+
     /*
      * Each port of virtio console device has one receive
      * queue. One or more empty buffers are placed by the
@@ -260,10 +330,11 @@ impl ConsoleEpollHandler {
     fn process_output_queue(&mut self) -> Result<bool, Error> {
         let trans_queue = &mut self.output_queue; //transmitq
         let mut used_descs = false;
+        let mut socket_client_gone = false;
 
         while let Some(mut desc_chain) = trans_queue.pop_descriptor_chain(self.mem.memory()) {
             let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
-            if let Some(out) = &mut self.out {
+            if let Some(out) = self.out.as_mut().filter(|_| !socket_client_gone) {
                 let mut buf: Vec<u8> = Vec::new();
                 desc_chain
                     .memory()
@@ -275,8 +346,19 @@ impl ConsoleEpollHandler {
                     )
                     .map_err(Error::GuestMemoryRead)?;
 
-                out.write_all(&buf).map_err(Error::OutputWriteAll)?;
-                out.flush().map_err(Error::OutputFlush)?;
+                let result = out
+                    .write_all(&buf)
+                    .map_err(Error::OutputWriteAll)
+                    .and_then(|_| out.flush().map_err(Error::OutputFlush));
+                if let Err(e) = result {
+                    // The socket client going away must not bring the
+                    // device down, the port waits for a new client instead.
+                    if self.socket_stream.is_none() {
+                        return Err(e);
+                    }
+                    warn!("Disconnecting virtio-console socket client: {}", e);
+                    socket_client_gone = true;
+                }
             }
             trans_queue
                 .add_used(desc_chain.memory(), desc_chain.head_index(), desc.len())
@@ -284,6 +366,12 @@ impl ConsoleEpollHandler {
             used_descs = true;
         }
 
+        if socket_client_gone {
+            // Closing the socket also removes it from the epoll.
+            self.socket_stream = None;
+            self.out = None;
+        }
+
         Ok(used_descs)
     }
 
@@ -316,6 +404,9 @@ impl ConsoleEpollHandler {
             helper.add_event_custom(in_file.as_raw_fd(), FILE_EVENT, events)?;
             self.file_event_registered = true;
         }
+        if let Some(listener) = self.endpoint.listener() {
+            helper.add_event(listener.as_raw_fd(), SOCKET_LISTENER_EVENT)?;
+        }
 
         // In case of PTY, we want to be able to detect a connection on the
         // other end of the PTY. This is done by detecting there's no event
@@ -368,6 +459,45 @@ impl ConsoleEpollHandler {
 
         Ok(())
     }
+
+    fn accept_socket_client(
+        &mut self,
+        helper: &mut EpollHelper,
+    ) -> result::Result<(), EpollHelperError> {
+        let stream = self
+            .endpoint
+            .listener()
+            .unwrap()
+            .accept()
+            .map_err(|e| EpollHelperError::HandleEvent(anyhow!("Failed to accept: {:?}", e)))?;
+
+        // Only one client can be bridged to the port, any additional
+        // connection is closed straight away.
+        if self.socket_stream.is_some() {
+            warn!("virtio-console socket already has a client connected");
+            return Ok(());
+        }
+
+        helper.add_event_custom(stream.as_raw_fd(), FILE_EVENT, epoll::Events::EPOLLIN)?;
+        self.out = Some(Box::new(stream.try_clone().map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to clone socket: {:?}", e))
+        })?));
+        self.socket_stream = Some(stream);
+
+        Ok(())
+    }
+
+    fn disconnect_socket_client(
+        &mut self,
+        helper: &mut EpollHelper,
+    ) -> result::Result<(), EpollHelperError> {
+        if let Some(stream) = self.socket_stream.take() {
+            helper.del_event_custom(stream.as_raw_fd(), FILE_EVENT, epoll::Events::EPOLLIN)?;
+            self.out = None;
+        }
+
+        Ok(())
+    }
 }
 
 impl EpollHelperHandler for ConsoleEpollHandler {
@@ -443,13 +573,28 @@ impl EpollHelperHandler for ConsoleEpollHandler {
                     })?;
                 self.resizer.update_console_size();
             }
+            SOCKET_LISTENER_EVENT => {
+                self.accept_socket_client(helper)?;
+            }
             FILE_EVENT => {
+                if self.socket_stream.is_some()
+                    && event.events & (libc::EPOLLHUP | libc::EPOLLERR) as u32 != 0
+                    && event.events & libc::EPOLLIN as u32 == 0
+                {
+                    return self.disconnect_socket_client(helper);
+                }
                 if event.events & libc::EPOLLIN as u32 != 0 {
                     let mut input = [0u8; 64];
-                    if let Some(ref mut in_file) = self.endpoint.in_file() {
-                        if let Ok(count) = in_file.read(&mut input) {
-                            let mut in_buffer = self.in_buffer.lock().unwrap();
-                            in_buffer.extend(&input[..count]);
+                    if let Some(ref mut in_file) = self.in_file() {
+                        match in_file.read(&mut input) {
+                            Ok(0) if self.socket_stream.is_some() => {
+                                return self.disconnect_socket_client(helper);
+                            }
+                            Ok(count) => {
+                                let mut in_buffer = self.in_buffer.lock().unwrap();
+                                in_buffer.extend(&input[..count]);
+                            }
+                            Err(_) => {}
                         }
 
                         let needs_notification = self.process_input_queue().map_err(|e| {
@@ -712,6 +857,12 @@ impl Drop for Console {
             let _ = kill_evt.write(1);
         }
         self.common.wait_for_epoll_threads();
+
+        // Remove the socket created for the port so that it can be bound
+        // again, e.g. when the device is hotplugged back.
+        if let Some(listener) = self.endpoint.listener() {
+            listener.remove_socket_file();
+        }
     }
 }
 
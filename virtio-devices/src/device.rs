@@ -86,6 +86,11 @@ pub trait VirtioDevice: Send {
         let _ = value;
     }
 
+    /// The set of feature bits acknowledged so far.
+    fn acked_features(&self) -> u64 {
+        0
+    }
+
     /// Reads this device configuration space at `offset`.
     fn read_config(&self, _offset: u64, _data: &mut [u8]) {
         warn!(
@@ -261,7 +266,13 @@ impl VirtioCommon {
     pub fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         // We first must resume the virtio thread if it was paused.
         if self.pause_evt.take().is_some() {
-            self.resume().ok()?;
+            if let Err(e) = self.resume() {
+                error!(
+                    "Error resuming virtio-{}: {:?}",
+                    VirtioDeviceType::from(self.device_type),
+                    e
+                );
+            }
         }
 
         if let Some(kill_evt) = self.kill_evt.take() {
@@ -277,8 +288,13 @@ impl VirtioCommon {
             }
         }
 
-        // Return the interrupt
-        Some(self.interrupt_cb.take().unwrap())
+        // The driver negotiates the features again before reactivating the
+        // device, e.g. when the guest resumes from suspend, and might ack
+        // fewer of them.
+        self.acked_features = 0;
+
+        // Return the interrupt, if the device has been activated
+        self.interrupt_cb.take()
     }
 
     // Wait for the worker thread to finish and return
@@ -345,3 +361,58 @@ impl Pausable for VirtioCommon {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoopInterrupt;
+
+    impl VirtioInterrupt for NoopInterrupt {
+        fn trigger(
+            &self,
+            _int_type: VirtioInterruptType,
+        ) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_virtio_common_reset() {
+        let mut common = VirtioCommon {
+            avail_features: 0b1011,
+            paused_sync: Some(Arc::new(Barrier::new(1))),
+            ..Default::default()
+        };
+
+        // Resetting a device which hasn't been activated only forgets the
+        // features acked by the driver.
+        common.ack_features(0b11);
+        assert!(common.reset().is_none());
+        assert_eq!(common.acked_features, 0);
+
+        common.ack_features(0b1001);
+        let interrupt: Arc<dyn VirtioInterrupt> = Arc::new(NoopInterrupt);
+        common.activate(&[], &interrupt).unwrap();
+        let (kill_evt, _) = common.dup_eventfds();
+        common.epoll_threads = Some(vec![thread::spawn(|| {})]);
+        common.pause().unwrap();
+
+        // The worker threads are resumed, killed and joined, and the
+        // interrupt is given back to the transport.
+        let interrupt_cb = common.reset().unwrap();
+        assert!(Arc::ptr_eq(&interrupt_cb, &interrupt));
+        assert_eq!(kill_evt.read().unwrap(), 1);
+        assert!(!common.paused.load(Ordering::SeqCst));
+        assert!(common.kill_evt.is_none());
+        assert!(common.pause_evt.is_none());
+        assert!(common.epoll_threads.is_none());
+        assert!(common.interrupt_cb.is_none());
+        assert_eq!(common.acked_features, 0);
+
+        // The features acked on reinitialization aren't merged with the
+        // previous ones.
+        common.ack_features(0b10);
+        assert_eq!(common.acked_features, 0b10);
+    }
+}
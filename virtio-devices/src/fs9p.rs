@@ -0,0 +1,889 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! virtio-9p transport.
+//!
+//! The device only carries the 9P2000.L messages between the guest and a 9P
+//! server listening on a UNIX socket, the same way the virtio-fs device relies
+//! on a vhost-user daemon. The server runs outside of the VMM, in its own
+//! sandbox, and is the only one accessing the shared directory.
+//!
+//! Each descriptor chain holds a request (T-message) in its device readable
+//! descriptors followed by the buffers for the reply (R-message) in its device
+//! writable descriptors. Requests are forwarded as soon as they are available
+//! and the replies, which can come back in any order, are matched with their
+//! request through the 9P tag.
+//!
+//! The socket is non-blocking: the requests the server doesn't accept right
+//! away are buffered until it can take them, and no more requests are taken
+//! from the queue meanwhile. The messages exchanged in both directions are
+//! bounded by the msize negotiated through Tversion.
+
+use super::Error as DeviceError;
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioDevice, VirtioDeviceType, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::GuestMemoryMmap;
+use crate::{VirtioInterrupt, VirtioInterruptType};
+use anyhow::anyhow;
+use seccompiler::SeccompAction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier};
+use thiserror::Error;
+use virtio_queue::{Queue, QueueT};
+use vm_memory::{Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic};
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::{AccessPlatform, Translatable};
+use vmm_sys_util::eventfd::EventFd;
+
+// The mount tag is available from the configuration space.
+const VIRTIO_9P_MOUNT_TAG: u64 = 0;
+
+// New descriptors are pending on the virtio queue.
+const QUEUE_AVAIL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+// Replies are pending on the server socket.
+const SERVER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+
+// Every 9P message starts with size[4] type[1] tag[2].
+const HEADER_LEN: usize = 7;
+// Largest message accepted in both directions until an msize is negotiated,
+// well above the largest msize a guest can negotiate over virtio.
+const MAX_MESSAGE_LEN: usize = 16 << 20;
+const READ_CHUNK_LEN: usize = 64 << 10;
+// Requests are no longer taken from the queue while that much data is waiting
+// for the server to accept it.
+const MAX_PENDING_TX_LEN: usize = MAX_MESSAGE_LEN;
+
+const P9_RLERROR: u8 = 7;
+const P9_TVERSION: u8 = 100;
+const P9_RVERSION: u8 = 101;
+const P9_TFLUSH: u8 = 108;
+const P9_RFLUSH: u8 = 109;
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Failed to read from guest memory: {0}")]
+    GuestMemoryRead(vm_memory::guest_memory::Error),
+    #[error("Failed to write to guest memory: {0}")]
+    GuestMemoryWrite(vm_memory::guest_memory::Error),
+    #[error("Failed adding used index: {0}")]
+    QueueAddUsed(virtio_queue::Error),
+    #[error("Failed to send request to the 9P server: {0}")]
+    ServerWrite(io::Error),
+    #[error("Failed to receive reply from the 9P server: {0}")]
+    ServerRead(io::Error),
+    #[error("9P server closed the connection")]
+    ServerClosed,
+    #[error("Invalid message from the 9P server")]
+    InvalidReply,
+}
+
+struct PendingRequest {
+    head_index: u16,
+    // Guest buffers the reply is written to.
+    reply: Vec<(GuestAddress, usize)>,
+    // Tag of the request cancelled by a Tflush.
+    flushed_tag: Option<u16>,
+    // msize requested by a Tversion.
+    msize: Option<usize>,
+}
+
+fn tag(msg: &[u8]) -> u16 {
+    u16::from_le_bytes([msg[5], msg[6]])
+}
+
+fn msize(msg: &[u8]) -> Option<usize> {
+    msg.get(HEADER_LEN..HEADER_LEN + 4)
+        .map(|s| u32::from_le_bytes(s.try_into().unwrap()) as usize)
+}
+
+fn lerror(tag: u16, errno: i32) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(HEADER_LEN + 4);
+    msg.extend_from_slice(&(HEADER_LEN as u32 + 4).to_le_bytes());
+    msg.push(P9_RLERROR);
+    msg.extend_from_slice(&tag.to_le_bytes());
+    msg.extend_from_slice(&errno.to_le_bytes());
+    msg
+}
+
+struct Fs9pEpollHandler {
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    queue: Queue,
+    server: Option<UnixStream>,
+    // Whether the server socket is polled for writing.
+    server_writable_event: bool,
+    // Partial replies received from the server.
+    rx: Vec<u8>,
+    // Requests not accepted by the server yet.
+    tx: Vec<u8>,
+    // Largest message allowed in both directions.
+    msize: usize,
+    pending: HashMap<u16, PendingRequest>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    queue_evt: EventFd,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+}
+
+impl Fs9pEpollHandler {
+    fn complete(&mut self, request: PendingRequest, msg: &[u8]) -> result::Result<(), Error> {
+        let mem = self.mem.memory();
+        let mut written = 0;
+        for (addr, len) in request.reply {
+            if written == msg.len() {
+                break;
+            }
+            let len = std::cmp::min(len, msg.len() - written);
+            mem.write_slice(&msg[written..written + len], addr)
+                .map_err(Error::GuestMemoryWrite)?;
+            written += len;
+        }
+        if written < msg.len() {
+            warn!(
+                "9P reply truncated to {} bytes out of {}",
+                written,
+                msg.len()
+            );
+        }
+
+        self.queue
+            .add_used(mem.deref(), request.head_index, written as u32)
+            .map_err(Error::QueueAddUsed)
+    }
+
+    fn process_queue(&mut self) -> result::Result<bool, Error> {
+        let mem = self.mem.memory();
+        let mut used_descs = false;
+        while self.tx.len() < MAX_PENDING_TX_LEN {
+            let Some(mut desc_chain) = self.queue.pop_descriptor_chain(mem.clone()) else {
+                break;
+            };
+            let head_index = desc_chain.head_index();
+
+            let mut msg = Vec::new();
+            let mut reply = Vec::new();
+            let mut valid = true;
+            for desc in desc_chain.by_ref() {
+                let addr = desc
+                    .addr()
+                    .translate_gva(self.access_platform.as_ref(), desc.len() as usize);
+                if desc.is_write_only() {
+                    reply.push((addr, desc.len() as usize));
+                } else if !valid || !reply.is_empty() {
+                    valid = false;
+                } else if msg.len() + desc.len() as usize > self.msize {
+                    // Don't let the guest size the buffer beyond what the
+                    // request can be made of.
+                    valid = false;
+                } else {
+                    let offset = msg.len();
+                    msg.resize(offset + desc.len() as usize, 0);
+                    mem.read_slice(&mut msg[offset..], addr)
+                        .map_err(Error::GuestMemoryRead)?;
+                }
+            }
+
+            // The request must fit in the readable descriptors.
+            let size = msg
+                .get(..4)
+                .map(|s| u32::from_le_bytes(s.try_into().unwrap()) as usize);
+            let size = match size {
+                Some(size) if valid && size >= HEADER_LEN && size <= msg.len() => size,
+                _ => {
+                    warn!("Invalid 9P request");
+                    self.queue
+                        .add_used(mem.deref(), head_index, 0)
+                        .map_err(Error::QueueAddUsed)?;
+                    used_descs = true;
+                    continue;
+                }
+            };
+            msg.truncate(size);
+            let request = PendingRequest {
+                head_index,
+                reply,
+                flushed_tag: (msg[4] == P9_TFLUSH && size >= HEADER_LEN + 2)
+                    .then(|| u16::from_le_bytes([msg[7], msg[8]])),
+                msize: if msg[4] == P9_TVERSION {
+                    msize(&msg)
+                } else {
+                    None
+                },
+            };
+
+            if self.server.is_none() {
+                self.complete(request, &lerror(tag(&msg), libc::EIO))?;
+                used_descs = true;
+                continue;
+            }
+            if self.pending.contains_key(&tag(&msg)) {
+                warn!("9P request tag {} already in use", tag(&msg));
+                self.complete(request, &lerror(tag(&msg), libc::EINVAL))?;
+                used_descs = true;
+                continue;
+            }
+
+            self.pending.insert(tag(&msg), request);
+            self.tx.extend_from_slice(&msg);
+        }
+
+        self.flush_requests()?;
+
+        Ok(used_descs)
+    }
+
+    // Sends the pending requests until the server stops accepting them,
+    // without blocking the device thread.
+    fn flush_requests(&mut self) -> result::Result<(), Error> {
+        let Some(mut server) = self.server.as_ref() else {
+            return Ok(());
+        };
+        while !self.tx.is_empty() {
+            match server.write(&self.tx) {
+                Ok(0) => return Err(Error::ServerClosed),
+                Ok(written) => {
+                    self.tx.drain(..written);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::ServerWrite(e)),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Only polls the server for writing while requests are waiting for it,
+    // as the socket is writable most of the time.
+    fn update_server_events(
+        &mut self,
+        helper: &mut EpollHelper,
+    ) -> result::Result<(), EpollHelperError> {
+        let Some(server) = self.server.as_ref() else {
+            return Ok(());
+        };
+        let writable_event = !self.tx.is_empty();
+        if writable_event != self.server_writable_event {
+            let mut events = epoll::Events::EPOLLIN;
+            if writable_event {
+                events |= epoll::Events::EPOLLOUT;
+            }
+            helper.mod_event_custom(server.as_raw_fd(), SERVER_EVENT, events)?;
+            self.server_writable_event = writable_event;
+        }
+
+        Ok(())
+    }
+
+    fn process_replies(&mut self) -> result::Result<bool, Error> {
+        // rx never holds more than a partial message, which is at most msize
+        // long.
+        let offset = self.rx.len();
+        let len = std::cmp::min(READ_CHUNK_LEN, self.msize - offset);
+        self.rx.resize(offset + len, 0);
+        let read = self.server.as_ref().unwrap().read(&mut self.rx[offset..]);
+        self.rx.truncate(offset + *read.as_ref().unwrap_or(&0));
+        match read {
+            Ok(0) => return Err(Error::ServerClosed),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(false),
+            Err(e) => return Err(Error::ServerRead(e)),
+        }
+
+        let mut used_descs = false;
+        while self.rx.len() >= 4 {
+            let size = u32::from_le_bytes(self.rx[..4].try_into().unwrap()) as usize;
+            if !(HEADER_LEN..=self.msize).contains(&size) {
+                return Err(Error::InvalidReply);
+            }
+            if self.rx.len() < size {
+                break;
+            }
+            let msg: Vec<u8> = self.rx.drain(..size).collect();
+
+            let Some(request) = self.pending.remove(&tag(&msg)) else {
+                warn!("Dropping 9P reply with unknown tag {}", tag(&msg));
+                continue;
+            };
+            // The server can only lower the msize asked by the guest.
+            if let (P9_RVERSION, Some(requested)) = (msg[4], request.msize) {
+                match msize(&msg) {
+                    Some(msize) if msize >= HEADER_LEN && msize <= requested => {
+                        self.msize = std::cmp::min(msize, MAX_MESSAGE_LEN);
+                    }
+                    _ => return Err(Error::InvalidReply),
+                }
+            }
+            // The server won't answer a flushed request anymore, which still
+            // needs to be given back to the guest before the flush completes.
+            if let (P9_RFLUSH, Some(flushed_tag)) = (msg[4], request.flushed_tag) {
+                if let Some(flushed) = self.pending.remove(&flushed_tag) {
+                    self.complete(flushed, &lerror(flushed_tag, libc::EINTR))?;
+                }
+            }
+            self.complete(request, &msg)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    // Fails all the requests once the server is gone, rather than leaving the
+    // guest waiting forever.
+    fn disconnect(&mut self, helper: &mut EpollHelper) -> result::Result<(), Error> {
+        if let Some(server) = self.server.take() {
+            if let Err(e) =
+                helper.del_event_custom(server.as_raw_fd(), SERVER_EVENT, epoll::Events::EPOLLIN)
+            {
+                error!("Failed to remove 9P server from epoll: {:?}", e);
+            }
+        }
+        self.server_writable_event = false;
+        self.rx.clear();
+        self.tx.clear();
+
+        let pending: Vec<(u16, PendingRequest)> = self.pending.drain().collect();
+        for (tag, request) in pending {
+            self.complete(request, &lerror(tag, libc::EIO))?;
+        }
+
+        Ok(())
+    }
+
+    fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(0))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                DeviceError::FailedSignalingUsedQueue(e)
+            })
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(self.queue_evt.as_raw_fd(), QUEUE_AVAIL_EVENT)?;
+        if let Some(server) = self.server.as_ref() {
+            helper.add_event(server.as_raw_fd(), SERVER_EVENT)?;
+        }
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for Fs9pEpollHandler {
+    fn handle_event(
+        &mut self,
+        helper: &mut EpollHelper,
+        event: &epoll::Event,
+    ) -> result::Result<(), EpollHelperError> {
+        let ev_type = event.data as u16;
+        let result = match ev_type {
+            QUEUE_AVAIL_EVENT => {
+                self.queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                self.process_queue()
+            }
+            SERVER_EVENT => {
+                let mut result = Ok(false);
+                if event.events & libc::EPOLLOUT as u32 != 0 {
+                    // The requests held back can be taken from the queue
+                    // once the pending ones are sent.
+                    result = self.flush_requests().and_then(|_| self.process_queue());
+                }
+                if event.events & !(libc::EPOLLOUT as u32) != 0 {
+                    result = result.and_then(|used| Ok(self.process_replies()? || used));
+                }
+                result
+            }
+            _ => {
+                return Err(EpollHelperError::HandleEvent(anyhow!(
+                    "Unexpected event: {}",
+                    ev_type
+                )));
+            }
+        };
+
+        let needs_notification = match result {
+            Ok(needs_notification) => needs_notification,
+            Err(
+                e @ (Error::ServerWrite(_)
+                | Error::ServerRead(_)
+                | Error::ServerClosed
+                | Error::InvalidReply),
+            ) => {
+                error!("Lost connection to the 9P server: {}", e);
+                // Requests left on the queue are failed now that the server
+                // is gone.
+                self.disconnect(helper)
+                    .and_then(|_| self.process_queue())
+                    .map(|_| true)
+                    .map_err(|e| {
+                        EpollHelperError::HandleEvent(anyhow!(
+                            "Failed to complete 9P requests: {:?}",
+                            e
+                        ))
+                    })?
+            }
+            Err(e) => {
+                return Err(EpollHelperError::HandleEvent(anyhow!(
+                    "Failed to process 9P requests: {:?}",
+                    e
+                )));
+            }
+        };
+
+        self.update_server_events(helper)?;
+
+        if needs_notification {
+            self.signal_used_queue().map_err(|e| {
+                EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Virtio device sharing host directories with the guest through 9P2000.L,
+/// for guests lacking virtio-fs support.
+pub struct Fs9p {
+    common: VirtioCommon,
+    id: String,
+    config: Vec<u8>,
+    server: UnixStream,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Fs9pState {
+    pub avail_features: u64,
+    pub acked_features: u64,
+}
+
+impl Fs9p {
+    /// Create a new virtio-9p device forwarding the requests of the guest to
+    /// the 9P server listening on `socket`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: String,
+        socket: &str,
+        tag: &str,
+        queue_size: u16,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<Fs9pState>,
+    ) -> io::Result<Fs9p> {
+        let server = UnixStream::connect(socket)?;
+
+        let (avail_features, acked_features, paused) = if let Some(state) = state {
+            info!("Restoring virtio-9p {}", id);
+            (state.avail_features, state.acked_features, true)
+        } else {
+            let mut avail_features = 1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_9P_MOUNT_TAG;
+
+            if iommu {
+                avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+            }
+
+            (avail_features, 0, false)
+        };
+
+        // struct virtio_9p_config { le16 tag_len; u8 tag[]; }
+        let mut config = (tag.len() as u16).to_le_bytes().to_vec();
+        config.extend_from_slice(tag.as_bytes());
+
+        Ok(Fs9p {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Fs9P as u32,
+                queue_sizes: vec![queue_size],
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                avail_features,
+                acked_features,
+                min_queues: 1,
+                paused: Arc::new(AtomicBool::new(paused)),
+                ..Default::default()
+            },
+            id,
+            config,
+            server,
+            seccomp_action,
+            exit_evt,
+        })
+    }
+
+    fn state(&self) -> Fs9pState {
+        Fs9pState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+        }
+    }
+}
+
+impl Drop for Fs9p {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl VirtioDevice for Fs9p {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(&self.config, offset, data);
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        mut queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        let server = self.server.try_clone().map_err(|e| {
+            error!("failed cloning 9P server socket: {}", e);
+            ActivateError::BadActivate
+        })?;
+        server.set_nonblocking(true).map_err(|e| {
+            error!("failed making 9P server socket non-blocking: {}", e);
+            ActivateError::BadActivate
+        })?;
+
+        let (_, queue, queue_evt) = queues.remove(0);
+
+        let mut handler = Fs9pEpollHandler {
+            mem,
+            queue,
+            server: Some(server),
+            server_writable_event: false,
+            rx: Vec::new(),
+            tx: Vec::new(),
+            msize: MAX_MESSAGE_LEN,
+            pending: HashMap::new(),
+            interrupt_cb,
+            queue_evt,
+            kill_evt,
+            pause_evt,
+            access_platform: self.common.access_platform.clone(),
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioFs9p,
+            &mut epoll_threads,
+            &self.exit_evt,
+            move || handler.run(paused, paused_sync.unwrap()),
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Fs9p {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Fs9p {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_state(&self.state())
+    }
+}
+
+impl Transportable for Fs9p {}
+impl Migratable for Fs9p {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use virtio_bindings::virtio_ring::{VRING_DESC_F_NEXT, VRING_DESC_F_WRITE};
+    use vm_virtio::queue::testing::VirtQueue as GuestQ;
+
+    const MEM_SIZE: usize = 16 << 20;
+    const QSIZE: u16 = 8;
+
+    struct NoopVirtioInterrupt {}
+
+    impl VirtioInterrupt for NoopVirtioInterrupt {
+        fn trigger(
+            &self,
+            _int_type: VirtioInterruptType,
+        ) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    fn create_handler(
+        mem: &GuestMemoryMmap,
+        guest_q: &GuestQ,
+        server: UnixStream,
+    ) -> Fs9pEpollHandler {
+        server.set_nonblocking(true).unwrap();
+        Fs9pEpollHandler {
+            mem: GuestMemoryAtomic::new(mem.clone()),
+            queue: guest_q.create_queue(),
+            server: Some(server),
+            server_writable_event: false,
+            rx: Vec::new(),
+            tx: Vec::new(),
+            msize: MAX_MESSAGE_LEN,
+            pending: HashMap::new(),
+            interrupt_cb: Arc::new(NoopVirtioInterrupt {}),
+            queue_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            kill_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            pause_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            access_platform: None,
+        }
+    }
+
+    fn message(msg_type: u8, tag: u16, body: &[u8]) -> Vec<u8> {
+        let mut msg = ((HEADER_LEN + body.len()) as u32).to_le_bytes().to_vec();
+        msg.push(msg_type);
+        msg.extend_from_slice(&tag.to_le_bytes());
+        msg.extend_from_slice(body);
+        msg
+    }
+
+    fn version(msg_type: u8, msize: u32) -> Vec<u8> {
+        let mut body = msize.to_le_bytes().to_vec();
+        body.extend_from_slice(&8u16.to_le_bytes());
+        body.extend_from_slice(b"9P2000.L");
+        message(msg_type, 0xffff, &body)
+    }
+
+    // Makes the descriptors `first..` a chain holding `request` in a single
+    // readable descriptor, followed by a 4 KiB writable one, and makes it
+    // available as the `avail_idx`th entry.
+    fn add_chain(guest_q: &GuestQ, first: u16, avail_idx: u16, request: &[u8], len: u32) {
+        let addr = 0x10_0000 + first as u64 * 0x1000;
+        guest_q
+            .mem
+            .write_slice(request, GuestAddress(addr))
+            .unwrap();
+        guest_q.dtable[first as usize].set(
+            addr,
+            len,
+            VRING_DESC_F_NEXT.try_into().unwrap(),
+            first + 1,
+        );
+        guest_q.dtable[first as usize + 1].set(
+            0x80_0000 + first as u64 * 0x1000,
+            0x1000,
+            VRING_DESC_F_WRITE.try_into().unwrap(),
+            0,
+        );
+        guest_q.avail.ring[avail_idx as usize].set(first);
+        guest_q.avail.idx.set(avail_idx + 1);
+    }
+
+    fn read_server(server: &mut UnixStream) -> Vec<u8> {
+        let mut data = vec![0; 0x1000];
+        match server.read(&mut data) {
+            Ok(len) => data.truncate(len),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => data.clear(),
+            Err(e) => panic!("{}", e),
+        }
+        data
+    }
+
+    #[test]
+    fn test_fs9p_version() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), MEM_SIZE)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0), &mem, QSIZE);
+        let (device, mut server) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut handler = create_handler(&mem, &guest_q, device);
+
+        let tversion = version(P9_TVERSION, 0x2000);
+        add_chain(&guest_q, 0, 0, &tversion, tversion.len() as u32);
+        assert!(!handler.process_queue().unwrap());
+        assert_eq!(read_server(&mut server), tversion);
+
+        // The server lowers the msize, which bounds the requests.
+        let rversion = version(P9_RVERSION, 0x1000);
+        server.write_all(&rversion).unwrap();
+        assert!(handler.process_replies().unwrap());
+        assert_eq!(handler.msize, 0x1000);
+        assert_eq!(guest_q.used.idx.get(), 1);
+        assert_eq!(guest_q.used.ring[0].get().len(), rversion.len() as u32);
+        let mut reply = vec![0; rversion.len()];
+        mem.read_slice(&mut reply, GuestAddress(0x80_0000)).unwrap();
+        assert_eq!(reply, rversion);
+
+        // A request spanning more than msize isn't read nor forwarded.
+        let tclunk = message(120, 1, &1u32.to_le_bytes());
+        add_chain(&guest_q, 2, 1, &tclunk, 0x1001);
+        assert!(handler.process_queue().unwrap());
+        assert_eq!(guest_q.used.idx.get(), 2);
+        assert_eq!(guest_q.used.ring[1].get().id(), 2);
+        assert_eq!(guest_q.used.ring[1].get().len(), 0);
+        assert!(read_server(&mut server).is_empty());
+
+        // So is a reply larger than msize.
+        add_chain(&guest_q, 4, 2, &tclunk, tclunk.len() as u32);
+        assert!(!handler.process_queue().unwrap());
+        assert_eq!(read_server(&mut server), tclunk);
+        server.write_all(&0x1001u32.to_le_bytes()).unwrap();
+        assert!(matches!(
+            handler.process_replies(),
+            Err(Error::InvalidReply)
+        ));
+    }
+
+    #[test]
+    fn test_fs9p_version_msize_raised() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), MEM_SIZE)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0), &mem, QSIZE);
+        let (device, mut server) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut handler = create_handler(&mem, &guest_q, device);
+
+        let tversion = version(P9_TVERSION, 0x2000);
+        add_chain(&guest_q, 0, 0, &tversion, tversion.len() as u32);
+        handler.process_queue().unwrap();
+        assert_eq!(read_server(&mut server), tversion);
+
+        // The server can't raise the msize asked by the guest.
+        server.write_all(&version(P9_RVERSION, 0x4000)).unwrap();
+        assert!(matches!(
+            handler.process_replies(),
+            Err(Error::InvalidReply)
+        ));
+        assert_eq!(handler.msize, MAX_MESSAGE_LEN);
+    }
+
+    #[test]
+    fn test_fs9p_invalid_requests() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), MEM_SIZE)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0), &mem, QSIZE);
+        let (device, mut server) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut handler = create_handler(&mem, &guest_q, device);
+
+        let tclunk = message(120, 1, &1u32.to_le_bytes());
+        // Message shorter than its header.
+        let mut short = tclunk.clone();
+        short[..4].copy_from_slice(&(HEADER_LEN as u32 - 1).to_le_bytes());
+        add_chain(&guest_q, 0, 0, &short, short.len() as u32);
+        // Message larger than the readable descriptors.
+        add_chain(&guest_q, 2, 1, &tclunk, tclunk.len() as u32 - 1);
+        // Descriptor larger than any message, beyond guest memory.
+        add_chain(&guest_q, 4, 2, &tclunk, MAX_MESSAGE_LEN as u32 + 1);
+        // Readable descriptor following a writable one.
+        guest_q
+            .mem
+            .write_slice(&tclunk, GuestAddress(0x10_6000))
+            .unwrap();
+        guest_q.dtable[6].set(
+            0x80_6000,
+            0x1000,
+            (VRING_DESC_F_WRITE | VRING_DESC_F_NEXT).try_into().unwrap(),
+            7,
+        );
+        guest_q.dtable[7].set(0x10_6000, tclunk.len() as u32, 0, 0);
+        guest_q.avail.ring[3].set(6);
+        guest_q.avail.idx.set(4);
+
+        assert!(handler.process_queue().unwrap());
+        assert_eq!(guest_q.used.idx.get(), 4);
+        for i in 0..4 {
+            assert_eq!(guest_q.used.ring[i].get().id(), i as u32 * 2);
+            assert_eq!(guest_q.used.ring[i].get().len(), 0);
+        }
+        assert!(read_server(&mut server).is_empty());
+        assert!(handler.pending.is_empty());
+    }
+
+    #[test]
+    fn test_fs9p_request_buffering() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), MEM_SIZE)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0), &mem, QSIZE);
+        let (device, mut server) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut handler = create_handler(&mem, &guest_q, device);
+
+        // A request larger than what the socket can hold is partially sent
+        // without blocking, the rest waits for the server.
+        let len = 4 << 20;
+        let twrite = message(118, 1, &vec![0xa5; len - HEADER_LEN]);
+        add_chain(&guest_q, 0, 0, &twrite, len as u32);
+        assert!(!handler.process_queue().unwrap());
+        assert!(!handler.tx.is_empty());
+        assert!(handler.tx.len() < len);
+
+        let mut received = Vec::new();
+        while received.len() < len {
+            received.extend(read_server(&mut server));
+            handler.flush_requests().unwrap();
+        }
+        assert!(handler.tx.is_empty());
+        assert_eq!(received, twrite);
+    }
+}
@@ -21,26 +21,33 @@ use thiserror::Error;
 
 #[macro_use]
 mod device;
+#[cfg(feature = "balloon")]
 pub mod balloon;
 pub mod block;
 mod console;
 pub mod epoll_helper;
+mod fs9p;
 mod iommu;
 pub mod mem;
 pub mod net;
 mod pmem;
+#[cfg(feature = "rng")]
 mod rng;
 pub mod seccomp_filters;
 mod thread_helper;
 pub mod transport;
+#[cfg(feature = "vdpa")]
 pub mod vdpa;
 pub mod vhost_user;
+#[cfg(feature = "vsock")]
 pub mod vsock;
+#[cfg(feature = "watchdog")]
 pub mod watchdog;
 
+#[cfg(feature = "balloon")]
 pub use self::balloon::Balloon;
-pub use self::block::{Block, BlockState};
-pub use self::console::{Console, ConsoleResizer, Endpoint};
+pub use self::block::{Block, BlockState, BlockTracer, OnIoError};
+pub use self::console::{Console, ConsoleResizer, Endpoint, SocketListener};
 pub use self::device::{
     DmaRemapping, UserspaceMapping, VirtioCommon, VirtioDevice, VirtioInterrupt,
     VirtioInterruptType, VirtioSharedMemoryList,
@@ -48,13 +55,18 @@ pub use self::device::{
 pub use self::epoll_helper::{
     EpollHelper, EpollHelperError, EpollHelperHandler, EPOLL_HELPER_EVENT_LAST,
 };
+pub use self::fs9p::Fs9p;
 pub use self::iommu::{AccessPlatformMapping, Iommu, IommuMapping};
 pub use self::mem::{BlocksState, Mem, VirtioMemMappingSource, VIRTIO_MEM_ALIGN_SIZE};
 pub use self::net::{Net, NetCtrlEpollHandler};
 pub use self::pmem::Pmem;
+#[cfg(feature = "rng")]
 pub use self::rng::Rng;
+#[cfg(feature = "vdpa")]
 pub use self::vdpa::{Vdpa, VdpaDmaMapping};
+#[cfg(feature = "vsock")]
 pub use self::vsock::Vsock;
+#[cfg(feature = "watchdog")]
 pub use self::watchdog::Watchdog;
 use vm_memory::{bitmap::AtomicBitmap, GuestAddress, GuestMemory};
 use vm_virtio::VirtioDeviceType;
@@ -97,6 +109,7 @@ pub enum ActivateError {
     #[error("Failed to create rate limiter: {0}")]
     CreateRateLimiter(std::io::Error),
     #[error("Failed to activate the vDPA device: {0}")]
+    #[cfg(feature = "vdpa")]
     ActivateVdpa(vdpa::Error),
 }
 
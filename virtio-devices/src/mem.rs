@@ -683,6 +683,8 @@ impl EpollHelperHandler for MemEpollHandler {
 pub enum VirtioMemMappingSource {
     Container,
     Device(u32),
+    // Host access to the memory of a confidential guest
+    Isolation,
 }
 
 #[derive(Serialize, Deserialize)]
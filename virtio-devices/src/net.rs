@@ -22,6 +22,7 @@ use net_util::CtrlQueue;
 use net_util::{
     build_net_config_space, build_net_config_space_with_mq, open_tap, MacAddr, NetCounters,
     NetQueuePair, OpenTapError, RxVirtio, Tap, TapError, TxVirtio, VirtioNetConfig,
+    VIRTIO_NET_F_STANDBY,
 };
 use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
@@ -446,6 +447,7 @@ impl Net {
         offload_tso: bool,
         offload_ufo: bool,
         offload_csum: bool,
+        standby: bool,
     ) -> Result<Self> {
         assert!(!taps.is_empty());
 
@@ -489,6 +491,10 @@ impl Net {
                     }
                 }
 
+                if standby {
+                    avail_features |= 1 << VIRTIO_NET_F_STANDBY;
+                }
+
                 avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ;
                 let queue_num = num_queues + 1;
 
@@ -562,6 +568,7 @@ impl Net {
         offload_tso: bool,
         offload_ufo: bool,
         offload_csum: bool,
+        standby: bool,
     ) -> Result<Self> {
         let taps = open_tap(
             if_name,
@@ -588,6 +595,7 @@ impl Net {
             offload_tso,
             offload_ufo,
             offload_csum,
+            standby,
         )
     }
 
@@ -606,6 +614,7 @@ impl Net {
         offload_tso: bool,
         offload_ufo: bool,
         offload_csum: bool,
+        standby: bool,
     ) -> Result<Self> {
         let mut taps: Vec<Tap> = Vec::new();
         let num_queue_pairs = fds.len();
@@ -641,6 +650,7 @@ impl Net {
             offload_tso,
             offload_ufo,
             offload_csum,
+            standby,
         )
     }
 
@@ -692,6 +702,10 @@ impl VirtioDevice for Net {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.as_slice(), offset, data);
     }
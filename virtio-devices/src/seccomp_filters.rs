@@ -13,6 +13,7 @@ pub enum Thread {
     VirtioBalloon,
     VirtioBlock,
     VirtioConsole,
+    VirtioFs9p,
     VirtioIommu,
     VirtioMem,
     VirtioNet,
@@ -21,6 +22,7 @@ pub enum Thread {
     VirtioRng,
     VirtioVhostBlock,
     VirtioVhostFs,
+    VirtioVhostGpu,
     VirtioVhostNet,
     VirtioVhostNetCtl,
     VirtioVsock,
@@ -49,6 +51,9 @@ macro_rules! or {
 const TIOCGWINSZ: u64 = 0x5413;
 const FIONBIO: u64 = 0x5421;
 
+// See include/uapi/linux/fs.h in the kernel code.
+const BLKSECDISCARD: u64 = 0x127d;
+
 // See include/uapi/linux/vfio.h in the kernel code.
 const VFIO_IOMMU_MAP_DMA: u64 = 0x3b71;
 const VFIO_IOMMU_UNMAP_DMA: u64 = 0x3b72;
@@ -85,6 +90,10 @@ fn create_virtio_mem_ioctl_seccomp_rule() -> Vec<SeccompRule> {
     ]
 }
 
+fn create_virtio_block_ioctl_seccomp_rule() -> Vec<SeccompRule> {
+    or![and![Cond::new(1, ArgLen::Dword, Eq, BLKSECDISCARD).unwrap()]]
+}
+
 fn virtio_balloon_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![(libc::SYS_fallocate, vec![])]
 }
@@ -93,6 +102,7 @@ fn virtio_block_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
         (libc::SYS_fallocate, vec![]),
         (libc::SYS_fdatasync, vec![]),
+        (libc::SYS_fstat, vec![]),
         (libc::SYS_fsync, vec![]),
         (libc::SYS_ftruncate, vec![]),
         (libc::SYS_getrandom, vec![]),
@@ -100,7 +110,10 @@ fn virtio_block_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
         (libc::SYS_io_getevents, vec![]),
         (libc::SYS_io_submit, vec![]),
         (libc::SYS_io_uring_enter, vec![]),
+        (libc::SYS_ioctl, create_virtio_block_ioctl_seccomp_rule()),
+        (libc::SYS_ioprio_set, vec![]),
         (libc::SYS_lseek, vec![]),
+        (libc::SYS_newfstatat, vec![]),
         (libc::SYS_pread64, vec![]),
         (libc::SYS_preadv, vec![]),
         (libc::SYS_pwritev, vec![]),
@@ -114,6 +127,7 @@ fn virtio_block_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
 
 fn virtio_console_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
+        (libc::SYS_accept4, vec![]),
         (libc::SYS_ioctl, create_virtio_console_ioctl_seccomp_rule()),
         (libc::SYS_sched_getaffinity, vec![]),
         (libc::SYS_set_robust_list, vec![]),
@@ -149,6 +163,10 @@ fn virtio_net_ctl_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![(libc::SYS_ioctl, create_virtio_net_ctl_ioctl_seccomp_rule())]
 }
 
+fn virtio_fs9p_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![(libc::SYS_recvfrom, vec![]), (libc::SYS_sendto, vec![])]
+}
+
 fn virtio_pmem_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![(libc::SYS_fsync, vec![])]
 }
@@ -209,6 +227,17 @@ fn virtio_vhost_block_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     ]
 }
 
+fn virtio_vhost_gpu_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![
+        (libc::SYS_clock_nanosleep, vec![]),
+        (libc::SYS_connect, vec![]),
+        (libc::SYS_nanosleep, vec![]),
+        (libc::SYS_recvmsg, vec![]),
+        (libc::SYS_sendmsg, vec![]),
+        (libc::SYS_socket, vec![]),
+    ]
+}
+
 fn create_vsock_ioctl_seccomp_rule() -> Vec<SeccompRule> {
     or![and![Cond::new(1, ArgLen::Dword, Eq, FIONBIO,).unwrap()],]
 }
@@ -217,9 +246,16 @@ fn virtio_vsock_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
         (libc::SYS_accept4, vec![]),
         (libc::SYS_connect, vec![]),
+        (libc::SYS_getsockopt, vec![]),
         (libc::SYS_ioctl, create_vsock_ioctl_seccomp_rule()),
+        #[cfg(target_arch = "x86_64")]
+        (libc::SYS_poll, vec![]),
+        #[cfg(target_arch = "aarch64")]
+        (libc::SYS_ppoll, vec![]),
         (libc::SYS_recvfrom, vec![]),
+        (libc::SYS_setsockopt, vec![]),
         (libc::SYS_socket, vec![]),
+        (libc::SYS_writev, vec![]),
         // If debug_assertions is enabled, closing a file first checks
         // whether the FD is valid with fcntl.
         #[cfg(debug_assertions)]
@@ -240,6 +276,7 @@ fn get_seccomp_rules(thread_type: Thread) -> Vec<(i64, Vec<SeccompRule>)> {
         Thread::VirtioBalloon => virtio_balloon_thread_rules(),
         Thread::VirtioBlock => virtio_block_thread_rules(),
         Thread::VirtioConsole => virtio_console_thread_rules(),
+        Thread::VirtioFs9p => virtio_fs9p_thread_rules(),
         Thread::VirtioIommu => virtio_iommu_thread_rules(),
         Thread::VirtioMem => virtio_mem_thread_rules(),
         Thread::VirtioNet => virtio_net_thread_rules(),
@@ -248,6 +285,7 @@ fn get_seccomp_rules(thread_type: Thread) -> Vec<(i64, Vec<SeccompRule>)> {
         Thread::VirtioRng => virtio_rng_thread_rules(),
         Thread::VirtioVhostBlock => virtio_vhost_block_thread_rules(),
         Thread::VirtioVhostFs => virtio_vhost_fs_thread_rules(),
+        Thread::VirtioVhostGpu => virtio_vhost_gpu_thread_rules(),
         Thread::VirtioVhostNet => virtio_vhost_net_thread_rules(),
         Thread::VirtioVhostNetCtl => virtio_vhost_net_ctl_thread_rules(),
         Thread::VirtioVsock => virtio_vsock_thread_rules(),
@@ -17,6 +17,9 @@ use vm_virtio::AccessPlatform;
 
 pub const VIRTIO_PCI_COMMON_CONFIG_ID: &str = "virtio_pci_common_config";
 
+/// Vector value used to disable MSI for a queue.
+pub const VIRTQ_MSI_NO_VECTOR: u16 = 0xffff;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct VirtioPciCommonConfigState {
     pub driver_status: u8,
@@ -158,6 +161,21 @@ impl VirtioPciCommonConfig {
         }
     }
 
+    /// Resets the registers owned by the driver when it resets the device.
+    /// The queues lose their readiness, size and addresses, and the feature
+    /// and queue selectors as well as the MSI-X vectors get back to their
+    /// initial value, so that the driver initializing the device again, e.g.
+    /// on resume from suspend, doesn't inherit the previous configuration.
+    pub fn reset(&mut self, queues: &mut [Queue]) {
+        queues.iter_mut().for_each(Queue::reset);
+        self.queue_select = 0;
+        self.device_feature_select = 0;
+        self.driver_feature_select = 0;
+        self.msix_config
+            .store(VIRTQ_MSI_NO_VECTOR, Ordering::Release);
+        self.msix_queues.lock().unwrap().fill(VIRTQ_MSI_NO_VECTOR);
+    }
+
     pub fn read(
         &mut self,
         offset: u64,
@@ -492,4 +510,46 @@ mod tests {
         assert_eq!(read_back[0], 0xaa);
         assert_eq!(read_back[1], 0x55);
     }
+
+    #[test]
+    fn reset_regs() {
+        let mut regs = VirtioPciCommonConfig {
+            access_platform: None,
+            driver_status: 0,
+            config_generation: 0,
+            device_feature_select: 0x1,
+            driver_feature_select: 0x1,
+            queue_select: 0x0,
+            msix_config: Arc::new(AtomicU16::new(0)),
+            msix_queues: Arc::new(Mutex::new(vec![VIRTQ_MSI_NO_VECTOR; 2])),
+        };
+
+        let dev = Arc::new(Mutex::new(DummyDevice(0)));
+        let mut queues = vec![Queue::new(QUEUE_SIZE).unwrap(); 2];
+
+        // The driver sets up the second queue.
+        regs.write(0x16, &[0x1, 0x0], &mut queues, dev.clone());
+        regs.write(0x18, &[0x10, 0x0], &mut queues, dev.clone());
+        regs.write(0x1a, &[0x1, 0x0], &mut queues, dev.clone());
+        regs.write(0x1c, &[0x1, 0x0], &mut queues, dev);
+        assert_eq!(queues[1].size(), 0x10);
+        assert!(queues[1].ready());
+
+        regs.reset(&mut queues);
+        assert_eq!(regs.queue_select, 0);
+        assert_eq!(regs.device_feature_select, 0);
+        assert_eq!(regs.driver_feature_select, 0);
+        assert_eq!(
+            regs.msix_config.load(Ordering::Acquire),
+            VIRTQ_MSI_NO_VECTOR
+        );
+        assert_eq!(
+            *regs.msix_queues.lock().unwrap(),
+            vec![VIRTQ_MSI_NO_VECTOR; 2]
+        );
+        for q in queues.iter() {
+            assert_eq!(q.size(), QUEUE_SIZE);
+            assert!(!q.ready());
+        }
+    }
 }
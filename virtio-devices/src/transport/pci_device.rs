@@ -40,10 +40,7 @@ use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottabl
 use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
 
-use super::pci_common_config::VirtioPciCommonConfigState;
-
-/// Vector value used to disable MSI for a queue.
-const VIRTQ_MSI_NO_VECTOR: u16 = 0xffff;
+use super::pci_common_config::{VirtioPciCommonConfigState, VIRTQ_MSI_NO_VECTOR};
 
 enum PciCapabilityType {
     Common = 1,
@@ -312,6 +309,8 @@ impl VirtioPciDeviceActivator {
 pub enum VirtioPciDeviceError {
     #[error("Failed creating VirtioPciDevice: {0}")]
     CreateVirtioPciDevice(#[source] anyhow::Error),
+    #[error("Failed swapping the virtio device: {0}")]
+    SwapVirtioDevice(#[source] anyhow::Error),
 }
 pub type Result<T> = std::result::Result<T, VirtioPciDeviceError>;
 
@@ -596,14 +595,7 @@ impl VirtioPciDevice {
             pending_activations,
         };
 
-        if let Some(msix_config) = &virtio_pci_device.msix_config {
-            virtio_pci_device.virtio_interrupt = Some(Arc::new(VirtioInterruptMsix::new(
-                msix_config.clone(),
-                virtio_pci_device.common_config.msix_config.clone(),
-                virtio_pci_device.common_config.msix_queues.clone(),
-                virtio_pci_device.interrupt_source_group.clone(),
-            )));
-        }
+        virtio_pci_device.virtio_interrupt = virtio_pci_device.msix_interrupt();
 
         // In case of a restore, we can activate the device, as we know at
         // this point the virtqueues are in the right state and the device is
@@ -786,6 +778,86 @@ impl VirtioPciDevice {
         self.device.clone()
     }
 
+    /// Replaces the virtio device behind the transport, which the guest
+    /// doesn't notice as long as the new device is of the same type, with the
+    /// same queues, and offers the features the driver acknowledged. An
+    /// activated device is reset, which stops it processing the virtqueues,
+    /// and the new one is activated to take them over where it left them.
+    /// Returns the replaced device.
+    pub fn swap_virtio_device(
+        &mut self,
+        device: Arc<Mutex<dyn VirtioDevice>>,
+    ) -> Result<Arc<Mutex<dyn VirtioDevice>>> {
+        {
+            let old_device = self.device.lock().unwrap();
+            let mut new_device = device.lock().unwrap();
+            if new_device.device_type() != old_device.device_type()
+                || new_device.queue_max_sizes() != old_device.queue_max_sizes()
+            {
+                return Err(VirtioPciDeviceError::SwapVirtioDevice(anyhow!(
+                    "Device type or queues differ"
+                )));
+            }
+
+            if let Some(access_platform) = &self.common_config.access_platform {
+                new_device.set_access_platform(access_platform.clone());
+            }
+
+            let acked_features = old_device.acked_features();
+            let missing_features = acked_features & !new_device.features();
+            if missing_features != 0 {
+                return Err(VirtioPciDeviceError::SwapVirtioDevice(anyhow!(
+                    "Missing acknowledged features 0x{:x}",
+                    missing_features
+                )));
+            }
+            new_device.ack_features(acked_features);
+        }
+
+        let old_device = std::mem::replace(&mut self.device, device);
+        if !self.device_activated.load(Ordering::SeqCst) {
+            return Ok(old_device);
+        }
+
+        // The interrupt returned by the device on reset is the one created
+        // with the transport, which is created again in case the device
+        // couldn't be reset cleanly, e.g. its backend being gone.
+        if old_device.lock().unwrap().reset().is_none() {
+            warn!("{}: Failed resetting the replaced device", self.id);
+        }
+        self.device_activated.store(false, Ordering::SeqCst);
+        self.virtio_interrupt = self.msix_interrupt();
+
+        // The device has been stopped between two requests, so everything
+        // made available to it has been used.
+        let memory = self.memory.memory();
+        for queue in self.queues.iter_mut().filter(|q| q.ready()) {
+            let used_idx = queue
+                .used_idx(memory.deref(), Ordering::Acquire)
+                .map_err(|e| VirtioPciDeviceError::SwapVirtioDevice(anyhow!("{:?}", e)))?
+                .0;
+            queue.set_next_avail(used_idx);
+            queue.set_next_used(used_idx);
+        }
+
+        self.activate().map_err(|e| {
+            VirtioPciDeviceError::SwapVirtioDevice(anyhow!("Failed activating the device: {:?}", e))
+        })?;
+
+        Ok(old_device)
+    }
+
+    fn msix_interrupt(&self) -> Option<Arc<dyn VirtioInterrupt>> {
+        self.msix_config.as_ref().map(|msix_config| {
+            Arc::new(VirtioInterruptMsix::new(
+                msix_config.clone(),
+                self.common_config.msix_config.clone(),
+                self.common_config.msix_queues.clone(),
+                self.interrupt_source_group.clone(),
+            )) as Arc<dyn VirtioInterrupt>
+        })
+    }
+
     fn prepare_activator(&mut self, barrier: Option<Arc<Barrier>>) -> VirtioPciDeviceActivator {
         let mut queues = Vec::new();
 
@@ -1163,6 +1235,8 @@ impl PciDevice for VirtioPciDevice {
     }
 
     fn write_bar(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        let driver_status = self.common_config.driver_status;
+
         match offset {
             o if o < COMMON_CONFIG_BAR_OFFSET + COMMON_CONFIG_SIZE => self.common_config.write(
                 o - COMMON_CONFIG_BAR_OFFSET,
@@ -1229,21 +1303,27 @@ impl PciDevice for VirtioPciDevice {
         }
 
         // Device has been reset by the driver
-        if self.device_activated.load(Ordering::SeqCst) && self.is_driver_init() {
+        if driver_status != DEVICE_INIT as u8 && self.is_driver_init() {
             let mut device = self.device.lock().unwrap();
-            if let Some(virtio_interrupt) = device.reset() {
-                // Upon reset the device returns its interrupt EventFD
-                self.virtio_interrupt = Some(virtio_interrupt);
-                self.device_activated.store(false, Ordering::SeqCst);
-
-                // Reset queue readiness (changes queue_enable), queue sizes
-                // and selected_queue as per spec for reset
-                self.queues.iter_mut().for_each(Queue::reset);
-                self.common_config.queue_select = 0;
+            if self.device_activated.load(Ordering::SeqCst) {
+                if let Some(virtio_interrupt) = device.reset() {
+                    // Upon reset the device returns its interrupt EventFD
+                    self.virtio_interrupt = Some(virtio_interrupt);
+                    self.device_activated.store(false, Ordering::SeqCst);
+                } else {
+                    error!("Attempt to reset device when not implemented in underlying device");
+                    self.common_config.driver_status = crate::DEVICE_FAILED as u8;
+                    return None;
+                }
             } else {
-                error!("Attempt to reset device when not implemented in underlying device");
-                self.common_config.driver_status = crate::DEVICE_FAILED as u8;
+                // The driver gave up on initializing the device, but it may
+                // have acked some features already.
+                device.reset();
             }
+            drop(device);
+
+            self.common_config.reset(&mut self.queues);
+            self.interrupt_status.store(0, Ordering::Release);
         }
 
         None
@@ -439,6 +439,10 @@ impl VirtioDevice for Vdpa {
             return None;
         }
 
+        // The driver negotiates the features again before reactivating the
+        // device.
+        self.common.acked_features = 0;
+
         event!("vdpa", "reset", "id", &self.id);
 
         // Return the virtio interrupt handler
@@ -7,12 +7,12 @@ use super::{Error, Result, DEFAULT_VIRTIO_FEATURES};
 use crate::seccomp_filters::Thread;
 use crate::thread_helper::spawn_virtio_thread;
 use crate::vhost_user::VhostUserCommon;
+use crate::{ActivateError, VirtioInterrupt, VirtioInterruptType, VIRTIO_F_IOMMU_PLATFORM};
 use crate::{GuestMemoryMmap, GuestRegionMmap};
-use crate::{VirtioInterrupt, VIRTIO_F_IOMMU_PLATFORM};
-use block::VirtioBlockConfig;
+use block::{VirtioBlockConfig, VIRTIO_BLK_CONFIG_LEGACY_SIZE};
 use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
-use std::mem;
+use std::io;
 use std::result;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Barrier, Mutex};
@@ -22,7 +22,9 @@ use vhost::vhost_user::message::{
     VhostUserConfigFlags, VhostUserProtocolFeatures, VhostUserVirtioFeatures,
     VHOST_USER_CONFIG_OFFSET,
 };
-use vhost::vhost_user::{FrontendReqHandler, VhostUserFrontend, VhostUserFrontendReqHandler};
+use vhost::vhost_user::{
+    FrontendReqHandler, HandlerResult, VhostUserFrontend, VhostUserFrontendReqHandler,
+};
 use virtio_bindings::virtio_blk::{
     VIRTIO_BLK_F_BLK_SIZE, VIRTIO_BLK_F_CONFIG_WCE, VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH,
     VIRTIO_BLK_F_GEOMETRY, VIRTIO_BLK_F_MQ, VIRTIO_BLK_F_RO, VIRTIO_BLK_F_SEG_MAX,
@@ -38,6 +40,15 @@ use vmm_sys_util::eventfd::EventFd;
 
 const DEFAULT_QUEUE_NUMBER: usize = 1;
 
+// The backend request channel lets the backend notify configuration changes.
+const AVAIL_PROTOCOL_FEATURES: VhostUserProtocolFeatures = VhostUserProtocolFeatures::CONFIG
+    .union(VhostUserProtocolFeatures::MQ)
+    .union(VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS)
+    .union(VhostUserProtocolFeatures::REPLY_ACK)
+    .union(VhostUserProtocolFeatures::INFLIGHT_SHMFD)
+    .union(VhostUserProtocolFeatures::LOG_SHMFD)
+    .union(VhostUserProtocolFeatures::BACKEND_REQ);
+
 #[derive(Serialize, Deserialize)]
 pub struct State {
     pub avail_features: u64,
@@ -47,14 +58,83 @@ pub struct State {
     pub vu_num_queues: usize,
 }
 
-struct BackendReqHandler {}
-impl VhostUserFrontendReqHandler for BackendReqHandler {}
+// Only the configuration space known to all backends is fetched, the secure
+// erase fields being left unset as the feature isn't offered over vhost-user.
+fn get_backend_config(vu: &mut VhostUserHandle) -> Result<Option<VirtioBlockConfig>> {
+    let config_len = VIRTIO_BLK_CONFIG_LEGACY_SIZE;
+    let config_space: Vec<u8> = vec![0u8; config_len];
+    let (_, config_space) = vu
+        .socket_handle()
+        .get_config(
+            VHOST_USER_CONFIG_OFFSET,
+            config_len as u32,
+            VhostUserConfigFlags::WRITABLE,
+            config_space.as_slice(),
+        )
+        .map_err(Error::VhostUserGetConfig)?;
+    if config_space.len() != config_len {
+        return Ok(None);
+    }
+
+    let mut config = VirtioBlockConfig::default();
+    config.as_mut_slice()[..config_len].copy_from_slice(&config_space);
+    Ok(Some(config))
+}
+
+// Fetch the configuration from the backend, and let the guest know when it
+// changed, e.g. after the backend resized the disk or got upgraded.
+fn refresh_config(
+    vu: &mut VhostUserHandle,
+    config: &Mutex<VirtioBlockConfig>,
+    interrupt_cb: &Arc<dyn VirtioInterrupt>,
+) -> Result<()> {
+    let Some(mut new_config) = get_backend_config(vu)? else {
+        return Ok(());
+    };
+
+    let mut config = config.lock().unwrap();
+    // The number of queues and the writeback mode are owned by the frontend.
+    new_config.num_queues = config.num_queues;
+    new_config.writeback = config.writeback;
+    if new_config.as_slice() == config.as_slice() {
+        return Ok(());
+    }
+
+    let capacity = new_config.capacity;
+    info!(
+        "vhost-user-blk configuration changed, capacity {} sectors",
+        capacity
+    );
+    *config = new_config;
+    interrupt_cb
+        .trigger(VirtioInterruptType::Config)
+        .map_err(Error::FailedSignalingUsedQueue)
+}
+
+struct BackendReqHandler {
+    vu: Arc<Mutex<VhostUserHandle>>,
+    config: Arc<Mutex<VirtioBlockConfig>>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+}
+
+impl VhostUserFrontendReqHandler for BackendReqHandler {
+    fn handle_config_change(&self) -> HandlerResult<u64> {
+        debug!("handle_config_change");
+        refresh_config(
+            &mut self.vu.lock().unwrap(),
+            &self.config,
+            &self.interrupt_cb,
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{e:?}")))?;
+        Ok(0)
+    }
+}
 
 pub struct Blk {
     common: VirtioCommon,
     vu_common: VhostUserCommon,
     id: String,
-    config: VirtioBlockConfig,
+    config: Arc<Mutex<VirtioBlockConfig>>,
     guest_memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
     epoll_thread: Option<thread::JoinHandle<()>>,
     seccomp_action: SeccompAction,
@@ -118,12 +198,7 @@ impl Blk {
                 avail_features |= 1 << VIRTIO_BLK_F_MQ;
             }
 
-            let avail_protocol_features = VhostUserProtocolFeatures::CONFIG
-                | VhostUserProtocolFeatures::MQ
-                | VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS
-                | VhostUserProtocolFeatures::REPLY_ACK
-                | VhostUserProtocolFeatures::INFLIGHT_SHMFD
-                | VhostUserProtocolFeatures::LOG_SHMFD;
+            let avail_protocol_features = AVAIL_PROTOCOL_FEATURES;
 
             let (acked_features, acked_protocol_features) =
                 vu.negotiate_features_vhost_user(avail_features, avail_protocol_features)?;
@@ -143,20 +218,9 @@ impl Blk {
                 return Err(Error::BadQueueNum);
             }
 
-            let config_len = mem::size_of::<VirtioBlockConfig>();
-            let config_space: Vec<u8> = vec![0u8; config_len];
-            let (_, config_space) = vu
-                .socket_handle()
-                .get_config(
-                    VHOST_USER_CONFIG_OFFSET,
-                    config_len as u32,
-                    VhostUserConfigFlags::WRITABLE,
-                    config_space.as_slice(),
-                )
-                .map_err(Error::VhostUserGetConfig)?;
             let mut config = VirtioBlockConfig::default();
-            if let Some(backend_config) = VirtioBlockConfig::from_slice(config_space.as_slice()) {
-                config = *backend_config;
+            if let Some(backend_config) = get_backend_config(&mut vu)? {
+                config = backend_config;
                 config.num_queues = num_queues as u16;
             }
 
@@ -188,12 +252,13 @@ impl Blk {
             vu_common: VhostUserCommon {
                 vu: Some(Arc::new(Mutex::new(vu))),
                 acked_protocol_features,
+                avail_protocol_features: AVAIL_PROTOCOL_FEATURES.bits(),
                 socket_path: vu_cfg.socket,
                 vu_num_queues,
                 ..Default::default()
             },
             id,
-            config,
+            config: Arc::new(Mutex::new(config)),
             guest_memory: None,
             epoll_thread: None,
             seccomp_action,
@@ -206,8 +271,8 @@ impl Blk {
         State {
             avail_features: self.common.avail_features,
             acked_features: self.common.acked_features,
-            config: self.config,
-            acked_protocol_features: self.vu_common.acked_protocol_features,
+            config: *self.config.lock().unwrap(),
+            acked_protocol_features: self.vu_common.current_protocol_features(),
             vu_num_queues: self.vu_common.vu_num_queues,
         }
     }
@@ -251,15 +316,15 @@ impl VirtioDevice for Blk {
     }
 
     fn read_config(&self, offset: u64, data: &mut [u8]) {
-        self.read_config_from_slice(self.config.as_slice(), offset, data);
+        self.read_config_from_slice(self.config.lock().unwrap().as_slice(), offset, data);
     }
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
+        let mut config = self.config.lock().unwrap();
         // The "writeback" field is the only mutable field
         let writeback_offset =
-            (&self.config.writeback as *const _ as u64) - (&self.config as *const _ as u64);
-        if offset != writeback_offset || data.len() != std::mem::size_of_val(&self.config.writeback)
-        {
+            (&config.writeback as *const _ as u64) - (&*config as *const _ as u64);
+        if offset != writeback_offset || data.len() != std::mem::size_of_val(&config.writeback) {
             error!(
                 "Attempt to write to read-only field: offset {:x} length {}",
                 offset,
@@ -268,7 +333,7 @@ impl VirtioDevice for Blk {
             return;
         }
 
-        self.config.writeback = data[0];
+        config.writeback = data[0];
         if let Some(vu) = &self.vu_common.vu {
             if let Err(e) = vu
                 .lock()
@@ -291,7 +356,31 @@ impl VirtioDevice for Blk {
         self.common.activate(&queues, &interrupt_cb)?;
         self.guest_memory = Some(mem.clone());
 
-        let backend_req_handler: Option<FrontendReqHandler<BackendReqHandler>> = None;
+        let backend_req_handler = if self.vu_common.acked_protocol_features
+            & VhostUserProtocolFeatures::BACKEND_REQ.bits()
+            != 0
+        {
+            let vu_frontend_req_handler = Arc::new(BackendReqHandler {
+                vu: self.vu_common.vu.clone().unwrap(),
+                config: self.config.clone(),
+                interrupt_cb: interrupt_cb.clone(),
+            });
+
+            let mut req_handler = FrontendReqHandler::new(vu_frontend_req_handler)
+                .map_err(|e| ActivateError::VhostUserSetup(Error::FrontendReqHandlerCreation(e)))?;
+
+            if self.vu_common.acked_protocol_features & VhostUserProtocolFeatures::REPLY_ACK.bits()
+                != 0
+            {
+                req_handler.set_reply_ack_flag(true);
+            }
+
+            Some(req_handler)
+        } else {
+            None
+        };
+        let config = self.config.clone();
+        let reconnect_interrupt_cb = interrupt_cb.clone();
 
         // Run a dedicated thread for handling potential reconnections with
         // the backend.
@@ -307,6 +396,13 @@ impl VirtioDevice for Blk {
             pause_evt,
         )?;
 
+        // A reconnected backend might expose a different configuration.
+        handler.on_reconnect = Some(Box::new(move |vu| {
+            if let Err(e) = refresh_config(vu, &config, &reconnect_interrupt_cb) {
+                error!("Failed refreshing vhost-user-blk configuration: {:?}", e);
+            }
+        }));
+
         let paused = self.common.paused.clone();
         let paused_sync = self.common.paused_sync.clone();
 
@@ -326,11 +422,6 @@ impl VirtioDevice for Blk {
     }
 
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
-        // We first must resume the virtio thread if it was paused.
-        if self.common.pause_evt.take().is_some() {
-            self.common.resume().ok()?;
-        }
-
         if let Some(vu) = &self.vu_common.vu {
             if let Err(e) = vu.lock().unwrap().reset_vhost_user() {
                 error!("Failed to reset vhost-user daemon: {:?}", e);
@@ -338,15 +429,9 @@ impl VirtioDevice for Blk {
             }
         }
 
-        if let Some(kill_evt) = self.common.kill_evt.take() {
-            // Ignore the result because there is nothing we can do about it.
-            let _ = kill_evt.write(1);
-        }
-
+        let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
-
-        // Return the interrupt
-        Some(self.common.interrupt_cb.take().unwrap())
+        result
     }
 
     fn shutdown(&mut self) {
@@ -457,7 +457,7 @@ impl Fs {
             avail_features: self.common.avail_features,
             acked_features: self.common.acked_features,
             config: self.config,
-            acked_protocol_features: self.vu_common.acked_protocol_features,
+            acked_protocol_features: self.vu_common.current_protocol_features(),
             vu_num_queues: self.vu_common.vu_num_queues,
             backend_req_support: self.backend_req_support,
         }
@@ -576,11 +576,6 @@ impl VirtioDevice for Fs {
     }
 
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
-        // We first must resume the virtio thread if it was paused.
-        if self.common.pause_evt.take().is_some() {
-            self.common.resume().ok()?;
-        }
-
         if let Some(vu) = &self.vu_common.vu {
             if let Err(e) = vu.lock().unwrap().reset_vhost_user() {
                 error!("Failed to reset vhost-user daemon: {:?}", e);
@@ -588,15 +583,9 @@ impl VirtioDevice for Fs {
             }
         }
 
-        if let Some(kill_evt) = self.common.kill_evt.take() {
-            // Ignore the result because there is nothing we can do about it.
-            let _ = kill_evt.write(1);
-        }
-
+        let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
-
-        // Return the interrupt
-        Some(self.common.interrupt_cb.take().unwrap())
+        result
     }
 
     fn shutdown(&mut self) {
@@ -0,0 +1,481 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::{ActivateResult, VirtioCommon, VirtioDevice, VirtioDeviceType};
+use super::vu_common_ctrl::{VhostUserConfig, VhostUserHandle};
+use super::{Error, Result, DEFAULT_VIRTIO_FEATURES};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::vhost_user::VhostUserCommon;
+use crate::{ActivateError, VirtioInterrupt, VirtioInterruptType, VIRTIO_F_IOMMU_PLATFORM};
+use crate::{GuestMemoryMmap, GuestRegionMmap};
+use seccompiler::SeccompAction;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::mem::size_of;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use vhost::vhost_user::message::{
+    VhostUserConfigFlags, VhostUserProtocolFeatures, VhostUserVirtioFeatures,
+    VHOST_USER_CONFIG_OFFSET,
+};
+use vhost::vhost_user::{
+    FrontendReqHandler, HandlerResult, VhostUserFrontend, VhostUserFrontendReqHandler,
+};
+use virtio_queue::Queue;
+use vm_memory::{ByteValued, GuestMemoryAtomic};
+use vm_migration::{
+    protocol::MemoryRangeTable, Migratable, MigratableError, Pausable, Snapshot, Snapshottable,
+    Transportable,
+};
+use vmm_sys_util::eventfd::EventFd;
+
+// Control and cursor queues.
+const NUM_QUEUES: usize = 2;
+
+// Device features
+const VIRTIO_GPU_F_VIRGL: u64 = 0;
+const VIRTIO_GPU_F_EDID: u64 = 1;
+const VIRTIO_GPU_F_CONTEXT_INIT: u64 = 4;
+
+// Only event of the configuration space, a display was hotplugged or resized.
+const VIRTIO_GPU_EVENT_DISPLAY: u32 = 1 << 0;
+
+// The backend request channel lets the backend notify display events.
+const AVAIL_PROTOCOL_FEATURES: VhostUserProtocolFeatures = VhostUserProtocolFeatures::CONFIG
+    .union(VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS)
+    .union(VhostUserProtocolFeatures::REPLY_ACK)
+    .union(VhostUserProtocolFeatures::LOG_SHMFD)
+    .union(VhostUserProtocolFeatures::BACKEND_REQ);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(C)]
+pub struct VirtioGpuConfig {
+    pub events_read: u32,
+    pub events_clear: u32,
+    pub num_scanouts: u32,
+    pub num_capsets: u32,
+}
+
+// SAFETY: only a series of integers
+unsafe impl ByteValued for VirtioGpuConfig {}
+
+#[derive(Serialize, Deserialize)]
+pub struct State {
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub config: VirtioGpuConfig,
+    pub acked_protocol_features: u64,
+    pub vu_num_queues: usize,
+}
+
+fn get_backend_config(vu: &mut VhostUserHandle) -> Result<VirtioGpuConfig> {
+    let config_len = size_of::<VirtioGpuConfig>();
+    let config_space: Vec<u8> = vec![0u8; config_len];
+    let (_, config_space) = vu
+        .socket_handle()
+        .get_config(
+            VHOST_USER_CONFIG_OFFSET,
+            config_len as u32,
+            VhostUserConfigFlags::WRITABLE,
+            config_space.as_slice(),
+        )
+        .map_err(Error::VhostUserGetConfig)?;
+
+    let mut config = VirtioGpuConfig::default();
+    if config_space.len() == config_len {
+        config.as_mut_slice().copy_from_slice(&config_space);
+    }
+    Ok(config)
+}
+
+// Fetch the configuration from the backend, and let the guest know about the
+// pending display events, e.g. after a window of the backend got resized.
+fn refresh_config(
+    vu: &mut VhostUserHandle,
+    config: &Mutex<VirtioGpuConfig>,
+    interrupt_cb: &Arc<dyn VirtioInterrupt>,
+) -> Result<()> {
+    let new_config = get_backend_config(vu)?;
+
+    let mut config = config.lock().unwrap();
+    if new_config == *config {
+        return Ok(());
+    }
+
+    *config = new_config;
+    if config.events_read & VIRTIO_GPU_EVENT_DISPLAY == 0 {
+        return Ok(());
+    }
+
+    interrupt_cb
+        .trigger(VirtioInterruptType::Config)
+        .map_err(Error::FailedSignalingUsedQueue)
+}
+
+struct BackendReqHandler {
+    vu: Arc<Mutex<VhostUserHandle>>,
+    config: Arc<Mutex<VirtioGpuConfig>>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+}
+
+impl VhostUserFrontendReqHandler for BackendReqHandler {
+    fn handle_config_change(&self) -> HandlerResult<u64> {
+        debug!("handle_config_change");
+        refresh_config(
+            &mut self.vu.lock().unwrap(),
+            &self.config,
+            &self.interrupt_cb,
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{e:?}")))?;
+        Ok(0)
+    }
+}
+
+pub struct Gpu {
+    common: VirtioCommon,
+    vu_common: VhostUserCommon,
+    id: String,
+    config: Arc<Mutex<VirtioGpuConfig>>,
+    guest_memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+    epoll_thread: Option<thread::JoinHandle<()>>,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+    iommu: bool,
+}
+
+impl Gpu {
+    /// Create a new vhost-user-gpu device
+    pub fn new(
+        id: String,
+        socket: &str,
+        queue_size: u16,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        iommu: bool,
+        state: Option<State>,
+    ) -> Result<Gpu> {
+        let vu_cfg = VhostUserConfig {
+            socket: socket.to_string(),
+            num_queues: NUM_QUEUES,
+            queue_size,
+        };
+
+        let mut vu =
+            VhostUserHandle::connect_vhost_user(false, &vu_cfg.socket, NUM_QUEUES as u64, false)?;
+
+        let (
+            avail_features,
+            acked_features,
+            acked_protocol_features,
+            vu_num_queues,
+            config,
+            paused,
+        ) = if let Some(state) = state {
+            info!("Restoring vhost-user-gpu {}", id);
+
+            vu.set_protocol_features_vhost_user(
+                state.acked_features,
+                state.acked_protocol_features,
+            )?;
+
+            (
+                state.avail_features,
+                state.acked_features,
+                state.acked_protocol_features,
+                state.vu_num_queues,
+                state.config,
+                true,
+            )
+        } else {
+            // Filling device and vring features VMM supports. The blob
+            // resources aren't offered as they need a shared memory region
+            // the backend could map the host resources into.
+            let avail_features = 1 << VIRTIO_GPU_F_VIRGL
+                | 1 << VIRTIO_GPU_F_EDID
+                | 1 << VIRTIO_GPU_F_CONTEXT_INIT
+                | DEFAULT_VIRTIO_FEATURES;
+
+            let (acked_features, acked_protocol_features) =
+                vu.negotiate_features_vhost_user(avail_features, AVAIL_PROTOCOL_FEATURES)?;
+
+            // The number of scanouts and capability sets is owned by the
+            // backend, depending on its displays and renderers.
+            let config = get_backend_config(&mut vu)?;
+
+            (
+                acked_features,
+                // If part of the available features that have been acked,
+                // the PROTOCOL_FEATURES bit must be already set through
+                // the VIRTIO acked features as we know the guest would
+                // never ack it, thus the feature would be lost.
+                acked_features & VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits(),
+                acked_protocol_features,
+                NUM_QUEUES,
+                config,
+                false,
+            )
+        };
+
+        Ok(Gpu {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Gpu as u32,
+                queue_sizes: vec![vu_cfg.queue_size; NUM_QUEUES],
+                avail_features,
+                acked_features,
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                min_queues: NUM_QUEUES as u16,
+                paused: Arc::new(AtomicBool::new(paused)),
+                ..Default::default()
+            },
+            vu_common: VhostUserCommon {
+                vu: Some(Arc::new(Mutex::new(vu))),
+                acked_protocol_features,
+                avail_protocol_features: AVAIL_PROTOCOL_FEATURES.bits(),
+                socket_path: vu_cfg.socket,
+                vu_num_queues,
+                ..Default::default()
+            },
+            id,
+            config: Arc::new(Mutex::new(config)),
+            guest_memory: None,
+            epoll_thread: None,
+            seccomp_action,
+            exit_evt,
+            iommu,
+        })
+    }
+
+    fn state(&self) -> State {
+        State {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+            config: *self.config.lock().unwrap(),
+            acked_protocol_features: self.vu_common.current_protocol_features(),
+            vu_num_queues: self.vu_common.vu_num_queues,
+        }
+    }
+}
+
+impl Drop for Gpu {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            if let Err(e) = kill_evt.write(1) {
+                error!("failed to kill vhost-user-gpu: {:?}", e);
+            }
+        }
+        self.common.wait_for_epoll_threads();
+        if let Some(thread) = self.epoll_thread.take() {
+            if let Err(e) = thread.join() {
+                error!("Error joining thread: {:?}", e);
+            }
+        }
+    }
+}
+
+impl VirtioDevice for Gpu {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        let mut features = self.common.avail_features;
+        if self.iommu {
+            features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+        }
+        features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(self.config.lock().unwrap().as_slice(), offset, data);
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        let mut config = self.config.lock().unwrap();
+        // The "events_clear" field is the only mutable field
+        if offset as usize != std::mem::offset_of!(VirtioGpuConfig, events_clear)
+            || data.len() != size_of::<u32>()
+        {
+            error!(
+                "Attempt to write to read-only field: offset {:x} length {}",
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        let events_clear = u32::from_le_bytes(data.try_into().unwrap());
+        config.events_read &= !events_clear;
+        if let Some(vu) = &self.vu_common.vu {
+            if let Err(e) = vu
+                .lock()
+                .unwrap()
+                .socket_handle()
+                .set_config(offset as u32, VhostUserConfigFlags::WRITABLE, data)
+                .map_err(Error::VhostUserSetConfig)
+            {
+                error!("Failed setting vhost-user-gpu configuration: {:?}", e);
+            }
+        }
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &interrupt_cb)?;
+        self.guest_memory = Some(mem.clone());
+
+        let backend_req_handler = if self.vu_common.acked_protocol_features
+            & VhostUserProtocolFeatures::BACKEND_REQ.bits()
+            != 0
+        {
+            let vu_frontend_req_handler = Arc::new(BackendReqHandler {
+                vu: self.vu_common.vu.clone().unwrap(),
+                config: self.config.clone(),
+                interrupt_cb: interrupt_cb.clone(),
+            });
+
+            let mut req_handler = FrontendReqHandler::new(vu_frontend_req_handler)
+                .map_err(|e| ActivateError::VhostUserSetup(Error::FrontendReqHandlerCreation(e)))?;
+
+            if self.vu_common.acked_protocol_features & VhostUserProtocolFeatures::REPLY_ACK.bits()
+                != 0
+            {
+                req_handler.set_reply_ack_flag(true);
+            }
+
+            Some(req_handler)
+        } else {
+            None
+        };
+        let config = self.config.clone();
+        let reconnect_interrupt_cb = interrupt_cb.clone();
+
+        // Run a dedicated thread for handling potential reconnections with
+        // the backend.
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        let mut handler = self.vu_common.activate(
+            mem,
+            queues,
+            interrupt_cb,
+            self.common.acked_features,
+            backend_req_handler,
+            kill_evt,
+            pause_evt,
+        )?;
+
+        // A reconnected backend might drive different displays.
+        handler.on_reconnect = Some(Box::new(move |vu| {
+            if let Err(e) = refresh_config(vu, &config, &reconnect_interrupt_cb) {
+                error!("Failed refreshing vhost-user-gpu configuration: {:?}", e);
+            }
+        }));
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+
+        let mut epoll_threads = Vec::new();
+
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioVhostGpu,
+            &mut epoll_threads,
+            &self.exit_evt,
+            move || handler.run(paused, paused_sync.unwrap()),
+        )?;
+        self.epoll_thread = Some(epoll_threads.remove(0));
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        if let Some(vu) = &self.vu_common.vu {
+            if let Err(e) = vu.lock().unwrap().reset_vhost_user() {
+                error!("Failed to reset vhost-user daemon: {:?}", e);
+                return None;
+            }
+        }
+
+        let result = self.common.reset();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn shutdown(&mut self) {
+        self.vu_common.shutdown()
+    }
+
+    fn add_memory_region(
+        &mut self,
+        region: &Arc<GuestRegionMmap>,
+    ) -> std::result::Result<(), crate::Error> {
+        self.vu_common.add_memory_region(&self.guest_memory, region)
+    }
+}
+
+impl Pausable for Gpu {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.vu_common.pause()?;
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()?;
+
+        if let Some(epoll_thread) = &self.epoll_thread {
+            epoll_thread.thread().unpark();
+        }
+
+        self.vu_common.resume()
+    }
+}
+
+impl Snapshottable for Gpu {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        self.vu_common.snapshot(&self.state())
+    }
+}
+impl Transportable for Gpu {}
+
+impl Migratable for Gpu {
+    fn start_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        self.vu_common.start_dirty_log(&self.guest_memory)
+    }
+
+    fn stop_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        self.vu_common.stop_dirty_log()
+    }
+
+    fn dirty_log(&mut self) -> std::result::Result<MemoryRangeTable, MigratableError> {
+        self.vu_common.dirty_log(&self.guest_memory)
+    }
+
+    fn start_migration(&mut self) -> std::result::Result<(), MigratableError> {
+        self.vu_common.start_migration()
+    }
+
+    fn complete_migration(&mut self) -> std::result::Result<(), MigratableError> {
+        self.vu_common
+            .complete_migration(self.common.kill_evt.take())
+    }
+}
@@ -31,11 +31,13 @@ use vu_common_ctrl::VhostUserHandle;
 
 pub mod blk;
 pub mod fs;
+pub mod gpu;
 pub mod net;
 pub mod vu_common_ctrl;
 
 pub use self::blk::Blk;
 pub use self::fs::*;
+pub use self::gpu::Gpu;
 pub use self::net::Net;
 pub use self::vu_common_ctrl::VhostUserConfig;
 
@@ -69,6 +71,8 @@ pub enum Error {
     VhostUserConnect,
     #[error("Get features failed: {0}")]
     VhostUserGetFeatures(VhostError),
+    #[error("Backend no longer supports the acked features {0:#x}")]
+    VhostUserFeaturesMissing(u64),
     #[error("Get queue max number failed: {0}")]
     VhostUserGetQueueMaxNum(VhostError),
     #[error("Get protocol features failed: {0}")]
@@ -163,6 +167,9 @@ pub struct Inflight {
     pub fd: Option<std::fs::File>,
 }
 
+// Called once a backend reconnected and got set up again.
+pub type ReconnectCallback = Box<dyn FnMut(&mut VhostUserHandle) + Send>;
+
 pub struct VhostUserEpollHandler<S: VhostUserFrontendReqHandler> {
     pub vu: Arc<Mutex<VhostUserHandle>>,
     pub mem: GuestMemoryAtomic<GuestMemoryMmap>,
@@ -172,10 +179,12 @@ pub struct VhostUserEpollHandler<S: VhostUserFrontendReqHandler> {
     pub virtio_interrupt: Arc<dyn VirtioInterrupt>,
     pub acked_features: u64,
     pub acked_protocol_features: u64,
+    pub avail_protocol_features: u64,
     pub socket_path: String,
     pub server: bool,
     pub backend_req_handler: Option<FrontendReqHandler<S>>,
     pub inflight: Option<Inflight>,
+    pub on_reconnect: Option<ReconnectCallback>,
 }
 
 impl<S: VhostUserFrontendReqHandler> VhostUserEpollHandler<S> {
@@ -220,8 +229,10 @@ impl<S: VhostUserFrontendReqHandler> VhostUserEpollHandler<S> {
             ))
         })?;
 
-        // Initialize the backend
-        vhost_user
+        // Initialize the backend, which might have been upgraded, hence
+        // renegotiating the protocol features among the ones acked so far
+        // and the ones the device offers.
+        let acked_protocol_features = vhost_user
             .reinitialize_vhost_user(
                 self.mem.memory().deref(),
                 self.queues
@@ -230,9 +241,9 @@ impl<S: VhostUserFrontendReqHandler> VhostUserEpollHandler<S> {
                     .collect(),
                 &self.virtio_interrupt,
                 self.acked_features,
-                self.acked_protocol_features,
+                self.acked_protocol_features | self.avail_protocol_features,
                 &self.backend_req_handler,
-                self.inflight.as_mut(),
+                &mut self.inflight,
             )
             .map_err(|e| {
                 EpollHelperError::IoError(std::io::Error::new(
@@ -241,6 +252,19 @@ impl<S: VhostUserFrontendReqHandler> VhostUserEpollHandler<S> {
                 ))
             })?;
 
+        let lost_protocol_features = self.acked_protocol_features & !acked_protocol_features;
+        if lost_protocol_features != 0 {
+            warn!(
+                "vhost-user backend no longer supports protocol features {:?}",
+                VhostUserProtocolFeatures::from_bits_truncate(lost_protocol_features)
+            );
+        }
+        self.acked_protocol_features = acked_protocol_features;
+
+        if let Some(on_reconnect) = self.on_reconnect.as_mut() {
+            on_reconnect(&mut vhost_user);
+        }
+
         helper.add_event_custom(
             vhost_user.socket_handle().as_raw_fd(),
             HUP_CONNECTION_EVENT,
@@ -296,6 +320,9 @@ impl<S: VhostUserFrontendReqHandler> EpollHelperHandler for VhostUserEpollHandle
 pub struct VhostUserCommon {
     pub vu: Option<Arc<Mutex<VhostUserHandle>>>,
     pub acked_protocol_features: u64,
+    // Protocol features offered to a backend reconnecting, on top of the
+    // ones acked so far.
+    pub avail_protocol_features: u64,
     pub socket_path: String,
     pub vu_num_queues: usize,
     pub migration_started: bool,
@@ -351,10 +378,12 @@ impl VhostUserCommon {
             virtio_interrupt: interrupt_cb,
             acked_features,
             acked_protocol_features: self.acked_protocol_features,
+            avail_protocol_features: self.avail_protocol_features,
             socket_path: self.socket_path.clone(),
             server: self.server,
             backend_req_handler,
             inflight,
+            on_reconnect: None,
         })
     }
 
@@ -373,6 +402,15 @@ impl VhostUserCommon {
         Ok(())
     }
 
+    /// Protocol features negotiated with the current backend, which might
+    /// differ from the initial ones if the backend reconnected.
+    pub fn current_protocol_features(&self) -> u64 {
+        self.vu
+            .as_ref()
+            .map(|vu| vu.lock().unwrap().acked_protocol_features())
+            .unwrap_or(self.acked_protocol_features)
+    }
+
     pub fn shutdown(&mut self) {
         if let Some(vu) = &self.vu {
             // SAFETY: trivially safe
@@ -391,7 +429,8 @@ impl VhostUserCommon {
         region: &Arc<GuestRegionMmap>,
     ) -> std::result::Result<(), crate::Error> {
         if let Some(vu) = &self.vu {
-            if self.acked_protocol_features & VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS.bits()
+            if vu.lock().unwrap().acked_protocol_features()
+                & VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS.bits()
                 != 0
             {
                 return vu
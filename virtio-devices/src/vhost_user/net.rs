@@ -10,7 +10,7 @@ use crate::{
     VirtioInterrupt, VIRTIO_F_IOMMU_PLATFORM, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_VERSION_1,
 };
 use crate::{GuestMemoryMmap, GuestRegionMmap};
-use net_util::{build_net_config_space, CtrlQueue, MacAddr, VirtioNetConfig};
+use net_util::{build_net_config_space, CtrlQueue, MacAddr, VirtioNetConfig, VIRTIO_NET_F_STANDBY};
 use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
 use std::result;
@@ -77,6 +77,7 @@ impl Net {
         offload_tso: bool,
         offload_ufo: bool,
         offload_csum: bool,
+        standby: bool,
     ) -> Result<Net> {
         let mut num_queues = vu_cfg.num_queues;
 
@@ -94,9 +95,10 @@ impl Net {
             info!("Restoring vhost-user-net {}", id);
 
             // The backend acknowledged features must not contain
-            // VIRTIO_NET_F_MAC since we don't expect the backend
-            // to handle it.
-            let backend_acked_features = state.acked_features & !(1 << VIRTIO_NET_F_MAC);
+            // VIRTIO_NET_F_MAC and VIRTIO_NET_F_STANDBY since we don't
+            // expect the backend to handle them.
+            let backend_acked_features =
+                state.acked_features & !(1 << VIRTIO_NET_F_MAC | 1 << VIRTIO_NET_F_STANDBY);
 
             vu.set_protocol_features_vhost_user(
                 backend_acked_features,
@@ -185,6 +187,11 @@ impl Net {
             // the guest, even if it hasn't been negotiated with the backend.
             acked_features |= 1 << VIRTIO_NET_F_MAC;
 
+            // Same for the standby feature, which only matters to the guest.
+            if standby {
+                acked_features |= 1 << VIRTIO_NET_F_STANDBY;
+            }
+
             (
                 acked_features,
                 // If part of the available features that have been acked,
@@ -234,7 +241,7 @@ impl Net {
             avail_features: self.common.avail_features,
             acked_features: self.common.acked_features,
             config: self.config,
-            acked_protocol_features: self.vu_common.acked_protocol_features,
+            acked_protocol_features: self.vu_common.current_protocol_features(),
             vu_num_queues: self.vu_common.vu_num_queues,
         }
     }
@@ -284,6 +291,10 @@ impl VirtioDevice for Net {
         self.common.ack_features(value)
     }
 
+    fn acked_features(&self) -> u64 {
+        self.common.acked_features
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         self.read_config_from_slice(self.config.as_slice(), offset, data);
     }
@@ -341,8 +352,10 @@ impl VirtioDevice for Net {
         let backend_req_handler: Option<FrontendReqHandler<BackendReqHandler>> = None;
 
         // The backend acknowledged features must not contain VIRTIO_NET_F_MAC
-        // since we don't expect the backend to handle it.
-        let backend_acked_features = self.common.acked_features & !(1 << VIRTIO_NET_F_MAC);
+        // and VIRTIO_NET_F_STANDBY since we don't expect the backend to
+        // handle them.
+        let backend_acked_features =
+            self.common.acked_features & !(1 << VIRTIO_NET_F_MAC | 1 << VIRTIO_NET_F_STANDBY);
 
         // Run a dedicated thread for handling potential reconnections with
         // the backend.
@@ -376,11 +389,6 @@ impl VirtioDevice for Net {
     }
 
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
-        // We first must resume the virtio thread if it was paused.
-        if self.common.pause_evt.take().is_some() {
-            self.common.resume().ok()?;
-        }
-
         if let Some(vu) = &self.vu_common.vu {
             if let Err(e) = vu.lock().unwrap().reset_vhost_user() {
                 error!("Failed to reset vhost-user daemon: {:?}", e);
@@ -388,15 +396,9 @@ impl VirtioDevice for Net {
             }
         }
 
-        if let Some(kill_evt) = self.common.kill_evt.take() {
-            // Ignore the result because there is nothing we can do about it.
-            let _ = kill_evt.write(1);
-        }
-
+        let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
-
-        // Return the interrupt
-        Some(self.common.interrupt_cb.take().unwrap())
+        result
     }
 
     fn shutdown(&mut self) {
@@ -53,6 +53,7 @@ pub struct VhostUserHandle {
     supports_migration: bool,
     shm_log: Option<Arc<MmapRegion>>,
     acked_features: u64,
+    acked_protocol_features: u64,
     vrings_info: Option<Vec<VringInfo>>,
     queue_indexes: Vec<usize>,
 }
@@ -143,11 +144,77 @@ impl VhostUserHandle {
             self.vu.set_hdr_flags(VhostUserHeaderFlag::NEED_REPLY);
         }
 
+        self.acked_protocol_features = acked_protocol_features.bits();
         self.update_supports_migration(acked_features, acked_protocol_features.bits());
 
         Ok((acked_features, acked_protocol_features.bits()))
     }
 
+    /// Negotiate the features again with a backend which reconnected, as it
+    /// might have been upgraded in the meantime. The VIRTIO features already
+    /// acked by the guest must still be supported by the backend, while the
+    /// protocol features are renegotiated among `avail_protocol_features`.
+    pub fn renegotiate_features_vhost_user(
+        &mut self,
+        acked_features: u64,
+        avail_protocol_features: u64,
+        num_queues: usize,
+    ) -> Result<u64> {
+        self.vu.set_owner().map_err(Error::VhostUserSetOwner)?;
+
+        let backend_features = self
+            .vu
+            .get_features()
+            .map_err(Error::VhostUserGetFeatures)?;
+        let missing_features = acked_features & !backend_features;
+        if missing_features != 0 {
+            return Err(Error::VhostUserFeaturesMissing(missing_features));
+        }
+
+        let acked_protocol_features =
+            if acked_features & VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits() != 0 {
+                let backend_protocol_features = self
+                    .vu
+                    .get_protocol_features()
+                    .map_err(Error::VhostUserGetProtocolFeatures)?;
+
+                let acked_protocol_features =
+                    VhostUserProtocolFeatures::from_bits_truncate(avail_protocol_features)
+                        & backend_protocol_features;
+
+                self.vu
+                    .set_protocol_features(acked_protocol_features)
+                    .map_err(Error::VhostUserSetProtocolFeatures)?;
+
+                acked_protocol_features
+            } else {
+                VhostUserProtocolFeatures::empty()
+            };
+
+        if acked_protocol_features.contains(VhostUserProtocolFeatures::REPLY_ACK) {
+            self.vu.set_hdr_flags(VhostUserHeaderFlag::NEED_REPLY);
+        }
+
+        if acked_protocol_features.contains(VhostUserProtocolFeatures::MQ) {
+            let backend_num_queues =
+                self.vu
+                    .get_queue_num()
+                    .map_err(Error::VhostUserGetQueueMaxNum)? as usize;
+            if num_queues > backend_num_queues {
+                error!(
+                    "vhost-user backend only supports {} queues while {} are in use",
+                    backend_num_queues, num_queues
+                );
+                return Err(Error::BadQueueNum);
+            }
+        }
+
+        self.acked_protocol_features = acked_protocol_features.bits();
+        self.update_supports_migration(acked_features, acked_protocol_features.bits());
+
+        Ok(acked_protocol_features.bits())
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn setup_vhost_user<S: VhostUserFrontendReqHandler>(
         &mut self,
@@ -267,7 +334,11 @@ impl VhostUserHandle {
 
         self.enable_vhost_user_vrings(self.queue_indexes.clone(), true)?;
 
-        if let Some(backend_req_handler) = backend_req_handler {
+        // A backend which reconnected might no longer support the backend
+        // request channel.
+        if let Some(backend_req_handler) = backend_req_handler.as_ref().filter(|_| {
+            self.acked_protocol_features & VhostUserProtocolFeatures::BACKEND_REQ.bits() != 0
+        }) {
             self.vu
                 .set_backend_request_fd(&backend_req_handler.get_tx_raw_fd())
                 .map_err(Error::VhostUserSetBackendRequestFd)?;
@@ -328,11 +399,14 @@ impl VhostUserHandle {
             }
         }
 
+        self.acked_protocol_features = acked_protocol_features;
         self.update_supports_migration(acked_features, acked_protocol_features);
 
         Ok(())
     }
 
+    /// Set up a backend which reconnected, returning the protocol features
+    /// negotiated with it.
     #[allow(clippy::too_many_arguments)]
     pub fn reinitialize_vhost_user<S: VhostUserFrontendReqHandler>(
         &mut self,
@@ -340,11 +414,23 @@ impl VhostUserHandle {
         queues: Vec<(usize, Queue, EventFd)>,
         virtio_interrupt: &Arc<dyn VirtioInterrupt>,
         acked_features: u64,
-        acked_protocol_features: u64,
+        avail_protocol_features: u64,
         backend_req_handler: &Option<FrontendReqHandler<S>>,
-        inflight: Option<&mut Inflight>,
-    ) -> Result<()> {
-        self.set_protocol_features_vhost_user(acked_features, acked_protocol_features)?;
+        inflight: &mut Option<Inflight>,
+    ) -> Result<u64> {
+        let acked_protocol_features = self.renegotiate_features_vhost_user(
+            acked_features,
+            avail_protocol_features,
+            queues.len(),
+        )?;
+
+        // The inflight region is handed over to the new backend so that it
+        // can resubmit the requests the previous one didn't complete.
+        if acked_protocol_features & VhostUserProtocolFeatures::INFLIGHT_SHMFD.bits() != 0 {
+            inflight.get_or_insert_with(Inflight::default);
+        } else if inflight.take().is_some() {
+            warn!("vhost-user backend dropped inflight tracking, pending requests might be lost");
+        }
 
         self.setup_vhost_user(
             mem,
@@ -352,8 +438,10 @@ impl VhostUserHandle {
             virtio_interrupt,
             acked_features,
             backend_req_handler,
-            inflight,
-        )
+            inflight.as_mut(),
+        )?;
+
+        Ok(acked_protocol_features)
     }
 
     pub fn connect_vhost_user(
@@ -378,6 +466,7 @@ impl VhostUserHandle {
                 supports_migration: false,
                 shm_log: None,
                 acked_features: 0,
+                acked_protocol_features: 0,
                 vrings_info: None,
                 queue_indexes: Vec::new(),
             })
@@ -394,6 +483,7 @@ impl VhostUserHandle {
                             supports_migration: false,
                             shm_log: None,
                             acked_features: 0,
+                            acked_protocol_features: 0,
                             vrings_info: None,
                             queue_indexes: Vec::new(),
                         })
@@ -419,6 +509,10 @@ impl VhostUserHandle {
         &mut self.vu
     }
 
+    pub fn acked_protocol_features(&self) -> u64 {
+        self.acked_protocol_features
+    }
+
     pub fn pause_vhost_user(&mut self) -> Result<()> {
         if self.ready {
             self.enable_vhost_user_vrings(self.queue_indexes.clone(), false)?;
@@ -436,11 +530,9 @@ impl VhostUserHandle {
     }
 
     fn update_supports_migration(&mut self, acked_features: u64, acked_protocol_features: u64) {
-        if (acked_features & u64::from(vhost::vhost_kern::vhost_binding::VHOST_F_LOG_ALL) != 0)
-            && (acked_protocol_features & VhostUserProtocolFeatures::LOG_SHMFD.bits() != 0)
-        {
-            self.supports_migration = true;
-        }
+        self.supports_migration =
+            (acked_features & u64::from(vhost::vhost_kern::vhost_binding::VHOST_F_LOG_ALL) != 0)
+                && (acked_protocol_features & VhostUserProtocolFeatures::LOG_SHMFD.bits() != 0);
     }
 
     fn update_log_base(&mut self, last_ram_addr: u64) -> Result<Option<Arc<MmapRegion>>> {
@@ -577,6 +577,12 @@ where
         self.state
     }
 
+    /// Return a reference to the underlying host-side stream.
+    ///
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+
     /// Send some raw, untracked, data straight to the underlying connected stream.
     /// Returns: number of bytes written, or the error describing the write failure.
     ///
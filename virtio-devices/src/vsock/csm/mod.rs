@@ -12,12 +12,16 @@ mod txbuf;
 pub use connection::VsockConnection;
 
 pub mod defs {
-    /// Vsock connection TX buffer capacity.
-    pub const CONN_TX_BUF_SIZE: u32 = 64 * 1024;
+    /// Vsock connection TX buffer capacity. This is the amount of credit advertised to the
+    /// guest, so it needs to be large enough for the guest to keep several maximum-sized
+    /// packets in flight while we're flushing to the host stream.
+    pub const CONN_TX_BUF_SIZE: u32 = 256 * 1024;
 
     /// When the guest thinks we have less than this amount of free buffer space,
-    /// we will send them a credit update packet.
-    pub const CONN_CREDIT_UPDATE_THRESHOLD: u32 = 4 * 1024;
+    /// we will send them a credit update packet. Matching the largest RX/TX packet size
+    /// lets the guest learn about freed buffer space before it stalls, while still
+    /// batching many packets worth of flushed data into a single update.
+    pub const CONN_CREDIT_UPDATE_THRESHOLD: u32 = 64 * 1024;
 
     /// Connection request timeout, in millis.
     pub const CONN_REQUEST_TIMEOUT_MS: u64 = 2000;
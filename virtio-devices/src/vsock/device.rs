@@ -123,6 +123,8 @@ where
         debug!("vsock: epoll_handler::process_rx()");
 
         let mut used_descs = false;
+        // Hold the backend lock for the whole batch, rather than taking it for every packet.
+        let mut backend = self.backend.write().unwrap();
 
         while let Some(mut desc_chain) = self.queues[0].pop_descriptor_chain(self.mem.memory()) {
             let used_len = match VsockPacket::from_rx_virtq_head(
@@ -130,7 +132,7 @@ where
                 self.access_platform.as_ref(),
             ) {
                 Ok(mut pkt) => {
-                    if self.backend.write().unwrap().recv_pkt(&mut pkt).is_ok() {
+                    if backend.recv_pkt(&mut pkt).is_ok() {
                         pkt.hdr().len() as u32 + pkt.len()
                     } else {
                         // We are using a consuming iterator over the virtio buffers, so, if we can't
@@ -150,6 +152,7 @@ where
                 .map_err(DeviceError::QueueAddUsed)?;
             used_descs = true;
         }
+        drop(backend);
 
         if used_descs {
             self.signal_used_queue(0)
@@ -165,6 +168,7 @@ where
         debug!("vsock: epoll_handler::process_tx()");
 
         let mut used_descs = false;
+        let mut backend = self.backend.write().unwrap();
 
         while let Some(mut desc_chain) = self.queues[1].pop_descriptor_chain(self.mem.memory()) {
             let pkt = match VsockPacket::from_tx_virtq_head(
@@ -182,7 +186,7 @@ where
                 }
             };
 
-            if self.backend.write().unwrap().send_pkt(&pkt).is_err() {
+            if backend.send_pkt(&pkt).is_err() {
                 self.queues[1].go_to_previous_position();
                 break;
             }
@@ -192,6 +196,7 @@ where
                 .map_err(DeviceError::QueueAddUsed)?;
             used_descs = true;
         }
+        drop(backend);
 
         if used_descs {
             self.signal_used_queue(1)
@@ -375,6 +380,12 @@ where
         })
     }
 
+    /// Shared handle on the backend, so it can be reconfigured while the
+    /// device is running.
+    pub fn backend(&self) -> Arc<RwLock<B>> {
+        self.backend.clone()
+    }
+
     fn state(&self) -> VsockState {
         VsockState {
             avail_features: self.common.avail_features,
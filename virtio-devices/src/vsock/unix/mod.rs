@@ -7,11 +7,15 @@
 //! `muxer::VsockMuxer`, a connection multiplexer that uses `super::csm::VsockConnection` for
 //! handling vsock connection states.
 //!
+//! Specific guest ports can also be bridged to host TCP endpoints, in either direction, through
+//! the muxer forwarding table.
+//!
 //! Check out `muxer.rs` for a more detailed explanation of the inner workings of this backend.
 
 mod muxer;
 mod muxer_killq;
 mod muxer_rxq;
+mod muxer_stream;
 
 pub use muxer::VsockMuxer as VsockUnixBackend;
 pub use Error as VsockUnixError;
@@ -25,6 +29,9 @@ mod defs {
 
     /// Size of the muxer connection kill queue.
     pub const MUXER_KILLQ_SIZE: usize = 128;
+
+    /// How long to wait for a forwarded host TCP endpoint to accept a guest connection.
+    pub const TCP_CONNECT_TIMEOUT_MS: u64 = 1000;
 }
 
 #[derive(Debug)]
@@ -51,7 +58,17 @@ pub enum Error {
     UnixRead(std::io::Error),
     /// Muxer connection limit reached.
     TooManyConnections,
+    /// Error accepting a new connection from a host-side TCP listener.
+    TcpAccept(std::io::Error),
+    /// Error binding to a host-side TCP address.
+    TcpBind(std::io::Error),
+    /// Error connecting to a host-side TCP endpoint.
+    TcpConnect(std::io::Error),
+    /// A forwarding rule already exists for this port or address.
+    ForwardExists,
+    /// No matching forwarding rule was found.
+    UnknownForward,
 }
 
 type Result<T> = std::result::Result<T, Error>;
-type MuxerConnection = super::csm::VsockConnection<std::os::unix::net::UnixStream>;
+type MuxerConnection = super::csm::VsockConnection<muxer_stream::MuxerStream>;
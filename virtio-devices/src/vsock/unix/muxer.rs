@@ -37,12 +37,24 @@
 //!
 //! To route all these events to their handlers, the muxer uses another `HashMap` object,
 //! mapping `RawFd`s to `EpollListener`s.
-
+//!
+//! ## TCP forwarding
+//!
+//! Guest ports can be bridged to host TCP endpoints instead of Unix sockets:
+//! - a guest-initiated connection to a port listed with `add_tcp_forward()` is connected to
+//!   the given TCP address, rather than to the "\<host_sock_path>_\<port>" Unix socket;
+//! - a TCP address registered with `add_tcp_listener()` accepts host connections, and each of
+//!   them is forwarded to the matching guest port. Unlike connections accepted on the host
+//!   Unix socket, there is no "connect"/"OK" handshake: the TCP peer gets a plain byte stream.
+
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{self, ErrorKind, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
 
 use super::super::csm::ConnState;
 use super::super::defs::uapi;
@@ -53,6 +65,7 @@ use super::super::{
 use super::defs;
 use super::muxer_killq::MuxerKillQ;
 use super::muxer_rxq::MuxerRxQ;
+use super::muxer_stream::MuxerStream;
 use super::MuxerConnection;
 use super::{Error, Result};
 
@@ -90,6 +103,12 @@ enum EpollListener {
     /// A listener interested in reading host "connect \<port>" commands from a freshly
     /// connected host socket.
     LocalStream(UnixStream),
+    /// A listener interested in new connections to a forwarded host TCP address, to be
+    /// relayed to `guest_port`.
+    TcpListener {
+        listener: TcpListener,
+        guest_port: u32,
+    },
 }
 
 /// A partially read "CONNECT" command.
@@ -133,6 +152,11 @@ pub struct VsockMuxer {
     local_port_set: HashSet<u32>,
     /// The last used host-side port.
     local_port_last: u32,
+    /// Guest-initiated connections to these ports are forwarded to host TCP endpoints.
+    tcp_forwards: HashMap<u32, SocketAddr>,
+    /// The FDs of the TCP listeners forwarding host connections to the guest, keyed by the
+    /// address they were bound to.
+    tcp_listeners: HashMap<SocketAddr, RawFd>,
 }
 
 impl VsockChannel for VsockMuxer {
@@ -309,9 +333,9 @@ impl VsockEpollListener for VsockMuxer {
     fn notify(&mut self, _: epoll::Events) {
         debug!("vsock: muxer received kick");
 
-        let mut epoll_events = vec![epoll::Event::new(epoll::Events::empty(), 0); 32];
+        let mut epoll_events = [epoll::Event::new(epoll::Events::empty(), 0); 32];
         'epoll: loop {
-            match epoll::wait(self.epoll_file.as_raw_fd(), 0, epoll_events.as_mut_slice()) {
+            match epoll::wait(self.epoll_file.as_raw_fd(), 0, &mut epoll_events[..]) {
                 Ok(ev_cnt) => {
                     for evt in epoll_events.iter().take(ev_cnt) {
                         self.handle_event(
@@ -373,6 +397,8 @@ impl VsockMuxer {
             killq: MuxerKillQ::new(),
             local_port_last: (1u32 << 30) - 1,
             local_port_set: HashSet::with_capacity(defs::MAX_CONNECTIONS),
+            tcp_forwards: HashMap::new(),
+            tcp_listeners: HashMap::new(),
         };
 
         muxer.add_listener(muxer.host_sock.as_raw_fd(), EpollListener::HostSock)?;
@@ -459,7 +485,7 @@ impl VsockMuxer {
                                 peer_port,
                             },
                             MuxerConnection::new_local_init(
-                                stream,
+                                MuxerStream::Unix(stream),
                                 uapi::VSOCK_HOST_CID,
                                 self.cid,
                                 local_port,
@@ -473,6 +499,45 @@ impl VsockMuxer {
                 }
             }
 
+            // A new connection to a forwarded host TCP address is ready to be accepted. The
+            // destination port comes from the forwarding rule, so there's no "connect" command
+            // to wait for.
+            Some(EpollListener::TcpListener {
+                listener,
+                guest_port,
+            }) => {
+                let peer_port = *guest_port;
+                let accepted = listener.accept();
+                if self.conn_map.len() == defs::MAX_CONNECTIONS {
+                    // Dropping the accepted stream closes it.
+                    warn!("vsock: connection limit reached; refusing new TCP connection");
+                    return;
+                }
+                accepted
+                    .and_then(|(stream, _)| Self::setup_tcp_stream(stream))
+                    .map_err(Error::TcpAccept)
+                    .and_then(|stream| {
+                        let local_port = self.allocate_local_port();
+
+                        self.add_connection(
+                            ConnMapKey {
+                                local_port,
+                                peer_port,
+                            },
+                            MuxerConnection::new_local_init(
+                                MuxerStream::Tcp(stream),
+                                uapi::VSOCK_HOST_CID,
+                                self.cid,
+                                local_port,
+                                peer_port,
+                            ),
+                        )
+                    })
+                    .unwrap_or_else(|err| {
+                        warn!("vsock: unable to accept TCP connection: {:?}", err);
+                    });
+            }
+
             _ => {
                 info!(
                     "vsock: unexpected event: fd={:?}, event_set={:?}",
@@ -602,6 +667,7 @@ impl VsockMuxer {
             EpollListener::Connection { evset, .. } => evset,
             EpollListener::LocalStream(_) => epoll::Events::EPOLLIN,
             EpollListener::HostSock => epoll::Events::EPOLLIN,
+            EpollListener::TcpListener { .. } => epoll::Events::EPOLLIN,
         };
 
         epoll::ctl(
@@ -666,17 +732,32 @@ impl VsockMuxer {
 
     /// Handle a new connection request coming from our peer (the guest vsock driver).
     ///
-    /// This will attempt to connect to a host-side Unix socket, expected to be listening at
-    /// the file system path corresponding to the destination port. If successful, a new
-    /// connection object will be created and added to the connection pool. On failure, a new
-    /// RST packet will be scheduled for delivery to the guest.
+    /// This will attempt to connect to the host TCP endpoint the destination port is forwarded
+    /// to, if any, or else to a host-side Unix socket, expected to be listening at the file
+    /// system path corresponding to the destination port. If successful, a new connection
+    /// object will be created and added to the connection pool. On failure, a new RST packet
+    /// will be scheduled for delivery to the guest.
     ///
     fn handle_peer_request_pkt(&mut self, pkt: &VsockPacket) {
-        let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+        let stream = match self.tcp_forwards.get(&pkt.dst_port()) {
+            Some(addr) => TcpStream::connect_timeout(
+                addr,
+                Duration::from_millis(defs::TCP_CONNECT_TIMEOUT_MS),
+            )
+            .and_then(Self::setup_tcp_stream)
+            .map(MuxerStream::Tcp)
+            .map_err(Error::TcpConnect),
+            None => {
+                let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+
+                UnixStream::connect(port_path)
+                    .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
+                    .map(MuxerStream::Unix)
+                    .map_err(Error::UnixConnect)
+            }
+        };
 
-        UnixStream::connect(port_path)
-            .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
-            .map_err(Error::UnixConnect)
+        stream
             .and_then(|stream| {
                 self.add_connection(
                     ConnMapKey {
@@ -696,6 +777,76 @@ impl VsockMuxer {
             .unwrap_or_else(|_| self.enq_rst(pkt.dst_port(), pkt.src_port()));
     }
 
+    /// Prepare a freshly connected TCP stream for use by a `MuxerConnection`.
+    ///
+    fn setup_tcp_stream(stream: TcpStream) -> io::Result<TcpStream> {
+        stream.set_nonblocking(true)?;
+        // The guest driver already batches data into vsock packets, there's no point in
+        // delaying them any further.
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    /// Forward guest-initiated connections to `port` to the host TCP endpoint `addr`.
+    ///
+    pub fn add_tcp_forward(&mut self, port: u32, addr: SocketAddr) -> Result<()> {
+        match self.tcp_forwards.entry(port) {
+            Entry::Occupied(_) => Err(Error::ForwardExists),
+            Entry::Vacant(entry) => {
+                entry.insert(addr);
+                Ok(())
+            }
+        }
+    }
+
+    /// Stop forwarding guest-initiated connections to `port`. Connections that are already
+    /// established are left untouched.
+    ///
+    pub fn remove_tcp_forward(&mut self, port: u32) -> Result<()> {
+        self.tcp_forwards
+            .remove(&port)
+            .map(|_| ())
+            .ok_or(Error::UnknownForward)
+    }
+
+    /// Listen on the host TCP address `addr`, and forward every accepted connection to the
+    /// guest `port`.
+    ///
+    pub fn add_tcp_listener(&mut self, addr: SocketAddr, port: u32) -> Result<()> {
+        if self.tcp_listeners.contains_key(&addr) {
+            return Err(Error::ForwardExists);
+        }
+
+        let listener = TcpListener::bind(addr)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .map_err(Error::TcpBind)?;
+        let fd = listener.as_raw_fd();
+        self.add_listener(
+            fd,
+            EpollListener::TcpListener {
+                listener,
+                guest_port: port,
+            },
+        )?;
+        self.tcp_listeners.insert(addr, fd);
+
+        Ok(())
+    }
+
+    /// Stop listening on the host TCP address `addr`. Connections that are already
+    /// established are left untouched.
+    ///
+    pub fn remove_tcp_listener(&mut self, addr: SocketAddr) -> Result<()> {
+        let fd = self
+            .tcp_listeners
+            .remove(&addr)
+            .ok_or(Error::UnknownForward)?;
+        // Dropping the listener closes the socket.
+        self.remove_listener(fd);
+
+        Ok(())
+    }
+
     /// Perform an action that might mutate a connection's state.
     ///
     /// This is used as shorthand for repetitive tasks that need to be performed after a
@@ -716,8 +867,12 @@ impl VsockMuxer {
             mut_fn(conn);
 
             // If this is a host-initiated connection that has just become established, we'll have
-            // to send an ack message to the host end.
-            if prev_state == ConnState::LocalInit && conn.state() == ConnState::Established {
+            // to send an ack message to the host end (unless it came from a TCP listener, which
+            // has no handshake).
+            if prev_state == ConnState::LocalInit
+                && conn.state() == ConnState::Established
+                && conn.stream().wants_ack()
+            {
                 let msg = format!("OK {}\n", key.local_port);
                 match conn.send_bytes_raw(msg.as_bytes()) {
                     Ok(written) if written == msg.len() => (),
@@ -1451,4 +1606,117 @@ mod tests {
         // not be any pending RX in the muxer.
         assert!(!ctx.muxer.has_pending_rx());
     }
+
+    #[test]
+    fn test_tcp_forward() {
+        const LOCAL_PORT: u32 = 1026;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("tcp_forward");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        ctx.muxer.add_tcp_forward(LOCAL_PORT, addr).unwrap();
+        assert!(matches!(
+            ctx.muxer.add_tcp_forward(LOCAL_PORT, addr),
+            Err(Error::ForwardExists)
+        ));
+
+        // A guest connection to the forwarded port should reach the TCP listener.
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        assert_eq!(ctx.muxer.conn_map.len(), 1);
+        let (mut stream, _) = listener.accept().unwrap();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        assert_eq!(ctx.pkt.src_port(), LOCAL_PORT);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+
+        // Test guest -> host data flow.
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(LOCAL_PORT, PEER_PORT, &data);
+        ctx.send();
+        let mut buf = vec![0; data.len()];
+        stream.read_exact(buf.as_mut_slice()).unwrap();
+        assert_eq!(buf.as_slice(), data);
+
+        // Test host -> guest data flow.
+        let data = [5u8, 6, 7, 8];
+        stream.write_all(&data).unwrap();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
+
+        // Once the rule is gone, new connections to that port are refused.
+        ctx.muxer.remove_tcp_forward(LOCAL_PORT).unwrap();
+        assert!(matches!(
+            ctx.muxer.remove_tcp_forward(LOCAL_PORT),
+            Err(Error::UnknownForward)
+        ));
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT + 1, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT + 1);
+    }
+
+    #[test]
+    fn test_tcp_listener() {
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("tcp_listener");
+        // Grab a free port, then hand it over to the muxer.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        ctx.muxer.add_tcp_listener(addr, PEER_PORT).unwrap();
+        assert!(matches!(
+            ctx.muxer.add_tcp_listener(addr, PEER_PORT),
+            Err(Error::ForwardExists)
+        ));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        ctx.notify_muxer();
+
+        // The accepted connection should be forwarded straight to the guest port, without
+        // waiting for a "connect" command.
+        let local_port = ctx.muxer.local_port_last;
+        assert!(ctx.muxer.conn_map.contains_key(&ConnMapKey {
+            local_port,
+            peer_port: PEER_PORT,
+        }));
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_REQUEST);
+        assert_eq!(ctx.pkt.src_port(), local_port);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+        ctx.init_pkt(local_port, PEER_PORT, uapi::VSOCK_OP_RESPONSE);
+        ctx.send();
+
+        // TCP peers don't get the "OK <port>" acknowledgement.
+        let mut buf = [0u8; 32];
+        assert_eq!(
+            stream.read(&mut buf).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+
+        // Test guest -> host data flow.
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(local_port, PEER_PORT, &data);
+        ctx.send();
+        stream.set_nonblocking(false).unwrap();
+        let mut buf = vec![0; data.len()];
+        stream.read_exact(buf.as_mut_slice()).unwrap();
+        assert_eq!(buf.as_slice(), data);
+
+        ctx.muxer.remove_tcp_listener(addr).unwrap();
+        assert!(matches!(
+            ctx.muxer.remove_tcp_listener(addr),
+            Err(Error::UnknownForward)
+        ));
+        assert!(TcpStream::connect(addr).is_err());
+    }
 }
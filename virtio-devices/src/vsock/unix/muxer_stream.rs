@@ -0,0 +1,71 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! `MuxerStream` is the host-side stream backing a `MuxerConnection`. Most connections are
+//! carried over AF_UNIX sockets, but guest ports listed in the muxer forwarding table are
+//! bridged to host TCP endpoints instead, so the muxer needs to handle both kinds of stream
+//! through the same `VsockConnection` state machine.
+
+use std::io::{self, IoSlice, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+pub enum MuxerStream {
+    /// A connection to a host-side Unix socket.
+    Unix(UnixStream),
+    /// A connection to a host-side TCP endpoint.
+    Tcp(TcpStream),
+}
+
+impl MuxerStream {
+    /// Whether the host end expects the "OK \<port>" acknowledgement once a host-initiated
+    /// connection is established. Only the Unix socket protocol has a handshake; TCP peers
+    /// get a transparent byte stream.
+    pub fn wants_ack(&self) -> bool {
+        matches!(self, MuxerStream::Unix(_))
+    }
+}
+
+impl Read for MuxerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            MuxerStream::Unix(s) => s.read(buf),
+            MuxerStream::Tcp(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for MuxerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            MuxerStream::Unix(s) => s.write(buf),
+            MuxerStream::Tcp(s) => s.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            MuxerStream::Unix(s) => s.write_vectored(bufs),
+            MuxerStream::Tcp(s) => s.write_vectored(bufs),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            MuxerStream::Unix(s) => s.flush(),
+            MuxerStream::Tcp(s) => s.flush(),
+        }
+    }
+}
+
+impl AsRawFd for MuxerStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            MuxerStream::Unix(s) => s.as_raw_fd(),
+            MuxerStream::Tcp(s) => s.as_raw_fd(),
+        }
+    }
+}
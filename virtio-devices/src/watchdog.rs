@@ -388,10 +388,14 @@ impl VirtioDevice for Watchdog {
 }
 
 impl Pausable for Watchdog {
-    fn pause(&mut self) -> result::Result<(), MigratableError> {
-        info!("Watchdog paused - disabling timer");
+    fn quiesce(&mut self, _deadline: Instant) -> result::Result<(), MigratableError> {
+        // The vCPUs are paused, so the guest can't ping the watchdog anymore.
+        info!("Watchdog quiesced - disabling timer");
         timerfd_setup(&self.timer, 0)
-            .map_err(|e| MigratableError::Pause(anyhow!("Error clearing timer: {:?}", e)))?;
+            .map_err(|e| MigratableError::Pause(anyhow!("Error clearing timer: {:?}", e)))
+    }
+
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
         self.common.pause()
     }
 
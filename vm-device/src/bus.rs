@@ -7,8 +7,9 @@
 
 //! Handles routing to devices in an address space.
 
+use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::collections::btree_map::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::sync::{Arc, Barrier, Mutex, RwLock, Weak};
 use std::{convert, error, fmt, io, result};
 
@@ -118,32 +119,116 @@ impl PartialOrd for BusRange {
 ///
 /// This doesn't have any restrictions on what kind of device or address space this applies to. The
 /// only restriction is that no two devices can overlap in this address space.
-#[derive(Default)]
+///
+/// The ranges are kept sorted so that the device owning an address is found with a binary search,
+/// and each thread remembers the last device it accessed on every bus, as a vCPU usually hits the
+/// same device many times in a row.
 pub struct Bus {
-    devices: RwLock<BTreeMap<BusRange, Weak<dyn BusDeviceSync>>>,
+    id: u64,
+    // Only held by the bus, letting the per-thread caches find out it is gone.
+    alive: Arc<()>,
+    // Bumped on every change of the ranges, invalidating the per-thread caches.
+    generation: AtomicU64,
+    devices: RwLock<Vec<(BusRange, Weak<dyn BusDeviceSync>)>>,
+}
+
+// Identifies a bus in the per-thread caches.
+static NEXT_BUS_ID: AtomicU64 = AtomicU64::new(0);
+
+struct CachedRange {
+    bus_id: u64,
+    bus: Weak<()>,
+    generation: u64,
+    range: BusRange,
+    device: Weak<dyn BusDeviceSync>,
+}
+
+thread_local! {
+    static LAST_RANGES: RefCell<Vec<CachedRange>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        // The cache may already be gone if the thread is exiting.
+        let _ = LAST_RANGES.try_with(|ranges| {
+            ranges
+                .borrow_mut()
+                .retain(|cached| cached.bus_id != self.id)
+        });
+    }
 }
 
 impl Bus {
     /// Constructs an a bus with an empty address space.
     pub fn new() -> Bus {
         Bus {
-            devices: RwLock::new(BTreeMap::new()),
+            id: NEXT_BUS_ID.fetch_add(1, AtomicOrdering::Relaxed),
+            alive: Arc::new(()),
+            generation: AtomicU64::new(0),
+            devices: RwLock::new(Vec::new()),
         }
     }
 
+    fn cached(&self, generation: u64, addr: u64) -> Option<(BusRange, Arc<dyn BusDeviceSync>)> {
+        LAST_RANGES.with(|ranges| {
+            let ranges = ranges.borrow();
+            let cached = ranges.iter().find(|cached| cached.bus_id == self.id)?;
+            if cached.generation != generation
+                || addr < cached.range.base
+                || addr - cached.range.base >= cached.range.len
+            {
+                return None;
+            }
+            cached.device.upgrade().map(|d| (cached.range, d))
+        })
+    }
+
+    fn cache(&self, generation: u64, range: BusRange, device: &Arc<dyn BusDeviceSync>) {
+        LAST_RANGES.with(|ranges| {
+            let mut ranges = ranges.borrow_mut();
+            // The entries of the buses dropped by other threads are evicted
+            // here, the dropping thread only evicting its own.
+            ranges.retain(|cached| cached.bus.strong_count() > 0);
+            let cached = CachedRange {
+                bus_id: self.id,
+                bus: Arc::downgrade(&self.alive),
+                generation,
+                range,
+                device: Arc::downgrade(device),
+            };
+            match ranges.iter_mut().find(|cached| cached.bus_id == self.id) {
+                Some(entry) => *entry = cached,
+                None => ranges.push(cached),
+            }
+        })
+    }
+
     fn first_before(&self, addr: u64) -> Option<(BusRange, Arc<dyn BusDeviceSync>)> {
         let devices = self.devices.read().unwrap();
-        let (range, dev) = devices
-            .range(..=BusRange { base: addr, len: 1 })
-            .next_back()?;
-        dev.upgrade().map(|d| (*range, d.clone()))
+        let index = devices.partition_point(|(range, _)| range.base <= addr);
+        let (range, dev) = devices.get(index.checked_sub(1)?)?;
+        dev.upgrade().map(|d| (*range, d))
     }
 
     #[allow(clippy::type_complexity)]
     fn resolve(&self, addr: u64) -> Option<(u64, u64, Arc<dyn BusDeviceSync>)> {
+        // The generation is read before the ranges, so that a concurrent
+        // change can only leave a stale generation in the cache.
+        let generation = self.generation.load(AtomicOrdering::Acquire);
+        if let Some((range, dev)) = self.cached(generation, addr) {
+            return Some((range.base, addr - range.base, dev));
+        }
+
         if let Some((range, dev)) = self.first_before(addr) {
             let offset = addr - range.base;
             if offset < range.len {
+                self.cache(generation, range, &dev);
                 return Some((range.base, offset, dev));
             }
         }
@@ -155,26 +240,21 @@ impl Bus {
             return Err(Error::ZeroSizedRange);
         }
 
-        // Reject all cases where the new device's range overlaps with an existing device.
-        if self
-            .devices
-            .read()
-            .unwrap()
+        let mut devices = self.devices.write().unwrap();
+        let index = devices.partition_point(|(range, _)| range.base < base);
+
+        // Reject all cases where the new device's range overlaps with an existing device. Since
+        // the ranges don't overlap with each other, only the neighbours need to be checked.
+        if devices[index.saturating_sub(1)..]
             .iter()
+            .take(2)
             .any(|(range, _dev)| range.overlaps(base, len))
         {
             return Err(Error::Overlap);
         }
 
-        if self
-            .devices
-            .write()
-            .unwrap()
-            .insert(BusRange { base, len }, Arc::downgrade(&device))
-            .is_some()
-        {
-            return Err(Error::Overlap);
-        }
+        devices.insert(index, (BusRange { base, len }, Arc::downgrade(&device)));
+        self.generation.fetch_add(1, AtomicOrdering::Release);
 
         Ok(())
     }
@@ -185,29 +265,24 @@ impl Bus {
             return Err(Error::ZeroSizedRange);
         }
 
-        let bus_range = BusRange { base, len };
-
-        if self.devices.write().unwrap().remove(&bus_range).is_none() {
+        let mut devices = self.devices.write().unwrap();
+        let index = devices
+            .binary_search_by_key(&base, |(range, _)| range.base)
+            .map_err(|_| Error::MissingAddressRange)?;
+        if devices[index].0.len != len {
             return Err(Error::MissingAddressRange);
         }
+        devices.remove(index);
+        self.generation.fetch_add(1, AtomicOrdering::Release);
 
         Ok(())
     }
 
     /// Removes all entries referencing the given device.
     pub fn remove_by_device(&self, device: &Arc<dyn BusDeviceSync>) -> Result<()> {
-        let mut device_list = self.devices.write().unwrap();
-        let mut remove_key_list = Vec::new();
-
-        for (key, value) in device_list.iter() {
-            if Arc::ptr_eq(&value.upgrade().unwrap(), device) {
-                remove_key_list.push(*key);
-            }
-        }
-
-        for key in remove_key_list.iter() {
-            device_list.remove(key);
-        }
+        let mut devices = self.devices.write().unwrap();
+        devices.retain(|(_, value)| !Arc::ptr_eq(&value.upgrade().unwrap(), device));
+        self.generation.fetch_add(1, AtomicOrdering::Release);
 
         Ok(())
     }
@@ -360,6 +435,86 @@ mod tests {
         assert_eq!(data, [1, 2, 3, 4]);
     }
 
+    #[test]
+    fn bus_resolve_many_devices() {
+        let bus = Bus::new();
+        let devices: Vec<Arc<dyn BusDeviceSync>> =
+            (0..64).map(|_| Arc::new(DummyDevice) as _).collect();
+        // Insert in reverse order, the ranges are sorted on insertion.
+        for (i, device) in devices.iter().enumerate().rev() {
+            assert!(bus.insert(device.clone(), 0x1000 * i as u64, 0x100).is_ok());
+        }
+        assert!(bus.insert(devices[0].clone(), 0x20f0, 0x20).is_err());
+
+        for (i, device) in devices.iter().enumerate() {
+            let (base, offset, dev) = bus.resolve(0x1000 * i as u64 + 0x10).unwrap();
+            assert_eq!(base, 0x1000 * i as u64);
+            assert_eq!(offset, 0x10);
+            assert!(Arc::ptr_eq(&dev, device));
+            assert!(bus.resolve(0x1000 * i as u64 + 0x100).is_none());
+        }
+    }
+
+    #[test]
+    fn bus_cache_invalidation() {
+        let bus = Bus::new();
+        let other_bus = Bus::new();
+        let dummy = Arc::new(DummyDevice);
+        assert!(bus.insert(dummy.clone(), 0x10, 0x10).is_ok());
+        assert!(other_bus.insert(dummy.clone(), 0x40, 0x10).is_ok());
+
+        // Fill the cache of both buses.
+        assert_eq!(bus.resolve(0x12).unwrap().0, 0x10);
+        assert_eq!(other_bus.resolve(0x42).unwrap().0, 0x40);
+        assert!(bus.resolve(0x42).is_none());
+
+        assert!(bus.update_range(0x10, 0x10, 0x100, 0x10).is_ok());
+        assert!(bus.read(0x12, &mut [0, 0, 0, 0]).is_err());
+        assert_eq!(bus.resolve(0x102).unwrap().0, 0x100);
+        assert_eq!(other_bus.resolve(0x42).unwrap().0, 0x40);
+
+        assert!(bus.remove(0x100, 0x20).is_err());
+        assert!(bus.remove(0x100, 0x10).is_ok());
+        assert!(bus.read(0x102, &mut [0, 0, 0, 0]).is_err());
+        assert!(bus.remove(0x100, 0x10).is_err());
+
+        // A dropped device is not reachable through the cache.
+        drop(dummy);
+        assert!(other_bus.read(0x42, &mut [0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn bus_cache_eviction() {
+        let cached_buses = || {
+            LAST_RANGES.with(|ranges| {
+                ranges
+                    .borrow()
+                    .iter()
+                    .map(|cached| cached.bus_id)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let dummy = Arc::new(DummyDevice);
+        let bus = Bus::new();
+        let other_bus = Bus::new();
+        assert!(bus.insert(dummy.clone(), 0x10, 0x10).is_ok());
+        assert!(other_bus.insert(dummy.clone(), 0x40, 0x10).is_ok());
+        let (bus_id, other_bus_id) = (bus.id, other_bus.id);
+        assert!(bus.resolve(0x12).is_some());
+        assert!(cached_buses().contains(&bus_id));
+
+        // A bus dropped by another thread is evicted on the next update of
+        // the cache.
+        std::thread::spawn(move || drop(bus)).join().unwrap();
+        assert!(cached_buses().contains(&bus_id));
+        assert!(other_bus.resolve(0x42).is_some());
+        assert!(!cached_buses().contains(&bus_id));
+
+        // A bus dropped by this thread is evicted right away.
+        drop(other_bus);
+        assert!(!cached_buses().contains(&other_bus_id));
+    }
+
     #[test]
     fn bus_range_overlap() {
         let a = BusRange {
@@ -0,0 +1,142 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Helpers checking the snapshot states saved by previous releases can
+//! still be restored.
+//!
+//! Each time the layout of a `VersionedState` changes, the serialized state
+//! of the previous version (as found in the `state.json` of a snapshot) is
+//! recorded as a fixture, along with the state it must be restored into.
+
+use crate::{MigratableError, Snapshot, SnapshotData, VersionedState};
+use std::fmt::Debug;
+
+/// Restore the state serialized with the layout `version`.
+pub fn restore_state<T>(version: u16, state: &str) -> Result<T, MigratableError>
+where
+    T: VersionedState,
+{
+    SnapshotData {
+        state: state.to_string(),
+        version,
+    }
+    .to_versioned_state()
+}
+
+/// Check every fixture, made of the layout version, the serialized state and
+/// the expected state, is restored as expected. The expected states must also
+/// survive a snapshot with the current version.
+pub fn check_state_compatibility<T>(fixtures: &[(u16, &str, T)])
+where
+    T: VersionedState + PartialEq + Debug,
+{
+    for (version, state, expected) in fixtures {
+        let restored: T = restore_state(*version, state).unwrap_or_else(|e| {
+            panic!(
+                "Failed restoring {} version {}: {:?}",
+                std::any::type_name::<T>(),
+                version,
+                e
+            )
+        });
+        assert_eq!(
+            &restored,
+            expected,
+            "Unexpected {} restored from version {}",
+            std::any::type_name::<T>(),
+            version
+        );
+
+        let snapshot = Snapshot::new_from_versioned_state(expected).unwrap();
+        assert_eq!(&snapshot.to_versioned_state::<T>().unwrap(), expected);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    // Version 0 stored `freq` in kHz, renamed to `frequency` in version 1,
+    // before being converted to Hz in version 2.
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct TimerState {
+        frequency: u64,
+        #[serde(default)]
+        enabled: bool,
+    }
+
+    impl VersionedState for TimerState {
+        const VERSION: u16 = 2;
+
+        fn upgrade(
+            version: u16,
+            mut state: serde_json::Value,
+        ) -> Result<serde_json::Value, MigratableError> {
+            let state_map = state.as_object_mut().unwrap();
+            match version {
+                0 => {
+                    let freq = state_map.remove("freq").unwrap();
+                    state_map.insert("frequency".to_string(), freq);
+                }
+                1 => {
+                    let frequency = state_map["frequency"].as_u64().unwrap();
+                    state_map.insert("frequency".to_string(), (frequency * 1000).into());
+                }
+                _ => unreachable!(),
+            }
+
+            Ok(state)
+        }
+    }
+
+    #[test]
+    fn test_versioned_state_upgrade() {
+        check_state_compatibility(&[
+            (
+                0,
+                r#"{"freq":100}"#,
+                TimerState {
+                    frequency: 100_000,
+                    enabled: false,
+                },
+            ),
+            (
+                1,
+                r#"{"frequency":100,"enabled":true}"#,
+                TimerState {
+                    frequency: 100_000,
+                    enabled: true,
+                },
+            ),
+            (
+                2,
+                r#"{"frequency":100000,"enabled":true}"#,
+                TimerState {
+                    frequency: 100_000,
+                    enabled: true,
+                },
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_versioned_state_too_recent() {
+        assert!(restore_state::<TimerState>(3, r#"{"frequency":100000}"#).is_err());
+    }
+
+    #[test]
+    fn test_unversioned_snapshot_data() {
+        // Snapshot data saved before the versioning was introduced
+        let data: SnapshotData = serde_json::from_str(r#"{"state":"{\"freq\":100}"}"#).unwrap();
+        assert_eq!(
+            data.to_versioned_state::<TimerState>().unwrap(),
+            TimerState {
+                frequency: 100_000,
+                enabled: false,
+            }
+        );
+    }
+}
@@ -5,9 +5,12 @@
 
 use crate::protocol::MemoryRangeTable;
 use anyhow::anyhow;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::time::Instant;
 use thiserror::Error;
 
+pub mod compat;
 pub mod protocol;
 
 #[derive(Error, Debug)]
@@ -51,6 +54,15 @@ pub enum MigratableError {
 
 /// A Pausable component can be paused and resumed.
 pub trait Pausable {
+    /// Quiesce the component before it gets paused, by completing its
+    /// in-flight operations (e.g. IO requests) and stopping its timers, so
+    /// that the state saved afterwards is consistent. This is invoked once
+    /// the vCPUs are paused, and should return no later than `deadline`,
+    /// even if some operations are still pending.
+    fn quiesce(&mut self, _deadline: Instant) -> std::result::Result<(), MigratableError> {
+        Ok(())
+    }
+
     /// Pause the component.
     fn pause(&mut self) -> std::result::Result<(), MigratableError> {
         Ok(())
@@ -62,6 +74,33 @@ pub trait Pausable {
     }
 }
 
+/// A snapshot state with an explicit layout version, allowing the state
+/// saved by a release to be restored by the following ones.
+///
+/// Adding a field with `#[serde(default)]` doesn't require a new version.
+/// Any other change to the layout (e.g. renaming or removing a field, or
+/// changing its meaning) must bump `VERSION` and teach `upgrade()` how to
+/// convert the state from the previous version.
+///
+/// Versions start at 0, which is also the version given to the states saved
+/// before the versioning was introduced.
+pub trait VersionedState: Serialize + DeserializeOwned {
+    /// Current version of the state layout.
+    const VERSION: u16;
+
+    /// Convert the serialized state from the layout `version` to the layout
+    /// `version + 1`.
+    fn upgrade(
+        version: u16,
+        _state: serde_json::Value,
+    ) -> Result<serde_json::Value, MigratableError> {
+        Err(MigratableError::Restore(anyhow!(
+            "Missing upgrade from state version {}",
+            version
+        )))
+    }
+}
+
 /// A Snapshottable component snapshot section.
 /// Migratable component can split their migration snapshot into
 /// separate sections.
@@ -70,6 +109,9 @@ pub trait Pausable {
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct SnapshotData {
     state: String,
+    /// Layout version of the state, only relevant for a `VersionedState`.
+    #[serde(default)]
+    version: u16,
 }
 
 impl SnapshotData {
@@ -90,7 +132,46 @@ impl SnapshotData {
         let state = serde_json::to_string(state)
             .map_err(|e| MigratableError::Snapshot(anyhow!("Error serialising: {}", e)))?;
 
-        Ok(SnapshotData { state })
+        Ok(SnapshotData { state, version: 0 })
+    }
+
+    /// Generate the versioned state from the snapshot data, upgrading it
+    /// from the version it was saved with if needed.
+    pub fn to_versioned_state<T>(&self) -> Result<T, MigratableError>
+    where
+        T: VersionedState,
+    {
+        if self.version > T::VERSION {
+            return Err(MigratableError::Restore(anyhow!(
+                "State version {} is newer than the supported version {}",
+                self.version,
+                T::VERSION
+            )));
+        }
+
+        if self.version == T::VERSION {
+            return self.to_state();
+        }
+
+        let mut state: serde_json::Value = serde_json::from_str(&self.state)
+            .map_err(|e| MigratableError::Restore(anyhow!("Error deserialising: {}", e)))?;
+        for version in self.version..T::VERSION {
+            state = T::upgrade(version, state)?;
+        }
+
+        serde_json::from_value(state)
+            .map_err(|e| MigratableError::Restore(anyhow!("Error deserialising: {}", e)))
+    }
+
+    /// Create from versioned state
+    pub fn new_from_versioned_state<T>(state: &T) -> Result<Self, MigratableError>
+    where
+        T: VersionedState,
+    {
+        let mut data = Self::new_from_state(state)?;
+        data.version = T::VERSION;
+
+        Ok(data)
     }
 }
 
@@ -131,6 +212,16 @@ impl Snapshot {
         Ok(Snapshot::from_data(SnapshotData::new_from_state(state)?))
     }
 
+    /// Create from versioned state
+    pub fn new_from_versioned_state<T>(state: &T) -> Result<Self, MigratableError>
+    where
+        T: VersionedState,
+    {
+        Ok(Snapshot::from_data(SnapshotData::new_from_versioned_state(
+            state,
+        )?))
+    }
+
     /// Add a sub-component's Snapshot to the Snapshot.
     pub fn add_snapshot(&mut self, id: String, snapshot: Snapshot) {
         self.snapshots.insert(id, snapshot);
@@ -146,6 +237,17 @@ impl Snapshot {
             .ok_or_else(|| MigratableError::Restore(anyhow!("Missing snapshot data")))?
             .to_state()
     }
+
+    /// Generate the versioned state from the snapshot
+    pub fn to_versioned_state<T>(&self) -> Result<T, MigratableError>
+    where
+        T: VersionedState,
+    {
+        self.snapshot_data
+            .as_ref()
+            .ok_or_else(|| MigratableError::Restore(anyhow!("Missing snapshot data")))?
+            .to_versioned_state()
+    }
 }
 
 pub fn snapshot_from_id(snapshot: Option<&Snapshot>, id: &str) -> Option<Snapshot> {
@@ -163,6 +265,23 @@ where
     }
 }
 
+pub fn versioned_state_from_id<T>(
+    s: Option<&Snapshot>,
+    id: &str,
+) -> Result<Option<T>, MigratableError>
+where
+    T: VersionedState,
+{
+    if let Some(s) = s.as_ref() {
+        s.snapshots
+            .get(id)
+            .map(|s| s.to_versioned_state())
+            .transpose()
+    } else {
+        Ok(None)
+    }
+}
+
 /// A snapshottable component can be snapshotted.
 pub trait Snapshottable: Pausable {
     /// The snapshottable component id.
@@ -4,6 +4,7 @@
 //
 
 use crate::MigratableError;
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 use vm_memory::ByteValued;
@@ -47,6 +48,11 @@ use vm_memory::ByteValued;
 // The destination can at any time send an "error response" to cancel
 // The source can at any time send an "abandon request" to cancel
 
+// Largest configuration or state data a request can announce. The length comes
+// from the peer and the destination allocates a buffer of that size before
+// reading the data.
+pub const MAX_DATA_LENGTH: u64 = 256 << 20;
+
 #[repr(u16)]
 #[derive(Copy, Clone)]
 pub enum Command {
@@ -262,7 +268,12 @@ impl MemoryRangeTable {
     }
 
     pub fn read_from(fd: &mut dyn Read, length: u64) -> Result<MemoryRangeTable, MigratableError> {
-        assert!(length as usize % std::mem::size_of::<MemoryRange>() == 0);
+        if length % std::mem::size_of::<MemoryRange>() as u64 != 0 {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "Invalid memory range table length: {}",
+                length
+            )));
+        }
 
         let mut data: Vec<MemoryRange> = Vec::new();
         data.resize_with(
@@ -309,3 +320,32 @@ impl MemoryRangeTable {
         Self { data }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_range_table_read_from() {
+        let mut table = MemoryRangeTable::default();
+        table.push(MemoryRange {
+            gpa: 0x1000,
+            length: 0x2000,
+        });
+        table.push(MemoryRange {
+            gpa: 0x10_0000,
+            length: 0x1000,
+        });
+
+        let mut data = Vec::new();
+        table.write_to(&mut data).unwrap();
+
+        let read = MemoryRangeTable::read_from(&mut data.as_slice(), table.length()).unwrap();
+        assert_eq!(read.regions().len(), 2);
+        assert_eq!(read.regions()[1].gpa, 0x10_0000);
+
+        // A length which isn't a multiple of the range size is rejected
+        // rather than read.
+        assert!(MemoryRangeTable::read_from(&mut data.as_slice(), table.length() - 1).is_err());
+    }
+}
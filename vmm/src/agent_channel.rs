@@ -0,0 +1,848 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Host side of the guest agent channel.
+//!
+//! The channel is a console port backed by a Unix socket, carrying the
+//! clipboard, resolution and attestation protocol described in
+//! `docs/agent_channel.md`. Each message is made of an 8 bytes little endian
+//! header (`type`, `size`) followed by `size` bytes of payload.
+//! [`AgentClient`] is the peer connected to the socket of the port, talking to
+//! the agent running in the guest.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+pub const AGENT_PROTOCOL_VERSION: u32 = 1;
+pub const MAX_PAYLOAD_SIZE: u32 = 16 << 20;
+
+pub const CAP_CLIPBOARD: u32 = 1 << 0;
+pub const CAP_MONITORS: u32 = 1 << 1;
+pub const CAP_SNP_REPORT: u32 = 1 << 2;
+
+const HOST_CAPABILITIES: u32 = CAP_CLIPBOARD | CAP_MONITORS | CAP_SNP_REPORT;
+
+pub const SNP_REPORT_DATA_SIZE: usize = 64;
+// Size of the ATTESTATION_REPORT structure returned by MSG_REPORT_RSP, and
+// offset of its REPORT_DATA field.
+const SNP_REPORT_SIZE: usize = 0x4a0;
+const SNP_REPORT_DATA_OFFSET: usize = 0x50;
+
+pub const SELECTION_CLIPBOARD: u32 = 0;
+pub const SELECTION_PRIMARY: u32 = 1;
+
+pub const FORMAT_TEXT: u32 = 1;
+pub const FORMAT_PNG: u32 = 2;
+
+const HEADER_SIZE: usize = 8;
+
+const MSG_HELLO: u32 = 1;
+const MSG_CLIPBOARD_GRAB: u32 = 2;
+const MSG_CLIPBOARD_REQUEST: u32 = 3;
+const MSG_CLIPBOARD_DATA: u32 = 4;
+const MSG_CLIPBOARD_RELEASE: u32 = 5;
+const MSG_MONITORS_CONFIG: u32 = 6;
+const MSG_REPLY: u32 = 7;
+const MSG_SNP_REPORT_REQUEST: u32 = 8;
+const MSG_SNP_REPORT: u32 = 9;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Error reading from the agent channel: {0}")]
+    Read(#[source] io::Error),
+    #[error("Error writing to the agent channel: {0}")]
+    Write(#[source] io::Error),
+    #[error("Agent message payload of {0} bytes exceeds the {MAX_PAYLOAD_SIZE} bytes limit")]
+    PayloadTooLarge(usize),
+    #[error("Malformed agent message of type {0}")]
+    Malformed(u32),
+    #[error("Unsupported agent protocol version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Guest agent doesn't support the {0} capability")]
+    MissingCapability(&'static str),
+    #[error("Guest agent failed to apply message of type {0}: errno {1}")]
+    Reply(u32, u32),
+    #[error("Guest agent took over selection {0}")]
+    SelectionLost(u32),
+    #[error("Guest doesn't offer format {1} for selection {0}")]
+    FormatNotOffered(u32, u32),
+    #[error("Guest agent returned an attestation report of {0} bytes")]
+    InvalidReportSize(usize),
+    #[error("Guest agent returned an attestation report for other report data")]
+    ReportDataMismatch,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MonitorConfig {
+    pub width: u32,
+    pub height: u32,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Hello {
+        version: u32,
+        capabilities: u32,
+    },
+    ClipboardGrab {
+        selection: u32,
+        formats: Vec<u32>,
+    },
+    ClipboardRequest {
+        selection: u32,
+        format: u32,
+    },
+    ClipboardData {
+        selection: u32,
+        format: u32,
+        data: Vec<u8>,
+    },
+    ClipboardRelease {
+        selection: u32,
+    },
+    MonitorsConfig(Vec<MonitorConfig>),
+    Reply {
+        msg_type: u32,
+        result: u32,
+    },
+    SnpReportRequest {
+        report_data: [u8; SNP_REPORT_DATA_SIZE],
+    },
+    SnpReport(Vec<u8>),
+    /// Message of a type this side doesn't know about, its payload is skipped.
+    Unknown(u32),
+}
+
+struct Payload<'a> {
+    msg_type: u32,
+    data: &'a [u8],
+}
+
+impl Payload<'_> {
+    fn u32(&mut self) -> Result<u32> {
+        if self.data.len() < 4 {
+            return Err(Error::Malformed(self.msg_type));
+        }
+        let (value, rest) = self.data.split_at(4);
+        self.data = rest;
+        Ok(u32::from_le_bytes(value.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        self.u32().map(|v| v as i32)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.data.len() < N {
+            return Err(Error::Malformed(self.msg_type));
+        }
+        let (value, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(value.try_into().unwrap())
+    }
+
+    fn rest(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data).to_vec()
+    }
+
+    fn end(self) -> Result<()> {
+        if !self.data.is_empty() {
+            return Err(Error::Malformed(self.msg_type));
+        }
+        Ok(())
+    }
+}
+
+impl Message {
+    fn msg_type(&self) -> u32 {
+        match self {
+            Message::Hello { .. } => MSG_HELLO,
+            Message::ClipboardGrab { .. } => MSG_CLIPBOARD_GRAB,
+            Message::ClipboardRequest { .. } => MSG_CLIPBOARD_REQUEST,
+            Message::ClipboardData { .. } => MSG_CLIPBOARD_DATA,
+            Message::ClipboardRelease { .. } => MSG_CLIPBOARD_RELEASE,
+            Message::MonitorsConfig(_) => MSG_MONITORS_CONFIG,
+            Message::Reply { .. } => MSG_REPLY,
+            Message::SnpReportRequest { .. } => MSG_SNP_REPORT_REQUEST,
+            Message::SnpReport(_) => MSG_SNP_REPORT,
+            Message::Unknown(msg_type) => *msg_type,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let mut put = |v: u32| payload.extend_from_slice(&v.to_le_bytes());
+        match self {
+            Message::Hello {
+                version,
+                capabilities,
+            } => {
+                put(*version);
+                put(*capabilities);
+            }
+            Message::ClipboardGrab { selection, formats } => {
+                put(*selection);
+                put(formats.len() as u32);
+                formats.iter().for_each(|f| put(*f));
+            }
+            Message::ClipboardRequest { selection, format } => {
+                put(*selection);
+                put(*format);
+            }
+            Message::ClipboardData {
+                selection,
+                format,
+                data,
+            } => {
+                put(*selection);
+                put(*format);
+                payload.extend_from_slice(data);
+            }
+            Message::ClipboardRelease { selection } => put(*selection),
+            Message::MonitorsConfig(monitors) => {
+                put(monitors.len() as u32);
+                for m in monitors {
+                    put(m.width);
+                    put(m.height);
+                    put(m.x as u32);
+                    put(m.y as u32);
+                }
+            }
+            Message::Reply { msg_type, result } => {
+                put(*msg_type);
+                put(*result);
+            }
+            Message::SnpReportRequest { report_data } => payload.extend_from_slice(report_data),
+            Message::SnpReport(report) => payload.extend_from_slice(report),
+            Message::Unknown(_) => {}
+        }
+        payload
+    }
+
+    fn decode(msg_type: u32, data: &[u8]) -> Result<Message> {
+        let mut p = Payload { msg_type, data };
+        let msg = match msg_type {
+            MSG_HELLO => Message::Hello {
+                version: p.u32()?,
+                capabilities: p.u32()?,
+            },
+            MSG_CLIPBOARD_GRAB => {
+                let selection = p.u32()?;
+                let count = p.u32()?;
+                let formats = (0..count).map(|_| p.u32()).collect::<Result<_>>()?;
+                Message::ClipboardGrab { selection, formats }
+            }
+            MSG_CLIPBOARD_REQUEST => Message::ClipboardRequest {
+                selection: p.u32()?,
+                format: p.u32()?,
+            },
+            MSG_CLIPBOARD_DATA => Message::ClipboardData {
+                selection: p.u32()?,
+                format: p.u32()?,
+                data: p.rest(),
+            },
+            MSG_CLIPBOARD_RELEASE => Message::ClipboardRelease {
+                selection: p.u32()?,
+            },
+            MSG_MONITORS_CONFIG => {
+                let count = p.u32()?;
+                let monitors = (0..count)
+                    .map(|_| {
+                        Ok(MonitorConfig {
+                            width: p.u32()?,
+                            height: p.u32()?,
+                            x: p.i32()?,
+                            y: p.i32()?,
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Message::MonitorsConfig(monitors)
+            }
+            MSG_REPLY => Message::Reply {
+                msg_type: p.u32()?,
+                result: p.u32()?,
+            },
+            MSG_SNP_REPORT_REQUEST => Message::SnpReportRequest {
+                report_data: p.bytes()?,
+            },
+            MSG_SNP_REPORT => Message::SnpReport(p.rest()),
+            _ => return Ok(Message::Unknown(msg_type)),
+        };
+        p.end()?;
+
+        Ok(msg)
+    }
+}
+
+pub fn write_message(w: &mut impl Write, msg: &Message) -> Result<()> {
+    let payload = msg.payload();
+    if payload.len() > MAX_PAYLOAD_SIZE as usize {
+        return Err(Error::PayloadTooLarge(payload.len()));
+    }
+
+    let mut buf = Vec::with_capacity(HEADER_SIZE + payload.len());
+    buf.extend_from_slice(&msg.msg_type().to_le_bytes());
+    buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&payload);
+    w.write_all(&buf).map_err(Error::Write)?;
+    w.flush().map_err(Error::Write)
+}
+
+pub fn read_message(r: &mut impl Read) -> Result<Message> {
+    let mut header = [0u8; HEADER_SIZE];
+    r.read_exact(&mut header).map_err(Error::Read)?;
+    let msg_type = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if size > MAX_PAYLOAD_SIZE {
+        return Err(Error::PayloadTooLarge(size as usize));
+    }
+
+    let mut payload = vec![0u8; size as usize];
+    r.read_exact(&mut payload).map_err(Error::Read)?;
+
+    Message::decode(msg_type, &payload)
+}
+
+/// Host peer of the agent channel.
+pub struct AgentClient<T: Read + Write> {
+    stream: T,
+    capabilities: u32,
+    // Formats announced by the guest for each selection it owns.
+    guest_selections: HashMap<u32, Vec<u32>>,
+}
+
+impl<T: Read + Write> AgentClient<T> {
+    /// Perform the handshake with the guest agent, advertising every
+    /// capability supported by the host side.
+    pub fn new(stream: T) -> Result<Self> {
+        let mut client = AgentClient {
+            stream,
+            capabilities: 0,
+            guest_selections: HashMap::new(),
+        };
+        let hello = client.hello();
+        client.send(&hello)?;
+
+        loop {
+            if let Message::Hello { .. } = client.recv()? {
+                return Ok(client);
+            }
+        }
+    }
+
+    /// Capabilities supported by both the host and the guest agent.
+    pub fn capabilities(&self) -> u32 {
+        self.capabilities
+    }
+
+    fn hello(&self) -> Message {
+        Message::Hello {
+            version: AGENT_PROTOCOL_VERSION,
+            capabilities: HOST_CAPABILITIES,
+        }
+    }
+
+    fn send(&mut self, msg: &Message) -> Result<()> {
+        write_message(&mut self.stream, msg)
+    }
+
+    // Receive the next message, keeping track of the guest state and
+    // answering the messages that don't depend on the caller.
+    fn recv(&mut self) -> Result<Message> {
+        let msg = read_message(&mut self.stream)?;
+        match &msg {
+            Message::Hello {
+                version,
+                capabilities,
+            } => {
+                if *version != AGENT_PROTOCOL_VERSION {
+                    return Err(Error::UnsupportedVersion(*version));
+                }
+                // A restarted agent starts over with an empty clipboard.
+                self.capabilities = capabilities & HOST_CAPABILITIES;
+                self.guest_selections.clear();
+            }
+            Message::ClipboardGrab { selection, formats } => {
+                self.guest_selections.insert(*selection, formats.clone());
+            }
+            Message::ClipboardRelease { selection } => {
+                self.guest_selections.remove(selection);
+            }
+            Message::Unknown(msg_type) => {
+                debug!("Skipping unknown agent message of type {}", msg_type);
+            }
+            _ => {}
+        }
+
+        Ok(msg)
+    }
+
+    fn require(&self, capability: u32, name: &'static str) -> Result<()> {
+        if self.capabilities & capability == 0 {
+            return Err(Error::MissingCapability(name));
+        }
+        Ok(())
+    }
+
+    /// Ask the guest agent to apply the given display layout, and wait for
+    /// the outcome.
+    pub fn set_resolution(&mut self, monitors: &[MonitorConfig]) -> Result<()> {
+        self.require(CAP_MONITORS, "monitors")?;
+        self.send(&Message::MonitorsConfig(monitors.to_vec()))?;
+
+        loop {
+            match self.recv()? {
+                Message::Reply { msg_type, result } if msg_type == MSG_MONITORS_CONFIG => {
+                    if result != 0 {
+                        return Err(Error::Reply(msg_type, result));
+                    }
+                    return Ok(());
+                }
+                msg => self.handle_unowned(msg)?,
+            }
+        }
+    }
+
+    /// Ask the guest agent for a SEV-SNP attestation report holding the given
+    /// report data, which it requests from the PSP through its `sev-guest`
+    /// driver (`MSG_REPORT_REQ`).
+    pub fn snp_report(&mut self, report_data: &[u8; SNP_REPORT_DATA_SIZE]) -> Result<Vec<u8>> {
+        self.require(CAP_SNP_REPORT, "SEV-SNP report")?;
+        self.send(&Message::SnpReportRequest {
+            report_data: *report_data,
+        })?;
+
+        loop {
+            match self.recv()? {
+                Message::SnpReport(report) => {
+                    if report.len() != SNP_REPORT_SIZE {
+                        return Err(Error::InvalidReportSize(report.len()));
+                    }
+                    // The report is only checked against its signature by
+                    // the verifier, this merely catches a confused agent.
+                    if report[SNP_REPORT_DATA_OFFSET..SNP_REPORT_DATA_OFFSET + SNP_REPORT_DATA_SIZE]
+                        != report_data[..]
+                    {
+                        return Err(Error::ReportDataMismatch);
+                    }
+                    return Ok(report);
+                }
+                Message::Reply { msg_type, result } if msg_type == MSG_SNP_REPORT_REQUEST => {
+                    return Err(Error::Reply(msg_type, result));
+                }
+                msg => self.handle_unowned(msg)?,
+            }
+        }
+    }
+
+    /// Fetch the content of a selection owned by the guest, waiting for the
+    /// guest to announce one if it hasn't yet.
+    pub fn get_clipboard(&mut self, selection: u32, format: u32) -> Result<Vec<u8>> {
+        self.require(CAP_CLIPBOARD, "clipboard")?;
+
+        while !self.guest_selections.contains_key(&selection) {
+            let msg = self.recv()?;
+            self.handle_unowned(msg)?;
+        }
+        if !self.guest_selections[&selection].contains(&format) {
+            return Err(Error::FormatNotOffered(selection, format));
+        }
+
+        self.send(&Message::ClipboardRequest { selection, format })?;
+        loop {
+            match self.recv()? {
+                Message::ClipboardData {
+                    selection: s,
+                    format: f,
+                    data,
+                } if s == selection && f == format => return Ok(data),
+                // The content went away before it could be transferred.
+                Message::ClipboardGrab { selection: s, .. }
+                | Message::ClipboardRelease { selection: s }
+                    if s == selection =>
+                {
+                    return Err(Error::SelectionLost(selection))
+                }
+                msg => self.handle_unowned(msg)?,
+            }
+        }
+    }
+
+    /// Take ownership of a selection and serve its content to the guest,
+    /// until the guest takes the selection over or the channel is closed.
+    pub fn set_clipboard(&mut self, selection: u32, format: u32, data: &[u8]) -> Result<()> {
+        self.require(CAP_CLIPBOARD, "clipboard")?;
+        self.guest_selections.remove(&selection);
+        self.send(&Message::ClipboardGrab {
+            selection,
+            formats: vec![format],
+        })?;
+
+        loop {
+            let msg = match self.recv() {
+                Ok(msg) => msg,
+                // The guest closing the channel ends the ownership.
+                Err(Error::Read(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            match msg {
+                Message::ClipboardRequest {
+                    selection: s,
+                    format: f,
+                } if s == selection => {
+                    let data = if f == format {
+                        data.to_vec()
+                    } else {
+                        Vec::new()
+                    };
+                    self.send(&Message::ClipboardData {
+                        selection: s,
+                        format: f,
+                        data,
+                    })?;
+                }
+                Message::ClipboardGrab { selection: s, .. } if s == selection => return Ok(()),
+                msg => self.handle_unowned(msg)?,
+            }
+        }
+    }
+
+    // Handle the messages received while waiting for something else.
+    fn handle_unowned(&mut self, msg: Message) -> Result<()> {
+        match msg {
+            Message::Hello { .. } => {
+                let hello = self.hello();
+                self.send(&hello)
+            }
+            // Nothing is owned by the host, the content isn't available.
+            Message::ClipboardRequest { selection, format } => self.send(&Message::ClipboardData {
+                selection,
+                format,
+                data: Vec::new(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    fn roundtrip(msg: Message) {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &msg).unwrap();
+        assert_eq!(read_message(&mut buf.as_slice()).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        roundtrip(Message::Hello {
+            version: 1,
+            capabilities: CAP_CLIPBOARD | CAP_MONITORS,
+        });
+        roundtrip(Message::ClipboardGrab {
+            selection: SELECTION_PRIMARY,
+            formats: vec![FORMAT_TEXT, FORMAT_PNG],
+        });
+        roundtrip(Message::ClipboardRequest {
+            selection: SELECTION_CLIPBOARD,
+            format: FORMAT_TEXT,
+        });
+        roundtrip(Message::ClipboardData {
+            selection: SELECTION_CLIPBOARD,
+            format: FORMAT_TEXT,
+            data: b"hello".to_vec(),
+        });
+        roundtrip(Message::ClipboardRelease {
+            selection: SELECTION_CLIPBOARD,
+        });
+        roundtrip(Message::MonitorsConfig(vec![MonitorConfig {
+            width: 1920,
+            height: 1080,
+            x: -1920,
+            y: 0,
+        }]));
+        roundtrip(Message::Reply {
+            msg_type: MSG_MONITORS_CONFIG,
+            result: 0,
+        });
+        roundtrip(Message::SnpReportRequest {
+            report_data: [0xa5; SNP_REPORT_DATA_SIZE],
+        });
+        roundtrip(Message::SnpReport(vec![1; SNP_REPORT_SIZE]));
+    }
+
+    #[test]
+    fn test_message_encoding() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &Message::ClipboardRelease { selection: 1 }).unwrap();
+        assert_eq!(buf, [5, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_message_invalid() {
+        // Unknown messages are skipped along with their payload.
+        let mut buf = vec![42, 0, 0, 0, 2, 0, 0, 0, 0xaa, 0xbb];
+        write_message(&mut buf, &Message::ClipboardRelease { selection: 0 }).unwrap();
+        let mut r = buf.as_slice();
+        assert_eq!(read_message(&mut r).unwrap(), Message::Unknown(42));
+        assert_eq!(
+            read_message(&mut r).unwrap(),
+            Message::ClipboardRelease { selection: 0 }
+        );
+
+        // Truncated and trailing payloads
+        let buf = [2u8, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0];
+        assert!(matches!(
+            read_message(&mut buf.as_slice()),
+            Err(Error::Malformed(MSG_CLIPBOARD_GRAB))
+        ));
+        let buf = [5u8, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(
+            read_message(&mut buf.as_slice()),
+            Err(Error::Malformed(MSG_CLIPBOARD_RELEASE))
+        ));
+        let mut buf = vec![8u8, 0, 0, 0, 32, 0, 0, 0];
+        buf.extend_from_slice(&[0; 32]);
+        assert!(matches!(
+            read_message(&mut buf.as_slice()),
+            Err(Error::Malformed(MSG_SNP_REPORT_REQUEST))
+        ));
+
+        // Oversized payload
+        let mut buf = 4u32.to_le_bytes().to_vec();
+        buf.extend_from_slice(&(MAX_PAYLOAD_SIZE + 1).to_le_bytes());
+        assert!(matches!(
+            read_message(&mut buf.as_slice()),
+            Err(Error::PayloadTooLarge(_))
+        ));
+    }
+
+    fn guest_hello(guest: &mut UnixStream, capabilities: u32) {
+        assert!(matches!(
+            read_message(guest).unwrap(),
+            Message::Hello { .. }
+        ));
+        write_message(
+            guest,
+            &Message::Hello {
+                version: AGENT_PROTOCOL_VERSION,
+                capabilities,
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_agent_client_resolution() {
+        let (host, mut guest) = UnixStream::pair().unwrap();
+        let monitor = MonitorConfig {
+            width: 1280,
+            height: 720,
+            x: 0,
+            y: 0,
+        };
+
+        let agent = thread::spawn(move || {
+            guest_hello(&mut guest, CAP_MONITORS);
+            assert_eq!(
+                read_message(&mut guest).unwrap(),
+                Message::MonitorsConfig(vec![monitor])
+            );
+            write_message(
+                &mut guest,
+                &Message::Reply {
+                    msg_type: MSG_MONITORS_CONFIG,
+                    result: 0,
+                },
+            )
+            .unwrap();
+            assert!(matches!(
+                read_message(&mut guest).unwrap(),
+                Message::MonitorsConfig(_)
+            ));
+            write_message(
+                &mut guest,
+                &Message::Reply {
+                    msg_type: MSG_MONITORS_CONFIG,
+                    result: libc::EINVAL as u32,
+                },
+            )
+            .unwrap();
+        });
+
+        let mut client = AgentClient::new(host).unwrap();
+        assert_eq!(client.capabilities(), CAP_MONITORS);
+        client.set_resolution(&[monitor]).unwrap();
+        assert!(matches!(
+            client.set_resolution(&[monitor]),
+            Err(Error::Reply(MSG_MONITORS_CONFIG, e)) if e == libc::EINVAL as u32
+        ));
+        assert!(matches!(
+            client.get_clipboard(SELECTION_CLIPBOARD, FORMAT_TEXT),
+            Err(Error::MissingCapability(_))
+        ));
+        agent.join().unwrap();
+    }
+
+    #[test]
+    fn test_agent_client_clipboard() {
+        let (host, mut guest) = UnixStream::pair().unwrap();
+
+        let agent = thread::spawn(move || {
+            guest_hello(&mut guest, CAP_CLIPBOARD);
+
+            // Guest to host copy
+            write_message(
+                &mut guest,
+                &Message::ClipboardGrab {
+                    selection: SELECTION_CLIPBOARD,
+                    formats: vec![FORMAT_TEXT],
+                },
+            )
+            .unwrap();
+            assert_eq!(
+                read_message(&mut guest).unwrap(),
+                Message::ClipboardRequest {
+                    selection: SELECTION_CLIPBOARD,
+                    format: FORMAT_TEXT,
+                }
+            );
+            write_message(
+                &mut guest,
+                &Message::ClipboardData {
+                    selection: SELECTION_CLIPBOARD,
+                    format: FORMAT_TEXT,
+                    data: b"from guest".to_vec(),
+                },
+            )
+            .unwrap();
+
+            // Host to guest copy, the guest pastes twice, including in a
+            // format that wasn't announced, then copies something else.
+            assert_eq!(
+                read_message(&mut guest).unwrap(),
+                Message::ClipboardGrab {
+                    selection: SELECTION_CLIPBOARD,
+                    formats: vec![FORMAT_TEXT],
+                }
+            );
+            for (format, data) in [(FORMAT_TEXT, &b"from host"[..]), (FORMAT_PNG, &b""[..])] {
+                write_message(
+                    &mut guest,
+                    &Message::ClipboardRequest {
+                        selection: SELECTION_CLIPBOARD,
+                        format,
+                    },
+                )
+                .unwrap();
+                assert_eq!(
+                    read_message(&mut guest).unwrap(),
+                    Message::ClipboardData {
+                        selection: SELECTION_CLIPBOARD,
+                        format,
+                        data: data.to_vec(),
+                    }
+                );
+            }
+            write_message(
+                &mut guest,
+                &Message::ClipboardGrab {
+                    selection: SELECTION_CLIPBOARD,
+                    formats: vec![FORMAT_PNG],
+                },
+            )
+            .unwrap();
+        });
+
+        let mut client = AgentClient::new(host).unwrap();
+        assert_eq!(
+            client
+                .get_clipboard(SELECTION_CLIPBOARD, FORMAT_TEXT)
+                .unwrap(),
+            b"from guest"
+        );
+        client
+            .set_clipboard(SELECTION_CLIPBOARD, FORMAT_TEXT, b"from host")
+            .unwrap();
+        assert!(matches!(
+            client.get_clipboard(SELECTION_CLIPBOARD, FORMAT_TEXT),
+            Err(Error::FormatNotOffered(SELECTION_CLIPBOARD, FORMAT_TEXT))
+        ));
+        assert!(matches!(
+            client.set_resolution(&[]),
+            Err(Error::MissingCapability(_))
+        ));
+        agent.join().unwrap();
+    }
+
+    #[test]
+    fn test_agent_client_snp_report() {
+        let (host, mut guest) = UnixStream::pair().unwrap();
+        let report_data = [0x5a; SNP_REPORT_DATA_SIZE];
+
+        let agent = thread::spawn(move || {
+            guest_hello(&mut guest, CAP_SNP_REPORT);
+
+            // The agent fills the report with the data it was given, then
+            // returns one for other data, and fails the last request.
+            let mut report = vec![0u8; SNP_REPORT_SIZE];
+            for other in [false, true] {
+                let Message::SnpReportRequest { report_data } = read_message(&mut guest).unwrap()
+                else {
+                    panic!("Unexpected message");
+                };
+                report[SNP_REPORT_DATA_OFFSET..SNP_REPORT_DATA_OFFSET + SNP_REPORT_DATA_SIZE]
+                    .copy_from_slice(&report_data);
+                if other {
+                    report[SNP_REPORT_DATA_OFFSET] ^= 0xff;
+                }
+                write_message(&mut guest, &Message::SnpReport(report.clone())).unwrap();
+            }
+            assert!(matches!(
+                read_message(&mut guest).unwrap(),
+                Message::SnpReportRequest { .. }
+            ));
+            write_message(
+                &mut guest,
+                &Message::Reply {
+                    msg_type: MSG_SNP_REPORT_REQUEST,
+                    result: libc::ENOTTY as u32,
+                },
+            )
+            .unwrap();
+        });
+
+        let mut client = AgentClient::new(host).unwrap();
+        assert_eq!(client.capabilities(), CAP_SNP_REPORT);
+        let report = client.snp_report(&report_data).unwrap();
+        assert_eq!(report.len(), SNP_REPORT_SIZE);
+        assert_eq!(
+            report[SNP_REPORT_DATA_OFFSET..SNP_REPORT_DATA_OFFSET + SNP_REPORT_DATA_SIZE],
+            report_data
+        );
+        assert!(matches!(
+            client.snp_report(&report_data),
+            Err(Error::ReportDataMismatch)
+        ));
+        assert!(matches!(
+            client.snp_report(&report_data),
+            Err(Error::Reply(MSG_SNP_REPORT_REQUEST, e)) if e == libc::ENOTTY as u32
+        ));
+        assert!(matches!(
+            client.set_resolution(&[]),
+            Err(Error::MissingCapability(_))
+        ));
+        agent.join().unwrap();
+    }
+}
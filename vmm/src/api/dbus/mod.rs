@@ -2,14 +2,18 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 //
-use super::{ApiAction, ApiRequest};
+use super::operations::OPERATIONS;
+use super::{ApiAction, ApiError, ApiRequest};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::api::VmCoredump;
 use crate::api::{
-    AddDisk, Body, VmAddDevice, VmAddFs, VmAddNet, VmAddPmem, VmAddUserDevice, VmAddVdpa,
-    VmAddVsock, VmBoot, VmCounters, VmCreate, VmDelete, VmInfo, VmPause, VmPowerButton, VmReboot,
-    VmReceiveMigration, VmRemoveDevice, VmResize, VmResizeZone, VmRestore, VmResume,
-    VmSendMigration, VmShutdown, VmSnapshot, VmmPing, VmmShutdown,
+    AddDisk, Body, VmAddConsole, VmAddDevice, VmAddFs, VmAddNet, VmAddPmem, VmAddUserDevice,
+    VmAddVdpa, VmAddVsock, VmAddVsockForward, VmBlockTrace, VmBoot, VmCounters, VmCountersShm,
+    VmCreate, VmDelete, VmInfo, VmInjectError, VmKeepDisk, VmLaunchMeasurement, VmMigrateMemory,
+    VmPause, VmPauseData, VmPowerButton, VmReboot, VmReceiveMigration, VmRemoveDevice,
+    VmRemoveVsockForward, VmResize, VmResizeZone, VmRestore, VmResume, VmSendMigration,
+    VmSetZonePolicy, VmShutdown, VmSnapshot, VmSnpReport, VmSwapNet, VmTimeAdjust, VmTimeInfo,
+    VmUnplugStatus, VmValidateConfig, VmmPing, VmmSetLogLevel, VmmShutdown,
 };
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::{Error as VmmError, Result as VmmResult};
@@ -45,6 +49,15 @@ fn api_error(error: impl std::fmt::Debug + std::fmt::Display) -> fdo::Error {
     fdo::Error::Failed(format!("{error}"))
 }
 
+// Turns the request down while an operation keeps the VMM thread busy, the
+// same way the HTTP API does.
+fn check_busy() -> Result<()> {
+    if OPERATIONS.lock().unwrap().busy() {
+        return Err(api_error(ApiError::Busy));
+    }
+    Ok(())
+}
+
 // This method is intended to ensure that the DBusApi thread has enough time to
 // send a response to the VmmShutdown method call before it is terminated. If
 // this step is omitted, the thread may be terminated before it can send a
@@ -91,6 +104,7 @@ impl DBusApi {
         action: &'static Action,
         body: Action::RequestBody,
     ) -> Result<Optional<String>> {
+        check_busy()?;
         let api_sender = self.clone_api_sender().await;
         let api_notifier = self.clone_api_notifier()?;
 
@@ -108,16 +122,26 @@ impl DBusApi {
 #[interface(name = "org.cloudhypervisor.DBusApi1")]
 impl DBusApi {
     async fn vmm_ping(&self) -> Result<String> {
-        let api_sender = self.clone_api_sender().await;
-        let api_notifier = self.clone_api_notifier()?;
-
-        let result = blocking::unblock(move || VmmPing.send(api_notifier, api_sender, ()))
-            .await
-            .map_err(api_error)?;
+        // Answered without the VMM thread while it is busy.
+        let cached = OPERATIONS.lock().unwrap().vmm_ping();
+        let result = match cached {
+            Some(result) => result,
+            None => {
+                let api_sender = self.clone_api_sender().await;
+                let api_notifier = self.clone_api_notifier()?;
+
+                blocking::unblock(move || VmmPing.send(api_notifier, api_sender, ())).await
+            }
+        }
+        .map_err(api_error)?;
         serde_json::to_string(&result).map_err(api_error)
     }
 
     async fn vmm_shutdown(&self) -> Result<()> {
+        // The VMM thread handles the shutdown once the operations in
+        // progress are stopped.
+        OPERATIONS.lock().unwrap().cancel_pending();
+
         let api_sender = self.clone_api_sender().await;
         let api_notifier = self.clone_api_notifier()?;
 
@@ -126,6 +150,13 @@ impl DBusApi {
             .map_err(api_error)
     }
 
+    async fn vmm_set_log_level(&self, log_level_data: String) -> Result<()> {
+        let log_level_data = serde_json::from_str(&log_level_data).map_err(api_error)?;
+        self.vm_action(&VmmSetLogLevel, log_level_data)
+            .await
+            .map(|_| ())
+    }
+
     async fn vm_add_device(&self, device_config: String) -> Result<Optional<String>> {
         let device_config = serde_json::from_str(&device_config).map_err(api_error)?;
         self.vm_action(&VmAddDevice, device_config).await
@@ -150,6 +181,15 @@ impl DBusApi {
         self.vm_action(&VmAddNet, net_config).await
     }
 
+    async fn vm_swap_net(&self, net_config: String) -> Result<()> {
+        let mut net_config: NetConfig = serde_json::from_str(&net_config).map_err(api_error)?;
+        if net_config.fds.is_some() {
+            warn!("Ignoring FDs sent via the D-Bus request body");
+            net_config.fds = None;
+        }
+        self.vm_action(&VmSwapNet, net_config).await.map(|_| ())
+    }
+
     async fn vm_add_pmem(&self, pmem_config: String) -> Result<Optional<String>> {
         let pmem_config = serde_json::from_str(&pmem_config).map_err(api_error)?;
         self.vm_action(&VmAddPmem, pmem_config).await
@@ -165,11 +205,30 @@ impl DBusApi {
         self.vm_action(&VmAddVdpa, vdpa_config).await
     }
 
+    async fn vm_add_console(&self, console_port_config: String) -> Result<Optional<String>> {
+        let console_port_config = serde_json::from_str(&console_port_config).map_err(api_error)?;
+        self.vm_action(&VmAddConsole, console_port_config).await
+    }
+
     async fn vm_add_vsock(&self, vsock_config: String) -> Result<Optional<String>> {
         let vsock_config = serde_json::from_str(&vsock_config).map_err(api_error)?;
         self.vm_action(&VmAddVsock, vsock_config).await
     }
 
+    async fn vm_add_vsock_forward(&self, vsock_forward: String) -> Result<()> {
+        let vsock_forward = serde_json::from_str(&vsock_forward).map_err(api_error)?;
+        self.vm_action(&VmAddVsockForward, vsock_forward)
+            .await
+            .map(|_| ())
+    }
+
+    async fn vm_block_trace(&self, vm_block_trace: String) -> Result<()> {
+        let vm_block_trace = serde_json::from_str(&vm_block_trace).map_err(api_error)?;
+        self.vm_action(&VmBlockTrace, vm_block_trace)
+            .await
+            .map(|_| ())
+    }
+
     async fn vm_boot(&self) -> Result<()> {
         self.vm_action(&VmBoot, ()).await.map(|_| ())
     }
@@ -197,7 +256,63 @@ impl DBusApi {
         self.vm_action(&VmCounters, ()).await
     }
 
+    async fn vm_counters_shm(&self) -> Result<Optional<String>> {
+        self.vm_action(&VmCountersShm, ()).await
+    }
+
+    async fn vm_keep_disk(&self, vm_keep_disk: String) -> Result<()> {
+        let vm_keep_disk = serde_json::from_str(&vm_keep_disk).map_err(api_error)?;
+        self.vm_action(&VmKeepDisk, vm_keep_disk).await.map(|_| ())
+    }
+
+    async fn vm_launch_measurement(&self) -> Result<Optional<String>> {
+        self.vm_action(&VmLaunchMeasurement, ()).await
+    }
+
+    async fn vm_snp_report(&self, snp_report_data: String) -> Result<Optional<String>> {
+        let snp_report_data = serde_json::from_str(&snp_report_data).map_err(api_error)?;
+        self.vm_action(&VmSnpReport, snp_report_data).await
+    }
+
+    async fn vm_unplug_status(&self) -> Result<Optional<String>> {
+        self.vm_action(&VmUnplugStatus, ()).await
+    }
+
+    async fn vm_time_info(&self) -> Result<Optional<String>> {
+        self.vm_action(&VmTimeInfo, ()).await
+    }
+
+    async fn vm_time_adjust(&self, time_adjust_data: String) -> Result<()> {
+        let time_adjust_data = serde_json::from_str(&time_adjust_data).map_err(api_error)?;
+        self.vm_action(&VmTimeAdjust, time_adjust_data)
+            .await
+            .map(|_| ())
+    }
+
+    async fn vm_inject_error(&self, inject_error_data: String) -> Result<()> {
+        let inject_error_data = serde_json::from_str(&inject_error_data).map_err(api_error)?;
+        self.vm_action(&VmInjectError, inject_error_data)
+            .await
+            .map(|_| ())
+    }
+
+    async fn vm_migrate_memory(&self, migrate_memory_data: String) -> Result<()> {
+        let migrate_memory_data = serde_json::from_str(&migrate_memory_data).map_err(api_error)?;
+        self.vm_action(&VmMigrateMemory, migrate_memory_data)
+            .await
+            .map(|_| ())
+    }
+
+    async fn vm_set_zone_policy(&self, set_zone_policy_data: String) -> Result<()> {
+        let set_zone_policy_data =
+            serde_json::from_str(&set_zone_policy_data).map_err(api_error)?;
+        self.vm_action(&VmSetZonePolicy, set_zone_policy_data)
+            .await
+            .map(|_| ())
+    }
+
     async fn vm_create(&self, vm_config: String) -> Result<()> {
+        check_busy()?;
         let api_sender = self.clone_api_sender().await;
         let api_notifier = self.clone_api_notifier()?;
 
@@ -221,22 +336,45 @@ impl DBusApi {
         Ok(())
     }
 
+    async fn vm_validate_config(&self, vm_config: String) -> Result<String> {
+        check_busy()?;
+        let api_sender = self.clone_api_sender().await;
+        let api_notifier = self.clone_api_notifier()?;
+
+        let vm_config: VmConfig = serde_json::from_str(&vm_config).map_err(api_error)?;
+
+        let result = blocking::unblock(move || {
+            VmValidateConfig.send(api_notifier, api_sender, Box::new(vm_config))
+        })
+        .await
+        .map_err(api_error)?;
+        serde_json::to_string(&result).map_err(api_error)
+    }
+
     async fn vm_delete(&self) -> Result<()> {
         self.vm_action(&VmDelete, ()).await.map(|_| ())
     }
 
     async fn vm_info(&self) -> Result<String> {
-        let api_sender = self.clone_api_sender().await;
-        let api_notifier = self.clone_api_notifier()?;
-
-        let result = blocking::unblock(move || VmInfo.send(api_notifier, api_sender, ()))
-            .await
-            .map_err(api_error)?;
+        // Answered without the VMM thread while it is busy.
+        let cached = OPERATIONS.lock().unwrap().vm_info();
+        let result = match cached {
+            Some(result) => result,
+            None => {
+                let api_sender = self.clone_api_sender().await;
+                let api_notifier = self.clone_api_notifier()?;
+
+                blocking::unblock(move || VmInfo.send(api_notifier, api_sender, ())).await
+            }
+        }
+        .map_err(api_error)?;
         serde_json::to_string(&result).map_err(api_error)
     }
 
     async fn vm_pause(&self) -> Result<()> {
-        self.vm_action(&VmPause, ()).await.map(|_| ())
+        self.vm_action(&VmPause, VmPauseData::default())
+            .await
+            .map(|_| ())
     }
 
     async fn vm_power_button(&self) -> Result<()> {
@@ -254,6 +392,13 @@ impl DBusApi {
             .map(|_| ())
     }
 
+    async fn vm_remove_vsock_forward(&self, vsock_forward: String) -> Result<()> {
+        let vsock_forward = serde_json::from_str(&vsock_forward).map_err(api_error)?;
+        self.vm_action(&VmRemoveVsockForward, vsock_forward)
+            .await
+            .map(|_| ())
+    }
+
     async fn vm_resize(&self, vm_resize: String) -> Result<()> {
         let vm_resize = serde_json::from_str(&vm_resize).map_err(api_error)?;
         self.vm_action(&VmResize, vm_resize).await.map(|_| ())
@@ -5,13 +5,17 @@
 //
 
 use crate::api::http::{error_response, EndpointHandler, HttpError};
+use crate::api::operations::OPERATIONS;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::api::VmCoredump;
 use crate::api::{
-    AddDisk, ApiAction, ApiRequest, VmAddDevice, VmAddFs, VmAddNet, VmAddPmem, VmAddUserDevice,
-    VmAddVdpa, VmAddVsock, VmBoot, VmConfig, VmCounters, VmDelete, VmNmi, VmPause, VmPowerButton,
-    VmReboot, VmReceiveMigration, VmRemoveDevice, VmResize, VmResizeZone, VmRestore, VmResume,
-    VmSendMigration, VmShutdown, VmSnapshot,
+    AddDisk, ApiAction, ApiError, ApiRequest, VmAddConsole, VmAddDevice, VmAddFs, VmAddNet,
+    VmAddPmem, VmAddUserDevice, VmAddVdpa, VmAddVsock, VmAddVsockForward, VmBlockTrace, VmBoot,
+    VmConfig, VmCounters, VmCountersShm, VmDelete, VmInjectError, VmKeepDisk, VmLaunchMeasurement,
+    VmMigrateMemory, VmNmi, VmPause, VmPauseData, VmPowerButton, VmReboot, VmReceiveMigration,
+    VmRemoveDevice, VmRemoveVsockForward, VmResize, VmResizeZone, VmRestore, VmResume,
+    VmSendMigration, VmSetZonePolicy, VmShutdown, VmSnapshot, VmSnpReport, VmSwapNet, VmTimeAdjust,
+    VmTimeInfo, VmUnplugStatus, VmmSetLogLevel,
 };
 use crate::config::{NetConfig, RestoreConfig};
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
@@ -70,6 +74,49 @@ impl EndpointHandler for VmCreate {
     }
 }
 
+// /api/v1/vm.validate-config handler
+pub struct VmValidateConfig {}
+
+impl EndpointHandler for VmValidateConfig {
+    fn handle_request(
+        &self,
+        req: &Request,
+        api_notifier: EventFd,
+        api_sender: Sender<ApiRequest>,
+    ) -> Response {
+        match req.method() {
+            Method::Put => match &req.body {
+                Some(body) => {
+                    let vm_config: VmConfig = match serde_json::from_slice(body.raw())
+                        .map_err(HttpError::SerdeJsonDeserialize)
+                    {
+                        Ok(config) => config,
+                        Err(e) => return error_response(e, StatusCode::BadRequest),
+                    };
+
+                    match crate::api::VmValidateConfig
+                        .send(api_notifier, api_sender, Box::new(vm_config))
+                        .map_err(HttpError::ApiError)
+                    {
+                        Ok(validation) => {
+                            let mut response = Response::new(Version::Http11, StatusCode::OK);
+                            let validation_serialized = serde_json::to_string(&validation).unwrap();
+
+                            response.set_body(Body::new(validation_serialized));
+                            response
+                        }
+                        Err(e) => error_response(e, StatusCode::InternalServerError),
+                    }
+                }
+
+                None => Response::new(Version::Http11, StatusCode::BadRequest),
+            },
+
+            _ => error_response(HttpError::BadRequest, StatusCode::BadRequest),
+        }
+    }
+}
+
 pub trait GetHandler {
     fn handle_request(
         &'static self,
@@ -163,13 +210,51 @@ macro_rules! vm_action_put_handler_body {
     };
 }
 
+// Long-running actions, which are run as operations when asked to.
+macro_rules! vm_operation_put_handler_body {
+    ($action:ty, $kind:expr, $cancellable:expr) => {
+        impl PutHandler for $action {
+            fn handle_request(
+                &'static self,
+                api_notifier: EventFd,
+                api_sender: Sender<ApiRequest>,
+                body: &Option<Body>,
+                _files: Vec<File>,
+            ) -> std::result::Result<Option<Body>, HttpError> {
+                if let Some(body) = body {
+                    let data: <$action as ApiAction>::RequestBody =
+                        serde_json::from_slice(body.raw())?;
+                    if data.asynchronous {
+                        let operation = OPERATIONS
+                            .lock()
+                            .unwrap()
+                            .start(self, $kind, $cancellable, api_notifier, api_sender, data)
+                            .map_err(HttpError::ApiError)?;
+                        Ok(Some(Body::new(serde_json::to_string(&operation)?)))
+                    } else {
+                        self.send(api_notifier, api_sender, data)
+                            .map_err(HttpError::ApiError)
+                    }
+                } else {
+                    Err(HttpError::BadRequest)
+                }
+            }
+        }
+
+        impl GetHandler for $action {}
+    };
+}
+
 vm_action_get_handler!(VmCounters);
+vm_action_get_handler!(VmCountersShm);
+vm_action_get_handler!(VmLaunchMeasurement);
+vm_action_get_handler!(VmUnplugStatus);
+vm_action_get_handler!(VmTimeInfo);
 
 vm_action_put_handler!(VmBoot);
 vm_action_put_handler!(VmDelete);
 vm_action_put_handler!(VmShutdown);
 vm_action_put_handler!(VmReboot);
-vm_action_put_handler!(VmPause);
 vm_action_put_handler!(VmResume);
 vm_action_put_handler!(VmPowerButton);
 vm_action_put_handler!(VmNmi);
@@ -177,19 +262,53 @@ vm_action_put_handler!(VmNmi);
 vm_action_put_handler_body!(VmAddDevice);
 vm_action_put_handler_body!(AddDisk);
 vm_action_put_handler_body!(VmAddFs);
+vm_action_put_handler_body!(VmTimeAdjust);
+vm_action_put_handler_body!(VmInjectError);
+vm_action_put_handler_body!(VmSnpReport);
+vm_action_put_handler_body!(VmMigrateMemory);
+vm_action_put_handler_body!(VmSetZonePolicy);
+vm_action_put_handler_body!(VmmSetLogLevel);
 vm_action_put_handler_body!(VmAddPmem);
 vm_action_put_handler_body!(VmAddVdpa);
+vm_action_put_handler_body!(VmAddConsole);
 vm_action_put_handler_body!(VmAddVsock);
+vm_action_put_handler_body!(VmAddVsockForward);
+vm_action_put_handler_body!(VmRemoveVsockForward);
 vm_action_put_handler_body!(VmAddUserDevice);
 vm_action_put_handler_body!(VmRemoveDevice);
+vm_action_put_handler_body!(VmBlockTrace);
+vm_action_put_handler_body!(VmKeepDisk);
 vm_action_put_handler_body!(VmResize);
 vm_action_put_handler_body!(VmResizeZone);
-vm_action_put_handler_body!(VmSnapshot);
-vm_action_put_handler_body!(VmReceiveMigration);
-vm_action_put_handler_body!(VmSendMigration);
+
+vm_operation_put_handler_body!(VmSnapshot, "vm.snapshot", true);
+vm_operation_put_handler_body!(VmSendMigration, "vm.send-migration", true);
+vm_operation_put_handler_body!(VmReceiveMigration, "vm.receive-migration", false);
 
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-vm_action_put_handler_body!(VmCoredump);
+vm_operation_put_handler_body!(VmCoredump, "vm.coredump", false);
+
+// The quiesce parameters are optional when pausing the VM.
+impl PutHandler for VmPause {
+    fn handle_request(
+        &'static self,
+        api_notifier: EventFd,
+        api_sender: Sender<ApiRequest>,
+        body: &Option<Body>,
+        _files: Vec<File>,
+    ) -> std::result::Result<Option<Body>, HttpError> {
+        let pause_data = if let Some(body) = body {
+            serde_json::from_slice(body.raw())?
+        } else {
+            VmPauseData::default()
+        };
+
+        self.send(api_notifier, api_sender, pause_data)
+            .map_err(HttpError::ApiError)
+    }
+}
+
+impl GetHandler for VmPause {}
 
 impl PutHandler for VmAddNet {
     fn handle_request(
@@ -219,6 +338,34 @@ impl PutHandler for VmAddNet {
 
 impl GetHandler for VmAddNet {}
 
+impl PutHandler for VmSwapNet {
+    fn handle_request(
+        &'static self,
+        api_notifier: EventFd,
+        api_sender: Sender<ApiRequest>,
+        body: &Option<Body>,
+        mut files: Vec<File>,
+    ) -> std::result::Result<Option<Body>, HttpError> {
+        if let Some(body) = body {
+            let mut net_cfg: NetConfig = serde_json::from_slice(body.raw())?;
+            if net_cfg.fds.is_some() {
+                warn!("Ignoring FDs sent via the HTTP request body");
+                net_cfg.fds = None;
+            }
+            if !files.is_empty() {
+                let fds = files.drain(..).map(|f| f.into_raw_fd()).collect();
+                net_cfg.fds = Some(fds);
+            }
+            self.send(api_notifier, api_sender, net_cfg)
+                .map_err(HttpError::ApiError)
+        } else {
+            Err(HttpError::BadRequest)
+        }
+    }
+}
+
+impl GetHandler for VmSwapNet {}
+
 impl PutHandler for VmRestore {
     fn handle_request(
         &'static self,
@@ -256,8 +403,24 @@ impl PutHandler for VmRestore {
                 }
             }
 
-            self.send(api_notifier, api_sender, restore_cfg)
-                .map_err(HttpError::ApiError)
+            if restore_cfg.asynchronous {
+                let operation = OPERATIONS
+                    .lock()
+                    .unwrap()
+                    .start(
+                        self,
+                        "vm.restore",
+                        false,
+                        api_notifier,
+                        api_sender,
+                        restore_cfg,
+                    )
+                    .map_err(HttpError::ApiError)?;
+                Ok(Some(Body::new(serde_json::to_string(&operation)?)))
+            } else {
+                self.send(api_notifier, api_sender, restore_cfg)
+                    .map_err(HttpError::ApiError)
+            }
         } else {
             Err(HttpError::BadRequest)
         }
@@ -309,22 +472,35 @@ impl EndpointHandler for VmInfo {
         api_sender: Sender<ApiRequest>,
     ) -> Response {
         match req.method() {
-            Method::Get => match crate::api::VmInfo
-                .send(api_notifier, api_sender, ())
-                .map_err(HttpError::ApiError)
-            {
-                Ok(info) => {
-                    let mut response = Response::new(Version::Http11, StatusCode::OK);
-                    let info_serialized = serde_json::to_string(&info).unwrap();
-
-                    response.set_body(Body::new(info_serialized));
-                    response
+            Method::Get => {
+                // Answered without the VMM thread while it is busy.
+                let cached = OPERATIONS.lock().unwrap().vm_info();
+                let info = match cached {
+                    Some(info) => info,
+                    None => crate::api::VmInfo.send(api_notifier, api_sender, ()),
+                };
+
+                match info.map_err(HttpError::ApiError) {
+                    Ok(info) => {
+                        let mut response = Response::new(Version::Http11, StatusCode::OK);
+                        let info_serialized = serde_json::to_string(&info).unwrap();
+
+                        response.set_body(Body::new(info_serialized));
+                        response
+                    }
+                    Err(e @ HttpError::ApiError(ApiError::Busy)) => {
+                        error_response(e, StatusCode::ServiceUnavailable)
+                    }
+                    Err(e) => error_response(e, StatusCode::InternalServerError),
                 }
-                Err(e) => error_response(e, StatusCode::InternalServerError),
-            },
+            }
             _ => error_response(HttpError::BadRequest, StatusCode::BadRequest),
         }
     }
+
+    fn served_while_busy(&self) -> bool {
+        true
+    }
 }
 
 // /api/v1/vmm.info handler
@@ -338,23 +514,36 @@ impl EndpointHandler for VmmPing {
         api_sender: Sender<ApiRequest>,
     ) -> Response {
         match req.method() {
-            Method::Get => match crate::api::VmmPing
-                .send(api_notifier, api_sender, ())
-                .map_err(HttpError::ApiError)
-            {
-                Ok(pong) => {
-                    let mut response = Response::new(Version::Http11, StatusCode::OK);
-                    let info_serialized = serde_json::to_string(&pong).unwrap();
-
-                    response.set_body(Body::new(info_serialized));
-                    response
+            Method::Get => {
+                // Answered without the VMM thread while it is busy.
+                let cached = OPERATIONS.lock().unwrap().vmm_ping();
+                let pong = match cached {
+                    Some(pong) => pong,
+                    None => crate::api::VmmPing.send(api_notifier, api_sender, ()),
+                };
+
+                match pong.map_err(HttpError::ApiError) {
+                    Ok(pong) => {
+                        let mut response = Response::new(Version::Http11, StatusCode::OK);
+                        let info_serialized = serde_json::to_string(&pong).unwrap();
+
+                        response.set_body(Body::new(info_serialized));
+                        response
+                    }
+                    Err(e @ HttpError::ApiError(ApiError::Busy)) => {
+                        error_response(e, StatusCode::ServiceUnavailable)
+                    }
+                    Err(e) => error_response(e, StatusCode::InternalServerError),
                 }
-                Err(e) => error_response(e, StatusCode::InternalServerError),
-            },
+            }
 
             _ => error_response(HttpError::BadRequest, StatusCode::BadRequest),
         }
     }
+
+    fn served_while_busy(&self) -> bool {
+        true
+    }
 }
 
 // /api/v1/vmm.shutdown handler
@@ -369,6 +558,10 @@ impl EndpointHandler for VmmShutdown {
     ) -> Response {
         match req.method() {
             Method::Put => {
+                // The VMM thread handles the shutdown once the operations
+                // in progress are stopped.
+                OPERATIONS.lock().unwrap().cancel_pending();
+
                 match crate::api::VmmShutdown
                     .send(api_notifier, api_sender, ())
                     .map_err(HttpError::ApiError)
@@ -380,4 +573,72 @@ impl EndpointHandler for VmmShutdown {
             _ => error_response(HttpError::BadRequest, StatusCode::BadRequest),
         }
     }
+
+    fn served_while_busy(&self) -> bool {
+        true
+    }
+}
+
+// /api/v1/operations/{id} and /api/v1/operations/{id}/cancel handler
+pub struct Operation {
+    id: u64,
+    cancel: bool,
+}
+
+impl Operation {
+    /// Parses the path following `/operations/`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (id, cancel) = match path.strip_suffix("/cancel") {
+            Some(id) => (id, true),
+            None => (path, false),
+        };
+
+        Some(Operation {
+            id: id.parse().ok()?,
+            cancel,
+        })
+    }
+}
+
+impl EndpointHandler for Operation {
+    fn get_handler(
+        &self,
+        _api_notifier: EventFd,
+        _api_sender: Sender<ApiRequest>,
+        _body: &Option<Body>,
+    ) -> std::result::Result<Option<Body>, HttpError> {
+        if self.cancel {
+            return Err(HttpError::BadRequest);
+        }
+
+        let operation = OPERATIONS
+            .lock()
+            .unwrap()
+            .info(self.id)
+            .map_err(HttpError::ApiError)?;
+        Ok(Some(Body::new(serde_json::to_string(&operation)?)))
+    }
+
+    fn put_handler(
+        &self,
+        _api_notifier: EventFd,
+        _api_sender: Sender<ApiRequest>,
+        _body: &Option<Body>,
+        _files: Vec<File>,
+    ) -> std::result::Result<Option<Body>, HttpError> {
+        if !self.cancel {
+            return Err(HttpError::BadRequest);
+        }
+
+        let operation = OPERATIONS
+            .lock()
+            .unwrap()
+            .cancel(self.id)
+            .map_err(HttpError::ApiError)?;
+        Ok(Some(Body::new(serde_json::to_string(&operation)?)))
+    }
+
+    fn served_while_busy(&self) -> bool {
+        true
+    }
 }
@@ -3,14 +3,20 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use self::http_endpoint::{VmActionHandler, VmCreate, VmInfo, VmmPing, VmmShutdown};
+use self::http_endpoint::{
+    Operation, VmActionHandler, VmCreate, VmInfo, VmValidateConfig, VmmPing, VmmShutdown,
+};
+use crate::api::operations::OPERATIONS;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::api::VmCoredump;
 use crate::api::{
-    AddDisk, ApiError, ApiRequest, VmAddDevice, VmAddFs, VmAddNet, VmAddPmem, VmAddUserDevice,
-    VmAddVdpa, VmAddVsock, VmBoot, VmCounters, VmDelete, VmNmi, VmPause, VmPowerButton, VmReboot,
-    VmReceiveMigration, VmRemoveDevice, VmResize, VmResizeZone, VmRestore, VmResume,
-    VmSendMigration, VmShutdown, VmSnapshot,
+    AddDisk, ApiError, ApiErrorBody, ApiRequest, VmAddConsole, VmAddDevice, VmAddFs, VmAddNet,
+    VmAddPmem, VmAddUserDevice, VmAddVdpa, VmAddVsock, VmAddVsockForward, VmBlockTrace, VmBoot,
+    VmCounters, VmCountersShm, VmDelete, VmInjectError, VmKeepDisk, VmLaunchMeasurement,
+    VmMigrateMemory, VmNmi, VmPause, VmPowerButton, VmReboot, VmReceiveMigration, VmRemoveDevice,
+    VmRemoveVsockForward, VmResize, VmResizeZone, VmRestore, VmResume, VmSendMigration,
+    VmSetZonePolicy, VmShutdown, VmSnapshot, VmSnpReport, VmSwapNet, VmTimeAdjust, VmTimeInfo,
+    VmUnplugStatus, VmmSetLogLevel,
 };
 use crate::landlock::Landlock;
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
@@ -70,6 +76,26 @@ impl Display for HttpError {
     }
 }
 
+impl HttpError {
+    /// Machine-readable description of the error.
+    pub fn body(&self) -> ApiErrorBody {
+        let code = match self {
+            HttpError::SerdeJsonDeserialize(_) => "InvalidRequestBody",
+            HttpError::BadRequest => "BadRequest",
+            HttpError::NotFound => "NotFound",
+            HttpError::InternalServerError => "InternalError",
+            HttpError::ApiError(e) => return e.body(),
+        };
+
+        ApiErrorBody {
+            code: code.to_string(),
+            message: self.to_string(),
+            field: None,
+            retryable: false,
+        }
+    }
+}
+
 impl From<serde_json::Error> for HttpError {
     fn from(e: serde_json::Error) -> Self {
         HttpError::SerdeJsonDeserialize(e)
@@ -80,7 +106,13 @@ const HTTP_ROOT: &str = "/api/v1";
 
 pub fn error_response(error: HttpError, status: StatusCode) -> Response {
     let mut response = Response::new(Version::Http11, status);
-    response.set_body(Body::new(format!("{error}")));
+    match serde_json::to_string(&error.body()) {
+        Ok(body) => {
+            response.set_content_type(MediaType::ApplicationJson);
+            response.set_body(Body::new(body));
+        }
+        Err(_) => response.set_body(Body::new(format!("{error}"))),
+    }
 
     response
 }
@@ -123,10 +155,24 @@ pub trait EndpointHandler {
             Err(e @ HttpError::SerdeJsonDeserialize(_)) => {
                 error_response(e, StatusCode::BadRequest)
             }
+            Err(e @ HttpError::ApiError(ApiError::OperationNotFound(_))) => {
+                error_response(e, StatusCode::NotFound)
+            }
+            Err(e @ HttpError::ApiError(ApiError::Busy)) => {
+                error_response(e, StatusCode::ServiceUnavailable)
+            }
             Err(e) => error_response(e, StatusCode::InternalServerError),
         }
     }
 
+    /// Whether the endpoint is served while an operation keeps the VMM
+    /// thread busy, in which case the handler must not wait for the VMM
+    /// thread. The other endpoints are turned down until the operation
+    /// completes.
+    fn served_while_busy(&self) -> bool {
+        false
+    }
+
     fn put_handler(
         &self,
         _api_notifier: EventFd,
@@ -193,10 +239,22 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.add-vdpa"),
         Box::new(VmActionHandler::new(&VmAddVdpa)),
     );
+    r.routes.insert(
+        endpoint!("/vm.add-console"),
+        Box::new(VmActionHandler::new(&VmAddConsole)),
+    );
     r.routes.insert(
         endpoint!("/vm.add-vsock"),
         Box::new(VmActionHandler::new(&VmAddVsock)),
     );
+    r.routes.insert(
+        endpoint!("/vm.add-vsock-forward"),
+        Box::new(VmActionHandler::new(&VmAddVsockForward)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.block-trace"),
+        Box::new(VmActionHandler::new(&VmBlockTrace)),
+    );
     r.routes.insert(
         endpoint!("/vm.boot"),
         Box::new(VmActionHandler::new(&VmBoot)),
@@ -205,6 +263,10 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.counters"),
         Box::new(VmActionHandler::new(&VmCounters)),
     );
+    r.routes.insert(
+        endpoint!("/vm.counters-shm"),
+        Box::new(VmActionHandler::new(&VmCountersShm)),
+    );
     r.routes
         .insert(endpoint!("/vm.create"), Box::new(VmCreate {}));
     r.routes.insert(
@@ -212,6 +274,18 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         Box::new(VmActionHandler::new(&VmDelete)),
     );
     r.routes.insert(endpoint!("/vm.info"), Box::new(VmInfo {}));
+    r.routes.insert(
+        endpoint!("/vm.keep-disk"),
+        Box::new(VmActionHandler::new(&VmKeepDisk)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.launch-measurement"),
+        Box::new(VmActionHandler::new(&VmLaunchMeasurement)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.snp-report"),
+        Box::new(VmActionHandler::new(&VmSnpReport)),
+    );
     r.routes.insert(
         endpoint!("/vm.pause"),
         Box::new(VmActionHandler::new(&VmPause)),
@@ -232,6 +306,38 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.remove-device"),
         Box::new(VmActionHandler::new(&VmRemoveDevice)),
     );
+    r.routes.insert(
+        endpoint!("/vm.remove-vsock-forward"),
+        Box::new(VmActionHandler::new(&VmRemoveVsockForward)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.inject-error"),
+        Box::new(VmActionHandler::new(&VmInjectError)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.migrate-memory"),
+        Box::new(VmActionHandler::new(&VmMigrateMemory)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.set-zone-policy"),
+        Box::new(VmActionHandler::new(&VmSetZonePolicy)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.time-adjust"),
+        Box::new(VmActionHandler::new(&VmTimeAdjust)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.time-info"),
+        Box::new(VmActionHandler::new(&VmTimeInfo)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.unplug-status"),
+        Box::new(VmActionHandler::new(&VmUnplugStatus)),
+    );
+    r.routes.insert(
+        endpoint!("/vm.validate-config"),
+        Box::new(VmValidateConfig {}),
+    );
     r.routes.insert(
         endpoint!("/vm.resize"),
         Box::new(VmActionHandler::new(&VmResize)),
@@ -256,6 +362,10 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.shutdown"),
         Box::new(VmActionHandler::new(&VmShutdown)),
     );
+    r.routes.insert(
+        endpoint!("/vm.swap-net"),
+        Box::new(VmActionHandler::new(&VmSwapNet)),
+    );
     r.routes.insert(
         endpoint!("/vm.snapshot"),
         Box::new(VmActionHandler::new(&VmSnapshot)),
@@ -269,6 +379,10 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         .insert(endpoint!("/vmm.ping"), Box::new(VmmPing {}));
     r.routes
         .insert(endpoint!("/vmm.shutdown"), Box::new(VmmShutdown {}));
+    r.routes.insert(
+        endpoint!("/vmm.set-log-level"),
+        Box::new(VmActionHandler::new(&VmmSetLogLevel)),
+    );
     r.routes
         .insert(endpoint!("/vm.nmi"), Box::new(VmActionHandler::new(&VmNmi)));
 
@@ -281,7 +395,29 @@ fn handle_http_request(
     api_sender: &Sender<ApiRequest>,
 ) -> Response {
     let path = request.uri().get_abs_path().to_string();
-    let mut response = match HTTP_ROUTES.routes.get(&path) {
+    crate::crash_report::record_api_request(
+        &format!("{:?}", request.method()).to_uppercase(),
+        &path,
+    );
+    // The operations are addressed by their identifier in the path.
+    let operation = path
+        .strip_prefix(&endpoint!("/operations/"))
+        .and_then(Operation::from_path);
+    let route: Option<&dyn EndpointHandler> = match &operation {
+        Some(operation) => Some(operation),
+        None => HTTP_ROUTES
+            .routes
+            .get(&path)
+            .map(|route| route.as_ref() as &dyn EndpointHandler),
+    };
+
+    let mut response = match route {
+        Some(route) if !route.served_while_busy() && OPERATIONS.lock().unwrap().busy() => {
+            error_response(
+                HttpError::ApiError(ApiError::Busy),
+                StatusCode::ServiceUnavailable,
+            )
+        }
         Some(route) => match api_notifier.try_clone() {
             Ok(notifier) => route.handle_request(request, notifier, api_sender.clone()),
             Err(_) => error_response(
@@ -32,6 +32,7 @@
 #[cfg(feature = "dbus_api")]
 pub mod dbus;
 pub mod http;
+pub mod operations;
 
 #[cfg(feature = "dbus_api")]
 pub use self::dbus::start_dbus_thread;
@@ -39,19 +40,25 @@ pub use self::http::start_http_fd_thread;
 pub use self::http::start_http_path_thread;
 
 use crate::config::{
-    DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig, UserDeviceConfig,
-    VdpaConfig, VmConfig, VsockConfig,
+    ConsolePortConfig, DeviceConfig, DiskConfig, FsConfig, HostNumaPolicy, NetConfig, PmemConfig,
+    RestoreConfig, UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig, VsockForwardConfig,
 };
+use crate::config_check::ConfigProblem;
+use crate::device_manager::DeviceManagerError;
 use crate::device_tree::DeviceTree;
+use crate::log_filter::LogFilterError;
 use crate::vm::{Error as VmError, VmState};
 use crate::Error as VmmError;
 use core::fmt;
+pub use devices::ghes::ErrorSeverity;
 use micro_http::Body;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::io;
-use std::sync::mpsc::{channel, RecvError, SendError, Sender};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use vm_migration::MigratableError;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -112,6 +119,9 @@ pub enum ApiError {
     /// The VMM could not shutdown.
     VmmShutdown(VmError),
 
+    /// The log filter is invalid.
+    VmmSetLogLevel(LogFilterError),
+
     /// The VM could not be resized
     VmResize(VmError),
 
@@ -127,6 +137,12 @@ pub enum ApiError {
     /// The device could not be removed from the VM.
     VmRemoveDevice(VmError),
 
+    /// The block device tracing could not be started or stopped.
+    VmBlockTrace(VmError),
+
+    /// The overlay of the transient disk could not be kept.
+    VmKeepDisk(VmError),
+
     /// Cannot create seccomp filter
     CreateSeccompFilter(seccompiler::Error),
 
@@ -145,12 +161,21 @@ pub enum ApiError {
     /// The network device could not be added to the VM.
     VmAddNet(VmError),
 
+    /// The backend of the network device could not be switched.
+    VmSwapNet(VmError),
+
     /// The vDPA device could not be added to the VM.
     VmAddVdpa(VmError),
 
+    /// The console port could not be added to the VM.
+    VmAddConsole(VmError),
+
     /// The vsock device could not be added to the VM.
     VmAddVsock(VmError),
 
+    /// The vsock forwarding table could not be updated.
+    VmVsockForward(VmError),
+
     /// Error starting migration receiver
     VmReceiveMigration(MigratableError),
 
@@ -162,9 +187,164 @@ pub enum ApiError {
 
     /// Error triggering NMI
     VmNmi(VmError),
+
+    /// Error adjusting the guest clock
+    VmTimeAdjust(VmError),
+
+    /// Error injecting a hardware error
+    VmInjectError(VmError),
+
+    /// Error fetching the SEV-SNP attestation report
+    VmSnpReport(VmError),
+
+    /// Error moving the guest memory to another host NUMA node
+    VmMigrateMemory(VmError),
+
+    /// Error changing the host NUMA policy of a memory zone
+    VmSetZonePolicy(VmError),
+
+    /// An operation keeps the VMM busy
+    Busy,
+
+    /// No operation with this identifier
+    OperationNotFound(u64),
+
+    /// The operation can't be cancelled while running
+    OperationNotCancellable(u64),
 }
 pub type ApiResult<T> = Result<T, ApiError>;
 
+/// Machine-readable description of an API error, sent as the body of the
+/// HTTP error responses.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ApiErrorBody {
+    /// Stable identifier of the error, e.g. `DeviceNotFound`.
+    pub code: String,
+    /// Human readable description of the error.
+    pub message: String,
+    /// Path of the configuration field the error is about, e.g. `cpus.max_vcpus`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Whether the same request may succeed when sent again later.
+    pub retryable: bool,
+}
+
+// Code of the VM errors more specific than the failed action.
+fn vm_error_code(error: &VmError) -> Option<&'static str> {
+    match error {
+        VmError::VmNotCreated | VmError::VmMissingConfig => Some("VmNotCreated"),
+        VmError::VmAlreadyCreated => Some("VmAlreadyCreated"),
+        VmError::VmNotRunning => Some("VmNotRunning"),
+        VmError::InvalidStateTransition(..) => Some("InvalidStateTransition"),
+        VmError::ConfigValidation(_) | VmError::InvalidNumaConfig => Some("InvalidConfig"),
+        VmError::ApplyLandlock(_) => Some("LandlockDenied"),
+        VmError::NoDeviceToRemove(_)
+        | VmError::DeviceManager(DeviceManagerError::UnknownDeviceId(_)) => Some("DeviceNotFound"),
+        VmError::TooManyVsockDevices => Some("TooManyDevices"),
+        VmError::NoVsockDevice => Some("DeviceNotFound"),
+        VmError::TimeNotSupported => Some("NotSupported"),
+        VmError::InjectError(DeviceManagerError::GhesInjection(_)) => Some("Busy"),
+        _ => None,
+    }
+}
+
+impl ApiError {
+    fn vm_error(&self) -> Option<&VmError> {
+        use self::ApiError::*;
+        match self {
+            VmBoot(e) | VmCreate(e) | VmDelete(e) | VmInfo(e) | VmPause(e) | VmResume(e)
+            | VmShutdown(e) | VmReboot(e) | VmSnapshot(e) | VmRestore(e) | VmCoredump(e)
+            | VmmShutdown(e) | VmResize(e) | VmResizeZone(e) | VmAddDevice(e)
+            | VmAddUserDevice(e) | VmRemoveDevice(e) | VmBlockTrace(e) | VmKeepDisk(e)
+            | VmAddDisk(e) | VmAddFs(e) | VmAddPmem(e) | VmAddNet(e) | VmSwapNet(e)
+            | VmAddVdpa(e) | VmAddConsole(e) | VmAddVsock(e) | VmVsockForward(e)
+            | VmPowerButton(e) | VmNmi(e) | VmTimeAdjust(e) | VmInjectError(e) | VmSnpReport(e)
+            | VmMigrateMemory(e) | VmSetZonePolicy(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Stable identifier of the error, naming the cause when it is known and
+    /// the failed action otherwise.
+    pub fn code(&self) -> &'static str {
+        use self::ApiError::*;
+        if let Some(code) = self.vm_error().and_then(vm_error_code) {
+            return code;
+        }
+
+        match self {
+            EventFdWrite(_) | RequestSend(_) | ResponsePayloadType | ResponseRecv(_) => {
+                "InternalError"
+            }
+            VmNotBooted => "VmNotBooted",
+            VmNotCreated => "VmNotCreated",
+            Busy => "Busy",
+            OperationNotFound(_) => "OperationNotFound",
+            OperationNotCancellable(_) => "OperationNotCancellable",
+            CreateSeccompFilter(_) | ApplySeccompFilter(_) => "SeccompFilterFailed",
+            VmReceiveMigration(_) | VmSendMigration(_) => "MigrationFailed",
+            VmBoot(_) => "VmBootFailed",
+            VmCreate(_) => "VmCreateFailed",
+            VmDelete(_) => "VmDeleteFailed",
+            VmInfo(_) => "VmInfoFailed",
+            VmPause(_) => "VmPauseFailed",
+            VmResume(_) => "VmResumeFailed",
+            VmShutdown(_) => "VmShutdownFailed",
+            VmReboot(_) => "VmRebootFailed",
+            VmSnapshot(_) => "VmSnapshotFailed",
+            VmRestore(_) => "VmRestoreFailed",
+            VmCoredump(_) => "VmCoredumpFailed",
+            VmmShutdown(_) => "VmmShutdownFailed",
+            VmmSetLogLevel(_) => "InvalidLogFilter",
+            VmResize(_) | VmResizeZone(_) => "VmResizeFailed",
+            VmAddDevice(_) | VmAddUserDevice(_) | VmAddDisk(_) | VmAddFs(_) | VmAddPmem(_)
+            | VmAddNet(_) | VmAddVdpa(_) | VmAddConsole(_) | VmAddVsock(_) => "DeviceAddFailed",
+            VmRemoveDevice(_) => "DeviceRemoveFailed",
+            VmSwapNet(_) => "NetSwapFailed",
+            VmBlockTrace(_) => "BlockTraceFailed",
+            VmKeepDisk(_) => "KeepDiskFailed",
+            VmVsockForward(_) => "VsockForwardFailed",
+            VmPowerButton(_) => "VmPowerButtonFailed",
+            VmNmi(_) => "VmNmiFailed",
+            VmTimeAdjust(_) => "VmTimeAdjustFailed",
+            VmInjectError(_) => "VmInjectErrorFailed",
+            VmSnpReport(_) => "VmSnpReportFailed",
+            VmMigrateMemory(_) => "VmMigrateMemoryFailed",
+            VmSetZonePolicy(_) => "VmSetZonePolicyFailed",
+        }
+    }
+
+    /// Path of the configuration field the error is about, if any.
+    pub fn field(&self) -> Option<&'static str> {
+        match self.vm_error() {
+            Some(VmError::ConfigValidation(e)) => e.field(),
+            Some(VmError::InvalidNumaConfig) => Some("numa"),
+            _ => None,
+        }
+    }
+
+    /// Whether the same request may succeed when sent again later, the
+    /// failure being caused by the transient state of the VMM.
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            ApiError::EventFdWrite(_)
+                | ApiError::RequestSend(_)
+                | ApiError::ResponseRecv(_)
+                | ApiError::Busy
+        ) || matches!(self.vm_error(), Some(VmError::InvalidStateTransition(..)))
+    }
+
+    pub fn body(&self) -> ApiErrorBody {
+        ApiErrorBody {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            field: self.field().map(str::to_string),
+            retryable: self.retryable(),
+        }
+    }
+}
+
 impl Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::ApiError::*;
@@ -187,23 +367,39 @@ impl Display for ApiError {
             VmRestore(vm_error) => write!(f, "{}", vm_error),
             VmCoredump(vm_error) => write!(f, "{}", vm_error),
             VmmShutdown(vm_error) => write!(f, "{}", vm_error),
+            VmmSetLogLevel(log_filter_error) => write!(f, "{}", log_filter_error),
             VmResize(vm_error) => write!(f, "{}", vm_error),
             VmResizeZone(vm_error) => write!(f, "{}", vm_error),
             VmAddDevice(vm_error) => write!(f, "{}", vm_error),
             VmAddUserDevice(vm_error) => write!(f, "{}", vm_error),
             VmRemoveDevice(vm_error) => write!(f, "{}", vm_error),
+            VmBlockTrace(vm_error) => write!(f, "{}", vm_error),
+            VmKeepDisk(vm_error) => write!(f, "{}", vm_error),
             CreateSeccompFilter(seccomp_error) => write!(f, "{}", seccomp_error),
             ApplySeccompFilter(seccomp_error) => write!(f, "{}", seccomp_error),
             VmAddDisk(vm_error) => write!(f, "{}", vm_error),
             VmAddFs(vm_error) => write!(f, "{}", vm_error),
             VmAddPmem(vm_error) => write!(f, "{}", vm_error),
             VmAddNet(vm_error) => write!(f, "{}", vm_error),
+            VmSwapNet(vm_error) => write!(f, "{}", vm_error),
             VmAddVdpa(vm_error) => write!(f, "{}", vm_error),
+            VmAddConsole(vm_error) => write!(f, "{}", vm_error),
             VmAddVsock(vm_error) => write!(f, "{}", vm_error),
+            VmVsockForward(vm_error) => write!(f, "{}", vm_error),
             VmReceiveMigration(migratable_error) => write!(f, "{}", migratable_error),
             VmSendMigration(migratable_error) => write!(f, "{}", migratable_error),
             VmPowerButton(vm_error) => write!(f, "{}", vm_error),
             VmNmi(vm_error) => write!(f, "{}", vm_error),
+            VmTimeAdjust(vm_error) => write!(f, "{}", vm_error),
+            VmInjectError(vm_error) => write!(f, "{}", vm_error),
+            VmSnpReport(vm_error) => write!(f, "{}", vm_error),
+            VmMigrateMemory(vm_error) => write!(f, "{}", vm_error),
+            VmSetZonePolicy(vm_error) => write!(f, "{}", vm_error),
+            Busy => write!(f, "An operation is in progress"),
+            OperationNotFound(id) => write!(f, "Operation {} not found", id),
+            OperationNotCancellable(id) => {
+                write!(f, "Operation {} can't be cancelled while running", id)
+            }
         }
     }
 }
@@ -224,9 +420,15 @@ pub struct VmmPingResponse {
     pub features: Vec<String>,
 }
 
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmValidateConfigResponse {
+    /// Problems found with the configuration, empty if it is valid
+    pub problems: Vec<ConfigProblem>,
+}
+
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmResizeData {
-    pub desired_vcpus: Option<u8>,
+    pub desired_vcpus: Option<u32>,
     pub desired_ram: Option<u64>,
     pub desired_balloon: Option<u64>,
 }
@@ -240,24 +442,123 @@ pub struct VmResizeZoneData {
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmRemoveDeviceData {
     pub id: String,
+    /// Time given to the guest to eject the device, in milliseconds.
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// Forcefully remove the device once the timeout expired.
+    #[serde(default)]
+    pub surprise_removal: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmmLogLevelData {
+    /// Comma separated list of `<module>=<level>` directives or default level
+    pub filter: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmTimeAdjustData {
+    /// Amount of time to move the guest clock forward by, in nanoseconds.
+    pub delta_ns: u64,
+}
+
+/// Kind of hardware error injected into the guest
+#[derive(Clone, Copy, Deserialize, Serialize, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InjectErrorType {
+    #[default]
+    Memory,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmInjectErrorData {
+    /// Kind of hardware error
+    #[serde(rename = "type")]
+    pub error_type: InjectErrorType,
+    /// Guest physical address affected by the error
+    pub gpa: u64,
+    /// Severity of the error, corrected if omitted
+    #[serde(default)]
+    pub severity: ErrorSeverity,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmSnpReportData {
+    /// Hex encoded data bound into the report, up to 64 bytes
+    #[serde(default)]
+    pub report_data: Option<String>,
+    /// Console port the guest agent listens on, the first one backed by a
+    /// socket if omitted
+    #[serde(default)]
+    pub port: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmMigrateMemoryData {
+    /// Host NUMA node the guest memory is moved to
+    pub host_numa_node: u32,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmSetZonePolicyData {
+    /// Identifier of the memory zone
+    pub id: String,
+    /// Host NUMA node the memory of the zone is moved to
+    pub host_numa_node: u32,
+    /// Policy binding the memory of the zone to the host NUMA node
+    #[serde(default)]
+    pub policy: HostNumaPolicy,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmBlockTraceData {
+    /// Identifier of the block device
+    pub id: String,
+    /// File receiving the events, tracing is stopped if omitted
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmKeepDiskData {
+    /// Identifier of the transient disk
+    pub id: String,
+    /// New file the overlay is linked to
+    pub path: PathBuf,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmPauseData {
+    /// Time given to the devices to quiesce, in milliseconds
+    #[serde(default)]
+    pub quiesce_timeout: Option<u64>,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmSnapshotConfig {
     /// The snapshot destination URL
     pub destination_url: String,
+    /// Run the snapshot as an operation rather than waiting for it
+    #[serde(default, rename = "async")]
+    pub asynchronous: bool,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmCoredumpData {
     /// The coredump destination file
     pub destination_url: String,
+    /// Run the coredump as an operation rather than waiting for it
+    #[serde(default, rename = "async")]
+    pub asynchronous: bool,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmReceiveMigrationData {
     /// URL for the reception of migration state
     pub receiver_url: String,
+    /// Run the migration as an operation rather than waiting for it
+    #[serde(default, rename = "async")]
+    pub asynchronous: bool,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
@@ -267,6 +568,9 @@ pub struct VmSendMigrationData {
     /// Send memory across socket without copying
     #[serde(default)]
     pub local: bool,
+    /// Run the migration as an operation rather than waiting for it
+    #[serde(default, rename = "async")]
+    pub asynchronous: bool,
 }
 
 pub enum ApiResponsePayload {
@@ -279,6 +583,9 @@ pub enum ApiResponsePayload {
     /// Vmm ping response
     VmmPing(VmmPingResponse),
 
+    /// Problems found with a VM configuration
+    VmValidateConfig(VmValidateConfigResponse),
+
     /// Vm action response
     VmAction(Option<Vec<u8>>),
 }
@@ -291,7 +598,7 @@ pub trait RequestHandler {
 
     fn vm_boot(&mut self) -> Result<(), VmError>;
 
-    fn vm_pause(&mut self) -> Result<(), VmError>;
+    fn vm_pause(&mut self, pause_data: VmPauseData) -> Result<Option<Vec<u8>>, VmError>;
 
     fn vm_resume(&mut self) -> Result<(), VmError>;
 
@@ -310,13 +617,17 @@ pub trait RequestHandler {
 
     fn vmm_ping(&self) -> VmmPingResponse;
 
+    fn vm_validate_config(&self, config: &VmConfig) -> VmValidateConfigResponse;
+
     fn vm_delete(&mut self) -> Result<(), VmError>;
 
     fn vmm_shutdown(&mut self) -> Result<(), VmError>;
 
+    fn vmm_set_log_level(&mut self, filter: &str) -> Result<(), LogFilterError>;
+
     fn vm_resize(
         &mut self,
-        desired_vcpus: Option<u8>,
+        desired_vcpus: Option<u32>,
         desired_ram: Option<u64>,
         desired_balloon: Option<u64>,
     ) -> Result<(), VmError>;
@@ -330,7 +641,18 @@ pub trait RequestHandler {
         device_cfg: UserDeviceConfig,
     ) -> Result<Option<Vec<u8>>, VmError>;
 
-    fn vm_remove_device(&mut self, id: String) -> Result<(), VmError>;
+    fn vm_remove_device(
+        &mut self,
+        id: String,
+        timeout: Option<Duration>,
+        surprise_removal: bool,
+    ) -> Result<(), VmError>;
+
+    fn vm_unplug_status(&mut self) -> Result<Option<Vec<u8>>, VmError>;
+
+    fn vm_block_trace(&mut self, id: String, path: Option<PathBuf>) -> Result<(), VmError>;
+
+    fn vm_keep_disk(&mut self, id: String, path: PathBuf) -> Result<(), VmError>;
 
     fn vm_add_disk(&mut self, disk_cfg: DiskConfig) -> Result<Option<Vec<u8>>, VmError>;
 
@@ -340,12 +662,32 @@ pub trait RequestHandler {
 
     fn vm_add_net(&mut self, net_cfg: NetConfig) -> Result<Option<Vec<u8>>, VmError>;
 
+    fn vm_swap_net(&mut self, net_cfg: NetConfig) -> Result<(), VmError>;
+
     fn vm_add_vdpa(&mut self, vdpa_cfg: VdpaConfig) -> Result<Option<Vec<u8>>, VmError>;
 
+    fn vm_add_console(
+        &mut self,
+        console_port_cfg: ConsolePortConfig,
+    ) -> Result<Option<Vec<u8>>, VmError>;
+
     fn vm_add_vsock(&mut self, vsock_cfg: VsockConfig) -> Result<Option<Vec<u8>>, VmError>;
 
+    fn vm_add_vsock_forward(&mut self, forward: VsockForwardConfig) -> Result<(), VmError>;
+
+    fn vm_remove_vsock_forward(&mut self, forward: VsockForwardConfig) -> Result<(), VmError>;
+
     fn vm_counters(&mut self) -> Result<Option<Vec<u8>>, VmError>;
 
+    fn vm_counters_shm(&mut self) -> Result<Option<Vec<u8>>, VmError>;
+
+    fn vm_launch_measurement(&mut self) -> Result<Option<Vec<u8>>, VmError>;
+
+    fn vm_snp_report(
+        &mut self,
+        snp_report_data: VmSnpReportData,
+    ) -> Result<Option<Vec<u8>>, VmError>;
+
     fn vm_power_button(&mut self) -> Result<(), VmError>;
 
     fn vm_receive_migration(
@@ -359,6 +701,22 @@ pub trait RequestHandler {
     ) -> Result<(), MigratableError>;
 
     fn vm_nmi(&mut self) -> Result<(), VmError>;
+
+    fn vm_time_info(&mut self) -> Result<Option<Vec<u8>>, VmError>;
+
+    fn vm_time_adjust(&mut self, time_adjust_data: VmTimeAdjustData) -> Result<(), VmError>;
+
+    fn vm_inject_error(&mut self, inject_error_data: VmInjectErrorData) -> Result<(), VmError>;
+
+    fn vm_migrate_memory(
+        &mut self,
+        migrate_memory_data: VmMigrateMemoryData,
+    ) -> Result<(), VmError>;
+
+    fn vm_set_zone_policy(
+        &mut self,
+        set_zone_policy_data: VmSetZonePolicyData,
+    ) -> Result<(), VmError>;
 }
 
 /// It would be nice if we could pass around an object like this:
@@ -377,20 +735,56 @@ pub trait RequestHandler {
 pub type ApiRequest =
     Box<dyn FnOnce(&mut dyn RequestHandler) -> Result<bool, VmmError> + Send + 'static>;
 
-fn get_response<Action: ApiAction>(
+/// Progress of a request sent to the VMM thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RequestState {
+    Queued,
+    Running,
+    Cancelled,
+}
+
+// Sends the request for `action` to the VMM thread. The request is skipped
+// by the VMM thread if it was cancelled while still in the queue. The
+// request of an operation is run along with its `cancellation`.
+fn send_request<Action: ApiAction>(
     action: &Action,
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
     data: Action::RequestBody,
-) -> ApiResult<ApiResponsePayload> {
+    cancellation: Option<Arc<operations::Cancellation>>,
+) -> ApiResult<(Arc<Mutex<RequestState>>, Receiver<ApiResponse>)> {
     let (response_sender, response_receiver) = channel();
+    let state = Arc::new(Mutex::new(RequestState::Queued));
 
     let request = action.request(data, response_sender);
+    let request_state = state.clone();
+    let request: ApiRequest = Box::new(move |vmm| {
+        {
+            let mut state = request_state.lock().unwrap();
+            if *state == RequestState::Cancelled {
+                return Ok(false);
+            }
+            *state = RequestState::Running;
+        }
+        let _running = cancellation.map(operations::RunningOperation::new);
+        request(vmm)
+    });
 
     // Send the VM request.
     api_sender.send(request).map_err(ApiError::RequestSend)?;
     api_evt.write(1).map_err(ApiError::EventFdWrite)?;
 
+    Ok((state, response_receiver))
+}
+
+fn get_response<Action: ApiAction>(
+    action: &Action,
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Action::RequestBody,
+) -> ApiResult<ApiResponsePayload> {
+    let (_, response_receiver) = send_request(action, api_evt, api_sender, data, None)?;
+
     response_receiver.recv().map_err(ApiError::ResponseRecv)?
 }
 
@@ -460,23 +854,498 @@ impl ApiAction for VmAddDevice {
     }
 }
 
-pub struct AddDisk;
+pub struct AddDisk;
+
+impl ApiAction for AddDisk {
+    type RequestBody = DiskConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: AddDisk {:?}", config);
+
+            let response = vmm
+                .vm_add_disk(config)
+                .map_err(ApiError::VmAddDisk)
+                .map(ApiResponsePayload::VmAction);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmAddFs;
+
+impl ApiAction for VmAddFs {
+    type RequestBody = FsConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmAddFs {:?}", config);
+
+            let response = vmm
+                .vm_add_fs(config)
+                .map_err(ApiError::VmAddFs)
+                .map(ApiResponsePayload::VmAction);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmAddPmem;
+
+impl ApiAction for VmAddPmem {
+    type RequestBody = PmemConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmAddPmem {:?}", config);
+
+            let response = vmm
+                .vm_add_pmem(config)
+                .map_err(ApiError::VmAddPmem)
+                .map(ApiResponsePayload::VmAction);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmAddNet;
+
+impl ApiAction for VmAddNet {
+    type RequestBody = NetConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmAddNet {:?}", config);
+
+            let response = vmm
+                .vm_add_net(config)
+                .map_err(ApiError::VmAddNet)
+                .map(ApiResponsePayload::VmAction);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmSwapNet;
+
+impl ApiAction for VmSwapNet {
+    type RequestBody = NetConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmSwapNet {:?}", config);
+
+            let response = vmm
+                .vm_swap_net(config)
+                .map_err(ApiError::VmSwapNet)
+                .map(|_| ApiResponsePayload::Empty);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmAddConsole;
+
+impl ApiAction for VmAddConsole {
+    type RequestBody = ConsolePortConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmAddConsole {:?}", config);
+
+            let response = vmm
+                .vm_add_console(config)
+                .map_err(ApiError::VmAddConsole)
+                .map(ApiResponsePayload::VmAction);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmAddVdpa;
+
+impl ApiAction for VmAddVdpa {
+    type RequestBody = VdpaConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmAddVdpa {:?}", config);
+
+            let response = vmm
+                .vm_add_vdpa(config)
+                .map_err(ApiError::VmAddVdpa)
+                .map(ApiResponsePayload::VmAction);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmAddVsock;
+
+impl ApiAction for VmAddVsock {
+    type RequestBody = VsockConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmAddVsock {:?}", config);
+
+            let response = vmm
+                .vm_add_vsock(config)
+                .map_err(ApiError::VmAddVsock)
+                .map(ApiResponsePayload::VmAction);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmAddVsockForward;
+
+impl ApiAction for VmAddVsockForward {
+    type RequestBody = VsockForwardConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        forward: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmAddVsockForward {:?}", forward);
+
+            let response = vmm
+                .vm_add_vsock_forward(forward)
+                .map_err(ApiError::VmVsockForward)
+                .map(|_| ApiResponsePayload::Empty);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmRemoveVsockForward;
+
+impl ApiAction for VmRemoveVsockForward {
+    type RequestBody = VsockForwardConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        forward: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmRemoveVsockForward {:?}", forward);
+
+            let response = vmm
+                .vm_remove_vsock_forward(forward)
+                .map_err(ApiError::VmVsockForward)
+                .map(|_| ApiResponsePayload::Empty);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmAddUserDevice;
+
+impl ApiAction for VmAddUserDevice {
+    type RequestBody = UserDeviceConfig;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmAddUserDevice {:?}", config);
+
+            let response = vmm
+                .vm_add_user_device(config)
+                .map_err(ApiError::VmAddUserDevice)
+                .map(ApiResponsePayload::VmAction);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmBoot;
+
+impl ApiAction for VmBoot {
+    type RequestBody = ();
+    type ResponseBody = Option<Body>;
+
+    fn request(&self, _: Self::RequestBody, response_sender: Sender<ApiResponse>) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmBoot");
+
+            let response = vmm
+                .vm_boot()
+                .map_err(ApiError::VmBoot)
+                .map(|_| ApiResponsePayload::Empty);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+pub struct VmCoredump;
+
+#[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+impl ApiAction for VmCoredump {
+    type RequestBody = VmCoredumpData;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        coredump_data: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmCoredump {:?}", coredump_data);
+
+            let response = vmm
+                .vm_coredump(&coredump_data.destination_url)
+                .map_err(ApiError::VmCoredump)
+                .map(|_| ApiResponsePayload::Empty);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmCounters;
 
-impl ApiAction for AddDisk {
-    type RequestBody = DiskConfig;
+impl ApiAction for VmCounters {
+    type RequestBody = ();
     type ResponseBody = Option<Body>;
 
-    fn request(
-        &self,
-        config: Self::RequestBody,
-        response_sender: Sender<ApiResponse>,
-    ) -> ApiRequest {
+    fn request(&self, _: Self::RequestBody, response_sender: Sender<ApiResponse>) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: AddDisk {:?}", config);
+            info!("API request event: VmCounters");
 
             let response = vmm
-                .vm_add_disk(config)
-                .map_err(ApiError::VmAddDisk)
+                .vm_counters()
+                .map_err(ApiError::VmInfo)
                 .map(ApiResponsePayload::VmAction);
 
             response_sender
@@ -497,23 +1366,19 @@ impl ApiAction for AddDisk {
     }
 }
 
-pub struct VmAddFs;
+pub struct VmCountersShm;
 
-impl ApiAction for VmAddFs {
-    type RequestBody = FsConfig;
+impl ApiAction for VmCountersShm {
+    type RequestBody = ();
     type ResponseBody = Option<Body>;
 
-    fn request(
-        &self,
-        config: Self::RequestBody,
-        response_sender: Sender<ApiResponse>,
-    ) -> ApiRequest {
+    fn request(&self, _: Self::RequestBody, response_sender: Sender<ApiResponse>) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmAddFs {:?}", config);
+            info!("API request event: VmCountersShm");
 
             let response = vmm
-                .vm_add_fs(config)
-                .map_err(ApiError::VmAddFs)
+                .vm_counters_shm()
+                .map_err(ApiError::VmInfo)
                 .map(ApiResponsePayload::VmAction);
 
             response_sender
@@ -534,23 +1399,19 @@ impl ApiAction for VmAddFs {
     }
 }
 
-pub struct VmAddPmem;
+pub struct VmLaunchMeasurement;
 
-impl ApiAction for VmAddPmem {
-    type RequestBody = PmemConfig;
+impl ApiAction for VmLaunchMeasurement {
+    type RequestBody = ();
     type ResponseBody = Option<Body>;
 
-    fn request(
-        &self,
-        config: Self::RequestBody,
-        response_sender: Sender<ApiResponse>,
-    ) -> ApiRequest {
+    fn request(&self, _: Self::RequestBody, response_sender: Sender<ApiResponse>) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmAddPmem {:?}", config);
+            info!("API request event: VmLaunchMeasurement");
 
             let response = vmm
-                .vm_add_pmem(config)
-                .map_err(ApiError::VmAddPmem)
+                .vm_launch_measurement()
+                .map_err(ApiError::VmInfo)
                 .map(ApiResponsePayload::VmAction);
 
             response_sender
@@ -571,23 +1432,23 @@ impl ApiAction for VmAddPmem {
     }
 }
 
-pub struct VmAddNet;
+pub struct VmSnpReport;
 
-impl ApiAction for VmAddNet {
-    type RequestBody = NetConfig;
+impl ApiAction for VmSnpReport {
+    type RequestBody = VmSnpReportData;
     type ResponseBody = Option<Body>;
 
     fn request(
         &self,
-        config: Self::RequestBody,
+        snp_report_data: Self::RequestBody,
         response_sender: Sender<ApiResponse>,
     ) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmAddNet {:?}", config);
+            info!("API request event: VmSnpReport {:?}", snp_report_data);
 
             let response = vmm
-                .vm_add_net(config)
-                .map_err(ApiError::VmAddNet)
+                .vm_snp_report(snp_report_data)
+                .map_err(ApiError::VmSnpReport)
                 .map(ApiResponsePayload::VmAction);
 
             response_sender
@@ -608,23 +1469,19 @@ impl ApiAction for VmAddNet {
     }
 }
 
-pub struct VmAddVdpa;
+pub struct VmUnplugStatus;
 
-impl ApiAction for VmAddVdpa {
-    type RequestBody = VdpaConfig;
+impl ApiAction for VmUnplugStatus {
+    type RequestBody = ();
     type ResponseBody = Option<Body>;
 
-    fn request(
-        &self,
-        config: Self::RequestBody,
-        response_sender: Sender<ApiResponse>,
-    ) -> ApiRequest {
+    fn request(&self, _: Self::RequestBody, response_sender: Sender<ApiResponse>) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmAddVdpa {:?}", config);
+            info!("API request event: VmUnplugStatus");
 
             let response = vmm
-                .vm_add_vdpa(config)
-                .map_err(ApiError::VmAddVdpa)
+                .vm_unplug_status()
+                .map_err(ApiError::VmInfo)
                 .map(ApiResponsePayload::VmAction);
 
             response_sender
@@ -645,23 +1502,19 @@ impl ApiAction for VmAddVdpa {
     }
 }
 
-pub struct VmAddVsock;
+pub struct VmTimeInfo;
 
-impl ApiAction for VmAddVsock {
-    type RequestBody = VsockConfig;
+impl ApiAction for VmTimeInfo {
+    type RequestBody = ();
     type ResponseBody = Option<Body>;
 
-    fn request(
-        &self,
-        config: Self::RequestBody,
-        response_sender: Sender<ApiResponse>,
-    ) -> ApiRequest {
+    fn request(&self, _: Self::RequestBody, response_sender: Sender<ApiResponse>) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmAddVsock {:?}", config);
+            info!("API request event: VmTimeInfo");
 
             let response = vmm
-                .vm_add_vsock(config)
-                .map_err(ApiError::VmAddVsock)
+                .vm_time_info()
+                .map_err(ApiError::VmInfo)
                 .map(ApiResponsePayload::VmAction);
 
             response_sender
@@ -682,24 +1535,24 @@ impl ApiAction for VmAddVsock {
     }
 }
 
-pub struct VmAddUserDevice;
+pub struct VmTimeAdjust;
 
-impl ApiAction for VmAddUserDevice {
-    type RequestBody = UserDeviceConfig;
+impl ApiAction for VmTimeAdjust {
+    type RequestBody = VmTimeAdjustData;
     type ResponseBody = Option<Body>;
 
     fn request(
         &self,
-        config: Self::RequestBody,
+        time_adjust_data: Self::RequestBody,
         response_sender: Sender<ApiResponse>,
     ) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmAddUserDevice {:?}", config);
+            info!("API request event: VmTimeAdjust {:?}", time_adjust_data);
 
             let response = vmm
-                .vm_add_user_device(config)
-                .map_err(ApiError::VmAddUserDevice)
-                .map(ApiResponsePayload::VmAction);
+                .vm_time_adjust(time_adjust_data)
+                .map_err(ApiError::VmTimeAdjust)
+                .map(|_| ApiResponsePayload::Empty);
 
             response_sender
                 .send(response)
@@ -719,19 +1572,23 @@ impl ApiAction for VmAddUserDevice {
     }
 }
 
-pub struct VmBoot;
+pub struct VmInjectError;
 
-impl ApiAction for VmBoot {
-    type RequestBody = ();
+impl ApiAction for VmInjectError {
+    type RequestBody = VmInjectErrorData;
     type ResponseBody = Option<Body>;
 
-    fn request(&self, _: Self::RequestBody, response_sender: Sender<ApiResponse>) -> ApiRequest {
+    fn request(
+        &self,
+        inject_error_data: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmBoot");
+            info!("API request event: VmInjectError {:?}", inject_error_data);
 
             let response = vmm
-                .vm_boot()
-                .map_err(ApiError::VmBoot)
+                .vm_inject_error(inject_error_data)
+                .map_err(ApiError::VmInjectError)
                 .map(|_| ApiResponsePayload::Empty);
 
             response_sender
@@ -752,25 +1609,26 @@ impl ApiAction for VmBoot {
     }
 }
 
-#[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-pub struct VmCoredump;
+pub struct VmMigrateMemory;
 
-#[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-impl ApiAction for VmCoredump {
-    type RequestBody = VmCoredumpData;
+impl ApiAction for VmMigrateMemory {
+    type RequestBody = VmMigrateMemoryData;
     type ResponseBody = Option<Body>;
 
     fn request(
         &self,
-        coredump_data: Self::RequestBody,
+        migrate_memory_data: Self::RequestBody,
         response_sender: Sender<ApiResponse>,
     ) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmCoredump {:?}", coredump_data);
+            info!(
+                "API request event: VmMigrateMemory {:?}",
+                migrate_memory_data
+            );
 
             let response = vmm
-                .vm_coredump(&coredump_data.destination_url)
-                .map_err(ApiError::VmCoredump)
+                .vm_migrate_memory(migrate_memory_data)
+                .map_err(ApiError::VmMigrateMemory)
                 .map(|_| ApiResponsePayload::Empty);
 
             response_sender
@@ -791,20 +1649,27 @@ impl ApiAction for VmCoredump {
     }
 }
 
-pub struct VmCounters;
+pub struct VmSetZonePolicy;
 
-impl ApiAction for VmCounters {
-    type RequestBody = ();
+impl ApiAction for VmSetZonePolicy {
+    type RequestBody = VmSetZonePolicyData;
     type ResponseBody = Option<Body>;
 
-    fn request(&self, _: Self::RequestBody, response_sender: Sender<ApiResponse>) -> ApiRequest {
+    fn request(
+        &self,
+        set_zone_policy_data: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmCounters");
+            info!(
+                "API request event: VmSetZonePolicy {:?}",
+                set_zone_policy_data
+            );
 
             let response = vmm
-                .vm_counters()
-                .map_err(ApiError::VmInfo)
-                .map(ApiResponsePayload::VmAction);
+                .vm_set_zone_policy(set_zone_policy_data)
+                .map_err(ApiError::VmSetZonePolicy)
+                .map(|_| ApiResponsePayload::Empty);
 
             response_sender
                 .send(response)
@@ -863,6 +1728,45 @@ impl ApiAction for VmCreate {
     }
 }
 
+pub struct VmValidateConfig;
+
+impl ApiAction for VmValidateConfig {
+    type RequestBody = Box<VmConfig>;
+    type ResponseBody = VmValidateConfigResponse;
+
+    fn request(
+        &self,
+        config: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmValidateConfig {:?}", config);
+
+            let response = ApiResponsePayload::VmValidateConfig(vmm.vm_validate_config(&config));
+
+            response_sender
+                .send(Ok(response))
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<VmValidateConfigResponse> {
+        let response = get_response(self, api_evt, api_sender, data)?;
+
+        match response {
+            ApiResponsePayload::VmValidateConfig(response) => Ok(response),
+            _ => Err(ApiError::ResponsePayloadType),
+        }
+    }
+}
+
 pub struct VmDelete;
 
 impl ApiAction for VmDelete {
@@ -937,17 +1841,21 @@ impl ApiAction for VmInfo {
 pub struct VmPause;
 
 impl ApiAction for VmPause {
-    type RequestBody = ();
+    type RequestBody = VmPauseData;
     type ResponseBody = Option<Body>;
 
-    fn request(&self, _: Self::RequestBody, response_sender: Sender<ApiResponse>) -> ApiRequest {
+    fn request(
+        &self,
+        pause_data: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
         Box::new(move |vmm| {
-            info!("API request event: VmPause");
+            info!("API request event: VmPause {:?}", pause_data);
 
             let response = vmm
-                .vm_pause()
+                .vm_pause(pause_data)
                 .map_err(ApiError::VmPause)
-                .map(|_| ApiResponsePayload::Empty);
+                .map(ApiResponsePayload::VmAction);
 
             response_sender
                 .send(response)
@@ -1081,7 +1989,11 @@ impl ApiAction for VmRemoveDevice {
             info!("API request event: VmRemoveDevice {:?}", remove_device_data);
 
             let response = vmm
-                .vm_remove_device(remove_device_data.id)
+                .vm_remove_device(
+                    remove_device_data.id,
+                    remove_device_data.timeout.map(Duration::from_millis),
+                    remove_device_data.surprise_removal,
+                )
                 .map_err(ApiError::VmRemoveDevice)
                 .map(|_| ApiResponsePayload::Empty);
 
@@ -1103,6 +2015,80 @@ impl ApiAction for VmRemoveDevice {
     }
 }
 
+pub struct VmBlockTrace;
+
+impl ApiAction for VmBlockTrace {
+    type RequestBody = VmBlockTraceData;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        block_trace_data: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmBlockTrace {:?}", block_trace_data);
+
+            let response = vmm
+                .vm_block_trace(block_trace_data.id, block_trace_data.path)
+                .map_err(ApiError::VmBlockTrace)
+                .map(|_| ApiResponsePayload::Empty);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
+pub struct VmKeepDisk;
+
+impl ApiAction for VmKeepDisk {
+    type RequestBody = VmKeepDiskData;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        keep_disk_data: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmKeepDisk {:?}", keep_disk_data);
+
+            let response = vmm
+                .vm_keep_disk(keep_disk_data.id, keep_disk_data.path)
+                .map_err(ApiError::VmKeepDisk)
+                .map(|_| ApiResponsePayload::Empty);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
 pub struct VmResize;
 
 impl ApiAction for VmResize {
@@ -1423,6 +2409,43 @@ impl ApiAction for VmmShutdown {
     }
 }
 
+pub struct VmmSetLogLevel;
+
+impl ApiAction for VmmSetLogLevel {
+    type RequestBody = VmmLogLevelData;
+    type ResponseBody = Option<Body>;
+
+    fn request(
+        &self,
+        log_level_data: Self::RequestBody,
+        response_sender: Sender<ApiResponse>,
+    ) -> ApiRequest {
+        Box::new(move |vmm| {
+            info!("API request event: VmmSetLogLevel {:?}", log_level_data);
+
+            let response = vmm
+                .vmm_set_log_level(&log_level_data.filter)
+                .map_err(ApiError::VmmSetLogLevel)
+                .map(|_| ApiResponsePayload::Empty);
+
+            response_sender
+                .send(response)
+                .map_err(VmmError::ApiResponseSend)?;
+
+            Ok(false)
+        })
+    }
+
+    fn send(
+        &self,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Self::RequestBody,
+    ) -> ApiResult<Self::ResponseBody> {
+        get_response_body(self, api_evt, api_sender, data)
+    }
+}
+
 pub struct VmNmi;
 
 impl ApiAction for VmNmi {
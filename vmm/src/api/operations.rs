@@ -0,0 +1,377 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Long-running API operations.
+//!
+//! Snapshotting or migrating a VM can keep the VMM thread busy for minutes.
+//! Rather than waiting for it, a client can start such an action as an
+//! operation: the request is queued to the VMM thread as usual, but its
+//! response is collected by the operation, whose state the client polls
+//! through the operation identifier, and may cancel. The VMM thread reports
+//! the progress of the running operation with [`report_progress`], and stops
+//! it where it is safe to do so once [`cancel_requested`] says so.
+//!
+//! While an operation is queued or running, the requests which would have to
+//! wait for the VMM thread are turned down as `Busy`. The read-only ones are
+//! still served, from the state of the VMM captured when the operation was
+//! started.
+
+use super::{
+    send_request, ApiAction, ApiError, ApiErrorBody, ApiRequest, ApiResponse, ApiResult,
+    RequestState, VmInfo, VmInfoResponse, VmmPing, VmmPingResponse,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc::{Receiver, RecvError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use vmm_sys_util::eventfd::EventFd;
+
+// Number of completed operations kept for their outcome to be queried.
+const MAX_COMPLETED_OPERATIONS: usize = 16;
+
+// Only one operation runs at a time, the progress is about that one.
+static PROGRESS: AtomicU8 = AtomicU8::new(0);
+
+// Cancellation of the operation the VMM thread is running, if any.
+static RUNNING: Mutex<Option<Arc<Cancellation>>> = Mutex::new(None);
+
+/// Reports the progress of the running operation, in percent.
+pub fn report_progress(percent: u8) {
+    PROGRESS.store(percent.min(100), Ordering::Relaxed);
+}
+
+/// Whether the running operation should be stopped, in which case the caller
+/// must stop it.
+pub fn cancel_requested() -> bool {
+    match RUNNING.lock().unwrap().as_ref() {
+        Some(cancellation) if cancellation.requested.load(Ordering::Relaxed) => {
+            cancellation.honoured.store(true, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Cancellation state of an operation, shared with the VMM thread while it
+/// runs the operation.
+#[derive(Default)]
+pub struct Cancellation {
+    requested: AtomicBool,
+    // Whether the VMM thread stopped the operation as requested
+    honoured: AtomicBool,
+}
+
+/// Marks the operation as the one the VMM thread runs, until dropped.
+pub(super) struct RunningOperation;
+
+impl RunningOperation {
+    pub(super) fn new(cancellation: Arc<Cancellation>) -> Self {
+        *RUNNING.lock().unwrap() = Some(cancellation);
+        RunningOperation
+    }
+}
+
+impl Drop for RunningOperation {
+    fn drop(&mut self) {
+        *RUNNING.lock().unwrap() = None;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl OperationState {
+    fn is_pending(self) -> bool {
+        matches!(self, OperationState::Queued | OperationState::Running)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OperationInfo {
+    pub id: u64,
+    /// Endpoint which started the operation, e.g. `vm.snapshot`
+    pub kind: String,
+    pub state: OperationState,
+    /// Progress of the operation, in percent
+    pub progress: u8,
+    /// Why the operation failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiErrorBody>,
+}
+
+struct Operation {
+    kind: &'static str,
+    // Whether the operation can be stopped once running
+    cancellable: bool,
+    cancellation: Arc<Cancellation>,
+    request: Arc<Mutex<RequestState>>,
+    response: Receiver<ApiResponse>,
+    outcome: Option<(OperationState, u8, Option<ApiErrorBody>)>,
+}
+
+impl Operation {
+    fn state(&mut self) -> (OperationState, u8, Option<ApiErrorBody>) {
+        if let Some(outcome) = &self.outcome {
+            return outcome.clone();
+        }
+
+        let progress = PROGRESS.load(Ordering::Relaxed);
+        let outcome = match self.response.try_recv() {
+            Ok(Ok(_)) => (OperationState::Succeeded, 100, None),
+            // The operation failed because the VMM thread stopped it.
+            Ok(Err(_)) if self.cancellation.honoured.load(Ordering::Relaxed) => {
+                (OperationState::Cancelled, progress, None)
+            }
+            Ok(Err(e)) => (OperationState::Failed, progress, Some(e.body())),
+            Err(TryRecvError::Empty) => {
+                return match *self.request.lock().unwrap() {
+                    RequestState::Queued => (OperationState::Queued, 0, None),
+                    RequestState::Running => (OperationState::Running, progress, None),
+                    RequestState::Cancelled => (OperationState::Cancelled, 0, None),
+                };
+            }
+            // The request is dropped without a response once cancelled.
+            Err(TryRecvError::Disconnected) => match *self.request.lock().unwrap() {
+                RequestState::Cancelled => (OperationState::Cancelled, 0, None),
+                _ => (
+                    OperationState::Failed,
+                    progress,
+                    Some(ApiError::ResponseRecv(RecvError).body()),
+                ),
+            },
+        };
+        self.outcome = Some(outcome.clone());
+
+        outcome
+    }
+
+    fn info(&mut self, id: u64) -> OperationInfo {
+        let (state, progress, error) = self.state();
+        OperationInfo {
+            id,
+            kind: self.kind.to_string(),
+            state,
+            progress,
+            error,
+        }
+    }
+}
+
+/// Operations started through the API, together with the state of the VMM
+/// served to the read-only requests while an operation is pending.
+#[derive(Default)]
+pub struct Operations {
+    next_id: u64,
+    operations: BTreeMap<u64, Operation>,
+    ping: Option<VmmPingResponse>,
+    info: Option<VmInfoResponse>,
+}
+
+pub static OPERATIONS: Lazy<Mutex<Operations>> = Lazy::new(Default::default);
+
+impl Operations {
+    /// Whether an operation is queued or running on the VMM thread.
+    pub fn busy(&mut self) -> bool {
+        self.operations
+            .values_mut()
+            .any(|operation| operation.state().0.is_pending())
+    }
+
+    /// Answer to `vmm.ping` while busy, `None` if the VMM thread is available.
+    pub fn vmm_ping(&mut self) -> Option<ApiResult<VmmPingResponse>> {
+        self.busy().then(|| self.ping.clone().ok_or(ApiError::Busy))
+    }
+
+    /// Answer to `vm.info` while busy, `None` if the VMM thread is available.
+    pub fn vm_info(&mut self) -> Option<ApiResult<VmInfoResponse>> {
+        self.busy().then(|| self.info.clone().ok_or(ApiError::Busy))
+    }
+
+    /// Queues `action` to the VMM thread as the operation `kind`, which
+    /// checks for cancellation requests while running if `cancellable`.
+    pub fn start<Action: ApiAction>(
+        &mut self,
+        action: &Action,
+        kind: &'static str,
+        cancellable: bool,
+        api_evt: EventFd,
+        api_sender: Sender<ApiRequest>,
+        data: Action::RequestBody,
+    ) -> ApiResult<OperationInfo> {
+        if self.busy() {
+            return Err(ApiError::Busy);
+        }
+
+        // Capture what the read-only requests are answered with until the
+        // operation completes. The VM may not exist, e.g. when receiving a
+        // migration.
+        let evt = || api_evt.try_clone().map_err(ApiError::EventFdWrite);
+        self.ping = Some(VmmPing.send(evt()?, api_sender.clone(), ())?);
+        self.info = VmInfo.send(evt()?, api_sender.clone(), ()).ok();
+
+        report_progress(0);
+        let cancellation = Arc::new(Cancellation::default());
+        let (request, response) = send_request(
+            action,
+            api_evt,
+            api_sender,
+            data,
+            Some(cancellation.clone()),
+        )?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.operations.insert(
+            id,
+            Operation {
+                kind,
+                cancellable,
+                cancellation,
+                request,
+                response,
+                outcome: None,
+            },
+        );
+        self.prune();
+        info!("Started operation {}: {}", id, kind);
+
+        Ok(self.operations.get_mut(&id).unwrap().info(id))
+    }
+
+    /// State of the operation `id`.
+    pub fn info(&mut self, id: u64) -> ApiResult<OperationInfo> {
+        self.operations
+            .get_mut(&id)
+            .map(|operation| operation.info(id))
+            .ok_or(ApiError::OperationNotFound(id))
+    }
+
+    /// Cancels the operation `id`. Once running, only the cancellable
+    /// operations can be cancelled, and they only stop at the next point
+    /// where it is safe to. Cancelling a completed operation is a no-op.
+    pub fn cancel(&mut self, id: u64) -> ApiResult<OperationInfo> {
+        let operation = self
+            .operations
+            .get_mut(&id)
+            .ok_or(ApiError::OperationNotFound(id))?;
+
+        if operation.state().0.is_pending() {
+            let mut request = operation.request.lock().unwrap();
+            match *request {
+                RequestState::Queued => *request = RequestState::Cancelled,
+                RequestState::Running if operation.cancellable => {
+                    operation
+                        .cancellation
+                        .requested
+                        .store(true, Ordering::Relaxed);
+                }
+                RequestState::Running => return Err(ApiError::OperationNotCancellable(id)),
+                RequestState::Cancelled => {}
+            }
+            info!("Cancelling operation {}", id);
+        }
+
+        Ok(operation.info(id))
+    }
+
+    /// Cancels the pending operations, e.g. ahead of the VMM shutting down.
+    /// A running operation which can't be cancelled is left to complete.
+    pub fn cancel_pending(&mut self) {
+        let pending: Vec<u64> = self
+            .operations
+            .iter_mut()
+            .filter_map(|(id, operation)| operation.state().0.is_pending().then_some(*id))
+            .collect();
+
+        for id in pending {
+            if let Err(e) = self.cancel(id) {
+                info!("Waiting for operation {} to complete: {}", id, e);
+            }
+        }
+    }
+
+    // Forgets about the oldest completed operations.
+    fn prune(&mut self) {
+        let completed: Vec<u64> = self
+            .operations
+            .iter_mut()
+            .filter_map(|(id, operation)| (!operation.state().0.is_pending()).then_some(*id))
+            .collect();
+
+        for id in completed
+            .iter()
+            .take(completed.len().saturating_sub(MAX_COMPLETED_OPERATIONS))
+        {
+            self.operations.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::api::ApiResponsePayload;
+    use std::sync::mpsc::channel;
+
+    fn operation(cancellation: &Arc<Cancellation>, response: ApiResponse) -> Operation {
+        let (sender, receiver) = channel();
+        sender.send(response).unwrap();
+        Operation {
+            kind: "vm.snapshot",
+            cancellable: true,
+            cancellation: cancellation.clone(),
+            request: Arc::new(Mutex::new(RequestState::Running)),
+            response: receiver,
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn test_operation_cancellation() {
+        // Nothing to cancel without a running operation.
+        assert!(!cancel_requested());
+
+        let cancellation = Arc::new(Cancellation::default());
+        let running = RunningOperation::new(cancellation.clone());
+        assert!(!cancel_requested());
+        cancellation.requested.store(true, Ordering::Relaxed);
+        assert!(cancel_requested());
+        assert!(cancellation.honoured.load(Ordering::Relaxed));
+
+        // The request doesn't outlive the operation.
+        drop(running);
+        assert!(!cancel_requested());
+        let _running = RunningOperation::new(Arc::new(Cancellation::default()));
+        assert!(!cancel_requested());
+
+        // An operation failing for another reason than the cancellation
+        // reports its error.
+        let cancellation = Arc::new(Cancellation::default());
+        cancellation.requested.store(true, Ordering::Relaxed);
+        let mut failed = operation(&cancellation, Err(ApiError::VmNotBooted));
+        let (state, _, error) = failed.state();
+        assert_eq!(state, OperationState::Failed);
+        assert_eq!(error.unwrap().code, "VmNotBooted");
+
+        cancellation.honoured.store(true, Ordering::Relaxed);
+        let mut cancelled = operation(&cancellation, Err(ApiError::VmNotBooted));
+        let (state, _, error) = cancelled.state();
+        assert_eq!(state, OperationState::Cancelled);
+        assert!(error.is_none());
+
+        // Completing anyway is a success.
+        let mut succeeded = operation(&cancellation, Ok(ApiResponsePayload::Empty));
+        assert_eq!(succeeded.state().0, OperationState::Succeeded);
+    }
+}
@@ -0,0 +1,330 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Generation of a guest NUMA topology mirroring the host one.
+//!
+//! Every vCPU is assigned to the host NUMA node its host CPUs belong to, and
+//! one guest NUMA node is created for each host node in use. Each guest node
+//! gets a memory zone bound to its host node, sized in proportion to its
+//! number of vCPUs, and the distances measured between the host nodes.
+
+use crate::hugetlbfs;
+use crate::vm_config::{
+    CpusConfig, HostNumaPolicy, MemoryConfig, MemoryZoneConfig, NumaConfig, NumaDistance, VmConfig,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::result;
+use thiserror::Error;
+
+const NODE_SYSFS_DIR: &str = "/sys/devices/system/node";
+const ZONE_ALIGN_SIZE: u64 = 2 << 20;
+
+/// Errors associated with the automatic NUMA topology
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Error reading the host NUMA topology
+    #[error("Error reading the host NUMA topology: {0}")]
+    ReadTopology(#[source] io::Error),
+
+    /// Error getting the hugepage size the zones are aligned on
+    #[error("Error getting the hugepage size: {0}")]
+    HugePageSize(#[source] hugetlbfs::Error),
+
+    /// vCPU without any host CPU
+    #[error("vCPU {0} is not pinned to any host CPU")]
+    UnpinnedVcpu(u32),
+
+    /// Host CPU outside of any host NUMA node
+    #[error("Host CPU {0} doesn't belong to any host NUMA node")]
+    UnknownHostCpu(usize),
+
+    /// vCPU pinned to host CPUs from several host NUMA nodes
+    #[error("vCPU {0} is pinned to host CPUs from different host NUMA nodes")]
+    VcpuAcrossNodes(u32),
+
+    /// Not enough memory to give every guest NUMA node a zone
+    #[error("Memory size {size:#x} is too small to be split across {nodes} NUMA nodes")]
+    MemoryTooSmall { size: u64, nodes: usize },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+struct HostNode {
+    id: u32,
+    cpus: Vec<usize>,
+    // Distances to every host node, in the order of the node ids.
+    distances: Vec<u8>,
+}
+
+// Parse a CPU list such as "0-3,8,10-11".
+fn parse_cpulist(cpulist: &str) -> io::Result<Vec<usize>> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut cpus = Vec::new();
+    for range in cpulist.trim().split(',').filter(|r| !r.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let first = first.parse::<usize>().map_err(invalid)?;
+        let last = last.parse::<usize>().map_err(invalid)?;
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+fn parse_distances(distances: &str) -> io::Result<Vec<u8>> {
+    distances
+        .split_whitespace()
+        .map(|d| {
+            d.parse::<u8>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
+
+fn read_host_nodes(sysfs_dir: &Path) -> io::Result<Vec<HostNode>> {
+    let mut nodes = Vec::new();
+    for entry in fs::read_dir(sysfs_dir)? {
+        let entry = entry?;
+        let Some(id) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|id| id.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        let path = entry.path();
+        nodes.push(HostNode {
+            id,
+            cpus: parse_cpulist(&fs::read_to_string(path.join("cpulist"))?)?,
+            distances: parse_distances(&fs::read_to_string(path.join("distance"))?)?,
+        });
+    }
+    nodes.sort_by_key(|node| node.id);
+
+    Ok(nodes)
+}
+
+fn generate(
+    cpus: &CpusConfig,
+    memory: &mut MemoryConfig,
+    host_nodes: &[HostNode],
+    align: u64,
+) -> Result<Vec<NumaConfig>> {
+    // Host node index to the vCPUs pinned to it, in the order of the host
+    // node ids so that the guest node ids follow the host ones.
+    let mut node_vcpus: BTreeMap<usize, Vec<u32>> = BTreeMap::new();
+    for vcpu in 0..cpus.max_vcpus {
+        let host_cpus = cpus
+            .affinity
+            .iter()
+            .flatten()
+            .find(|affinity| affinity.vcpu == vcpu)
+            .map(|affinity| affinity.host_cpus.as_slice())
+            .unwrap_or_default();
+
+        let mut node_index = None;
+        for host_cpu in host_cpus {
+            let index = host_nodes
+                .iter()
+                .position(|node| node.cpus.contains(host_cpu))
+                .ok_or(Error::UnknownHostCpu(*host_cpu))?;
+            if node_index.is_some_and(|node_index| node_index != index) {
+                return Err(Error::VcpuAcrossNodes(vcpu));
+            }
+            node_index = Some(index);
+        }
+
+        let node_index = node_index.ok_or(Error::UnpinnedVcpu(vcpu))?;
+        node_vcpus.entry(node_index).or_default().push(vcpu);
+    }
+
+    let size = memory.size;
+    let units = size / align;
+    let max_vcpus = cpus.max_vcpus as u64;
+
+    let mut zones = Vec::new();
+    let mut numa = Vec::new();
+    let mut allocated = 0;
+    for (guest_id, (node_index, vcpus)) in node_vcpus.iter().enumerate() {
+        // The last node gets whatever is left, so that the zones add up to
+        // the requested memory size.
+        let zone_size = if guest_id == node_vcpus.len() - 1 {
+            size - allocated
+        } else {
+            units * vcpus.len() as u64 / max_vcpus * align
+        };
+        if zone_size == 0 {
+            return Err(Error::MemoryTooSmall {
+                size,
+                nodes: node_vcpus.len(),
+            });
+        }
+        allocated += zone_size;
+
+        let host_node = &host_nodes[*node_index];
+        let zone_id = format!("auto_numa{guest_id}");
+        info!(
+            "Guest NUMA node {} mirrors host NUMA node {}: vCPUs {:?}, {} MiB",
+            guest_id,
+            host_node.id,
+            vcpus,
+            zone_size >> 20
+        );
+
+        zones.push(MemoryZoneConfig {
+            id: zone_id.clone(),
+            size: zone_size,
+            file: None,
+            shared: memory.shared,
+            hugepages: memory.hugepages,
+            hugepage_size: memory.hugepage_size,
+            host_numa_node: Some(host_node.id),
+            host_numa_policy: HostNumaPolicy::Bind,
+            hotplug_size: None,
+            hotplugged_size: None,
+            prefault: memory.prefault,
+        });
+
+        let distances = node_vcpus
+            .keys()
+            .enumerate()
+            .filter(|(destination, _)| *destination != guest_id)
+            .filter_map(|(destination, other_index)| {
+                Some(NumaDistance {
+                    destination: destination as u32,
+                    distance: *host_node.distances.get(*other_index)?,
+                })
+            })
+            .collect();
+
+        numa.push(NumaConfig {
+            guest_numa_id: guest_id as u32,
+            cpus: Some(vcpus.clone()),
+            distances: Some(distances),
+            memory_zones: Some(vec![zone_id]),
+            #[cfg(target_arch = "x86_64")]
+            sgx_epc_sections: None,
+            pci_segments: None,
+        });
+    }
+
+    memory.size = 0;
+    memory.zones = Some(zones);
+    memory.auto_numa = false;
+
+    Ok(numa)
+}
+
+/// Replace the guest memory size of `config` with memory zones and NUMA
+/// nodes mirroring the host NUMA nodes the vCPUs are pinned to.
+pub fn apply(config: &mut VmConfig) -> Result<()> {
+    let host_nodes = read_host_nodes(Path::new(NODE_SYSFS_DIR)).map_err(Error::ReadTopology)?;
+
+    let align = match (config.memory.hugepages, config.memory.hugepage_size) {
+        (true, Some(hugepage_size)) => hugepage_size,
+        (true, None) => hugetlbfs::default_page_size().map_err(Error::HugePageSize)?,
+        (false, _) => ZONE_ALIGN_SIZE,
+    };
+
+    let numa = generate(&config.cpus, &mut config.memory, &host_nodes, align)?;
+    config.numa = Some(numa);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm_config::CpuAffinity;
+
+    fn host_nodes() -> Vec<HostNode> {
+        vec![
+            HostNode {
+                id: 0,
+                cpus: parse_cpulist("0-3,8-11\n").unwrap(),
+                distances: parse_distances("10 21\n").unwrap(),
+            },
+            HostNode {
+                id: 1,
+                cpus: parse_cpulist("4-7,12-15\n").unwrap(),
+                distances: parse_distances("21 10\n").unwrap(),
+            },
+        ]
+    }
+
+    fn cpus_config(affinity: Vec<(u32, Vec<usize>)>) -> CpusConfig {
+        CpusConfig {
+            boot_vcpus: affinity.len() as u32,
+            max_vcpus: affinity.len() as u32,
+            affinity: Some(
+                affinity
+                    .into_iter()
+                    .map(|(vcpu, host_cpus)| CpuAffinity { vcpu, host_cpus })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn memory_config() -> MemoryConfig {
+        MemoryConfig {
+            size: 3 << 30,
+            auto_numa: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_cpulist() {
+        assert_eq!(
+            parse_cpulist("0-2,8,10-11\n").unwrap(),
+            vec![0, 1, 2, 8, 10, 11]
+        );
+        assert_eq!(parse_cpulist("\n").unwrap(), Vec::<usize>::new());
+        assert!(parse_cpulist("0-a").is_err());
+    }
+
+    #[test]
+    fn test_generate() {
+        let cpus = cpus_config(vec![(0, vec![0]), (1, vec![4, 5]), (2, vec![12])]);
+        let mut memory = memory_config();
+        let numa = generate(&cpus, &mut memory, &host_nodes(), ZONE_ALIGN_SIZE).unwrap();
+
+        assert_eq!(memory.size, 0);
+        assert!(!memory.auto_numa);
+        let zones = memory.zones.unwrap();
+        assert_eq!(zones.len(), 2);
+        assert_eq!(zones[0].size, 1 << 30);
+        assert_eq!(zones[0].host_numa_node, Some(0));
+        assert_eq!(zones[1].size, 2 << 30);
+        assert_eq!(zones[1].host_numa_node, Some(1));
+
+        assert_eq!(numa[0].cpus, Some(vec![0]));
+        assert_eq!(numa[1].cpus, Some(vec![1, 2]));
+        assert_eq!(
+            numa[1].distances,
+            Some(vec![NumaDistance {
+                destination: 0,
+                distance: 21
+            }])
+        );
+
+        let cpus = cpus_config(vec![(0, vec![0, 4])]);
+        assert!(matches!(
+            generate(&cpus, &mut memory_config(), &host_nodes(), ZONE_ALIGN_SIZE),
+            Err(Error::VcpuAcrossNodes(0))
+        ));
+
+        let cpus = cpus_config(vec![(0, vec![0]), (1, vec![16])]);
+        assert!(matches!(
+            generate(&cpus, &mut memory_config(), &host_nodes(), ZONE_ALIGN_SIZE),
+            Err(Error::UnknownHostCpu(16))
+        ));
+    }
+}
@@ -0,0 +1,395 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Embedding of the VMM into Rust applications.
+//!
+//! [`VmBuilder`] describes a VM, and spawns the VMM thread running it without
+//! going through the HTTP or D-Bus APIs. The returned [`VmHandle`] drives the
+//! VM with the same actions as the other APIs.
+//!
+//! ```no_run
+//! use vmm::builder::VmBuilder;
+//! use vmm::config::DiskConfig;
+//!
+//! let vm = VmBuilder::new()
+//!     .cpus(2)
+//!     .memory(1 << 30)
+//!     .kernel("/path/to/vmlinux")
+//!     .cmdline("console=hvc0 root=/dev/vda1")
+//!     .disk(DiskConfig::parse("path=/path/to/disk.raw").unwrap())
+//!     .spawn()
+//!     .unwrap();
+//!
+//! println!("{:?}", vm.info().unwrap().state);
+//! vm.terminate().unwrap();
+//! ```
+
+use crate::api::{
+    ApiAction, ApiError, ApiRequest, VmBoot, VmCreate, VmInfo, VmInfoResponse, VmPause,
+    VmPauseData, VmPowerButton, VmReboot, VmResume, VmShutdown, VmmShutdown,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::config::DebugConsoleConfig;
+use crate::config::{
+    default_console, default_serial, ConsoleConfig, CpusConfig, DiskConfig, MemoryConfig,
+    NetConfig, PayloadConfig, RngConfig, VmConfig,
+};
+use crate::{start_vmm_thread, VmmThreadHandle, VmmVersionInfo};
+use libc::EFD_NONBLOCK;
+use seccompiler::SeccompAction;
+use std::io;
+use std::path::PathBuf;
+use std::result;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::signal::block_signal;
+
+/// Errors associated with the embedded VMM
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Error creating the hypervisor
+    #[error("Error creating the hypervisor: {0}")]
+    CreateHypervisor(#[source] hypervisor::HypervisorError),
+
+    /// Error creating or cloning an EventFd
+    #[error("Error creating an EventFd: {0}")]
+    EventFd(#[source] io::Error),
+
+    /// Error starting the VMM thread
+    #[error("Error starting the VMM thread: {0}")]
+    StartVmmThread(#[source] crate::Error),
+
+    /// Error returned by an API action
+    #[error("Error running the API action: {0:?}")]
+    Api(ApiError),
+
+    /// The VMM thread panicked
+    #[error("The VMM thread panicked")]
+    ThreadJoin,
+
+    /// The VMM thread exited with an error
+    #[error("The VMM thread exited with an error: {0}")]
+    VmmThread(#[source] crate::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Builder of a VM run by a VMM thread of the calling process.
+pub struct VmBuilder {
+    config: VmConfig,
+    hypervisor: Option<Arc<dyn hypervisor::Hypervisor>>,
+    seccomp_action: SeccompAction,
+    landlock_enable: bool,
+}
+
+impl Default for VmBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VmBuilder {
+    /// A VM with the default CPUs, memory and devices of the command line,
+    /// and no payload.
+    pub fn new() -> Self {
+        VmBuilder {
+            config: VmConfig {
+                cpus: CpusConfig::default(),
+                memory: MemoryConfig::default(),
+                payload: None,
+                rate_limit_groups: None,
+                disks: None,
+                net: None,
+                rng: RngConfig::default(),
+                balloon: None,
+                fs: None,
+                gpu: None,
+                pmem: None,
+                serial: default_serial(),
+                console: default_console(),
+                console_ports: None,
+                #[cfg(target_arch = "x86_64")]
+                debug_console: DebugConsoleConfig::default(),
+                devices: None,
+                user_devices: None,
+                vdpa: None,
+                vsock: None,
+                #[cfg(feature = "pvmemcontrol")]
+                pvmemcontrol: None,
+                pvpanic: false,
+                iommu: false,
+                #[cfg(target_arch = "x86_64")]
+                sgx_epc: None,
+                #[cfg(target_arch = "aarch64")]
+                fdt_overlays: None,
+                numa: None,
+                watchdog: false,
+                #[cfg(feature = "guest_debug")]
+                gdb: false,
+                pci_segments: None,
+                platform: None,
+                #[cfg(feature = "sev_snp")]
+                sev_snp: None,
+                tpm: None,
+                restart_policy: None,
+                #[cfg(feature = "mdns")]
+                mdns: None,
+                preserved_fds: None,
+                landlock_enable: false,
+                landlock_rules: None,
+            },
+            hypervisor: None,
+            seccomp_action: SeccompAction::Trap,
+            landlock_enable: false,
+        }
+    }
+
+    /// Start from a complete VM configuration, e.g. one parsed from the
+    /// command line syntax with [`VmConfig::parse`].
+    pub fn from_config(config: VmConfig) -> Self {
+        VmBuilder {
+            config,
+            ..Self::new()
+        }
+    }
+
+    /// Number of vCPUs, all of them booted.
+    pub fn cpus(mut self, vcpus: u32) -> Self {
+        self.config.cpus.boot_vcpus = vcpus;
+        self.config.cpus.max_vcpus = vcpus;
+        self
+    }
+
+    /// Size of the guest RAM in bytes.
+    pub fn memory(mut self, size: u64) -> Self {
+        self.config.memory.size = size;
+        self
+    }
+
+    fn payload(&mut self) -> &mut PayloadConfig {
+        self.config.payload.get_or_insert(PayloadConfig {
+            firmware: None,
+            kernel: None,
+            cmdline: None,
+            initramfs: None,
+            #[cfg(feature = "igvm")]
+            igvm: None,
+            #[cfg(feature = "igvm")]
+            igvm_compatibility_mask: None,
+            #[cfg(feature = "sev_snp")]
+            host_data: None,
+        })
+    }
+
+    pub fn kernel(mut self, path: impl Into<PathBuf>) -> Self {
+        self.payload().kernel = Some(path.into());
+        self
+    }
+
+    pub fn firmware(mut self, path: impl Into<PathBuf>) -> Self {
+        self.payload().firmware = Some(path.into());
+        self
+    }
+
+    pub fn initramfs(mut self, path: impl Into<PathBuf>) -> Self {
+        self.payload().initramfs = Some(path.into());
+        self
+    }
+
+    pub fn cmdline(mut self, cmdline: impl Into<String>) -> Self {
+        self.payload().cmdline = Some(cmdline.into());
+        self
+    }
+
+    /// Add a disk, can be called several times.
+    pub fn disk(mut self, disk: DiskConfig) -> Self {
+        self.config.disks.get_or_insert_with(Vec::new).push(disk);
+        self
+    }
+
+    /// Add a network interface, can be called several times.
+    pub fn net(mut self, net: NetConfig) -> Self {
+        self.config.net.get_or_insert_with(Vec::new).push(net);
+        self
+    }
+
+    pub fn serial(mut self, serial: ConsoleConfig) -> Self {
+        self.config.serial = serial;
+        self
+    }
+
+    pub fn console(mut self, console: ConsoleConfig) -> Self {
+        self.config.console = console;
+        self
+    }
+
+    /// Hypervisor to run the VM on, the one detected on the host by default.
+    pub fn hypervisor(mut self, hypervisor: Arc<dyn hypervisor::Hypervisor>) -> Self {
+        self.hypervisor = Some(hypervisor);
+        self
+    }
+
+    /// Action taken on a seccomp violation of the VMM threads, which kills
+    /// the process by default.
+    pub fn seccomp(mut self, seccomp_action: SeccompAction) -> Self {
+        self.seccomp_action = seccomp_action;
+        self
+    }
+
+    /// Restrict the VMM thread with Landlock.
+    pub fn landlock(mut self, enable: bool) -> Self {
+        self.landlock_enable = enable;
+        self
+    }
+
+    /// Start the VMM thread, then create and boot the VM.
+    ///
+    /// The signals handled by the VMM are blocked in the calling thread, so
+    /// that they are delivered to the VMM signal handling thread only.
+    pub fn spawn(self) -> Result<VmHandle> {
+        for sig in crate::vm::Vm::HANDLED_SIGNALS
+            .iter()
+            .chain(crate::Vmm::HANDLED_SIGNALS.iter())
+        {
+            if let Err(e) = block_signal(*sig) {
+                warn!("Error blocking signal {}: {}", sig, e);
+            }
+        }
+
+        let hypervisor = match self.hypervisor {
+            Some(hypervisor) => hypervisor,
+            None => hypervisor::new().map_err(Error::CreateHypervisor)?,
+        };
+
+        let (api_sender, api_receiver) = channel();
+        let api_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFd)?;
+        let exit_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFd)?;
+
+        let thread_handle = start_vmm_thread(
+            VmmVersionInfo::new(env!("CARGO_PKG_VERSION"), env!("CARGO_PKG_VERSION")),
+            &None,
+            None,
+            #[cfg(feature = "dbus_api")]
+            None,
+            api_evt.try_clone().map_err(Error::EventFd)?,
+            api_sender.clone(),
+            api_receiver,
+            #[cfg(feature = "guest_debug")]
+            None,
+            #[cfg(feature = "guest_debug")]
+            EventFd::new(EFD_NONBLOCK).map_err(Error::EventFd)?,
+            #[cfg(feature = "guest_debug")]
+            EventFd::new(EFD_NONBLOCK).map_err(Error::EventFd)?,
+            exit_evt,
+            &self.seccomp_action,
+            hypervisor,
+            self.landlock_enable,
+        )
+        .map_err(Error::StartVmmThread)?;
+
+        let handle = VmHandle {
+            api_evt,
+            api_sender,
+            thread_handle,
+        };
+
+        handle.send(&VmCreate, Arc::new(Mutex::new(self.config)))?;
+        handle.send(&VmBoot, ())?;
+
+        Ok(handle)
+    }
+}
+
+/// Handle on a VM spawned by [`VmBuilder::spawn`].
+pub struct VmHandle {
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    thread_handle: VmmThreadHandle,
+}
+
+impl VmHandle {
+    /// Run any API action against the VMM.
+    pub fn send<A: ApiAction>(&self, action: &A, body: A::RequestBody) -> Result<A::ResponseBody> {
+        action
+            .send(
+                self.api_evt.try_clone().map_err(Error::EventFd)?,
+                self.api_sender.clone(),
+                body,
+            )
+            .map_err(Error::Api)
+    }
+
+    pub fn info(&self) -> Result<VmInfoResponse> {
+        self.send(&VmInfo, ())
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        self.send(&VmPause, VmPauseData::default()).map(|_| ())
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        self.send(&VmResume, ()).map(|_| ())
+    }
+
+    pub fn reboot(&self) -> Result<()> {
+        self.send(&VmReboot, ()).map(|_| ())
+    }
+
+    pub fn power_button(&self) -> Result<()> {
+        self.send(&VmPowerButton, ()).map(|_| ())
+    }
+
+    /// Shut the VM down, the VMM thread keeps running.
+    pub fn shutdown(&self) -> Result<()> {
+        self.send(&VmShutdown, ()).map(|_| ())
+    }
+
+    /// Wait for the VMM thread to exit, once the guest powered off.
+    pub fn wait(self) -> Result<()> {
+        self.thread_handle
+            .thread_handle
+            .join()
+            .map_err(|_| Error::ThreadJoin)?
+            .map_err(Error::VmmThread)
+    }
+
+    /// Stop the VM and the VMM thread.
+    pub fn terminate(self) -> Result<()> {
+        self.send(&VmmShutdown, ())?;
+        self.wait()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vm_builder() {
+        let builder = VmBuilder::new()
+            .cpus(4)
+            .memory(1 << 30)
+            .kernel("/tmp/vmlinux")
+            .cmdline("console=hvc0")
+            .disk(DiskConfig::parse("path=/tmp/disk0.raw").unwrap())
+            .disk(DiskConfig::parse("path=/tmp/disk1.raw,readonly=on").unwrap());
+
+        let config = builder.config;
+        assert_eq!(config.cpus.boot_vcpus, 4);
+        assert_eq!(config.cpus.max_vcpus, 4);
+        assert_eq!(config.memory.size, 1 << 30);
+
+        let payload = config.payload.unwrap();
+        assert_eq!(payload.kernel, Some(PathBuf::from("/tmp/vmlinux")));
+        assert_eq!(payload.cmdline.as_deref(), Some("console=hvc0"));
+        assert_eq!(payload.firmware, None);
+
+        let disks = config.disks.unwrap();
+        assert_eq!(disks.len(), 2);
+        assert!(disks[1].readonly);
+    }
+}
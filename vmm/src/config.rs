@@ -3,20 +3,24 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use crate::device_manager;
 use crate::landlock::LandlockAccess;
 pub use crate::vm_config::*;
+use block::qos::{IoMaxConfig, IoPriority};
 use clap::ArgMatches;
 use option_parser::{
     ByteSized, IntegerList, OptionParser, OptionParserError, StringList, Toggle, Tuple,
 };
+use pci::VfioResetMethod;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeSet, HashMap};
 use std::fmt;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::result;
 use std::str::FromStr;
 use thiserror::Error;
-use virtio_devices::{RateLimiterConfig, TokenBucketConfig};
+use virtio_devices::{OnIoError, RateLimiterConfig, TokenBucketConfig};
 
 const MAX_NUM_PCI_SEGMENTS: u16 = 96;
 
@@ -29,6 +33,8 @@ pub enum Error {
     ParseFsTagTooLong,
     /// Filesystem socket is missing
     ParseFsSockMissing,
+    /// GPU socket is missing
+    ParseGpuSockMissing,
     /// Missing persistent memory file parameter.
     ParsePmemFileMissing,
     /// Missing vsock socket path parameter.
@@ -59,6 +65,8 @@ pub enum Error {
     ParseBalloon(OptionParserError),
     /// Error parsing filesystem parameters
     ParseFileSystem(OptionParserError),
+    /// Error parsing GPU parameters
+    ParseGpu(OptionParserError),
     /// Error parsing persistent memory parameters
     ParsePersistentMemory(OptionParserError),
     /// Failed parsing console
@@ -82,6 +90,12 @@ pub enum Error {
     /// Missing 'id' from SGX EPC section
     #[cfg(target_arch = "x86_64")]
     ParseSgxEpcIdMissing,
+    /// Failed parsing device tree overlay parameters
+    #[cfg(target_arch = "aarch64")]
+    ParseFdtOverlay(OptionParserError),
+    /// Missing 'path' from device tree overlay
+    #[cfg(target_arch = "aarch64")]
+    ParseFdtOverlayPathMissing,
     /// Failed parsing NUMA parameters
     ParseNuma(OptionParserError),
     /// Failed validating configuration
@@ -107,10 +121,31 @@ pub enum Error {
     ParseVdpa(OptionParserError),
     /// Missing path for vDPA device
     ParseVdpaPathMissing,
+    /// Failed parsing console port
+    ParseConsolePort(OptionParserError),
+    /// No mode given for console port
+    ParseConsolePortInvalidModeGiven,
     /// Failed parsing TPM device
     ParseTpm(OptionParserError),
     /// Missing path for TPM device
     ParseTpmPathMissing,
+    /// Failed parsing restart policy
+    ParseRestartPolicy(OptionParserError),
+    /// Failed parsing mDNS parameters
+    #[cfg(feature = "mdns")]
+    ParseMdns(OptionParserError),
+    /// Missing host name for mDNS
+    #[cfg(feature = "mdns")]
+    ParseMdnsHostnameMissing,
+    /// Missing guest IP address for mDNS
+    #[cfg(feature = "mdns")]
+    ParseMdnsIpMissing,
+    /// Failed parsing SEV-SNP parameters
+    #[cfg(feature = "sev_snp")]
+    ParseSevSnp(OptionParserError),
+    /// Failed parsing the IGVM compatibility mask
+    #[cfg(feature = "igvm")]
+    ParseIgvmCompatibilityMask(String),
     /// Error parsing Landlock rules
     ParseLandlockRules(OptionParserError),
     /// Missing fields in Landlock rules
@@ -125,17 +160,46 @@ pub enum ValidationError {
     ConsoleFileMissing,
     /// Missing socket path for console
     ConsoleSocketPathMissing,
+    /// Missing TCP address for console
+    ConsoleTcpAddressMissing,
+    /// Console output can only be shipped from an enabled serial port
+    ConsoleLogSocketUnsupported,
     /// Max is less than boot
     CpusMaxLowerThanBoot,
+    /// Max is more than the VMM can describe to the guest
+    CpusMaxTooHigh(u32),
     /// Missing file value for debug-console
     #[cfg(target_arch = "x86_64")]
     DebugconFileMissing,
+    /// Debug-console can't be exposed through a socket
+    #[cfg(target_arch = "x86_64")]
+    DebugconSocketUnsupported,
     /// Both socket and path specified
     DiskSocketAndPath,
     /// Using vhost user requires shared memory
     VhostUserRequiresSharedMemory,
     /// No socket provided for vhost_use
     VhostUserMissingSocket,
+    /// Encryption is not supported for vhost-user disks
+    VhostUserDiskEncryption,
+    /// Disk key given both as a file and as a KBS resource
+    DiskKeyFileAndResource,
+    /// KBS resources require both the URI of the KBS and the KBC socket
+    KbsNotConfigured,
+    /// IO timeout and error policy are not supported for vhost-user disks
+    VhostUserIoErrorPolicy,
+    /// IO timeout can't be zero
+    InvalidIoTimeout,
+    /// IO priority and cgroup limits are not supported for vhost-user disks
+    VhostUserDiskQos,
+    /// Disks served over HTTP must be read-only
+    HttpDiskNotReadonly,
+    /// Encryption, direct I/O and image formats are not supported for HTTP disks
+    HttpDiskUnsupportedOption,
+    /// A cache file is required by HTTP disks, and only by them
+    InvalidDiskCache,
+    /// Transient disks must be writable local raw or qcow2 images
+    TransientDiskUnsupportedOption,
     /// Trying to use IOMMU without PCI
     IommuUnsupported,
     /// Trying to use VFIO without PCI
@@ -147,6 +211,9 @@ pub enum ValidationError {
     #[cfg(target_arch = "aarch64")]
     /// Dies per package must be 1
     CpuTopologyDiesPerPackage,
+    #[cfg(target_arch = "aarch64")]
+    /// The VM generation ID is only supported on x86_64
+    VmGenIdUnsupported,
     /// Virtio needs a min of 2 queues
     VnetQueueLowerThan2,
     /// The input queue number for virtio_net must match the number of input fds
@@ -165,12 +232,34 @@ pub enum ValidationError {
     /// Missing firmware for TDX
     #[cfg(feature = "tdx")]
     TdxFirmwareMissing,
+    /// A migration TD can only be bound to a TD
+    #[cfg(feature = "tdx")]
+    MigTdWithoutTdx,
+    /// Quotes can only be generated for a TD
+    #[cfg(feature = "tdx")]
+    QuoteGenerationWithoutTdx,
+    /// The QGS socket is only used to generate quotes
+    #[cfg(feature = "tdx")]
+    QgsSocketWithoutQuoteGeneration,
+    /// Prefaulting the memory defeats the lazy acceptance of confidential guests
+    #[cfg(any(feature = "tdx", feature = "sev_snp"))]
+    ConfidentialPrefault,
+    /// SEV-ES and SEV-SNP can't be enabled together
+    #[cfg(feature = "sev_snp")]
+    SevEsWithSevSnp,
+    /// SEV-ES guests boot from a firmware only
+    #[cfg(feature = "sev_snp")]
+    SevEsFirmwareMissing,
     /// Insufficient vCPUs for queues
     TooManyQueues,
+    /// virtio-9p devices have a single request queue
+    Fs9pMultipleQueues,
     /// Need shared memory for vfio-user
     UserDevicesRequireSharedMemory,
     /// VSOCK Context Identifier has a special meaning, unsuitable for a VM.
     VsockSpecialCid(u32),
+    /// The same VSOCK port or TCP address is forwarded more than once.
+    DuplicateVsockForward(String),
     /// Memory zone is reused across NUMA nodes
     MemoryZoneReused(String, u32, u32),
     /// Invalid number of PCI segments
@@ -206,14 +295,65 @@ pub enum ValidationError {
     InvalidIoPortHex(String),
     #[cfg(feature = "sev_snp")]
     InvalidHostData,
+    /// Host data given both inline and as a KBS resource
+    #[cfg(feature = "sev_snp")]
+    KbsHostDataConflict,
+    /// SEV-SNP parameters given without SEV-SNP being enabled
+    #[cfg(feature = "sev_snp")]
+    SevSnpNotEnabled,
+    /// Host data of the SEV-SNP section isn't 64 hex characters
+    #[cfg(feature = "sev_snp")]
+    InvalidSevSnpHostData,
+    /// Host data given from more than one source
+    #[cfg(feature = "sev_snp")]
+    SevSnpHostDataConflict,
+    /// Reserved bits of the SEV-SNP guest policy aren't set as expected
+    #[cfg(feature = "sev_snp")]
+    InvalidSevSnpPolicy(u64),
+    /// Author key given without an ID block key
+    #[cfg(feature = "sev_snp")]
+    SevSnpAuthorKeyWithoutIdBlockKey,
+    /// ID block key given without the policy it signs
+    #[cfg(feature = "sev_snp")]
+    SevSnpIdBlockKeyWithoutPolicy,
+    /// ID block signed at launch along with an SVSM
+    #[cfg(feature = "sev_snp")]
+    SevSnpSvsmWithIdBlockKey,
+    /// TPM emulated by the host along with the vTPM of the SVSM
+    #[cfg(feature = "sev_snp")]
+    SevSnpSvsmWithTpm,
+    /// IGVM compatibility mask doesn't name a single platform
+    #[cfg(feature = "igvm")]
+    InvalidIgvmCompatibilityMask(u32),
     /// Restore expects all net ids that have fds
     RestoreMissingRequiredNetId(String),
     /// Number of FDs passed during Restore are incorrect to the NetConfig
     RestoreNetFdCountMismatch(String, usize, usize),
+    /// Lazy restore can't prefault the memory
+    LazyRestorePrefault,
+    /// Lazy restore doesn't support shared memory
+    LazyRestoreSharedMemory,
+    /// Clone restore can't be combined with lazy restore
+    CloneRestoreLazy,
+    /// Clone restore can't prefault the memory
+    CloneRestorePrefault,
+    /// Console port mode is not supported
+    ConsolePortInvalidMode(ConsoleOutputMode),
     /// Path provided in landlock-rules doesn't exist
     LandlockPathDoesNotExist(PathBuf),
     /// Access provided in landlock-rules in invalid
     InvalidLandlockAccess(String),
+    /// Automatic NUMA requires every vCPU to be pinned
+    AutoNumaWithoutAffinity(u32),
+    /// Automatic NUMA can't be combined with explicit NUMA nodes or memory zones
+    AutoNumaConflict,
+    /// Automatic NUMA doesn't support memory hotplug
+    AutoNumaMemoryHotplug,
+    /// Device left out of the build
+    DeviceNotBuiltIn(&'static str),
+    /// Host name published through mDNS isn't a valid DNS label
+    #[cfg(feature = "mdns")]
+    InvalidMdnsHostname(String),
 }
 
 type ValidationResult<T> = std::result::Result<T, ValidationError>;
@@ -224,10 +364,26 @@ impl fmt::Display for ValidationError {
         match self {
             KernelMissing => write!(f, "No kernel specified"),
             ConsoleFileMissing => write!(f, "Path missing when using file console mode"),
+            ConsoleLogSocketUnsupported => write!(
+                f,
+                "Output can only be sent to a log socket from an enabled serial port"
+            ),
             ConsoleSocketPathMissing => write!(f, "Path missing when using socket console mode"),
+            ConsoleTcpAddressMissing => write!(f, "Address missing when using tcp console mode"),
             CpusMaxLowerThanBoot => write!(f, "Max CPUs lower than boot CPUs"),
+            CpusMaxTooHigh(max) => write!(
+                f,
+                "Max CPUs ({max}) higher than the supported maximum ({MAX_SUPPORTED_VCPUS})"
+            ),
             #[cfg(target_arch = "x86_64")]
             DebugconFileMissing => write!(f, "Path missing when using file mode for debug console"),
+            #[cfg(target_arch = "x86_64")]
+            DebugconSocketUnsupported => {
+                write!(
+                    f,
+                    "Socket and tcp modes are not supported for debug console"
+                )
+            }
             DiskSocketAndPath => write!(f, "Disk path and vhost socket both provided"),
             VhostUserRequiresSharedMemory => {
                 write!(
@@ -236,6 +392,40 @@ impl fmt::Display for ValidationError {
                 )
             }
             VhostUserMissingSocket => write!(f, "No socket provided when using vhost-user"),
+            VhostUserDiskEncryption => {
+                write!(f, "Encryption is not supported for vhost-user disks")
+            }
+            DiskKeyFileAndResource => {
+                write!(f, "Disk key can't come from both a file and a KBS resource")
+            }
+            KbsNotConfigured => {
+                write!(
+                    f,
+                    "Fetching KBS resources requires both the KBS URI and the KBC socket"
+                )
+            }
+            VhostUserIoErrorPolicy => write!(
+                f,
+                "IO timeout and error policy are not supported for vhost-user disks"
+            ),
+            InvalidIoTimeout => write!(f, "IO timeout must be greater than 0"),
+            VhostUserDiskQos => write!(
+                f,
+                "IO priority and io.max limits are not supported for vhost-user disks"
+            ),
+            HttpDiskNotReadonly => write!(f, "Disks served over HTTP must be read-only"),
+            HttpDiskUnsupportedOption => write!(
+                f,
+                "Only raw images without encryption nor direct I/O can be served over HTTP"
+            ),
+            InvalidDiskCache => write!(
+                f,
+                "A cache file is required by the disks served over HTTP, and only supported by them"
+            ),
+            TransientDiskUnsupportedOption => write!(
+                f,
+                "Transient disks must be writable, unencrypted raw or qcow2 local images"
+            ),
             IommuUnsupported => write!(f, "Using an IOMMU without PCI support is unsupported"),
             VfioUnsupported => write!(f, "Using VFIO without PCI support is unsupported"),
             CpuTopologyZeroPart => write!(f, "No part of the CPU topology can be zero"),
@@ -245,6 +435,8 @@ impl fmt::Display for ValidationError {
             ),
             #[cfg(target_arch = "aarch64")]
             CpuTopologyDiesPerPackage => write!(f, "Dies per package must be 1"),
+            #[cfg(target_arch = "aarch64")]
+            VmGenIdUnsupported => write!(f, "The VM generation ID is only supported on x86_64"),
             VnetQueueLowerThan2 => write!(f, "Number of queues to virtio_net less than 2"),
             VnetQueueFdMismatch => write!(
                 f,
@@ -267,11 +459,41 @@ impl fmt::Display for ValidationError {
             }
             #[cfg(feature = "tdx")]
             TdxFirmwareMissing => {
-                write!(f, "No TDX firmware specified")
+                write!(f, "No TDX firmware or IGVM file specified")
+            }
+            #[cfg(feature = "tdx")]
+            MigTdWithoutTdx => {
+                write!(f, "A migration TD requires TDX to be enabled")
+            }
+            #[cfg(feature = "tdx")]
+            QuoteGenerationWithoutTdx => {
+                write!(f, "Quote generation requires TDX to be enabled")
+            }
+            #[cfg(feature = "tdx")]
+            QgsSocketWithoutQuoteGeneration => {
+                write!(f, "A QGS socket requires quote generation to be enabled")
+            }
+            #[cfg(any(feature = "tdx", feature = "sev_snp"))]
+            ConfidentialPrefault => {
+                write!(
+                    f,
+                    "Memory of confidential guests is accepted lazily and can't be prefaulted"
+                )
+            }
+            #[cfg(feature = "sev_snp")]
+            SevEsWithSevSnp => {
+                write!(f, "SEV-ES and SEV-SNP are mutually exclusive")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevEsFirmwareMissing => {
+                write!(f, "SEV-ES requires a firmware and no kernel nor IGVM file")
             }
             TooManyQueues => {
                 write!(f, "Number of vCPUs is insufficient for number of queues")
             }
+            Fs9pMultipleQueues => {
+                write!(f, "virtio-9p devices only support a single queue")
+            }
             UserDevicesRequireSharedMemory => {
                 write!(
                     f,
@@ -281,6 +503,9 @@ impl fmt::Display for ValidationError {
             VsockSpecialCid(cid) => {
                 write!(f, "{cid} is a special VSOCK CID")
             }
+            DuplicateVsockForward(s) => {
+                write!(f, "VSOCK forwarding of {s} is configured more than once")
+            }
             MemoryZoneReused(s, u1, u2) => {
                 write!(
                     f,
@@ -356,6 +581,49 @@ impl fmt::Display for ValidationError {
             InvalidHostData => {
                 write!(f, "Invalid host data format")
             }
+            #[cfg(feature = "sev_snp")]
+            KbsHostDataConflict => {
+                write!(f, "Host data can't come from both the payload and the KBS")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevSnpNotEnabled => {
+                write!(f, "SEV-SNP parameters require SEV-SNP to be enabled")
+            }
+            #[cfg(feature = "sev_snp")]
+            InvalidSevSnpHostData => {
+                write!(f, "SEV-SNP host data must be 64 hex characters")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevSnpHostDataConflict => {
+                write!(f, "Host data can only be given once")
+            }
+            #[cfg(feature = "sev_snp")]
+            InvalidSevSnpPolicy(p) => {
+                write!(f, "Invalid SEV-SNP guest policy: 0x{p:x}")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevSnpAuthorKeyWithoutIdBlockKey => {
+                write!(f, "The author key can't be used without an ID block key")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevSnpIdBlockKeyWithoutPolicy => {
+                write!(f, "The ID block key requires the guest policy to be set")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevSnpSvsmWithIdBlockKey => {
+                write!(f, "The ID block can't be signed at launch with an SVSM")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevSnpSvsmWithTpm => {
+                write!(f, "The TPM is provided by the SVSM, --tpm can't be used")
+            }
+            #[cfg(feature = "igvm")]
+            InvalidIgvmCompatibilityMask(m) => {
+                write!(
+                    f,
+                    "IGVM compatibility mask 0x{m:x} must have a single bit set"
+                )
+            }
             RestoreMissingRequiredNetId(s) => {
                 write!(f, "Net id {s} is associated with FDs and is required")
             }
@@ -365,6 +633,30 @@ impl fmt::Display for ValidationError {
                     "Number of Net FDs passed for '{s}' during Restore: {u1}. Expected: {u2}"
                 )
             }
+            LazyRestorePrefault => {
+                write!(
+                    f,
+                    "Lazy restore is incompatible with prefaulting the memory"
+                )
+            }
+            LazyRestoreSharedMemory => {
+                write!(f, "Lazy restore is not supported with shared memory")
+            }
+            CloneRestoreLazy => {
+                write!(f, "Clone restore is incompatible with lazy restore")
+            }
+            CloneRestorePrefault => {
+                write!(
+                    f,
+                    "Clone restore is incompatible with prefaulting the memory"
+                )
+            }
+            ConsolePortInvalidMode(mode) => {
+                write!(
+                    f,
+                    "Console port mode {mode:?} is not supported, only pty, file, socket, tcp, pipe and null are"
+                )
+            }
             LandlockPathDoesNotExist(s) => {
                 write!(
                     f,
@@ -375,6 +667,121 @@ impl fmt::Display for ValidationError {
             InvalidLandlockAccess(s) => {
                 write!(f, "{s}")
             }
+            AutoNumaWithoutAffinity(vcpu) => {
+                write!(
+                    f,
+                    "Automatic NUMA requires an affinity for every vCPU, vCPU {vcpu} has none"
+                )
+            }
+            AutoNumaConflict => {
+                write!(
+                    f,
+                    "Automatic NUMA is incompatible with --numa and --memory-zone"
+                )
+            }
+            AutoNumaMemoryHotplug => {
+                write!(f, "Automatic NUMA is incompatible with memory hotplug")
+            }
+            DeviceNotBuiltIn(s) => write!(f, "The {s} device is not part of this build"),
+            #[cfg(feature = "mdns")]
+            InvalidMdnsHostname(s) => write!(
+                f,
+                "Invalid mDNS host name {s:?}, expecting up to 63 letters, digits or hyphens"
+            ),
+        }
+    }
+}
+
+impl ValidationError {
+    /// Path of the VM configuration field the error is about, when a single
+    /// field is at fault.
+    pub fn field(&self) -> Option<&'static str> {
+        use self::ValidationError::*;
+        match self {
+            KernelMissing => Some("payload.kernel"),
+            ConsoleFileMissing => Some("console.file"),
+            ConsoleSocketPathMissing => Some("console.socket"),
+            ConsoleTcpAddressMissing => Some("console.tcp"),
+            ConsoleLogSocketUnsupported => Some("serial.log_socket"),
+            CpusMaxLowerThanBoot | CpusMaxTooHigh(_) => Some("cpus.max_vcpus"),
+            #[cfg(target_arch = "x86_64")]
+            DebugconFileMissing => Some("debug_console.file"),
+            #[cfg(target_arch = "x86_64")]
+            DebugconSocketUnsupported => Some("debug_console.mode"),
+            DiskSocketAndPath
+            | VhostUserMissingSocket
+            | VhostUserDiskEncryption
+            | DiskKeyFileAndResource
+            | VhostUserIoErrorPolicy
+            | InvalidIoTimeout
+            | VhostUserDiskQos
+            | HttpDiskNotReadonly
+            | HttpDiskUnsupportedOption
+            | InvalidDiskCache
+            | TransientDiskUnsupportedOption => Some("disks"),
+            VhostUserRequiresSharedMemory | UserDevicesRequireSharedMemory => Some("memory.shared"),
+            CpuTopologyCount | CpuTopologyZeroPart => Some("cpus.topology"),
+            #[cfg(target_arch = "aarch64")]
+            CpuTopologyDiesPerPackage => Some("cpus.topology"),
+            #[cfg(target_arch = "aarch64")]
+            VmGenIdUnsupported => Some("platform.vmgenid"),
+            VnetQueueLowerThan2
+            | VnetQueueFdMismatch
+            | VnetReservedFd
+            | NoHardwareChecksumOffload
+            | InvalidMtu(_) => Some("net"),
+            HugePageSizeWithoutHugePages | InvalidHugePageSize(_) => Some("memory.hugepage_size"),
+            #[cfg(feature = "tdx")]
+            TdxNoCpuHotplug => Some("cpus.max_vcpus"),
+            #[cfg(feature = "tdx")]
+            TdxFirmwareMissing => Some("payload.firmware"),
+            #[cfg(feature = "tdx")]
+            MigTdWithoutTdx => Some("platform.migtd_pid"),
+            #[cfg(feature = "tdx")]
+            QuoteGenerationWithoutTdx => Some("platform.quote_generation"),
+            #[cfg(feature = "tdx")]
+            QgsSocketWithoutQuoteGeneration => Some("platform.qgs_socket"),
+            #[cfg(any(feature = "tdx", feature = "sev_snp"))]
+            ConfidentialPrefault => Some("memory.prefault"),
+            #[cfg(feature = "sev_snp")]
+            SevEsWithSevSnp => Some("platform.sev_es"),
+            #[cfg(feature = "sev_snp")]
+            SevEsFirmwareMissing => Some("payload.firmware"),
+            VsockSpecialCid(_) => Some("vsock.cid"),
+            DuplicateVsockForward(_) => Some("vsock.forwards"),
+            MemoryZoneReused(..) => Some("numa.memory_zones"),
+            InvalidNumPciSegments(_) => Some("platform.num_pci_segments"),
+            InvalidPciSegmentApertureWeight(_) => Some("pci_segments"),
+            BalloonLargerThanRam(..) => Some("balloon.size"),
+            InvalidRateLimiterGroup => Some("rate_limit_groups"),
+            #[cfg(feature = "sev_snp")]
+            InvalidHostData => Some("payload.host_data"),
+            KbsNotConfigured => Some("platform.kbs_uri"),
+            #[cfg(feature = "sev_snp")]
+            KbsHostDataConflict => Some("platform.kbs_host_data"),
+            #[cfg(feature = "sev_snp")]
+            SevSnpNotEnabled => Some("platform.sev_snp"),
+            #[cfg(feature = "sev_snp")]
+            InvalidSevSnpHostData | SevSnpHostDataConflict => Some("sev_snp.host_data"),
+            #[cfg(feature = "sev_snp")]
+            InvalidSevSnpPolicy(_) => Some("sev_snp.policy"),
+            #[cfg(feature = "sev_snp")]
+            SevSnpAuthorKeyWithoutIdBlockKey => Some("sev_snp.id_block_key"),
+            #[cfg(feature = "sev_snp")]
+            SevSnpIdBlockKeyWithoutPolicy => Some("sev_snp.policy"),
+            #[cfg(feature = "sev_snp")]
+            SevSnpSvsmWithIdBlockKey => Some("sev_snp.id_block_key"),
+            #[cfg(feature = "sev_snp")]
+            SevSnpSvsmWithTpm => Some("tpm"),
+            #[cfg(feature = "igvm")]
+            InvalidIgvmCompatibilityMask(_) => Some("payload.igvm_compatibility_mask"),
+            LandlockPathDoesNotExist(_) | InvalidLandlockAccess(_) => Some("landlock_rules"),
+            AutoNumaWithoutAffinity(_) => Some("cpus.affinity"),
+            AutoNumaConflict | AutoNumaMemoryHotplug => Some("memory.auto_numa"),
+            Fs9pMultipleQueues => Some("fs"),
+            #[cfg(feature = "mdns")]
+            InvalidMdnsHostname(_) => Some("mdns.hostname"),
+            _ => None,
         }
     }
 }
@@ -401,6 +808,8 @@ impl fmt::Display for Error {
                 "Error parsing --fs: max tag length is {}",
                 virtio_devices::vhost_user::VIRTIO_FS_TAG_LEN
             ),
+            ParseGpu(o) => write!(f, "Error parsing --gpu: {o}"),
+            ParseGpuSockMissing => write!(f, "Error parsing --gpu: socket missing"),
             ParsePersistentMemory(o) => write!(f, "Error parsing --pmem: {o}"),
             ParsePmemFileMissing => write!(f, "Error parsing --pmem: file missing"),
             ParseVsock(o) => write!(f, "Error parsing --vsock: {o}"),
@@ -419,6 +828,10 @@ impl fmt::Display for Error {
             ParseSgxEpc(o) => write!(f, "Error parsing --sgx-epc: {o}"),
             #[cfg(target_arch = "x86_64")]
             ParseSgxEpcIdMissing => write!(f, "Error parsing --sgx-epc: id missing"),
+            #[cfg(target_arch = "aarch64")]
+            ParseFdtOverlay(o) => write!(f, "Error parsing --fdt-overlay: {o}"),
+            #[cfg(target_arch = "aarch64")]
+            ParseFdtOverlayPathMissing => write!(f, "Error parsing --fdt-overlay: path missing"),
             ParseNuma(o) => write!(f, "Error parsing --numa: {o}"),
             ParseRestoreSourceUrlMissing => {
                 write!(f, "Error parsing --restore: source_url missing")
@@ -438,8 +851,25 @@ impl fmt::Display for Error {
             ParsePlatform(o) => write!(f, "Error parsing --platform: {o}"),
             ParseVdpa(o) => write!(f, "Error parsing --vdpa: {o}"),
             ParseVdpaPathMissing => write!(f, "Error parsing --vdpa: path missing"),
+            ParseConsolePort(o) => write!(f, "Error parsing console port: {o}"),
+            ParseConsolePortInvalidModeGiven => {
+                write!(f, "Error parsing console port: invalid console mode given")
+            }
             ParseTpm(o) => write!(f, "Error parsing --tpm: {o}"),
             ParseTpmPathMissing => write!(f, "Error parsing --tpm: path missing"),
+            ParseRestartPolicy(o) => write!(f, "Error parsing --restart-policy: {o}"),
+            #[cfg(feature = "mdns")]
+            ParseMdns(o) => write!(f, "Error parsing --mdns: {o}"),
+            #[cfg(feature = "mdns")]
+            ParseMdnsHostnameMissing => write!(f, "Error parsing --mdns: hostname missing"),
+            #[cfg(feature = "mdns")]
+            ParseMdnsIpMissing => write!(f, "Error parsing --mdns: ip missing"),
+            #[cfg(feature = "sev_snp")]
+            ParseSevSnp(o) => write!(f, "Error parsing --sev-snp: {o}"),
+            #[cfg(feature = "igvm")]
+            ParseIgvmCompatibilityMask(s) => {
+                write!(f, "Error parsing --igvm-compatibility-mask: {s}")
+            }
             ParseLandlockRules(o) => write!(f, "Error parsing --landlock-rules: {o}"),
             ParseLandlockMissingFields => write!(
                 f,
@@ -449,6 +879,15 @@ impl fmt::Display for Error {
     }
 }
 
+// Parses an integer given in hex when prefixed with 0x, in decimal otherwise.
+#[cfg(any(feature = "igvm", feature = "sev_snp"))]
+fn parse_hex_or_dec(s: &str) -> result::Result<u64, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
 pub fn add_to_config<T>(items: &mut Option<Vec<T>>, item: T) {
     if let Some(items) = items {
         items.push(item);
@@ -473,6 +912,7 @@ pub struct VmParams<'a> {
     pub rng: &'a str,
     pub balloon: Option<&'a str>,
     pub fs: Option<Vec<&'a str>>,
+    pub gpu: Option<&'a str>,
     pub pmem: Option<Vec<&'a str>>,
     pub serial: &'a str,
     pub console: &'a str,
@@ -487,6 +927,8 @@ pub struct VmParams<'a> {
     pub pvpanic: bool,
     #[cfg(target_arch = "x86_64")]
     pub sgx_epc: Option<Vec<&'a str>>,
+    #[cfg(target_arch = "aarch64")]
+    pub fdt_overlays: Option<Vec<&'a str>>,
     pub numa: Option<Vec<&'a str>>,
     pub watchdog: bool,
     #[cfg(feature = "guest_debug")]
@@ -494,10 +936,17 @@ pub struct VmParams<'a> {
     pub pci_segments: Option<Vec<&'a str>>,
     pub platform: Option<&'a str>,
     pub tpm: Option<&'a str>,
+    pub restart_policy: Option<&'a str>,
+    #[cfg(feature = "mdns")]
+    pub mdns: Option<&'a str>,
     #[cfg(feature = "igvm")]
     pub igvm: Option<&'a str>,
+    #[cfg(feature = "igvm")]
+    pub igvm_compatibility_mask: Option<&'a str>,
     #[cfg(feature = "sev_snp")]
     pub host_data: Option<&'a str>,
+    #[cfg(feature = "sev_snp")]
+    pub sev_snp: Option<&'a str>,
     pub landlock_enable: bool,
     pub landlock_rules: Option<Vec<&'a str>>,
 }
@@ -532,6 +981,7 @@ impl<'a> VmParams<'a> {
         let fs: Option<Vec<&str>> = args
             .get_many::<String>("fs")
             .map(|x| x.map(|y| y as &str).collect());
+        let gpu: Option<&str> = args.get_one::<String>("gpu").map(|x| x as &str);
         let pmem: Option<Vec<&str>> = args
             .get_many::<String>("pmem")
             .map(|x| x.map(|y| y as &str).collect());
@@ -552,6 +1002,10 @@ impl<'a> VmParams<'a> {
         let sgx_epc: Option<Vec<&str>> = args
             .get_many::<String>("sgx-epc")
             .map(|x| x.map(|y| y as &str).collect());
+        #[cfg(target_arch = "aarch64")]
+        let fdt_overlays: Option<Vec<&str>> = args
+            .get_many::<String>("fdt-overlay")
+            .map(|x| x.map(|y| y as &str).collect());
         let numa: Option<Vec<&str>> = args
             .get_many::<String>("numa")
             .map(|x| x.map(|y| y as &str).collect());
@@ -563,10 +1017,20 @@ impl<'a> VmParams<'a> {
         #[cfg(feature = "guest_debug")]
         let gdb = args.contains_id("gdb");
         let tpm: Option<&str> = args.get_one::<String>("tpm").map(|x| x as &str);
+        let restart_policy: Option<&str> =
+            args.get_one::<String>("restart-policy").map(|x| x as &str);
+        #[cfg(feature = "mdns")]
+        let mdns: Option<&str> = args.get_one::<String>("mdns").map(|x| x as &str);
         #[cfg(feature = "igvm")]
         let igvm = args.get_one::<String>("igvm").map(|x| x as &str);
+        #[cfg(feature = "igvm")]
+        let igvm_compatibility_mask = args
+            .get_one::<String>("igvm-compatibility-mask")
+            .map(|x| x as &str);
         #[cfg(feature = "sev_snp")]
         let host_data = args.get_one::<String>("host-data").map(|x| x as &str);
+        #[cfg(feature = "sev_snp")]
+        let sev_snp = args.get_one::<String>("sev-snp").map(|x| x as &str);
         let landlock_enable = args.get_flag("landlock");
         let landlock_rules: Option<Vec<&str>> = args
             .get_many::<String>("landlock-rules")
@@ -586,6 +1050,7 @@ impl<'a> VmParams<'a> {
             rng,
             balloon,
             fs,
+            gpu,
             pmem,
             serial,
             console,
@@ -600,6 +1065,8 @@ impl<'a> VmParams<'a> {
             pvpanic,
             #[cfg(target_arch = "x86_64")]
             sgx_epc,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays,
             numa,
             watchdog,
             #[cfg(feature = "guest_debug")]
@@ -607,10 +1074,17 @@ impl<'a> VmParams<'a> {
             pci_segments,
             platform,
             tpm,
+            restart_policy,
+            #[cfg(feature = "mdns")]
+            mdns,
             #[cfg(feature = "igvm")]
             igvm,
+            #[cfg(feature = "igvm")]
+            igvm_compatibility_mask,
             #[cfg(feature = "sev_snp")]
             host_data,
+            #[cfg(feature = "sev_snp")]
+            sev_snp,
             landlock_enable,
             landlock_rules,
         }
@@ -634,6 +1108,89 @@ impl FromStr for HotplugMethod {
     }
 }
 
+pub enum ParseHostNumaPolicyError {
+    InvalidValue(String),
+}
+
+impl FromStr for HostNumaPolicy {
+    type Err = ParseHostNumaPolicyError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bind" => Ok(HostNumaPolicy::Bind),
+            "preferred" => Ok(HostNumaPolicy::Preferred),
+            _ => Err(ParseHostNumaPolicyError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+pub enum ParseOnResetLoopError {
+    InvalidValue(String),
+}
+
+impl FromStr for OnResetLoop {
+    type Err = ParseOnResetLoopError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "report" => Ok(OnResetLoop::Report),
+            "pause" => Ok(OnResetLoop::Pause),
+            _ => Err(ParseOnResetLoopError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[cfg(feature = "sev_snp")]
+pub enum ParseSnpCpuidModeError {
+    InvalidValue(String),
+}
+
+#[cfg(feature = "sev_snp")]
+impl FromStr for SnpCpuidMode {
+    type Err = ParseSnpCpuidModeError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "template" => Ok(SnpCpuidMode::Template),
+            "passthrough" => Ok(SnpCpuidMode::Passthrough),
+            _ => Err(ParseSnpCpuidModeError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+pub enum ParseRestartPolicyError {
+    InvalidValue(String),
+}
+
+impl FromStr for RestartPolicy {
+    type Err = ParseRestartPolicyError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "always" => Ok(RestartPolicy::Always),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "never" => Ok(RestartPolicy::Never),
+            _ => Err(ParseRestartPolicyError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+pub enum ParseFsProtocolError {
+    InvalidValue(String),
+}
+
+impl FromStr for FsProtocol {
+    type Err = ParseFsProtocolError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "virtiofs" => Ok(FsProtocol::VirtioFs),
+            "9p" => Ok(FsProtocol::P9),
+            _ => Err(ParseFsProtocolError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
 pub enum CpuTopologyParseError {
     InvalidValue(String),
 }
@@ -677,14 +1234,17 @@ impl CpusConfig {
             .add("kvm_hyperv")
             .add("max_phys_bits")
             .add("affinity")
-            .add("features");
+            .add("features")
+            .add("halt_poll_ns");
+        #[cfg(target_arch = "x86_64")]
+        parser.add("idle_poll");
         parser.parse(cpus).map_err(Error::ParseCpus)?;
 
-        let boot_vcpus: u8 = parser
+        let boot_vcpus: u32 = parser
             .convert("boot")
             .map_err(Error::ParseCpus)?
             .unwrap_or(DEFAULT_VCPUS);
-        let max_vcpus: u8 = parser
+        let max_vcpus: u32 = parser
             .convert("max")
             .map_err(Error::ParseCpus)?
             .unwrap_or(boot_vcpus);
@@ -699,7 +1259,7 @@ impl CpusConfig {
             .map_err(Error::ParseCpus)?
             .unwrap_or(DEFAULT_MAX_PHYS_BITS);
         let affinity = parser
-            .convert::<Tuple<u8, Vec<usize>>>("affinity")
+            .convert::<Tuple<u32, Vec<usize>>>("affinity")
             .map_err(Error::ParseCpus)?
             .map(|v| {
                 v.0.iter()
@@ -727,10 +1287,23 @@ impl CpusConfig {
                     features.amx = true;
                     Ok(())
                 }
+                #[cfg(target_arch = "x86_64")]
+                "cet" => {
+                    features.cet = true;
+                    Ok(())
+                }
                 _ => Err(Error::InvalidCpuFeatures(s)),
             }?;
         }
 
+        let halt_poll_ns = parser.convert("halt_poll_ns").map_err(Error::ParseCpus)?;
+        #[cfg(target_arch = "x86_64")]
+        let idle_poll = parser
+            .convert::<Toggle>("idle_poll")
+            .map_err(Error::ParseCpus)?
+            .unwrap_or(Toggle(false))
+            .0;
+
         Ok(CpusConfig {
             boot_vcpus,
             max_vcpus,
@@ -739,6 +1312,9 @@ impl CpusConfig {
             max_phys_bits,
             affinity,
             features,
+            halt_poll_ns,
+            #[cfg(target_arch = "x86_64")]
+            idle_poll,
         })
     }
 }
@@ -809,11 +1385,24 @@ impl PlatformConfig {
             .add("iommu_segments")
             .add("serial_number")
             .add("uuid")
-            .add("oem_strings");
+            .add("oem_strings")
+            .add("kbs_uri")
+            .add("kbc")
+            .add("on_reset_loop")
+            .add("vfio_quirks")
+            .add("vmgenid");
         #[cfg(feature = "tdx")]
-        parser.add("tdx");
+        parser
+            .add("tdx")
+            .add("migtd_pid")
+            .add("quote_generation")
+            .add("qgs_socket");
         #[cfg(feature = "sev_snp")]
-        parser.add("sev_snp");
+        parser
+            .add("sev_snp")
+            .add("sev_es")
+            .add("kbs_host_data")
+            .add("snp_cpuid");
         parser.parse(platform).map_err(Error::ParsePlatform)?;
 
         let num_pci_segments: u16 = parser
@@ -832,28 +1421,80 @@ impl PlatformConfig {
             .convert::<StringList>("oem_strings")
             .map_err(Error::ParsePlatform)?
             .map(|v| v.0);
+        let kbs_uri = parser.get("kbs_uri");
+        let kbc = parser.get("kbc").map(PathBuf::from);
+        let on_reset_loop = parser
+            .convert("on_reset_loop")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or_default();
+        let vfio_quirks = parser.get("vfio_quirks").map(PathBuf::from);
+        let vmgenid = parser
+            .convert::<Toggle>("vmgenid")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(false))
+            .0;
         #[cfg(feature = "tdx")]
         let tdx = parser
             .convert::<Toggle>("tdx")
             .map_err(Error::ParsePlatform)?
             .unwrap_or(Toggle(false))
             .0;
+        #[cfg(feature = "tdx")]
+        let migtd_pid = parser.convert("migtd_pid").map_err(Error::ParsePlatform)?;
+        #[cfg(feature = "tdx")]
+        let quote_generation = parser
+            .convert::<Toggle>("quote_generation")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(false))
+            .0;
+        #[cfg(feature = "tdx")]
+        let qgs_socket = parser.get("qgs_socket").map(PathBuf::from);
         #[cfg(feature = "sev_snp")]
         let sev_snp = parser
             .convert::<Toggle>("sev_snp")
             .map_err(Error::ParsePlatform)?
             .unwrap_or(Toggle(false))
             .0;
+        #[cfg(feature = "sev_snp")]
+        let sev_es = parser
+            .convert::<Toggle>("sev_es")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(false))
+            .0;
+        #[cfg(feature = "sev_snp")]
+        let kbs_host_data = parser.get("kbs_host_data");
+        #[cfg(feature = "sev_snp")]
+        let snp_cpuid = parser
+            .convert("snp_cpuid")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or_default();
         Ok(PlatformConfig {
             num_pci_segments,
             iommu_segments,
             serial_number,
             uuid,
             oem_strings,
+            kbs_uri,
+            kbc,
+            on_reset_loop,
+            vfio_quirks,
+            vmgenid,
             #[cfg(feature = "tdx")]
             tdx,
+            #[cfg(feature = "tdx")]
+            migtd_pid,
+            #[cfg(feature = "tdx")]
+            quote_generation,
+            #[cfg(feature = "tdx")]
+            qgs_socket,
             #[cfg(feature = "sev_snp")]
             sev_snp,
+            #[cfg(feature = "sev_snp")]
+            sev_es,
+            #[cfg(feature = "sev_snp")]
+            kbs_host_data,
+            #[cfg(feature = "sev_snp")]
+            snp_cpuid,
         })
     }
 
@@ -872,16 +1513,50 @@ impl PlatformConfig {
             }
         }
 
-        Ok(())
-    }
-}
+        #[cfg(feature = "tdx")]
+        if self.migtd_pid.is_some() && !self.tdx {
+            return Err(ValidationError::MigTdWithoutTdx);
+        }
 
-impl MemoryConfig {
-    pub fn parse(memory: &str, memory_zones: Option<Vec<&str>>) -> Result<Self> {
-        let mut parser = OptionParser::new();
-        parser
-            .add("size")
-            .add("file")
+        #[cfg(feature = "tdx")]
+        if self.quote_generation && !self.tdx {
+            return Err(ValidationError::QuoteGenerationWithoutTdx);
+        }
+
+        #[cfg(feature = "tdx")]
+        if self.qgs_socket.is_some() && !self.quote_generation {
+            return Err(ValidationError::QgsSocketWithoutQuoteGeneration);
+        }
+
+        #[cfg(feature = "sev_snp")]
+        if self.sev_snp && self.sev_es {
+            return Err(ValidationError::SevEsWithSevSnp);
+        }
+
+        if self.kbs_uri.is_some() != self.kbc.is_some() {
+            return Err(ValidationError::KbsNotConfigured);
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        if self.vmgenid {
+            return Err(ValidationError::VmGenIdUnsupported);
+        }
+
+        #[cfg(feature = "sev_snp")]
+        if self.kbs_host_data.is_some() && self.kbs_uri.is_none() {
+            return Err(ValidationError::KbsNotConfigured);
+        }
+
+        Ok(())
+    }
+}
+
+impl MemoryConfig {
+    pub fn parse(memory: &str, memory_zones: Option<Vec<&str>>) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("size")
+            .add("file")
             .add("mergeable")
             .add("hotplug_method")
             .add("hotplug_size")
@@ -890,7 +1565,9 @@ impl MemoryConfig {
             .add("hugepages")
             .add("hugepage_size")
             .add("prefault")
-            .add("thp");
+            .add("thp")
+            .add("auto_numa")
+            .add("coredump");
         parser.parse(memory).map_err(Error::ParseMemory)?;
 
         let size = parser
@@ -939,6 +1616,16 @@ impl MemoryConfig {
             .map_err(Error::ParseMemory)?
             .unwrap_or(Toggle(true))
             .0;
+        let auto_numa = parser
+            .convert::<Toggle>("auto_numa")
+            .map_err(Error::ParseMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let coredump = parser
+            .convert::<Toggle>("coredump")
+            .map_err(Error::ParseMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
 
         let zones: Option<Vec<MemoryZoneConfig>> = if let Some(memory_zones) = &memory_zones {
             let mut zones = Vec::new();
@@ -952,6 +1639,7 @@ impl MemoryConfig {
                     .add("hugepages")
                     .add("hugepage_size")
                     .add("host_numa_node")
+                    .add("host_numa_policy")
                     .add("hotplug_size")
                     .add("hotplugged_size")
                     .add("prefault");
@@ -982,6 +1670,10 @@ impl MemoryConfig {
                 let host_numa_node = parser
                     .convert::<u32>("host_numa_node")
                     .map_err(Error::ParseMemoryZone)?;
+                let host_numa_policy = parser
+                    .convert("host_numa_policy")
+                    .map_err(Error::ParseMemoryZone)?
+                    .unwrap_or_default();
                 let hotplug_size = parser
                     .convert::<ByteSized>("hotplug_size")
                     .map_err(Error::ParseMemoryZone)?
@@ -1004,6 +1696,7 @@ impl MemoryConfig {
                     hugepages,
                     hugepage_size,
                     host_numa_node,
+                    host_numa_policy,
                     hotplug_size,
                     hotplugged_size,
                     prefault,
@@ -1026,6 +1719,8 @@ impl MemoryConfig {
             prefault,
             zones,
             thp,
+            auto_numa,
+            coredump,
         })
     }
 
@@ -1137,19 +1832,31 @@ impl RateLimiterGroupConfig {
 
 impl DiskConfig {
     pub const SYNTAX: &'static str = "Disk parameters \
-         \"path=<disk_image_path>,readonly=on|off,direct=on|off,iommu=on|off,\
+         \"path=<disk_image_path>,readonly=on|off,share=on|off,\
+         image_type=raw|qcow2|vhd|vhdx,key_file=<encryption_key_path>,\
+         key_resource=<kbs_resource_path>,\
+         direct=on|off,iommu=on|off,\
          num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,\
          vhost_user=on|off,socket=<vhost_user_socket_path>,\
          bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,\
          ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,\
          id=<device_id>,pci_segment=<segment_id>,rate_limit_group=<group_id>,\
-         queue_affinity=<list_of_queue_indices_with_their_associated_cpuset>";
+         queue_affinity=<list_of_queue_indices_with_their_associated_cpuset>,\
+         io_timeout=<timeout_ms>,on_io_error=report|pause,\
+         io_priority=rt|be|idle[:<level>],io_max_rbps=<bytes_per_second>,\
+         io_max_wbps=<bytes_per_second>,io_max_riops=<io_ops_per_second>,\
+         io_max_wiops=<io_ops_per_second>,cache=<http_disk_cache_path>,\
+         transient=on|off";
 
     pub fn parse(disk: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
         parser
             .add("path")
             .add("readonly")
+            .add("share")
+            .add("image_type")
+            .add("key_file")
+            .add("key_resource")
             .add("direct")
             .add("iommu")
             .add("queue_size")
@@ -1168,7 +1875,16 @@ impl DiskConfig {
             .add("pci_segment")
             .add("serial")
             .add("rate_limit_group")
-            .add("queue_affinity");
+            .add("queue_affinity")
+            .add("io_timeout")
+            .add("on_io_error")
+            .add("io_priority")
+            .add("io_max_rbps")
+            .add("io_max_wbps")
+            .add("io_max_riops")
+            .add("io_max_wiops")
+            .add("cache")
+            .add("transient");
         parser.parse(disk).map_err(Error::ParseDisk)?;
 
         let path = parser.get("path").map(PathBuf::from);
@@ -1177,6 +1893,16 @@ impl DiskConfig {
             .map_err(Error::ParseDisk)?
             .unwrap_or(Toggle(false))
             .0;
+        let share = parser
+            .convert::<Toggle>("share")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let image_type = parser
+            .convert::<block::ImageType>("image_type")
+            .map_err(Error::ParseDisk)?;
+        let key_file = parser.get("key_file").map(PathBuf::from);
+        let key_resource = parser.get("key_resource");
         let direct = parser
             .convert::<Toggle>("direct")
             .map_err(Error::ParseDisk)?
@@ -1253,6 +1979,27 @@ impl DiskConfig {
                     })
                     .collect()
             });
+        let io_timeout = parser.convert("io_timeout").map_err(Error::ParseDisk)?;
+        let on_io_error = parser
+            .convert::<OnIoError>("on_io_error")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or_default();
+        let io_priority = parser
+            .convert::<IoPriority>("io_priority")
+            .map_err(Error::ParseDisk)?;
+        let io_max = IoMaxConfig {
+            rbps: parser.convert("io_max_rbps").map_err(Error::ParseDisk)?,
+            wbps: parser.convert("io_max_wbps").map_err(Error::ParseDisk)?,
+            riops: parser.convert("io_max_riops").map_err(Error::ParseDisk)?,
+            wiops: parser.convert("io_max_wiops").map_err(Error::ParseDisk)?,
+        };
+        let io_max = (io_max != IoMaxConfig::default()).then_some(io_max);
+        let cache = parser.get("cache").map(PathBuf::from);
+        let transient = parser
+            .convert::<Toggle>("transient")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or(Toggle(false))
+            .0;
         let bw_tb_config = if bw_size != 0 && bw_refill_time != 0 {
             Some(TokenBucketConfig {
                 size: bw_size,
@@ -1283,6 +2030,10 @@ impl DiskConfig {
         Ok(DiskConfig {
             path,
             readonly,
+            share,
+            image_type,
+            key_file,
+            key_resource,
             direct,
             iommu,
             num_queues,
@@ -1297,6 +2048,12 @@ impl DiskConfig {
             pci_segment,
             serial,
             queue_affinity,
+            io_timeout,
+            on_io_error,
+            io_priority,
+            io_max,
+            cache,
+            transient,
         })
     }
 
@@ -1309,6 +2066,65 @@ impl DiskConfig {
             return Err(ValidationError::IommuNotSupported);
         }
 
+        if self.vhost_user && (self.key_file.is_some() || self.key_resource.is_some()) {
+            return Err(ValidationError::VhostUserDiskEncryption);
+        }
+
+        if self.key_file.is_some() && self.key_resource.is_some() {
+            return Err(ValidationError::DiskKeyFileAndResource);
+        }
+
+        if self.key_resource.is_some()
+            && !vm_config
+                .platform
+                .as_ref()
+                .is_some_and(|p| p.kbs_uri.is_some())
+        {
+            return Err(ValidationError::KbsNotConfigured);
+        }
+
+        if self.vhost_user && (self.io_timeout.is_some() || self.on_io_error != OnIoError::Report) {
+            return Err(ValidationError::VhostUserIoErrorPolicy);
+        }
+
+        if self.io_timeout == Some(0) {
+            return Err(ValidationError::InvalidIoTimeout);
+        }
+
+        if self.vhost_user && (self.io_priority.is_some() || self.io_max.is_some()) {
+            return Err(ValidationError::VhostUserDiskQos);
+        }
+
+        if self.is_http() {
+            if !self.readonly {
+                return Err(ValidationError::HttpDiskNotReadonly);
+            }
+            if self.key_file.is_some()
+                || self.key_resource.is_some()
+                || self.direct
+                || self.image_type.is_some_and(|t| t != block::ImageType::Raw)
+            {
+                return Err(ValidationError::HttpDiskUnsupportedOption);
+            }
+        }
+
+        if self.is_http() != self.cache.is_some() {
+            return Err(ValidationError::InvalidDiskCache);
+        }
+
+        if self.transient
+            && (self.readonly
+                || self.vhost_user
+                || self.is_http()
+                || self.key_file.is_some()
+                || self.key_resource.is_some()
+                || self
+                    .image_type
+                    .is_some_and(|t| t != block::ImageType::Raw && t != block::ImageType::Qcow2))
+        {
+            return Err(ValidationError::TransientDiskUnsupportedOption);
+        }
+
         if let Some(platform_config) = vm_config.platform.as_ref() {
             if self.pci_segment >= platform_config.num_pci_segments {
                 return Err(ValidationError::InvalidPciSegment(self.pci_segment));
@@ -1353,7 +2169,7 @@ impl NetConfig {
     vhost_user=<vhost_user_enable>,socket=<vhost_user_socket_path>,vhost_mode=client|server,\
     bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,\
     ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,pci_segment=<segment_id>\
-    offload_tso=on|off,offload_ufo=on|off,offload_csum=on|off\"";
+    offload_tso=on|off,offload_ufo=on|off,offload_csum=on|off,standby=on|off\"";
 
     pub fn parse(net: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -1367,6 +2183,7 @@ impl NetConfig {
             .add("offload_tso")
             .add("offload_ufo")
             .add("offload_csum")
+            .add("standby")
             .add("mtu")
             .add("iommu")
             .add("queue_size")
@@ -1414,6 +2231,11 @@ impl NetConfig {
             .map_err(Error::ParseNetwork)?
             .unwrap_or(Toggle(true))
             .0;
+        let standby = parser
+            .convert::<Toggle>("standby")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or(Toggle(false))
+            .0;
         let mtu = parser.convert("mtu").map_err(Error::ParseNetwork)?;
         let iommu = parser
             .convert::<Toggle>("iommu")
@@ -1518,6 +2340,7 @@ impl NetConfig {
             offload_tso,
             offload_ufo,
             offload_csum,
+            standby,
         };
         Ok(config)
     }
@@ -1634,8 +2457,9 @@ impl BalloonConfig {
 
 impl FsConfig {
     pub const SYNTAX: &'static str = "virtio-fs parameters \
-    \"tag=<tag_name>,socket=<socket_path>,num_queues=<number_of_queues>,\
-    queue_size=<size_of_each_queue>,id=<device_id>,pci_segment=<segment_id>\"";
+    \"tag=<tag_name>,socket=<socket_path>,protocol=virtiofs|9p,\
+    num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,id=<device_id>,\
+    pci_segment=<segment_id>\"";
 
     pub fn parse(fs: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -1644,6 +2468,7 @@ impl FsConfig {
             .add("queue_size")
             .add("num_queues")
             .add("socket")
+            .add("protocol")
             .add("id")
             .add("pci_segment");
         parser.parse(fs).map_err(Error::ParseFileSystem)?;
@@ -1653,6 +2478,10 @@ impl FsConfig {
             return Err(Error::ParseFsTagTooLong);
         }
         let socket = PathBuf::from(parser.get("socket").ok_or(Error::ParseFsSockMissing)?);
+        let protocol = parser
+            .convert("protocol")
+            .map_err(Error::ParseFileSystem)?
+            .unwrap_or_default();
 
         let queue_size = parser
             .convert("queue_size")
@@ -1673,6 +2502,7 @@ impl FsConfig {
         Ok(FsConfig {
             tag,
             socket,
+            protocol,
             num_queues,
             queue_size,
             id,
@@ -1685,6 +2515,62 @@ impl FsConfig {
             return Err(ValidationError::TooManyQueues);
         }
 
+        if self.protocol == FsProtocol::P9 && self.num_queues != 1 {
+            return Err(ValidationError::Fs9pMultipleQueues);
+        }
+
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
+
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) {
+                    return Err(ValidationError::IommuNotSupportedOnSegment(
+                        self.pci_segment,
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl GpuConfig {
+    pub const SYNTAX: &'static str = "vhost-user-gpu parameters \
+    \"socket=<socket_path>,queue_size=<size_of_each_queue>,id=<device_id>,\
+    pci_segment=<segment_id>\"";
+
+    pub fn parse(gpu: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("socket")
+            .add("queue_size")
+            .add("id")
+            .add("pci_segment");
+        parser.parse(gpu).map_err(Error::ParseGpu)?;
+
+        let socket = PathBuf::from(parser.get("socket").ok_or(Error::ParseGpuSockMissing)?);
+        let queue_size = parser
+            .convert("queue_size")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or_else(default_gpuconfig_queue_size);
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or_default();
+
+        Ok(GpuConfig {
+            socket,
+            queue_size,
+            id,
+            pci_segment,
+        })
+    }
+
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
         if let Some(platform_config) = vm_config.platform.as_ref() {
             if self.pci_segment >= platform_config.num_pci_segments {
                 return Err(ValidationError::InvalidPciSegment(self.pci_segment));
@@ -1777,11 +2663,15 @@ impl ConsoleConfig {
             .add_valueless("null")
             .add("file")
             .add("iommu")
-            .add("socket");
+            .add("socket")
+            .add("tcp")
+            .add("pipe")
+            .add("log_socket");
         parser.parse(console).map_err(Error::ParseConsole)?;
 
         let mut file: Option<PathBuf> = default_consoleconfig_file();
         let mut socket: Option<PathBuf> = None;
+        let mut tcp: Option<SocketAddr> = None;
         let mut mode: ConsoleOutputMode = ConsoleOutputMode::Off;
 
         if parser.is_set("off") {
@@ -1802,6 +2692,20 @@ impl ConsoleConfig {
             socket = Some(PathBuf::from(parser.get("socket").ok_or(
                 Error::Validation(ValidationError::ConsoleSocketPathMissing),
             )?));
+        } else if parser.is_set("tcp") {
+            mode = ConsoleOutputMode::Tcp;
+            tcp = Some(
+                parser
+                    .convert("tcp")
+                    .map_err(Error::ParseConsole)?
+                    .ok_or(Error::Validation(ValidationError::ConsoleTcpAddressMissing))?,
+            );
+        } else if parser.is_set("pipe") {
+            mode = ConsoleOutputMode::Pipe;
+            file =
+                Some(PathBuf::from(parser.get("pipe").ok_or(
+                    Error::Validation(ValidationError::ConsoleFileMissing),
+                )?));
         } else {
             return Err(Error::ParseConsoleInvalidModeGiven);
         }
@@ -1810,12 +2714,15 @@ impl ConsoleConfig {
             .map_err(Error::ParseConsole)?
             .unwrap_or(Toggle(false))
             .0;
+        let log_socket = parser.get("log_socket").map(PathBuf::from);
 
         Ok(Self {
             file,
             mode,
             iommu,
             socket,
+            tcp,
+            log_socket,
         })
     }
 }
@@ -1830,7 +2737,9 @@ impl DebugConsoleConfig {
             .add_valueless("tty")
             .add_valueless("null")
             .add("file")
-            .add("iobase");
+            .add("pipe")
+            .add("iobase")
+            .add("timestamps");
         parser
             .parse(debug_console_ops)
             .map_err(Error::ParseConsole)?;
@@ -1852,6 +2761,12 @@ impl DebugConsoleConfig {
                 Some(PathBuf::from(parser.get("file").ok_or(
                     Error::Validation(ValidationError::ConsoleFileMissing),
                 )?));
+        } else if parser.is_set("pipe") {
+            mode = ConsoleOutputMode::Pipe;
+            file =
+                Some(PathBuf::from(parser.get("pipe").ok_or(
+                    Error::Validation(ValidationError::ConsoleFileMissing),
+                )?));
         } else {
             return Err(Error::ParseConsoleInvalidModeGiven);
         }
@@ -1869,13 +2784,24 @@ impl DebugConsoleConfig {
             }
         }
 
-        Ok(Self { file, mode, iobase })
+        let timestamps = parser
+            .convert::<Toggle>("timestamps")
+            .map_err(Error::ParseConsole)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(Self {
+            file,
+            mode,
+            iobase,
+            timestamps,
+        })
     }
 }
 
 impl DeviceConfig {
     pub const SYNTAX: &'static str =
-        "Direct device assignment parameters \"path=<device_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>\"";
+        "Direct device assignment parameters \"path=<device_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>,reset=device|flr|bus|acpi|none\"";
 
     pub fn parse(device: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -1884,7 +2810,8 @@ impl DeviceConfig {
             .add("id")
             .add("iommu")
             .add("pci_segment")
-            .add("x_nv_gpudirect_clique");
+            .add("x_nv_gpudirect_clique")
+            .add("reset");
         parser.parse(device).map_err(Error::ParseDevice)?;
 
         let path = parser
@@ -1904,12 +2831,16 @@ impl DeviceConfig {
         let x_nv_gpudirect_clique = parser
             .convert::<u8>("x_nv_gpudirect_clique")
             .map_err(Error::ParseDevice)?;
+        let reset = parser
+            .convert::<VfioResetMethod>("reset")
+            .map_err(Error::ParseDevice)?;
         Ok(DeviceConfig {
             path,
             iommu,
             id,
             pci_segment,
             x_nv_gpudirect_clique,
+            reset,
         })
     }
 
@@ -2035,9 +2966,125 @@ impl VdpaConfig {
     }
 }
 
+impl ConsolePortConfig {
+    pub const SYNTAX: &'static str = "Console port parameters \
+        \"pty|null|file=<path>|socket=<path>|tcp=<host:port>|pipe=<path>,iommu=on|off,\
+        id=<device_id>,pci_segment=<segment_id>\"";
+
+    pub fn parse(console_port: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add_valueless("pty")
+            .add_valueless("null")
+            .add("file")
+            .add("socket")
+            .add("tcp")
+            .add("pipe")
+            .add("iommu")
+            .add("id")
+            .add("pci_segment");
+        parser
+            .parse(console_port)
+            .map_err(Error::ParseConsolePort)?;
+
+        let mut file: Option<PathBuf> = None;
+        let mut socket: Option<PathBuf> = None;
+        let mut tcp: Option<SocketAddr> = None;
+        let mode = if parser.is_set("pty") {
+            ConsoleOutputMode::Pty
+        } else if parser.is_set("null") {
+            ConsoleOutputMode::Null
+        } else if parser.is_set("file") {
+            file =
+                Some(PathBuf::from(parser.get("file").ok_or(
+                    Error::Validation(ValidationError::ConsoleFileMissing),
+                )?));
+            ConsoleOutputMode::File
+        } else if parser.is_set("socket") {
+            socket = Some(PathBuf::from(parser.get("socket").ok_or(
+                Error::Validation(ValidationError::ConsoleSocketPathMissing),
+            )?));
+            ConsoleOutputMode::Socket
+        } else if parser.is_set("tcp") {
+            tcp = Some(
+                parser
+                    .convert("tcp")
+                    .map_err(Error::ParseConsolePort)?
+                    .ok_or(Error::Validation(ValidationError::ConsoleTcpAddressMissing))?,
+            );
+            ConsoleOutputMode::Tcp
+        } else if parser.is_set("pipe") {
+            file =
+                Some(PathBuf::from(parser.get("pipe").ok_or(
+                    Error::Validation(ValidationError::ConsoleFileMissing),
+                )?));
+            ConsoleOutputMode::Pipe
+        } else {
+            return Err(Error::ParseConsolePortInvalidModeGiven);
+        };
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseConsolePort)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParseConsolePort)?
+            .unwrap_or_default();
+
+        Ok(ConsolePortConfig {
+            mode,
+            file,
+            socket,
+            tcp,
+            iommu,
+            id,
+            pci_segment,
+        })
+    }
+
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        match self.mode {
+            ConsoleOutputMode::Pty | ConsoleOutputMode::Null => {}
+            ConsoleOutputMode::File | ConsoleOutputMode::Pipe => {
+                if self.file.is_none() {
+                    return Err(ValidationError::ConsoleFileMissing);
+                }
+            }
+            ConsoleOutputMode::Socket => {
+                if self.socket.is_none() {
+                    return Err(ValidationError::ConsoleSocketPathMissing);
+                }
+            }
+            ConsoleOutputMode::Tcp => {
+                if self.tcp.is_none() {
+                    return Err(ValidationError::ConsoleTcpAddressMissing);
+                }
+            }
+            _ => return Err(ValidationError::ConsolePortInvalidMode(self.mode.clone())),
+        }
+
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
+
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl VsockConfig {
     pub const SYNTAX: &'static str = "Virtio VSOCK parameters \
-        \"cid=<context_id>,socket=<socket_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>\"";
+        \"cid=<context_id>,socket=<socket_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>,\
+        connect=[<guest_port>@<host_tcp_address>,...],listen=[<guest_port>@<host_tcp_address>,...]\"";
 
     pub fn parse(vsock: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -2046,7 +3093,9 @@ impl VsockConfig {
             .add("cid")
             .add("iommu")
             .add("id")
-            .add("pci_segment");
+            .add("pci_segment")
+            .add("connect")
+            .add("listen");
         parser.parse(vsock).map_err(Error::ParseVsock)?;
 
         let socket = parser
@@ -2068,12 +3117,35 @@ impl VsockConfig {
             .map_err(Error::ParseVsock)?
             .unwrap_or_default();
 
+        let mut forwards = Vec::new();
+        for (option, direction) in [
+            ("connect", VsockForwardDirection::GuestToHost),
+            ("listen", VsockForwardDirection::HostToGuest),
+        ] {
+            if let Some(tuples) = parser
+                .convert::<Tuple<u32, SocketAddr>>(option)
+                .map_err(Error::ParseVsock)?
+            {
+                forwards.extend(
+                    tuples
+                        .0
+                        .into_iter()
+                        .map(|(port, address)| VsockForwardConfig {
+                            port,
+                            address,
+                            direction,
+                        }),
+                );
+            }
+        }
+
         Ok(VsockConfig {
             cid,
             socket,
             iommu,
             id,
             pci_segment,
+            forwards,
         })
     }
 
@@ -2090,10 +3162,41 @@ impl VsockConfig {
             }
         }
 
+        for (i, forward) in self.forwards.iter().enumerate() {
+            if self.forwards[..i].iter().any(|f| forward.conflicts_with(f)) {
+                return Err(ValidationError::DuplicateVsockForward(forward.to_string()));
+            }
+        }
+
         Ok(())
     }
 }
 
+impl VsockForwardConfig {
+    /// Two rules conflict when they would both handle the same connections: guest
+    /// connections to the same port, or host connections to the same TCP address.
+    pub fn conflicts_with(&self, other: &VsockForwardConfig) -> bool {
+        self.direction == other.direction
+            && match self.direction {
+                VsockForwardDirection::GuestToHost => self.port == other.port,
+                VsockForwardDirection::HostToGuest => self.address == other.address,
+            }
+    }
+}
+
+impl fmt::Display for VsockForwardConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.direction {
+            VsockForwardDirection::GuestToHost => {
+                write!(f, "guest port {} to {}", self.port, self.address)
+            }
+            VsockForwardDirection::HostToGuest => {
+                write!(f, "{} to guest port {}", self.address, self.port)
+            }
+        }
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 impl SgxEpcConfig {
     pub const SYNTAX: &'static str = "SGX EPC parameters \
@@ -2120,6 +3223,25 @@ impl SgxEpcConfig {
     }
 }
 
+#[cfg(target_arch = "aarch64")]
+impl FdtOverlayConfig {
+    pub const SYNTAX: &'static str = "Device tree overlay parameters \
+        \"path=<overlay_dtbo_path>\"";
+
+    pub fn parse(fdt_overlay: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("path");
+        parser.parse(fdt_overlay).map_err(Error::ParseFdtOverlay)?;
+
+        let path = parser
+            .get("path")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseFdtOverlayPathMissing)?;
+
+        Ok(FdtOverlayConfig { path })
+    }
+}
+
 impl NumaConfig {
     pub const SYNTAX: &'static str = "Settings related to a given NUMA node \
         \"guest_numa_id=<node_id>,cpus=<cpus_id>,distances=<list_of_distances_to_destination_nodes>,\
@@ -2145,7 +3267,7 @@ impl NumaConfig {
         let cpus = parser
             .convert::<IntegerList>("cpus")
             .map_err(Error::ParseNuma)?
-            .map(|v| v.0.iter().map(|e| *e as u8).collect());
+            .map(|v| v.0.iter().map(|e| *e as u32).collect());
         let distances = parser
             .convert::<Tuple<u64, u64>>("distances")
             .map_err(Error::ParseNuma)?
@@ -2232,21 +3354,38 @@ pub struct RestoreConfig {
     #[serde(default)]
     pub prefault: bool,
     #[serde(default)]
+    pub lazy: bool,
+    #[serde(default)]
+    pub clone: bool,
+    #[serde(default)]
     pub net_fds: Option<Vec<RestoredNetConfig>>,
+    /// Run the restore as an API operation rather than waiting for it
+    #[serde(default, rename = "async")]
+    pub asynchronous: bool,
 }
 
 impl RestoreConfig {
     pub const SYNTAX: &'static str = "Restore from a VM snapshot. \
-        \nRestore parameters \"source_url=<source_url>,prefault=on|off,\
-        net_fds=<list_of_net_ids_with_their_associated_fds>\" \
+        \nRestore parameters \"source_url=<source_url>,prefault=on|off,lazy=on|off,\
+        clone=on|off,net_fds=<list_of_net_ids_with_their_associated_fds>\" \
         \n`source_url` should be a valid URL (e.g file:///foo/bar or tcp://192.168.1.10/foo) \
         \n`prefault` brings memory pages in when enabled (disabled by default) \
+        \n`lazy` starts the VM before the memory is fully restored, paging it in \
+        on demand (disabled by default) \
+        \n`clone` maps the snapshot memory copy-on-write so that it can be shared \
+        by several VMs restored from it, and gives the VM a new generation ID \
+        (disabled by default) \
         \n`net_fds` is a list of net ids with new file descriptors. \
         Only net devices backed by FDs directly are needed as input.";
 
     pub fn parse(restore: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("source_url").add("prefault").add("net_fds");
+        parser
+            .add("source_url")
+            .add("prefault")
+            .add("lazy")
+            .add("clone")
+            .add("net_fds");
         parser.parse(restore).map_err(Error::ParseRestore)?;
 
         let source_url = parser
@@ -2258,6 +3397,16 @@ impl RestoreConfig {
             .map_err(Error::ParseRestore)?
             .unwrap_or(Toggle(false))
             .0;
+        let lazy = parser
+            .convert::<Toggle>("lazy")
+            .map_err(Error::ParseRestore)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let clone = parser
+            .convert::<Toggle>("clone")
+            .map_err(Error::ParseRestore)?
+            .unwrap_or(Toggle(false))
+            .0;
         let net_fds = parser
             .convert::<Tuple<String, Vec<u64>>>("net_fds")
             .map_err(Error::ParseRestore)?
@@ -2274,7 +3423,10 @@ impl RestoreConfig {
         Ok(RestoreConfig {
             source_url,
             prefault,
+            lazy,
+            clone,
             net_fds,
+            asynchronous: false,
         })
     }
 
@@ -2320,13 +3472,38 @@ impl RestoreConfig {
             warn!("Ignoring unused 'net_fds' for VM restore.")
         }
 
-        Ok(())
-    }
-}
+        if self.lazy {
+            if self.prefault {
+                return Err(ValidationError::LazyRestorePrefault);
+            }
 
-impl TpmConfig {
-    pub const SYNTAX: &'static str = "TPM device \
-        \"(UNIX Domain Socket from swtpm) socket=</path/to/a/socket>\"";
+            // Other processes accessing the guest memory wouldn't have their
+            // page faults handled.
+            let memory = &vm_config.memory;
+            if memory.shared || memory.zones.iter().flatten().any(|z| z.shared) {
+                return Err(ValidationError::LazyRestoreSharedMemory);
+            }
+        }
+
+        if self.clone {
+            if self.lazy {
+                return Err(ValidationError::CloneRestoreLazy);
+            }
+
+            // Prefaulting would populate the memory which is about to be
+            // replaced with the snapshot mapping.
+            if self.prefault {
+                return Err(ValidationError::CloneRestorePrefault);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TpmConfig {
+    pub const SYNTAX: &'static str = "TPM device \
+        \"(UNIX Domain Socket from swtpm) socket=</path/to/a/socket>\"";
 
     pub fn parse(tpm: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -2340,6 +3517,160 @@ impl TpmConfig {
     }
 }
 
+impl RestartPolicyConfig {
+    pub const SYNTAX: &'static str = "Restart policy of the VM as the guest resets or \
+        shuts down \"policy=always|on-failure|never,max_reboots=<count>,\
+        window=<seconds>\"";
+
+    pub fn parse(restart_policy: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("policy").add("max_reboots").add("window");
+        parser
+            .parse(restart_policy)
+            .map_err(Error::ParseRestartPolicy)?;
+        let policy = parser
+            .convert("policy")
+            .map_err(Error::ParseRestartPolicy)?
+            .unwrap_or_default();
+        let max_reboots = parser
+            .convert("max_reboots")
+            .map_err(Error::ParseRestartPolicy)?;
+        let window = parser
+            .convert("window")
+            .map_err(Error::ParseRestartPolicy)?
+            .unwrap_or(DEFAULT_RESTART_POLICY_WINDOW);
+        Ok(RestartPolicyConfig {
+            policy,
+            max_reboots,
+            window,
+        })
+    }
+}
+
+#[cfg(feature = "mdns")]
+impl MdnsConfig {
+    pub const SYNTAX: &'static str = "Name of the guest published through mDNS, \
+        as <hostname>.local \"hostname=<hostname>,ip=<guest_ipv4_address>\"";
+
+    pub fn parse(mdns: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("hostname").add("ip");
+        parser.parse(mdns).map_err(Error::ParseMdns)?;
+        let hostname = parser
+            .get("hostname")
+            .ok_or(Error::ParseMdnsHostnameMissing)?;
+        let ip = parser
+            .convert("ip")
+            .map_err(Error::ParseMdns)?
+            .ok_or(Error::ParseMdnsIpMissing)?;
+        Ok(MdnsConfig { hostname, ip })
+    }
+
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.hostname.is_empty()
+            || self.hostname.len() > 63
+            || !self
+                .hostname
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(ValidationError::InvalidMdnsHostname(self.hostname.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sev_snp")]
+impl SevSnpConfig {
+    pub const SYNTAX: &'static str = "SEV-SNP launch parameters \
+        \"host_data=<hex_encoded_host_data>,host_data_file=<host_data_path>,\
+        policy=<guest_policy>,id_block_key=<id_key_path>,author_key=<author_key_path>,\
+        svsm=<svsm_igvm_path>,lazy_unmeasured=on|off,certificates=<certificate_table_path>\"";
+
+    pub fn parse(sev_snp: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("host_data")
+            .add("host_data_file")
+            .add("policy")
+            .add("id_block_key")
+            .add("author_key")
+            .add("svsm")
+            .add("lazy_unmeasured")
+            .add("certificates");
+        parser.parse(sev_snp).map_err(Error::ParseSevSnp)?;
+
+        let host_data = parser.get("host_data");
+        let host_data_file = parser.get("host_data_file").map(PathBuf::from);
+        // The policy is usually written in hex, as in the AMD specification.
+        let policy = parser
+            .get("policy")
+            .map(|p| {
+                parse_hex_or_dec(&p).map_err(|_| {
+                    Error::ParseSevSnp(OptionParserError::Conversion("policy".to_owned(), p))
+                })
+            })
+            .transpose()?;
+        let id_block_key = parser.get("id_block_key").map(PathBuf::from);
+        let author_key = parser.get("author_key").map(PathBuf::from);
+        let svsm = parser.get("svsm").map(PathBuf::from);
+        let lazy_unmeasured = parser
+            .convert::<Toggle>("lazy_unmeasured")
+            .map_err(Error::ParseSevSnp)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let certificates = parser.get("certificates").map(PathBuf::from);
+
+        Ok(SevSnpConfig {
+            host_data,
+            host_data_file,
+            policy,
+            id_block_key,
+            author_key,
+            svsm,
+            lazy_unmeasured,
+            certificates,
+        })
+    }
+
+    pub fn validate(&self) -> ValidationResult<()> {
+        if let Some(host_data) = &self.host_data {
+            if host_data.len() != 64 || !host_data.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(ValidationError::InvalidSevSnpHostData);
+            }
+            if self.host_data_file.is_some() {
+                return Err(ValidationError::SevSnpHostDataConflict);
+            }
+        }
+
+        // See AMD SEV-SNP ABI Section 4.3 - Guest Policy, bit 17 being
+        // reserved to one and bits 63:25 to zero.
+        if let Some(policy) = self.policy {
+            if policy & (1 << 17) == 0 || policy >> 25 != 0 {
+                return Err(ValidationError::InvalidSevSnpPolicy(policy));
+            }
+        }
+
+        if self.author_key.is_some() && self.id_block_key.is_none() {
+            return Err(ValidationError::SevSnpAuthorKeyWithoutIdBlockKey);
+        }
+
+        // The policy is part of the signed ID block.
+        if self.id_block_key.is_some() && self.policy.is_none() {
+            return Err(ValidationError::SevSnpIdBlockKeyWithoutPolicy);
+        }
+
+        // The launch digest the ID block is signed for only covers the
+        // firmware.
+        if self.svsm.is_some() && self.id_block_key.is_some() {
+            return Err(ValidationError::SevSnpSvsmWithIdBlockKey);
+        }
+
+        Ok(())
+    }
+}
+
 impl LandlockConfig {
     pub const SYNTAX: &'static str = "Landlock parameters \
         \"path=<path/to/{file/dir}>,access=[rw]\"";
@@ -2424,11 +3755,28 @@ impl VmConfig {
             .as_ref()
             .ok_or(ValidationError::KernelMissing)?;
 
+        // Each platform of an IGVM file owns one bit of the mask.
+        #[cfg(feature = "igvm")]
+        if let Some(mask) = self
+            .payload
+            .as_ref()
+            .and_then(|p| p.igvm_compatibility_mask)
+        {
+            if mask.count_ones() != 1 {
+                return Err(ValidationError::InvalidIgvmCompatibilityMask(mask));
+            }
+        }
+
         #[cfg(feature = "tdx")]
         {
             let tdx_enabled = self.platform.as_ref().map(|p| p.tdx).unwrap_or(false);
             // At this point we know payload isn't None.
-            if tdx_enabled && self.payload.as_ref().unwrap().firmware.is_none() {
+            let payload = self.payload.as_ref().unwrap();
+            #[cfg(feature = "igvm")]
+            let igvm_present = payload.igvm.is_some();
+            #[cfg(not(feature = "igvm"))]
+            let igvm_present = false;
+            if tdx_enabled && payload.firmware.is_none() && !igvm_present {
                 return Err(ValidationError::TdxFirmwareMissing);
             }
             if tdx_enabled && (self.cpus.max_vcpus != self.cpus.boot_vcpus) {
@@ -2436,6 +3784,28 @@ impl VmConfig {
             }
         }
 
+        #[cfg(any(feature = "tdx", feature = "sev_snp"))]
+        {
+            let mut confidential = false;
+            #[cfg(feature = "tdx")]
+            {
+                confidential |= self.is_tdx_enabled();
+            }
+            #[cfg(feature = "sev_snp")]
+            {
+                confidential |= self.is_sev_snp_enabled();
+            }
+
+            // Populating the whole memory from the host would make the boot
+            // as slow as accepting it all upfront.
+            if confidential
+                && (self.memory.prefault
+                    || self.memory.zones.iter().flatten().any(|zone| zone.prefault))
+            {
+                return Err(ValidationError::ConfidentialPrefault);
+            }
+        }
+
         #[cfg(feature = "sev_snp")]
         {
             let host_data_opt = &self.payload.as_ref().unwrap().host_data;
@@ -2444,8 +3814,39 @@ impl VmConfig {
                 if host_data.len() != 64 {
                     return Err(ValidationError::InvalidHostData);
                 }
+
+                if self
+                    .platform
+                    .as_ref()
+                    .is_some_and(|p| p.kbs_host_data.is_some())
+                {
+                    return Err(ValidationError::KbsHostDataConflict);
+                }
+            }
+
+            // The firmware is the only payload measured at launch.
+            if self.is_sev_es_enabled() {
+                let payload = self.payload.as_ref().unwrap();
+                #[cfg(feature = "igvm")]
+                let igvm = payload.igvm.is_some();
+                #[cfg(not(feature = "igvm"))]
+                let igvm = false;
+                if payload.firmware.is_none() || payload.kernel.is_some() || igvm {
+                    return Err(ValidationError::SevEsFirmwareMissing);
+                }
+            }
+        }
+        for (family, requested) in [
+            ("balloon", self.balloon.is_some()),
+            ("vdpa", self.vdpa.is_some()),
+            ("vsock", self.vsock.is_some()),
+            ("watchdog", self.watchdog),
+        ] {
+            if requested && !device_manager::virtio_devices_built_in(family) {
+                return Err(ValidationError::DeviceNotBuiltIn(family));
             }
         }
+
         // The 'conflict' check is introduced in commit 24438e0390d3
         // (vm-virtio: Enable the vmm support for virtio-console).
         //
@@ -2471,18 +3872,48 @@ impl VmConfig {
             warn!("Using TTY output for multiple consoles: {:?}", tty_consoles);
         }
 
-        if self.console.mode == ConsoleOutputMode::File && self.console.file.is_none() {
-            return Err(ValidationError::ConsoleFileMissing);
+        for console in [&self.console, &self.serial] {
+            match console.mode {
+                ConsoleOutputMode::File | ConsoleOutputMode::Pipe if console.file.is_none() => {
+                    return Err(ValidationError::ConsoleFileMissing);
+                }
+                ConsoleOutputMode::Socket if console.socket.is_none() => {
+                    return Err(ValidationError::ConsoleSocketPathMissing);
+                }
+                ConsoleOutputMode::Tcp if console.tcp.is_none() => {
+                    return Err(ValidationError::ConsoleTcpAddressMissing);
+                }
+                _ => {}
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        match self.debug_console.mode {
+            ConsoleOutputMode::File | ConsoleOutputMode::Pipe
+                if self.debug_console.file.is_none() =>
+            {
+                return Err(ValidationError::DebugconFileMissing);
+            }
+            ConsoleOutputMode::Socket | ConsoleOutputMode::Tcp => {
+                return Err(ValidationError::DebugconSocketUnsupported);
+            }
+            _ => {}
         }
 
-        if self.serial.mode == ConsoleOutputMode::File && self.serial.file.is_none() {
-            return Err(ValidationError::ConsoleFileMissing);
+        if self.console.log_socket.is_some()
+            || (self.serial.mode == ConsoleOutputMode::Off && self.serial.log_socket.is_some())
+        {
+            return Err(ValidationError::ConsoleLogSocketUnsupported);
         }
 
         if self.cpus.max_vcpus < self.cpus.boot_vcpus {
             return Err(ValidationError::CpusMaxLowerThanBoot);
         }
 
+        if self.cpus.max_vcpus > MAX_SUPPORTED_VCPUS {
+            return Err(ValidationError::CpusMaxTooHigh(self.cpus.max_vcpus));
+        }
+
         if let Some(rate_limit_groups) = &self.rate_limit_groups {
             for rate_limit_group in rate_limit_groups {
                 rate_limit_group.validate(self)?;
@@ -2535,7 +3966,11 @@ impl VmConfig {
         }
 
         if let Some(fses) = &self.fs {
-            if !fses.is_empty() && !self.backed_by_shared_memory() {
+            // Only the vhost-user daemon behind virtio-fs accesses the guest
+            // memory.
+            if fses.iter().any(|fs| fs.protocol == FsProtocol::VirtioFs)
+                && !self.backed_by_shared_memory()
+            {
                 return Err(ValidationError::VhostUserRequiresSharedMemory);
             }
             for fs in fses {
@@ -2545,6 +3980,15 @@ impl VmConfig {
             }
         }
 
+        if let Some(gpu) = &self.gpu {
+            if !self.backed_by_shared_memory() {
+                return Err(ValidationError::VhostUserRequiresSharedMemory);
+            }
+            gpu.validate(self)?;
+
+            Self::validate_identifier(&mut id_list, &gpu.id)?;
+        }
+
         if let Some(pmems) = &self.pmem {
             for pmem in pmems {
                 pmem.validate(self)?;
@@ -2574,7 +4018,10 @@ impl VmConfig {
                 return Err(ValidationError::CpuTopologyDiesPerPackage);
             }
 
-            let total = t.threads_per_core * t.cores_per_die * t.dies_per_package * t.packages;
+            let total = u32::from(t.threads_per_core)
+                * u32::from(t.cores_per_die)
+                * u32::from(t.dies_per_package)
+                * u32::from(t.packages);
             if total != self.cpus.max_vcpus {
                 return Err(ValidationError::CpuTopologyCount);
             }
@@ -2589,6 +4036,26 @@ impl VmConfig {
             }
         }
 
+        if self.memory.auto_numa {
+            if self.numa.is_some() || self.memory.zones.is_some() {
+                return Err(ValidationError::AutoNumaConflict);
+            }
+            if self.memory.hotplug_size.is_some() {
+                return Err(ValidationError::AutoNumaMemoryHotplug);
+            }
+            for vcpu in 0..self.cpus.max_vcpus {
+                if !self
+                    .cpus
+                    .affinity
+                    .iter()
+                    .flatten()
+                    .any(|a| a.vcpu == vcpu && !a.host_cpus.is_empty())
+                {
+                    return Err(ValidationError::AutoNumaWithoutAffinity(vcpu));
+                }
+            }
+        }
+
         if let Some(user_devices) = &self.user_devices {
             if !user_devices.is_empty() && !self.backed_by_shared_memory() {
                 return Err(ValidationError::UserDevicesRequireSharedMemory);
@@ -2616,6 +4083,15 @@ impl VmConfig {
             }
         }
 
+        if let Some(console_ports) = &self.console_ports {
+            for console_port in console_ports {
+                console_port.validate(self)?;
+                self.iommu |= console_port.iommu;
+
+                Self::validate_identifier(&mut id_list, &console_port.id)?;
+            }
+        }
+
         if let Some(balloon) = &self.balloon {
             let mut ram_size = self.memory.size;
 
@@ -2737,6 +4213,35 @@ impl VmConfig {
             }
         }
 
+        #[cfg(feature = "mdns")]
+        self.mdns.as_ref().map(|m| m.validate()).transpose()?;
+
+        #[cfg(feature = "sev_snp")]
+        if let Some(sev_snp) = &self.sev_snp {
+            if !self.is_sev_snp_enabled() {
+                return Err(ValidationError::SevSnpNotEnabled);
+            }
+            sev_snp.validate()?;
+
+            // The guest would find the TPM of the host, which isn't protected
+            // from it, besides the one of the SVSM.
+            if sev_snp.svsm.is_some() && self.tpm.is_some() {
+                return Err(ValidationError::SevSnpSvsmWithTpm);
+            }
+
+            // The payload, the KBS and this section are exclusive sources of
+            // the host data.
+            if (sev_snp.host_data.is_some() || sev_snp.host_data_file.is_some())
+                && (self.payload.as_ref().is_some_and(|p| p.host_data.is_some())
+                    || self
+                        .platform
+                        .as_ref()
+                        .is_some_and(|p| p.kbs_host_data.is_some()))
+            {
+                return Err(ValidationError::SevSnpHostDataConflict);
+            }
+        }
+
         Ok(id_list)
     }
 
@@ -2792,6 +4297,11 @@ impl VmConfig {
             fs = Some(fs_config_list);
         }
 
+        let mut gpu: Option<GpuConfig> = None;
+        if let Some(gpu_params) = &vm_params.gpu {
+            gpu = Some(GpuConfig::parse(gpu_params)?);
+        }
+
         let mut pmem: Option<Vec<PmemConfig>> = None;
         if let Some(pmem_list) = &vm_params.pmem {
             let mut pmem_config_list = Vec::new();
@@ -2869,6 +4379,12 @@ impl VmConfig {
             }
         }
 
+        #[cfg(target_arch = "aarch64")]
+        let fdt_overlays = vm_params
+            .fdt_overlays
+            .map(|list| list.into_iter().map(FdtOverlayConfig::parse).collect())
+            .transpose()?;
+
         let mut numa: Option<Vec<NumaConfig>> = None;
         if let Some(numa_list) = &vm_params.numa {
             let mut numa_config_list = Vec::new();
@@ -2886,6 +4402,17 @@ impl VmConfig {
         let payload_present =
             vm_params.kernel.is_some() || vm_params.firmware.is_some() || vm_params.igvm.is_some();
 
+        #[cfg(feature = "igvm")]
+        let igvm_compatibility_mask = vm_params
+            .igvm_compatibility_mask
+            .map(|mask| {
+                parse_hex_or_dec(mask)
+                    .ok()
+                    .and_then(|mask| u32::try_from(mask).ok())
+                    .ok_or_else(|| Error::ParseIgvmCompatibilityMask(mask.to_owned()))
+            })
+            .transpose()?;
+
         let payload = if payload_present {
             Some(PayloadConfig {
                 kernel: vm_params.kernel.map(PathBuf::from),
@@ -2894,6 +4421,8 @@ impl VmConfig {
                 firmware: vm_params.firmware.map(PathBuf::from),
                 #[cfg(feature = "igvm")]
                 igvm: vm_params.igvm.map(PathBuf::from),
+                #[cfg(feature = "igvm")]
+                igvm_compatibility_mask,
                 #[cfg(feature = "sev_snp")]
                 host_data: vm_params.host_data.map(|s| s.to_string()),
             })
@@ -2909,6 +4438,17 @@ impl VmConfig {
             });
         }
 
+        let restart_policy = vm_params
+            .restart_policy
+            .map(RestartPolicyConfig::parse)
+            .transpose()?;
+
+        #[cfg(feature = "mdns")]
+        let mdns = vm_params.mdns.map(MdnsConfig::parse).transpose()?;
+
+        #[cfg(feature = "sev_snp")]
+        let sev_snp = vm_params.sev_snp.map(SevSnpConfig::parse).transpose()?;
+
         #[cfg(feature = "guest_debug")]
         let gdb = vm_params.gdb;
 
@@ -2932,9 +4472,11 @@ impl VmConfig {
             rng,
             balloon,
             fs,
+            gpu,
             pmem,
             serial,
             console,
+            console_ports: None,
             #[cfg(target_arch = "x86_64")]
             debug_console,
             devices,
@@ -2947,13 +4489,20 @@ impl VmConfig {
             iommu: false, // updated in VmConfig::validate()
             #[cfg(target_arch = "x86_64")]
             sgx_epc,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays,
             numa,
             watchdog: vm_params.watchdog,
             #[cfg(feature = "guest_debug")]
             gdb,
             pci_segments,
             platform,
+            #[cfg(feature = "sev_snp")]
+            sev_snp,
             tpm,
+            restart_policy,
+            #[cfg(feature = "mdns")]
+            mdns,
             preserved_fds: None,
             landlock_enable: vm_params.landlock_enable,
             landlock_rules,
@@ -3014,6 +4563,13 @@ impl VmConfig {
             removed |= vdpa.len() != len;
         }
 
+        // Remove if console port
+        if let Some(console_ports) = self.console_ports.as_mut() {
+            let len = console_ports.len();
+            console_ports.retain(|dev| dev.id.as_ref().map(|id| id.as_ref()) != Some(id));
+            removed |= console_ports.len() != len;
+        }
+
         // Remove if vsock device
         if let Some(vsock) = self.vsock.as_ref() {
             if vsock.id.as_ref().map(|id| id.as_ref()) == Some(id) {
@@ -3022,6 +4578,14 @@ impl VmConfig {
             }
         }
 
+        // Remove if gpu device
+        if let Some(gpu) = self.gpu.as_ref() {
+            if gpu.id.as_ref().map(|id| id.as_ref()) == Some(id) {
+                self.gpu = None;
+                removed = true;
+            }
+        }
+
         removed
     }
 
@@ -3049,6 +4613,11 @@ impl VmConfig {
     pub fn is_sev_snp_enabled(&self) -> bool {
         self.platform.as_ref().map(|p| p.sev_snp).unwrap_or(false)
     }
+
+    #[cfg(feature = "sev_snp")]
+    pub fn is_sev_es_enabled(&self) -> bool {
+        self.platform.as_ref().map(|p| p.sev_es).unwrap_or(false)
+    }
 }
 
 impl Clone for VmConfig {
@@ -3065,9 +4634,11 @@ impl Clone for VmConfig {
             #[cfg(feature = "pvmemcontrol")]
             pvmemcontrol: self.pvmemcontrol.clone(),
             fs: self.fs.clone(),
+            gpu: self.gpu.clone(),
             pmem: self.pmem.clone(),
             serial: self.serial.clone(),
             console: self.console.clone(),
+            console_ports: self.console_ports.clone(),
             #[cfg(target_arch = "x86_64")]
             debug_console: self.debug_console.clone(),
             devices: self.devices.clone(),
@@ -3076,10 +4647,17 @@ impl Clone for VmConfig {
             vsock: self.vsock.clone(),
             #[cfg(target_arch = "x86_64")]
             sgx_epc: self.sgx_epc.clone(),
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays: self.fdt_overlays.clone(),
             numa: self.numa.clone(),
             pci_segments: self.pci_segments.clone(),
             platform: self.platform.clone(),
+            #[cfg(feature = "sev_snp")]
+            sev_snp: self.sev_snp.clone(),
             tpm: self.tpm.clone(),
+            restart_policy: self.restart_policy.clone(),
+            #[cfg(feature = "mdns")]
+            mdns: self.mdns.clone(),
             preserved_fds: self
                 .preserved_fds
                 .as_ref()
@@ -3105,6 +4683,7 @@ impl Drop for VmConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use block::qos::IoPriorityClass;
     use net_util::MacAddr;
     use std::fs::File;
     use std::net::Ipv4Addr;
@@ -3174,6 +4753,38 @@ mod tests {
                 ..Default::default()
             },
         );
+        assert_eq!(
+            CpusConfig::parse("boot=1,halt_poll_ns=200000")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                halt_poll_ns: Some(200000),
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("boot=1,idle_poll=on")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                idle_poll: true,
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("boot=1,features=[amx,cet]")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                features: CpuFeatures {
+                    amx: true,
+                    cet: true,
+                },
+                ..Default::default()
+            }
+        );
 
         Ok(())
     }
@@ -3230,6 +4841,22 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            MemoryConfig::parse("size=8G,auto_numa=on", None)?,
+            MemoryConfig {
+                size: 8 << 30,
+                auto_numa: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,coredump=on", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                coredump: true,
+                ..Default::default()
+            }
+        );
         assert_eq!(
             MemoryConfig::parse("hugepages=on,size=1G,hugepage_size=2M", None)?,
             MemoryConfig {
@@ -3239,6 +4866,21 @@ mod tests {
                 ..Default::default()
             }
         );
+        let zones = MemoryConfig::parse(
+            "size=0",
+            Some(vec![
+                "id=mem0,size=1G,host_numa_node=1,host_numa_policy=preferred",
+            ]),
+        )?
+        .zones
+        .unwrap();
+        assert_eq!(zones[0].host_numa_node, Some(1));
+        assert_eq!(zones[0].host_numa_policy, HostNumaPolicy::Preferred);
+        assert!(MemoryConfig::parse(
+            "size=0",
+            Some(vec!["id=mem0,size=1G,host_numa_policy=interleave"])
+        )
+        .is_err());
         Ok(())
     }
 
@@ -3319,6 +4961,10 @@ mod tests {
         DiskConfig {
             path: Some(PathBuf::from("/path/to_file")),
             readonly: false,
+            share: false,
+            image_type: None,
+            key_file: None,
+            key_resource: None,
             direct: false,
             iommu: false,
             num_queues: 1,
@@ -3333,6 +4979,12 @@ mod tests {
             pci_segment: 0,
             serial: None,
             queue_affinity: None,
+            io_timeout: None,
+            on_io_error: OnIoError::Report,
+            io_priority: None,
+            io_max: None,
+            cache: None,
+            transient: false,
         }
     }
 
@@ -3382,6 +5034,42 @@ mod tests {
                 ..disk_fixture()
             }
         );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,readonly=on")?,
+            DiskConfig {
+                readonly: true,
+                ..disk_fixture()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,share=on")?,
+            DiskConfig {
+                share: true,
+                ..disk_fixture()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,image_type=qcow2")?,
+            DiskConfig {
+                image_type: Some(block::ImageType::Qcow2),
+                ..disk_fixture()
+            }
+        );
+        assert!(DiskConfig::parse("path=/path/to_file,image_type=foo").is_err());
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,key_file=/path/to_key")?,
+            DiskConfig {
+                key_file: Some(PathBuf::from("/path/to_key")),
+                ..disk_fixture()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,key_resource=default/key/disk0")?,
+            DiskConfig {
+                key_resource: Some("default/key/disk0".to_owned()),
+                ..disk_fixture()
+            }
+        );
         assert_eq!(
             DiskConfig::parse("path=/path/to_file,direct=on")?,
             DiskConfig {
@@ -3427,6 +5115,48 @@ mod tests {
                 ..disk_fixture()
             }
         );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,io_timeout=30000,on_io_error=pause")?,
+            DiskConfig {
+                io_timeout: Some(30000),
+                on_io_error: OnIoError::Pause,
+                ..disk_fixture()
+            }
+        );
+        assert!(DiskConfig::parse("path=/path/to_file,on_io_error=ignore").is_err());
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,io_priority=be:2,io_max_wbps=1048576")?,
+            DiskConfig {
+                io_priority: Some(IoPriority {
+                    class: IoPriorityClass::BestEffort,
+                    level: 2,
+                }),
+                io_max: Some(IoMaxConfig {
+                    wbps: Some(1048576),
+                    ..Default::default()
+                }),
+                ..disk_fixture()
+            }
+        );
+        assert!(DiskConfig::parse("path=/path/to_file,io_priority=be:9").is_err());
+        assert_eq!(
+            DiskConfig::parse(
+                "path=http://images.example/disk.raw,readonly=on,cache=/var/cache/disk.raw"
+            )?,
+            DiskConfig {
+                path: Some(PathBuf::from("http://images.example/disk.raw")),
+                readonly: true,
+                cache: Some(PathBuf::from("/var/cache/disk.raw")),
+                ..disk_fixture()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,transient=on")?,
+            DiskConfig {
+                transient: true,
+                ..disk_fixture()
+            }
+        );
         Ok(())
     }
 
@@ -3451,6 +5181,7 @@ mod tests {
             offload_tso: true,
             offload_ufo: true,
             offload_csum: true,
+            standby: false,
         }
     }
 
@@ -3503,6 +5234,14 @@ mod tests {
             }
         );
 
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,standby=on")?,
+            NetConfig {
+                standby: true,
+                ..net_fixture()
+            }
+        );
+
         assert_eq!(
             NetConfig::parse("mac=de:ad:be:ef:12:34,fd=[3,7],num_queues=4")?,
             NetConfig {
@@ -3547,6 +5286,7 @@ mod tests {
         FsConfig {
             socket: PathBuf::from("/tmp/sock"),
             tag: "mytag".to_owned(),
+            protocol: FsProtocol::VirtioFs,
             num_queues: 1,
             queue_size: 1024,
             id: None,
@@ -3569,6 +5309,14 @@ mod tests {
                 ..fs_fixture()
             }
         );
+        assert_eq!(
+            FsConfig::parse("tag=mytag,socket=/tmp/sock,protocol=9p")?,
+            FsConfig {
+                protocol: FsProtocol::P9,
+                ..fs_fixture()
+            }
+        );
+        assert!(FsConfig::parse("tag=mytag,socket=/tmp/sock,protocol=nfs").is_err());
 
         Ok(())
     }
@@ -3623,6 +5371,8 @@ mod tests {
                 iommu: false,
                 file: None,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             }
         );
         assert_eq!(
@@ -3632,6 +5382,8 @@ mod tests {
                 iommu: false,
                 file: None,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             }
         );
         assert_eq!(
@@ -3641,6 +5393,8 @@ mod tests {
                 iommu: false,
                 file: None,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             }
         );
         assert_eq!(
@@ -3650,6 +5404,8 @@ mod tests {
                 iommu: false,
                 file: None,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             }
         );
         assert_eq!(
@@ -3659,6 +5415,8 @@ mod tests {
                 iommu: false,
                 file: Some(PathBuf::from("/tmp/console")),
                 socket: None,
+                tcp: None,
+                log_socket: None,
             }
         );
         assert_eq!(
@@ -3668,6 +5426,8 @@ mod tests {
                 iommu: true,
                 file: None,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             }
         );
         assert_eq!(
@@ -3677,6 +5437,8 @@ mod tests {
                 iommu: true,
                 file: Some(PathBuf::from("/tmp/console")),
                 socket: None,
+                tcp: None,
+                log_socket: None,
             }
         );
         assert_eq!(
@@ -3686,18 +5448,55 @@ mod tests {
                 iommu: true,
                 file: None,
                 socket: Some(PathBuf::from("/tmp/serial.sock")),
+                tcp: None,
+                log_socket: None,
             }
         );
-        Ok(())
-    }
-
-    fn device_fixture() -> DeviceConfig {
-        DeviceConfig {
-            path: PathBuf::from("/path/to/device"),
-            id: None,
+        assert_eq!(
+            ConsoleConfig::parse("tcp=127.0.0.1:4444")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Tcp,
+                iommu: false,
+                file: None,
+                socket: None,
+                tcp: Some("127.0.0.1:4444".parse().unwrap()),
+                log_socket: None,
+            }
+        );
+        assert!(ConsoleConfig::parse("tcp=localhost").is_err());
+        assert_eq!(
+            ConsoleConfig::parse("pipe=/dev/ttyUSB0")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Pipe,
+                iommu: false,
+                file: Some(PathBuf::from("/dev/ttyUSB0")),
+                socket: None,
+                tcp: None,
+                log_socket: None,
+            }
+        );
+        assert_eq!(
+            ConsoleConfig::parse("pty,log_socket=/run/shipper.sock")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Pty,
+                iommu: false,
+                file: None,
+                socket: None,
+                tcp: None,
+                log_socket: Some(PathBuf::from("/run/shipper.sock")),
+            }
+        );
+        Ok(())
+    }
+
+    fn device_fixture() -> DeviceConfig {
+        DeviceConfig {
+            path: PathBuf::from("/path/to/device"),
+            id: None,
             iommu: false,
             pci_segment: 0,
             x_nv_gpudirect_clique: None,
+            reset: None,
         }
     }
 
@@ -3727,6 +5526,15 @@ mod tests {
             }
         );
 
+        assert_eq!(
+            DeviceConfig::parse("path=/path/to/device,reset=flr")?,
+            DeviceConfig {
+                reset: Some(VfioResetMethod::Flr),
+                ..device_fixture()
+            }
+        );
+        assert!(DeviceConfig::parse("path=/path/to/device,reset=warm").is_err());
+
         Ok(())
     }
 
@@ -3756,6 +5564,74 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_console_port_parsing() -> Result<()> {
+        // mode is required
+        assert!(ConsolePortConfig::parse("").is_err());
+        assert!(ConsolePortConfig::parse("tty").is_err());
+        assert_eq!(
+            ConsolePortConfig::parse("pty")?,
+            ConsolePortConfig {
+                mode: ConsoleOutputMode::Pty,
+                file: None,
+                socket: None,
+                tcp: None,
+                iommu: false,
+                id: None,
+                pci_segment: 0,
+            }
+        );
+        assert_eq!(
+            ConsolePortConfig::parse("file=/tmp/agent.log,id=agent,pci_segment=1")?,
+            ConsolePortConfig {
+                mode: ConsoleOutputMode::File,
+                file: Some(PathBuf::from("/tmp/agent.log")),
+                socket: None,
+                tcp: None,
+                iommu: false,
+                id: Some("agent".to_owned()),
+                pci_segment: 1,
+            }
+        );
+        assert_eq!(
+            ConsolePortConfig::parse("socket=/tmp/agent.sock,id=agent")?,
+            ConsolePortConfig {
+                mode: ConsoleOutputMode::Socket,
+                file: None,
+                socket: Some(PathBuf::from("/tmp/agent.sock")),
+                tcp: None,
+                iommu: false,
+                id: Some("agent".to_owned()),
+                pci_segment: 0,
+            }
+        );
+        assert_eq!(
+            ConsolePortConfig::parse("tcp=[::1]:4444,id=agent")?,
+            ConsolePortConfig {
+                mode: ConsoleOutputMode::Tcp,
+                file: None,
+                socket: None,
+                tcp: Some("[::1]:4444".parse().unwrap()),
+                iommu: false,
+                id: Some("agent".to_owned()),
+                pci_segment: 0,
+            }
+        );
+        assert_eq!(
+            ConsolePortConfig::parse("pipe=/tmp/agent.fifo")?,
+            ConsolePortConfig {
+                mode: ConsoleOutputMode::Pipe,
+                file: Some(PathBuf::from("/tmp/agent.fifo")),
+                socket: None,
+                tcp: None,
+                iommu: false,
+                id: None,
+                pci_segment: 0,
+            }
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_tpm_parsing() -> Result<()> {
         // path is required
@@ -3769,6 +5645,135 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_restart_policy_parsing() -> Result<()> {
+        assert_eq!(
+            RestartPolicyConfig::parse("")?,
+            RestartPolicyConfig {
+                policy: RestartPolicy::OnFailure,
+                max_reboots: None,
+                window: DEFAULT_RESTART_POLICY_WINDOW,
+            }
+        );
+        assert_eq!(
+            RestartPolicyConfig::parse("policy=always,max_reboots=3,window=120")?,
+            RestartPolicyConfig {
+                policy: RestartPolicy::Always,
+                max_reboots: Some(3),
+                window: 120,
+            }
+        );
+        assert_eq!(
+            RestartPolicyConfig::parse("policy=never")?.policy,
+            RestartPolicy::Never
+        );
+        assert!(RestartPolicyConfig::parse("policy=sometimes").is_err());
+        assert!(RestartPolicyConfig::parse("max_reboots=-1").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "mdns")]
+    #[test]
+    fn test_mdns_parsing() -> Result<()> {
+        assert!(MdnsConfig::parse("").is_err());
+        assert!(MdnsConfig::parse("hostname=vm").is_err());
+        assert!(MdnsConfig::parse("hostname=vm,ip=192.168.249").is_err());
+        let mdns = MdnsConfig::parse("hostname=dev-vm,ip=192.168.249.2")?;
+        assert_eq!(
+            mdns,
+            MdnsConfig {
+                hostname: "dev-vm".to_owned(),
+                ip: Ipv4Addr::new(192, 168, 249, 2),
+            }
+        );
+        assert!(mdns.validate().is_ok());
+        assert!(MdnsConfig::parse("hostname=vm.local,ip=192.168.249.2")?
+            .validate()
+            .is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "sev_snp")]
+    #[test]
+    fn test_sev_snp_parsing() -> Result<()> {
+        assert_eq!(SevSnpConfig::parse("")?, SevSnpConfig::default());
+        assert!(SevSnpConfig::parse("policy=0x3zz00").is_err());
+        assert_eq!(
+            SevSnpConfig::parse("policy=0x30000,host_data_file=/tmp/host_data")?,
+            SevSnpConfig {
+                policy: Some(0x30000),
+                host_data_file: Some(PathBuf::from("/tmp/host_data")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            SevSnpConfig::parse("policy=196608,id_block_key=/tmp/id.pem")?,
+            SevSnpConfig {
+                policy: Some(0x30000),
+                id_block_key: Some(PathBuf::from("/tmp/id.pem")),
+                ..Default::default()
+            }
+        );
+
+        let host_data = "243eb7dc1a21129caa91dcbb794922b933baecb5823a377eb431188673288c07";
+        assert!(SevSnpConfig::parse(&format!("host_data={host_data}"))?
+            .validate()
+            .is_ok());
+        assert_eq!(
+            SevSnpConfig::parse(&format!("host_data={host_data},host_data_file=/tmp/hd"))?
+                .validate(),
+            Err(ValidationError::SevSnpHostDataConflict)
+        );
+        assert_eq!(
+            SevSnpConfig::parse(&format!("host_data={}", &host_data[..62]))?.validate(),
+            Err(ValidationError::InvalidSevSnpHostData)
+        );
+        assert_eq!(
+            SevSnpConfig::parse("policy=0x10000")?.validate(),
+            Err(ValidationError::InvalidSevSnpPolicy(0x10000))
+        );
+        assert_eq!(
+            SevSnpConfig::parse("policy=0x2030000")?.validate(),
+            Err(ValidationError::InvalidSevSnpPolicy(0x2030000))
+        );
+        assert_eq!(
+            SevSnpConfig::parse("author_key=/tmp/author.pem")?.validate(),
+            Err(ValidationError::SevSnpAuthorKeyWithoutIdBlockKey)
+        );
+        assert_eq!(
+            SevSnpConfig::parse("id_block_key=/tmp/id.pem")?.validate(),
+            Err(ValidationError::SevSnpIdBlockKeyWithoutPolicy)
+        );
+        assert_eq!(
+            SevSnpConfig::parse("svsm=/tmp/svsm.igvm")?,
+            SevSnpConfig {
+                svsm: Some(PathBuf::from("/tmp/svsm.igvm")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            SevSnpConfig::parse("policy=0x30000,id_block_key=/tmp/id.pem,svsm=/tmp/svsm.igvm")?
+                .validate(),
+            Err(ValidationError::SevSnpSvsmWithIdBlockKey)
+        );
+        assert_eq!(
+            SevSnpConfig::parse("lazy_unmeasured=on")?,
+            SevSnpConfig {
+                lazy_unmeasured: true,
+                ..Default::default()
+            }
+        );
+        assert!(SevSnpConfig::parse("lazy_unmeasured=maybe").is_err());
+        assert_eq!(
+            SevSnpConfig::parse("certificates=/tmp/certs.bin")?,
+            SevSnpConfig {
+                certificates: Some(PathBuf::from("/tmp/certs.bin")),
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_vsock_parsing() -> Result<()> {
         // socket and cid is required
@@ -3781,6 +5786,7 @@ mod tests {
                 iommu: false,
                 id: None,
                 pci_segment: 0,
+                forwards: Vec::new(),
             }
         );
         assert_eq!(
@@ -3791,6 +5797,70 @@ mod tests {
                 iommu: true,
                 id: None,
                 pci_segment: 0,
+                forwards: Vec::new(),
+            }
+        );
+        assert_eq!(
+            VsockConfig::parse(
+                "socket=/tmp/sock,cid=3,connect=[1234@127.0.0.1:8080],listen=[22@[::1]:2222,80@0.0.0.0:8000]"
+            )?
+            .forwards,
+            vec![
+                VsockForwardConfig {
+                    port: 1234,
+                    address: "127.0.0.1:8080".parse().unwrap(),
+                    direction: VsockForwardDirection::GuestToHost,
+                },
+                VsockForwardConfig {
+                    port: 22,
+                    address: "[::1]:2222".parse().unwrap(),
+                    direction: VsockForwardDirection::HostToGuest,
+                },
+                VsockForwardConfig {
+                    port: 80,
+                    address: "0.0.0.0:8000".parse().unwrap(),
+                    direction: VsockForwardDirection::HostToGuest,
+                },
+            ]
+        );
+        assert!(VsockConfig::parse("socket=/tmp/sock,cid=3,connect=[1234@localhost]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gpu_parsing() -> Result<()> {
+        // socket is required
+        assert!(GpuConfig::parse("").is_err());
+        assert_eq!(
+            GpuConfig::parse("socket=/tmp/gpu.sock")?,
+            GpuConfig {
+                socket: PathBuf::from("/tmp/gpu.sock"),
+                queue_size: 256,
+                id: None,
+                pci_segment: 0,
+            }
+        );
+        assert_eq!(
+            GpuConfig::parse("socket=/tmp/gpu.sock,queue_size=1024,id=mygpu,pci_segment=1")?,
+            GpuConfig {
+                socket: PathBuf::from("/tmp/gpu.sock"),
+                queue_size: 1024,
+                id: Some("mygpu".to_owned()),
+                pci_segment: 1,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_fdt_overlay_parsing() -> Result<()> {
+        // path is required
+        assert!(FdtOverlayConfig::parse("").is_err());
+        assert_eq!(
+            FdtOverlayConfig::parse("path=/tmp/overlay.dtbo")?,
+            FdtOverlayConfig {
+                path: PathBuf::from("/tmp/overlay.dtbo"),
             }
         );
         Ok(())
@@ -3803,7 +5873,32 @@ mod tests {
             RestoreConfig {
                 source_url: PathBuf::from("/path/to/snapshot"),
                 prefault: false,
+                lazy: false,
+                clone: false,
+                net_fds: None,
+                asynchronous: false,
+            }
+        );
+        assert_eq!(
+            RestoreConfig::parse("source_url=/path/to/snapshot,lazy=on")?,
+            RestoreConfig {
+                source_url: PathBuf::from("/path/to/snapshot"),
+                prefault: false,
+                lazy: true,
+                clone: false,
+                net_fds: None,
+                asynchronous: false,
+            }
+        );
+        assert_eq!(
+            RestoreConfig::parse("source_url=/path/to/snapshot,clone=on")?,
+            RestoreConfig {
+                source_url: PathBuf::from("/path/to/snapshot"),
+                prefault: false,
+                lazy: false,
+                clone: true,
                 net_fds: None,
+                asynchronous: false,
             }
         );
         assert_eq!(
@@ -3813,6 +5908,8 @@ mod tests {
             RestoreConfig {
                 source_url: PathBuf::from("/path/to/snapshot"),
                 prefault: false,
+                lazy: false,
+                clone: false,
                 net_fds: Some(vec![
                     RestoredNetConfig {
                         id: "net0".to_string(),
@@ -3825,6 +5922,7 @@ mod tests {
                         fds: Some(vec![5, 6, 7, 8]),
                     }
                 ]),
+                asynchronous: false,
             }
         );
         // Parsing should fail as source_url is a required field
@@ -3844,9 +5942,11 @@ mod tests {
             rng: RngConfig::default(),
             balloon: None,
             fs: None,
+            gpu: None,
             pmem: None,
             serial: default_serial(),
             console: default_console(),
+            console_ports: None,
             #[cfg(target_arch = "x86_64")]
             debug_console: DebugConsoleConfig::default(),
             devices: None,
@@ -3859,13 +5959,20 @@ mod tests {
             iommu: false,
             #[cfg(target_arch = "x86_64")]
             sgx_epc: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays: None,
             numa: None,
             watchdog: false,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             pci_segments: None,
             platform: None,
+            #[cfg(feature = "sev_snp")]
+            sev_snp: None,
             tpm: None,
+            restart_policy: None,
+            #[cfg(feature = "mdns")]
+            mdns: None,
             preserved_fds: None,
             net: Some(vec![
                 NetConfig {
@@ -3893,6 +6000,8 @@ mod tests {
         let valid_config = RestoreConfig {
             source_url: PathBuf::from("/path/to/snapshot"),
             prefault: false,
+            lazy: false,
+            clone: false,
             net_fds: Some(vec![
                 RestoredNetConfig {
                     id: "net0".to_string(),
@@ -3905,6 +6014,7 @@ mod tests {
                     fds: Some(vec![7, 8]),
                 },
             ]),
+            asynchronous: false,
         };
         assert!(valid_config.validate(&snapshot_vm_config).is_ok());
 
@@ -3967,7 +6077,10 @@ mod tests {
         let another_valid_config = RestoreConfig {
             source_url: PathBuf::from("/path/to/snapshot"),
             prefault: false,
+            lazy: false,
+            clone: false,
             net_fds: None,
+            asynchronous: false,
         };
         snapshot_vm_config.net = Some(vec![NetConfig {
             id: Some("net2".to_owned()),
@@ -3975,6 +6088,40 @@ mod tests {
             ..net_fixture()
         }]);
         assert!(another_valid_config.validate(&snapshot_vm_config).is_ok());
+
+        let mut lazy_config = another_valid_config.clone();
+        lazy_config.lazy = true;
+        assert!(lazy_config.validate(&snapshot_vm_config).is_ok());
+
+        lazy_config.prefault = true;
+        assert_eq!(
+            lazy_config.validate(&snapshot_vm_config),
+            Err(ValidationError::LazyRestorePrefault)
+        );
+
+        lazy_config.prefault = false;
+        snapshot_vm_config.memory.shared = true;
+        assert_eq!(
+            lazy_config.validate(&snapshot_vm_config),
+            Err(ValidationError::LazyRestoreSharedMemory)
+        );
+
+        let mut clone_config = another_valid_config.clone();
+        clone_config.clone = true;
+        assert!(clone_config.validate(&snapshot_vm_config).is_ok());
+
+        clone_config.lazy = true;
+        assert_eq!(
+            clone_config.validate(&snapshot_vm_config),
+            Err(ValidationError::CloneRestoreLazy)
+        );
+
+        clone_config.lazy = false;
+        clone_config.prefault = true;
+        assert_eq!(
+            clone_config.validate(&snapshot_vm_config),
+            Err(ValidationError::CloneRestorePrefault)
+        );
     }
 
     fn platform_fixture() -> PlatformConfig {
@@ -3984,13 +6131,88 @@ mod tests {
             serial_number: None,
             uuid: None,
             oem_strings: None,
+            kbs_uri: None,
+            kbc: None,
+            on_reset_loop: OnResetLoop::Report,
+            vfio_quirks: None,
+            vmgenid: false,
             #[cfg(feature = "tdx")]
             tdx: false,
+            #[cfg(feature = "tdx")]
+            migtd_pid: None,
+            #[cfg(feature = "tdx")]
+            quote_generation: false,
+            #[cfg(feature = "tdx")]
+            qgs_socket: None,
             #[cfg(feature = "sev_snp")]
             sev_snp: false,
+            #[cfg(feature = "sev_snp")]
+            sev_es: false,
+            #[cfg(feature = "sev_snp")]
+            kbs_host_data: None,
+            #[cfg(feature = "sev_snp")]
+            snp_cpuid: SnpCpuidMode::Template,
         }
     }
 
+    #[test]
+    fn test_platform_parsing() -> Result<()> {
+        assert_eq!(
+            PlatformConfig::parse("num_pci_segments=96")?,
+            platform_fixture()
+        );
+        assert_eq!(
+            PlatformConfig::parse("num_pci_segments=96,on_reset_loop=pause")?,
+            PlatformConfig {
+                on_reset_loop: OnResetLoop::Pause,
+                ..platform_fixture()
+            }
+        );
+        assert!(PlatformConfig::parse("on_reset_loop=reboot").is_err());
+        #[cfg(feature = "sev_snp")]
+        {
+            assert_eq!(
+                PlatformConfig::parse("num_pci_segments=96,sev_snp=on,snp_cpuid=passthrough")?,
+                PlatformConfig {
+                    sev_snp: true,
+                    snp_cpuid: SnpCpuidMode::Passthrough,
+                    ..platform_fixture()
+                }
+            );
+            assert!(PlatformConfig::parse("snp_cpuid=host").is_err());
+        }
+        #[cfg(feature = "tdx")]
+        assert_eq!(
+            PlatformConfig::parse(
+                "num_pci_segments=96,tdx=on,quote_generation=on,qgs_socket=/tmp/qgs.socket"
+            )?,
+            PlatformConfig {
+                tdx: true,
+                quote_generation: true,
+                qgs_socket: Some(PathBuf::from("/tmp/qgs.socket")),
+                ..platform_fixture()
+            }
+        );
+        assert_eq!(
+            PlatformConfig::parse(
+                "num_pci_segments=96,vfio_quirks=/etc/cloud-hypervisor/vfio-quirks.toml"
+            )?,
+            PlatformConfig {
+                vfio_quirks: Some(PathBuf::from("/etc/cloud-hypervisor/vfio-quirks.toml")),
+                ..platform_fixture()
+            }
+        );
+        assert_eq!(
+            PlatformConfig::parse("num_pci_segments=96,vmgenid=on")?,
+            PlatformConfig {
+                vmgenid: true,
+                ..platform_fixture()
+            }
+        );
+
+        Ok(())
+    }
+
     fn numa_fixture() -> NumaConfig {
         NumaConfig {
             guest_numa_id: 0,
@@ -4023,6 +6245,8 @@ mod tests {
                 prefault: false,
                 zones: None,
                 thp: true,
+                auto_numa: false,
+                coredump: false,
             },
             payload: Some(PayloadConfig {
                 kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -4031,6 +6255,8 @@ mod tests {
                 initramfs: None,
                 #[cfg(feature = "igvm")]
                 igvm: None,
+                #[cfg(feature = "igvm")]
+                igvm_compatibility_mask: None,
                 #[cfg(feature = "sev_snp")]
                 host_data: Some(
                     "243eb7dc1a21129caa91dcbb794922b933baecb5823a377eb431188673288c07".to_string(),
@@ -4045,19 +6271,25 @@ mod tests {
             },
             balloon: None,
             fs: None,
+            gpu: None,
             pmem: None,
             serial: ConsoleConfig {
                 file: None,
                 mode: ConsoleOutputMode::Null,
                 iommu: false,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             },
             console: ConsoleConfig {
                 file: None,
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             },
+            console_ports: None,
             #[cfg(target_arch = "x86_64")]
             debug_console: DebugConsoleConfig::default(),
             devices: None,
@@ -4070,13 +6302,20 @@ mod tests {
             iommu: false,
             #[cfg(target_arch = "x86_64")]
             sgx_epc: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays: None,
             numa: None,
             watchdog: false,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             pci_segments: None,
             platform: None,
+            #[cfg(feature = "sev_snp")]
+            sev_snp: None,
             tpm: None,
+            restart_policy: None,
+            #[cfg(feature = "mdns")]
+            mdns: None,
             preserved_fds: None,
             landlock_enable: false,
             landlock_rules: None,
@@ -4104,6 +6343,35 @@ mod tests {
             Err(ValidationError::ConsoleFileMissing)
         );
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.debug_console.mode = ConsoleOutputMode::File;
+            invalid_config.debug_console.file = None;
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::DebugconFileMissing)
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.debug_console.mode = ConsoleOutputMode::Tcp;
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::DebugconSocketUnsupported)
+            );
+        }
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.console.log_socket = Some(PathBuf::from("/run/shipper.sock"));
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ConsoleLogSocketUnsupported)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.serial.log_socket = Some(PathBuf::from("/run/shipper.sock"));
+        assert!(still_valid_config.validate().is_ok());
+
         let mut invalid_config = valid_config.clone();
         invalid_config.cpus.max_vcpus = 16;
         invalid_config.cpus.boot_vcpus = 32;
@@ -4112,6 +6380,23 @@ mod tests {
             Err(ValidationError::CpusMaxLowerThanBoot)
         );
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.cpus.max_vcpus = MAX_SUPPORTED_VCPUS;
+        still_valid_config.cpus.topology = Some(CpuTopology {
+            threads_per_core: 2,
+            cores_per_die: 128,
+            dies_per_package: 1,
+            packages: 16,
+        });
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.max_vcpus = MAX_SUPPORTED_VCPUS + 1;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::CpusMaxTooHigh(MAX_SUPPORTED_VCPUS + 1))
+        );
+
         let mut invalid_config = valid_config.clone();
         invalid_config.cpus.max_vcpus = 16;
         invalid_config.cpus.boot_vcpus = 16;
@@ -4137,6 +6422,158 @@ mod tests {
             Err(ValidationError::DiskSocketAndPath)
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: None,
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            key_file: Some(PathBuf::from("/path/to/key")),
+            ..disk_fixture()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserDiskEncryption)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            key_file: Some(PathBuf::from("/path/to/key")),
+            key_resource: Some("default/key/disk0".to_owned()),
+            ..disk_fixture()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DiskKeyFileAndResource)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            key_resource: Some("default/key/disk0".to_owned()),
+            ..disk_fixture()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::KbsNotConfigured)
+        );
+
+        let mut still_valid_config = invalid_config.clone();
+        still_valid_config.platform = Some(PlatformConfig {
+            kbs_uri: Some("https://kbs.example:8080".to_owned()),
+            kbc: Some(PathBuf::from("/run/kbc.sock")),
+            ..platform_fixture()
+        });
+        still_valid_config.validate().unwrap();
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.platform.as_mut().unwrap().kbc = None;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::KbsNotConfigured)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: None,
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            on_io_error: OnIoError::Pause,
+            ..disk_fixture()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserIoErrorPolicy)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            io_timeout: Some(0),
+            ..disk_fixture()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidIoTimeout)
+        );
+
+        let http_disk = DiskConfig {
+            path: Some(PathBuf::from("http://images.example/disk.raw")),
+            readonly: true,
+            cache: Some(PathBuf::from("/var/cache/disk.raw")),
+            ..disk_fixture()
+        };
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.disks = Some(vec![http_disk.clone()]);
+        still_valid_config.validate().unwrap();
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            readonly: false,
+            ..http_disk.clone()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::HttpDiskNotReadonly)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            image_type: Some(block::ImageType::Qcow2),
+            ..http_disk.clone()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::HttpDiskUnsupportedOption)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            cache: None,
+            ..http_disk
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidDiskCache)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            cache: Some(PathBuf::from("/var/cache/disk.raw")),
+            ..disk_fixture()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidDiskCache)
+        );
+
+        let transient_disk = DiskConfig {
+            transient: true,
+            ..disk_fixture()
+        };
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.disks = Some(vec![transient_disk.clone()]);
+        still_valid_config.validate().unwrap();
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            readonly: true,
+            ..transient_disk.clone()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::TransientDiskUnsupportedOption)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            image_type: Some(block::ImageType::Vhdx),
+            ..transient_disk
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::TransientDiskUnsupportedOption)
+        );
+
         let mut invalid_config = valid_config.clone();
         invalid_config.memory.shared = true;
         invalid_config.disks = Some(vec![DiskConfig {
@@ -4217,6 +6654,37 @@ mod tests {
             Err(ValidationError::VhostUserRequiresSharedMemory)
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.gpu = Some(GpuConfig::parse("socket=/tmp/gpu.sock").unwrap());
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserRequiresSharedMemory)
+        );
+
+        let mut still_valid_config = invalid_config.clone();
+        still_valid_config.memory.shared = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.fs = Some(vec![FsConfig {
+            protocol: FsProtocol::P9,
+            ..fs_fixture()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.boot_vcpus = 2;
+        invalid_config.cpus.max_vcpus = 2;
+        invalid_config.fs = Some(vec![FsConfig {
+            protocol: FsProtocol::P9,
+            num_queues: 2,
+            ..fs_fixture()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::Fs9pMultipleQueues)
+        );
+
         let mut still_valid_config = valid_config.clone();
         still_valid_config.memory.shared = true;
         assert!(still_valid_config.validate().is_ok());
@@ -4250,6 +6718,40 @@ mod tests {
         still_valid_config.platform = Some(platform_fixture());
         assert!(still_valid_config.validate().is_ok());
 
+        #[cfg(feature = "tdx")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.platform = Some(PlatformConfig {
+                migtd_pid: Some(1234),
+                ..platform_fixture()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::MigTdWithoutTdx)
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.platform = Some(PlatformConfig {
+                quote_generation: true,
+                ..platform_fixture()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::QuoteGenerationWithoutTdx)
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.platform = Some(PlatformConfig {
+                tdx: true,
+                qgs_socket: Some(PathBuf::from("/tmp/qgs.socket")),
+                ..platform_fixture()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::QgsSocketWithoutQuoteGeneration)
+            );
+        }
+
         let mut invalid_config = valid_config.clone();
         invalid_config.platform = Some(PlatformConfig {
             num_pci_segments: MAX_NUM_PCI_SEGMENTS + 1,
@@ -4338,6 +6840,7 @@ mod tests {
             id: None,
             iommu: true,
             pci_segment: 1,
+            forwards: Vec::new(),
         });
         assert!(still_valid_config.validate().is_ok());
 
@@ -4414,12 +6917,34 @@ mod tests {
             id: None,
             iommu: false,
             pci_segment: 1,
+            forwards: Vec::new(),
         });
         assert_eq!(
             invalid_config.validate(),
             Err(ValidationError::OnIommuSegment(1))
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.vsock = Some(
+            VsockConfig::parse(
+                "socket=/tmp/sock,cid=3,connect=[1234@127.0.0.1:8080,1234@127.0.0.1:8081]",
+            )
+            .unwrap(),
+        );
+        assert!(matches!(
+            invalid_config.validate(),
+            Err(ValidationError::DuplicateVsockForward(_))
+        ));
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.vsock = Some(
+            VsockConfig::parse(
+                "socket=/tmp/sock,cid=3,connect=[1234@127.0.0.1:8080],listen=[1234@127.0.0.1:8080]",
+            )
+            .unwrap(),
+        );
+        assert!(still_valid_config.validate().is_ok());
+
         let mut invalid_config = valid_config.clone();
         invalid_config.memory.shared = true;
         invalid_config.platform = Some(PlatformConfig {
@@ -4590,6 +7115,8 @@ mod tests {
                 initramfs: None,
                 #[cfg(feature = "igvm")]
                 igvm: None,
+                #[cfg(feature = "igvm")]
+                igvm_compatibility_mask: None,
                 #[cfg(feature = "sev_snp")]
                 host_data: Some("".to_string()),
             });
@@ -4604,6 +7131,8 @@ mod tests {
                 initramfs: None,
                 #[cfg(feature = "igvm")]
                 igvm: None,
+                #[cfg(feature = "igvm")]
+                igvm_compatibility_mask: None,
                 #[cfg(feature = "sev_snp")]
                 host_data: None,
             });
@@ -4618,12 +7147,120 @@ mod tests {
                 initramfs: None,
                 #[cfg(feature = "igvm")]
                 igvm: None,
+                #[cfg(feature = "igvm")]
+                igvm_compatibility_mask: None,
                 #[cfg(feature = "sev_snp")]
                 host_data: Some(
                     "243eb7dc1a21129caa91dcbb794922b933baecb5823a377eb43118867328".to_string(),
                 ),
             });
             assert!(config_with_invalid_host_data.validate().is_err());
+
+            // SEV-SNP parameters
+            let mut invalid_config = valid_config.clone();
+            invalid_config.sev_snp = Some(SevSnpConfig {
+                policy: Some(0x30000),
+                ..Default::default()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::SevSnpNotEnabled)
+            );
+            invalid_config.platform = Some(PlatformConfig {
+                sev_snp: true,
+                ..platform_fixture()
+            });
+            assert!(invalid_config.validate().is_ok());
+            invalid_config.sev_snp.as_mut().unwrap().host_data_file =
+                Some(PathBuf::from("/tmp/host_data"));
+            assert!(invalid_config.validate().is_ok());
+            invalid_config.payload.as_mut().unwrap().host_data = Some(
+                "243eb7dc1a21129caa91dcbb794922b933baecb5823a377eb431188673288c07".to_string(),
+            );
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::SevSnpHostDataConflict)
+            );
+            invalid_config.payload.as_mut().unwrap().host_data = None;
+            invalid_config.sev_snp.as_mut().unwrap().svsm = Some(PathBuf::from("/tmp/svsm.igvm"));
+            assert!(invalid_config.validate().is_ok());
+            invalid_config.tpm = Some(TpmConfig {
+                socket: PathBuf::from("/tmp/swtpm.sock"),
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::SevSnpSvsmWithTpm)
+            );
+
+            // Memory hotplug, through ACPI or virtio-mem
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.platform = Some(PlatformConfig {
+                sev_snp: true,
+                ..platform_fixture()
+            });
+            still_valid_config.memory.hotplug_size = Some(1 << 30);
+            assert!(still_valid_config.validate().is_ok());
+            let mut virtio_mem_config = still_valid_config.clone();
+            virtio_mem_config.memory.hotplug_method = HotplugMethod::VirtioMem;
+            assert!(virtio_mem_config.validate().is_ok());
+
+            // Prefaulting the memory of a confidential guest
+            let mut invalid_config = still_valid_config.clone();
+            invalid_config.memory.prefault = true;
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::ConfidentialPrefault)
+            );
+
+            // SEV-ES
+            let mut invalid_config = valid_config.clone();
+            invalid_config.platform = Some(PlatformConfig {
+                sev_es: true,
+                ..platform_fixture()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::SevEsFirmwareMissing)
+            );
+            let mut still_valid_config = invalid_config.clone();
+            still_valid_config.payload = Some(PayloadConfig {
+                firmware: Some(PathBuf::from("/path/to/firmware")),
+                kernel: None,
+                cmdline: None,
+                initramfs: None,
+                #[cfg(feature = "igvm")]
+                igvm: None,
+                #[cfg(feature = "igvm")]
+                igvm_compatibility_mask: None,
+                host_data: None,
+            });
+            assert!(still_valid_config.validate().is_ok());
+            let mut invalid_config = still_valid_config.clone();
+            invalid_config.platform.as_mut().unwrap().sev_snp = true;
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::SevEsWithSevSnp)
+            );
+        }
+
+        #[cfg(feature = "igvm")]
+        {
+            let payload = valid_config.payload.clone().unwrap();
+            let mut invalid_config = valid_config.clone();
+            invalid_config.payload = Some(PayloadConfig {
+                igvm_compatibility_mask: Some(0x3),
+                ..payload.clone()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidIgvmCompatibilityMask(0x3))
+            );
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.payload = Some(PayloadConfig {
+                igvm_compatibility_mask: Some(0x2),
+                ..payload
+            });
+            assert!(still_valid_config.validate().is_ok());
         }
 
         let mut still_valid_config = valid_config;
@@ -4637,6 +7274,19 @@ mod tests {
         }
         let _still_valid_config = still_valid_config.clone();
     }
+    #[test]
+    fn test_validation_error_field() {
+        assert_eq!(
+            ValidationError::CpusMaxLowerThanBoot.field(),
+            Some("cpus.max_vcpus")
+        );
+        assert_eq!(ValidationError::InvalidMtu(64).field(), Some("net"));
+        assert_eq!(
+            ValidationError::IdentifierNotUnique("_disk0".to_owned()).field(),
+            None
+        );
+    }
+
     #[test]
     fn test_landlock_parsing() -> Result<()> {
         // should not be empty
@@ -0,0 +1,295 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Checks of a VM configuration against the host, without creating the VM.
+//!
+//! Unlike [`VmConfig::validate`], which stops at the first inconsistency of
+//! the configuration itself, the checks run to completion and report every
+//! problem found: missing or inaccessible files, hugepages the pool can't
+//! provide, VFIO devices without a usable IOMMU group and limits of the
+//! hypervisor.
+
+use crate::config::VmConfig;
+use crate::hugetlbfs;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+/// A problem preventing the VM from being created or booted on this host.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// Path of the option at fault in the configuration, e.g. "disks"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    fn new(field: Option<&str>, message: String) -> Self {
+        ConfigProblem {
+            field: field.map(|f| f.to_owned()),
+            message,
+        }
+    }
+}
+
+fn check_file(problems: &mut Vec<ConfigProblem>, field: &str, path: &Path, write: bool) {
+    if let Err(e) = OpenOptions::new().read(true).write(write).open(path) {
+        problems.push(ConfigProblem::new(
+            Some(field),
+            format!(
+                "Cannot open {} for {}: {e}",
+                path.display(),
+                if write { "writing" } else { "reading" }
+            ),
+        ));
+    }
+}
+
+fn check_files(config: &VmConfig, problems: &mut Vec<ConfigProblem>) {
+    if let Some(payload) = &config.payload {
+        for (field, path) in [
+            ("payload.firmware", &payload.firmware),
+            ("payload.kernel", &payload.kernel),
+            ("payload.initramfs", &payload.initramfs),
+            #[cfg(feature = "igvm")]
+            ("payload.igvm", &payload.igvm),
+        ] {
+            if let Some(path) = path {
+                check_file(problems, field, path, false);
+            }
+        }
+    }
+
+    for disk in config.disks.iter().flatten() {
+        if disk.vhost_user {
+            continue;
+        }
+        if disk.is_http() {
+            // The cache file is created by the VMM when missing.
+            if let Some(cache) = disk.cache.as_ref().filter(|cache| cache.exists()) {
+                check_file(problems, "disks", cache, true);
+            }
+        } else if let Some(path) = &disk.path {
+            check_file(problems, "disks", path, !disk.readonly);
+        }
+        if let Some(key_file) = &disk.key_file {
+            check_file(problems, "disks", key_file, false);
+        }
+    }
+
+    for pmem in config.pmem.iter().flatten() {
+        // The file is created by the VMM when a size is given.
+        if pmem.size.is_none() || pmem.file.exists() {
+            check_file(problems, "pmem", &pmem.file, !pmem.discard_writes);
+        }
+    }
+
+    for zone in config.memory.zones.iter().flatten() {
+        if let Some(file) = &zone.file {
+            if !file.exists() {
+                problems.push(ConfigProblem::new(
+                    Some("memory.zones"),
+                    format!("Memory zone {} file {} not found", zone.id, file.display()),
+                ));
+            }
+        }
+    }
+}
+
+fn check_hugepages(config: &VmConfig, problems: &mut Vec<ConfigProblem>) {
+    let mut requests = Vec::new();
+    if config.memory.hugepages && config.memory.size > 0 {
+        requests.push(("memory", config.memory.size, config.memory.hugepage_size));
+    }
+    for zone in config.memory.zones.iter().flatten() {
+        if zone.hugepages {
+            requests.push(("memory.zones", zone.size, zone.hugepage_size));
+        }
+    }
+
+    for (field, size, page_size) in requests {
+        let result = match page_size {
+            Some(page_size) => Ok(page_size),
+            None => hugetlbfs::default_page_size(),
+        }
+        .and_then(|page_size| hugetlbfs::check_pool(size, page_size));
+
+        if let Err(e) = result {
+            problems.push(ConfigProblem::new(Some(field), e.to_string()));
+        }
+    }
+}
+
+fn check_iommu_groups(config: &VmConfig, problems: &mut Vec<ConfigProblem>) {
+    for device in config.devices.iter().flatten() {
+        let group = match fs::read_link(device.path.join("iommu_group")) {
+            Ok(group) => group,
+            Err(e) => {
+                problems.push(ConfigProblem::new(
+                    Some("devices"),
+                    format!("No IOMMU group for {}: {e}", device.path.display()),
+                ));
+                continue;
+            }
+        };
+
+        if let Some(group) = group.file_name() {
+            check_file(
+                problems,
+                "devices",
+                &Path::new("/dev/vfio").join(group),
+                true,
+            );
+        }
+    }
+}
+
+fn check_vcpus(
+    config: &VmConfig,
+    max_vcpus: u32,
+    #[cfg(target_arch = "x86_64")] x2apic_api: bool,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    if config.cpus.max_vcpus > max_vcpus {
+        problems.push(ConfigProblem::new(
+            Some("cpus.max_vcpus"),
+            format!(
+                "{} vCPUs requested, the hypervisor supports {max_vcpus}",
+                config.cpus.max_vcpus
+            ),
+        ));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    if !x2apic_api && crate::cpu::x2apic_ids_required(&config.cpus) {
+        problems.push(ConfigProblem::new(
+            Some("cpus.max_vcpus"),
+            format!(
+                "{} vCPUs need APIC IDs beyond {}, which the hypervisor doesn't support",
+                config.cpus.max_vcpus,
+                arch::x86_64::MAX_XAPIC_ID
+            ),
+        ));
+    }
+}
+
+fn check_host(config: &VmConfig) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+
+    // The static validation enables the virtio-iommu when needed, which must
+    // not leak into the checked configuration.
+    if let Err(e) = config.clone().validate() {
+        problems.push(ConfigProblem::new(e.field(), e.to_string()));
+    }
+
+    check_files(config, &mut problems);
+    check_hugepages(config, &mut problems);
+    check_iommu_groups(config, &mut problems);
+
+    problems
+}
+
+/// Every problem found with `config` on this host, none if the VM can be
+/// created.
+pub fn check(config: &VmConfig, hypervisor: &dyn hypervisor::Hypervisor) -> Vec<ConfigProblem> {
+    let mut problems = check_host(config);
+
+    if let Err(e) = hypervisor.check_required_extensions() {
+        problems.push(ConfigProblem::new(
+            None,
+            format!("Missing hypervisor capabilities: {e}"),
+        ));
+    }
+
+    #[cfg(feature = "tdx")]
+    if config
+        .platform
+        .as_ref()
+        .is_some_and(|p| p.migtd_pid.is_some())
+        && !hypervisor.tdx_migration_supported()
+    {
+        problems.push(ConfigProblem::new(
+            Some("platform.migtd_pid"),
+            "TD live migration is not supported by the hypervisor".to_owned(),
+        ));
+    }
+
+    check_vcpus(
+        config,
+        hypervisor.get_max_vcpus(),
+        #[cfg(target_arch = "x86_64")]
+        hypervisor.x2apic_api_supported(),
+        &mut problems,
+    );
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_check_host_reports_all_problems() {
+        let disk = TempFile::new().unwrap();
+        let config: VmConfig = serde_json::from_str(&format!(
+            r#"{{
+                "cpus": {{"boot_vcpus": 2, "max_vcpus": 1}},
+                "payload": {{"kernel": "/nonexistent/vmlinux"}},
+                "disks": [
+                    {{"path": "{}"}},
+                    {{"path": "/nonexistent/disk.raw"}}
+                ],
+                "devices": [{{"path": "/nonexistent/0000:00:01.0"}}]
+            }}"#,
+            disk.as_path().display()
+        ))
+        .unwrap();
+
+        let fields: Vec<Option<String>> = check_host(&config)
+            .into_iter()
+            .map(|problem| problem.field)
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                Some("cpus.max_vcpus".to_owned()),
+                Some("payload.kernel".to_owned()),
+                Some("disks".to_owned()),
+                Some("devices".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_check_vcpus_x2apic() {
+        let config = |vcpus: u32| -> VmConfig {
+            serde_json::from_str(&format!(
+                r#"{{"cpus": {{"boot_vcpus": {vcpus}, "max_vcpus": {vcpus}}}}}"#
+            ))
+            .unwrap()
+        };
+
+        // 255 vCPUs fit in the xAPIC range.
+        let mut problems = Vec::new();
+        check_vcpus(&config(255), 256, false, &mut problems);
+        assert!(problems.is_empty());
+
+        // More need 32-bit APIC IDs, whatever the hypervisor vCPU limit.
+        check_vcpus(&config(256), 256, false, &mut problems);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].field.as_deref(), Some("cpus.max_vcpus"));
+
+        let mut problems = Vec::new();
+        check_vcpus(&config(1024), 4096, true, &mut problems);
+        assert!(problems.is_empty());
+
+        check_vcpus(&config(1024), 512, true, &mut problems);
+        assert_eq!(problems.len(), 1);
+    }
+}
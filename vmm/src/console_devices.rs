@@ -11,7 +11,9 @@
 //
 
 use crate::sigwinch_listener::listen_for_sigwinch_on_tty;
+use crate::vm_config::pipe_pair;
 use crate::vm_config::ConsoleOutputMode;
+use crate::vm_config::ConsolePortConfig;
 use crate::Vmm;
 use libc::cfmakeraw;
 use libc::isatty;
@@ -23,20 +25,23 @@ use std::fs::read_link;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
-#[cfg(target_arch = "x86_64")]
-use std::io::stdout;
 use std::mem::zeroed;
+use std::net::SocketAddr;
+use std::net::TcpListener;
 use std::os::fd::AsRawFd;
 use std::os::fd::FromRawFd;
 use std::os::fd::IntoRawFd;
+use std::os::fd::OwnedFd;
 use std::os::fd::RawFd;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::net::UnixListener;
+use std::path::Path;
 use std::path::PathBuf;
 use std::result;
 use std::sync::Arc;
 use std::sync::Mutex;
 use thiserror::Error;
+use virtio_devices::{Endpoint, SocketListener};
 
 const TIOCSPTLCK: libc::c_int = 0x4004_5431;
 const TIOCGPTPEER: libc::c_int = 0x5441;
@@ -59,16 +64,24 @@ pub enum ConsoleDeviceError {
     /// Error starting sigwinch listener
     #[error("Error starting sigwinch listener: {0}")]
     StartSigwinchListener(#[source] std::io::Error),
+
+    /// Host endpoint of the console mode not given
+    #[error("Missing {0} for the console mode")]
+    MissingEndpoint(&'static str),
 }
 
 type ConsoleDeviceResult<T> = result::Result<T, ConsoleDeviceError>;
 
 #[derive(Default, Clone)]
 pub struct ConsoleInfo {
-    // For each of File, Pty, Tty and Socket modes, below fields  hold the FD
-    // of console, serial and debug devices.
+    // For each mode but Off and Null, below fields hold the FD of the host
+    // endpoint of the console, serial and debug devices.
     pub console_main_fd: Option<RawFd>,
     pub serial_main_fd: Option<RawFd>,
+    // Input of the console and serial devices, when not read from the above
+    // FD, i.e. for pairs of named pipes.
+    pub console_input_fd: Option<RawFd>,
+    pub serial_input_fd: Option<RawFd>,
     #[cfg(target_arch = "x86_64")]
     pub debug_main_fd: Option<RawFd>,
 }
@@ -120,7 +133,7 @@ fn set_raw_mode(
     .map_err(ConsoleDeviceError::SetPtyRaw)
 }
 
-fn create_pty() -> io::Result<(File, File, PathBuf)> {
+pub(crate) fn create_pty() -> io::Result<(File, File, PathBuf)> {
     // Try to use /dev/pts/ptmx first then fall back to /dev/ptmx
     // This is done to try and use the devpts filesystem that
     // could be available for use in the process's namespace first.
@@ -166,137 +179,260 @@ fn create_pty() -> io::Result<(File, File, PathBuf)> {
     Ok((main, unsafe { File::from_raw_fd(sub_fd) }, path))
 }
 
-pub(crate) fn pre_create_console_devices(vmm: &mut Vmm) -> ConsoleDeviceResult<ConsoleInfo> {
-    let vm_config = vmm.vm_config.as_mut().unwrap().clone();
-    let mut vmconfig = vm_config.lock().unwrap();
-    let mut console_info = ConsoleInfo::default();
+/// Host endpoint of a character device of the guest, be it the serial port,
+/// a virtio-console device or the debug console.
+pub(crate) struct HostChardev {
+    /// Descriptor the device reads from and writes to: the output file, the
+    /// main side of the PTY, the pipe or the listening socket.
+    pub file: File,
+    /// Descriptor the device reads from instead, for pairs of named pipes.
+    pub input: Option<File>,
+    /// Terminal the size of the console is taken from, for the PTY and TTY
+    /// modes.
+    pub tty: Option<File>,
+}
 
-    match vmconfig.console.mode {
-        ConsoleOutputMode::File => {
-            let file = File::create(vmconfig.console.file.as_ref().unwrap())
-                .map_err(ConsoleDeviceError::CreateConsoleDevice)?;
-            console_info.console_main_fd = Some(file.into_raw_fd());
-        }
-        ConsoleOutputMode::Pty => {
-            let (main_fd, sub_fd, path) =
-                create_pty().map_err(ConsoleDeviceError::CreateConsoleDevice)?;
-            console_info.console_main_fd = Some(main_fd.into_raw_fd());
-            set_raw_mode(&sub_fd.as_raw_fd(), vmm.original_termios_opt.clone())?;
-            vmconfig.console.file = Some(path.clone());
-            vmm.console_resize_pipe = Some(Arc::new(
-                listen_for_sigwinch_on_tty(
-                    sub_fd,
-                    &vmm.seccomp_action,
-                    vmm.hypervisor.hypervisor_type(),
-                )
-                .map_err(ConsoleDeviceError::StartSigwinchListener)?,
-            ));
-        }
-        ConsoleOutputMode::Tty => {
-            // Duplicating the file descriptors like this is needed as otherwise
-            // they will be closed on a reboot and the numbers reused
+fn dup_stdout() -> ConsoleDeviceResult<File> {
+    // Duplicating the file descriptor is needed as otherwise it would be
+    // closed along with the device, e.g. on a reboot, and the number reused
+    // by a different file.
 
-            // SAFETY: FFI call to dup. Trivially safe.
-            let stdout = unsafe { libc::dup(libc::STDOUT_FILENO) };
-            if stdout == -1 {
-                return vmm_sys_util::errno::errno_result().map_err(ConsoleDeviceError::DupFd);
-            }
-            // SAFETY: stdout is valid and owned solely by us.
-            let stdout = unsafe { File::from_raw_fd(stdout) };
+    // SAFETY: FFI call to dup. Trivially safe.
+    let stdout = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if stdout == -1 {
+        return vmm_sys_util::errno::errno_result().map_err(ConsoleDeviceError::DupFd);
+    }
+    // SAFETY: stdout is valid and owned solely by us.
+    Ok(unsafe { File::from_raw_fd(stdout) })
+}
 
-            // SAFETY: FFI call. Trivially safe.
-            if unsafe { libc::isatty(stdout.as_raw_fd()) } == 1 {
-                vmm.console_resize_pipe = Some(Arc::new(
-                    listen_for_sigwinch_on_tty(
-                        stdout.try_clone().unwrap(),
-                        &vmm.seccomp_action,
-                        vmm.hypervisor.hypervisor_type(),
-                    )
-                    .map_err(ConsoleDeviceError::StartSigwinchListener)?,
-                ));
-            }
+fn open_read_write(path: &Path) -> io::Result<File> {
+    // Host character devices don't become the controlling terminal of the
+    // VMM. Opened for both reading and writing, a named pipe neither blocks
+    // until it has a peer nor reports the end of the stream when its peer
+    // goes away, the next peer opening it being bridged in turn.
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)
+}
 
-            // Make sure stdout is in raw mode, if it's a terminal.
-            set_raw_mode(&stdout, vmm.original_termios_opt.clone())?;
-            console_info.console_main_fd = Some(stdout.into_raw_fd());
-        }
-        ConsoleOutputMode::Null | ConsoleOutputMode::Socket | ConsoleOutputMode::Off => {}
+// Opens the named pipes `<path>.in` and `<path>.out` when they both exist,
+// `path` otherwise, returning the output and the input if it's separate.
+fn open_pipe(path: &Path) -> io::Result<(File, Option<File>)> {
+    let (input, output) = pipe_pair(path);
+    if input.exists() && output.exists() {
+        Ok((open_read_write(&output)?, Some(open_read_write(&input)?)))
+    } else {
+        Ok((open_read_write(path)?, None))
     }
+}
 
-    match vmconfig.serial.mode {
-        ConsoleOutputMode::File => {
-            let file = File::create(vmconfig.serial.file.as_ref().unwrap())
-                .map_err(ConsoleDeviceError::CreateConsoleDevice)?;
-            console_info.serial_main_fd = Some(file.into_raw_fd());
-        }
+/// Creates the host endpoint of a character device from its `mode`. The path
+/// of a newly allocated PTY is recorded in `file`, so that it can be
+/// retrieved through `vm.info`.
+pub(crate) fn create_host_chardev(
+    mode: &ConsoleOutputMode,
+    file: &mut Option<PathBuf>,
+    socket: Option<&PathBuf>,
+    tcp: Option<SocketAddr>,
+    original_termios_opt: Arc<Mutex<Option<termios>>>,
+) -> ConsoleDeviceResult<Option<HostChardev>> {
+    let mut input = None;
+    let (file, tty) = match mode {
+        ConsoleOutputMode::File => (
+            File::create(
+                file.as_ref()
+                    .ok_or(ConsoleDeviceError::MissingEndpoint("file"))?,
+            )
+            .map_err(ConsoleDeviceError::CreateConsoleDevice)?,
+            None,
+        ),
         ConsoleOutputMode::Pty => {
-            let (main_fd, sub_fd, path) =
+            let (main, sub, path) =
                 create_pty().map_err(ConsoleDeviceError::CreateConsoleDevice)?;
-            console_info.serial_main_fd = Some(main_fd.into_raw_fd());
-            set_raw_mode(&sub_fd.as_raw_fd(), vmm.original_termios_opt.clone())?;
-            vmconfig.serial.file = Some(path.clone());
+            set_raw_mode(&sub.as_raw_fd(), original_termios_opt)?;
+            *file = Some(path);
+            (main, Some(sub))
         }
         ConsoleOutputMode::Tty => {
-            // During vm_shutdown, when serial device is closed, FD#2(STDOUT)
-            // will be closed and FD#2 could be reused in a future boot of the
-            // guest by a different file.
-            //
-            // To ensure FD#2 always points to STANDARD OUT, a `dup` of STDOUT
-            // is passed to serial device. Doing so, even if the serial device
-            // were to be closed, FD#2 will continue to point to STANDARD OUT.
-
-            // SAFETY: FFI call to dup. Trivially safe.
-            let stdout = unsafe { libc::dup(libc::STDOUT_FILENO) };
-            if stdout == -1 {
-                return vmm_sys_util::errno::errno_result().map_err(ConsoleDeviceError::DupFd);
-            }
-            // SAFETY: stdout is valid and owned solely by us.
-            let stdout = unsafe { File::from_raw_fd(stdout) };
+            let stdout = dup_stdout()?;
+            // SAFETY: FFI call. Trivially safe.
+            let tty = if unsafe { libc::isatty(stdout.as_raw_fd()) } == 1 {
+                Some(
+                    stdout
+                        .try_clone()
+                        .map_err(ConsoleDeviceError::CreateConsoleDevice)?,
+                )
+            } else {
+                None
+            };
+            // Make sure stdout is in raw mode, if it's a terminal.
+            set_raw_mode(&stdout, original_termios_opt)?;
+            (stdout, tty)
+        }
+        ConsoleOutputMode::Pipe => {
+            let (pipe, pipe_input) = open_pipe(
+                file.as_ref()
+                    .ok_or(ConsoleDeviceError::MissingEndpoint("file"))?,
+            )
+            .map_err(ConsoleDeviceError::CreateConsoleDevice)?;
+            // Host serial ports are bridged byte for byte.
+            set_raw_mode(&pipe, Arc::new(Mutex::new(None)))?;
+            input = pipe_input;
+            (pipe, None)
+        }
+        ConsoleOutputMode::Socket => (
+            File::from(OwnedFd::from(
+                UnixListener::bind(socket.ok_or(ConsoleDeviceError::MissingEndpoint("socket"))?)
+                    .map_err(ConsoleDeviceError::CreateConsoleDevice)?,
+            )),
+            None,
+        ),
+        ConsoleOutputMode::Tcp => (
+            File::from(OwnedFd::from(
+                TcpListener::bind(tcp.ok_or(ConsoleDeviceError::MissingEndpoint("tcp address"))?)
+                    .map_err(ConsoleDeviceError::CreateConsoleDevice)?,
+            )),
+            None,
+        ),
+        ConsoleOutputMode::Null | ConsoleOutputMode::Off => return Ok(None),
+    };
+
+    Ok(Some(HostChardev { file, input, tty }))
+}
 
+/// Listening socket of a host character device created by
+/// `create_host_chardev()`.
+pub(crate) fn socket_listener(mode: &ConsoleOutputMode, file: File) -> SocketListener {
+    if *mode == ConsoleOutputMode::Tcp {
+        SocketListener::Tcp(TcpListener::from(OwnedFd::from(file)))
+    } else {
+        SocketListener::Unix(UnixListener::from(OwnedFd::from(file)))
+    }
+}
+
+/// Virtio-console endpoint bridged to the host character device `file`, and
+/// `input` when the input is read from a separate file.
+pub(crate) fn virtio_console_endpoint(
+    mode: &ConsoleOutputMode,
+    file: File,
+    input: Option<File>,
+) -> ConsoleDeviceResult<Endpoint> {
+    let clone = |f: &File| {
+        f.try_clone()
+            .map_err(ConsoleDeviceError::CreateConsoleDevice)
+    };
+    Ok(match mode {
+        ConsoleOutputMode::File => Endpoint::File(file),
+        ConsoleOutputMode::Pty => Endpoint::PtyPair(clone(&file)?, file),
+        ConsoleOutputMode::Pipe => match input {
+            Some(input) => Endpoint::FilePair(file, input),
+            None => Endpoint::FilePair(clone(&file)?, file),
+        },
+        ConsoleOutputMode::Tty => {
+            // If an interactive TTY then we can accept input
             // SAFETY: FFI call. Trivially safe.
-            if unsafe { libc::isatty(stdout.as_raw_fd()) } == 1 {
-                vmm.console_resize_pipe = Some(Arc::new(
-                    listen_for_sigwinch_on_tty(
-                        stdout.try_clone().unwrap(),
-                        &vmm.seccomp_action,
-                        vmm.hypervisor.hypervisor_type(),
-                    )
-                    .map_err(ConsoleDeviceError::StartSigwinchListener)?,
-                ));
+            if unsafe { libc::isatty(libc::STDIN_FILENO) == 1 } {
+                // SAFETY: FFI call to dup. Trivially safe.
+                let stdin = unsafe { libc::dup(libc::STDIN_FILENO) };
+                if stdin == -1 {
+                    return vmm_sys_util::errno::errno_result().map_err(ConsoleDeviceError::DupFd);
+                }
+                // SAFETY: stdin is valid and owned solely by us.
+                let stdin = unsafe { File::from_raw_fd(stdin) };
+                Endpoint::FilePair(file, stdin)
+            } else {
+                Endpoint::File(file)
             }
-
-            // Make sure stdout is in raw mode, if it's a terminal.
-            set_raw_mode(&stdout, vmm.original_termios_opt.clone())?;
-            console_info.serial_main_fd = Some(stdout.into_raw_fd());
         }
-        ConsoleOutputMode::Socket => {
-            let listener = UnixListener::bind(vmconfig.serial.socket.as_ref().unwrap())
-                .map_err(ConsoleDeviceError::CreateConsoleDevice)?;
-            console_info.serial_main_fd = Some(listener.into_raw_fd());
+        ConsoleOutputMode::Socket | ConsoleOutputMode::Tcp => {
+            Endpoint::Socket(socket_listener(mode, file))
         }
-        ConsoleOutputMode::Null | ConsoleOutputMode::Off => {}
+        ConsoleOutputMode::Null | ConsoleOutputMode::Off => Endpoint::Null,
+    })
+}
+
+/// Creates the endpoint of an additional console port.
+pub(crate) fn create_console_port_endpoint(
+    console_port: &mut ConsolePortConfig,
+) -> ConsoleDeviceResult<Endpoint> {
+    // The PTY is allocated for the port, there is no original terminal
+    // configuration to restore on exit.
+    match create_host_chardev(
+        &console_port.mode,
+        &mut console_port.file,
+        console_port.socket.as_ref(),
+        console_port.tcp,
+        Arc::new(Mutex::new(None)),
+    )? {
+        Some(chardev) => virtio_console_endpoint(&console_port.mode, chardev.file, chardev.input),
+        None => Ok(Endpoint::Null),
     }
+}
 
-    #[cfg(target_arch = "x86_64")]
-    match vmconfig.debug_console.mode {
-        ConsoleOutputMode::File => {
-            let file = File::create(vmconfig.debug_console.file.as_ref().unwrap())
-                .map_err(ConsoleDeviceError::CreateConsoleDevice)?;
-            console_info.debug_main_fd = Some(file.into_raw_fd());
+pub(crate) fn pre_create_console_devices(vmm: &mut Vmm) -> ConsoleDeviceResult<ConsoleInfo> {
+    let vm_config = vmm.vm_config.as_mut().unwrap().clone();
+    let mut vmconfig = vm_config.lock().unwrap();
+    let mut console_info = ConsoleInfo::default();
+
+    let console = &mut vmconfig.console;
+    if let Some(chardev) = create_host_chardev(
+        &console.mode,
+        &mut console.file,
+        console.socket.as_ref(),
+        console.tcp,
+        vmm.original_termios_opt.clone(),
+    )? {
+        if let Some(tty) = chardev.tty {
+            vmm.console_resize_pipe = Some(Arc::new(
+                listen_for_sigwinch_on_tty(
+                    tty,
+                    &vmm.seccomp_action,
+                    vmm.hypervisor.hypervisor_type(),
+                )
+                .map_err(ConsoleDeviceError::StartSigwinchListener)?,
+            ));
         }
-        ConsoleOutputMode::Pty => {
-            let (main_fd, sub_fd, path) =
-                create_pty().map_err(ConsoleDeviceError::CreateConsoleDevice)?;
-            console_info.debug_main_fd = Some(main_fd.into_raw_fd());
-            set_raw_mode(&sub_fd.as_raw_fd(), vmm.original_termios_opt.clone())?;
-            vmconfig.debug_console.file = Some(path.clone());
+        console_info.console_main_fd = Some(chardev.file.into_raw_fd());
+        console_info.console_input_fd = chardev.input.map(|f| f.into_raw_fd());
+    }
+
+    let serial = &mut vmconfig.serial;
+    if let Some(chardev) = create_host_chardev(
+        &serial.mode,
+        &mut serial.file,
+        serial.socket.as_ref(),
+        serial.tcp,
+        vmm.original_termios_opt.clone(),
+    )? {
+        if let (ConsoleOutputMode::Tty, Some(tty)) = (&serial.mode, chardev.tty) {
+            vmm.console_resize_pipe = Some(Arc::new(
+                listen_for_sigwinch_on_tty(
+                    tty,
+                    &vmm.seccomp_action,
+                    vmm.hypervisor.hypervisor_type(),
+                )
+                .map_err(ConsoleDeviceError::StartSigwinchListener)?,
+            ));
         }
-        ConsoleOutputMode::Tty => {
-            let out = stdout();
-            console_info.debug_main_fd = Some(out.as_raw_fd());
-            set_raw_mode(&out, vmm.original_termios_opt.clone())?;
+        console_info.serial_main_fd = Some(chardev.file.into_raw_fd());
+        console_info.serial_input_fd = chardev.input.map(|f| f.into_raw_fd());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let debug_console = &mut vmconfig.debug_console;
+        if let Some(chardev) = create_host_chardev(
+            &debug_console.mode,
+            &mut debug_console.file,
+            None,
+            None,
+            vmm.original_termios_opt.clone(),
+        )? {
+            console_info.debug_main_fd = Some(chardev.file.into_raw_fd());
         }
-        ConsoleOutputMode::Null | ConsoleOutputMode::Socket | ConsoleOutputMode::Off => {}
     }
 
     Ok(console_info)
@@ -0,0 +1,294 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Shipping of the guest console output to a log collector.
+//!
+//! Each line written by the guest is sent as a JSON object, on its own line,
+//! to a log shipper listening on a UNIX socket:
+//!
+//! ```text
+//! {"timestamp":"2024-06-10T16:01:27.123456Z","stream":"serial","vm_id":"...","message":"..."}
+//! ```
+//!
+//! The guest is never held up by the shipper. Lines are handed over to a
+//! dedicated thread through a bounded queue, and the lines which don't fit in
+//! the queue, or which can't be delivered because nothing listens on the
+//! socket, are dropped. The number of dropped lines is reported through a
+//! `{"timestamp":...,"stream":...,"vm_id":...,"dropped":<count>}` record as
+//! soon as the shipper catches up. The connection is established lazily, and
+//! again after the shipper went away, which lets it be socket activated.
+
+use serde::Serialize;
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Number of lines waiting to be shipped before new ones get dropped.
+const QUEUE_LEN: usize = 4096;
+// Longer lines are split.
+const MAX_LINE_LEN: usize = 4096;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    stream: &'a str,
+    vm_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dropped: Option<u64>,
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with microseconds.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Civil date from the number of days since 1970-01-01, as described in
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_micros()
+    )
+}
+
+struct Line {
+    time: SystemTime,
+    data: Vec<u8>,
+}
+
+/// Log of a console output stream, shared by all the writers of the stream.
+#[derive(Clone)]
+pub struct ConsoleLog {
+    sender: SyncSender<Line>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl ConsoleLog {
+    /// Starts shipping the lines of `stream` to the log shipper listening on
+    /// `socket`. The shipping stops once the log and all its writers are
+    /// dropped.
+    pub fn new(socket: PathBuf, stream: &'static str, vm_id: String) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let shipper = Shipper {
+            socket,
+            stream,
+            vm_id,
+            dropped: dropped.clone(),
+            connection: None,
+            next_attempt: Instant::now(),
+        };
+
+        thread::Builder::new()
+            .name(format!("{stream}-log"))
+            .spawn(move || shipper.run(receiver))?;
+
+        Ok(ConsoleLog { sender, dropped })
+    }
+
+    /// Returns a writer copying the output to the log, on top of writing it
+    /// to `out`.
+    pub fn writer(&self, out: Option<Box<dyn Write + Send>>) -> Box<dyn Write + Send> {
+        Box::new(ConsoleLogWriter {
+            log: self.clone(),
+            out,
+            line: Vec::new(),
+        })
+    }
+
+    fn send(&self, data: Vec<u8>) {
+        let line = Line {
+            time: SystemTime::now(),
+            data,
+        };
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(line) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+struct ConsoleLogWriter {
+    log: ConsoleLog,
+    out: Option<Box<dyn Write + Send>>,
+    line: Vec<u8>,
+}
+
+impl Write for ConsoleLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let buf = match self.out.as_mut() {
+            Some(out) => &buf[..out.write(buf)?],
+            None => buf,
+        };
+
+        for &byte in buf {
+            match byte {
+                b'\n' => self.log.send(std::mem::take(&mut self.line)),
+                b'\r' => {}
+                _ => {
+                    self.line.push(byte);
+                    if self.line.len() == MAX_LINE_LEN {
+                        self.log.send(std::mem::take(&mut self.line));
+                    }
+                }
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.out.as_mut() {
+            Some(out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ConsoleLogWriter {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.log.send(std::mem::take(&mut self.line));
+        }
+    }
+}
+
+struct Shipper {
+    socket: PathBuf,
+    stream: &'static str,
+    vm_id: String,
+    dropped: Arc<AtomicU64>,
+    connection: Option<UnixStream>,
+    next_attempt: Instant,
+}
+
+impl Shipper {
+    fn record(&self, time: SystemTime, message: Option<&str>, dropped: Option<u64>) -> Vec<u8> {
+        let mut record = serde_json::to_vec(&Record {
+            timestamp: rfc3339(time),
+            stream: self.stream,
+            vm_id: &self.vm_id,
+            message,
+            dropped,
+        })
+        .unwrap();
+        record.push(b'\n');
+        record
+    }
+
+    fn ship(&mut self, record: &[u8]) -> bool {
+        if self.connection.is_none() {
+            if Instant::now() < self.next_attempt {
+                return false;
+            }
+            match UnixStream::connect(&self.socket) {
+                Ok(connection) => {
+                    info!("Shipping {} output to {:?}", self.stream, self.socket);
+                    self.connection = Some(connection);
+                }
+                Err(e) => {
+                    debug!("Cannot connect to log shipper {:?}: {}", self.socket, e);
+                    self.next_attempt = Instant::now() + RECONNECT_DELAY;
+                    return false;
+                }
+            }
+        }
+
+        // Blocking here is what lets the queue absorb the bursts the shipper
+        // can't keep up with.
+        if let Err(e) = self.connection.as_mut().unwrap().write_all(record) {
+            warn!("Lost connection to log shipper {:?}: {}", self.socket, e);
+            self.connection = None;
+            self.next_attempt = Instant::now() + RECONNECT_DELAY;
+            return false;
+        }
+
+        true
+    }
+
+    fn run(mut self, receiver: Receiver<Line>) {
+        for line in receiver {
+            let dropped = self.dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                let record = self.record(SystemTime::now(), None, Some(dropped));
+                if !self.ship(&record) {
+                    self.dropped.fetch_add(dropped, Ordering::Relaxed);
+                }
+            }
+
+            let message = String::from_utf8_lossy(&line.data);
+            let record = self.record(line.time, Some(&message), None);
+            if !self.ship(&record) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000000Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::new(1709251199, 123456000)),
+            "2024-02-29T23:59:59.123456Z"
+        );
+    }
+
+    #[test]
+    fn test_console_log() {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let socket = dir.as_path().join("shipper.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let log = ConsoleLog::new(socket, "serial", "vm0".to_owned()).unwrap();
+        let mut writer = log.writer(None);
+        writer.write_all(b"hello\r\nwor").unwrap();
+        writer.write_all(b"ld").unwrap();
+        drop(writer);
+        drop(log);
+
+        let (connection, _) = listener.accept().unwrap();
+        let records: Vec<serde_json::Value> = BufReader::new(connection)
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["stream"], "serial");
+        assert_eq!(records[0]["vm_id"], "vm0");
+        assert_eq!(records[0]["message"], "hello");
+        assert_eq!(records[1]["message"], "world");
+        assert!(records[1].get("dropped").is_none());
+    }
+}
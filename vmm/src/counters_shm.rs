@@ -0,0 +1,293 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Publication of the VM counters through a shared memory segment.
+//!
+//! The counters are copied periodically into a memfd, which monitoring agents
+//! map through `/proc/<pid>/fd/<fd>` (as returned by the `vm.counters-shm`
+//! API) and sample without any round trip to the VMM. The segment is
+//! `COUNTERS_SHM_SIZE` bytes long, all the fields being little endian:
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic, "CHCT"
+//!      4     4  version, 1
+//!      8     8  sequence, odd while the counters are being updated
+//!     16     4  number of entries
+//!     20     4  size of an entry, 64
+//!     24     8  time of the last update, in ns since the UNIX epoch
+//!     32     4  update interval, in ms
+//!     36    28  reserved
+//!     64        entries
+//! ```
+//!
+//! Each entry is made of a NUL padded `<device id>/<counter>` name over 56
+//! bytes, followed by the 64-bit value of the counter. The entries are sorted
+//! by name. A consistent snapshot is read by retrying as long as the sequence
+//! is odd, or changed while the entries were copied.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::num::Wrapping;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use vmm_sys_util::eventfd::EventFd;
+
+pub type Counters = HashMap<String, HashMap<&'static str, Wrapping<u64>>>;
+
+pub const COUNTERS_SHM_VERSION: u32 = 1;
+const COUNTERS_SHM_MAGIC: u32 = u32::from_le_bytes(*b"CHCT");
+const HEADER_SIZE: usize = 64;
+const ENTRY_SIZE: usize = 64;
+const ENTRY_NAME_SIZE: usize = 56;
+const MAX_ENTRIES: usize = 4095;
+pub const COUNTERS_SHM_SIZE: usize = HEADER_SIZE + MAX_ENTRIES * ENTRY_SIZE;
+pub const COUNTERS_SHM_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct CountersShmInfo {
+    pub path: String,
+    pub size: usize,
+    pub version: u32,
+    pub update_interval_ms: u64,
+}
+
+struct Mapping {
+    addr: *mut u8,
+}
+
+// SAFETY: the mapping is only accessed from the thread updating it
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    fn new(file: &File) -> io::Result<Self> {
+        // SAFETY: FFI call with a valid fd, the result is checked
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                COUNTERS_SHM_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Mapping {
+            addr: addr as *mut u8,
+        })
+    }
+
+    fn write<T: Copy>(&self, offset: usize, value: T) {
+        assert!(offset + std::mem::size_of::<T>() <= COUNTERS_SHM_SIZE);
+        // SAFETY: the offset is within the mapping
+        unsafe { ptr::write_volatile(self.addr.add(offset) as *mut T, value) }
+    }
+
+    fn sequence(&self) -> &AtomicU64 {
+        // SAFETY: the sequence is 8 bytes aligned within the mapping
+        unsafe { &*(self.addr.add(8) as *const AtomicU64) }
+    }
+
+    fn update(&self, counters: &Counters) {
+        let mut entries: Vec<(String, u64)> = counters
+            .iter()
+            .flat_map(|(id, counters)| {
+                counters
+                    .iter()
+                    .map(move |(name, value)| (format!("{id}/{name}"), value.0))
+            })
+            .collect();
+        entries.sort();
+        if entries.len() > MAX_ENTRIES {
+            warn!(
+                "Only {} of the {} counters fit in the shared memory",
+                MAX_ENTRIES,
+                entries.len()
+            );
+            entries.truncate(MAX_ENTRIES);
+        }
+
+        let sequence = self.sequence();
+        sequence.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        for (i, (name, value)) in entries.iter().enumerate() {
+            let offset = HEADER_SIZE + i * ENTRY_SIZE;
+            let mut name_bytes = [0u8; ENTRY_NAME_SIZE];
+            // Keep a NUL at the end of the longer names
+            let len = name.len().min(ENTRY_NAME_SIZE - 1);
+            name_bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+            self.write(offset, name_bytes);
+            self.write(offset + ENTRY_NAME_SIZE, *value);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.write(16, entries.len() as u32);
+        self.write(24, now.as_nanos() as u64);
+
+        sequence.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: FFI call with the address and size of the mapping
+        unsafe { libc::munmap(self.addr as *mut libc::c_void, COUNTERS_SHM_SIZE) };
+    }
+}
+
+/// Shared memory segment the counters are copied to by a dedicated thread.
+pub struct CountersShm {
+    file: File,
+    kill_evt: EventFd,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl CountersShm {
+    pub fn new<F>(collect: F) -> io::Result<Self>
+    where
+        F: Fn() -> Counters + Send + 'static,
+    {
+        let name = CString::new("ch_counters").unwrap();
+        // SAFETY: FFI call with a valid name, the result is checked
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is valid and owned by nothing else
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(COUNTERS_SHM_SIZE as u64)?;
+
+        let mapping = Mapping::new(&file)?;
+        mapping.write(0, COUNTERS_SHM_MAGIC);
+        mapping.write(4, COUNTERS_SHM_VERSION);
+        mapping.write(20, ENTRY_SIZE as u32);
+        mapping.write(32, COUNTERS_SHM_UPDATE_INTERVAL.as_millis() as u32);
+        mapping.update(&collect());
+
+        let kill_evt = EventFd::new(libc::EFD_NONBLOCK)?;
+        let thread_kill_evt = kill_evt.try_clone()?;
+        let handle = thread::Builder::new()
+            .name("counters_shm".to_string())
+            .spawn(move || Self::update_counters(mapping, thread_kill_evt, collect))?;
+
+        Ok(CountersShm {
+            file,
+            kill_evt,
+            handle: Some(handle),
+        })
+    }
+
+    fn update_counters<F: Fn() -> Counters>(mapping: Mapping, kill_evt: EventFd, collect: F) {
+        let mut fds = [libc::pollfd {
+            fd: kill_evt.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+
+        loop {
+            // SAFETY: FFI call with a valid array of pollfd
+            let ret = unsafe {
+                libc::poll(
+                    fds.as_mut_ptr(),
+                    fds.len() as libc::nfds_t,
+                    COUNTERS_SHM_UPDATE_INTERVAL.as_millis() as i32,
+                )
+            };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("Error polling the counters kill event: {}", e);
+                return;
+            }
+            if fds[0].revents & libc::POLLIN != 0 {
+                return;
+            }
+
+            mapping.update(&collect());
+        }
+    }
+
+    pub fn info(&self) -> CountersShmInfo {
+        CountersShmInfo {
+            path: format!("/proc/{}/fd/{}", std::process::id(), self.file.as_raw_fd()),
+            size: COUNTERS_SHM_SIZE,
+            version: COUNTERS_SHM_VERSION,
+            update_interval_ms: COUNTERS_SHM_UPDATE_INTERVAL.as_millis() as u64,
+        }
+    }
+}
+
+impl Drop for CountersShm {
+    fn drop(&mut self) {
+        let _ = self.kill_evt.write(1);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_shm_layout() {
+        let shm = CountersShm::new(|| {
+            HashMap::from([
+                (
+                    "_net0".to_string(),
+                    HashMap::from([("rx_bytes", Wrapping(42)), ("tx_bytes", Wrapping(7))]),
+                ),
+                (
+                    "_disk0".to_string(),
+                    HashMap::from([("read_ops", Wrapping(3))]),
+                ),
+            ])
+        })
+        .unwrap();
+
+        let info = shm.info();
+        assert_eq!(info.size, COUNTERS_SHM_SIZE);
+        let data = std::fs::read(&info.path).unwrap();
+        assert_eq!(data.len(), COUNTERS_SHM_SIZE);
+
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        assert_eq!(&data[0..4], b"CHCT");
+        assert_eq!(u32_at(4), COUNTERS_SHM_VERSION);
+        assert_eq!(u64_at(8) % 2, 0);
+        assert_eq!(u32_at(16), 3);
+        assert_eq!(u32_at(20), ENTRY_SIZE as u32);
+        assert_eq!(u32_at(32), 100);
+
+        let entry = |i: usize| {
+            let offset = HEADER_SIZE + i * ENTRY_SIZE;
+            let name = &data[offset..offset + ENTRY_NAME_SIZE];
+            let len = name.iter().position(|b| *b == 0).unwrap();
+            (
+                std::str::from_utf8(&name[..len]).unwrap().to_string(),
+                u64_at(offset + ENTRY_NAME_SIZE),
+            )
+        };
+        assert_eq!(entry(0), ("_disk0/read_ops".to_string(), 3));
+        assert_eq!(entry(1), ("_net0/rx_bytes".to_string(), 42));
+        assert_eq!(entry(2), ("_net0/tx_bytes".to_string(), 7));
+    }
+}
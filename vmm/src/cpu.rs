@@ -23,6 +23,8 @@ use crate::gdb::{get_raw_tid, Debuggable, DebuggableError};
 #[cfg(target_arch = "x86_64")]
 use crate::memory_manager::MemoryManager;
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
+#[cfg(feature = "tdx")]
+use crate::tdx_quote::TdxQuoteGenerator;
 #[cfg(target_arch = "x86_64")]
 use crate::vm::physical_bits;
 use crate::GuestMemoryMmap;
@@ -46,10 +48,12 @@ use gdbstub_arch::x86::reg::{X86SegmentRegs, X86_64CoreRegs as CoreRegs};
 use hypervisor::arch::x86::msr_index;
 #[cfg(target_arch = "x86_64")]
 use hypervisor::arch::x86::CpuIdEntry;
-#[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+#[cfg(all(target_arch = "x86_64", any(feature = "guest_debug", feature = "igvm")))]
 use hypervisor::arch::x86::MsrEntry;
-#[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+#[cfg(all(target_arch = "x86_64", any(feature = "guest_debug", feature = "igvm")))]
 use hypervisor::arch::x86::SpecialRegisters;
+#[cfg(all(target_arch = "x86_64", feature = "igvm"))]
+use hypervisor::arch::x86::CPUID_FLAG_VALID_INDEX;
 #[cfg(target_arch = "aarch64")]
 use hypervisor::kvm::kvm_bindings;
 #[cfg(all(target_arch = "aarch64", feature = "kvm"))]
@@ -60,21 +64,25 @@ use hypervisor::kvm::{TdxExitDetails, TdxExitStatus};
 use hypervisor::CpuVendor;
 #[cfg(feature = "kvm")]
 use hypervisor::HypervisorType;
-#[cfg(feature = "guest_debug")]
+#[cfg(any(feature = "guest_debug", feature = "igvm"))]
 use hypervisor::StandardRegisters;
 use hypervisor::{CpuState, HypervisorCpuError, VmExit, VmOps};
 use libc::{c_void, siginfo_t};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use linux_loader::elf::Elf64_Nhdr;
 use seccompiler::{apply_filter, SeccompAction};
-use std::collections::BTreeMap;
+#[cfg(target_arch = "x86_64")]
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use std::io::Write;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use std::mem::size_of;
+use std::num::Wrapping;
 use std::os::unix::thread::JoinHandleExt;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
+use std::time::{Duration, Instant};
 use std::{cmp, io, result, thread};
 use thiserror::Error;
 use tracer::trace_scoped;
@@ -112,6 +120,9 @@ macro_rules! extract_bits_64_without_offset {
 
 pub const CPU_MANAGER_ACPI_SIZE: usize = 0xc;
 
+// Upper bound of the threads creating or configuring the vCPUs in parallel.
+const MAX_VCPU_SETUP_THREAD_COUNT: usize = 16;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Error creating vCPU: {0}")]
@@ -191,16 +202,47 @@ pub enum Error {
     #[error("Maximum number of vCPUs exceeds host limit")]
     MaximumVcpusExceeded,
 
+    #[cfg(target_arch = "x86_64")]
+    #[error("APIC IDs beyond the xAPIC range are not supported by the hypervisor")]
+    X2ApicIdsNotSupported,
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error enabling 32-bit APIC IDs: {0}")]
+    EnableX2ApicApi(#[source] hypervisor::HypervisorVmError),
+
     #[cfg(feature = "sev_snp")]
     #[error("Failed to set sev control register: {0}")]
     SetSevControlRegister(#[source] hypervisor::HypervisorCpuError),
 
-    #[cfg(target_arch = "x86_64")]
+    #[cfg(feature = "igvm")]
+    #[error("Error setting the vCPU initial registers: {0}")]
+    SetInitialRegisters(#[source] hypervisor::HypervisorCpuError),
+
     #[error("Failed to inject NMI")]
     NmiError(hypervisor::HypervisorCpuError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error reading vCPU TSC information: {0}")]
+    GetTscInfo(#[source] hypervisor::HypervisorCpuError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error setting vCPU TSC frequency: {0}")]
+    SetTscFrequency(#[source] hypervisor::HypervisorCpuError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error restoring the guest TSC frequency: {0}")]
+    TscIncompatible(#[source] arch::Error),
 }
 pub type Result<T> = result::Result<T, Error>;
 
+/// Per-vCPU TSC parameters reported through the time-info API.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VcpuTscInfo {
+    pub tsc_khz: Option<u32>,
+    pub tsc_offset: Option<u64>,
+}
+
 #[cfg(target_arch = "x86_64")]
 #[allow(dead_code)]
 #[repr(packed)]
@@ -324,11 +366,45 @@ macro_rules! round_up {
     };
 }
 
+/// Number of exits kept by each vCPU to diagnose a triple fault.
+const EXIT_HISTORY_LEN: usize = 16;
+
+/// Last exits of a vCPU, along with the time they happened since the vCPU
+/// started running.
+struct ExitHistory {
+    start: Instant,
+    exits: [Option<(Duration, VmExit)>; EXIT_HISTORY_LEN],
+    next: usize,
+}
+
+impl ExitHistory {
+    fn new() -> Self {
+        ExitHistory {
+            start: Instant::now(),
+            exits: [None; EXIT_HISTORY_LEN],
+            next: 0,
+        }
+    }
+
+    fn record(&mut self, exit: VmExit) {
+        self.exits[self.next] = Some((self.start.elapsed(), exit));
+        self.next = (self.next + 1) % EXIT_HISTORY_LEN;
+    }
+
+    /// Iterates over the recorded exits, the oldest first.
+    fn iter(&self) -> impl Iterator<Item = &(Duration, VmExit)> {
+        self.exits[self.next..]
+            .iter()
+            .chain(self.exits[..self.next].iter())
+            .flatten()
+    }
+}
+
 /// A wrapper around creating and using a kvm-based VCPU.
 pub struct Vcpu {
     // The hypervisor abstracted CPU.
     vcpu: Arc<dyn hypervisor::Vcpu>,
-    id: u8,
+    id: u32,
     #[cfg(target_arch = "aarch64")]
     mpidr: u64,
     saved_state: Option<CpuState>,
@@ -346,8 +422,8 @@ impl Vcpu {
     /// * `vm_ops` - Optional object for exit handling.
     /// * `cpu_vendor` - CPU vendor as reported by __cpuid(0x0)
     pub fn new(
-        id: u8,
-        apic_id: u8,
+        id: u32,
+        apic_id: u32,
         vm: &Arc<dyn hypervisor::Vm>,
         vm_ops: Option<Arc<dyn VmOps>>,
         #[cfg(target_arch = "x86_64")] cpu_vendor: CpuVendor,
@@ -468,12 +544,94 @@ impl Vcpu {
         self.vcpu.run()
     }
 
+    /// Logs the registers and the last exits of the vCPU after a triple
+    /// fault, which would otherwise silently reset the VM.
+    #[cfg(target_arch = "x86_64")]
+    fn report_triple_fault(&self, exit_history: &ExitHistory) {
+        error!("vCPU {} triple faulted, resetting the VM", self.id);
+
+        let mut rip = None;
+        match self.vcpu.get_regs() {
+            Ok(regs) => {
+                rip = Some(regs.get_rip());
+                error!("vCPU {} registers: {:x?}", self.id, regs);
+            }
+            Err(e) => error!("Error getting vCPU {} registers: {}", self.id, e),
+        }
+        match self.vcpu.get_sregs() {
+            Ok(sregs) => error!("vCPU {} special registers: {:x?}", self.id, sregs),
+            Err(e) => error!("Error getting vCPU {} special registers: {}", self.id, e),
+        }
+        for (time, exit) in exit_history.iter() {
+            error!(
+                "vCPU {} exit at {}.{:>06}s: {:?}",
+                self.id,
+                time.as_secs(),
+                time.subsec_micros(),
+                exit
+            );
+        }
+
+        event!(
+            "vcpu",
+            "triple-fault",
+            "id",
+            self.id.to_string(),
+            "rip",
+            rip.map(|rip| format!("{rip:#x}")).unwrap_or_default()
+        );
+    }
+
     #[cfg(feature = "sev_snp")]
     pub fn set_sev_control_register(&self, vmsa_pfn: u64) -> Result<()> {
         self.vcpu
             .set_sev_control_register(vmsa_pfn)
             .map_err(Error::SetSevControlRegister)
     }
+
+    /// Gets the registers the vCPU starts with when the payload provides
+    /// its initial state.
+    #[cfg(all(target_arch = "x86_64", feature = "igvm"))]
+    pub fn initial_registers(&self) -> Result<(StandardRegisters, SpecialRegisters)> {
+        let regs = self.vcpu.get_regs().map_err(Error::SetInitialRegisters)?;
+        let sregs = self.vcpu.get_sregs().map_err(Error::SetInitialRegisters)?;
+        Ok((regs, sregs))
+    }
+
+    /// Sets the registers the vCPU starts with when the payload provides its
+    /// initial state.
+    #[cfg(all(target_arch = "x86_64", feature = "igvm"))]
+    pub fn set_initial_registers(
+        &self,
+        regs: &StandardRegisters,
+        sregs: &SpecialRegisters,
+        msrs: &[MsrEntry],
+    ) -> Result<()> {
+        self.vcpu
+            .set_regs(regs)
+            .map_err(Error::SetInitialRegisters)?;
+        self.vcpu
+            .set_sregs(sregs)
+            .map_err(Error::SetInitialRegisters)?;
+        self.vcpu
+            .set_msrs(msrs)
+            .map_err(Error::SetInitialRegisters)?;
+        Ok(())
+    }
+
+    /// Gets the core registers the vCPU starts with when the payload
+    /// provides its initial state.
+    #[cfg(all(target_arch = "aarch64", feature = "igvm"))]
+    pub fn initial_registers(&self) -> Result<StandardRegisters> {
+        self.vcpu.get_regs().map_err(Error::SetInitialRegisters)
+    }
+
+    /// Sets the core registers the vCPU starts with when the payload provides
+    /// its initial state.
+    #[cfg(all(target_arch = "aarch64", feature = "igvm"))]
+    pub fn set_initial_registers(&self, regs: &StandardRegisters) -> Result<()> {
+        self.vcpu.set_regs(regs).map_err(Error::SetInitialRegisters)
+    }
 }
 
 impl Pausable for Vcpu {}
@@ -506,25 +664,39 @@ pub struct CpuManager {
     vm: Arc<dyn hypervisor::Vm>,
     vcpus_kill_signalled: Arc<AtomicBool>,
     vcpus_pause_signalled: Arc<AtomicBool>,
+    // Incremented on every pause request, each vCPU thread acknowledges the
+    // generation it parked for.
+    vcpus_pause_generation: Arc<AtomicU64>,
     vcpus_kick_signalled: Arc<AtomicBool>,
+    // Set once a vCPU failed, triple faulting or failing to run, for the
+    // restart policy to tell the failures apart from the guest requests.
+    vcpus_failed: Arc<AtomicBool>,
     exit_evt: EventFd,
     #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
     reset_evt: EventFd,
     #[cfg(feature = "guest_debug")]
     vm_debug_evt: EventFd,
     vcpu_states: Vec<VcpuState>,
-    selected_cpu: u8,
+    selected_cpu: u32,
     vcpus: Vec<Arc<Mutex<Vcpu>>>,
     seccomp_action: SeccompAction,
     vm_ops: Arc<dyn VmOps>,
-    #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
     acpi_address: Option<GuestAddress>,
-    proximity_domain_per_cpu: BTreeMap<u8, u32>,
-    affinity: BTreeMap<u8, Vec<usize>>,
+    proximity_domain_per_cpu: BTreeMap<u32, u32>,
+    affinity: BTreeMap<u32, Vec<usize>>,
     dynamic: bool,
     hypervisor: Arc<dyn hypervisor::Hypervisor>,
     #[cfg(feature = "sev_snp")]
     sev_snp_enabled: bool,
+    // GPA of the VMSA each vCPU starts from, per vCPU id.
+    #[cfg(feature = "sev_snp")]
+    vmsa_gpas: BTreeMap<u32, u64>,
+    // TSC frequency of the guest, which may differ from the host one after
+    // a migration relying on TSC scaling.
+    #[cfg(target_arch = "x86_64")]
+    tsc_khz: Option<u32>,
+    #[cfg(feature = "tdx")]
+    tdx_quote_generator: Option<Arc<TdxQuoteGenerator>>,
 }
 
 const CPU_ENABLE_FLAG: usize = 0;
@@ -542,11 +714,13 @@ impl BusDevice for CpuManager {
 
         match offset {
             CPU_SELECTION_OFFSET => {
-                data[0] = self.selected_cpu;
+                let bytes = self.selected_cpu.to_le_bytes();
+                let len = data.len().min(bytes.len());
+                data[..len].copy_from_slice(&bytes[..len]);
             }
             CPU_STATUS_OFFSET => {
                 if self.selected_cpu < self.max_vcpus() {
-                    let state = &self.vcpu_states[usize::from(self.selected_cpu)];
+                    let state = &self.vcpu_states[self.selected_cpu as usize];
                     if state.active() {
                         data[0] |= 1 << CPU_ENABLE_FLAG;
                     }
@@ -572,11 +746,14 @@ impl BusDevice for CpuManager {
     fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
         match offset {
             CPU_SELECTION_OFFSET => {
-                self.selected_cpu = data[0];
+                let mut bytes = [0u8; 4];
+                let len = data.len().min(bytes.len());
+                bytes[..len].copy_from_slice(&data[..len]);
+                self.selected_cpu = u32::from_le_bytes(bytes);
             }
             CPU_STATUS_OFFSET => {
                 if self.selected_cpu < self.max_vcpus() {
-                    let state = &mut self.vcpu_states[usize::from(self.selected_cpu)];
+                    let state = &mut self.vcpu_states[self.selected_cpu as usize];
                     // The ACPI code writes back a 1 to acknowledge the insertion
                     if (data[0] & (1 << CPU_INSERTING_FLAG) == 1 << CPU_INSERTING_FLAG)
                         && state.inserting
@@ -618,7 +795,8 @@ struct VcpuState {
     handle: Option<thread::JoinHandle<()>>,
     kill: Arc<AtomicBool>,
     vcpu_run_interrupted: Arc<AtomicBool>,
-    paused: Arc<AtomicBool>,
+    paused_generation: Arc<AtomicU64>,
+    usage: Arc<VcpuUsage>,
 }
 
 impl VcpuState {
@@ -626,13 +804,19 @@ impl VcpuState {
         self.handle.is_some()
     }
 
-    fn signal_thread(&self) {
+    fn kick_thread(&self) {
         if let Some(handle) = self.handle.as_ref() {
+            // SAFETY: FFI call with correct arguments
+            unsafe {
+                libc::pthread_kill(handle.as_pthread_t() as _, SIGRTMIN());
+            }
+        }
+    }
+
+    fn signal_thread(&self) {
+        if self.active() {
             loop {
-                // SAFETY: FFI call with correct arguments
-                unsafe {
-                    libc::pthread_kill(handle.as_pthread_t() as _, SIGRTMIN());
-                }
+                self.kick_thread();
                 if self.vcpu_run_interrupted.load(Ordering::SeqCst) {
                     break;
                 } else {
@@ -659,6 +843,209 @@ impl VcpuState {
     }
 }
 
+// Time spent by a vCPU thread, in ns.
+#[derive(Clone, Copy)]
+struct VcpuUsageSample {
+    time: Instant,
+    // CPU time of the thread, host and guest alike.
+    cpu_time: u64,
+    // Part of the CPU time spent running the guest.
+    guest_time: u64,
+    // Time the thread waited on a run queue while runnable, the time stolen
+    // from the guest by the host scheduler.
+    run_delay: u64,
+    // Page faults taken by the thread, including the ones on the guest
+    // memory resolved by the hypervisor while the vCPU is stalled.
+    minor_faults: u64,
+    major_faults: u64,
+    // Time the thread was blocked on block I/O, e.g. reading back guest memory
+    // from a file or from the swap. Only accounted by hosts with delay
+    // accounting enabled.
+    blkio_delay: u64,
+}
+
+// Fields of /proc/<pid>/task/<tid>/stat, numbered from 1 as in proc(5).
+const STAT_MINFLT: usize = 10;
+const STAT_MAJFLT: usize = 12;
+const STAT_DELAYACCT_BLKIO_TICKS: usize = 42;
+const STAT_GUEST_TIME: usize = 43;
+
+// The fields are counted after the command name, the 2nd field, which may
+// contain spaces and parentheses.
+fn parse_stat_field(stat: &str, field: usize) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(field - 3)?.parse().ok()
+}
+
+// The run queue wait time is the second field of
+// /proc/<pid>/task/<tid>/schedstat, in ns.
+fn parse_run_delay(schedstat: &str) -> Option<u64> {
+    schedstat.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn usage_percent(time: u64, elapsed: Duration) -> u64 {
+    match elapsed.as_nanos() {
+        0 => 0,
+        elapsed => cmp::min(time as u128 * 100 / elapsed, 100) as u64,
+    }
+}
+
+impl VcpuUsageSample {
+    fn read(handle: &thread::JoinHandle<()>, tid: i32) -> io::Result<Self> {
+        let time = Instant::now();
+
+        let mut clock_id: libc::clockid_t = 0;
+        // SAFETY: FFI call with the handle of a thread not joined yet
+        let ret = unsafe { libc::pthread_getcpuclockid(handle.as_pthread_t(), &mut clock_id) };
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret));
+        }
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: FFI call with a valid timespec
+        if unsafe { libc::clock_gettime(clock_id, &mut ts) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cpu_time = ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64;
+
+        let invalid_data = || io::Error::from(io::ErrorKind::InvalidData);
+        let stat = std::fs::read_to_string(format!("/proc/self/task/{tid}/stat"))?;
+        let stat_field = |field| parse_stat_field(&stat, field).ok_or_else(invalid_data);
+        // SAFETY: FFI call, trivially safe
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+        let tick = 1_000_000_000 / ticks_per_sec;
+        let guest_time = stat_field(STAT_GUEST_TIME)? * tick;
+        let minor_faults = stat_field(STAT_MINFLT)?;
+        let major_faults = stat_field(STAT_MAJFLT)?;
+        let blkio_delay = stat_field(STAT_DELAYACCT_BLKIO_TICKS)? * tick;
+        let schedstat = std::fs::read_to_string(format!("/proc/self/task/{tid}/schedstat"))?;
+        let run_delay = parse_run_delay(&schedstat).ok_or_else(invalid_data)?;
+
+        Ok(VcpuUsageSample {
+            time,
+            cpu_time,
+            guest_time,
+            run_delay,
+            minor_faults,
+            major_faults,
+            blkio_delay,
+        })
+    }
+}
+
+// CPU usage of a vCPU thread, the utilization being computed over the time
+// elapsed since the previous sample.
+#[derive(Default)]
+struct VcpuUsage {
+    // Kernel id of the vCPU thread, set once it started.
+    tid: AtomicI32,
+    last_sample: Mutex<Option<VcpuUsageSample>>,
+}
+
+impl VcpuUsage {
+    fn sample(
+        &self,
+        handle: &thread::JoinHandle<()>,
+    ) -> io::Result<HashMap<&'static str, Wrapping<u64>>> {
+        let tid = self.tid.load(Ordering::Acquire);
+        if tid == 0 {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        let sample = VcpuUsageSample::read(handle, tid)?;
+        let previous = self.last_sample.lock().unwrap().replace(sample);
+
+        let mut counters = HashMap::new();
+        counters.insert("cpu_time_us", Wrapping(sample.cpu_time / 1000));
+        counters.insert("guest_time_us", Wrapping(sample.guest_time / 1000));
+        counters.insert(
+            "host_time_us",
+            Wrapping(sample.cpu_time.saturating_sub(sample.guest_time) / 1000),
+        );
+        counters.insert("run_delay_us", Wrapping(sample.run_delay / 1000));
+        counters.insert("minor_faults", Wrapping(sample.minor_faults));
+        counters.insert("major_faults", Wrapping(sample.major_faults));
+        counters.insert("blkio_delay_us", Wrapping(sample.blkio_delay / 1000));
+
+        let (utilization, guest_utilization, steal, blkio_stall) = match previous {
+            Some(previous) => {
+                let elapsed = sample.time.duration_since(previous.time);
+                (
+                    usage_percent(sample.cpu_time.saturating_sub(previous.cpu_time), elapsed),
+                    usage_percent(
+                        sample.guest_time.saturating_sub(previous.guest_time),
+                        elapsed,
+                    ),
+                    usage_percent(sample.run_delay.saturating_sub(previous.run_delay), elapsed),
+                    usage_percent(
+                        sample.blkio_delay.saturating_sub(previous.blkio_delay),
+                        elapsed,
+                    ),
+                )
+            }
+            None => (0, 0, 0, 0),
+        };
+        counters.insert("utilization_percent", Wrapping(utilization));
+        counters.insert("guest_utilization_percent", Wrapping(guest_utilization));
+        counters.insert("steal_percent", Wrapping(steal));
+        counters.insert("blkio_stall_percent", Wrapping(blkio_stall));
+
+        Ok(counters)
+    }
+}
+
+// Queues the generation of a quote for the buffer the TD shared, the TD
+// polling the status of the buffer for its completion.
+#[cfg(feature = "tdx")]
+fn tdx_get_quote(
+    quote_generator: Option<&TdxQuoteGenerator>,
+    gpa: u64,
+    size: u64,
+) -> TdxExitStatus {
+    let Some(quote_generator) = quote_generator else {
+        warn!("TDG_VP_VMCALL_GET_QUOTE not supported");
+        return TdxExitStatus::InvalidOperand;
+    };
+
+    match quote_generator.get_quote(gpa, size) {
+        Ok(()) => TdxExitStatus::Success,
+        Err(e) => {
+            warn!("Failed to get a quote: {}", e);
+            TdxExitStatus::InvalidOperand
+        }
+    }
+}
+
+/// Run `f` for each of the `items`, spread across a few threads. Creating or
+/// configuring a vCPU takes many ioctls, which would add up on large VMs.
+fn setup_vcpus_in_parallel<T: Sync, R: Send>(
+    items: &[T],
+    f: impl Fn(&T) -> Result<R> + Sync,
+) -> Result<Vec<R>> {
+    let num_threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(MAX_VCPU_SETUP_THREAD_COUNT)
+        .min(items.len());
+    if num_threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = items
+            .chunks(items.len().div_ceil(num_threads))
+            .map(|chunk| s.spawn(move || chunk.iter().map(f).collect::<Result<Vec<R>>>()))
+            .collect();
+
+        let mut results = Vec::with_capacity(items.len());
+        for handle in handles {
+            results.extend(handle.join().map_err(Error::ThreadCleanup)??);
+        }
+        Ok(results)
+    })
+}
+
 impl CpuManager {
     #[allow(unused_variables)]
     #[allow(clippy::too_many_arguments)]
@@ -675,12 +1062,22 @@ impl CpuManager {
         numa_nodes: &NumaNodes,
         #[cfg(feature = "sev_snp")] sev_snp_enabled: bool,
     ) -> Result<Arc<Mutex<CpuManager>>> {
-        if u32::from(config.max_vcpus) > hypervisor.get_max_vcpus() {
+        if config.max_vcpus > hypervisor.get_max_vcpus() {
             return Err(Error::MaximumVcpusExceeded);
         }
 
-        let mut vcpu_states = Vec::with_capacity(usize::from(config.max_vcpus));
-        vcpu_states.resize_with(usize::from(config.max_vcpus), VcpuState::default);
+        // The interrupts of the vCPUs beyond the xAPIC range can only be
+        // routed with 32-bit destination IDs.
+        #[cfg(target_arch = "x86_64")]
+        if x2apic_ids_required(config) {
+            if !hypervisor.x2apic_api_supported() {
+                return Err(Error::X2ApicIdsNotSupported);
+            }
+            vm.enable_x2apic_api().map_err(Error::EnableX2ApicApi)?;
+        }
+
+        let mut vcpu_states = Vec::with_capacity(config.max_vcpus as usize);
+        vcpu_states.resize_with(config.max_vcpus as usize, VcpuState::default);
         let hypervisor_type = hypervisor.hypervisor_type();
         #[cfg(target_arch = "x86_64")]
         let cpu_vendor = hypervisor.get_cpu_vendor();
@@ -717,7 +1114,7 @@ impl CpuManager {
             }
         }
 
-        let proximity_domain_per_cpu: BTreeMap<u8, u32> = {
+        let proximity_domain_per_cpu: BTreeMap<u32, u32> = {
             let mut cpu_list = Vec::new();
             for (proximity_domain, numa_node) in numa_nodes.iter() {
                 for cpu in numa_node.cpus.iter() {
@@ -742,6 +1139,12 @@ impl CpuManager {
         let dynamic = !tdx_enabled;
         #[cfg(not(feature = "tdx"))]
         let dynamic = true;
+        #[cfg(target_arch = "aarch64")]
+        let dynamic = dynamic && vcpu_hotplug_supported(&vm);
+        #[cfg(target_arch = "aarch64")]
+        if !dynamic && config.max_vcpus > config.boot_vcpus {
+            warn!("vCPU hotplug isn't supported, only the boot vCPUs are usable");
+        }
 
         Ok(Arc::new(Mutex::new(CpuManager {
             config: config.clone(),
@@ -751,14 +1154,16 @@ impl CpuManager {
             vm,
             vcpus_kill_signalled: Arc::new(AtomicBool::new(false)),
             vcpus_pause_signalled: Arc::new(AtomicBool::new(false)),
+            vcpus_pause_generation: Arc::new(AtomicU64::new(0)),
             vcpus_kick_signalled: Arc::new(AtomicBool::new(false)),
+            vcpus_failed: Arc::new(AtomicBool::new(false)),
             vcpu_states,
             exit_evt,
             reset_evt,
             #[cfg(feature = "guest_debug")]
             vm_debug_evt,
             selected_cpu: 0,
-            vcpus: Vec::with_capacity(usize::from(config.max_vcpus)),
+            vcpus: Vec::with_capacity(config.max_vcpus as usize),
             seccomp_action,
             vm_ops,
             acpi_address: None,
@@ -768,6 +1173,12 @@ impl CpuManager {
             hypervisor: hypervisor.clone(),
             #[cfg(feature = "sev_snp")]
             sev_snp_enabled,
+            #[cfg(feature = "sev_snp")]
+            vmsa_gpas: BTreeMap::new(),
+            #[cfg(target_arch = "x86_64")]
+            tsc_khz: None,
+            #[cfg(feature = "tdx")]
+            tdx_quote_generator: None,
         })))
     }
 
@@ -796,6 +1207,8 @@ impl CpuManager {
                     #[cfg(feature = "tdx")]
                     tdx,
                     amx: self.config.features.amx,
+                    cet: self.config.features.cet,
+                    msi_ext_dest_id: x2apic_ids_required(&self.config),
                 },
             )
             .map_err(Error::CommonCpuId)?
@@ -804,19 +1217,19 @@ impl CpuManager {
         Ok(())
     }
 
-    fn create_vcpu(&mut self, cpu_id: u8, snapshot: Option<Snapshot>) -> Result<Arc<Mutex<Vcpu>>> {
+    fn create_vcpu(&self, cpu_id: u32, snapshot: Option<Snapshot>) -> Result<Vcpu> {
         info!("Creating vCPU: cpu_id = {}", cpu_id);
 
         #[cfg(target_arch = "x86_64")]
         let topology = self.get_vcpu_topology();
         #[cfg(target_arch = "x86_64")]
-        let x2apic_id = arch::x86_64::get_x2apic_id(cpu_id as u32, topology);
+        let x2apic_id = arch::x86_64::get_x2apic_id(cpu_id, topology);
         #[cfg(target_arch = "aarch64")]
-        let x2apic_id = cpu_id as u32;
+        let x2apic_id = cpu_id;
 
         let mut vcpu = Vcpu::new(
             cpu_id,
-            x2apic_id as u8,
+            x2apic_id,
             &self.vm,
             Some(self.vm_ops.clone()),
             #[cfg(target_arch = "x86_64")]
@@ -831,6 +1244,22 @@ impl CpuManager {
             let state: CpuState = snapshot.to_state().map_err(|e| {
                 Error::VcpuCreate(anyhow!("Could not get vCPU state from snapshot {:?}", e))
             })?;
+
+            // Until its state is set, the vCPU runs at the TSC frequency of
+            // this host, from which the guest one must be reachable.
+            #[cfg(target_arch = "x86_64")]
+            if let (Some(guest_khz), Some(host_khz)) = (
+                state.tsc_khz(),
+                vcpu.vcpu.tsc_khz().map_err(Error::GetTscInfo)?,
+            ) {
+                arch::x86_64::check_tsc_compatibility(
+                    guest_khz,
+                    host_khz,
+                    self.hypervisor.tsc_scaling_supported(),
+                )
+                .map_err(Error::TscIncompatible)?;
+            }
+
             vcpu.vcpu
                 .set_state(&state)
                 .map_err(|e| Error::VcpuCreate(anyhow!("Could not set the vCPU state {:?}", e)))?;
@@ -838,6 +1267,28 @@ impl CpuManager {
             vcpu.saved_state = Some(state);
         }
 
+        Ok(vcpu)
+    }
+
+    fn add_vcpu(&mut self, vcpu: Vcpu) -> Result<Arc<Mutex<Vcpu>>> {
+        #[cfg(target_arch = "x86_64")]
+        {
+            // The first vCPU defines the guest TSC frequency, either the host
+            // one or the one restored from the snapshot, which create_vcpu()
+            // has already applied. Later vCPUs, such as hot-plugged ones, must
+            // run at the same frequency.
+            let tsc_khz = vcpu.vcpu.tsc_khz().map_err(Error::GetTscInfo)?;
+            match (self.tsc_khz, tsc_khz) {
+                (None, _) => self.tsc_khz = tsc_khz,
+                (Some(guest_khz), Some(vcpu_khz)) if guest_khz != vcpu_khz => {
+                    vcpu.vcpu
+                        .set_tsc_khz(guest_khz)
+                        .map_err(Error::SetTscFrequency)?;
+                }
+                _ => {}
+            }
+        }
+
         let vcpu = Arc::new(Mutex::new(vcpu));
 
         // Adding vCPU to the CpuManager's vCPU list.
@@ -856,11 +1307,22 @@ impl CpuManager {
         #[cfg(feature = "sev_snp")]
         if self.sev_snp_enabled {
             if let Some((kernel_entry_point, _)) = boot_setup {
-                info!("Setting SEV Control Register - no op");
-                #[cfg(not(feature = "kvm"))]
-                vcpu.set_sev_control_register(
-                    kernel_entry_point.entry_addr.0 / crate::igvm::HV_PAGE_SIZE,
-                )?;
+                // Each vCPU starts from its own VMSA, the entry point being
+                // the one of the boot vCPU. The APs without a VMSA are left
+                // for the guest to start through AP creation requests.
+                let vmsa_gpa = self
+                    .vmsa_gpas
+                    .get(&vcpu.id)
+                    .copied()
+                    .or_else(|| (vcpu.id == 0).then_some(kernel_entry_point.entry_addr.0));
+                if let Some(vmsa_gpa) = vmsa_gpa {
+                    info!(
+                        "Setting SEV Control Register of vCPU {} - VMSA 0x{:x}",
+                        vcpu.id, vmsa_gpa
+                    );
+                    #[cfg(not(feature = "kvm"))]
+                    vcpu.set_sev_control_register(vmsa_gpa / crate::igvm::HV_PAGE_SIZE)?;
+                }
             }
 
             //TODO: set up CPUID for SEV-SNP
@@ -876,7 +1338,14 @@ impl CpuManager {
 
         #[cfg(target_arch = "x86_64")]
         let topology = self.config.topology.clone().map_or_else(
-            || Some((1, self.boot_vcpus(), 1)),
+            || {
+                // A flat topology only fits in the CPUID leaves as long as all
+                // the vCPUs fit in a single die, the APIC ID is the vCPU id
+                // otherwise.
+                u8::try_from(self.boot_vcpus())
+                    .ok()
+                    .map(|cores| (1, cores, 1))
+            },
             |t| Some((t.threads_per_core, t.cores_per_die, t.dies_per_package)),
         );
         #[cfg(target_arch = "x86_64")]
@@ -893,10 +1362,21 @@ impl CpuManager {
         Ok(())
     }
 
+    /// Configure the given vCPUs, several of them at once.
+    pub fn configure_vcpus(
+        &self,
+        vcpus: &[Arc<Mutex<Vcpu>>],
+        boot_setup: Option<(EntryPoint, &GuestMemoryAtomic<GuestMemoryMmap>)>,
+    ) -> Result<()> {
+        setup_vcpus_in_parallel(vcpus, |vcpu| self.configure_vcpu(vcpu.clone(), boot_setup))?;
+
+        Ok(())
+    }
+
     /// Only create new vCPUs if there aren't any inactive ones to reuse
     fn create_vcpus(
         &mut self,
-        desired_vcpus: u8,
+        desired_vcpus: u32,
         snapshot: Option<Snapshot>,
     ) -> Result<Vec<Arc<Mutex<Vcpu>>>> {
         let mut vcpus: Vec<Arc<Mutex<Vcpu>>> = vec![];
@@ -913,13 +1393,21 @@ impl CpuManager {
         }
 
         // Only create vCPUs in excess of all the allocated vCPUs.
-        for cpu_id in self.vcpus.len() as u8..desired_vcpus {
-            vcpus.push(self.create_vcpu(
-                cpu_id,
+        let cpu_ids: Vec<u32> = (self.vcpus.len() as u32..desired_vcpus).collect();
+        let this = &*self;
+        let created = setup_vcpus_in_parallel(&cpu_ids, |cpu_id| {
+            this.create_vcpu(
+                *cpu_id,
                 // TODO: The special format of the CPU id can be removed once
                 // ready to break live upgrade.
                 snapshot_from_id(snapshot.as_ref(), cpu_id.to_string().as_str()),
-            )?);
+            )
+        })?;
+
+        // The vCPUs are added in order, the first one defining the guest TSC
+        // frequency.
+        for vcpu in created {
+            vcpus.push(self.add_vcpu(vcpu)?);
         }
 
         Ok(vcpus)
@@ -948,10 +1436,15 @@ impl CpuManager {
         self.vcpus.clone()
     }
 
+    /// Whether a vCPU triple faulted, failed to run or panicked.
+    pub fn vcpus_failed(&self) -> bool {
+        self.vcpus_failed.load(Ordering::SeqCst)
+    }
+
     fn start_vcpu(
         &mut self,
         vcpu: Arc<Mutex<Vcpu>>,
-        vcpu_id: u8,
+        vcpu_id: u32,
         vcpu_thread_barrier: Arc<Barrier>,
         inserting: bool,
     ) -> Result<()> {
@@ -962,16 +1455,24 @@ impl CpuManager {
         #[cfg(feature = "guest_debug")]
         let vm_debug_evt = self.vm_debug_evt.try_clone().unwrap();
         let panic_exit_evt = self.exit_evt.try_clone().unwrap();
+        let vcpus_failed = self.vcpus_failed.clone();
+        let panic_vcpus_failed = self.vcpus_failed.clone();
         let vcpu_kill_signalled = self.vcpus_kill_signalled.clone();
         let vcpu_pause_signalled = self.vcpus_pause_signalled.clone();
+        let vcpu_pause_generation = self.vcpus_pause_generation.clone();
         let vcpu_kick_signalled = self.vcpus_kick_signalled.clone();
+        #[cfg(feature = "tdx")]
+        let tdx_quote_generator = self.tdx_quote_generator.clone();
 
-        let vcpu_kill = self.vcpu_states[usize::from(vcpu_id)].kill.clone();
-        let vcpu_run_interrupted = self.vcpu_states[usize::from(vcpu_id)]
+        let vcpu_kill = self.vcpu_states[vcpu_id as usize].kill.clone();
+        let vcpu_run_interrupted = self.vcpu_states[vcpu_id as usize]
             .vcpu_run_interrupted
             .clone();
         let panic_vcpu_run_interrupted = vcpu_run_interrupted.clone();
-        let vcpu_paused = self.vcpu_states[usize::from(vcpu_id)].paused.clone();
+        let vcpu_paused_generation = self.vcpu_states[vcpu_id as usize].paused_generation.clone();
+        // The usage of the previous thread of the vCPU, if any, is dropped.
+        let vcpu_usage = Arc::new(VcpuUsage::default());
+        self.vcpu_states[vcpu_id as usize].usage = vcpu_usage.clone();
 
         // Prepare the CPU set the current vCPU is expected to run onto.
         let cpuset = self.affinity.get(&vcpu_id).map(|host_cpus| {
@@ -1003,6 +1504,11 @@ impl CpuManager {
             thread::Builder::new()
                 .name(format!("vcpu{vcpu_id}"))
                 .spawn(move || {
+                    // SAFETY: FFI call, trivially safe
+                    vcpu_usage
+                        .tid
+                        .store(unsafe { libc::gettid() }, Ordering::Release);
+
                     // Schedule the thread to run on the expected CPU set
                     if let Some(cpuset) = cpuset.as_ref() {
                         // SAFETY: FFI call with correct arguments
@@ -1041,6 +1547,7 @@ impl CpuManager {
                     vcpu_thread_barrier.wait();
 
                     std::panic::catch_unwind(move || {
+                        let mut exit_history = ExitHistory::new();
                         loop {
                             // If we are being told to pause, we park the thread
                             // until the pause boolean is toggled.
@@ -1082,8 +1589,16 @@ impl CpuManager {
 
                                 vcpu_run_interrupted.store(true, Ordering::SeqCst);
 
-                                vcpu_paused.store(true, Ordering::SeqCst);
-                                while vcpu_pause_signalled.load(Ordering::SeqCst) {
+                                // Acknowledge the pause request, again if another
+                                // one came in while parked from the previous one.
+                                loop {
+                                    vcpu_paused_generation.store(
+                                        vcpu_pause_generation.load(Ordering::SeqCst),
+                                        Ordering::SeqCst,
+                                    );
+                                    if !vcpu_pause_signalled.load(Ordering::SeqCst) {
+                                        break;
+                                    }
                                     thread::park();
                                 }
                                 vcpu_run_interrupted.store(false, Ordering::SeqCst);
@@ -1113,8 +1628,7 @@ impl CpuManager {
                             let mut vcpu = vcpu.lock().unwrap();
                             #[cfg(not(feature = "tdx"))]
                             let vcpu = vcpu.lock().unwrap();
-                            // vcpu.run() returns false on a triple-fault so trigger a reset
-                            match vcpu.run() {
+                            match vcpu.run().inspect(|exit| exit_history.record(*exit)) {
                                 Ok(run) => match run {
                                     #[cfg(feature = "kvm")]
                                     VmExit::Debug => {
@@ -1145,6 +1659,14 @@ impl CpuManager {
                                         reset_evt.write(1).unwrap();
                                         break;
                                     }
+                                    #[cfg(target_arch = "x86_64")]
+                                    VmExit::TripleFault => {
+                                        vcpu.report_triple_fault(&exit_history);
+                                        vcpus_failed.store(true, Ordering::SeqCst);
+                                        vcpu_run_interrupted.store(true, Ordering::SeqCst);
+                                        reset_evt.write(1).unwrap();
+                                        break;
+                                    }
                                     VmExit::Shutdown => {
                                         info!("VmExit::Shutdown");
                                         vcpu_run_interrupted.store(true, Ordering::SeqCst);
@@ -1154,16 +1676,24 @@ impl CpuManager {
                                     #[cfg(feature = "tdx")]
                                     VmExit::Tdx => {
                                         if let Some(vcpu) = Arc::get_mut(&mut vcpu.vcpu) {
-                                            match vcpu.get_tdx_exit_details() {
+                                            let status = match vcpu.get_tdx_exit_details() {
                                                 Ok(details) => match details {
-                                                    TdxExitDetails::GetQuote => warn!("TDG_VP_VMCALL_GET_QUOTE not supported"),
+                                                    TdxExitDetails::GetQuote { gpa, size } => {
+                                                        tdx_get_quote(tdx_quote_generator.as_deref(), gpa, size)
+                                                    }
+                                                    // The guest polls the status of the quote
+                                                    // buffer instead.
                                                     TdxExitDetails::SetupEventNotifyInterrupt => {
-                                                        warn!("TDG_VP_VMCALL_SETUP_EVENT_NOTIFY_INTERRUPT not supported")
+                                                        warn!("TDG_VP_VMCALL_SETUP_EVENT_NOTIFY_INTERRUPT not supported");
+                                                        TdxExitStatus::InvalidOperand
                                                     }
                                                 },
-                                                Err(e) => error!("Unexpected TDX VMCALL: {}", e),
-                                            }
-                                            vcpu.set_tdx_status(TdxExitStatus::InvalidOperand);
+                                                Err(e) => {
+                                                    error!("Unexpected TDX VMCALL: {}", e);
+                                                    TdxExitStatus::InvalidOperand
+                                                }
+                                            };
+                                            vcpu.set_tdx_status(status);
                                         } else {
                                             // We should never reach this code as
                                             // this means the design from the code
@@ -1175,6 +1705,7 @@ impl CpuManager {
 
                                 Err(e) => {
                                     error!("VCPU generated error: {:?}", Error::VcpuRun(e.into()));
+                                    vcpus_failed.store(true, Ordering::SeqCst);
                                     vcpu_run_interrupted.store(true, Ordering::SeqCst);
                                     exit_evt.write(1).unwrap();
                                     break;
@@ -1192,6 +1723,7 @@ impl CpuManager {
                     })
                     .or_else(|_| {
                         panic_vcpu_run_interrupted.store(true, Ordering::SeqCst);
+                        panic_vcpus_failed.store(true, Ordering::SeqCst);
                         error!("vCPU thread panicked");
                         panic_exit_evt.write(1)
                     })
@@ -1202,8 +1734,8 @@ impl CpuManager {
 
         // On hot plug calls into this function entry_point is None. It is for
         // those hotplug CPU additions that we need to set the inserting flag.
-        self.vcpu_states[usize::from(vcpu_id)].handle = handle;
-        self.vcpu_states[usize::from(vcpu_id)].inserting = inserting;
+        self.vcpu_states[vcpu_id as usize].handle = handle;
+        self.vcpu_states[vcpu_id as usize].inserting = inserting;
 
         Ok(())
     }
@@ -1211,7 +1743,7 @@ impl CpuManager {
     /// Start up as many vCPUs threads as needed to reach `desired_vcpus`
     fn activate_vcpus(
         &mut self,
-        desired_vcpus: u8,
+        desired_vcpus: u32,
         inserting: bool,
         paused: Option<bool>,
     ) -> Result<()> {
@@ -1246,11 +1778,11 @@ impl CpuManager {
         Ok(())
     }
 
-    fn mark_vcpus_for_removal(&mut self, desired_vcpus: u8) {
+    fn mark_vcpus_for_removal(&mut self, desired_vcpus: u32) {
         // Mark vCPUs for removal, actual removal happens on ejection
         for cpu_id in desired_vcpus..self.present_vcpus() {
-            self.vcpu_states[usize::from(cpu_id)].removing = true;
-            self.vcpu_states[usize::from(cpu_id)]
+            self.vcpu_states[cpu_id as usize].removing = true;
+            self.vcpu_states[cpu_id as usize]
                 .pending_removal
                 .store(true, Ordering::SeqCst);
         }
@@ -1265,9 +1797,9 @@ impl CpuManager {
         false
     }
 
-    fn remove_vcpu(&mut self, cpu_id: u8) -> Result<()> {
+    fn remove_vcpu(&mut self, cpu_id: u32) -> Result<()> {
         info!("Removing vCPU: cpu_id = {}", cpu_id);
-        let state = &mut self.vcpu_states[usize::from(cpu_id)];
+        let state = &mut self.vcpu_states[cpu_id as usize];
         state.kill.store(true, Ordering::SeqCst);
         state.signal_thread();
         state.join_thread()?;
@@ -1286,6 +1818,14 @@ impl CpuManager {
     ) -> Result<Vec<Arc<Mutex<Vcpu>>>> {
         trace_scoped!("create_boot_vcpus");
 
+        // The vCPUs can't be created once the vGIC is initialized, the
+        // hotpluggable ones are created upfront and only started when they
+        // are plugged.
+        #[cfg(target_arch = "aarch64")]
+        if self.dynamic {
+            return self.create_vcpus(self.max_vcpus(), snapshot);
+        }
+
         self.create_vcpus(self.boot_vcpus(), snapshot)
     }
 
@@ -1295,7 +1835,13 @@ impl CpuManager {
     }
 
     pub fn start_restored_vcpus(&mut self) -> Result<()> {
-        self.activate_vcpus(self.vcpus.len() as u8, false, Some(true))
+        // All the possible vCPUs exist on AArch64, only the plugged ones run.
+        #[cfg(target_arch = "aarch64")]
+        let present_vcpus = self.boot_vcpus();
+        #[cfg(target_arch = "x86_64")]
+        let present_vcpus = self.vcpus.len() as u32;
+
+        self.activate_vcpus(present_vcpus, false, Some(true))
             .map_err(|e| {
                 Error::StartRestoreVcpu(anyhow!("Failed to start restored vCPUs: {:#?}", e))
             })?;
@@ -1303,7 +1849,7 @@ impl CpuManager {
         Ok(())
     }
 
-    pub fn resize(&mut self, desired_vcpus: u8) -> Result<bool> {
+    pub fn resize(&mut self, desired_vcpus: u32) -> Result<bool> {
         if desired_vcpus.cmp(&self.present_vcpus()) == cmp::Ordering::Equal {
             return Ok(false);
         }
@@ -1319,9 +1865,7 @@ impl CpuManager {
         match desired_vcpus.cmp(&self.present_vcpus()) {
             cmp::Ordering::Greater => {
                 let vcpus = self.create_vcpus(desired_vcpus, None)?;
-                for vcpu in vcpus {
-                    self.configure_vcpu(vcpu, None)?
-                }
+                self.configure_vcpus(&vcpus, None)?;
                 self.activate_vcpus(desired_vcpus, true, None)?;
                 Ok(true)
             }
@@ -1372,11 +1916,30 @@ impl CpuManager {
         Ok(())
     }
 
-    pub fn boot_vcpus(&self) -> u8 {
+    /// Samples the CPU usage of the running vCPUs, the utilization being
+    /// computed since the previous sample.
+    pub fn counters(&self) -> HashMap<String, HashMap<&'static str, Wrapping<u64>>> {
+        let mut counters = HashMap::new();
+        for (vcpu_id, state) in self.vcpu_states.iter().enumerate() {
+            let Some(handle) = state.handle.as_ref() else {
+                continue;
+            };
+            match state.usage.sample(handle) {
+                Ok(vcpu_counters) => {
+                    counters.insert(format!("vcpu{vcpu_id}"), vcpu_counters);
+                }
+                Err(e) => debug!("Failed to sample the usage of vCPU {}: {}", vcpu_id, e),
+            }
+        }
+
+        counters
+    }
+
+    pub fn boot_vcpus(&self) -> u32 {
         self.config.boot_vcpus
     }
 
-    pub fn max_vcpus(&self) -> u8 {
+    pub fn max_vcpus(&self) -> u32 {
         self.config.max_vcpus
     }
 
@@ -1386,20 +1949,34 @@ impl CpuManager {
         self.cpuid.clone()
     }
 
-    fn present_vcpus(&self) -> u8 {
+    fn present_vcpus(&self) -> u32 {
         self.vcpu_states
             .iter()
-            .fold(0, |acc, state| acc + state.active() as u8)
+            .fold(0, |acc, state| acc + state.active() as u32)
     }
 
+    /// MPIDRs of the vCPUs the VM boots with, the hotpluggable ones being
+    /// only described through ACPI.
     #[cfg(target_arch = "aarch64")]
     pub fn get_mpidrs(&self) -> Vec<u64> {
         self.vcpus
             .iter()
+            .take(self.boot_vcpus() as usize)
             .map(|cpu| cpu.lock().unwrap().get_mpidr())
             .collect()
     }
 
+    /// Number of vCPUs the vGIC needs a redistributor for, the hotpluggable
+    /// ones included.
+    #[cfg(target_arch = "aarch64")]
+    pub fn vgic_vcpu_count(&self) -> u32 {
+        if self.dynamic {
+            self.max_vcpus()
+        } else {
+            self.boot_vcpus()
+        }
+    }
+
     #[cfg(target_arch = "aarch64")]
     pub fn get_saved_states(&self) -> Vec<CpuState> {
         self.vcpus
@@ -1426,12 +2003,12 @@ impl CpuManager {
             madt.write(36, arch::layout::APIC_START.0);
 
             for cpu in 0..self.config.max_vcpus {
-                let x2apic_id = get_x2apic_id(cpu.into(), self.get_vcpu_topology());
+                let x2apic_id = get_x2apic_id(cpu, self.get_vcpu_topology());
 
                 let lapic = LocalX2Apic {
                     r#type: acpi::ACPI_X2APIC_PROCESSOR,
                     length: 16,
-                    processor_id: cpu.into(),
+                    processor_id: cpu,
                     apic_id: x2apic_id,
                     flags: if cpu < self.config.boot_vcpus {
                         1 << MADT_CPU_ENABLE_FLAG
@@ -1469,7 +2046,7 @@ impl CpuManager {
              */
 
             // See section 5.2.12.14 GIC CPU Interface (GICC) Structure in ACPI spec.
-            for cpu in 0..self.config.boot_vcpus {
+            for cpu in 0..self.vgic_vcpu_count() {
                 let vcpu = &self.vcpus[cpu as usize];
                 let mpidr = vcpu.lock().unwrap().get_mpidr();
                 /* ARMv8 MPIDR format:
@@ -1485,9 +2062,9 @@ impl CpuManager {
                     r#type: acpi::ACPI_APIC_GENERIC_CPU_INTERFACE,
                     length: 80,
                     reserved0: 0,
-                    cpu_interface_number: cpu as u32,
-                    uid: cpu as u32,
-                    flags: 1,
+                    cpu_interface_number: cpu,
+                    uid: cpu,
+                    flags: gicc_flags(cpu, self.config.boot_vcpus, self.dynamic),
                     parking_version: 0,
                     performance_interrupt: 0,
                     parked_address: 0,
@@ -1504,7 +2081,7 @@ impl CpuManager {
 
                 madt.append(gicc);
             }
-            let vgic_config = Gic::create_default_config(self.config.boot_vcpus.into());
+            let vgic_config = Gic::create_default_config(self.vgic_vcpu_count().into());
 
             // GIC Distributor structure. See section 5.2.12.15 in ACPI spec.
             let gicd = GicD {
@@ -1554,8 +2131,10 @@ impl CpuManager {
         // If topology is not specified, the default setting is:
         // 1 package, multiple cores, 1 thread per core
         // This is also the behavior when PPTT is missing.
-        let (threads_per_core, cores_per_package, packages) =
-            self.get_vcpu_topology().unwrap_or((1, self.max_vcpus(), 1));
+        let (threads_per_core, cores_per_package, packages) = self
+            .get_vcpu_topology()
+            .map(|(t, c, p)| (u32::from(t), u32::from(c), u32::from(p)))
+            .unwrap_or((1, self.max_vcpus(), 1));
 
         let mut pptt = Sdt::new(*b"PPTT", 36, 2, *b"CLOUDH", *b"CHPPTT  ", 1);
 
@@ -1568,7 +2147,7 @@ impl CpuManager {
                     reserved: 0,
                     flags: 0x2,
                     parent: 0,
-                    acpi_processor_id: cluster_idx as u32,
+                    acpi_processor_id: cluster_idx,
                     num_private_resources: 0,
                 };
                 pptt.append(cluster_hierarchy_node);
@@ -1583,7 +2162,7 @@ impl CpuManager {
                             reserved: 0,
                             flags: 0x2,
                             parent: cluster_offset as u32,
-                            acpi_processor_id: core_idx as u32,
+                            acpi_processor_id: core_idx,
                             num_private_resources: 0,
                         };
                         pptt.append(core_hierarchy_node);
@@ -1624,8 +2203,8 @@ impl CpuManager {
     }
 
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-    fn create_standard_regs(&self, cpu_id: u8) -> StandardRegisters {
-        self.vcpus[usize::from(cpu_id)]
+    fn create_standard_regs(&self, cpu_id: u32) -> StandardRegisters {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1633,8 +2212,8 @@ impl CpuManager {
     }
 
     #[cfg(feature = "guest_debug")]
-    fn get_regs(&self, cpu_id: u8) -> Result<StandardRegisters> {
-        self.vcpus[usize::from(cpu_id)]
+    fn get_regs(&self, cpu_id: u32) -> Result<StandardRegisters> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1643,8 +2222,8 @@ impl CpuManager {
     }
 
     #[cfg(feature = "guest_debug")]
-    fn set_regs(&self, cpu_id: u8, regs: &StandardRegisters) -> Result<()> {
-        self.vcpus[usize::from(cpu_id)]
+    fn set_regs(&self, cpu_id: u32, regs: &StandardRegisters) -> Result<()> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1653,8 +2232,8 @@ impl CpuManager {
     }
 
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-    fn get_sregs(&self, cpu_id: u8) -> Result<SpecialRegisters> {
-        self.vcpus[usize::from(cpu_id)]
+    fn get_sregs(&self, cpu_id: u32) -> Result<SpecialRegisters> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1663,8 +2242,8 @@ impl CpuManager {
     }
 
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-    fn set_sregs(&self, cpu_id: u8, sregs: &SpecialRegisters) -> Result<()> {
-        self.vcpus[usize::from(cpu_id)]
+    fn set_sregs(&self, cpu_id: u32, sregs: &SpecialRegisters) -> Result<()> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1676,10 +2255,10 @@ impl CpuManager {
     fn translate_gva(
         &self,
         _guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
-        cpu_id: u8,
+        cpu_id: u32,
         gva: u64,
     ) -> Result<u64> {
-        let (gpa, _) = self.vcpus[usize::from(cpu_id)]
+        let (gpa, _) = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1708,22 +2287,22 @@ impl CpuManager {
     fn translate_gva(
         &self,
         guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
-        cpu_id: u8,
+        cpu_id: u32,
         gva: u64,
     ) -> Result<u64> {
-        let tcr_el1: u64 = self.vcpus[usize::from(cpu_id)]
+        let tcr_el1: u64 = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
             .get_sys_reg(regs::TCR_EL1)
             .map_err(|e| Error::TranslateVirtualAddress(e.into()))?;
-        let ttbr1_el1: u64 = self.vcpus[usize::from(cpu_id)]
+        let ttbr1_el1: u64 = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
             .get_sys_reg(regs::TTBR1_EL1)
             .map_err(|e| Error::TranslateVirtualAddress(e.into()))?;
-        let id_aa64mmfr0_el1: u64 = self.vcpus[usize::from(cpu_id)]
+        let id_aa64mmfr0_el1: u64 = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1865,26 +2444,53 @@ impl CpuManager {
         self.interrupt_controller = Some(interrupt_controller);
     }
 
+    #[cfg(feature = "tdx")]
+    pub(crate) fn set_tdx_quote_generator(&mut self, quote_generator: Arc<TdxQuoteGenerator>) {
+        self.tdx_quote_generator = Some(quote_generator);
+    }
+
+    /// Physical address width of the guest, as reported by CPUID.
+    #[cfg(target_arch = "x86_64")]
+    pub fn physical_bits(&self) -> u8 {
+        physical_bits(&self.hypervisor, self.config.max_phys_bits)
+    }
+
     pub(crate) fn vcpus_kill_signalled(&self) -> &Arc<AtomicBool> {
         &self.vcpus_kill_signalled
     }
 
-    #[cfg(feature = "igvm")]
+    /// Values of the CPUID leaf `eax`, `ecx` of the vCPU for the given XCR0
+    /// and XSS, as computed by the hypervisor. Until the vCPU is created, or
+    /// when the hypervisor can't compute them, these are the values set on
+    /// the vCPUs, a leaf left out of them reading as zeros.
+    #[cfg(all(target_arch = "x86_64", feature = "igvm"))]
     pub(crate) fn get_cpuid_leaf(
         &self,
-        cpu_id: u8,
+        cpu_id: u32,
         eax: u32,
         ecx: u32,
         xfem: u64,
         xss: u64,
-    ) -> Result<[u32; 4]> {
-        let leaf_info = self.vcpus[usize::from(cpu_id)]
-            .lock()
-            .unwrap()
-            .vcpu
-            .get_cpuid_values(eax, ecx, xfem, xss)
-            .unwrap();
-        Ok(leaf_info)
+    ) -> [u32; 4] {
+        if let Some(vcpu) = self.vcpus.get(cpu_id as usize) {
+            match vcpu
+                .lock()
+                .unwrap()
+                .vcpu
+                .get_cpuid_values(eax, ecx, xfem, xss)
+            {
+                Ok(leaf) => return leaf,
+                Err(e) => debug!("Using the common CPUID leaf 0x{:x}: {}", eax, e),
+            }
+        }
+
+        self.cpuid
+            .iter()
+            .find(|entry| {
+                entry.function == eax
+                    && (entry.flags & CPUID_FLAG_VALID_INDEX == 0 || entry.index == ecx)
+            })
+            .map_or([0; 4], |entry| [entry.eax, entry.ebx, entry.ecx, entry.edx])
     }
 
     #[cfg(feature = "sev_snp")]
@@ -1892,6 +2498,26 @@ impl CpuManager {
         self.sev_snp_enabled
     }
 
+    #[cfg(feature = "sev_snp")]
+    pub(crate) fn set_vmsa_gpas(&mut self, vmsa_gpas: BTreeMap<u32, u64>) {
+        self.vmsa_gpas = vmsa_gpas;
+    }
+
+    /// Collect the TSC frequency and offset of every vCPU. This issues vCPU
+    /// ioctls, so it must only be called while the vCPUs are paused.
+    #[cfg(target_arch = "x86_64")]
+    pub(crate) fn tsc_info(&self) -> Result<Vec<VcpuTscInfo>> {
+        let mut info = Vec::with_capacity(self.vcpus.len());
+        for vcpu in self.vcpus.iter() {
+            let vcpu = vcpu.lock().unwrap();
+            info.push(VcpuTscInfo {
+                tsc_khz: vcpu.vcpu.tsc_khz().map_err(Error::GetTscInfo)?,
+                tsc_offset: vcpu.vcpu.tsc_offset().map_err(Error::GetTscInfo)?,
+            });
+        }
+        Ok(info)
+    }
+
     pub(crate) fn nmi(&self) -> Result<()> {
         self.vcpus_kick_signalled.store(true, Ordering::SeqCst);
 
@@ -1906,7 +2532,7 @@ impl CpuManager {
 }
 
 struct Cpu {
-    cpu_id: u8,
+    cpu_id: u32,
     proximity_domain: u32,
     dynamic: bool,
     #[cfg(target_arch = "x86_64")]
@@ -1919,15 +2545,57 @@ const MADT_CPU_ENABLE_FLAG: usize = 0;
 #[cfg(target_arch = "x86_64")]
 const MADT_CPU_ONLINE_CAPABLE_FLAG: usize = 1;
 
+#[cfg(target_arch = "aarch64")]
+const GICC_ENABLED_FLAG: usize = 0;
+
+#[cfg(target_arch = "aarch64")]
+const GICC_ONLINE_CAPABLE_FLAG: usize = 3;
+
+/// GICC flags of a vCPU, the ones which are not present at boot being
+/// marked online capable so that the guest can enable them later on.
+#[cfg(target_arch = "aarch64")]
+fn gicc_flags(cpu: u32, boot_vcpus: u32, dynamic: bool) -> u32 {
+    if cpu < boot_vcpus {
+        1 << GICC_ENABLED_FLAG
+    } else if dynamic {
+        1 << GICC_ONLINE_CAPABLE_FLAG
+    } else {
+        0
+    }
+}
+
+/// vCPU hotplug relies on the hypervisor turning the vCPUs on and off
+/// through PSCI on its own, the VMM only starting and stopping their threads.
+#[cfg(target_arch = "aarch64")]
+fn vcpu_hotplug_supported(vm: &Arc<dyn hypervisor::Vm>) -> bool {
+    vm.as_any()
+        .downcast_ref::<hypervisor::kvm::KvmVm>()
+        .map(|vm| vm.check_extension(Cap::ArmPsci02) && vm.check_extension(Cap::MpState))
+        .unwrap_or(false)
+}
+
+/// Whether some vCPUs get an APIC ID beyond the xAPIC range, which only
+/// x2APIC and the extended MSI destination ID can describe.
+#[cfg(target_arch = "x86_64")]
+pub fn x2apic_ids_required(config: &CpusConfig) -> bool {
+    arch::x86_64::x2apic_ids_required(
+        config.max_vcpus,
+        config
+            .topology
+            .as_ref()
+            .map(|t| (t.threads_per_core, t.cores_per_die, t.packages)),
+    )
+}
+
 impl Cpu {
     #[cfg(target_arch = "x86_64")]
     fn generate_mat(&self) -> Vec<u8> {
-        let x2apic_id = arch::x86_64::get_x2apic_id(self.cpu_id.into(), self.topology);
+        let x2apic_id = arch::x86_64::get_x2apic_id(self.cpu_id, self.topology);
 
         let lapic = LocalX2Apic {
             r#type: crate::acpi::ACPI_X2APIC_PROCESSOR,
             length: 16,
-            processor_id: self.cpu_id.into(),
+            processor_id: self.cpu_id,
             apic_id: x2apic_id,
             flags: 1 << MADT_CPU_ENABLE_FLAG,
             _reserved: 0,
@@ -1945,14 +2613,15 @@ impl Aml for Cpu {
     fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
         #[cfg(target_arch = "x86_64")]
         let mat_data: Vec<u8> = self.generate_mat();
+        // Let the AML integers use the shortest encoding for the vCPU id.
+        let cpu_id = self.cpu_id as usize;
         #[allow(clippy::if_same_then_else)]
         if self.dynamic {
             aml::Device::new(
                 format!("C{:03X}", self.cpu_id).as_str().into(),
                 vec![
                     &aml::Name::new("_HID".into(), &"ACPI0007"),
-                    &aml::Name::new("_UID".into(), &self.cpu_id),
-                    // Currently, AArch64 cannot support following fields.
+                    &aml::Name::new("_UID".into(), &cpu_id),
                     /*
                     _STA return value:
                     Bit [0] – Set if the device is present.
@@ -1962,7 +2631,6 @@ impl Aml for Cpu {
                     Bit [4] – Set if the battery is present.
                     Bits [31:5] – Reserved (must be cleared).
                     */
-                    #[cfg(target_arch = "x86_64")]
                     &aml::Method::new(
                         "_STA".into(),
                         0,
@@ -1970,7 +2638,7 @@ impl Aml for Cpu {
                         // Call into CSTA method which will interrogate device
                         vec![&aml::Return::new(&aml::MethodCall::new(
                             "CSTA".into(),
-                            vec![&self.cpu_id],
+                            vec![&cpu_id],
                         ))],
                     ),
                     &aml::Method::new(
@@ -1985,13 +2653,12 @@ impl Aml for Cpu {
                     #[cfg(target_arch = "x86_64")]
                     &aml::Name::new("_MAT".into(), &aml::BufferData::new(mat_data)),
                     // Trigger CPU ejection
-                    #[cfg(target_arch = "x86_64")]
                     &aml::Method::new(
                         "_EJ0".into(),
                         1,
                         false,
                         // Call into CEJ0 method which will actually eject device
-                        vec![&aml::MethodCall::new("CEJ0".into(), vec![&self.cpu_id])],
+                        vec![&aml::MethodCall::new("CEJ0".into(), vec![&cpu_id])],
                     ),
                 ],
             )
@@ -2001,7 +2668,7 @@ impl Aml for Cpu {
                 format!("C{:03X}", self.cpu_id).as_str().into(),
                 vec![
                     &aml::Name::new("_HID".into(), &"ACPI0007"),
-                    &aml::Name::new("_UID".into(), &self.cpu_id),
+                    &aml::Name::new("_UID".into(), &cpu_id),
                     #[cfg(target_arch = "x86_64")]
                     &aml::Method::new(
                         "_STA".into(),
@@ -2029,14 +2696,14 @@ impl Aml for Cpu {
 }
 
 struct CpuNotify {
-    cpu_id: u8,
+    cpu_id: u32,
 }
 
 impl Aml for CpuNotify {
     fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
         let object = aml::Path::new(&format!("C{:03X}", self.cpu_id));
         aml::If::new(
-            &aml::Equal::new(&aml::Arg(0), &self.cpu_id),
+            &aml::Equal::new(&aml::Arg(0), &(self.cpu_id as usize)),
             vec![&aml::Notify::new(&object, &aml::Arg(1))],
         )
         .to_aml_bytes(sink)
@@ -2044,10 +2711,17 @@ impl Aml for CpuNotify {
 }
 
 struct CpuMethods {
-    max_vcpus: u8,
+    max_vcpus: u32,
     dynamic: bool,
 }
 
+// On AArch64 all the possible vCPUs are present, the ones which are not
+// plugged only being disabled. They are not present at all otherwise.
+#[cfg(target_arch = "aarch64")]
+const CPU_STA_DISABLED: u8 = 0xd;
+#[cfg(target_arch = "x86_64")]
+const CPU_STA_DISABLED: u8 = 0;
+
 impl Aml for CpuMethods {
     fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
         if self.dynamic {
@@ -2061,7 +2735,7 @@ impl Aml for CpuMethods {
                     &aml::Acquire::new("\\_SB_.PRES.CPLK".into(), 0xffff),
                     // Write CPU number (in first argument) to I/O port via field
                     &aml::Store::new(&aml::Path::new("\\_SB_.PRES.CSEL"), &aml::Arg(0)),
-                    &aml::Store::new(&aml::Local(0), &aml::ZERO),
+                    &aml::Store::new(&aml::Local(0), &CPU_STA_DISABLED),
                     // Check if CPEN bit is set, if so make the local variable 0xf (see _STA for details of meaning)
                     &aml::If::new(
                         &aml::Equal::new(&aml::Path::new("\\_SB_.PRES.CPEN"), &aml::ONE),
@@ -2069,7 +2743,7 @@ impl Aml for CpuMethods {
                     ),
                     // Release lock
                     &aml::Release::new("\\_SB_.PRES.CPLK".into()),
-                    // Return 0 or 0xf
+                    // Return CPU_STA_DISABLED or 0xf
                     &aml::Return::new(&aml::Local(0)),
                 ],
             )
@@ -2082,7 +2756,7 @@ impl Aml for CpuMethods {
 
             let mut cpu_notifies_refs: Vec<&dyn Aml> = Vec::new();
             for cpu_id in 0..self.max_vcpus {
-                cpu_notifies_refs.push(&cpu_notifies[usize::from(cpu_id)]);
+                cpu_notifies_refs.push(&cpu_notifies[cpu_id as usize]);
             }
 
             aml::Method::new("CTFY".into(), 2, true, cpu_notifies_refs).to_aml_bytes(sink);
@@ -2111,7 +2785,7 @@ impl Aml for CpuMethods {
                     &aml::Acquire::new("\\_SB_.PRES.CPLK".into(), 0xffff),
                     &aml::Store::new(&aml::Local(0), &aml::ZERO),
                     &aml::While::new(
-                        &aml::LessThan::new(&aml::Local(0), &self.max_vcpus),
+                        &aml::LessThan::new(&aml::Local(0), &(self.max_vcpus as usize)),
                         vec![
                             // Write CPU number (in first argument) to I/O port via field
                             &aml::Store::new(&aml::Path::new("\\_SB_.PRES.CSEL"), &aml::Local(0)),
@@ -2163,8 +2837,7 @@ impl Aml for CpuMethods {
 
 impl Aml for CpuManager {
     fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
-        #[cfg(target_arch = "x86_64")]
-        if let Some(acpi_address) = self.acpi_address {
+        if let Some(acpi_address) = self.acpi_address.filter(|_| self.dynamic) {
             // CPU hotplug controller
             aml::Device::new(
                 "_SB_.PRES".into(),
@@ -2257,14 +2930,41 @@ impl Aml for CpuManager {
 
 impl Pausable for CpuManager {
     fn pause(&mut self) -> std::result::Result<(), MigratableError> {
+        let generation = self.vcpus_pause_generation.fetch_add(1, Ordering::SeqCst) + 1;
+
         // Tell the vCPUs to pause themselves next time they exit
         self.vcpus_pause_signalled.store(true, Ordering::SeqCst);
 
-        // Signal to the spawned threads (vCPUs and console signal handler). For the vCPU threads
-        // this will interrupt the KVM_RUN ioctl() allowing the loop to check the boolean set
-        // above.
+        // Signal all the vCPU threads at once, interrupting the KVM_RUN ioctl() allowing
+        // the loop to check the boolean set above, rather than waiting for each of them
+        // in turn.
         for state in self.vcpu_states.iter() {
-            state.signal_thread();
+            state.kick_thread();
+        }
+
+        // The vCPU thread acknowledges the pause generation before parking, wait here for
+        // each activated vCPU. A signal received outside of KVM_RUN is lost, and a vCPU
+        // still parked from a previous pause needs to be woken up to acknowledge this one,
+        // so keep nudging the ones which didn't.
+        loop {
+            let mut pending = self
+                .vcpu_states
+                .iter()
+                .filter(|state| {
+                    state.active() && state.paused_generation.load(Ordering::SeqCst) != generation
+                })
+                .peekable();
+            if pending.peek().is_none() {
+                break;
+            }
+
+            // To avoid a priority inversion with the vCPU thread
+            thread::sleep(std::time::Duration::from_millis(1));
+
+            for state in pending {
+                state.kick_thread();
+                state.unpark_thread();
+            }
         }
 
         for vcpu in self.vcpus.iter() {
@@ -2281,17 +2981,6 @@ impl Pausable for CpuManager {
             }
         }
 
-        // The vCPU thread will change its paused state before parking, wait here for each
-        // activated vCPU change their state to ensure they have parked.
-        for state in self.vcpu_states.iter() {
-            if state.active() {
-                while !state.paused.load(Ordering::SeqCst) {
-                    // To avoid a priority inversion with the vCPU thread
-                    thread::sleep(std::time::Duration::from_millis(1));
-                }
-            }
-        }
-
         Ok(())
     }
 
@@ -2308,7 +2997,6 @@ impl Pausable for CpuManager {
         // boolean. Since it'll be set to false, they will exit their pause loop
         // and go back to vmx root.
         for state in self.vcpu_states.iter() {
-            state.paused.store(false, Ordering::SeqCst);
             state.unpark_thread();
         }
         Ok(())
@@ -2365,7 +3053,7 @@ impl Debuggable for CpuManager {
     fn read_regs(&self, cpu_id: usize) -> std::result::Result<CoreRegs, DebuggableError> {
         // General registers: RAX, RBX, RCX, RDX, RSI, RDI, RBP, RSP, r8-r15
         let gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         let regs = [
             gregs.get_rax(),
@@ -2393,7 +3081,7 @@ impl Debuggable for CpuManager {
 
         // Segment registers: CS, SS, DS, ES, FS, GS
         let sregs = self
-            .get_sregs(cpu_id as u8)
+            .get_sregs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         let segments = X86SegmentRegs {
             cs: sregs.cs.selector as u32,
@@ -2418,7 +3106,7 @@ impl Debuggable for CpuManager {
     #[cfg(target_arch = "aarch64")]
     fn read_regs(&self, cpu_id: usize) -> std::result::Result<CoreRegs, DebuggableError> {
         let gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         Ok(CoreRegs {
             x: gregs.get_regs(),
@@ -2435,9 +3123,9 @@ impl Debuggable for CpuManager {
         regs: &CoreRegs,
     ) -> std::result::Result<(), DebuggableError> {
         let orig_gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
-        let mut gregs = self.create_standard_regs(cpu_id as u8);
+        let mut gregs = self.create_standard_regs(cpu_id as u32);
         gregs.set_rax(regs.regs[0]);
         gregs.set_rbx(regs.regs[1]);
         gregs.set_rcx(regs.regs[2]);
@@ -2458,13 +3146,13 @@ impl Debuggable for CpuManager {
         // Update the lower 32-bit of rflags.
         gregs.set_rflags((orig_gregs.get_rflags() & !(u32::MAX as u64)) | (regs.eflags as u64));
 
-        self.set_regs(cpu_id as u8, &gregs)
+        self.set_regs(cpu_id as u32, &gregs)
             .map_err(DebuggableError::WriteRegs)?;
 
         // Segment registers: CS, SS, DS, ES, FS, GS
         // Since GDB care only selectors, we call get_sregs() first.
         let mut sregs = self
-            .get_sregs(cpu_id as u8)
+            .get_sregs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         sregs.cs.selector = regs.segments.cs as u16;
         sregs.ss.selector = regs.segments.ss as u16;
@@ -2473,7 +3161,7 @@ impl Debuggable for CpuManager {
         sregs.fs.selector = regs.segments.fs as u16;
         sregs.gs.selector = regs.segments.gs as u16;
 
-        self.set_sregs(cpu_id as u8, &sregs)
+        self.set_sregs(cpu_id as u32, &sregs)
             .map_err(DebuggableError::WriteRegs)?;
 
         // TODO: Add other registers
@@ -2488,14 +3176,14 @@ impl Debuggable for CpuManager {
         regs: &CoreRegs,
     ) -> std::result::Result<(), DebuggableError> {
         let mut gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
 
         gregs.set_regs(regs.x);
         gregs.set_sp(regs.sp);
         gregs.set_pc(regs.pc);
 
-        self.set_regs(cpu_id as u8, &gregs)
+        self.set_regs(cpu_id as u32, &gregs)
             .map_err(DebuggableError::WriteRegs)?;
 
         Ok(())
@@ -2513,7 +3201,7 @@ impl Debuggable for CpuManager {
 
         while total_read < len as u64 {
             let gaddr = vaddr.0 + total_read;
-            let paddr = match self.translate_gva(guest_memory, cpu_id as u8, gaddr) {
+            let paddr = match self.translate_gva(guest_memory, cpu_id as u32, gaddr) {
                 Ok(paddr) => paddr,
                 Err(_) if gaddr == u64::MIN => gaddr, // Silently return GVA as GPA if GVA == 0.
                 Err(e) => return Err(DebuggableError::TranslateGva(e)),
@@ -2543,7 +3231,7 @@ impl Debuggable for CpuManager {
 
         while total_written < data.len() as u64 {
             let gaddr = vaddr.0 + total_written;
-            let paddr = match self.translate_gva(guest_memory, cpu_id as u8, gaddr) {
+            let paddr = match self.translate_gva(guest_memory, cpu_id as u32, gaddr) {
                 Ok(paddr) => paddr,
                 Err(_) if gaddr == u64::MIN => gaddr, // Silently return GVA as GPA if GVA == 0.
                 Err(e) => return Err(DebuggableError::TranslateGva(e)),
@@ -2608,7 +3296,7 @@ impl CpuElf64Writable for CpuManager {
             pos += descsz - size_of::<X86_64UserRegs>() - size_of::<u64>();
 
             let orig_rax: u64 = 0;
-            let gregs = self.vcpus[usize::from(vcpu_id)]
+            let gregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2636,7 +3324,7 @@ impl CpuElf64Writable for CpuManager {
                 orig_rax,
             ];
 
-            let sregs = self.vcpus[usize::from(vcpu_id)]
+            let sregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2710,7 +3398,7 @@ impl CpuElf64Writable for CpuManager {
 
             pos += round_up!(COREDUMP_NAME_SIZE as usize, 4);
 
-            let gregs = self.vcpus[usize::from(vcpu_id)]
+            let gregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2739,7 +3427,7 @@ impl CpuElf64Writable for CpuManager {
                 gregs.get_r15(),
             ];
 
-            let sregs = self.vcpus[usize::from(vcpu_id)]
+            let sregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2816,6 +3504,61 @@ mod tests {
     use hypervisor::StandardRegisters;
     use linux_loader::loader::bootparam::setup_header;
 
+    #[test]
+    fn test_vcpu_usage_parsing() {
+        let stat = "4242 (vcpu 0) S 1 4240 4240 0 -1 4194368 12 0 5 0 150 30 0 0 20 0 \
+                    8 0 1000 0 0 18446744073709551615 0 0 0 0 0 0 0 0 0 0 0 0 -1 3 0 0 \
+                    7 120 0";
+        assert_eq!(
+            super::parse_stat_field(stat, super::STAT_GUEST_TIME),
+            Some(120)
+        );
+        assert_eq!(super::parse_stat_field(stat, super::STAT_MINFLT), Some(12));
+        assert_eq!(super::parse_stat_field(stat, super::STAT_MAJFLT), Some(5));
+        assert_eq!(
+            super::parse_stat_field(stat, super::STAT_DELAYACCT_BLKIO_TICKS),
+            Some(7)
+        );
+        assert_eq!(
+            super::parse_stat_field("4242 (vcpu0", super::STAT_GUEST_TIME),
+            None
+        );
+        assert_eq!(super::parse_run_delay("1500000 250000 42\n"), Some(250000));
+        assert_eq!(super::parse_run_delay("1500000"), None);
+
+        assert_eq!(
+            super::usage_percent(50, super::Duration::from_nanos(200)),
+            25
+        );
+        assert_eq!(
+            super::usage_percent(300, super::Duration::from_nanos(200)),
+            100
+        );
+        assert_eq!(super::usage_percent(300, super::Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn test_exit_history() {
+        let mut history = super::ExitHistory::new();
+        assert_eq!(history.iter().count(), 0);
+
+        history.record(hypervisor::VmExit::Reset);
+        for _ in 0..super::EXIT_HISTORY_LEN {
+            history.record(hypervisor::VmExit::Ignore);
+        }
+        history.record(hypervisor::VmExit::TripleFault);
+
+        let exits: Vec<_> = history.iter().map(|(_, exit)| *exit).collect();
+        assert_eq!(exits.len(), super::EXIT_HISTORY_LEN);
+        assert!(exits[..super::EXIT_HISTORY_LEN - 1]
+            .iter()
+            .all(|exit| matches!(exit, hypervisor::VmExit::Ignore)));
+        assert!(matches!(
+            exits.last(),
+            Some(hypervisor::VmExit::TripleFault)
+        ));
+    }
+
     #[test]
     fn test_setlint() {
         let hv = hypervisor::new().unwrap();
@@ -2948,14 +3691,25 @@ mod tests {
 #[cfg(test)]
 mod tests {
     use arch::{aarch64::regs, layout};
-    use hypervisor::kvm::aarch64::is_system_register;
+    use hypervisor::kvm::aarch64::{is_system_register, reg_size, KVM_REG_ARM64_SVE_VLS};
     use hypervisor::kvm::kvm_bindings::{
         kvm_vcpu_init, user_pt_regs, KVM_REG_ARM64, KVM_REG_ARM64_SYSREG, KVM_REG_ARM_CORE,
-        KVM_REG_SIZE_U64,
+        KVM_REG_SIZE_U128, KVM_REG_SIZE_U32, KVM_REG_SIZE_U64,
     };
     use hypervisor::{arm64_core_reg_id, offset_of};
     use std::mem;
 
+    #[test]
+    fn test_gicc_flags() {
+        use super::gicc_flags;
+
+        assert_eq!(gicc_flags(0, 2, true), 1);
+        assert_eq!(gicc_flags(1, 2, false), 1);
+        // Hotpluggable vCPUs are online capable but not enabled at boot.
+        assert_eq!(gicc_flags(2, 2, true), 1 << 3);
+        assert_eq!(gicc_flags(2, 2, false), 0);
+    }
+
     #[test]
     fn test_setup_regs() {
         let hv = hypervisor::new().unwrap();
@@ -2997,6 +3751,18 @@ mod tests {
         assert!(is_system_register(regid));
     }
 
+    #[test]
+    fn test_reg_size() {
+        let offset = offset_of!(user_pt_regs, pstate);
+        assert_eq!(reg_size(arm64_core_reg_id!(KVM_REG_SIZE_U32, offset)), 4);
+        let offset = offset_of!(user_pt_regs, pc);
+        assert_eq!(reg_size(arm64_core_reg_id!(KVM_REG_SIZE_U64, offset)), 8);
+        let regid = KVM_REG_ARM64 | KVM_REG_SIZE_U128 | KVM_REG_ARM64_SYSREG as u64;
+        assert_eq!(reg_size(regid), 16);
+        assert!(is_system_register(regid));
+        assert_eq!(reg_size(KVM_REG_ARM64_SVE_VLS), 64);
+    }
+
     #[test]
     fn test_save_restore_core_regs() {
         let hv = hypervisor::new().unwrap();
@@ -0,0 +1,567 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Crash reports of the VMM.
+//!
+//! When a thread of the VMM panics, or receives a fatal signal such as a
+//! SIGSEGV, a JSON report is written to the crash directory as
+//! `crash-<pid>-<timestamp>.json`. It holds the cause of the crash and the
+//! backtrace of the panicking thread, or the location of the faulting
+//! instruction, along with the state of the VMM at that time: the threads of
+//! the process, the latest HTTP API requests, the devices of the VM and the
+//! latest events.
+//!
+//! The crashing thread only hands the report over to a dedicated thread,
+//! started before any seccomp filter or Landlock ruleset is applied, which
+//! collects the state of the VMM and writes the report. This keeps the report
+//! within the reach of the threads which aren't allowed to open files. Only
+//! the first crash is reported, as a crash often brings others in its wake.
+//!
+//! The fatal signal handler sticks to async signal safe operations: it writes
+//! a fixed-size record of the signal to a pipe and waits for the report
+//! without any syscall, before letting the signal kill the process.
+//!
+//! The guest memory is kept out of the core dump following the crash, unless
+//! the VM was asked to include it: the reporting thread marks its mappings
+//! with `MADV_DONTDUMP` again before the crashing thread resumes, and the
+//! report lists the excluded ranges.
+
+use crate::device_tree::DeviceTree;
+use serde::Serialize;
+use std::backtrace::Backtrace;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use vm_memory::ByteValued;
+
+const API_REQUESTS_LEN: usize = 32;
+// How long the crashing thread waits for the report to be written.
+const REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+const FATAL_SIGNALS: [libc::c_int; 5] = [
+    libc::SIGSEGV,
+    libc::SIGBUS,
+    libc::SIGILL,
+    libc::SIGFPE,
+    libc::SIGABRT,
+];
+
+static REPORTER: OnceLock<Mutex<Sender<Crash>>> = OnceLock::new();
+static REPORTED: AtomicBool = AtomicBool::new(false);
+// Write end of the pipe the fatal signals are handed over through.
+static SIGNAL_FD: AtomicI32 = AtomicI32::new(-1);
+// Set once the report of a fatal signal is written, or given up on.
+static SIGNAL_REPORTED: AtomicBool = AtomicBool::new(false);
+static API_REQUESTS: Mutex<VecDeque<ApiRequestRecord>> = Mutex::new(VecDeque::new());
+static DEVICE_TREE: Mutex<Option<Weak<Mutex<DeviceTree>>>> = Mutex::new(None);
+// Host mappings of the guest memory left out of the core dumps, by address.
+static GUEST_MEMORY: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+struct Crash {
+    reason: String,
+    thread: Option<String>,
+    backtrace: Option<String>,
+    location: Option<String>,
+    done: Sender<()>,
+}
+
+// What the fatal signal handler writes to the pipe, small enough to be written
+// atomically.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct FatalSignal {
+    signal: i32,
+    code: i32,
+    fault_addr: u64,
+    instruction_addr: u64,
+}
+
+// SAFETY: FatalSignal only contains integers, without padding.
+unsafe impl ByteValued for FatalSignal {}
+
+#[derive(Clone, Serialize)]
+struct ApiRequestRecord {
+    timestamp: u64,
+    method: String,
+    path: String,
+}
+
+#[derive(Serialize)]
+struct ThreadRecord {
+    tid: u32,
+    name: String,
+    state: String,
+}
+
+#[derive(Serialize)]
+struct MemoryRangeRecord {
+    host_addr: u64,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct CrashReport {
+    pid: u32,
+    timestamp: u64,
+    reason: String,
+    // Unknown for the fatal signals, the handler having no safe way to get
+    // the name and backtrace of its thread.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backtrace: Option<String>,
+    // Mapped file and offset of the faulting instruction.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    threads: Vec<ThreadRecord>,
+    api_requests: Vec<ApiRequestRecord>,
+    devices: Vec<String>,
+    events: Vec<serde_json::Value>,
+    excluded_memory: Vec<MemoryRangeRecord>,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Writes the crash reports to `dir`, which is created if needed.
+pub fn enable(dir: PathBuf) -> io::Result<()> {
+    fs::create_dir_all(&dir)?;
+
+    let (sender, receiver) = mpsc::channel();
+    if REPORTER.set(Mutex::new(sender.clone())).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "crash reports already enabled",
+        ));
+    }
+    thread::Builder::new()
+        .name("crash_reporter".to_string())
+        .spawn(move || report_crashes(&dir, receiver))?;
+
+    let mut fds = [-1; 2];
+    // SAFETY: FFI call with a valid array of two fds.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: The read end of the pipe was just created and is owned by the
+    // thread below. The write end is kept open for the lifetime of the
+    // process.
+    let signals = unsafe { File::from_raw_fd(fds[0]) };
+    SIGNAL_FD.store(fds[1], Ordering::Relaxed);
+    thread::Builder::new()
+        .name("crash_signals".to_string())
+        .spawn(move || report_fatal_signals(signals, sender))?;
+
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        report(info.to_string());
+        previous_hook(info);
+    }));
+
+    for signal in FATAL_SIGNALS {
+        // SAFETY: Zero-initialized sigaction, filled below.
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = handle_fatal_signal as usize;
+        // The default action is restored for the faulting instruction to
+        // raise the signal again once the handler returns, or for the handler
+        // to raise it again when it was sent by a process, which requires the
+        // signal not to be blocked.
+        action.sa_flags =
+            libc::SA_SIGINFO | libc::SA_ONSTACK | libc::SA_RESETHAND | libc::SA_NODEFER;
+
+        // SAFETY: FFI call with a valid sigaction.
+        if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Records an HTTP API request, for the crash reports to list the latest ones.
+pub fn record_api_request(method: &str, path: &str) {
+    if REPORTER.get().is_none() {
+        return;
+    }
+
+    let mut requests = API_REQUESTS.lock().unwrap();
+    if requests.len() == API_REQUESTS_LEN {
+        requests.pop_front();
+    }
+    requests.push_back(ApiRequestRecord {
+        timestamp: unix_time(),
+        method: method.to_string(),
+        path: path.to_string(),
+    });
+}
+
+/// Sets the device tree of the VM the crash reports list the devices of.
+pub fn set_device_tree(device_tree: &Arc<Mutex<DeviceTree>>) {
+    *DEVICE_TREE.lock().unwrap() = Some(Arc::downgrade(device_tree));
+}
+
+/// Records a mapping of the guest memory left out of the core dumps.
+pub fn exclude_guest_memory(host_addr: u64, size: u64) {
+    if REPORTER.get().is_none() {
+        return;
+    }
+
+    GUEST_MEMORY.lock().unwrap().insert(host_addr, size);
+}
+
+/// Forgets a mapping of the guest memory once it is removed from the guest.
+pub fn include_guest_memory(host_addr: u64) {
+    if REPORTER.get().is_none() {
+        return;
+    }
+
+    GUEST_MEMORY.lock().unwrap().remove(&host_addr);
+}
+
+// Only async signal safe operations are allowed here, the thread having
+// possibly crashed with the allocator or some locks held: reading the siginfo
+// and the context, writing to the pipe, and reading the clock, which the vDSO
+// provides without a syscall the thread filter could deny.
+extern "C" fn handle_fatal_signal(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    // SAFETY: The kernel passes the siginfo of the signal.
+    let info = unsafe { &*info };
+    if !REPORTED.swap(true, Ordering::SeqCst) {
+        report_fatal_signal(signal, info, context);
+    }
+
+    // The default action is back, a faulting instruction raises the signal
+    // again as soon as the handler returns, but a signal sent by a process
+    // must be raised again, the process exiting if the thread isn't allowed
+    // to.
+    if info.si_code <= 0 {
+        // SAFETY: FFI calls raising a signal with its default action, and
+        // exiting the process without running any handler.
+        unsafe {
+            libc::raise(signal);
+            libc::_exit(128 + signal);
+        }
+    }
+}
+
+// Hands the signal over to the reporting threads, and waits for the report.
+fn report_fatal_signal(signal: libc::c_int, info: &libc::siginfo_t, context: *mut libc::c_void) {
+    let mut record = FatalSignal {
+        signal,
+        code: info.si_code,
+        ..Default::default()
+    };
+    // Only the signals raised by the kernel come with a faulting address.
+    if info.si_code > 0 {
+        // SAFETY: The kernel passes the context of the faulting thread.
+        unsafe {
+            record.fault_addr = info.si_addr() as u64;
+            let context = &*(context as *const libc::ucontext_t);
+            #[cfg(target_arch = "x86_64")]
+            {
+                record.instruction_addr = context.uc_mcontext.gregs[libc::REG_RIP as usize] as u64;
+            }
+            #[cfg(target_arch = "aarch64")]
+            {
+                record.instruction_addr = context.uc_mcontext.pc;
+            }
+        }
+    }
+
+    // SAFETY: FFI call writing a buffer of the given size to the pipe.
+    let written = unsafe {
+        libc::write(
+            SIGNAL_FD.load(Ordering::Relaxed),
+            record.as_slice().as_ptr() as *const libc::c_void,
+            record.as_slice().len(),
+        )
+    };
+    if written == record.as_slice().len() as isize {
+        let deadline = monotonic_time().saturating_add(REPORT_TIMEOUT);
+        while !SIGNAL_REPORTED.load(Ordering::SeqCst) && monotonic_time() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+// Async signal safe, as clock_gettime() doesn't need a syscall for the
+// monotonic clock.
+fn monotonic_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: FFI call with a valid timespec.
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+fn signal_reason(record: &FatalSignal) -> String {
+    // SAFETY: FFI call returning a static string.
+    let name = unsafe { std::ffi::CStr::from_ptr(libc::strsignal(record.signal)) };
+    let mut reason = format!("Signal {} ({})", record.signal, name.to_string_lossy());
+    if record.code > 0 {
+        reason.push_str(&format!(" at address {:#x}", record.fault_addr));
+    }
+
+    reason
+}
+
+// Turns the fatal signals into crash reports, outside of the signal handler.
+fn report_fatal_signals(mut signals: File, reporter: Sender<Crash>) {
+    let mut record = FatalSignal::default();
+    while signals.read_exact(record.as_mut_slice()).is_ok() {
+        let location = (record.code > 0).then(|| {
+            let maps = fs::read_to_string("/proc/self/maps").unwrap_or_default();
+            match crate::seccomp_report::locate(&maps, record.instruction_addr) {
+                Some((path, offset)) => format!("{path}+{offset:#x}"),
+                None => format!("{:#x}", record.instruction_addr),
+            }
+        });
+
+        let (done, done_receiver) = mpsc::channel();
+        let crash = Crash {
+            reason: signal_reason(&record),
+            thread: None,
+            backtrace: None,
+            location,
+            done,
+        };
+        if reporter.send(crash).is_ok() {
+            let _ = done_receiver.recv_timeout(REPORT_TIMEOUT);
+        }
+        SIGNAL_REPORTED.store(true, Ordering::SeqCst);
+    }
+}
+
+fn report(reason: String) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    if REPORTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let (done, done_receiver) = mpsc::channel();
+    let crash = Crash {
+        reason,
+        thread: Some(thread::current().name().unwrap_or("unnamed").to_string()),
+        backtrace: Some(Backtrace::force_capture().to_string()),
+        location: None,
+        done,
+    };
+    let sent = match reporter.try_lock() {
+        Ok(reporter) => reporter.send(crash).is_ok(),
+        Err(_) => false,
+    };
+    if sent {
+        let _ = done_receiver.recv_timeout(REPORT_TIMEOUT);
+    }
+}
+
+fn report_crashes(dir: &Path, receiver: Receiver<Crash>) {
+    while let Ok(crash) = receiver.recv() {
+        exclude_guest_memory_from_dump();
+        let report = CrashReport {
+            pid: std::process::id(),
+            timestamp: unix_time(),
+            reason: crash.reason,
+            thread: crash.thread,
+            backtrace: crash.backtrace,
+            location: crash.location,
+            threads: threads(),
+            api_requests: api_requests(),
+            devices: devices(),
+            events: event_monitor::recent_events()
+                .iter()
+                .filter_map(|event| serde_json::from_str(event).ok())
+                .collect(),
+            excluded_memory: excluded_memory(),
+        };
+
+        match write_report(dir, &report) {
+            Ok(path) => error!("VMM crashed, report written to {}", path.display()),
+            Err(e) => error!("Error writing the crash report: {}", e),
+        }
+        let _ = crash.done.send(());
+    }
+}
+
+fn write_report(dir: &Path, report: &CrashReport) -> io::Result<PathBuf> {
+    let path = dir.join(format!("crash-{}-{}.json", report.pid, report.timestamp));
+    let contents = serde_json::to_vec_pretty(report).map_err(io::Error::other)?;
+    fs::write(&path, contents)?;
+
+    Ok(path)
+}
+
+// The crashing thread may hold the locks, which are only tried.
+fn api_requests() -> Vec<ApiRequestRecord> {
+    API_REQUESTS
+        .try_lock()
+        .map(|requests| requests.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn excluded_memory() -> Vec<MemoryRangeRecord> {
+    GUEST_MEMORY
+        .try_lock()
+        .map(|memory| {
+            memory
+                .iter()
+                .map(|(&host_addr, &size)| MemoryRangeRecord { host_addr, size })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Marks the guest memory again, in case a mapping lost its advice since it
+// was created, for the core dump written once the crash handler returns.
+fn exclude_guest_memory_from_dump() {
+    let Ok(memory) = GUEST_MEMORY.try_lock() else {
+        return;
+    };
+    for (&host_addr, &size) in memory.iter() {
+        // SAFETY: the range is a mapping of the guest memory, which is only
+        // forgotten once it is removed from the guest.
+        unsafe {
+            libc::madvise(
+                host_addr as *mut libc::c_void,
+                size as libc::size_t,
+                libc::MADV_DONTDUMP,
+            )
+        };
+    }
+}
+
+fn devices() -> Vec<String> {
+    let device_tree = DEVICE_TREE
+        .try_lock()
+        .ok()
+        .and_then(|device_tree| device_tree.as_ref().and_then(Weak::upgrade));
+    let mut devices: Vec<String> = device_tree
+        .and_then(|device_tree| {
+            device_tree
+                .try_lock()
+                .ok()
+                .map(|device_tree| device_tree.iter().map(|(id, _)| id.clone()).collect())
+        })
+        .unwrap_or_default();
+    devices.sort();
+
+    devices
+}
+
+fn threads() -> Vec<ThreadRecord> {
+    let Ok(tasks) = fs::read_dir("/proc/self/task") else {
+        return Vec::new();
+    };
+
+    let mut threads: Vec<ThreadRecord> = tasks
+        .filter_map(|task| {
+            let task = task.ok()?;
+            let tid = task.file_name().to_str()?.parse().ok()?;
+            let name = fs::read_to_string(task.path().join("comm")).unwrap_or_default();
+            // The state follows the name, which is within parentheses.
+            let stat = fs::read_to_string(task.path().join("stat")).unwrap_or_default();
+            let state = stat
+                .rsplit_once(") ")
+                .and_then(|(_, fields)| fields.split(' ').next())
+                .unwrap_or_default();
+            Some(ThreadRecord {
+                tid,
+                name: name.trim_end().to_string(),
+                state: state.to_string(),
+            })
+        })
+        .collect();
+    threads.sort_by_key(|thread| thread.tid);
+
+    threads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_write_crash_report() {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let report = CrashReport {
+            pid: 42,
+            timestamp: 1_700_000_000,
+            reason: "Signal 11 (Segmentation fault)".to_string(),
+            thread: None,
+            backtrace: None,
+            location: Some("/usr/bin/cloud-hypervisor+0x6d2f4e".to_string()),
+            threads: threads(),
+            api_requests: vec![ApiRequestRecord {
+                timestamp: 1_699_999_999,
+                method: "PUT".to_string(),
+                path: "/api/v1/vm.boot".to_string(),
+            }],
+            devices: vec!["_disk0".to_string()],
+            events: vec![serde_json::json!({"source": "vm", "event": "booted"})],
+            excluded_memory: vec![MemoryRangeRecord {
+                host_addr: 0x7f00_0000_0000,
+                size: 1 << 30,
+            }],
+        };
+
+        let path = write_report(dir.as_path(), &report).unwrap();
+        assert_eq!(
+            path,
+            dir.as_path().join("crash-42-1700000000.json").as_path()
+        );
+        let contents: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(contents["reason"], "Signal 11 (Segmentation fault)");
+        assert_eq!(contents["location"], "/usr/bin/cloud-hypervisor+0x6d2f4e");
+        assert!(contents.get("backtrace").is_none());
+        assert_eq!(contents["api_requests"][0]["path"], "/api/v1/vm.boot");
+        assert_eq!(contents["devices"][0], "_disk0");
+        assert_eq!(contents["events"][0]["event"], "booted");
+        assert_eq!(contents["excluded_memory"][0]["size"], 1 << 30);
+        assert!(contents["threads"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|thread| thread["tid"] == std::process::id()));
+    }
+
+    #[test]
+    fn test_signal_reason() {
+        let fault = FatalSignal {
+            signal: libc::SIGSEGV,
+            // SEGV_MAPERR
+            code: 1,
+            fault_addr: 0x10,
+            instruction_addr: 0x55d4c1c01234,
+        };
+        assert!(signal_reason(&fault).ends_with(" at address 0x10"));
+
+        // Signals sent by a process don't come with a faulting address.
+        let abort = FatalSignal {
+            signal: libc::SIGABRT,
+            code: libc::SI_TKILL,
+            ..Default::default()
+        };
+        assert!(!signal_reason(&abort).contains("address"));
+    }
+}
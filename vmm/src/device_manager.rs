@@ -9,15 +9,22 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+#[cfg(feature = "vsock")]
+use crate::config::VsockForwardDirection;
 use crate::config::{
-    ConsoleOutputMode, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, UserDeviceConfig,
-    VdpaConfig, VhostMode, VmConfig, VsockConfig,
+    ConsoleOutputMode, ConsolePortConfig, DeviceConfig, DiskConfig, FsConfig, FsProtocol,
+    GpuConfig, NetConfig, PmemConfig, UserDeviceConfig, VdpaConfig, VhostMode, VmConfig,
+    VsockConfig, VsockForwardConfig,
 };
-use crate::console_devices::{ConsoleDeviceError, ConsoleInfo};
+use crate::console_devices::{
+    create_console_port_endpoint, virtio_console_endpoint, ConsoleDeviceError, ConsoleInfo,
+};
+use crate::console_log::ConsoleLog;
 use crate::cpu::{CpuManager, CPU_MANAGER_ACPI_SIZE};
 use crate::device_tree::{DeviceNode, DeviceTree};
 use crate::interrupt::LegacyUserspaceInterruptManager;
 use crate::interrupt::MsiInterruptManager;
+use crate::kbs;
 use crate::memory_manager::{Error as MemoryManagerError, MemoryManager, MEMORY_MANAGER_ACPI_SIZE};
 use crate::pci_segment::PciSegment;
 use crate::serial_manager::{Error as SerialManagerError, SerialManager};
@@ -34,6 +41,11 @@ use arch::layout::{APIC_START, IOAPIC_SIZE, IOAPIC_START};
 use arch::NumaNodes;
 #[cfg(target_arch = "aarch64")]
 use arch::{DeviceType, MmioDeviceInfo};
+use block::crypt::CryptError;
+use block::crypt_sync::CryptDiskSync;
+use block::fcntl::{ImageLock, LockType};
+use block::http::HttpError;
+use block::http_sync::HttpDiskSync;
 use block::{
     async_io::DiskFile, block_aio_is_supported, block_io_uring_is_supported, detect_image_type,
     fixed_vhd_sync::FixedVhdDiskSync, qcow, qcow_sync::QcowDiskSync, raw_async_aio::RawFileDiskAio,
@@ -54,37 +66,41 @@ use devices::pvmemcontrol::{PvmemcontrolBusDevice, PvmemcontrolPciDevice};
 use devices::{
     interrupt_controller, interrupt_controller::InterruptController, AcpiNotificationFlags,
 };
+use guest_tables::acpi::AcpiPlatformAddresses;
 use hypervisor::IoEventAddress;
 use libc::{
     tcsetattr, termios, MAP_NORESERVE, MAP_PRIVATE, MAP_SHARED, O_TMPFILE, PROT_READ, PROT_WRITE,
     TCSANOW,
 };
 use pci::{
-    DeviceRelocation, MmioRegion, PciBarRegionType, PciBdf, PciDevice, VfioDmaMapping,
-    VfioPciDevice, VfioUserDmaMapping, VfioUserPciDevice, VfioUserPciDeviceError,
+    vfio_dirty_log, vfio_dirty_log_enable, DeviceRelocation, MmioRegion, PciBarRegionType, PciBdf,
+    PciDevice, VfioDmaMapping, VfioPciDevice, VfioQuirks, VfioUserDmaMapping, VfioUserPciDevice,
+    VfioUserPciDeviceError, VFIO_DIRTY_PAGE_SIZE,
 };
 use rate_limiter::group::RateLimiterGroup;
 use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::CString;
 use std::fs::{File, OpenOptions};
 use std::io::{self, stdout, Seek, SeekFrom};
 use std::num::Wrapping;
 use std::os::fd::RawFd;
+use std::os::unix::ffi::OsStringExt;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::PathBuf;
 use std::result;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tracer::trace_scoped;
 use vfio_ioctls::{VfioContainer, VfioDevice, VfioDeviceFd};
 use virtio_devices::transport::VirtioTransport;
 use virtio_devices::transport::{VirtioPciDevice, VirtioPciDeviceActivator};
 use virtio_devices::vhost_user::VhostUserConfig;
-use virtio_devices::{
-    AccessPlatformMapping, ActivateError, VdpaDmaMapping, VirtioMemMappingSource,
-};
+#[cfg(feature = "vdpa")]
+use virtio_devices::VdpaDmaMapping;
+use virtio_devices::{AccessPlatformMapping, ActivateError, BlockTracer, VirtioMemMappingSource};
 use virtio_devices::{Endpoint, IommuMapping};
 use vm_allocator::{AddressAllocator, SystemAllocator};
 use vm_device::dma_mapping::ExternalDmaMapping;
@@ -98,12 +114,13 @@ use vm_memory::{Address, GuestAddress, GuestUsize, MmapRegion};
 #[cfg(target_arch = "x86_64")]
 use vm_memory::{GuestAddressSpace, GuestMemory};
 use vm_migration::{
-    protocol::MemoryRangeTable, snapshot_from_id, state_from_id, Migratable, MigratableError,
-    Pausable, Snapshot, SnapshotData, Snapshottable, Transportable,
+    protocol::MemoryRangeTable, snapshot_from_id, state_from_id, versioned_state_from_id,
+    Migratable, MigratableError, Pausable, Snapshot, SnapshotData, Snapshottable, Transportable,
 };
 use vm_virtio::AccessPlatform;
 use vm_virtio::VirtioDeviceType;
 use vmm_sys_util::eventfd::EventFd;
+use zeroize::Zeroizing;
 #[cfg(target_arch = "x86_64")]
 use {devices::debug_console, devices::legacy::Serial};
 
@@ -118,27 +135,40 @@ const SERIAL_DEVICE_NAME: &str = "__serial";
 const DEBUGCON_DEVICE_NAME: &str = "__debug_console";
 #[cfg(target_arch = "aarch64")]
 const GPIO_DEVICE_NAME: &str = "__gpio";
+#[cfg(feature = "rng")]
 const RNG_DEVICE_NAME: &str = "__rng";
 const IOMMU_DEVICE_NAME: &str = "__iommu";
 #[cfg(feature = "pvmemcontrol")]
 const PVMEMCONTROL_DEVICE_NAME: &str = "__pvmemcontrol";
+#[cfg(feature = "balloon")]
 const BALLOON_DEVICE_NAME: &str = "__balloon";
 const CONSOLE_DEVICE_NAME: &str = "__console";
 const PVPANIC_DEVICE_NAME: &str = "__pvpanic";
+#[cfg(target_arch = "x86_64")]
+const VMGENID_DEVICE_NAME: &str = "__vmgenid";
+const GHES_DEVICE_NAME: &str = "__ghes";
 
 // Devices that the user may name and for which we generate
 // identifiers if the user doesn't give one
+const CONSOLE_PORT_DEVICE_NAME_PREFIX: &str = "_console";
 const DISK_DEVICE_NAME_PREFIX: &str = "_disk";
 const FS_DEVICE_NAME_PREFIX: &str = "_fs";
+const GPU_DEVICE_NAME_PREFIX: &str = "_gpu";
 const NET_DEVICE_NAME_PREFIX: &str = "_net";
 const PMEM_DEVICE_NAME_PREFIX: &str = "_pmem";
+#[cfg(feature = "vdpa")]
 const VDPA_DEVICE_NAME_PREFIX: &str = "_vdpa";
+#[cfg(feature = "vsock")]
 const VSOCK_DEVICE_NAME_PREFIX: &str = "_vsock";
+#[cfg(feature = "watchdog")]
 const WATCHDOG_DEVICE_NAME: &str = "__watchdog";
 const VFIO_DEVICE_NAME_PREFIX: &str = "_vfio";
 const VFIO_USER_DEVICE_NAME_PREFIX: &str = "_vfio_user";
 const VIRTIO_PCI_DEVICE_NAME_PREFIX: &str = "_virtio-pci";
 
+// Smallest alignment of the PCI segment MMIO apertures
+const MIN_MMIO_ALIGNMENT: u64 = 4 << 10;
+
 /// Errors associated with device manager
 #[derive(Debug)]
 pub enum DeviceManagerError {
@@ -166,9 +196,18 @@ pub enum DeviceManagerError {
     /// Cannot create virtio-fs device
     CreateVirtioFs(virtio_devices::vhost_user::Error),
 
+    /// Cannot create virtio-9p device
+    CreateVirtio9p(io::Error),
+
     /// Virtio-fs device was created without a socket.
     NoVirtioFsSock,
 
+    /// Cannot create vhost-user-gpu device
+    CreateVhostUserGpu(virtio_devices::vhost_user::Error),
+
+    /// Vhost-user-gpu device was created without a socket.
+    NoVhostUserGpuSock,
+
     /// Cannot create vhost-user-blk device
     CreateVhostUserBlk(virtio_devices::vhost_user::Error),
 
@@ -176,6 +215,7 @@ pub enum DeviceManagerError {
     CreateVirtioPmem(io::Error),
 
     /// Cannot create vDPA device
+    #[cfg(feature = "vdpa")]
     CreateVdpa(virtio_devices::vdpa::Error),
 
     /// Cannot create virtio-vsock device
@@ -184,6 +224,10 @@ pub enum DeviceManagerError {
     /// Cannot create tpm device
     CreateTpmDevice(anyhow::Error),
 
+    /// Cannot create the TDX quote generator
+    #[cfg(feature = "tdx")]
+    CreateTdxQuoteGenerator(crate::tdx_quote::Error),
+
     /// Failed to convert Path to &str for the vDPA device.
     CreateVdpaConvertPath,
 
@@ -191,6 +235,7 @@ pub enum DeviceManagerError {
     CreateVsockConvertPath,
 
     /// Cannot create virtio-vsock backend
+    #[cfg(feature = "vsock")]
     CreateVsockBackend(virtio_devices::vsock::VsockUnixError),
 
     /// Cannot create virtio-iommu device
@@ -209,12 +254,18 @@ pub enum DeviceManagerError {
     /// Failed to parse disk image format
     DetectImageType(io::Error),
 
+    /// Disk image format does not match the expected one
+    DiskImageTypeMismatch(PathBuf, ImageType, ImageType),
+
     /// Cannot open qcow disk path
     QcowDeviceCreate(qcow::Error),
 
     /// Cannot create serial manager
     CreateSerialManager(SerialManagerError),
 
+    /// Cannot start shipping the console output
+    CreateConsoleLog(io::Error),
+
     /// Cannot spawn the serial manager thread
     SpawnSerialManager(SerialManagerError),
 
@@ -279,6 +330,9 @@ pub enum DeviceManagerError {
     /// Error setting pty raw mode
     SetPtyRaw(ConsoleDeviceError),
 
+    /// Error creating the host endpoint of a console device
+    CreateConsoleEndpoint(ConsoleDeviceError),
+
     /// Error getting pty peer
     GetPtyPeer(vmm_sys_util::errno::Error),
 
@@ -288,6 +342,18 @@ pub enum DeviceManagerError {
     /// Cannot create a VFIO PCI device
     VfioPciCreate(pci::VfioPciError),
 
+    /// Cannot read the VFIO quirks file
+    ReadVfioQuirks(io::Error),
+
+    /// Invalid VFIO quirks
+    ParseVfioQuirks(pci::VfioQuirksError),
+
+    /// Cannot read the ids of the VFIO device
+    ReadVfioDeviceIds(io::Error),
+
+    /// Cannot select the reset method of the VFIO device
+    SelectVfioResetMethod(io::Error),
+
     /// Failed to map VFIO MMIO region.
     VfioMapRegion(pci::VfioPciError),
 
@@ -414,15 +480,23 @@ pub enum DeviceManagerError {
     /// No support for device passthrough
     NoDevicePassthroughSupport,
 
-    /// No socket option support for console device
-    NoSocketOptionSupportForConsoleDevice,
-
     /// Failed to resize virtio-balloon
+    #[cfg(feature = "balloon")]
     VirtioBalloonResize(virtio_devices::balloon::Error),
 
     /// Missing virtio-balloon, can't proceed as expected.
     MissingVirtioBalloon,
 
+    /// Failed to update the forwarding table of the virtio-vsock device
+    #[cfg(feature = "vsock")]
+    VsockForward(virtio_devices::vsock::VsockUnixError),
+
+    /// Missing virtio-vsock, can't proceed as expected.
+    MissingVirtioVsock,
+
+    /// Device family left out of the build
+    DeviceNotBuiltIn(&'static str),
+
     /// Missing virtual IOMMU device
     MissingVirtualIommu,
 
@@ -448,6 +522,42 @@ pub enum DeviceManagerError {
     /// Failed to create FixedVhdxDiskSync
     CreateFixedVhdxDiskSync(vhdx::VhdxError),
 
+    /// Failed to read the disk encryption key
+    ReadDiskKey(io::Error),
+
+    /// Failed to fetch the disk encryption key from the key broker service
+    FetchDiskKey(kbs::Error),
+
+    /// Failed to create the disk backend
+    CreateDiskBackend(block::Error),
+
+    /// Failed to create CryptDiskSync
+    CreateCryptDiskSync(CryptError),
+
+    /// Failed to open the cache file of the disk served over HTTP
+    OpenDiskCache(io::Error),
+
+    /// Failed to create HttpDiskSync
+    CreateHttpDiskSync(HttpError),
+
+    /// Failed to set the cgroup io.max limits of the disk
+    SetDiskIoMax(block::qos::IoMaxError),
+
+    /// Failed to start or stop the block device tracing
+    BlockTrace(io::Error),
+
+    /// Failed to create the overlay of the transient disk
+    CreateTransientOverlay(io::Error),
+
+    /// Failed to keep the overlay of the transient disk
+    KeepTransientDisk(io::Error),
+
+    /// The disk isn't transient
+    NotTransientDisk(String),
+
+    /// Failed to switch the backend of the network device
+    SwapNetBackend(virtio_devices::transport::VirtioPciDeviceError),
+
     /// Failed to add DMA mapping handler to virtio-mem device.
     AddDmaMappingHandlerVirtioMem(virtio_devices::mem::Error),
 
@@ -472,9 +582,6 @@ pub enum DeviceManagerError {
     /// Failed to update memory mappings for VFIO user device
     UpdateMemoryForVfioUserPciDevice(VfioUserPciDeviceError),
 
-    /// Cannot duplicate file descriptor
-    DupFd(vmm_sys_util::errno::Error),
-
     /// Failed to DMA map virtio device.
     VirtioDmaMap(std::io::Error),
 
@@ -499,6 +606,24 @@ pub enum DeviceManagerError {
     /// Cannot create a PvPanic device
     PvPanicCreate(devices::pvpanic::PvPanicError),
 
+    /// Cannot create the VM generation ID device
+    VmGenIdCreate(devices::vmgenid::VmGenIdError),
+
+    /// Failed to update the VM generation ID
+    VmGenIdUpdate(devices::vmgenid::VmGenIdError),
+
+    /// Failed to notify the guest about the VM generation ID change
+    VmGenIdNotification(io::Error),
+
+    /// Missing the hardware error source device resources
+    MissingGhesResources,
+
+    /// Failed to record the hardware error
+    GhesInjection(devices::ghes::GhesError),
+
+    /// Failed to notify the guest about the hardware error
+    GhesNotification(io::Error),
+
     /// Cannot create a RateLimiterGroup
     RateLimiterGroupCreate(rate_limiter::group::Error),
 
@@ -510,6 +635,9 @@ pub enum DeviceManagerError {
 
     // Invalid console fd
     InvalidConsoleFd,
+
+    /// MMIO range too small to be split across the PCI segments
+    MmioAreaTooSmall(u64, u64),
 }
 
 pub type DeviceManagerResult<T> = result::Result<T, DeviceManagerError>;
@@ -736,6 +864,43 @@ impl DeviceRelocation for AddressManager {
     }
 }
 
+/// Time taken by a device to quiesce when the VM got paused.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceQuiesceReport {
+    pub id: String,
+    pub duration_us: u64,
+    /// The deadline was reached by the time the device got quiesced.
+    pub timed_out: bool,
+}
+
+/// Progress of a device removal requested through the API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnplugState {
+    /// The guest has been notified and is expected to release the device.
+    Pending,
+    /// The guest consumed the removal notification.
+    GuestAcked,
+    /// The device has been ejected.
+    Done,
+    /// The guest did not eject the device before the deadline.
+    Failed,
+}
+
+/// Status of the latest removal request of a device.
+#[derive(Clone, Serialize)]
+pub struct DeviceUnplugStatus {
+    pub id: String,
+    pub bdf: PciBdf,
+    pub state: UnplugState,
+    /// The device got forcefully removed once the deadline expired.
+    pub surprise_removed: bool,
+    #[serde(skip)]
+    deadline: Option<Instant>,
+    #[serde(skip)]
+    surprise_removal: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 struct DeviceManagerState {
     device_tree: DeviceTree,
@@ -773,12 +938,35 @@ struct MetaVirtioDevice {
     dma_handler: Option<Arc<dyn ExternalDmaMapping>>,
 }
 
-#[derive(Default)]
-pub struct AcpiPlatformAddresses {
-    pub pm_timer_address: Option<GenericAddress>,
-    pub reset_reg_address: Option<GenericAddress>,
-    pub sleep_control_reg_address: Option<GenericAddress>,
-    pub sleep_status_reg_address: Option<GenericAddress>,
+type VirtioDevicesMaker = fn(&mut DeviceManager) -> DeviceManagerResult<Vec<MetaVirtioDevice>>;
+
+// Families of virtio devices built into the VMM, in the order they are created.
+// The optional ones are only built in along with their cargo feature.
+const VIRTIO_DEVICES_REGISTRY: &[(&str, VirtioDevicesMaker)] = &[
+    ("block", DeviceManager::make_virtio_block_devices),
+    ("net", DeviceManager::make_virtio_net_devices),
+    #[cfg(feature = "rng")]
+    ("rng", DeviceManager::make_virtio_rng_devices),
+    ("fs", DeviceManager::make_virtio_fs_devices),
+    ("gpu", DeviceManager::make_virtio_gpu_devices),
+    ("pmem", DeviceManager::make_virtio_pmem_devices),
+    #[cfg(feature = "vsock")]
+    ("vsock", DeviceManager::make_virtio_vsock_devices),
+    ("mem", DeviceManager::make_virtio_mem_devices),
+    #[cfg(feature = "balloon")]
+    ("balloon", DeviceManager::make_virtio_balloon_devices),
+    #[cfg(feature = "watchdog")]
+    ("watchdog", DeviceManager::make_virtio_watchdog_devices),
+    #[cfg(feature = "vdpa")]
+    ("vdpa", DeviceManager::make_vdpa_devices),
+    ("console", DeviceManager::make_virtio_console_port_devices),
+];
+
+/// Whether the virtio devices of the given family are built into the VMM.
+pub fn virtio_devices_built_in(family: &str) -> bool {
+    VIRTIO_DEVICES_REGISTRY
+        .iter()
+        .any(|(name, _)| *name == family)
 }
 
 #[cfg(feature = "sev_snp")]
@@ -814,6 +1002,37 @@ impl AccessPlatform for SevSnpPageAccessProxy {
     }
 }
 
+// Hands the blocks plugged by virtio-mem over to the SEV-SNP guest, and takes
+// them back when they are unplugged.
+#[cfg(feature = "sev_snp")]
+struct SevSnpPageAccessMapping {
+    vm: Arc<dyn hypervisor::Vm>,
+}
+
+#[cfg(feature = "sev_snp")]
+impl ExternalDmaMapping for SevSnpPageAccessMapping {
+    fn map(&self, _iova: u64, gpa: u64, size: u64) -> std::result::Result<(), io::Error> {
+        self.vm
+            .release_page_access(gpa, size)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    fn unmap(&self, iova: u64, size: u64) -> std::result::Result<(), io::Error> {
+        // Several blocks can be unplugged at once, beyond what a single
+        // request can describe.
+        let end = iova + size;
+        let mut gpa = iova;
+        while gpa < end {
+            let len = std::cmp::min(end - gpa, 1 << 30);
+            self.vm
+                .gain_page_access(gpa, len as u32)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            gpa += len;
+        }
+        Ok(())
+    }
+}
+
 pub struct DeviceManager {
     // Manage address space related to devices
     address_manager: Arc<AddressManager>,
@@ -900,6 +1119,9 @@ pub struct DeviceManager {
     exit_evt: EventFd,
     reset_evt: EventFd,
 
+    // Event for requesting the VM to pause, e.g. on IO errors
+    pause_evt: EventFd,
+
     #[cfg(target_arch = "aarch64")]
     id_to_dev_info: HashMap<(DeviceType, String), MmioDeviceInfo>,
 
@@ -910,8 +1132,24 @@ pub struct DeviceManager {
     numa_nodes: NumaNodes,
 
     // Possible handle to the virtio-balloon device
+    #[cfg(feature = "balloon")]
     balloon: Option<Arc<Mutex<virtio_devices::Balloon>>>,
 
+    // Possible handle to the backend of the virtio-vsock device, along with
+    // the device identifier
+    #[cfg(feature = "vsock")]
+    vsock_backend: Option<(
+        String,
+        Arc<std::sync::RwLock<virtio_devices::vsock::VsockUnixBackend>>,
+    )>,
+
+    // Handles to control the request tracing of the virtio-block devices
+    block_tracers: HashMap<String, BlockTracer>,
+
+    // Overlays of the transient disks, unlinked files discarded on shutdown
+    // unless explicitly kept
+    transient_disks: HashMap<String, (File, QcowDiskSync)>,
+
     // Virtio Device activation EventFd to allow the VMM thread to trigger device
     // activation and thus start the threads from the VMM thread
     activate_evt: EventFd,
@@ -936,6 +1174,12 @@ pub struct DeviceManager {
     // pvpanic device
     pvpanic_device: Option<Arc<Mutex<devices::PvPanicDevice>>>,
 
+    // VM generation ID device
+    vmgenid_device: Option<Arc<Mutex<devices::VmGenIdDevice>>>,
+
+    // Hardware error source device
+    ghes_device: Option<Arc<Mutex<devices::GhesDevice>>>,
+
     // Flag to force setting the iommu on virtio devices
     force_iommu: bool,
 
@@ -962,6 +1206,9 @@ pub struct DeviceManager {
     rate_limit_groups: HashMap<String, Arc<RateLimiterGroup>>,
 
     mmio_regions: Arc<Mutex<Vec<MmioRegion>>>,
+
+    // Status of the device removals requested since the VM started
+    unplugs: BTreeMap<String, DeviceUnplugStatus>,
 }
 
 fn create_mmio_allocators(
@@ -970,11 +1217,30 @@ fn create_mmio_allocators(
     num_pci_segments: u16,
     weights: Vec<u32>,
     alignment: u64,
-) -> Vec<Arc<Mutex<AddressAllocator>>> {
-    let total_weight: u32 = weights.iter().sum();
+) -> DeviceManagerResult<Vec<Arc<Mutex<AddressAllocator>>>> {
+    let total_weight = weights.iter().sum::<u32>() as u64;
+
+    // Start each PCI segment mmio range on an aligned boundary. When guest
+    // RAM leaves less than one aligned unit per weight, lower the alignment
+    // instead of handing out empty apertures.
+    let mut alignment = alignment;
+    let (start, pci_segment_mmio_size) = loop {
+        let aligned_start = start.div_ceil(alignment) * alignment;
+        let size = if aligned_start < end {
+            (end - aligned_start + 1) / (alignment * total_weight) * alignment
+        } else {
+            0
+        };
+
+        if size > 0 {
+            break (aligned_start, size);
+        }
 
-    // Start each PCI segment mmio range on an aligned boundary
-    let pci_segment_mmio_size = (end - start + 1) / (alignment * total_weight as u64) * alignment;
+        if alignment <= MIN_MMIO_ALIGNMENT {
+            return Err(DeviceManagerError::MmioAreaTooSmall(start, end));
+        }
+        alignment >>= 1;
+    };
 
     let mut mmio_allocators = vec![];
     let mut i = 0;
@@ -983,13 +1249,14 @@ fn create_mmio_allocators(
         let mmio_start = start + i * pci_segment_mmio_size;
         let mmio_size = pci_segment_mmio_size * weight;
         let allocator = Arc::new(Mutex::new(
-            AddressAllocator::new(GuestAddress(mmio_start), mmio_size).unwrap(),
+            AddressAllocator::new(GuestAddress(mmio_start), mmio_size)
+                .ok_or(DeviceManagerError::MmioAreaTooSmall(start, end))?,
         ));
         mmio_allocators.push(allocator);
         i += weight;
     }
 
-    mmio_allocators
+    Ok(mmio_allocators)
 }
 
 impl DeviceManager {
@@ -1003,6 +1270,7 @@ impl DeviceManager {
         cpu_manager: Arc<Mutex<CpuManager>>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        pause_evt: EventFd,
         seccomp_action: SeccompAction,
         numa_nodes: NumaNodes,
         activate_evt: &EventFd,
@@ -1050,7 +1318,7 @@ impl DeviceManager {
             num_pci_segments,
             mmio32_aperture_weights,
             4 << 10,
-        );
+        )?;
 
         let mut mmio64_aperture_weights: Vec<u32> =
             std::iter::repeat(DEFAULT_PCI_SEGMENT_APERTURE_WEIGHT)
@@ -1071,7 +1339,7 @@ impl DeviceManager {
             num_pci_segments,
             mmio64_aperture_weights,
             4 << 30,
-        );
+        )?;
 
         let address_manager = Arc::new(AddressManager {
             allocator: memory_manager.lock().unwrap().allocator(),
@@ -1196,11 +1464,17 @@ impl DeviceManager {
             device_tree,
             exit_evt,
             reset_evt,
+            pause_evt,
             #[cfg(target_arch = "aarch64")]
             id_to_dev_info: HashMap::new(),
             seccomp_action,
             numa_nodes,
+            #[cfg(feature = "balloon")]
             balloon: None,
+            #[cfg(feature = "vsock")]
+            vsock_backend: None,
+            block_tracers: HashMap::new(),
+            transient_disks: HashMap::new(),
             activate_evt: activate_evt
                 .try_clone()
                 .map_err(DeviceManagerError::EventFd)?,
@@ -1215,6 +1489,8 @@ impl DeviceManager {
             #[cfg(feature = "pvmemcontrol")]
             pvmemcontrol_devices: None,
             pvpanic_device: None,
+            vmgenid_device: None,
+            ghes_device: None,
             force_iommu,
             io_uring_supported: None,
             aio_supported: None,
@@ -1225,6 +1501,7 @@ impl DeviceManager {
             snapshot,
             rate_limit_groups,
             mmio_regions: Arc::new(Mutex::new(Vec::new())),
+            unplugs: BTreeMap::new(),
         };
 
         let device_manager = Arc::new(Mutex::new(device_manager));
@@ -1262,6 +1539,9 @@ impl DeviceManager {
             .unwrap()
             .set_interrupt_controller(interrupt_controller.clone());
 
+        #[cfg(feature = "tdx")]
+        self.add_tdx_quote_generator()?;
+
         // Now we can create the legacy interrupt manager, which needs the freshly
         // formed IOAPIC device.
         let legacy_interrupt_manager: Arc<
@@ -1305,6 +1585,19 @@ impl DeviceManager {
             )?;
         }
 
+        #[cfg(target_arch = "x86_64")]
+        if self
+            .config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .is_some_and(|p| p.vmgenid)
+        {
+            self.vmgenid_device = Some(self.add_vmgenid_device()?);
+        }
+        self.ghes_device = Some(self.add_ghes_device()?);
+
         self.original_termios_opt = original_termios_opt;
 
         self.console = self.add_console_devices(
@@ -1476,7 +1769,7 @@ impl DeviceManager {
     ) -> DeviceManagerResult<Arc<Mutex<dyn InterruptController>>> {
         let interrupt_controller: Arc<Mutex<gic::Gic>> = Arc::new(Mutex::new(
             gic::Gic::new(
-                self.config.lock().unwrap().cpus.boot_vcpus,
+                self.cpu_manager.lock().unwrap().vgic_vcpu_count(),
                 Arc::clone(&self.msi_interrupt_manager),
                 self.address_manager.vm.clone(),
             )
@@ -1535,7 +1828,7 @@ impl DeviceManager {
                 id.clone(),
                 APIC_START,
                 Arc::clone(&self.msi_interrupt_manager),
-                state_from_id(self.snapshot.as_ref(), id.as_str())
+                versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
                     .map_err(DeviceManagerError::RestoreGetState)?,
             )
             .map_err(DeviceManagerError::CreateInterruptController)?,
@@ -1724,15 +2017,26 @@ impl DeviceManager {
                 .insert(cmos, 0x70, 0x2)
                 .map_err(DeviceManagerError::BusError)?;
 
-            let fwdebug = Arc::new(Mutex::new(devices::legacy::FwDebugDevice::new()));
+            // The debug console takes over the firmware debug port when it
+            // is asked to capture the firmware output.
+            let debug_console_config = self.config.lock().unwrap().debug_console.clone();
+            let fw_port_captured = matches!(
+                debug_console_config.mode,
+                ConsoleOutputMode::File | ConsoleOutputMode::Tty | ConsoleOutputMode::Pipe
+            ) && debug_console_config.iobase.map(|port| port as u64)
+                == Some(debug_console::FIRMWARE_PORT);
+
+            if !fw_port_captured {
+                let fwdebug = Arc::new(Mutex::new(devices::legacy::FwDebugDevice::new()));
 
-            self.bus_devices
-                .push(Arc::clone(&fwdebug) as Arc<dyn BusDeviceSync>);
+                self.bus_devices
+                    .push(Arc::clone(&fwdebug) as Arc<dyn BusDeviceSync>);
 
-            self.address_manager
-                .io_bus
-                .insert(fwdebug, 0x402, 0x1)
-                .map_err(DeviceManagerError::BusError)?;
+                self.address_manager
+                    .io_bus
+                    .insert(fwdebug, debug_console::FIRMWARE_PORT, 0x1)
+                    .map_err(DeviceManagerError::BusError)?;
+            }
         }
 
         // 0x80 debug port
@@ -1807,7 +2111,7 @@ impl DeviceManager {
         let gpio_device = Arc::new(Mutex::new(devices::legacy::Gpio::new(
             id.clone(),
             interrupt_group,
-            state_from_id(self.snapshot.as_ref(), id.as_str())
+            versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
                 .map_err(DeviceManagerError::RestoreGetState)?,
         )));
 
@@ -1846,17 +2150,14 @@ impl DeviceManager {
         debug_console_writer: Box<dyn io::Write + Send>,
     ) -> DeviceManagerResult<Arc<Mutex<DebugConsole>>> {
         let id = String::from(DEBUGCON_DEVICE_NAME);
+        let debug_console_config = self.config.lock().unwrap().debug_console.clone();
         let debug_console = Arc::new(Mutex::new(DebugConsole::new(
             id.clone(),
             debug_console_writer,
+            debug_console_config.timestamps.then_some(self.timestamp),
         )));
 
-        let port = self
-            .config
-            .lock()
-            .unwrap()
-            .debug_console
-            .clone()
+        let port = debug_console_config
             .iobase
             .map(|port| port as u64)
             .unwrap_or(debug_console::DEFAULT_PORT);
@@ -1908,7 +2209,7 @@ impl DeviceManager {
             id.clone(),
             interrupt_group,
             serial_writer,
-            state_from_id(self.snapshot.as_ref(), id.as_str())
+            versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
                 .map_err(DeviceManagerError::RestoreGetState)?,
         )));
 
@@ -1965,7 +2266,7 @@ impl DeviceManager {
             interrupt_group,
             serial_writer,
             self.timestamp,
-            state_from_id(self.snapshot.as_ref(), id.as_str())
+            versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
                 .map_err(DeviceManagerError::RestoreGetState)?,
         )));
 
@@ -2006,59 +2307,30 @@ impl DeviceManager {
         &mut self,
         virtio_devices: &mut Vec<MetaVirtioDevice>,
         console_fd: Option<RawFd>,
+        console_input_fd: Option<RawFd>,
         resize_pipe: Option<Arc<File>>,
     ) -> DeviceManagerResult<Option<Arc<virtio_devices::ConsoleResizer>>> {
         let console_config = self.config.lock().unwrap().console.clone();
         let endpoint = match console_config.mode {
-            ConsoleOutputMode::File => {
-                if let Some(file_fd) = console_fd {
-                    // SAFETY: file_fd is guaranteed to be a valid fd from
-                    // pre_create_console_devices() in vmm/src/console_devices.rs
-                    Endpoint::File(unsafe { File::from_raw_fd(file_fd) })
-                } else {
-                    return Err(DeviceManagerError::InvalidConsoleFd);
-                }
-            }
-            ConsoleOutputMode::Pty => {
-                if let Some(pty_fd) = console_fd {
-                    // SAFETY: pty_fd is guaranteed to be a valid fd from
-                    // pre_create_console_devices() in vmm/src/console_devices.rs
-                    let file = unsafe { File::from_raw_fd(pty_fd) };
+            ConsoleOutputMode::Off => return Ok(None),
+            ConsoleOutputMode::Null => Endpoint::Null,
+            ref mode => {
+                let fd = console_fd.ok_or(DeviceManagerError::InvalidConsoleFd)?;
+                // SAFETY: fd and console_input_fd are guaranteed to be valid
+                // fds from pre_create_console_devices() in
+                // vmm/src/console_devices.rs
+                let (file, input) = unsafe {
+                    (
+                        File::from_raw_fd(fd),
+                        console_input_fd.map(|fd| File::from_raw_fd(fd)),
+                    )
+                };
+                if *mode == ConsoleOutputMode::Pty {
                     self.console_resize_pipe = resize_pipe;
-                    Endpoint::PtyPair(file.try_clone().unwrap(), file)
-                } else {
-                    return Err(DeviceManagerError::InvalidConsoleFd);
-                }
-            }
-            ConsoleOutputMode::Tty => {
-                if let Some(tty_fd) = console_fd {
-                    // SAFETY: tty_fd is guaranteed to be a valid fd from
-                    // pre_create_console_devices() in vmm/src/console_devices.rs
-                    let stdout = unsafe { File::from_raw_fd(tty_fd) };
-                    // If an interactive TTY then we can accept input
-                    // SAFETY: FFI call. Trivially safe.
-                    if unsafe { libc::isatty(libc::STDIN_FILENO) == 1 } {
-                        // SAFETY: FFI call to dup. Trivially safe.
-                        let stdin = unsafe { libc::dup(libc::STDIN_FILENO) };
-                        if stdin == -1 {
-                            return vmm_sys_util::errno::errno_result()
-                                .map_err(DeviceManagerError::DupFd);
-                        }
-                        // SAFETY: stdin is valid and owned solely by us.
-                        let stdin = unsafe { File::from_raw_fd(stdin) };
-                        Endpoint::FilePair(stdout, stdin)
-                    } else {
-                        Endpoint::File(stdout)
-                    }
-                } else {
-                    return Err(DeviceManagerError::InvalidConsoleFd);
                 }
+                virtio_console_endpoint(mode, file, input)
+                    .map_err(DeviceManagerError::CreateConsoleEndpoint)?
             }
-            ConsoleOutputMode::Socket => {
-                return Err(DeviceManagerError::NoSocketOptionSupportForConsoleDevice);
-            }
-            ConsoleOutputMode::Null => Endpoint::Null,
-            ConsoleOutputMode::Off => return Ok(None),
         };
         let id = String::from(CONSOLE_DEVICE_NAME);
 
@@ -2122,6 +2394,7 @@ impl DeviceManager {
 
         // SAFETY: console_info is Some, so it's safe to unwrap.
         let console_info = console_info.unwrap();
+        // The serial manager writes to the endpoints it reads from.
         let serial_writer: Option<Box<dyn io::Write + Send>> = match serial_config.mode {
             ConsoleOutputMode::File | ConsoleOutputMode::Tty => {
                 if console_info.serial_main_fd.is_none() {
@@ -2137,17 +2410,47 @@ impl DeviceManager {
             ConsoleOutputMode::Off
             | ConsoleOutputMode::Null
             | ConsoleOutputMode::Pty
-            | ConsoleOutputMode::Socket => None,
+            | ConsoleOutputMode::Pipe
+            | ConsoleOutputMode::Socket
+            | ConsoleOutputMode::Tcp => None,
+        };
+        let serial_log = if let Some(log_socket) = serial_config.log_socket.as_ref() {
+            // Records are tagged with the UUID of the VM, if any, otherwise
+            // with the PID of the VMM.
+            let vm_id = self
+                .config
+                .lock()
+                .unwrap()
+                .platform
+                .as_ref()
+                .and_then(|p| p.uuid.clone())
+                .unwrap_or_else(|| std::process::id().to_string());
+            Some(
+                ConsoleLog::new(log_socket.clone(), "serial", vm_id)
+                    .map_err(DeviceManagerError::CreateConsoleLog)?,
+            )
+        } else {
+            None
+        };
+        let serial_writer = match serial_log.as_ref() {
+            Some(serial_log) => Some(serial_log.writer(serial_writer)),
+            None => serial_writer,
         };
         if serial_config.mode != ConsoleOutputMode::Off {
             let serial = self.add_serial_device(interrupt_manager, serial_writer)?;
             self.serial_manager = match serial_config.mode {
-                ConsoleOutputMode::Pty | ConsoleOutputMode::Tty | ConsoleOutputMode::Socket => {
+                ConsoleOutputMode::Pty
+                | ConsoleOutputMode::Tty
+                | ConsoleOutputMode::Pipe
+                | ConsoleOutputMode::Socket
+                | ConsoleOutputMode::Tcp => {
                     let serial_manager = SerialManager::new(
                         serial,
                         console_info.serial_main_fd,
+                        console_info.serial_input_fd,
                         serial_config.mode,
                         serial_config.socket,
+                        serial_log,
                     )
                     .map_err(DeviceManagerError::CreateSerialManager)?;
                     if let Some(mut serial_manager) = serial_manager {
@@ -2172,7 +2475,7 @@ impl DeviceManager {
             let debug_console_config = self.config.lock().unwrap().debug_console.clone();
             let debug_console_writer: Option<Box<dyn io::Write + Send>> =
                 match debug_console_config.mode {
-                    ConsoleOutputMode::File | ConsoleOutputMode::Tty => {
+                    ConsoleOutputMode::File | ConsoleOutputMode::Tty | ConsoleOutputMode::Pipe => {
                         if console_info.debug_main_fd.is_none() {
                             return Err(DeviceManagerError::InvalidConsoleInfo);
                         }
@@ -2186,7 +2489,8 @@ impl DeviceManager {
                     ConsoleOutputMode::Off
                     | ConsoleOutputMode::Null
                     | ConsoleOutputMode::Pty
-                    | ConsoleOutputMode::Socket => None,
+                    | ConsoleOutputMode::Socket
+                    | ConsoleOutputMode::Tcp => None,
                 };
             if let Some(writer) = debug_console_writer {
                 let _ = self.add_debug_console_device(writer)?;
@@ -2196,6 +2500,7 @@ impl DeviceManager {
         let console_resizer = self.add_virtio_console_device(
             virtio_devices,
             console_info.console_main_fd,
+            console_info.console_input_fd,
             console_resize_pipe,
         )?;
 
@@ -2228,30 +2533,10 @@ impl DeviceManager {
     fn make_virtio_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices: Vec<MetaVirtioDevice> = Vec::new();
 
-        // Create "standard" virtio devices (net/block/rng)
-        devices.append(&mut self.make_virtio_block_devices()?);
-        devices.append(&mut self.make_virtio_net_devices()?);
-        devices.append(&mut self.make_virtio_rng_devices()?);
-
-        // Add virtio-fs if required
-        devices.append(&mut self.make_virtio_fs_devices()?);
-
-        // Add virtio-pmem if required
-        devices.append(&mut self.make_virtio_pmem_devices()?);
-
-        // Add virtio-vsock if required
-        devices.append(&mut self.make_virtio_vsock_devices()?);
-
-        devices.append(&mut self.make_virtio_mem_devices()?);
-
-        // Add virtio-balloon if required
-        devices.append(&mut self.make_virtio_balloon_devices()?);
-
-        // Add virtio-watchdog device
-        devices.append(&mut self.make_virtio_watchdog_devices()?);
-
-        // Add vDPA devices if required
-        devices.append(&mut self.make_vdpa_devices()?);
+        for (family, make_devices) in VIRTIO_DEVICES_REGISTRY {
+            debug!("Creating virtio {} devices", family);
+            devices.append(&mut make_devices(self)?);
+        }
 
         Ok(devices)
     }
@@ -2278,6 +2563,92 @@ impl DeviceManager {
         supported
     }
 
+    // The image is read from the cache file, which gets filled as the guest
+    // reads the image. The cache file is locked for writing, as two VMs can't
+    // share it.
+    fn make_http_disk(
+        &self,
+        disk_cfg: &mut DiskConfig,
+    ) -> DeviceManagerResult<(Box<dyn DiskFile>, ImageLock)> {
+        let url = disk_cfg
+            .path
+            .as_ref()
+            .and_then(|path| path.to_str())
+            .ok_or(DeviceManagerError::NoDiskPath)?;
+        let cache = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(disk_cfg.cache.as_ref().unwrap())
+            .map_err(DeviceManagerError::OpenDiskCache)?;
+
+        if let Some(io_max) = disk_cfg.io_max.as_ref() {
+            block::qos::set_io_max(&cache, io_max).map_err(DeviceManagerError::SetDiskIoMax)?;
+        }
+
+        let image_lock = ImageLock::new(
+            cache
+                .try_clone()
+                .map_err(DeviceManagerError::OpenDiskCache)?,
+            LockType::Write,
+        );
+
+        info!("Using synchronous HTTP disk file");
+        let image = Box::new(
+            HttpDiskSync::new(url, cache).map_err(DeviceManagerError::CreateHttpDiskSync)?,
+        ) as Box<dyn DiskFile>;
+        disk_cfg.image_type = Some(ImageType::Raw);
+
+        Ok((image, image_lock))
+    }
+
+    fn make_transient_overlay(
+        &mut self,
+        id: &str,
+        disk_cfg: &DiskConfig,
+    ) -> DeviceManagerResult<Box<dyn DiskFile>> {
+        // The overlay records the base image by its absolute path, which is
+        // reopened read-only for the clusters the guest didn't write.
+        let base = disk_cfg
+            .path
+            .as_ref()
+            .ok_or(DeviceManagerError::NoDiskPath)?
+            .canonicalize()
+            .map_err(DeviceManagerError::CreateTransientOverlay)?;
+        let base = base.to_str().ok_or_else(|| {
+            DeviceManagerError::CreateTransientOverlay(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The disk path isn't valid UTF-8",
+            ))
+        })?;
+
+        let mut custom_flags = O_TMPFILE;
+        if disk_cfg.direct {
+            custom_flags |= libc::O_DIRECT;
+        }
+        let overlay = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(custom_flags)
+            .open(std::env::temp_dir())
+            .map_err(DeviceManagerError::CreateTransientOverlay)?;
+
+        info!("Using synchronous QCOW overlay over {}", base);
+        let image = QcowDiskSync::new_with_backing(
+            overlay
+                .try_clone()
+                .map_err(DeviceManagerError::CreateTransientOverlay)?,
+            disk_cfg.direct,
+            base,
+        )
+        .map_err(DeviceManagerError::CreateQcowDiskSync)?;
+        self.transient_disks
+            .insert(id.to_owned(), (overlay, image.clone()));
+
+        Ok(Box::new(image) as Box<dyn DiskFile>)
+    }
+
     fn make_virtio_block_device(
         &mut self,
         disk_cfg: &mut DiskConfig,
@@ -2323,89 +2694,153 @@ impl DeviceManager {
                 vhost_user_block as Arc<Mutex<dyn Migratable>>,
             )
         } else {
-            let mut options = OpenOptions::new();
-            options.read(true);
-            options.write(!disk_cfg.readonly);
-            if disk_cfg.direct {
-                options.custom_flags(libc::O_DIRECT);
-            }
-            // Open block device path
-            let mut file: File = options
-                .open(
-                    disk_cfg
-                        .path
-                        .as_ref()
-                        .ok_or(DeviceManagerError::NoDiskPath)?
-                        .clone(),
-                )
-                .map_err(DeviceManagerError::Disk)?;
-            let image_type =
-                detect_image_type(&mut file).map_err(DeviceManagerError::DetectImageType)?;
-
-            let image = match image_type {
-                ImageType::FixedVhd => {
-                    // Use asynchronous backend relying on io_uring if the
-                    // syscalls are supported.
-                    if cfg!(feature = "io_uring")
-                        && !disk_cfg.disable_io_uring
-                        && self.io_uring_is_supported()
-                    {
-                        info!("Using asynchronous fixed VHD disk file (io_uring)");
+            let (image, image_lock) = if disk_cfg.is_http() {
+                self.make_http_disk(disk_cfg)?
+            } else {
+                let mut options = OpenOptions::new();
+                options.read(true);
+                options.write(!disk_cfg.readonly && !disk_cfg.transient);
+                if disk_cfg.direct {
+                    options.custom_flags(libc::O_DIRECT);
+                }
+                // Open block device path
+                let mut file: File = options
+                    .open(
+                        disk_cfg
+                            .path
+                            .as_ref()
+                            .ok_or(DeviceManagerError::NoDiskPath)?
+                            .clone(),
+                    )
+                    .map_err(DeviceManagerError::Disk)?;
+                let image_type =
+                    detect_image_type(&mut file).map_err(DeviceManagerError::DetectImageType)?;
+
+                // Refuse attaching an image whose format differs from the expected
+                // one, otherwise pin the detected format so that any later attach
+                // (reboot, restore, migration) verifies the image is unchanged.
+                match disk_cfg.image_type {
+                    Some(expected) if expected != image_type => {
+                        return Err(DeviceManagerError::DiskImageTypeMismatch(
+                            disk_cfg.path.clone().unwrap(),
+                            expected,
+                            image_type,
+                        ));
+                    }
+                    Some(_) => {}
+                    None => disk_cfg.image_type = Some(image_type),
+                }
+
+                if let Some(io_max) = disk_cfg.io_max.as_ref() {
+                    block::qos::set_io_max(&file, io_max)
+                        .map_err(DeviceManagerError::SetDiskIoMax)?;
+                }
+
+                // Read-only and shared attachments can coexist, while a writable
+                // attachment requires exclusive access to the image. Transient
+                // disks never write to the image itself.
+                let lock_type = if disk_cfg.readonly || disk_cfg.share || disk_cfg.transient {
+                    LockType::Read
+                } else {
+                    LockType::Write
+                };
+                let image_lock = ImageLock::new(
+                    file.try_clone().map_err(DeviceManagerError::Disk)?,
+                    lock_type,
+                );
 
-                        #[cfg(not(feature = "io_uring"))]
-                        unreachable!("Checked in if statement above");
-                        #[cfg(feature = "io_uring")]
+                let image = match image_type {
+                    _ if disk_cfg.transient => self.make_transient_overlay(&id, disk_cfg)?,
+                    // Encrypted disks rely on a synchronous backend as the data
+                    // goes through the cipher before reaching the image.
+                    _ if disk_cfg.key_file.is_some() || disk_cfg.key_resource.is_some() => {
+                        info!("Using synchronous encrypted {} disk file", image_type);
+                        // The key is wiped from memory once the cipher is set up.
+                        let key = Zeroizing::new(if let Some(resource) = &disk_cfg.key_resource {
+                            // Fetched right before use so that the key never lands
+                            // in the VM configuration. The configuration isn't kept
+                            // locked while waiting for the key broker.
+                            let config = self.config.lock().unwrap().clone();
+                            kbs::fetch(&config, resource)
+                                .map_err(DeviceManagerError::FetchDiskKey)?
+                        } else {
+                            std::fs::read(disk_cfg.key_file.as_ref().unwrap())
+                                .map_err(DeviceManagerError::ReadDiskKey)?
+                        });
+                        file.rewind().map_err(DeviceManagerError::Disk)?;
+                        let backend = block::create_disk_file(file, disk_cfg.direct)
+                            .map_err(DeviceManagerError::CreateDiskBackend)?;
+                        Box::new(
+                            CryptDiskSync::new(backend, &key)
+                                .map_err(DeviceManagerError::CreateCryptDiskSync)?,
+                        ) as Box<dyn DiskFile>
+                    }
+                    ImageType::FixedVhd => {
+                        // Use asynchronous backend relying on io_uring if the
+                        // syscalls are supported.
+                        if cfg!(feature = "io_uring")
+                            && !disk_cfg.disable_io_uring
+                            && self.io_uring_is_supported()
                         {
+                            info!("Using asynchronous fixed VHD disk file (io_uring)");
+
+                            #[cfg(not(feature = "io_uring"))]
+                            unreachable!("Checked in if statement above");
+                            #[cfg(feature = "io_uring")]
+                            {
+                                Box::new(
+                                    FixedVhdDiskAsync::new(file)
+                                        .map_err(DeviceManagerError::CreateFixedVhdDiskAsync)?,
+                                ) as Box<dyn DiskFile>
+                            }
+                        } else {
+                            info!("Using synchronous fixed VHD disk file");
                             Box::new(
-                                FixedVhdDiskAsync::new(file)
-                                    .map_err(DeviceManagerError::CreateFixedVhdDiskAsync)?,
+                                FixedVhdDiskSync::new(file)
+                                    .map_err(DeviceManagerError::CreateFixedVhdDiskSync)?,
                             ) as Box<dyn DiskFile>
                         }
-                    } else {
-                        info!("Using synchronous fixed VHD disk file");
-                        Box::new(
-                            FixedVhdDiskSync::new(file)
-                                .map_err(DeviceManagerError::CreateFixedVhdDiskSync)?,
-                        ) as Box<dyn DiskFile>
                     }
-                }
-                ImageType::Raw => {
-                    // Use asynchronous backend relying on io_uring if the
-                    // syscalls are supported.
-                    if cfg!(feature = "io_uring")
-                        && !disk_cfg.disable_io_uring
-                        && self.io_uring_is_supported()
-                    {
-                        info!("Using asynchronous RAW disk file (io_uring)");
-
-                        #[cfg(not(feature = "io_uring"))]
-                        unreachable!("Checked in if statement above");
-                        #[cfg(feature = "io_uring")]
+                    ImageType::Raw => {
+                        // Use asynchronous backend relying on io_uring if the
+                        // syscalls are supported.
+                        if cfg!(feature = "io_uring")
+                            && !disk_cfg.disable_io_uring
+                            && self.io_uring_is_supported()
                         {
-                            Box::new(RawFileDisk::new(file)) as Box<dyn DiskFile>
+                            info!("Using asynchronous RAW disk file (io_uring)");
+
+                            #[cfg(not(feature = "io_uring"))]
+                            unreachable!("Checked in if statement above");
+                            #[cfg(feature = "io_uring")]
+                            {
+                                Box::new(RawFileDisk::new(file)) as Box<dyn DiskFile>
+                            }
+                        } else if !disk_cfg.disable_aio && self.aio_is_supported() {
+                            info!("Using asynchronous RAW disk file (aio)");
+                            Box::new(RawFileDiskAio::new(file)) as Box<dyn DiskFile>
+                        } else {
+                            info!("Using synchronous RAW disk file");
+                            Box::new(RawFileDiskSync::new(file)) as Box<dyn DiskFile>
                         }
-                    } else if !disk_cfg.disable_aio && self.aio_is_supported() {
-                        info!("Using asynchronous RAW disk file (aio)");
-                        Box::new(RawFileDiskAio::new(file)) as Box<dyn DiskFile>
-                    } else {
-                        info!("Using synchronous RAW disk file");
-                        Box::new(RawFileDiskSync::new(file)) as Box<dyn DiskFile>
                     }
-                }
-                ImageType::Qcow2 => {
-                    info!("Using synchronous QCOW disk file");
-                    Box::new(
-                        QcowDiskSync::new(file, disk_cfg.direct)
-                            .map_err(DeviceManagerError::CreateQcowDiskSync)?,
-                    ) as Box<dyn DiskFile>
-                }
-                ImageType::Vhdx => {
-                    info!("Using synchronous VHDX disk file");
-                    Box::new(
-                        VhdxDiskSync::new(file)
-                            .map_err(DeviceManagerError::CreateFixedVhdxDiskSync)?,
-                    ) as Box<dyn DiskFile>
-                }
+                    ImageType::Qcow2 => {
+                        info!("Using synchronous QCOW disk file");
+                        Box::new(
+                            QcowDiskSync::new(file, disk_cfg.direct)
+                                .map_err(DeviceManagerError::CreateQcowDiskSync)?,
+                        ) as Box<dyn DiskFile>
+                    }
+                    ImageType::Vhdx => {
+                        info!("Using synchronous VHDX disk file");
+                        Box::new(
+                            VhdxDiskSync::new(file)
+                                .map_err(DeviceManagerError::CreateFixedVhdxDiskSync)?,
+                        ) as Box<dyn DiskFile>
+                    }
+                };
+
+                (image, image_lock)
             };
 
             let rate_limit_group =
@@ -2471,10 +2906,20 @@ impl DeviceManager {
                     state_from_id(self.snapshot.as_ref(), id.as_str())
                         .map_err(DeviceManagerError::RestoreGetState)?,
                     queue_affinity,
+                    disk_cfg.io_priority,
+                    Some(image_lock),
+                    disk_cfg.io_timeout.map(Duration::from_millis),
+                    disk_cfg.on_io_error,
+                    self.pause_evt
+                        .try_clone()
+                        .map_err(DeviceManagerError::EventFd)?,
                 )
                 .map_err(DeviceManagerError::CreateVirtioBlock)?,
             ));
 
+            self.block_tracers
+                .insert(id.clone(), virtio_block.lock().unwrap().tracer());
+
             (
                 Arc::clone(&virtio_block) as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
                 virtio_block as Arc<Mutex<dyn Migratable>>,
@@ -2553,6 +2998,7 @@ impl DeviceManager {
                     net_cfg.offload_tso,
                     net_cfg.offload_ufo,
                     net_cfg.offload_csum,
+                    net_cfg.standby,
                 ) {
                     Ok(vun_device) => vun_device,
                     Err(e) => {
@@ -2590,6 +3036,7 @@ impl DeviceManager {
                         net_cfg.offload_tso,
                         net_cfg.offload_ufo,
                         net_cfg.offload_csum,
+                        net_cfg.standby,
                     )
                     .map_err(DeviceManagerError::CreateVirtioNet)?,
                 ))
@@ -2610,6 +3057,7 @@ impl DeviceManager {
                     net_cfg.offload_tso,
                     net_cfg.offload_ufo,
                     net_cfg.offload_csum,
+                    net_cfg.standby,
                 )
                 .map_err(DeviceManagerError::CreateVirtioNet)?;
 
@@ -2641,6 +3089,7 @@ impl DeviceManager {
                         net_cfg.offload_tso,
                         net_cfg.offload_ufo,
                         net_cfg.offload_csum,
+                        net_cfg.standby,
                     )
                     .map_err(DeviceManagerError::CreateVirtioNet)?,
                 ))
@@ -2683,6 +3132,7 @@ impl DeviceManager {
         Ok(devices)
     }
 
+    #[cfg(feature = "rng")]
     fn make_virtio_rng_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices = Vec::new();
 
@@ -2744,32 +3194,58 @@ impl DeviceManager {
         let mut node = device_node!(id);
 
         if let Some(fs_socket) = fs_cfg.socket.to_str() {
-            let virtio_fs_device = Arc::new(Mutex::new(
-                virtio_devices::vhost_user::Fs::new(
-                    id.clone(),
-                    fs_socket,
-                    &fs_cfg.tag,
-                    fs_cfg.num_queues,
-                    fs_cfg.queue_size,
-                    None,
-                    self.seccomp_action.clone(),
-                    self.exit_evt
-                        .try_clone()
-                        .map_err(DeviceManagerError::EventFd)?,
-                    self.force_iommu,
-                    state_from_id(self.snapshot.as_ref(), id.as_str())
-                        .map_err(DeviceManagerError::RestoreGetState)?,
-                )
-                .map_err(DeviceManagerError::CreateVirtioFs)?,
-            ));
+            let exit_evt = self
+                .exit_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?;
+            let (virtio_device, migratable): (
+                Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+                Arc<Mutex<dyn Migratable>>,
+            ) = match fs_cfg.protocol {
+                FsProtocol::VirtioFs => {
+                    let virtio_fs_device = Arc::new(Mutex::new(
+                        virtio_devices::vhost_user::Fs::new(
+                            id.clone(),
+                            fs_socket,
+                            &fs_cfg.tag,
+                            fs_cfg.num_queues,
+                            fs_cfg.queue_size,
+                            None,
+                            self.seccomp_action.clone(),
+                            exit_evt,
+                            self.force_iommu,
+                            state_from_id(self.snapshot.as_ref(), id.as_str())
+                                .map_err(DeviceManagerError::RestoreGetState)?,
+                        )
+                        .map_err(DeviceManagerError::CreateVirtioFs)?,
+                    ));
+                    (virtio_fs_device.clone(), virtio_fs_device)
+                }
+                FsProtocol::P9 => {
+                    let virtio_9p_device = Arc::new(Mutex::new(
+                        virtio_devices::Fs9p::new(
+                            id.clone(),
+                            fs_socket,
+                            &fs_cfg.tag,
+                            fs_cfg.queue_size,
+                            self.force_iommu,
+                            self.seccomp_action.clone(),
+                            exit_evt,
+                            state_from_id(self.snapshot.as_ref(), id.as_str())
+                                .map_err(DeviceManagerError::RestoreGetState)?,
+                        )
+                        .map_err(DeviceManagerError::CreateVirtio9p)?,
+                    ));
+                    (virtio_9p_device.clone(), virtio_9p_device)
+                }
+            };
 
             // Update the device tree with the migratable device.
-            node.migratable = Some(Arc::clone(&virtio_fs_device) as Arc<Mutex<dyn Migratable>>);
+            node.migratable = Some(migratable);
             self.device_tree.lock().unwrap().insert(id.clone(), node);
 
             Ok(MetaVirtioDevice {
-                virtio_device: Arc::clone(&virtio_fs_device)
-                    as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+                virtio_device,
                 iommu: false,
                 id,
                 pci_segment: fs_cfg.pci_segment,
@@ -2794,26 +3270,89 @@ impl DeviceManager {
         Ok(devices)
     }
 
-    fn make_virtio_pmem_device(
+    fn make_virtio_gpu_device(
         &mut self,
-        pmem_cfg: &mut PmemConfig,
+        gpu_cfg: &mut GpuConfig,
     ) -> DeviceManagerResult<MetaVirtioDevice> {
-        let id = if let Some(id) = &pmem_cfg.id {
+        let id = if let Some(id) = &gpu_cfg.id {
             id.clone()
         } else {
-            let id = self.next_device_name(PMEM_DEVICE_NAME_PREFIX)?;
-            pmem_cfg.id = Some(id.clone());
+            let id = self.next_device_name(GPU_DEVICE_NAME_PREFIX)?;
+            gpu_cfg.id = Some(id.clone());
             id
         };
 
-        info!("Creating virtio-pmem device: {:?}", pmem_cfg);
-
-        let mut node = device_node!(id);
+        info!("Creating vhost-user-gpu device: {:?}", gpu_cfg);
 
-        // Look for the id in the device tree. If it can be found, that means
-        // the device is being restored, otherwise it's created from scratch.
-        let region_range = if let Some(node) = self.device_tree.lock().unwrap().get(&id) {
-            info!("Restoring virtio-pmem {} resources", id);
+        let gpu_socket = gpu_cfg
+            .socket
+            .to_str()
+            .ok_or(DeviceManagerError::NoVhostUserGpuSock)?;
+        let gpu_device = Arc::new(Mutex::new(
+            virtio_devices::vhost_user::Gpu::new(
+                id.clone(),
+                gpu_socket,
+                gpu_cfg.queue_size,
+                self.seccomp_action.clone(),
+                self.exit_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
+                self.force_iommu,
+                state_from_id(self.snapshot.as_ref(), id.as_str())
+                    .map_err(DeviceManagerError::RestoreGetState)?,
+            )
+            .map_err(DeviceManagerError::CreateVhostUserGpu)?,
+        ));
+
+        // Fill the device tree with a new node. In case of restore, we
+        // know there is nothing to do, so we can simply override the
+        // existing entry.
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, gpu_device));
+
+        Ok(MetaVirtioDevice {
+            virtio_device: Arc::clone(&gpu_device) as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+            iommu: false,
+            id,
+            pci_segment: gpu_cfg.pci_segment,
+            dma_handler: None,
+        })
+    }
+
+    fn make_virtio_gpu_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let mut gpu = self.config.lock().unwrap().gpu.clone();
+        if let Some(ref mut gpu_cfg) = &mut gpu {
+            devices.push(self.make_virtio_gpu_device(gpu_cfg)?);
+        }
+        self.config.lock().unwrap().gpu = gpu;
+
+        Ok(devices)
+    }
+
+    fn make_virtio_pmem_device(
+        &mut self,
+        pmem_cfg: &mut PmemConfig,
+    ) -> DeviceManagerResult<MetaVirtioDevice> {
+        let id = if let Some(id) = &pmem_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(PMEM_DEVICE_NAME_PREFIX)?;
+            pmem_cfg.id = Some(id.clone());
+            id
+        };
+
+        info!("Creating virtio-pmem device: {:?}", pmem_cfg);
+
+        let mut node = device_node!(id);
+
+        // Look for the id in the device tree. If it can be found, that means
+        // the device is being restored, otherwise it's created from scratch.
+        let region_range = if let Some(node) = self.device_tree.lock().unwrap().get(&id) {
+            info!("Restoring virtio-pmem {} resources", id);
 
             let mut region_range: Option<(u64, u64)> = None;
             for resource in node.resources.iter() {
@@ -2980,6 +3519,7 @@ impl DeviceManager {
         Ok(devices)
     }
 
+    #[cfg(feature = "vsock")]
     fn make_virtio_vsock_device(
         &mut self,
         vsock_cfg: &mut VsockConfig,
@@ -2998,9 +3538,13 @@ impl DeviceManager {
             .socket
             .to_str()
             .ok_or(DeviceManagerError::CreateVsockConvertPath)?;
-        let backend =
+        let mut backend =
             virtio_devices::vsock::VsockUnixBackend::new(vsock_cfg.cid, socket_path.to_string())
                 .map_err(DeviceManagerError::CreateVsockBackend)?;
+        for forward in vsock_cfg.forwards.iter() {
+            Self::apply_vsock_forward(&mut backend, forward, true)
+                .map_err(DeviceManagerError::VsockForward)?;
+        }
 
         let vsock_device = Arc::new(Mutex::new(
             virtio_devices::Vsock::new(
@@ -3018,6 +3562,7 @@ impl DeviceManager {
             )
             .map_err(DeviceManagerError::CreateVirtioVsock)?,
         ));
+        self.vsock_backend = Some((id.clone(), vsock_device.lock().unwrap().backend()));
 
         // Fill the device tree with a new node. In case of restore, we
         // know there is nothing to do, so we can simply override the
@@ -3037,6 +3582,7 @@ impl DeviceManager {
         })
     }
 
+    #[cfg(feature = "vsock")]
     fn make_virtio_vsock_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices = Vec::new();
 
@@ -3079,6 +3625,22 @@ impl DeviceManager {
                     .map_err(DeviceManagerError::CreateVirtioMem)?,
                 ));
 
+                // The plugged blocks are validated by the guest, which must
+                // own them.
+                #[cfg(feature = "sev_snp")]
+                if self.config.lock().unwrap().is_sev_snp_enabled() {
+                    virtio_mem_device
+                        .lock()
+                        .unwrap()
+                        .add_dma_mapping_handler(
+                            VirtioMemMappingSource::Isolation,
+                            Arc::new(SevSnpPageAccessMapping {
+                                vm: self.address_manager.vm.clone(),
+                            }),
+                        )
+                        .map_err(DeviceManagerError::AddDmaMappingHandlerVirtioMem)?;
+                }
+
                 // Update the virtio-mem zone so that it has a handle onto the
                 // virtio-mem device, which will be used for triggering a resize
                 // if needed.
@@ -3150,6 +3712,7 @@ impl DeviceManager {
         Ok((pvmemcontrol_bus_device, pvmemcontrol_pci_device))
     }
 
+    #[cfg(feature = "balloon")]
     fn make_virtio_balloon_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices = Vec::new();
 
@@ -3193,6 +3756,7 @@ impl DeviceManager {
         Ok(devices)
     }
 
+    #[cfg(feature = "watchdog")]
     fn make_virtio_watchdog_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices = Vec::new();
 
@@ -3233,6 +3797,7 @@ impl DeviceManager {
         Ok(devices)
     }
 
+    #[cfg(feature = "vdpa")]
     fn make_vdpa_device(
         &mut self,
         vdpa_cfg: &mut VdpaConfig,
@@ -3284,6 +3849,7 @@ impl DeviceManager {
         })
     }
 
+    #[cfg(feature = "vdpa")]
     fn make_vdpa_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices = Vec::new();
         // Add vdpa if required
@@ -3298,6 +3864,69 @@ impl DeviceManager {
         Ok(devices)
     }
 
+    fn make_virtio_console_port_device(
+        &mut self,
+        console_port_cfg: &mut ConsolePortConfig,
+    ) -> DeviceManagerResult<MetaVirtioDevice> {
+        let id = if let Some(id) = &console_port_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(CONSOLE_PORT_DEVICE_NAME_PREFIX)?;
+            console_port_cfg.id = Some(id.clone());
+            id
+        };
+
+        let endpoint = create_console_port_endpoint(console_port_cfg)
+            .map_err(DeviceManagerError::CreateConsoleEndpoint)?;
+
+        info!("Creating virtio-console port: {:?}", console_port_cfg);
+
+        let (virtio_console_device, _) = virtio_devices::Console::new(
+            id.clone(),
+            endpoint,
+            None,
+            self.force_iommu | console_port_cfg.iommu,
+            self.seccomp_action.clone(),
+            self.exit_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?,
+            state_from_id(self.snapshot.as_ref(), id.as_str())
+                .map_err(DeviceManagerError::RestoreGetState)?,
+        )
+        .map_err(DeviceManagerError::CreateVirtioConsole)?;
+        let virtio_console_device = Arc::new(Mutex::new(virtio_console_device));
+
+        // Fill the device tree with a new node. In case of restore, we
+        // know there is nothing to do, so we can simply override the
+        // existing entry.
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, virtio_console_device));
+
+        Ok(MetaVirtioDevice {
+            virtio_device: virtio_console_device as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+            iommu: console_port_cfg.iommu,
+            id,
+            pci_segment: console_port_cfg.pci_segment,
+            dma_handler: None,
+        })
+    }
+
+    fn make_virtio_console_port_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let mut console_ports = self.config.lock().unwrap().console_ports.clone();
+        if let Some(console_port_list_cfg) = &mut console_ports {
+            for console_port_cfg in console_port_list_cfg.iter_mut() {
+                devices.push(self.make_virtio_console_port_device(console_port_cfg)?);
+            }
+        }
+        self.config.lock().unwrap().console_ports = console_ports;
+
+        Ok(devices)
+    }
+
     fn next_device_name(&mut self, prefix: &str) -> DeviceManagerResult<String> {
         let start_id = self.device_id_cnt;
         loop {
@@ -3354,6 +3983,25 @@ impl DeviceManager {
         ))
     }
 
+    // The quirks are read for each device, for the changes to the file to
+    // apply to the devices hotplugged afterwards.
+    fn vfio_quirks(&self) -> DeviceManagerResult<Option<VfioQuirks>> {
+        let path = self
+            .config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .and_then(|platform| platform.vfio_quirks.clone());
+
+        path.map(|path| {
+            let quirks =
+                std::fs::read_to_string(path).map_err(DeviceManagerError::ReadVfioQuirks)?;
+            VfioQuirks::parse(&quirks).map_err(DeviceManagerError::ParseVfioQuirks)
+        })
+        .transpose()
+    }
+
     fn add_vfio_device(
         &mut self,
         device_cfg: &mut DeviceConfig,
@@ -3408,6 +4056,30 @@ impl DeviceManager {
             vfio_container
         };
 
+        // The quirks are looked up by the vendor and device ids.
+        let quirk = if let Some(quirks) = self.vfio_quirks()? {
+            let (vendor, device) = pci::vfio_device_ids(&device_cfg.path)
+                .map_err(DeviceManagerError::ReadVfioDeviceIds)?;
+            quirks.find(vendor, device).cloned()
+        } else {
+            None
+        };
+        if let Some(quirk) = &quirk {
+            info!(
+                "Applying the quirks of {:04x}:{:04x} to VFIO device {}",
+                quirk.vendor, quirk.device, vfio_name
+            );
+        }
+
+        // The reset method is selected before the device is opened, which
+        // resets it already.
+        let reset_method = device_cfg
+            .reset
+            .or(quirk.as_ref().map(|quirk| quirk.reset))
+            .unwrap_or_default();
+        pci::vfio_select_reset_method(&device_cfg.path, reset_method)
+            .map_err(DeviceManagerError::SelectVfioResetMethod)?;
+
         let vfio_device = VfioDevice::new(&device_cfg.path, Arc::clone(&vfio_container))
             .map_err(DeviceManagerError::VfioCreate)?;
 
@@ -3474,6 +4146,8 @@ impl DeviceManager {
             Arc::new(move || memory_manager.lock().unwrap().allocate_memory_slot()),
             vm_migration::snapshot_from_id(self.snapshot.as_ref(), vfio_name.as_str()),
             device_cfg.x_nv_gpudirect_clique,
+            quirk,
+            reset_method,
         )
         .map_err(DeviceManagerError::VfioPciCreate)?;
 
@@ -3889,6 +4563,127 @@ impl DeviceManager {
         Ok(Some(pvpanic_device))
     }
 
+    #[cfg(feature = "tdx")]
+    fn add_tdx_quote_generator(&mut self) -> DeviceManagerResult<()> {
+        let platform = self.config.lock().unwrap().platform.clone();
+        let Some(platform) = platform.filter(|p| p.tdx && p.quote_generation) else {
+            return Ok(());
+        };
+
+        let socket = platform
+            .qgs_socket
+            .unwrap_or_else(|| PathBuf::from(crate::tdx_quote::DEFAULT_QGS_SOCKET));
+        info!("Forwarding the quote requests of the TD to {:?}", socket);
+        let phys_bits = self.cpu_manager.lock().unwrap().physical_bits();
+        let quote_generator = crate::tdx_quote::TdxQuoteGenerator::new(
+            socket,
+            self.memory_manager.lock().unwrap().guest_memory(),
+            phys_bits,
+        )
+        .map_err(DeviceManagerError::CreateTdxQuoteGenerator)?;
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .set_tdx_quote_generator(Arc::new(quote_generator));
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn add_vmgenid_device(&mut self) -> DeviceManagerResult<Arc<Mutex<devices::VmGenIdDevice>>> {
+        let id = String::from(VMGENID_DEVICE_NAME);
+
+        info!("Creating VM generation ID device {}", id);
+
+        // The generation ID is stored in guest RAM, at an address reserved
+        // by the memory layout and never handed to the guest as usable memory.
+        let vmgenid_device = Arc::new(Mutex::new(
+            devices::VmGenIdDevice::new(
+                id.clone(),
+                arch::layout::VMGENID_START,
+                self.memory_manager.lock().unwrap().guest_memory(),
+                versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                    .map_err(DeviceManagerError::RestoreGetState)?,
+            )
+            .map_err(DeviceManagerError::VmGenIdCreate)?,
+        ));
+
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, vmgenid_device));
+
+        Ok(vmgenid_device)
+    }
+
+    fn add_ghes_device(&mut self) -> DeviceManagerResult<Arc<Mutex<devices::GhesDevice>>> {
+        let id = String::from(GHES_DEVICE_NAME);
+
+        info!("Creating hardware error source device {}", id);
+
+        // Look for the id in the device tree. If it can be found, that means
+        // the device is being restored, and the guest expects it at the same
+        // address since the ACPI tables are not regenerated.
+        let address = if let Some(node) = self.device_tree.lock().unwrap().get(&id) {
+            info!("Restoring hardware error source {} resources", id);
+
+            let mut address = None;
+            for resource in node.resources.iter() {
+                match resource {
+                    Resource::MmioAddressRange { base, .. } => {
+                        if address.is_some() {
+                            return Err(DeviceManagerError::ResourceAlreadyExists);
+                        }
+
+                        address = Some(GuestAddress(*base));
+                    }
+                    _ => {
+                        error!("Unexpected resource {:?} for {}", resource, id);
+                    }
+                }
+            }
+
+            Some(address.ok_or(DeviceManagerError::MissingGhesResources)?)
+        } else {
+            None
+        };
+
+        let address = self
+            .address_manager
+            .allocator
+            .lock()
+            .unwrap()
+            .allocate_platform_mmio_addresses(address, devices::GHES_DEVICE_MMIO_SIZE, None)
+            .ok_or(DeviceManagerError::AllocateMmioAddress)?;
+
+        let ghes_device = Arc::new(Mutex::new(devices::GhesDevice::new(
+            id.clone(),
+            address,
+            versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                .map_err(DeviceManagerError::RestoreGetState)?,
+        )));
+
+        self.address_manager
+            .mmio_bus
+            .insert(
+                ghes_device.clone(),
+                address.0,
+                devices::GHES_DEVICE_MMIO_SIZE,
+            )
+            .map_err(DeviceManagerError::BusError)?;
+        self.bus_devices
+            .push(Arc::clone(&ghes_device) as Arc<dyn BusDeviceSync>);
+
+        let mut node = device_node!(id, ghes_device);
+        node.resources.push(Resource::MmioAddressRange {
+            base: address.0,
+            size: devices::GHES_DEVICE_MMIO_SIZE,
+        });
+        self.device_tree.lock().unwrap().insert(id, node);
+
+        Ok(ghes_device)
+    }
+
     fn pci_resources(
         &self,
         id: &str,
@@ -4066,7 +4861,12 @@ impl DeviceManager {
         })
     }
 
-    pub fn remove_device(&mut self, id: String) -> DeviceManagerResult<()> {
+    pub fn remove_device(
+        &mut self,
+        id: String,
+        timeout: Option<Duration>,
+        surprise_removal: bool,
+    ) -> DeviceManagerResult<()> {
         // The node can be directly a PCI node in case the 'id' refers to a
         // VFIO device or a virtio-pci one.
         // In case the 'id' refers to a virtio device, we must find the PCI
@@ -4074,7 +4874,7 @@ impl DeviceManager {
         let device_tree = self.device_tree.lock().unwrap();
         let node = device_tree
             .get(&id)
-            .ok_or(DeviceManagerError::UnknownDeviceId(id))?;
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.clone()))?;
 
         let pci_device_node = if node.pci_bdf.is_some() && node.pci_device_handle.is_some() {
             node
@@ -4118,13 +4918,134 @@ impl DeviceManager {
             }
         }
 
+        drop(device_tree);
+
         // Update the PCID bitmap
         self.pci_segments[pci_segment_id as usize].pci_devices_down |= 1 << pci_device_bdf.device();
 
+        event!(
+            "vm",
+            "device-unplug-requested",
+            "id",
+            &id,
+            "bdf",
+            pci_device_bdf.to_string()
+        );
+
+        self.unplugs.insert(
+            id.clone(),
+            DeviceUnplugStatus {
+                id,
+                bdf: pci_device_bdf,
+                state: UnplugState::Pending,
+                surprise_removed: false,
+                deadline: timeout.map(|t| Instant::now() + t),
+                surprise_removal,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Returns the status of the device removals requested so far.
+    pub fn unplug_status(&self) -> Vec<DeviceUnplugStatus> {
+        self.unplugs.values().cloned().collect()
+    }
+
+    /// Returns the earliest deadline among the removals still waiting for
+    /// the guest.
+    pub fn next_unplug_deadline(&self) -> Option<Instant> {
+        self.unplugs
+            .values()
+            .filter(|u| matches!(u.state, UnplugState::Pending | UnplugState::GuestAcked))
+            .filter_map(|u| u.deadline)
+            .min()
+    }
+
+    /// Handles the removals the guest did not complete in time. Depending
+    /// on the request, the device is either forcefully ejected or the
+    /// removal is reported as failed.
+    pub fn process_unplug_timeouts(&mut self) -> DeviceManagerResult<()> {
+        let now = Instant::now();
+        let expired: Vec<(String, PciBdf, bool)> = self
+            .unplugs
+            .values()
+            .filter(|u| matches!(u.state, UnplugState::Pending | UnplugState::GuestAcked))
+            .filter(|u| u.deadline.is_some_and(|d| d <= now))
+            .map(|u| (u.id.clone(), u.bdf, u.surprise_removal))
+            .collect();
+
+        for (id, bdf, surprise_removal) in expired {
+            if surprise_removal {
+                warn!("Guest did not eject device {}, removing it forcefully", id);
+                // Prevent the guest from ejecting the slot on its own later
+                // on, as it might have been reused in the meantime.
+                self.pci_segments[bdf.segment() as usize].pci_devices_down &= !(1 << bdf.device());
+                self.eject_device(bdf.segment(), bdf.device())?;
+                if let Some(unplug) = self.unplugs.get_mut(&id) {
+                    unplug.surprise_removed = true;
+                }
+            } else {
+                warn!("Guest did not eject device {} in time", id);
+                if let Some(unplug) = self.unplugs.get_mut(&id) {
+                    unplug.state = UnplugState::Failed;
+                }
+                event!(
+                    "vm",
+                    "device-unplug-failed",
+                    "id",
+                    &id,
+                    "bdf",
+                    bdf.to_string()
+                );
+            }
+        }
+
         Ok(())
     }
 
+    fn set_unplug_state(&mut self, bdf: PciBdf, from: &[UnplugState], state: UnplugState) {
+        for unplug in self.unplugs.values_mut() {
+            if unplug.bdf == bdf && from.contains(&unplug.state) {
+                unplug.state = state;
+            }
+        }
+    }
+
     pub fn eject_device(&mut self, pci_segment_id: u16, device_id: u8) -> DeviceManagerResult<()> {
+        let pci_device_bdf = PciBdf::new(pci_segment_id, 0, device_id, 0);
+        let in_progress = [
+            UnplugState::Pending,
+            UnplugState::GuestAcked,
+            UnplugState::Failed,
+        ];
+
+        // The slot may have already been forcefully ejected, in which case
+        // the PCI device ID must not be given back to the bus twice.
+        if !self
+            .device_tree
+            .lock()
+            .unwrap()
+            .pci_devices()
+            .iter()
+            .any(|node| node.pci_bdf == Some(pci_device_bdf))
+        {
+            return Err(DeviceManagerError::MissingPciDevice);
+        }
+
+        match self.eject_pci_device(pci_segment_id, device_id) {
+            Ok(()) => {
+                self.set_unplug_state(pci_device_bdf, &in_progress, UnplugState::Done);
+                Ok(())
+            }
+            Err(e) => {
+                self.set_unplug_state(pci_device_bdf, &in_progress, UnplugState::Failed);
+                Err(e)
+            }
+        }
+    }
+
+    fn eject_pci_device(&mut self, pci_segment_id: u16, device_id: u8) -> DeviceManagerResult<()> {
         info!(
             "Ejecting device_id = {} on segment_id={}",
             device_id, pci_segment_id
@@ -4315,6 +5236,17 @@ impl DeviceManager {
                 .retain(|handler| !Arc::ptr_eq(&handler.virtio_device, &virtio_device));
         }
 
+        if let Some(tracer) = self.block_tracers.remove(&id) {
+            if let Err(e) = tracer.stop() {
+                warn!("Failed stopping the tracing of {}: {}", id, e);
+            }
+        }
+        self.transient_disks.remove(&id);
+        #[cfg(feature = "vsock")]
+        if matches!(&self.vsock_backend, Some((vsock_id, _)) if *vsock_id == id) {
+            self.vsock_backend = None;
+        }
+
         event!(
             "vm",
             "device-removed",
@@ -4416,6 +5348,64 @@ impl DeviceManager {
         self.hotplug_virtio_pci_device(device)
     }
 
+    /// Switches the backend of the network device `net_cfg.id`, e.g. from a
+    /// TAP interface to a vhost-user socket, without the guest noticing.
+    pub fn swap_net(&mut self, net_cfg: &mut NetConfig) -> DeviceManagerResult<()> {
+        let id = net_cfg.id.clone().unwrap_or_default();
+
+        let (node, virtio_pci_device) = {
+            let device_tree = self.device_tree.lock().unwrap();
+            let node = device_tree
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.clone()))?;
+            let pci_device_handle = node
+                .parent
+                .as_ref()
+                .and_then(|parent| device_tree.get(parent))
+                .and_then(|parent| parent.pci_device_handle.as_ref())
+                .ok_or(DeviceManagerError::MissingPciDevice)?;
+            let PciDeviceHandle::Virtio(virtio_pci_device) = pci_device_handle else {
+                return Err(DeviceManagerError::MissingPciDevice);
+            };
+            (node, virtio_pci_device.clone())
+        };
+
+        // The new backend mustn't pick up the state of the replaced one from
+        // the snapshot the VM may have been restored from.
+        let snapshot = self.snapshot.take();
+        let handle = self.make_virtio_net_device(net_cfg);
+        self.snapshot = snapshot;
+        let handle = handle?;
+
+        let old_device = match virtio_pci_device
+            .lock()
+            .unwrap()
+            .swap_virtio_device(handle.virtio_device.clone())
+        {
+            Ok(old_device) => old_device,
+            Err(e) => {
+                self.device_tree.lock().unwrap().insert(id, node);
+                return Err(DeviceManagerError::SwapNetBackend(e));
+            }
+        };
+
+        // The new device node replaced the previous one, which was attached
+        // to the virtio-pci node.
+        if let Some(new_node) = self.device_tree.lock().unwrap().get_mut(&id) {
+            new_node.parent = node.parent;
+        }
+        if let Some(meta) = self.virtio_devices.iter_mut().find(|h| h.id == id) {
+            *meta = handle;
+        }
+        old_device.lock().unwrap().shutdown();
+
+        event!("vm", "net-backend-swapped", "id", &id);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "vdpa")]
     pub fn add_vdpa(&mut self, vdpa_cfg: &mut VdpaConfig) -> DeviceManagerResult<PciDeviceInfo> {
         self.validate_identifier(&vdpa_cfg.id)?;
 
@@ -4427,6 +5417,26 @@ impl DeviceManager {
         self.hotplug_virtio_pci_device(device)
     }
 
+    #[cfg(not(feature = "vdpa"))]
+    pub fn add_vdpa(&mut self, _vdpa_cfg: &mut VdpaConfig) -> DeviceManagerResult<PciDeviceInfo> {
+        Err(DeviceManagerError::DeviceNotBuiltIn("vdpa"))
+    }
+
+    pub fn add_console(
+        &mut self,
+        console_port_cfg: &mut ConsolePortConfig,
+    ) -> DeviceManagerResult<PciDeviceInfo> {
+        self.validate_identifier(&console_port_cfg.id)?;
+
+        if console_port_cfg.iommu && !self.is_iommu_segment(console_port_cfg.pci_segment) {
+            return Err(DeviceManagerError::InvalidIommuHotplug);
+        }
+
+        let device = self.make_virtio_console_port_device(console_port_cfg)?;
+        self.hotplug_virtio_pci_device(device)
+    }
+
+    #[cfg(feature = "vsock")]
     pub fn add_vsock(&mut self, vsock_cfg: &mut VsockConfig) -> DeviceManagerResult<PciDeviceInfo> {
         self.validate_identifier(&vsock_cfg.id)?;
 
@@ -4438,6 +5448,14 @@ impl DeviceManager {
         self.hotplug_virtio_pci_device(device)
     }
 
+    #[cfg(not(feature = "vsock"))]
+    pub fn add_vsock(
+        &mut self,
+        _vsock_cfg: &mut VsockConfig,
+    ) -> DeviceManagerResult<PciDeviceInfo> {
+        Err(DeviceManagerError::DeviceNotBuiltIn("vsock"))
+    }
+
     pub fn counters(&self) -> HashMap<String, HashMap<&'static str, Wrapping<u64>>> {
         let mut counters = HashMap::new();
 
@@ -4451,6 +5469,106 @@ impl DeviceManager {
         counters
     }
 
+    pub fn trace_block_device(&self, id: &str, path: Option<PathBuf>) -> DeviceManagerResult<()> {
+        let tracer = self
+            .block_tracers
+            .get(id)
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.to_owned()))?;
+
+        if let Some(path) = path {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .map_err(DeviceManagerError::BlockTrace)?;
+            tracer.start(file).map_err(DeviceManagerError::BlockTrace)
+        } else {
+            tracer.stop().map_err(DeviceManagerError::BlockTrace)
+        }
+    }
+
+    pub fn keep_transient_disk(&self, id: &str, path: PathBuf) -> DeviceManagerResult<()> {
+        let (overlay, image) = self
+            .transient_disks
+            .get(id)
+            .ok_or_else(|| DeviceManagerError::NotTransientDisk(id.to_owned()))?;
+
+        // Write the cached metadata out for the image to be consistent once
+        // linked. Later writes land in the same file, which is flushed again
+        // when the disk is closed.
+        image
+            .flush()
+            .map_err(DeviceManagerError::KeepTransientDisk)?;
+
+        let overlay_path = CString::new(format!("/proc/self/fd/{}", overlay.as_raw_fd())).unwrap();
+        let path = CString::new(path.into_os_string().into_vec()).map_err(|_| {
+            DeviceManagerError::KeepTransientDisk(io::Error::from(io::ErrorKind::InvalidInput))
+        })?;
+        // SAFETY: FFI call with valid NUL terminated paths.
+        let ret = unsafe {
+            libc::linkat(
+                libc::AT_FDCWD,
+                overlay_path.as_ptr(),
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                libc::AT_SYMLINK_FOLLOW,
+            )
+        };
+        if ret < 0 {
+            return Err(DeviceManagerError::KeepTransientDisk(
+                io::Error::last_os_error(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "vsock")]
+    fn apply_vsock_forward(
+        backend: &mut virtio_devices::vsock::VsockUnixBackend,
+        forward: &VsockForwardConfig,
+        add: bool,
+    ) -> Result<(), virtio_devices::vsock::VsockUnixError> {
+        match (forward.direction, add) {
+            (VsockForwardDirection::GuestToHost, true) => {
+                backend.add_tcp_forward(forward.port, forward.address)
+            }
+            (VsockForwardDirection::GuestToHost, false) => backend.remove_tcp_forward(forward.port),
+            (VsockForwardDirection::HostToGuest, true) => {
+                backend.add_tcp_listener(forward.address, forward.port)
+            }
+            (VsockForwardDirection::HostToGuest, false) => {
+                backend.remove_tcp_listener(forward.address)
+            }
+        }
+    }
+
+    #[cfg(feature = "vsock")]
+    pub fn update_vsock_forward(
+        &self,
+        forward: &VsockForwardConfig,
+        add: bool,
+    ) -> DeviceManagerResult<()> {
+        let (_, backend) = self
+            .vsock_backend
+            .as_ref()
+            .ok_or(DeviceManagerError::MissingVirtioVsock)?;
+
+        Self::apply_vsock_forward(&mut backend.write().unwrap(), forward, add)
+            .map_err(DeviceManagerError::VsockForward)
+    }
+
+    #[cfg(not(feature = "vsock"))]
+    pub fn update_vsock_forward(
+        &self,
+        _forward: &VsockForwardConfig,
+        _add: bool,
+    ) -> DeviceManagerResult<()> {
+        Err(DeviceManagerError::MissingVirtioVsock)
+    }
+
+    #[cfg(feature = "balloon")]
     pub fn resize_balloon(&mut self, size: u64) -> DeviceManagerResult<()> {
         if let Some(balloon) = &self.balloon {
             return balloon
@@ -4464,6 +5582,13 @@ impl DeviceManager {
         Err(DeviceManagerError::MissingVirtioBalloon)
     }
 
+    #[cfg(not(feature = "balloon"))]
+    pub fn resize_balloon(&mut self, _size: u64) -> DeviceManagerResult<()> {
+        warn!("No balloon setup: Can't resize the balloon");
+        Err(DeviceManagerError::MissingVirtioBalloon)
+    }
+
+    #[cfg(feature = "balloon")]
     pub fn balloon_size(&self) -> u64 {
         if let Some(balloon) = &self.balloon {
             return balloon.lock().unwrap().get_actual();
@@ -4472,6 +5597,11 @@ impl DeviceManager {
         0
     }
 
+    #[cfg(not(feature = "balloon"))]
+    pub fn balloon_size(&self) -> u64 {
+        0
+    }
+
     pub fn device_tree(&self) -> Arc<Mutex<DeviceTree>> {
         self.device_tree.clone()
     }
@@ -4512,6 +5642,94 @@ impl DeviceManager {
             .map_err(DeviceManagerError::PowerButtonNotification);
     }
 
+    /// Let each device complete its in-flight operations before the VM is
+    /// paused, reporting how long each one took.
+    pub fn quiesce_devices(
+        &mut self,
+        deadline: Instant,
+    ) -> result::Result<Vec<DeviceQuiesceReport>, MigratableError> {
+        let mut reports = Vec::new();
+        for (id, device_node) in self.device_tree.lock().unwrap().iter() {
+            if let Some(migratable) = &device_node.migratable {
+                let start = Instant::now();
+                migratable.lock().unwrap().quiesce(deadline)?;
+                let end = Instant::now();
+                reports.push(DeviceQuiesceReport {
+                    id: id.clone(),
+                    duration_us: end.duration_since(start).as_micros() as u64,
+                    timed_out: end > deadline,
+                });
+            }
+        }
+
+        Ok(reports)
+    }
+
+    /// Give the VM a new generation ID and let the guest know about it,
+    /// which is needed when several VMs are restored from the same snapshot.
+    /// Nothing to do when the VM wasn't given a generation ID device.
+    pub fn update_vmgenid(&self) -> DeviceManagerResult<()> {
+        let Some(vmgenid_device) = &self.vmgenid_device else {
+            return Ok(());
+        };
+
+        vmgenid_device
+            .lock()
+            .unwrap()
+            .regenerate()
+            .map_err(DeviceManagerError::VmGenIdUpdate)?;
+
+        self.ged_notification_device
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .notify(AcpiNotificationFlags::VMGENID_CHANGED)
+            .map_err(DeviceManagerError::VmGenIdNotification)
+    }
+
+    /// Report a memory error at `gpa` to the guest, through the hardware
+    /// error source described by the HEST.
+    pub fn inject_memory_error(
+        &self,
+        gpa: GuestAddress,
+        severity: devices::ghes::ErrorSeverity,
+    ) -> DeviceManagerResult<()> {
+        self.ghes_device
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .inject_memory_error(gpa, severity)
+            .map_err(DeviceManagerError::GhesInjection)?;
+
+        self.ged_notification_device
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .notify(AcpiNotificationFlags::HARDWARE_ERROR)
+            .map_err(DeviceManagerError::GhesNotification)
+    }
+
+    pub fn ghes_error_status_address(&self) -> u64 {
+        self.ghes_device
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .error_status_address()
+    }
+
+    pub fn ged_irq(&self) -> u32 {
+        self.ged_notification_device
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .irq()
+    }
+
     pub fn iommu_attached_devices(&self) -> &Option<(PciBdf, Vec<PciBdf>)> {
         &self.iommu_attached_devices
     }
@@ -4746,6 +5964,14 @@ impl Aml for DeviceManager {
             TpmDevice {}.to_aml_bytes(sink);
         }
 
+        if let Some(vmgenid_device) = &self.vmgenid_device {
+            vmgenid_device.lock().unwrap().to_aml_bytes(sink);
+        }
+
+        if let Some(ghes_device) = &self.ghes_device {
+            ghes_device.lock().unwrap().to_aml_bytes(sink);
+        }
+
         self.ged_notification_device
             .as_ref()
             .unwrap()
@@ -4811,12 +6037,21 @@ impl Snapshottable for DeviceManager {
 impl Transportable for DeviceManager {}
 
 impl Migratable for DeviceManager {
+    // The guest memory written by the DMA of the passthrough devices, which
+    // the hypervisor doesn't see, is tracked by the VFIO container they share.
     fn start_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
         for (_, device_node) in self.device_tree.lock().unwrap().iter() {
             if let Some(migratable) = &device_node.migratable {
                 migratable.lock().unwrap().start_dirty_log()?;
             }
         }
+
+        if let Some(vfio_container) = &self.vfio_container {
+            vfio_dirty_log_enable(vfio_container, true).map_err(|e| {
+                MigratableError::MigrateSend(anyhow!("Error starting VFIO dirty log {}", e))
+            })?;
+        }
+
         Ok(())
     }
 
@@ -4826,6 +6061,13 @@ impl Migratable for DeviceManager {
                 migratable.lock().unwrap().stop_dirty_log()?;
             }
         }
+
+        if let Some(vfio_container) = &self.vfio_container {
+            vfio_dirty_log_enable(vfio_container, false).map_err(|e| {
+                MigratableError::MigrateSend(anyhow!("Error stopping VFIO dirty log {}", e))
+            })?;
+        }
+
         Ok(())
     }
 
@@ -4836,6 +6078,31 @@ impl Migratable for DeviceManager {
                 tables.push(migratable.lock().unwrap().dirty_log()?);
             }
         }
+
+        if let Some(vfio_container) = &self.vfio_container {
+            // The guest memory is mapped region by region, including the
+            // virtio-mem ones whose blocks are mapped as they get plugged.
+            let memory_manager = self.memory_manager.lock().unwrap();
+            for (_, zone) in memory_manager.memory_zones().iter() {
+                let virtio_mem_region = zone.virtio_mem_zone().as_ref().map(|z| z.region());
+                for region in zone.regions().iter().chain(virtio_mem_region) {
+                    let gpa = region.start_addr().raw_value();
+                    let bitmap =
+                        vfio_dirty_log(vfio_container, gpa, region.len()).map_err(|e| {
+                            MigratableError::MigrateSend(anyhow!(
+                                "Error getting VFIO dirty log {}",
+                                e
+                            ))
+                        })?;
+                    tables.push(MemoryRangeTable::from_bitmap(
+                        bitmap,
+                        gpa,
+                        VFIO_DIRTY_PAGE_SIZE,
+                    ));
+                }
+            }
+        }
+
         Ok(MemoryRangeTable::new_from_tables(tables))
     }
 
@@ -4887,6 +6154,24 @@ impl BusDevice for DeviceManager {
                         .pci_devices_down
                         .to_le_bytes(),
                 );
+                // The guest is now aware of the devices being removed
+                let pci_devices_down = self.pci_segments[self.selected_segment].pci_devices_down;
+                for unplug in self.unplugs.values_mut() {
+                    if unplug.state == UnplugState::Pending
+                        && unplug.bdf.segment() as usize == self.selected_segment
+                        && pci_devices_down & (1 << unplug.bdf.device()) != 0
+                    {
+                        unplug.state = UnplugState::GuestAcked;
+                        event!(
+                            "vm",
+                            "device-unplug-acked",
+                            "id",
+                            &unplug.id,
+                            "bdf",
+                            unplug.bdf.to_string()
+                        );
+                    }
+                }
                 // Clear the PCID bitmap
                 self.pci_segments[self.selected_segment].pci_devices_down = 0;
             }
@@ -4983,7 +6268,7 @@ mod tests {
 
     #[test]
     fn test_create_mmio_allocators() {
-        let res = create_mmio_allocators(0x100000, 0x400000, 1, vec![1], 4 << 10);
+        let res = create_mmio_allocators(0x100000, 0x400000, 1, vec![1], 4 << 10).unwrap();
         assert_eq!(res.len(), 1);
         assert_eq!(
             res[0].lock().unwrap().base(),
@@ -4994,7 +6279,7 @@ mod tests {
             vm_memory::GuestAddress(0x3fffff)
         );
 
-        let res = create_mmio_allocators(0x100000, 0x400000, 2, vec![1, 1], 4 << 10);
+        let res = create_mmio_allocators(0x100000, 0x400000, 2, vec![1, 1], 4 << 10).unwrap();
         assert_eq!(res.len(), 2);
         assert_eq!(
             res[0].lock().unwrap().base(),
@@ -5013,7 +6298,7 @@ mod tests {
             vm_memory::GuestAddress(0x3fffff)
         );
 
-        let res = create_mmio_allocators(0x100000, 0x400000, 2, vec![2, 1], 4 << 10);
+        let res = create_mmio_allocators(0x100000, 0x400000, 2, vec![2, 1], 4 << 10).unwrap();
         assert_eq!(res.len(), 2);
         assert_eq!(
             res[0].lock().unwrap().base(),
@@ -5032,4 +6317,45 @@ mod tests {
             vm_memory::GuestAddress(0x3fffff)
         );
     }
+
+    #[test]
+    fn test_create_mmio_allocators_small_area() {
+        // A 64-bit window smaller than one 4GiB unit per segment falls back
+        // to a lower alignment instead of empty apertures.
+        let res =
+            create_mmio_allocators(0x1_4000_0000, 0x1_bfff_ffff, 2, vec![1, 1], 4 << 30).unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(
+            res[0].lock().unwrap().base(),
+            vm_memory::GuestAddress(0x1_4000_0000)
+        );
+        assert_eq!(
+            res[0].lock().unwrap().end(),
+            vm_memory::GuestAddress(0x1_7fff_ffff)
+        );
+        assert_eq!(
+            res[1].lock().unwrap().base(),
+            vm_memory::GuestAddress(0x1_8000_0000)
+        );
+        assert_eq!(
+            res[1].lock().unwrap().end(),
+            vm_memory::GuestAddress(0x1_bfff_ffff)
+        );
+
+        assert!(
+            create_mmio_allocators(0x1_0000_0000, 0x1_0000_0fff, 2, vec![1, 1], 4 << 30).is_err()
+        );
+    }
+
+    #[test]
+    fn test_virtio_devices_registry() {
+        assert!(virtio_devices_built_in("block"));
+        assert!(virtio_devices_built_in("console"));
+        assert!(!virtio_devices_built_in("unknown"));
+        assert_eq!(
+            virtio_devices_built_in("balloon"),
+            cfg!(feature = "balloon")
+        );
+        assert_eq!(virtio_devices_built_in("vsock"), cfg!(feature = "vsock"));
+    }
 }
@@ -0,0 +1,250 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Creation of the hugetlbfs files backing the guest memory.
+//!
+//! The hugepage pool is checked before any file gets created, so that running
+//! out of hugepages is reported with the amounts involved rather than as an
+//! mmap(2) failure. The files are created with O_TMPFILE on a hugetlbfs mount
+//! matching the page size, or through memfd_create(2) when there is none, so
+//! they never outlive the VMM, even when it crashes.
+
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::result;
+use thiserror::Error;
+
+const HUGEPAGES_SYSFS_DIR: &str = "/sys/kernel/mm/hugepages";
+const MEMINFO_PATH: &str = "/proc/meminfo";
+const MOUNTS_PATH: &str = "/proc/mounts";
+
+/// Errors associated with hugetlbfs files
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Error reading the default hugepage size
+    #[error("Error reading the default hugepage size: {0}")]
+    DefaultPageSize(#[source] io::Error),
+
+    /// Error reading the state of the hugepage pool
+    #[error("Error reading the pool of {0} KiB hugepages: {1}")]
+    ReadPool(u64, #[source] io::Error),
+
+    /// Not enough hugepages left in the pool
+    #[error("Not enough {page_size_kib} KiB hugepages: {requested} needed, {available} available")]
+    PoolExhausted {
+        page_size_kib: u64,
+        requested: u64,
+        available: u64,
+    },
+
+    /// Not enough space left on the hugetlbfs mount
+    #[error(
+        "Not enough space on hugetlbfs mount {}: {requested} bytes needed, {available} available",
+        path.display()
+    )]
+    MountExhausted {
+        path: PathBuf,
+        requested: u64,
+        available: u64,
+    },
+
+    /// Error creating the hugetlbfs file
+    #[error("Error creating hugetlbfs file: {0}")]
+    CreateFile(#[source] io::Error),
+
+    /// Error setting the size of the hugetlbfs file
+    #[error("Error setting hugetlbfs file size: {0}")]
+    SetLen(#[source] io::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+fn parse_meminfo_hugepagesize(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Hugepagesize:"))
+        .and_then(|size| size.trim().strip_suffix("kB"))
+        .and_then(|size| size.trim().parse::<u64>().ok())
+        .map(|size_kib| size_kib << 10)
+}
+
+fn parse_hugetlbfs_mounts(mounts: &str) -> Vec<PathBuf> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let path = fields.nth(1)?;
+            (fields.next()? == "hugetlbfs").then(|| PathBuf::from(path))
+        })
+        .collect()
+}
+
+/// Size of the hugepages used when no size is specified.
+pub fn default_page_size() -> Result<u64> {
+    let meminfo = fs::read_to_string(MEMINFO_PATH).map_err(Error::DefaultPageSize)?;
+    parse_meminfo_hugepagesize(&meminfo).ok_or_else(|| {
+        Error::DefaultPageSize(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no hugepage size in /proc/meminfo",
+        ))
+    })
+}
+
+fn read_pool_counter(page_size_kib: u64, counter: &str) -> Result<u64> {
+    let path = format!("{HUGEPAGES_SYSFS_DIR}/hugepages-{page_size_kib}kB/{counter}");
+    fs::read_to_string(path)
+        .map_err(|e| Error::ReadPool(page_size_kib, e))?
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| Error::ReadPool(page_size_kib, io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+/// Number of hugepages of `page_size` which are neither in use nor
+/// reserved by another mapping.
+pub fn available_pages(page_size: u64) -> Result<u64> {
+    let page_size_kib = page_size >> 10;
+    let free = read_pool_counter(page_size_kib, "free_hugepages")?;
+    let reserved = read_pool_counter(page_size_kib, "resv_hugepages")?;
+    Ok(free.saturating_sub(reserved))
+}
+
+/// Check the hugepage pool can provide `size` bytes of hugepages of
+/// `page_size`.
+pub fn check_pool(size: u64, page_size: u64) -> Result<()> {
+    let requested = size.div_ceil(page_size);
+    let available = available_pages(page_size)?;
+    if requested > available {
+        return Err(Error::PoolExhausted {
+            page_size_kib: page_size >> 10,
+            requested,
+            available,
+        });
+    }
+
+    Ok(())
+}
+
+// Block size and available bytes of a hugetlbfs mount. The available size
+// is only reported when the mount is given a size limit.
+fn statfs_mount(path: &Path) -> Option<(u64, Option<u64>)> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut buf = std::mem::MaybeUninit::<libc::statfs>::uninit();
+
+    // SAFETY: FFI call with a valid path and buffer
+    let ret = unsafe { libc::statfs(c_path.as_ptr(), buf.as_mut_ptr()) };
+    if ret != 0 {
+        return None;
+    }
+
+    // SAFETY: `buf` is valid at this point
+    let buf = unsafe { buf.assume_init() };
+    // The types of these fields depend on the libc, hence the `as _`.
+    let bsize: u64 = buf.f_bsize as _;
+    let blocks: u64 = buf.f_blocks as _;
+    let bavail: u64 = buf.f_bavail as _;
+    Some((bsize, (blocks != 0).then_some(bavail * bsize)))
+}
+
+// Find a writable hugetlbfs mount serving hugepages of `page_size`.
+fn find_mount(page_size: u64) -> Option<(PathBuf, Option<u64>)> {
+    let mounts = fs::read_to_string(MOUNTS_PATH).ok()?;
+    parse_hugetlbfs_mounts(&mounts)
+        .into_iter()
+        .find_map(|path| {
+            let (bsize, available) = statfs_mount(&path)?;
+            let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+            // SAFETY: FFI call with a valid path
+            let writable = unsafe { libc::access(c_path.as_ptr(), libc::W_OK) } == 0;
+            (bsize == page_size && writable).then_some((path, available))
+        })
+}
+
+fn memfd_create(page_size: u64) -> Result<File> {
+    let name = CString::new("ch_ram").unwrap();
+    // The log2 of the hugepage size goes in bits [26:31] of the flags.
+    let flags = libc::MFD_CLOEXEC | libc::MFD_HUGETLB | (page_size.trailing_zeros() << 26);
+
+    // SAFETY: FFI call with correct arguments
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_create, name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(Error::CreateFile(io::Error::last_os_error()));
+    }
+
+    // SAFETY: fd is valid
+    Ok(unsafe { File::from_raw_fd(fd as i32) })
+}
+
+/// Create an unnamed hugetlbfs file of `size` bytes, made of hugepages of
+/// `page_size` or of the default size. When `reserve` is set, fails if the
+/// hugepage pool or the mount can't provide enough pages for the whole file.
+pub fn create_file(size: u64, page_size: Option<u64>, reserve: bool) -> Result<File> {
+    let page_size = match page_size {
+        Some(page_size) => page_size,
+        None => default_page_size()?,
+    };
+
+    if reserve {
+        check_pool(size, page_size)?;
+    }
+
+    let file = match find_mount(page_size) {
+        Some((path, available)) => {
+            if let Some(available) = available.filter(|available| reserve && *available < size) {
+                return Err(Error::MountExhausted {
+                    path,
+                    requested: size,
+                    available,
+                });
+            }
+
+            info!("Creating guest memory file on hugetlbfs mount {:?}", path);
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .mode(0o600)
+                .custom_flags(libc::O_TMPFILE | libc::O_CLOEXEC)
+                .open(&path)
+                .map_err(Error::CreateFile)?
+        }
+        None => memfd_create(page_size)?,
+    };
+    file.set_len(size).map_err(Error::SetLen)?;
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_meminfo_hugepagesize() {
+        let meminfo = "MemTotal:       32594364 kB\n\
+                       HugePages_Total:       0\n\
+                       Hugepagesize:       2048 kB\n\
+                       Hugetlb:               0 kB\n";
+        assert_eq!(parse_meminfo_hugepagesize(meminfo), Some(2 << 20));
+        assert_eq!(parse_meminfo_hugepagesize("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn test_parse_hugetlbfs_mounts() {
+        let mounts = "proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n\
+                      hugetlbfs /dev/hugepages hugetlbfs rw,relatime,pagesize=2M 0 0\n\
+                      none /mnt/huge1G hugetlbfs rw,relatime,pagesize=1024M 0 0\n";
+        assert_eq!(
+            parse_hugetlbfs_mounts(mounts),
+            vec![
+                PathBuf::from("/dev/hugepages"),
+                PathBuf::from("/mnt/huge1G")
+            ]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! CPUID page of an SEV-SNP guest.
+//!
+//! The guest can't trust the CPUID values the hypervisor returns, so they are
+//! handed over at launch through a page validated by the PSP, which rejects
+//! the values the CPU doesn't support. The leaves of the page are either the
+//! ones listed by the IGVM file (template) or the ones of the VM
+//! (passthrough), their values coming from the vCPUs in both cases, filtered
+//! by the policy of `filter_leaf`.
+
+use hypervisor::arch::x86::{CpuIdEntry, CPUID_FLAG_VALID_INDEX};
+use std::mem::size_of;
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+/// Number of leaves held by the CPUID page.
+pub const SNP_CPUID_COUNT_MAX: usize = 64;
+
+// XCR0 with the x87 state only, and the matching size of the XSAVE area, the
+// legacy region and the header
+const XCR0_X87: u64 = 1;
+const XSAVE_LEGACY_SIZE: u32 = 0x240;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromZeroes, FromBytes, AsBytes)]
+pub struct SnpCpuidFunc {
+    pub eax_in: u32,
+    pub ecx_in: u32,
+    pub xcr0_in: u64,
+    pub xss_in: u64,
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub reserved: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
+pub struct SnpCpuidInfo {
+    pub count: u32,
+    pub _reserved1: u32,
+    pub _reserved2: u64,
+    pub entries: [SnpCpuidFunc; SNP_CPUID_COUNT_MAX],
+}
+
+impl SnpCpuidInfo {
+    pub fn entries(&self) -> &[SnpCpuidFunc] {
+        &self.entries[..self.count as usize]
+    }
+}
+
+/// Adjusts the values of a leaf for the PSP to accept them, returning false
+/// for the leaves left out of the page.
+pub fn filter_leaf(leaf: &mut SnpCpuidFunc) -> bool {
+    match leaf.eax_in {
+        // The hypervisor leaves aren't CPU features, the guest gets them
+        // through the GHCB.
+        0x4000_0000..=0x4fff_ffff => return false,
+        // SVM features, nested virtualization not being available to an SNP
+        // guest
+        0x8000_000a => return false,
+        0x1 => {
+            // Initial APIC ID, specific to each vCPU
+            leaf.ebx &= 0x00ff_ffff;
+            // Hypervisor bit
+            leaf.ecx &= !(1 << 31);
+        }
+        // x2APIC ID, specific to each vCPU
+        0xb | 0x1f => leaf.edx = 0,
+        // Size of the XSAVE area for the XCR0 and XSS of the leaf
+        0xd if leaf.ecx_in <= 1 && leaf.xcr0_in == XCR0_X87 && leaf.xss_in == 0 => {
+            leaf.ebx = XSAVE_LEGACY_SIZE;
+        }
+        // SVM
+        0x8000_0001 => leaf.ecx &= !(1 << 2),
+        // Extended APIC ID, specific to each vCPU
+        0x8000_001e => leaf.eax = 0,
+        _ => {}
+    }
+
+    true
+}
+
+// Builds the page out of the leaves, their values being returned by
+// `get_leaf` for the function, index, XCR0 and XSS of the leaf.
+fn fill<F>(leaves: impl Iterator<Item = SnpCpuidFunc>, mut get_leaf: F) -> SnpCpuidInfo
+where
+    F: FnMut(u32, u32, u64, u64) -> [u32; 4],
+{
+    let mut info = SnpCpuidInfo::new_zeroed();
+    for mut leaf in leaves {
+        [leaf.eax, leaf.ebx, leaf.ecx, leaf.edx] =
+            get_leaf(leaf.eax_in, leaf.ecx_in, leaf.xcr0_in, leaf.xss_in);
+        if !filter_leaf(&mut leaf) {
+            continue;
+        }
+        if info.count as usize == SNP_CPUID_COUNT_MAX {
+            warn!(
+                "The CPUID page is full, leaving out the leaves from 0x{:x}",
+                leaf.eax_in
+            );
+            break;
+        }
+        info.entries[info.count as usize] = leaf;
+        info.count += 1;
+    }
+
+    info
+}
+
+/// CPUID page holding the leaves of the `template` page of the IGVM file.
+pub fn template_page<F>(template: &[u8], get_leaf: F) -> SnpCpuidInfo
+where
+    F: FnMut(u32, u32, u64, u64) -> [u32; 4],
+{
+    // The data of the page is zero extended.
+    let mut info = SnpCpuidInfo::new_zeroed();
+    let len = template.len().min(size_of::<SnpCpuidInfo>());
+    info.as_bytes_mut()[..len].copy_from_slice(&template[..len]);
+
+    if info.count as usize > SNP_CPUID_COUNT_MAX {
+        warn!(
+            "The CPUID page of the IGVM file holds {} leaves, keeping the first {}",
+            info.count, SNP_CPUID_COUNT_MAX
+        );
+    }
+    let count = (info.count as usize).min(SNP_CPUID_COUNT_MAX);
+
+    fill(info.entries[..count].iter().copied(), get_leaf)
+}
+
+/// CPUID page holding the leaves of the VM, as set on its vCPUs.
+pub fn passthrough_page<F>(cpuid: &[CpuIdEntry], get_leaf: F) -> SnpCpuidInfo
+where
+    F: FnMut(u32, u32, u64, u64) -> [u32; 4],
+{
+    let mut leaves: Vec<(u32, u32)> = cpuid
+        .iter()
+        .map(|entry| {
+            let index = if entry.flags & CPUID_FLAG_VALID_INDEX != 0 {
+                entry.index
+            } else {
+                0
+            };
+            (entry.function, index)
+        })
+        .collect();
+    leaves.sort_unstable();
+    leaves.dedup();
+
+    fill(
+        leaves.into_iter().map(|(function, index)| SnpCpuidFunc {
+            eax_in: function,
+            ecx_in: index,
+            // The XSAVE area is described for the legacy state, the guest
+            // computing the size of the others on its own.
+            xcr0_in: if function == 0xd && index <= 1 {
+                XCR0_X87
+            } else {
+                0
+            },
+            ..SnpCpuidFunc::new_zeroed()
+        }),
+        get_leaf,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(eax_in: u32, ecx_in: u32) -> SnpCpuidFunc {
+        SnpCpuidFunc {
+            eax_in,
+            ecx_in,
+            ..SnpCpuidFunc::new_zeroed()
+        }
+    }
+
+    fn host_leaf(function: u32, index: u32, _xcr0: u64, _xss: u64) -> [u32; 4] {
+        [function, index, 0xffff_ffff, 0xffff_ffff]
+    }
+
+    #[test]
+    fn test_snp_cpuid_filter_leaf() {
+        let mut l = SnpCpuidFunc {
+            ebx: 0x0312_0800,
+            ecx: 0xfed8_3203,
+            ..leaf(1, 0)
+        };
+        assert!(filter_leaf(&mut l));
+        assert_eq!(l.ebx, 0x0012_0800);
+        assert_eq!(l.ecx, 0x7ed8_3203);
+
+        let mut l = SnpCpuidFunc {
+            xcr0_in: XCR0_X87,
+            ebx: 0x988,
+            ..leaf(0xd, 0)
+        };
+        assert!(filter_leaf(&mut l));
+        assert_eq!(l.ebx, XSAVE_LEGACY_SIZE);
+
+        assert!(!filter_leaf(&mut leaf(0x4000_0000, 0)));
+        assert!(!filter_leaf(&mut leaf(0x8000_000a, 0)));
+    }
+
+    #[test]
+    fn test_snp_cpuid_template_page() {
+        let mut template = SnpCpuidInfo::new_zeroed();
+        template.count = 3;
+        template.entries[0] = leaf(0, 0);
+        template.entries[1] = leaf(0x4000_0001, 0);
+        template.entries[2] = leaf(7, 1);
+
+        // The template may be shorter than the page.
+        let len = 16 + 3 * size_of::<SnpCpuidFunc>();
+        let page = template_page(&template.as_bytes()[..len], host_leaf);
+        assert_eq!(page.count, 2);
+        assert_eq!(
+            page.entries(),
+            &[
+                SnpCpuidFunc {
+                    ebx: 0,
+                    ecx: 0xffff_ffff,
+                    edx: 0xffff_ffff,
+                    ..leaf(0, 0)
+                },
+                SnpCpuidFunc {
+                    eax: 7,
+                    ebx: 1,
+                    ecx: 0xffff_ffff,
+                    edx: 0xffff_ffff,
+                    ..leaf(7, 1)
+                }
+            ]
+        );
+
+        assert_eq!(template_page(&[], host_leaf).count, 0);
+    }
+
+    #[test]
+    fn test_snp_cpuid_passthrough_page() {
+        let entry = |function, index, flags| CpuIdEntry {
+            function,
+            index,
+            flags,
+            ..Default::default()
+        };
+        let cpuid = vec![
+            entry(7, 0, CPUID_FLAG_VALID_INDEX),
+            entry(0xd, 1, CPUID_FLAG_VALID_INDEX),
+            entry(1, 5, 0),
+            entry(0x4000_0000, 0, 0),
+            entry(7, 0, CPUID_FLAG_VALID_INDEX),
+        ];
+        let page = passthrough_page(&cpuid, host_leaf);
+        let leaves: Vec<(u32, u32, u64)> = page
+            .entries()
+            .iter()
+            .map(|l| (l.eax_in, l.ecx_in, l.xcr0_in))
+            .collect();
+        assert_eq!(leaves, vec![(1, 0, 0), (7, 0, 0), (0xd, 1, XCR0_X87)]);
+
+        // The leaves past the size of the page are left out.
+        let cpuid: Vec<CpuIdEntry> = (0..100).map(|i| entry(0x8000_0000 + i, 0, 0)).collect();
+        assert_eq!(
+            passthrough_page(&cpuid, host_leaf).count as usize,
+            SNP_CPUID_COUNT_MAX
+        );
+    }
+}
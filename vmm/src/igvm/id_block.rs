@@ -0,0 +1,206 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Signing of the SEV-SNP ID block at launch.
+//!
+//! SNP_LAUNCH_FINISH checks the ID block, binding the launch digest and the
+//! policy of the guest to an ID key, itself optionally signed by an author
+//! key. Rather than the pre-signed ID block of the IGVM file, the ID block
+//! can be generated at launch from the expected launch digest and signed
+//! with ECDSA P-384 keys of the operator, for the keys to be rotated without
+//! rebuilding the IGVM file.
+
+use igvm_defs::{
+    IGVM_VHS_SNP_ID_BLOCK, IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY, IGVM_VHS_SNP_ID_BLOCK_SIGNATURE,
+};
+use p384::ecdsa::signature::Signer;
+use p384::ecdsa::{Signature, SigningKey};
+use p384::pkcs8::DecodePrivateKey;
+use p384::SecretKey;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+// Algorithm and curve of the keys, per the AMD SEV-SNP ABI specification.
+const ECDSA_P384_SHA384: u32 = 1;
+const CURVE_P384: u32 = 2;
+
+const ID_BLOCK_SIZE: usize = 0x60;
+const ID_BLOCK_VERSION: u32 = 1;
+// Size of a public key as laid out in ID_AUTH_INFO.
+const PUBLIC_KEY_SIZE: usize = 0x404;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to read the key {0:?}: {1}")]
+    ReadKey(PathBuf, #[source] io::Error),
+    #[error("The key {0:?} is not a PEM encoded ECDSA P-384 private key")]
+    InvalidKey(PathBuf),
+}
+
+// ID block of the launch, the ID key signing it.
+fn id_block(snp_id_block: &IGVM_VHS_SNP_ID_BLOCK, policy: u64) -> [u8; ID_BLOCK_SIZE] {
+    let mut id_block = [0u8; ID_BLOCK_SIZE];
+    id_block[..0x30].copy_from_slice(&snp_id_block.ld);
+    id_block[0x30..0x40].copy_from_slice(&snp_id_block.family_id);
+    id_block[0x40..0x50].copy_from_slice(&snp_id_block.image_id);
+    id_block[0x50..0x54].copy_from_slice(&snp_id_block.version.to_le_bytes());
+    id_block[0x54..0x58].copy_from_slice(&snp_id_block.guest_svn.to_le_bytes());
+    id_block[0x58..].copy_from_slice(&policy.to_le_bytes());
+    id_block
+}
+
+// Public key as signed by the author key, the IGVM structure having a
+// reserved field after the curve.
+fn public_key(key: &IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY) -> [u8; PUBLIC_KEY_SIZE] {
+    let mut public_key = [0u8; PUBLIC_KEY_SIZE];
+    public_key[..4].copy_from_slice(&key.curve.to_le_bytes());
+    public_key[4..76].copy_from_slice(&key.qx);
+    public_key[76..148].copy_from_slice(&key.qy);
+    public_key
+}
+
+// The firmware takes the integers little endian, zero extended to 72 bytes.
+fn little_endian(big_endian: &[u8]) -> [u8; 72] {
+    let mut le = [0u8; 72];
+    for (dst, src) in le.iter_mut().zip(big_endian.iter().rev()) {
+        *dst = *src;
+    }
+    le
+}
+
+fn read_key(path: &Path) -> Result<SigningKey, Error> {
+    let pem = std::fs::read_to_string(path).map_err(|e| Error::ReadKey(path.to_path_buf(), e))?;
+    let key = SecretKey::from_pkcs8_pem(&pem)
+        .ok()
+        .or_else(|| SecretKey::from_sec1_pem(&pem).ok())
+        .ok_or_else(|| Error::InvalidKey(path.to_path_buf()))?;
+    Ok(SigningKey::from(key))
+}
+
+fn sign(key: &SigningKey, data: &[u8]) -> IGVM_VHS_SNP_ID_BLOCK_SIGNATURE {
+    let signature: Signature = key.sign(data);
+    let (r, s) = signature.split_bytes();
+    IGVM_VHS_SNP_ID_BLOCK_SIGNATURE {
+        r_comp: little_endian(&r),
+        s_comp: little_endian(&s),
+    }
+}
+
+fn verifying_key(key: &SigningKey) -> IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY {
+    let point = key.verifying_key().to_encoded_point(false);
+    IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY {
+        curve: CURVE_P384,
+        reserved: 0,
+        // An uncompressed point always has both coordinates.
+        qx: little_endian(point.x().unwrap()),
+        qy: little_endian(point.y().unwrap()),
+    }
+}
+
+/// Keys of the operator signing the ID block at launch.
+pub struct IdBlockSigner {
+    id_key: SigningKey,
+    author_key: Option<SigningKey>,
+    policy: u64,
+}
+
+impl IdBlockSigner {
+    /// Reads the PEM encoded ID and author keys, the ID block being signed
+    /// along with the `policy` the guest is launched with.
+    pub fn new(id_key: &Path, author_key: Option<&Path>, policy: u64) -> Result<Self, Error> {
+        Ok(IdBlockSigner {
+            id_key: read_key(id_key)?,
+            author_key: author_key.map(read_key).transpose()?,
+            policy,
+        })
+    }
+
+    /// Generates the ID block for the launch digest `ld` and signs it. The
+    /// family, image and SVN of the ID block of the IGVM file are kept.
+    pub fn sign(&self, snp_id_block: &mut IGVM_VHS_SNP_ID_BLOCK, ld: &[u8; 48]) {
+        snp_id_block.ld = *ld;
+        snp_id_block.version = ID_BLOCK_VERSION;
+        snp_id_block.id_key_algorithm = ECDSA_P384_SHA384;
+        snp_id_block.id_public_key = verifying_key(&self.id_key);
+        snp_id_block.id_key_signature = sign(&self.id_key, &id_block(snp_id_block, self.policy));
+
+        if let Some(author_key) = &self.author_key {
+            snp_id_block.author_key_enabled = 1;
+            snp_id_block.author_key_algorithm = ECDSA_P384_SHA384;
+            snp_id_block.author_public_key = verifying_key(author_key);
+            snp_id_block.author_key_signature =
+                sign(author_key, &public_key(&snp_id_block.id_public_key));
+        } else {
+            snp_id_block.author_key_enabled = 0;
+            snp_id_block.author_key_algorithm = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p384::ecdsa::signature::Verifier;
+    use p384::ecdsa::VerifyingKey;
+    use p384::{EncodedPoint, FieldBytes};
+    use zerocopy::FromZeroes;
+
+    const COORDINATE_SIZE: usize = 48;
+
+    fn big_endian(le: &[u8; 72]) -> Vec<u8> {
+        assert!(le[COORDINATE_SIZE..].iter().all(|b| *b == 0));
+        le[..COORDINATE_SIZE].iter().rev().copied().collect()
+    }
+
+    fn verify(
+        key: &IGVM_VHS_SNP_ID_BLOCK_PUBLIC_KEY,
+        signature: &IGVM_VHS_SNP_ID_BLOCK_SIGNATURE,
+        data: &[u8],
+    ) {
+        let point = EncodedPoint::from_affine_coordinates(
+            big_endian(&key.qx).as_slice().into(),
+            big_endian(&key.qy).as_slice().into(),
+            false,
+        );
+        let key = VerifyingKey::from_encoded_point(&point).unwrap();
+        let signature = Signature::from_scalars(
+            FieldBytes::clone_from_slice(&big_endian(&signature.r_comp)),
+            FieldBytes::clone_from_slice(&big_endian(&signature.s_comp)),
+        )
+        .unwrap();
+        key.verify(data, &signature).unwrap();
+    }
+
+    #[test]
+    fn test_sign_id_block() {
+        let signer = IdBlockSigner {
+            id_key: SigningKey::from_slice(&[0x11; COORDINATE_SIZE]).unwrap(),
+            author_key: Some(SigningKey::from_slice(&[0x22; COORDINATE_SIZE]).unwrap()),
+            policy: 0x30000,
+        };
+        let mut snp_id_block = IGVM_VHS_SNP_ID_BLOCK::new_zeroed();
+        snp_id_block.guest_svn = 3;
+        signer.sign(&mut snp_id_block, &[0xaa; 48]);
+
+        let id_block = id_block(&snp_id_block, 0x30000);
+        assert_eq!(&id_block[..0x30], &[0xaa; 0x30]);
+        assert_eq!(&id_block[0x50..0x58], &[1, 0, 0, 0, 3, 0, 0, 0]);
+        assert_eq!(&id_block[0x58..], &0x30000u64.to_le_bytes());
+
+        assert_eq!(snp_id_block.id_public_key.curve, CURVE_P384);
+        verify(
+            &snp_id_block.id_public_key,
+            &snp_id_block.id_key_signature,
+            &id_block,
+        );
+        assert_eq!(snp_id_block.author_key_enabled, 1);
+        verify(
+            &snp_id_block.author_public_key,
+            &snp_id_block.author_key_signature,
+            &public_key(&snp_id_block.id_public_key),
+        );
+    }
+}
@@ -3,29 +3,43 @@
 // Copyright © 2023, Microsoft Corporation
 //
 use crate::cpu::CpuManager;
-use vm_memory::GuestAddress;
+use vm_memory::{Address, GuestAddress};
 use zerocopy::AsBytes;
 
+#[cfg(target_arch = "x86_64")]
+use crate::igvm::cpuid;
+#[cfg(feature = "sev_snp")]
+use crate::igvm::id_block::IdBlockSigner;
+#[cfg(feature = "sev_snp")]
+use crate::igvm::measurement;
+use crate::igvm::validate::{self, ValidationError};
+#[cfg(feature = "sev_snp")]
+use crate::igvm::{is_lazy_page, map_in_parallel};
 use crate::igvm::{
-    loader::Loader, BootPageAcceptance, IgvmLoadedInfo, StartupMemoryType, HV_PAGE_SIZE,
+    loader::Loader, BootPageAcceptance, IgvmLoadedInfo, IgvmMapping, StartupMemoryType,
+    HV_PAGE_SIZE, HV_PAGE_SIZE_2MB,
 };
 use crate::memory_manager::MemoryManager;
+#[cfg(target_arch = "x86_64")]
+use hypervisor::arch::x86::{msr_index, MsrEntry, SegmentRegister};
+#[cfg(target_arch = "aarch64")]
+use igvm::registers::AArch64Register;
+#[cfg(target_arch = "x86_64")]
+use igvm::registers::{self, X86Register};
 use igvm::{snp_defs::SevVmsa, IgvmDirectiveHeader, IgvmFile, IgvmPlatformHeader, IsolationType};
 use igvm_defs::{
-    IgvmPageDataType, IgvmPlatformType, IGVM_VHS_PARAMETER, IGVM_VHS_PARAMETER_INSERT,
+    IgvmEnvironmentInfo, IgvmPageDataType, IgvmPlatformType, Vtl, IGVM_VHS_MEMORY_RANGE,
+    IGVM_VHS_MMIO_RANGES, IGVM_VHS_PARAMETER, IGVM_VHS_PARAMETER_INSERT,
 };
 use std::collections::HashMap;
 use std::ffi::CString;
-use std::io::Read;
-use std::io::Seek;
-use std::io::SeekFrom;
 use std::mem::size_of;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
 #[cfg(feature = "sev_snp")]
+use crate::vm_config::SnpCpuidMode;
 use crate::GuestMemoryMmap;
-#[cfg(feature = "sev_snp")]
 use igvm_defs::{MemoryMapEntryType, IGVM_VHS_MEMORY_MAP_ENTRY};
 
 cfg_if::cfg_if! {
@@ -40,6 +54,7 @@ enum IsolatedPageType {
     Vmsa = mshv_bindings::hv_isolated_page_type_HV_ISOLATED_PAGE_TYPE_VMSA,
 }
 const ISOLATED_PAGE_SIZE: u32 = mshv_bindings::hv_isolated_page_size_HV_ISOLATED_PAGE_SIZE_4KB;
+const ISOLATED_PAGE_SIZE_2MB: u32 = mshv_bindings::hv_isolated_page_size_HV_ISOLATED_PAGE_SIZE_2MB;
 const ISOLATED_PAGE_SHIFT: u32 = mshv_bindings::HV_HYP_PAGE_SHIFT;
     } else if #[cfg(all(feature = "kvm", feature = "sev_snp"))] {
         #[derive(Debug)]
@@ -52,7 +67,23 @@ enum IsolatedPageType {
     Cpuid = 6, /* KVM_SEV_SNP_PAGE_TYPE_CPUID */
 }
 const ISOLATED_PAGE_SIZE: u32 = 0x1000; // 4KB
+const ISOLATED_PAGE_SIZE_2MB: u32 = 0x20_0000; // 2MB
 const ISOLATED_PAGE_SHIFT: u32 = 12;
+    } else {
+// Without SEV-SNP no page is imported to an isolated guest, the types only
+// describing the pages loaded.
+#[allow(dead_code)]
+#[derive(Debug)]
+#[repr(u32)]
+enum IsolatedPageType {
+    Normal = 1,
+    Vmsa = 2,
+    Unmeasured = 4,
+    Secrets = 5,
+    Cpuid = 6,
+}
+const ISOLATED_PAGE_SIZE: u32 = 0x1000; // 4KB
+const ISOLATED_PAGE_SIZE_2MB: u32 = 0x20_0000; // 2MB
     }
 }
 
@@ -60,26 +91,66 @@ const ISOLATED_PAGE_SHIFT: u32 = 12;
 pub enum Error {
     #[error("command line is not a valid C string")]
     InvalidCommandLine(#[source] std::ffi::NulError),
-    #[error("failed to read igvm file")]
+    #[error("failed to map igvm file")]
     Igvm(#[source] std::io::Error),
     #[error("invalid igvm file")]
     InvalidIgvmFile(#[source] igvm::Error),
+    #[error("invalid igvm file: {0}")]
+    Validation(#[source] ValidationError),
+    #[error("no platform of the igvm file supports {0:?} isolation")]
+    NoMatchingPlatform(IsolationType),
+    #[error("no platform of the igvm file has the compatibility mask 0x{0:x}")]
+    UnknownPlatform(u32),
+    #[error("platform 0x{0:x} of the igvm file doesn't support {1:?} isolation")]
+    PlatformMismatch(u32, IsolationType),
     #[error("invalid guest memory map")]
     InvalidGuestMemmap(#[source] arch::Error),
     #[error("loader error")]
     Loader(#[source] crate::igvm::loader::Error),
     #[error("parameter too large for parameter area")]
     ParameterTooLarge,
+    #[error("no device tree for the device tree parameter")]
+    MissingDeviceTree,
+    #[error("page data at 0x{0:x} is larger than its page")]
+    PageDataTooLarge(u64),
     #[error("Error importing isolated pages: {0}")]
     ImportIsolatedPages(#[source] hypervisor::HypervisorVmError),
     #[error("Error completing importing isolated pages: {0}")]
     CompleteIsolatedImport(#[source] hypervisor::HypervisorVmError),
     #[error("Error decoding host data: {0}")]
     FailedToDecodeHostData(#[source] hex::FromHexError),
+    #[cfg(feature = "sev_snp")]
+    #[error("Error computing the launch digest of the ID block: {0}")]
+    LaunchDigest(#[source] crate::igvm::measurement::Error),
     #[error("Error applying VMSA to vCPU registers: {0}")]
     SetVmsa(#[source] crate::cpu::Error),
     #[error("Error mapping mem regions")]
     MemoryManager,
+    #[error("VP context for {0:?} is not supported")]
+    UnsupportedVtl(Vtl),
+    #[error("VP context of the SVSM at VMPL{0}, the SVSM must run at VMPL0")]
+    SvsmVmpl(u8),
+    #[error("VP context of the firmware at VMPL0, which is reserved to the SVSM")]
+    FirmwareVmpl0,
+    #[error("VP context for VP index {0} which is not a boot vCPU")]
+    InvalidVpIndex(u16),
+    #[error("Error setting the VP context registers: {0}")]
+    SetVpContext(#[source] crate::cpu::Error),
+    #[cfg(feature = "tdx")]
+    #[error("Page data type {0:?} is not supported with TDX")]
+    UnsupportedTdxPageDataType(IgvmPageDataType),
+    #[cfg(feature = "tdx")]
+    #[error("No memory declared for the TD HOB")]
+    MissingTdHob,
+    #[cfg(feature = "tdx")]
+    #[error("Error populating the TD HOB: {0}")]
+    PopulateTdHob(#[source] arch::x86_64::tdx::TdvfError),
+    #[cfg(feature = "tdx")]
+    #[error("Error initializing the TDX vCPUs: {0}")]
+    InitializeTdx(#[source] crate::cpu::Error),
+    #[cfg(feature = "tdx")]
+    #[error("Error initializing the TDX memory region: {0}")]
+    InitializeTdxMemoryRegion(#[source] hypervisor::HypervisorVmError),
 }
 
 #[allow(dead_code)]
@@ -90,6 +161,68 @@ struct GpaPages {
     pub page_size: u32,
 }
 
+impl GpaPages {
+    // Number of bytes covered by the page.
+    #[cfg_attr(not(feature = "sev_snp"), allow(dead_code))]
+    fn len(&self) -> u64 {
+        if self.page_size == ISOLATED_PAGE_SIZE_2MB {
+            HV_PAGE_SIZE_2MB
+        } else {
+            HV_PAGE_SIZE
+        }
+    }
+}
+
+/// Pages imported per call to the hypervisor.
+#[cfg(feature = "sev_snp")]
+const IMPORT_BATCH_SIZE: usize = 4096;
+
+/// Replace the runs of small pages covering a large page, sharing a type
+/// whose pages can be large, by that large page. The `gpas` are sorted, and
+/// `host_contiguous` tells whether the host mapping of the large page at a
+/// GPA is contiguous, for the page to be imported from a single address.
+#[cfg(feature = "sev_snp")]
+fn coalesce_large_pages(gpas: &[GpaPages], host_contiguous: impl Fn(u64) -> bool) -> Vec<GpaPages> {
+    const PAGES_PER_LARGE_PAGE: usize = (HV_PAGE_SIZE_2MB / HV_PAGE_SIZE) as usize;
+
+    let mut coalesced = Vec::with_capacity(gpas.len());
+    let mut i = 0;
+    while i < gpas.len() {
+        let first = gpas[i];
+        let covers_large_page = first.gpa % HV_PAGE_SIZE_2MB == 0
+            && (first.page_type == IsolatedPageType::Normal as u32
+                || first.page_type == IsolatedPageType::Unmeasured as u32)
+            && gpas.get(i..i + PAGES_PER_LARGE_PAGE).is_some_and(|run| {
+                run.iter().enumerate().all(|(n, page)| {
+                    page.gpa == first.gpa + n as u64 * HV_PAGE_SIZE
+                        && page.page_type == first.page_type
+                        && page.page_size == ISOLATED_PAGE_SIZE
+                })
+            });
+
+        if covers_large_page && host_contiguous(first.gpa) {
+            coalesced.push(GpaPages {
+                page_size: ISOLATED_PAGE_SIZE_2MB,
+                ..first
+            });
+            i += PAGES_PER_LARGE_PAGE;
+        } else {
+            coalesced.push(first);
+            i += 1;
+        }
+    }
+
+    coalesced
+}
+
+/// A page added to the TD private memory at launch.
+#[cfg(feature = "tdx")]
+#[derive(Copy, Clone)]
+struct TdxPage {
+    gpa: u64,
+    measured: bool,
+}
+
 #[derive(Debug)]
 enum ParameterAreaState {
     /// Parameter area has been declared via a ParameterArea header.
@@ -98,7 +231,6 @@ enum ParameterAreaState {
     Inserted,
 }
 
-#[cfg(feature = "sev_snp")]
 fn igvm_memmap_from_ram_range(ram_range: (u64, u64)) -> IGVM_VHS_MEMORY_MAP_ENTRY {
     assert!(ram_range.0 % HV_PAGE_SIZE == 0);
     assert!((ram_range.1 - ram_range.0) % HV_PAGE_SIZE == 0);
@@ -112,20 +244,179 @@ fn igvm_memmap_from_ram_range(ram_range: (u64, u64)) -> IGVM_VHS_MEMORY_MAP_ENTR
     }
 }
 
-#[cfg(feature = "sev_snp")]
+// All the RAM is described as usable memory, but for the `reserved_pages`
+// of the SVSM. For isolated guests, only the pages imported by the IGVM file
+// are accepted at launch. The rest is unaccepted and the guest firmware
+// reports it as such to the OS, which accepts it lazily on first use.
 fn generate_memory_map(
     guest_mem: &GuestMemoryMmap,
+    reserved_pages: &[u64],
 ) -> Result<Vec<IGVM_VHS_MEMORY_MAP_ENTRY>, Error> {
-    let mut memory_map = Vec::new();
-
     // Get usable physical memory ranges
     let ram_ranges = arch::generate_ram_ranges(guest_mem).map_err(Error::InvalidGuestMemmap)?;
 
-    for ram_range in ram_ranges {
-        memory_map.push(igvm_memmap_from_ram_range(ram_range));
+    Ok(carve_reserved_pages(ram_ranges, reserved_pages))
+}
+
+// Splits the RAM ranges around the reserved pages, which are described as
+// reserved by the platform.
+fn carve_reserved_pages(
+    ram_ranges: Vec<(u64, u64)>,
+    reserved_pages: &[u64],
+) -> Vec<IGVM_VHS_MEMORY_MAP_ENTRY> {
+    let mut reserved_pages = reserved_pages.to_vec();
+    reserved_pages.sort_unstable();
+    reserved_pages.dedup();
+
+    let mut memory_map = Vec::new();
+    for (start, end) in ram_ranges {
+        let mut usable_start = start;
+        let mut pages = reserved_pages
+            .iter()
+            .copied()
+            .filter(|gpa| (start..end).contains(gpa))
+            .peekable();
+        while let Some(reserved_start) = pages.next() {
+            let mut reserved_end = reserved_start + HV_PAGE_SIZE;
+            while pages.next_if_eq(&reserved_end).is_some() {
+                reserved_end += HV_PAGE_SIZE;
+            }
+            if usable_start < reserved_start {
+                memory_map.push(igvm_memmap_from_ram_range((usable_start, reserved_start)));
+            }
+            memory_map.push(IGVM_VHS_MEMORY_MAP_ENTRY {
+                entry_type: MemoryMapEntryType::PLATFORM_RESERVED,
+                ..igvm_memmap_from_ram_range((reserved_start, reserved_end))
+            });
+            usable_start = reserved_end;
+        }
+        if usable_start < end {
+            memory_map.push(igvm_memmap_from_ram_range((usable_start, end)));
+        }
     }
 
-    Ok(memory_map)
+    memory_map
+}
+
+// The 32-bit MMIO hole below 4GiB, followed by the 64-bit device area above
+// the RAM.
+fn generate_mmio_ranges(memory_manager: &MemoryManager) -> IGVM_VHS_MMIO_RANGES {
+    let start_of_device_area = memory_manager.start_of_device_area().raw_value();
+    let end_of_device_area = memory_manager.end_of_device_area().raw_value();
+
+    IGVM_VHS_MMIO_RANGES {
+        mmio_ranges: [
+            IGVM_VHS_MEMORY_RANGE {
+                starting_gpa_page_number: arch::layout::MEM_32BIT_DEVICES_START.raw_value()
+                    / HV_PAGE_SIZE,
+                number_of_pages: arch::layout::MEM_32BIT_DEVICES_SIZE / HV_PAGE_SIZE,
+            },
+            IGVM_VHS_MEMORY_RANGE {
+                starting_gpa_page_number: start_of_device_area / HV_PAGE_SIZE,
+                number_of_pages: (end_of_device_area - start_of_device_area + 1) / HV_PAGE_SIZE,
+            },
+        ],
+    }
+}
+
+// Compatibility mask of the platform of the IGVM file to load, the one given
+// by `compatibility_mask` or else the first one supporting the
+// `isolation_type`.
+fn select_platform(
+    igvm_file: &IgvmFile,
+    isolation_type: IsolationType,
+    compatibility_mask: Option<u32>,
+) -> Result<u32, Error> {
+    let platform_type = match isolation_type {
+        IsolationType::Tdx => IgvmPlatformType::TDX,
+        IsolationType::Vbs => IgvmPlatformType::VSM_ISOLATION,
+        _ => IgvmPlatformType::SEV_SNP,
+    };
+    let mut platforms = igvm_file
+        .platforms()
+        .iter()
+        .map(|IgvmPlatformHeader::SupportedPlatform(info)| info);
+
+    let info = match compatibility_mask {
+        Some(mask) => platforms
+            .find(|info| info.compatibility_mask == mask)
+            .ok_or(Error::UnknownPlatform(mask))?,
+        None => platforms
+            .find(|info| info.platform_type == platform_type)
+            .ok_or(Error::NoMatchingPlatform(isolation_type))?,
+    };
+    if info.platform_type != platform_type {
+        return Err(Error::PlatformMismatch(
+            info.compatibility_mask,
+            isolation_type,
+        ));
+    }
+
+    Ok(info.compatibility_mask)
+}
+
+// GPAs of the pages the IGVM file imports or requires, for the platform of
+// the compatibility `mask`.
+fn imported_pages(igvm_file: &IgvmFile, mask: u32) -> Vec<u64> {
+    let mut parameter_areas = HashMap::new();
+    let mut ranges = Vec::new();
+
+    for header in igvm_file
+        .directives()
+        .iter()
+        .filter(|header| header.compatibility_mask().unwrap_or(mask) & mask != 0)
+    {
+        match header {
+            IgvmDirectiveHeader::PageData { gpa, flags, .. } => {
+                let page_size = if flags.is_2mb_page() {
+                    HV_PAGE_SIZE_2MB
+                } else {
+                    HV_PAGE_SIZE
+                };
+                ranges.push((*gpa, page_size));
+            }
+            IgvmDirectiveHeader::ParameterArea {
+                number_of_bytes,
+                parameter_area_index,
+                ..
+            } => {
+                parameter_areas.insert(*parameter_area_index, *number_of_bytes);
+            }
+            IgvmDirectiveHeader::ParameterInsert(IGVM_VHS_PARAMETER_INSERT {
+                gpa,
+                parameter_area_index,
+                ..
+            }) => {
+                let size = parameter_areas
+                    .get(parameter_area_index)
+                    .copied()
+                    .unwrap_or(HV_PAGE_SIZE);
+                ranges.push((*gpa, size));
+            }
+            IgvmDirectiveHeader::RequiredMemory {
+                gpa,
+                number_of_bytes,
+                ..
+            } => ranges.push((*gpa, u64::from(*number_of_bytes))),
+            IgvmDirectiveHeader::SnpVpContext { gpa, .. } => ranges.push((*gpa, HV_PAGE_SIZE)),
+            _ => {}
+        }
+    }
+
+    ranges
+        .into_iter()
+        .flat_map(|(gpa, size)| (gpa..gpa + size).step_by(HV_PAGE_SIZE as usize))
+        .collect()
+}
+
+// Environment the firmware runs in. Only hardware isolation keeps the guest
+// memory private, the host being able to access it otherwise, VBS included.
+// The VTL the firmware starts in is given by its VP context.
+fn environment_info(isolation_type: IsolationType) -> IgvmEnvironmentInfo {
+    IgvmEnvironmentInfo::new().with_memory_is_shared(!matches!(
+        isolation_type,
+        IsolationType::Snp | IsolationType::Tdx
+    ))
 }
 
 // Import a parameter to the given parameter area.
@@ -134,12 +425,19 @@ fn import_parameter(
     info: &IGVM_VHS_PARAMETER,
     parameter: &[u8],
 ) -> Result<(), Error> {
-    let (parameter_area, max_size) = match parameter_areas
-        .get_mut(&info.parameter_area_index)
-        .expect("parameter area should be present")
-    {
-        ParameterAreaState::Allocated { data, max_size } => (data, max_size),
-        ParameterAreaState::Inserted => panic!("igvmfile is not valid"),
+    let index = info.parameter_area_index;
+    let (parameter_area, max_size) = match parameter_areas.get_mut(&index) {
+        Some(ParameterAreaState::Allocated { data, max_size }) => (data, max_size),
+        Some(ParameterAreaState::Inserted) => {
+            return Err(Error::Validation(ValidationError::InsertedParameterArea(
+                index,
+            )))
+        }
+        None => {
+            return Err(Error::Validation(ValidationError::UndeclaredParameterArea(
+                index,
+            )))
+        }
     };
     let offset = info.byte_offset as usize;
     let end_of_parameter = offset + parameter.len();
@@ -157,25 +455,266 @@ fn import_parameter(
     Ok(())
 }
 
+#[cfg(target_arch = "x86_64")]
+fn segment_register(reg: &registers::SegmentRegister) -> SegmentRegister {
+    let attributes = reg.attributes;
+    let present = ((attributes >> 7) & 1) as u8;
+    SegmentRegister {
+        base: reg.base,
+        limit: reg.limit,
+        selector: reg.selector,
+        type_: (attributes & 0xf) as u8,
+        s: ((attributes >> 4) & 1) as u8,
+        dpl: ((attributes >> 5) & 3) as u8,
+        present,
+        avl: ((attributes >> 12) & 1) as u8,
+        l: ((attributes >> 13) & 1) as u8,
+        db: ((attributes >> 14) & 1) as u8,
+        g: ((attributes >> 15) & 1) as u8,
+        unusable: u8::from(present == 0),
+    }
+}
+
+// Apply the VP context to the boot vCPU, on top of its reset state. Returns
+// the instruction pointer it starts from.
+#[cfg(target_arch = "x86_64")]
+fn set_vbs_vp_context(
+    cpu_manager: &Arc<Mutex<CpuManager>>,
+    registers: &[X86Register],
+) -> Result<u64, Error> {
+    let vcpu = cpu_manager.lock().unwrap().vcpus()[0].clone();
+    let vcpu = vcpu.lock().unwrap();
+    let (mut regs, mut sregs) = vcpu.initial_registers().map_err(Error::SetVpContext)?;
+    let mut msrs = Vec::new();
+
+    for register in registers {
+        match register {
+            X86Register::Gdtr(table) => {
+                sregs.gdt.base = table.base;
+                sregs.gdt.limit = table.limit;
+            }
+            X86Register::Idtr(table) => {
+                sregs.idt.base = table.base;
+                sregs.idt.limit = table.limit;
+            }
+            X86Register::Cs(segment) => sregs.cs = segment_register(segment),
+            X86Register::Ds(segment) => sregs.ds = segment_register(segment),
+            X86Register::Es(segment) => sregs.es = segment_register(segment),
+            X86Register::Fs(segment) => sregs.fs = segment_register(segment),
+            X86Register::Gs(segment) => sregs.gs = segment_register(segment),
+            X86Register::Ss(segment) => sregs.ss = segment_register(segment),
+            X86Register::Tr(segment) => sregs.tr = segment_register(segment),
+            X86Register::Cr0(value) => sregs.cr0 = *value,
+            X86Register::Cr3(value) => sregs.cr3 = *value,
+            X86Register::Cr4(value) => sregs.cr4 = *value,
+            X86Register::Efer(value) => sregs.efer = *value,
+            X86Register::Pat(value) => msrs.push(MsrEntry {
+                index: msr_index::MSR_IA32_CR_PAT,
+                data: *value,
+            }),
+            X86Register::Rbp(value) => regs.set_rbp(*value),
+            X86Register::Rip(value) => regs.set_rip(*value),
+            X86Register::Rsi(value) => regs.set_rsi(*value),
+            X86Register::Rsp(value) => regs.set_rsp(*value),
+            X86Register::R8(value) => regs.set_r8(*value),
+            X86Register::R9(value) => regs.set_r9(*value),
+            X86Register::R10(value) => regs.set_r10(*value),
+            X86Register::R11(value) => regs.set_r11(*value),
+            X86Register::R12(value) => regs.set_r12(*value),
+            X86Register::Rflags(value) => regs.set_rflags(*value),
+            register => debug!("Ignoring VP context register {:x?}", register),
+        }
+    }
+
+    vcpu.set_initial_registers(&regs, &sregs, &msrs)
+        .map_err(Error::SetVpContext)?;
+
+    Ok(regs.get_rip())
+}
+
+// Apply the VP context to the boot vCPU, on top of the core registers it is
+// configured with. The system registers are left to their reset values, the
+// firmware starting with the MMU off. Returns the program counter it starts
+// from.
+#[cfg(target_arch = "aarch64")]
+fn set_aarch64_vbs_vp_context(
+    cpu_manager: &Arc<Mutex<CpuManager>>,
+    registers: &[AArch64Register],
+) -> Result<u64, Error> {
+    let vcpu = cpu_manager.lock().unwrap().vcpus()[0].clone();
+    let vcpu = vcpu.lock().unwrap();
+    let mut regs = vcpu.initial_registers().map_err(Error::SetVpContext)?;
+    let mut gprs = regs.get_regs();
+
+    for register in registers {
+        match register {
+            AArch64Register::Pc(value) => regs.set_pc(*value),
+            AArch64Register::X0(value) => gprs[0] = *value,
+            AArch64Register::X1(value) => gprs[1] = *value,
+            AArch64Register::Cpsr(value) => regs.set_pstate(*value),
+            register => debug!("Ignoring VP context register {:x?}", register),
+        }
+    }
+    regs.set_regs(gprs);
+
+    vcpu.set_initial_registers(&regs)
+        .map_err(Error::SetVpContext)?;
+
+    Ok(regs.get_pc())
+}
+
+// Merge the pages into ranges of contiguous pages sharing the same
+// measurement, as (gpa, size, measured) tuples.
+#[cfg(feature = "tdx")]
+fn tdx_page_ranges(mut pages: Vec<TdxPage>) -> Vec<(u64, u64, bool)> {
+    pages.sort_by_key(|page| page.gpa);
+    pages.dedup_by_key(|page| page.gpa);
+
+    let mut ranges: Vec<(u64, u64, bool)> = Vec::new();
+    for page in pages {
+        if let Some((gpa, size, measured)) = ranges.last_mut() {
+            if *gpa + *size == page.gpa && *measured == page.measured {
+                *size += HV_PAGE_SIZE;
+                continue;
+            }
+        }
+        ranges.push((page.gpa, HV_PAGE_SIZE, page.measured));
+    }
+
+    ranges
+}
+
+// The TD HOB describes the pages added at launch as system memory, as TDVF
+// sections are, while the rest of the RAM is left unaccepted for the guest to
+// accept lazily.
+#[cfg(feature = "tdx")]
+fn populate_td_hob(
+    memory_manager: &Arc<Mutex<MemoryManager>>,
+    hob_address: u64,
+    accepted: &[(u64, u64, bool)],
+) -> Result<(), Error> {
+    use arch::x86_64::tdx::TdHob;
+    use vm_memory::GuestAddressSpace;
+
+    let memory_manager = memory_manager.lock().unwrap();
+    let boot_guest_memory = memory_manager.boot_guest_memory();
+    let guest_memory = memory_manager.guest_memory();
+    let mem = guest_memory.memory();
+    let mut hob = TdHob::start(hob_address);
+
+    for (gpa, size, _) in accepted {
+        hob.add_memory_resource(&mem, *gpa, *size, false, true)
+            .map_err(Error::PopulateTdHob)?;
+    }
+
+    let ram_ranges =
+        arch::generate_ram_ranges(&boot_guest_memory).map_err(Error::InvalidGuestMemmap)?;
+    for (start, end) in ram_ranges {
+        let mut current = start;
+        for (gpa, size, _) in accepted {
+            if gpa + size <= current || *gpa >= end {
+                continue;
+            }
+            if *gpa > current {
+                hob.add_memory_resource(&mem, current, gpa - current, true, true)
+                    .map_err(Error::PopulateTdHob)?;
+            }
+            current = gpa + size;
+        }
+        if current < end {
+            hob.add_memory_resource(&mem, current, end - current, true, true)
+                .map_err(Error::PopulateTdHob)?;
+        }
+    }
+
+    hob.add_mmio_resource(
+        &mem,
+        arch::layout::MEM_32BIT_DEVICES_START.raw_value(),
+        arch::layout::APIC_START.raw_value() - arch::layout::MEM_32BIT_DEVICES_START.raw_value(),
+    )
+    .map_err(Error::PopulateTdHob)?;
+    let start_of_device_area = memory_manager.start_of_device_area().raw_value();
+    let end_of_device_area = memory_manager.end_of_device_area().raw_value();
+    hob.add_mmio_resource(
+        &mem,
+        start_of_device_area,
+        end_of_device_area - start_of_device_area,
+    )
+    .map_err(Error::PopulateTdHob)?;
+
+    hob.finish(&mem).map_err(Error::PopulateTdHob)
+}
+
+// Add the imported pages to the TD private memory, extending the measurement
+// with the content of the measured ones.
+#[cfg(feature = "tdx")]
+fn init_tdx_memory(
+    memory_manager: &Arc<Mutex<MemoryManager>>,
+    ranges: &[(u64, u64, bool)],
+) -> Result<(), Error> {
+    use vm_memory::{GuestAddressSpace, GuestMemory};
+
+    let memory_manager = memory_manager.lock().unwrap();
+    let guest_memory = memory_manager.guest_memory();
+    let mem = guest_memory.memory();
+
+    for (gpa, size, measured) in ranges {
+        let host_address = mem
+            .get_host_address(GuestAddress(*gpa))
+            .map_err(|_| Error::MemoryManager)?;
+        memory_manager
+            .vm
+            .tdx_init_memory_region(host_address as u64, *gpa, *size, *measured)
+            .map_err(Error::InitializeTdxMemoryRegion)?;
+    }
+
+    Ok(())
+}
+
 ///
 /// Load the given IGVM file to guest memory.
-/// Right now it only supports SNP based isolation.
+/// Right now it supports SNP and TDX based isolation.
 /// We can boot legacy VM with an igvm file without
-/// any isolation.
+/// any isolation. The `device_tree` fills the device
+/// tree parameter areas of the file, on aarch64.
 ///
+#[allow(clippy::too_many_arguments)]
+// The file is parsed from a mapping rather than from a copy of its content,
+// the page data being written to the guest memory straight from the parsed
+// directives. All the platforms are kept, a file possibly targeting several
+// isolation types.
+fn parse_igvm_file(file: &std::fs::File) -> Result<IgvmFile, Error> {
+    let mapping = IgvmMapping::new(file).map_err(Error::Igvm)?;
+    IgvmFile::new_from_binary(&mapping, None).map_err(Error::InvalidIgvmFile)
+}
+
 pub fn load_igvm(
-    mut file: &std::fs::File,
+    file: &std::fs::File,
     memory_manager: Arc<Mutex<MemoryManager>>,
     cpu_manager: Arc<Mutex<CpuManager>>,
     cmdline: &str,
+    device_tree: Option<&[u8]>,
+    isolation_type: IsolationType,
     #[cfg(feature = "sev_snp")] host_data: &Option<String>,
+    #[cfg(feature = "sev_snp")] snp_cpuid: SnpCpuidMode,
+    #[cfg(feature = "sev_snp")] snp_policy: Option<u64>,
+    #[cfg(feature = "sev_snp")] id_block_signer: Option<&IdBlockSigner>,
+    #[cfg(feature = "sev_snp")] svsm: Option<&std::fs::File>,
+    #[cfg(feature = "sev_snp")] lazy_unmeasured: bool,
+    compatibility_mask: Option<u32>,
 ) -> Result<Box<IgvmLoadedInfo>, Error> {
     let mut loaded_info: Box<IgvmLoadedInfo> = Box::default();
     let command_line = CString::new(cmdline).map_err(Error::InvalidCommandLine)?;
-    let mut file_contents = Vec::new();
     let memory = memory_manager.lock().as_ref().unwrap().guest_memory();
     let mut gpas: Vec<GpaPages> = Vec::new();
     let proc_count = cpu_manager.lock().unwrap().vcpus().len() as u32;
+    #[cfg(feature = "tdx")]
+    let tdx_enabled = matches!(isolation_type, IsolationType::Tdx);
+    #[cfg(feature = "tdx")]
+    let mut tdx_pages: Vec<TdxPage> = Vec::new();
+    // Pages of the payload left unaccepted, as GPA and size.
+    #[cfg(feature = "sev_snp")]
+    let mut unaccepted_pages: Vec<(u64, u64)> = Vec::new();
 
     #[cfg(feature = "sev_snp")]
     let mut host_data_contents = [0u8; 32];
@@ -185,382 +724,508 @@ pub fn load_igvm(
             .map_err(Error::FailedToDecodeHostData)?;
     }
 
-    file.seek(SeekFrom::Start(0)).map_err(Error::Igvm)?;
-    file.read_to_end(&mut file_contents).map_err(Error::Igvm)?;
+    let igvm_file = parse_igvm_file(file)?;
+    let mask = select_platform(&igvm_file, isolation_type, compatibility_mask)?;
+    info!("Loading the igvm platform 0x{:x}", mask);
 
-    let igvm_file = IgvmFile::new_from_binary(&file_contents, Some(IsolationType::Snp))
-        .map_err(Error::InvalidIgvmFile)?;
+    // The SVSM runs at VMPL0 and starts the firmware at a lower VMPL. It is
+    // loaded first, in the same launch as the firmware for both to be
+    // measured, and its memory is reserved in the memory map of the firmware.
+    let mut files = Vec::new();
+    #[cfg(feature = "sev_snp")]
+    if let Some(svsm) = svsm {
+        let svsm_file = parse_igvm_file(svsm)?;
+        let svsm_mask = select_platform(&svsm_file, isolation_type, None)?;
+        info!("Loading the igvm platform 0x{:x} of the SVSM", svsm_mask);
+        files.push((svsm_file, svsm_mask, true));
+    }
+    let svsm_loaded = !files.is_empty();
+    files.push((igvm_file, mask, false));
 
-    let mask = match &igvm_file.platforms()[0] {
-        IgvmPlatformHeader::SupportedPlatform(info) => {
-            debug_assert!(info.platform_type == IgvmPlatformType::SEV_SNP);
-            info.compatibility_mask
+    // The whole platform is checked before anything is loaded, the RAM being
+    // checked by the loader as the memory is required.
+    for (igvm_file, mask, _) in files.iter() {
+        if let Some(error) = validate::validate_platform(igvm_file, *mask, None)
+            .into_iter()
+            .next()
+        {
+            return Err(Error::Validation(error));
         }
-    };
+    }
 
     let mut loader = Loader::new(memory);
 
-    // FIXME: use IGVM to provide address information?
-    // This should be part of the boot ram and reported in the E820 table.
-    #[cfg(all(feature = "kvm", feature = "sev_snp"))]
-    {
-        let mut memory_manager = memory_manager.lock().unwrap();
-        // Region for loading Stage 0;
-        memory_manager
-            .add_ram_region(GuestAddress(0xffe0_0000), 0x20_0000)
-            .map_err(|_| Error::MemoryManager)?;
-        // Region for loading the VMSA page
+    // The firmware is commonly loaded right below 4GiB, where the reset
+    // vector is, and the SNP VMSA pages at the top of the address space, out
+    // of the guest RAM. The RAM backing them is added as the file lays them
+    // out.
+    let mut svsm_pages = Vec::new();
+    for (igvm_file, mask, is_svsm) in files.iter() {
+        let pages = imported_pages(igvm_file, *mask);
+        if *is_svsm {
+            svsm_pages.clone_from(&pages);
+        }
         memory_manager
-            .add_ram_region(GuestAddress(0xffff_ffff_f000), 0x1000)
+            .lock()
+            .unwrap()
+            .add_ram_regions_for_pages(pages)
             .map_err(|_| Error::MemoryManager)?;
     }
 
-    let mut parameter_areas: HashMap<u32, ParameterAreaState> = HashMap::new();
+    for (igvm_file, mask, is_svsm) in files.iter() {
+        let mask = *mask;
+        // Parameter areas are numbered per file.
+        let mut parameter_areas: HashMap<u32, ParameterAreaState> = HashMap::new();
 
-    for header in igvm_file.directives() {
-        debug_assert!(header.compatibility_mask().unwrap_or(mask) & mask == mask);
-        match header {
-            IgvmDirectiveHeader::PageData {
-                gpa,
-                compatibility_mask: _,
-                flags,
-                data_type,
-                data,
-            } => {
-                debug_assert!(data.len() as u64 % HV_PAGE_SIZE == 0);
+        for header in igvm_file
+            .directives()
+            .iter()
+            .filter(|header| header.compatibility_mask().unwrap_or(mask) & mask != 0)
+        {
+            match header {
+                IgvmDirectiveHeader::PageData {
+                    gpa,
+                    compatibility_mask: _,
+                    flags,
+                    data_type,
+                    data,
+                } => {
+                    debug_assert!(data.len() as u64 % HV_PAGE_SIZE == 0);
+
+                    // Large pages are imported at once rather than as 512 small
+                    // pages, for the loader and the hypervisor alike.
+                    let (page_size, isolated_page_size) = if flags.is_2mb_page() {
+                        (HV_PAGE_SIZE_2MB, ISOLATED_PAGE_SIZE_2MB)
+                    } else {
+                        (HV_PAGE_SIZE, ISOLATED_PAGE_SIZE)
+                    };
+                    if data.len() as u64 > page_size {
+                        return Err(Error::PageDataTooLarge(*gpa));
+                    }
+
+                    // The unmeasured pages with no content are neither
+                    // imported nor measured, the guest accepting them on
+                    // first use. Those of the SVSM are always imported.
+                    #[cfg(feature = "sev_snp")]
+                    if lazy_unmeasured && !*is_svsm && is_lazy_page(flags, *data_type, data) {
+                        unaccepted_pages.push((*gpa, page_size));
+                        continue;
+                    }
 
-                // TODO: only 4k or empty page data supported right now
-                assert!(data.len() as u64 == HV_PAGE_SIZE || data.is_empty());
+                    // Shared pages are left out of the TD private memory, all the
+                    // others are added, measured unless flagged otherwise.
+                    #[cfg(feature = "tdx")]
+                    if tdx_enabled {
+                        if *data_type != IgvmPageDataType::NORMAL {
+                            return Err(Error::UnsupportedTdxPageDataType(*data_type));
+                        }
+                        if !flags.shared() {
+                            tdx_pages.extend((0..page_size / HV_PAGE_SIZE).map(|i| TdxPage {
+                                gpa: gpa + i * HV_PAGE_SIZE,
+                                measured: !flags.unmeasured(),
+                            }));
+                        }
+                    }
 
-                let acceptance = match *data_type {
-                    IgvmPageDataType::NORMAL => {
-                        if flags.unmeasured() {
+                    let acceptance = match *data_type {
+                        IgvmPageDataType::NORMAL => {
+                            if flags.unmeasured() {
+                                gpas.push(GpaPages {
+                                    gpa: *gpa,
+                                    page_type: IsolatedPageType::Unmeasured as u32,
+                                    page_size: isolated_page_size,
+                                });
+                                BootPageAcceptance::ExclusiveUnmeasured
+                            } else {
+                                gpas.push(GpaPages {
+                                    gpa: *gpa,
+                                    page_type: IsolatedPageType::Normal as u32,
+                                    page_size: isolated_page_size,
+                                });
+                                BootPageAcceptance::Exclusive
+                            }
+                        }
+                        IgvmPageDataType::SECRETS => {
+                            info!("PageData - SECRETS - GPA: 0x{:x}", *gpa);
                             gpas.push(GpaPages {
                                 gpa: *gpa,
-                                page_type: IsolatedPageType::Unmeasured as u32,
-                                page_size: ISOLATED_PAGE_SIZE,
+                                page_type: IsolatedPageType::Secrets as u32,
+                                page_size: isolated_page_size,
                             });
-                            BootPageAcceptance::ExclusiveUnmeasured
-                        } else {
+                            BootPageAcceptance::SecretsPage
+                        }
+                        #[cfg(target_arch = "x86_64")]
+                        IgvmPageDataType::CPUID_DATA => {
+                            info!("PageData - CPUID - GPA: 0x{:x}", *gpa);
                             gpas.push(GpaPages {
                                 gpa: *gpa,
-                                page_type: IsolatedPageType::Normal as u32,
-                                page_size: ISOLATED_PAGE_SIZE,
+                                page_type: IsolatedPageType::Cpuid as u32,
+                                page_size: isolated_page_size,
                             });
-                            BootPageAcceptance::Exclusive
+                            BootPageAcceptance::CpuidPage
                         }
-                    }
-                    IgvmPageDataType::SECRETS => {
-                        info!("PageData - SECRETS - GPA: 0x{:x}", *gpa);
-                        gpas.push(GpaPages {
-                            gpa: *gpa,
-                            page_type: IsolatedPageType::Secrets as u32,
-                            page_size: ISOLATED_PAGE_SIZE,
-                        });
-                        BootPageAcceptance::SecretsPage
-                    }
-                    IgvmPageDataType::CPUID_DATA => {
-                        info!("PageData - CPUID - GPA: 0x{:x}", *gpa);
-                        // SAFETY: CPUID is readonly
-
-                        /*unsafe {
-                            let cpuid_page_p: *mut hv_psp_cpuid_page =
-                                data.as_ptr() as *mut hv_psp_cpuid_page; // as *mut hv_psp_cpuid_page;
-                            let cpuid_page: &mut hv_psp_cpuid_page = &mut *cpuid_page_p;
-                            for i in 0..cpuid_page.count {
-                                let leaf = cpuid_page.cpuid_leaf_info[i as usize];
-                                let mut in_leaf = cpu_manager
-                                    .lock()
-                                    .unwrap()
-                                    .get_cpuid_leaf(
-                                        0,
-                                        leaf.eax_in,
-                                        leaf.ecx_in,
-                                        leaf.xfem_in,
-                                        leaf.xss_in,
-                                    )
-                                    .unwrap();
-                                if leaf.eax_in == 1 {
-                                    in_leaf[2] &= 0x7FFFFFFF;
-                                }
-                                cpuid_page.cpuid_leaf_info[i as usize].eax_out = in_leaf[0];
-                                cpuid_page.cpuid_leaf_info[i as usize].ebx_out = in_leaf[1];
-                                cpuid_page.cpuid_leaf_info[i as usize].ecx_out = in_leaf[2];
-                                cpuid_page.cpuid_leaf_info[i as usize].edx_out = in_leaf[3];
-                            }
-                        }*/
-                        gpas.push(GpaPages {
-                            gpa: *gpa,
-                            page_type: IsolatedPageType::Cpuid as u32,
-                            page_size: ISOLATED_PAGE_SIZE,
-                        });
-                        BootPageAcceptance::CpuidPage
-                    }
-                    // TODO: other data types SNP / TDX only, unsupported
-                    _ => todo!("unsupported IgvmPageDataType"),
-                };
+                        data_type => {
+                            return Err(Error::Validation(
+                                ValidationError::UnsupportedPageDataType(data_type, *gpa),
+                            ))
+                        }
+                    };
 
-                if *data_type == IgvmPageDataType::CPUID_DATA {
-                    use zerocopy::{AsBytes, FromBytes, FromZeroes};
-                    #[repr(C)]
-                    #[derive(Debug, Clone, PartialEq, Eq, FromZeroes, FromBytes, AsBytes)]
-                    pub struct SnpCpuidFunc {
-                        pub eax_in: u32,
-                        pub ecx_in: u32,
-                        pub xcr0_in: u64,
-                        pub xss_in: u64,
-                        pub eax: u32,
-                        pub ebx: u32,
-                        pub ecx: u32,
-                        pub edx: u32,
-                        pub reserved: u64,
-                    }
+                    #[cfg(target_arch = "x86_64")]
+                    if *data_type == IgvmPageDataType::CPUID_DATA {
+                        // The values of the leaves come from the vCPUs, the page
+                        // of the file only listing them.
+                        let cpu_manager = cpu_manager.lock().unwrap();
+                        let get_leaf = |function, index, xcr0, xss| {
+                            cpu_manager.get_cpuid_leaf(0, function, index, xcr0, xss)
+                        };
+                        #[cfg(feature = "sev_snp")]
+                        let cpuid_page = match snp_cpuid {
+                            SnpCpuidMode::Template => cpuid::template_page(data, get_leaf),
+                            SnpCpuidMode::Passthrough => {
+                                cpuid::passthrough_page(&cpu_manager.common_cpuid(), get_leaf)
+                            }
+                        };
+                        #[cfg(not(feature = "sev_snp"))]
+                        let cpuid_page = cpuid::template_page(data, get_leaf);
+                        debug!(
+                            "CPUID page with {} leaves: {:x?}",
+                            cpuid_page.count,
+                            cpuid_page.entries()
+                        );
 
-                    #[repr(C)]
-                    #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
-                    pub struct SnpCpuidInfo {
-                        pub count: u32,
-                        pub _reserved1: u32,
-                        pub _reserved2: u64,
-                        pub entries: [SnpCpuidFunc; 64],
+                        loader
+                            .import_pages(gpa / HV_PAGE_SIZE, 1, acceptance, cpuid_page.as_bytes())
+                            .map_err(Error::Loader)?;
+                        continue;
                     }
-                    let mut snp_cpu_id_info = SnpCpuidInfo::new_zeroed();
-                    snp_cpu_id_info.count = 1;
 
-                    // Write SnpCpuidInfo to the CPUID page
                     loader
                         .import_pages(
                             gpa / HV_PAGE_SIZE,
-                            1,
+                            page_size / HV_PAGE_SIZE,
                             acceptance,
-                            snp_cpu_id_info.as_bytes(),
+                            data,
                         )
                         .map_err(Error::Loader)?;
-                } else {
-                    loader
-                        .import_pages(gpa / HV_PAGE_SIZE, 1, acceptance, data)
-                        .map_err(Error::Loader)?;
                 }
-            }
-            IgvmDirectiveHeader::ParameterArea {
-                number_of_bytes,
-                parameter_area_index,
-                initial_data,
-            } => {
-                debug_assert!(number_of_bytes % HV_PAGE_SIZE == 0);
-                debug_assert!(
-                    initial_data.is_empty() || initial_data.len() as u64 == *number_of_bytes
-                );
-
-                // Allocate a new parameter area. It must not be already used.
-                if parameter_areas
-                    .insert(
-                        *parameter_area_index,
-                        ParameterAreaState::Allocated {
-                            data: initial_data.clone(),
-                            max_size: *number_of_bytes,
-                        },
-                    )
-                    .is_some()
-                {
-                    panic!("IgvmFile is not valid, invalid invariant");
+                IgvmDirectiveHeader::ParameterArea {
+                    number_of_bytes,
+                    parameter_area_index,
+                    initial_data,
+                } => {
+                    debug_assert!(
+                        initial_data.is_empty() || initial_data.len() as u64 == *number_of_bytes
+                    );
+
+                    // Allocate a new parameter area. It must not be already used.
+                    if parameter_areas
+                        .insert(
+                            *parameter_area_index,
+                            ParameterAreaState::Allocated {
+                                data: initial_data.clone(),
+                                max_size: *number_of_bytes,
+                            },
+                        )
+                        .is_some()
+                    {
+                        return Err(Error::Validation(ValidationError::DuplicateParameterArea(
+                            *parameter_area_index,
+                        )));
+                    }
                 }
-            }
-            IgvmDirectiveHeader::VpCount(info) => {
-                import_parameter(&mut parameter_areas, info, proc_count.as_bytes())?;
-            }
-            IgvmDirectiveHeader::MmioRanges(_info) => {
-                todo!("unsupported IgvmPageDataType");
-            }
-            IgvmDirectiveHeader::MemoryMap(_info) => {
-                #[cfg(feature = "sev_snp")]
-                {
+                IgvmDirectiveHeader::VpCount(info) => {
+                    import_parameter(&mut parameter_areas, info, proc_count.as_bytes())?;
+                }
+                IgvmDirectiveHeader::MmioRanges(info) => {
+                    let mmio_ranges = generate_mmio_ranges(&memory_manager.lock().unwrap());
+                    import_parameter(&mut parameter_areas, info, mmio_ranges.as_bytes())?;
+                }
+                IgvmDirectiveHeader::MemoryMap(info) => {
                     let guest_mem = memory_manager.lock().unwrap().boot_guest_memory();
-                    let memory_map = generate_memory_map(&guest_mem)?;
-                    import_parameter(&mut parameter_areas, _info, memory_map.as_bytes())?;
+                    let reserved_pages: &[u64] = if *is_svsm { &[] } else { &svsm_pages };
+                    let memory_map = generate_memory_map(&guest_mem, reserved_pages)?;
+                    import_parameter(&mut parameter_areas, info, memory_map.as_bytes())?;
                 }
-
-                #[cfg(not(feature = "sev_snp"))]
-                todo!("Not implemented");
-            }
-            IgvmDirectiveHeader::CommandLine(info) => {
-                import_parameter(&mut parameter_areas, info, command_line.as_bytes_with_nul())?;
-            }
-            IgvmDirectiveHeader::RequiredMemory {
-                gpa,
-                compatibility_mask: _,
-                number_of_bytes,
-                vtl2_protectable: _,
-            } => {
-                let memory_type = StartupMemoryType::Ram;
-                loaded_info.gpas.push(*gpa);
-                loader
-                    .verify_startup_memory_available(
-                        gpa / HV_PAGE_SIZE,
-                        *number_of_bytes as u64 / HV_PAGE_SIZE,
-                        memory_type,
-                    )
-                    .map_err(Error::Loader)?;
-            }
-            IgvmDirectiveHeader::SnpVpContext {
-                gpa,
-                compatibility_mask: _,
-                vp_index,
-                vmsa,
-            } => {
-                info!("Load SnpVpContext: gpa: 0x{:x}", gpa);
-                assert_eq!(gpa % HV_PAGE_SIZE, 0);
-                let mut data: [u8; 4096] = [0; 4096];
-                let len = size_of::<SevVmsa>();
-                loaded_info.vmsa_gpa = *gpa;
-                loaded_info.vmsa = **vmsa;
-                // Only supported for index zero
-                if *vp_index == 0 {
+                IgvmDirectiveHeader::CommandLine(info) => {
+                    import_parameter(&mut parameter_areas, info, command_line.as_bytes_with_nul())?;
+                }
+                IgvmDirectiveHeader::DeviceTree(info) => {
+                    let device_tree = device_tree.ok_or(Error::MissingDeviceTree)?;
+                    import_parameter(&mut parameter_areas, info, device_tree)?;
+                }
+                IgvmDirectiveHeader::EnvironmentInfo(info) => {
+                    let environment_info = environment_info(isolation_type);
+                    import_parameter(&mut parameter_areas, info, environment_info.as_bytes())?;
+                }
+                IgvmDirectiveHeader::RequiredMemory {
+                    gpa,
+                    compatibility_mask: _,
+                    number_of_bytes,
+                    vtl2_protectable: _,
+                } => {
+                    let memory_type = StartupMemoryType::Ram;
+                    // IGVM has no header dedicated to the TD HOB, it is placed in
+                    // the first memory range required by the file, where TDVF
+                    // images converted to IGVM declare it.
+                    #[cfg(feature = "tdx")]
+                    if tdx_enabled && loaded_info.gpas.is_empty() {
+                        tdx_pages.extend((0..*number_of_bytes as u64 / HV_PAGE_SIZE).map(|i| {
+                            TdxPage {
+                                gpa: gpa + i * HV_PAGE_SIZE,
+                                measured: false,
+                            }
+                        }));
+                    }
+                    loaded_info.gpas.push(*gpa);
+                    loader
+                        .verify_startup_memory_available(
+                            gpa / HV_PAGE_SIZE,
+                            *number_of_bytes as u64 / HV_PAGE_SIZE,
+                            memory_type,
+                        )
+                        .map_err(Error::Loader)?;
+                }
+                IgvmDirectiveHeader::SnpVpContext {
+                    gpa,
+                    compatibility_mask: _,
+                    vp_index,
+                    vmsa,
+                } => {
+                    info!(
+                        "Load SnpVpContext: vp_index: {}, gpa: 0x{:x}",
+                        vp_index, gpa
+                    );
+                    assert_eq!(gpa % HV_PAGE_SIZE, 0);
+                    if u32::from(*vp_index) >= cpu_manager.lock().unwrap().boot_vcpus() {
+                        return Err(Error::InvalidVpIndex(*vp_index));
+                    }
+                    // Along with an SVSM, the vCPUs start in the SVSM at
+                    // VMPL0, the VMSAs of the firmware being measured for
+                    // the SVSM to run the firmware from them.
+                    if svsm_loaded {
+                        if *is_svsm && vmsa.vmpl != 0 {
+                            return Err(Error::SvsmVmpl(vmsa.vmpl));
+                        }
+                        if !*is_svsm && vmsa.vmpl == 0 {
+                            return Err(Error::FirmwareVmpl0);
+                        }
+                    }
+                    let mut data: [u8; 4096] = [0; 4096];
+                    let len = size_of::<SevVmsa>();
+                    // The boot vCPU VMSA provides the entry point
+                    if *is_svsm || !svsm_loaded {
+                        if *vp_index == 0 {
+                            loaded_info.vmsa_gpa = *gpa;
+                            loaded_info.vmsa = **vmsa;
+                        }
+                        loaded_info.vmsa_gpas.insert(u32::from(*vp_index), *gpa);
+                    }
                     data[..len].copy_from_slice(vmsa.as_bytes());
                     loader
                         .import_pages(gpa / HV_PAGE_SIZE, 1, BootPageAcceptance::VpContext, &data)
                         .map_err(Error::Loader)?;
-                }
 
-                gpas.push(GpaPages {
-                    gpa: *gpa,
-                    page_type: IsolatedPageType::Vmsa as u32,
-                    page_size: ISOLATED_PAGE_SIZE,
-                });
-            }
-            IgvmDirectiveHeader::SnpIdBlock {
-                compatibility_mask,
-                author_key_enabled,
-                reserved,
-                ld,
-                family_id,
-                image_id,
-                version,
-                guest_svn,
-                id_key_algorithm,
-                author_key_algorithm,
-                id_key_signature,
-                id_public_key,
-                author_key_signature,
-                author_public_key,
-            } => {
-                loaded_info.snp_id_block.compatibility_mask = *compatibility_mask;
-                loaded_info.snp_id_block.author_key_enabled = *author_key_enabled;
-                loaded_info.snp_id_block.reserved = *reserved;
-                loaded_info.snp_id_block.ld = *ld;
-                loaded_info.snp_id_block.family_id = *family_id;
-                loaded_info.snp_id_block.image_id = *image_id;
-                loaded_info.snp_id_block.version = *version;
-                loaded_info.snp_id_block.guest_svn = *guest_svn;
-                loaded_info.snp_id_block.id_key_algorithm = *id_key_algorithm;
-                loaded_info.snp_id_block.author_key_algorithm = *author_key_algorithm;
-                loaded_info.snp_id_block.id_key_signature = **id_key_signature;
-                loaded_info.snp_id_block.id_public_key = **id_public_key;
-                loaded_info.snp_id_block.author_key_signature = **author_key_signature;
-                loaded_info.snp_id_block.author_public_key = **author_public_key;
-            }
-            IgvmDirectiveHeader::X64VbsVpContext {
-                vtl: _,
-                registers: _,
-                compatibility_mask: _,
-            } => {
-                todo!("VbsVpContext not supported");
-            }
-            IgvmDirectiveHeader::VbsMeasurement { .. } => {
-                todo!("VbsMeasurement not supported")
-            }
-            IgvmDirectiveHeader::ParameterInsert(IGVM_VHS_PARAMETER_INSERT {
-                gpa,
-                compatibility_mask: _,
-                parameter_area_index,
-            }) => {
-                debug_assert!(gpa % HV_PAGE_SIZE == 0);
-
-                let area = parameter_areas
-                    .get_mut(parameter_area_index)
-                    .expect("igvmfile should be valid");
-                match area {
-                    ParameterAreaState::Allocated { data, max_size } => loader
-                        .import_pages(
-                            gpa / HV_PAGE_SIZE,
-                            *max_size / HV_PAGE_SIZE,
-                            BootPageAcceptance::ExclusiveUnmeasured,
-                            data,
-                        )
-                        .map_err(Error::Loader)?,
-                    ParameterAreaState::Inserted => panic!("igvmfile is invalid, multiple insert"),
+                    gpas.push(GpaPages {
+                        gpa: *gpa,
+                        page_type: IsolatedPageType::Vmsa as u32,
+                        page_size: ISOLATED_PAGE_SIZE,
+                    });
+                }
+                IgvmDirectiveHeader::SnpIdBlock {
+                    compatibility_mask,
+                    author_key_enabled,
+                    reserved,
+                    ld,
+                    family_id,
+                    image_id,
+                    version,
+                    guest_svn,
+                    id_key_algorithm,
+                    author_key_algorithm,
+                    id_key_signature,
+                    id_public_key,
+                    author_key_signature,
+                    author_public_key,
+                } => {
+                    loaded_info.snp_id_block.compatibility_mask = *compatibility_mask;
+                    loaded_info.snp_id_block.author_key_enabled = *author_key_enabled;
+                    loaded_info.snp_id_block.reserved = *reserved;
+                    loaded_info.snp_id_block.ld = *ld;
+                    loaded_info.snp_id_block.family_id = *family_id;
+                    loaded_info.snp_id_block.image_id = *image_id;
+                    loaded_info.snp_id_block.version = *version;
+                    loaded_info.snp_id_block.guest_svn = *guest_svn;
+                    loaded_info.snp_id_block.id_key_algorithm = *id_key_algorithm;
+                    loaded_info.snp_id_block.author_key_algorithm = *author_key_algorithm;
+                    loaded_info.snp_id_block.id_key_signature = **id_key_signature;
+                    loaded_info.snp_id_block.id_public_key = **id_public_key;
+                    loaded_info.snp_id_block.author_key_signature = **author_key_signature;
+                    loaded_info.snp_id_block.author_public_key = **author_public_key;
+                }
+                #[cfg(target_arch = "x86_64")]
+                IgvmDirectiveHeader::X64VbsVpContext {
+                    vtl,
+                    registers,
+                    compatibility_mask: _,
+                } => {
+                    // Only the boot vCPU of the VTL0 is given a context, the
+                    // others are started by the guest.
+                    if *vtl != Vtl::Vtl0 {
+                        return Err(Error::UnsupportedVtl(*vtl));
+                    }
+                    info!("Load X64VbsVpContext: {} registers", registers.len());
+                    loaded_info.vp_context_rip = set_vbs_vp_context(&cpu_manager, registers)?;
+                }
+                #[cfg(target_arch = "aarch64")]
+                IgvmDirectiveHeader::AArch64VbsVpContext {
+                    vtl,
+                    registers,
+                    compatibility_mask: _,
+                } => {
+                    if *vtl != Vtl::Vtl0 {
+                        return Err(Error::UnsupportedVtl(*vtl));
+                    }
+                    info!("Load AArch64VbsVpContext: {} registers", registers.len());
+                    loaded_info.vp_context_rip =
+                        set_aarch64_vbs_vp_context(&cpu_manager, registers)?;
+                }
+                IgvmDirectiveHeader::VbsMeasurement { .. } => {
+                    // The measurement is only checked by a hypervisor enforcing
+                    // the VBS isolation, the guest is not isolated here.
+                    debug!("Ignoring the VBS measurement");
+                }
+                IgvmDirectiveHeader::ParameterInsert(IGVM_VHS_PARAMETER_INSERT {
+                    gpa,
+                    compatibility_mask: _,
+                    parameter_area_index,
+                }) => {
+                    let area =
+                        parameter_areas
+                            .get_mut(parameter_area_index)
+                            .ok_or(Error::Validation(ValidationError::UndeclaredParameterArea(
+                                *parameter_area_index,
+                            )))?;
+                    let _page_count = match area {
+                        ParameterAreaState::Allocated { data, max_size } => {
+                            loader
+                                .import_pages(
+                                    gpa / HV_PAGE_SIZE,
+                                    *max_size / HV_PAGE_SIZE,
+                                    BootPageAcceptance::ExclusiveUnmeasured,
+                                    data,
+                                )
+                                .map_err(Error::Loader)?;
+                            *max_size / HV_PAGE_SIZE
+                        }
+                        ParameterAreaState::Inserted => {
+                            return Err(Error::Validation(ValidationError::InsertedParameterArea(
+                                *parameter_area_index,
+                            )))
+                        }
+                    };
+                    *area = ParameterAreaState::Inserted;
+                    #[cfg(feature = "tdx")]
+                    if tdx_enabled {
+                        tdx_pages.extend((0.._page_count).map(|i| TdxPage {
+                            gpa: gpa + i * HV_PAGE_SIZE,
+                            measured: false,
+                        }));
+                    }
+                    gpas.push(GpaPages {
+                        gpa: *gpa,
+                        page_type: IsolatedPageType::Unmeasured as u32,
+                        page_size: ISOLATED_PAGE_SIZE,
+                    });
+                }
+                header => {
+                    return Err(Error::Validation(ValidationError::UnsupportedDirective(
+                        validate::directive_name(header),
+                    )))
                 }
-                *area = ParameterAreaState::Inserted;
-                gpas.push(GpaPages {
-                    gpa: *gpa,
-                    page_type: IsolatedPageType::Unmeasured as u32,
-                    page_size: ISOLATED_PAGE_SIZE,
-                });
-            }
-            IgvmDirectiveHeader::ErrorRange { .. } => {
-                todo!("Error Range not supported")
-            }
-            _ => {
-                todo!("Header not supported!!")
             }
         }
     }
 
+    #[cfg(feature = "tdx")]
+    if tdx_enabled {
+        let hob_address = *loaded_info.gpas.first().ok_or(Error::MissingTdHob)?;
+        let ranges = tdx_page_ranges(tdx_pages);
+        populate_td_hob(&memory_manager, hob_address, &ranges)?;
+
+        // The vCPUs must be initialized before the memory is added to the TD.
+        cpu_manager
+            .lock()
+            .unwrap()
+            .initialize_tdx(hob_address)
+            .map_err(Error::InitializeTdx)?;
+        init_tdx_memory(&memory_manager, &ranges)?;
+
+        debug!(
+            "Loaded the IGVM file for TDX: hob_address: 0x{:x}",
+            hob_address
+        );
+        return Ok(loaded_info);
+    }
+
     #[cfg(feature = "sev_snp")]
-    {
+    if matches!(isolation_type, IsolationType::Snp) {
         use std::time::Instant;
-        use vm_memory::{GuestAddress, GuestAddressSpace, GuestMemory};
+        use vm_memory::{GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryRegion};
 
         let mut now = Instant::now();
 
-        // Sort the gpas to group them by the page type
+        // Sort the gpas to group them by the page type and size
         gpas.sort_by(|a, b| a.gpa.cmp(&b.gpa));
 
-        let gpas_grouped = gpas
-            .iter()
-            .fold(Vec::<Vec<GpaPages>>::new(), |mut acc, gpa| {
-                if let Some(last_vec) = acc.last_mut() {
-                    if last_vec[0].page_type == gpa.page_type {
-                        last_vec.push(*gpa);
-                        return acc;
-                    }
-                }
-                acc.push(vec![*gpa]);
-                acc
-            });
+        // Small pages covering a large page are imported at once, which the
+        // PSP measures the same way, as the small pages it is made of.
+        let guest_memory = memory_manager.lock().unwrap().guest_memory().memory();
+        let gpas = coalesce_large_pages(&gpas, |gpa| {
+            guest_memory
+                .get_slice(GuestAddress(gpa), HV_PAGE_SIZE_2MB as usize)
+                .is_ok()
+        });
 
-        // Import the pages as a group(by page type) of PFNs to reduce the
-        // hypercall.
-        for group in gpas_grouped.iter() {
-            info!(
-                "Importing {} page{}",
-                group.len(),
-                if group.len() > 1 { "s" } else { "" }
-            );
+        // Import the pages as batches of PFNs sharing the page type and size,
+        // to reduce the hypercalls. Each import extends the launch digest, so
+        // the batches are imported one after the other, in GPA order, for the
+        // measurement to be reproducible. The translation of the GPAs into
+        // the host addresses the pages are imported from is done ahead, in
+        // parallel.
+        let batches: Vec<&[GpaPages]> = gpas
+            .chunk_by(|a, b| a.page_type == b.page_type && a.page_size == b.page_size)
+            .flat_map(|group| group.chunks(IMPORT_BATCH_SIZE))
+            .collect();
+        let guest_memory: &GuestMemoryMmap = &guest_memory;
+        let addresses = map_in_parallel(&batches, |batch| {
             // Convert the gpa into PFN as MSHV hypercall takes an array
             // of PFN for importing the isolated pages
-            let pfns: Vec<u64> = group
+            let pfns: Vec<u64> = batch
                 .iter()
                 .map(|gpa| gpa.gpa >> ISOLATED_PAGE_SHIFT)
                 .collect();
-
-            let guest_memory = memory_manager.lock().unwrap().guest_memory().memory();
-            let uaddrs: Vec<_> = group
+            let uaddrs = batch
                 .iter()
                 .map(|gpa| {
-                    let guest_region_mmap = guest_memory.to_region_addr(GuestAddress(gpa.gpa));
-                    let uaddr_base = guest_region_mmap.unwrap().0.as_ptr() as u64; // FIXME: remove unwrap
-                    let uaddr_offset: u64 = guest_region_mmap.unwrap().1 .0; // FIXME: remove unwrap
-                    let uaddr = uaddr_base + uaddr_offset;
-                    uaddr
+                    guest_memory
+                        .get_host_address(GuestAddress(gpa.gpa))
+                        .map(|host_address| host_address as u64)
+                        .map_err(|_| Error::MemoryManager)
                 })
-                .collect();
+                .collect::<Result<Vec<u64>, Error>>()?;
+            Ok::<_, Error>((pfns, uaddrs))
+        });
+        info!(
+            "Importing {} pages in {} batches",
+            gpas.len(),
+            batches.len()
+        );
 
-            memory_manager
-                .lock()
-                .unwrap()
-                .vm
-                .import_isolated_pages(group[0].page_type, ISOLATED_PAGE_SIZE, &pfns, &uaddrs)
+        let vm = memory_manager.lock().unwrap().vm.clone();
+        for (batch, addresses) in batches.iter().zip(addresses) {
+            let (pfns, uaddrs) = addresses?;
+            vm.import_isolated_pages(batch[0].page_type, batch[0].page_size, &pfns, &uaddrs)
                 .map_err(Error::ImportIsolatedPages)?;
         }
 
@@ -570,6 +1235,31 @@ pub fn load_igvm(
             gpas.len()
         );
 
+        if let Some(private_memory) = memory_manager.lock().unwrap().private_memory() {
+            for (gpa, size) in unaccepted_pages.iter() {
+                private_memory.leave_unaccepted(*gpa, *size);
+            }
+        }
+        if !unaccepted_pages.is_empty() {
+            info!(
+                "{} unmeasured pages of the payload left unaccepted",
+                unaccepted_pages.len()
+            );
+        }
+
+        let ram_size: u64 = memory_manager
+            .lock()
+            .unwrap()
+            .guest_memory()
+            .memory()
+            .iter()
+            .map(|region| region.len())
+            .sum();
+        info!(
+            "{} MiB of guest memory left unaccepted",
+            ram_size.saturating_sub(gpas.iter().map(GpaPages::len).sum()) >> 20
+        );
+
         // Set vCPU initial states before calling SNP_LAUNCH_FINISH
         info!("Setting SEV Control Register - early");
         let vcpus = cpu_manager.lock().unwrap().vcpus();
@@ -584,12 +1274,20 @@ pub fn load_igvm(
 
         // FIXME: wait until for setting vCPU registers
 
+        // The ID block is generated from the launch digest the PSP is
+        // expected to compute, and signed with the keys of the operator.
+        if let Some(signer) = id_block_signer {
+            let ld = measurement::snp_launch_digest(file, lazy_unmeasured)
+                .map_err(Error::LaunchDigest)?;
+            signer.sign(&mut loaded_info.snp_id_block, &ld);
+        }
+
         // Call Complete Isolated Import since we are done importing isolated pages
         memory_manager
             .lock()
             .unwrap()
             .vm
-            .complete_isolated_import(loaded_info.snp_id_block, host_data_contents, 1)
+            .complete_isolated_import(loaded_info.snp_id_block, host_data_contents, snp_policy, 1)
             .map_err(Error::CompleteIsolatedImport)?;
 
         info!(
@@ -601,3 +1299,144 @@ pub fn load_igvm(
     debug!("Dumping the contents of VMSA page: {:x?}", loaded_info.vmsa);
     Ok(loaded_info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igvm::IgvmRevision;
+    use igvm_defs::IGVM_VHS_SUPPORTED_PLATFORM;
+
+    fn platform(compatibility_mask: u32, platform_type: IgvmPlatformType) -> IgvmPlatformHeader {
+        IgvmPlatformHeader::SupportedPlatform(IGVM_VHS_SUPPORTED_PLATFORM {
+            compatibility_mask,
+            highest_vtl: 0,
+            platform_type,
+            platform_version: 1,
+            shared_gpa_boundary: 0,
+        })
+    }
+
+    #[test]
+    fn test_select_platform() {
+        let igvm_file = IgvmFile::new(
+            IgvmRevision::V1,
+            vec![
+                platform(0x1, IgvmPlatformType::SEV_SNP),
+                platform(0x2, IgvmPlatformType::TDX),
+            ],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+
+        assert_eq!(
+            select_platform(&igvm_file, IsolationType::Snp, None).unwrap(),
+            0x1
+        );
+        assert_eq!(
+            select_platform(&igvm_file, IsolationType::Tdx, None).unwrap(),
+            0x2
+        );
+        assert!(matches!(
+            select_platform(&igvm_file, IsolationType::Vbs, None),
+            Err(Error::NoMatchingPlatform(IsolationType::Vbs))
+        ));
+        assert_eq!(
+            select_platform(&igvm_file, IsolationType::Tdx, Some(0x2)).unwrap(),
+            0x2
+        );
+        assert!(matches!(
+            select_platform(&igvm_file, IsolationType::Snp, Some(0x2)),
+            Err(Error::PlatformMismatch(0x2, IsolationType::Snp))
+        ));
+        assert!(matches!(
+            select_platform(&igvm_file, IsolationType::Snp, Some(0x4)),
+            Err(Error::UnknownPlatform(0x4))
+        ));
+    }
+
+    #[test]
+    fn test_environment_info() {
+        assert!(environment_info(IsolationType::Vbs).memory_is_shared());
+        assert!(!environment_info(IsolationType::Snp).memory_is_shared());
+        assert!(!environment_info(IsolationType::Tdx).memory_is_shared());
+        assert_eq!(
+            environment_info(IsolationType::Vbs).as_bytes(),
+            1u32.to_le_bytes()
+        );
+    }
+
+    #[cfg(feature = "sev_snp")]
+    #[test]
+    fn test_coalesce_large_pages() {
+        let page = |gpa, page_type: IsolatedPageType| GpaPages {
+            gpa,
+            page_type: page_type as u32,
+            page_size: ISOLATED_PAGE_SIZE,
+        };
+
+        // A full large page, followed by a run one page short of it.
+        let mut gpas: Vec<GpaPages> = (0..HV_PAGE_SIZE_2MB / HV_PAGE_SIZE)
+            .map(|n| page(n * HV_PAGE_SIZE, IsolatedPageType::Normal))
+            .collect();
+        gpas.extend((1..HV_PAGE_SIZE_2MB / HV_PAGE_SIZE).map(|n| {
+            page(
+                HV_PAGE_SIZE_2MB + n * HV_PAGE_SIZE,
+                IsolatedPageType::Normal,
+            )
+        }));
+        let coalesced = coalesce_large_pages(&gpas, |_| true);
+        assert_eq!(coalesced.len(), 1 + 511);
+        assert_eq!(coalesced[0].page_size, ISOLATED_PAGE_SIZE_2MB);
+        assert!(coalesced[1..]
+            .iter()
+            .all(|page| page.page_size == ISOLATED_PAGE_SIZE));
+        assert_eq!(
+            coalesced.iter().map(GpaPages::len).sum::<u64>(),
+            gpas.iter().map(GpaPages::len).sum::<u64>()
+        );
+
+        // Not contiguous on the host.
+        assert_eq!(coalesce_large_pages(&gpas, |_| false).len(), gpas.len());
+
+        // A page of another type in the run.
+        gpas[3] = page(3 * HV_PAGE_SIZE, IsolatedPageType::Cpuid);
+        assert_eq!(coalesce_large_pages(&gpas, |_| true).len(), gpas.len());
+    }
+
+    #[test]
+    fn test_carve_reserved_pages() {
+        let entry = |start: u64, pages: u64, entry_type| (start / HV_PAGE_SIZE, pages, entry_type);
+        let entries = |memory_map: Vec<IGVM_VHS_MEMORY_MAP_ENTRY>| {
+            memory_map
+                .iter()
+                .map(|e| (e.starting_gpa_page_number, e.number_of_pages, e.entry_type))
+                .collect::<Vec<_>>()
+        };
+        let ram_ranges = vec![(0, 0x10_0000), (0x100_0000, 0x200_0000)];
+
+        assert_eq!(
+            entries(carve_reserved_pages(ram_ranges.clone(), &[])),
+            vec![
+                entry(0, 0x100, MemoryMapEntryType::MEMORY),
+                entry(0x100_0000, 0x1000, MemoryMapEntryType::MEMORY),
+            ]
+        );
+
+        // Two contiguous pages in the middle of the second range, and a page
+        // at the start of the first, given out of order.
+        assert_eq!(
+            entries(carve_reserved_pages(
+                ram_ranges,
+                &[0x180_1000, 0, 0x180_0000, 0x180_0000]
+            )),
+            vec![
+                entry(0, 1, MemoryMapEntryType::PLATFORM_RESERVED),
+                entry(HV_PAGE_SIZE, 0xff, MemoryMapEntryType::MEMORY),
+                entry(0x100_0000, 0x800, MemoryMapEntryType::MEMORY),
+                entry(0x180_0000, 2, MemoryMapEntryType::PLATFORM_RESERVED),
+                entry(0x180_2000, 0x7fe, MemoryMapEntryType::MEMORY),
+            ]
+        );
+    }
+}
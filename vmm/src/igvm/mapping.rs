@@ -0,0 +1,99 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+/// Read only mapping of an IGVM file.
+///
+/// The file is parsed from the page cache, rather than from a copy of its
+/// content, which matters for the firmware images of several hundred MiB.
+pub struct IgvmMapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is read only and owned by the structure
+unsafe impl Send for IgvmMapping {}
+// SAFETY: the mapping is read only and owned by the structure
+unsafe impl Sync for IgvmMapping {}
+
+impl IgvmMapping {
+    pub fn new(file: &File) -> io::Result<Self> {
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))?;
+        if len == 0 {
+            return Ok(IgvmMapping {
+                addr: ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+
+        // SAFETY: FFI call with a valid fd, the result is checked
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The file is walked once, from start to end.
+        // SAFETY: the range is the mapping created above
+        unsafe { libc::madvise(addr, len, libc::MADV_SEQUENTIAL) };
+
+        Ok(IgvmMapping {
+            addr: addr as *mut u8,
+            len,
+        })
+    }
+}
+
+impl Deref for IgvmMapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping is valid and readable for its whole length until
+        // it is dropped
+        unsafe { slice::from_raw_parts(self.addr, self.len) }
+    }
+}
+
+impl Drop for IgvmMapping {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            // SAFETY: the range is the mapping created by new()
+            unsafe { libc::munmap(self.addr as *mut libc::c_void, self.len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_igvm_mapping() {
+        let file = TempFile::new().unwrap();
+        let contents: Vec<u8> = (0..0x3000u32).map(|i| i as u8).collect();
+        file.as_file().write_all(&contents).unwrap();
+        assert_eq!(&*IgvmMapping::new(file.as_file()).unwrap(), &contents[..]);
+
+        let file = TempFile::new().unwrap();
+        assert!(IgvmMapping::new(file.as_file()).unwrap().is_empty());
+    }
+}
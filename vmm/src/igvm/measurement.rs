@@ -0,0 +1,379 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Expected SEV-SNP launch measurement of an IGVM file.
+//!
+//! The PSP measures the pages of an SNP guest as they are added at launch,
+//! extending the launch digest with the SHA-384 digest of a PAGE_INFO
+//! structure per 4KiB page. The pages are walked here as `load_igvm` imports
+//! them, sorted by GPA, for the digest reported by the attestation of the
+//! guest to be computed ahead of time, without launching a VM.
+
+use crate::igvm::{is_lazy_page, map_in_parallel, IgvmMapping, HV_PAGE_SIZE, HV_PAGE_SIZE_2MB};
+use igvm::{
+    IgvmDirectiveHeader, IgvmFile, IgvmInitializationHeader, IgvmPlatformHeader, IsolationType,
+};
+use igvm_defs::{IgvmPageDataType, IgvmPlatformType, IGVM_VHS_PARAMETER_INSERT};
+use serde::Serialize;
+use sha2::{Digest, Sha384};
+use std::fs::File;
+use std::io;
+use thiserror::Error;
+use zerocopy::AsBytes;
+
+const DIGEST_SIZE: usize = 48;
+const PAGE_INFO_SIZE: usize = 0x70;
+
+// Page types of the PAGE_INFO structure
+const PAGE_TYPE_NORMAL: u8 = 1;
+const PAGE_TYPE_VMSA: u8 = 2;
+const PAGE_TYPE_UNMEASURED: u8 = 4;
+const PAGE_TYPE_SECRETS: u8 = 5;
+const PAGE_TYPE_CPUID: u8 = 6;
+
+pub type Sha384Digest = [u8; DIGEST_SIZE];
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to read the IGVM file: {0}")]
+    ReadIgvmFile(#[source] io::Error),
+    #[error("Invalid IGVM file: {0}")]
+    InvalidIgvmFile(#[source] igvm::Error),
+    #[error("The IGVM file does not support SEV-SNP")]
+    SnpNotSupported,
+    #[error("Page data type {0:?} is not supported")]
+    UnsupportedPageDataType(IgvmPageDataType),
+}
+
+/// Digests an attestation verifier checks the report of an SNP guest
+/// against, in hexadecimal.
+#[derive(Debug, Serialize)]
+pub struct LaunchMeasurement {
+    /// Launch digest, the MEASUREMENT field of the report.
+    pub launch_digest: String,
+    /// Digest of the ID block the ID key signs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_block_digest: Option<String>,
+    /// Digest of the ID public key, the ID_KEY_DIGEST field of the report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_key_digest: Option<String>,
+    /// Digest of the author public key, the AUTHOR_KEY_DIGEST field of the
+    /// report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author_key_digest: Option<String>,
+}
+
+struct MeasuredPage {
+    gpa: u64,
+    page_type: u8,
+    contents: Sha384Digest,
+}
+
+impl MeasuredPage {
+    // Only the content of the normal and VMSA pages is measured, the data
+    // being zero extended to the page size.
+    fn new(gpa: u64, page_type: u8, data: &[u8]) -> Self {
+        let contents = if page_type == PAGE_TYPE_NORMAL || page_type == PAGE_TYPE_VMSA {
+            let mut page = [0u8; HV_PAGE_SIZE as usize];
+            page[..data.len()].copy_from_slice(data);
+            Sha384::digest(page).into()
+        } else {
+            [0u8; DIGEST_SIZE]
+        };
+
+        MeasuredPage {
+            gpa,
+            page_type,
+            contents,
+        }
+    }
+
+    // PAGE_INFO structure measured by SNP_LAUNCH_UPDATE.
+    fn page_info(&self, digest: &Sha384Digest) -> [u8; PAGE_INFO_SIZE] {
+        let mut page_info = [0u8; PAGE_INFO_SIZE];
+        page_info[..0x30].copy_from_slice(digest);
+        page_info[0x30..0x60].copy_from_slice(&self.contents);
+        page_info[0x60..0x62].copy_from_slice(&(PAGE_INFO_SIZE as u16).to_le_bytes());
+        page_info[0x62] = self.page_type;
+        page_info[0x68..0x70].copy_from_slice(&self.gpa.to_le_bytes());
+
+        page_info
+    }
+}
+
+// Extend the launch digest with the pages, in the order they are imported.
+fn launch_digest(pages: &[MeasuredPage]) -> Sha384Digest {
+    pages.iter().fold([0u8; DIGEST_SIZE], |digest, page| {
+        Sha384::digest(page.page_info(&digest)).into()
+    })
+}
+
+/// Computes the expected SEV-SNP launch measurement of the IGVM `file`, the
+/// unmeasured pages being left out when `lazy_unmeasured` is set.
+pub fn snp_launch_measurement(
+    file: &File,
+    lazy_unmeasured: bool,
+) -> Result<LaunchMeasurement, Error> {
+    let mapping = IgvmMapping::new(file).map_err(Error::ReadIgvmFile)?;
+
+    measure(&mapping, lazy_unmeasured)
+}
+
+/// Computes the expected SEV-SNP launch digest of the IGVM `file`, as signed
+/// in the ID block.
+pub fn snp_launch_digest(file: &File, lazy_unmeasured: bool) -> Result<Sha384Digest, Error> {
+    let mapping = IgvmMapping::new(file).map_err(Error::ReadIgvmFile)?;
+
+    measure_pages(&mapping, lazy_unmeasured).map(|(digest, _)| digest)
+}
+
+// Digests of the ID block of the file: launch digest, ID block, ID key and
+// author key.
+type IdBlockDigests = (Sha384Digest, String, String, Option<String>);
+
+fn measure(file_contents: &[u8], lazy_unmeasured: bool) -> Result<LaunchMeasurement, Error> {
+    let (digest, id_block_digests) = measure_pages(file_contents, lazy_unmeasured)?;
+
+    let mut measurement = LaunchMeasurement {
+        launch_digest: hex::encode(digest),
+        id_block_digest: None,
+        id_key_digest: None,
+        author_key_digest: None,
+    };
+    if let Some((ld, id_block_digest, id_key_digest, author_key_digest)) = id_block_digests {
+        if ld != digest {
+            warn!("The launch digest of the ID block does not match the one of the IGVM file");
+        }
+        measurement.id_block_digest = Some(id_block_digest);
+        measurement.id_key_digest = Some(id_key_digest);
+        measurement.author_key_digest = author_key_digest;
+    }
+
+    Ok(measurement)
+}
+
+fn measure_pages(
+    file_contents: &[u8],
+    lazy_unmeasured: bool,
+) -> Result<(Sha384Digest, Option<IdBlockDigests>), Error> {
+    let igvm_file = IgvmFile::new_from_binary(file_contents, Some(IsolationType::Snp))
+        .map_err(Error::InvalidIgvmFile)?;
+
+    if !igvm_file.platforms().iter().any(|platform| {
+        matches!(platform, IgvmPlatformHeader::SupportedPlatform(info)
+            if info.platform_type == IgvmPlatformType::SEV_SNP)
+    }) {
+        return Err(Error::SnpNotSupported);
+    }
+
+    let policy = igvm_file
+        .initializations()
+        .iter()
+        .find_map(|header| match header {
+            IgvmInitializationHeader::GuestPolicy { policy, .. } => Some(*policy),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let mut pages: Vec<(u64, u8, &[u8])> = Vec::new();
+    let mut id_block_digests = None;
+
+    for header in igvm_file.directives() {
+        match header {
+            IgvmDirectiveHeader::PageData {
+                gpa,
+                flags,
+                data_type,
+                data,
+                ..
+            } => {
+                // Pages left unaccepted are not imported, nor measured.
+                if lazy_unmeasured && is_lazy_page(flags, *data_type, data) {
+                    continue;
+                }
+                let page_type = match *data_type {
+                    IgvmPageDataType::NORMAL if flags.unmeasured() => PAGE_TYPE_UNMEASURED,
+                    IgvmPageDataType::NORMAL => PAGE_TYPE_NORMAL,
+                    IgvmPageDataType::SECRETS => PAGE_TYPE_SECRETS,
+                    IgvmPageDataType::CPUID_DATA => PAGE_TYPE_CPUID,
+                    data_type => return Err(Error::UnsupportedPageDataType(data_type)),
+                };
+                let page_size = if flags.is_2mb_page() {
+                    HV_PAGE_SIZE_2MB
+                } else {
+                    HV_PAGE_SIZE
+                };
+
+                // A large page is measured as the 4KiB pages it is made of.
+                let mut chunks = data.chunks(HV_PAGE_SIZE as usize);
+                for i in 0..page_size / HV_PAGE_SIZE {
+                    pages.push((
+                        gpa + i * HV_PAGE_SIZE,
+                        page_type,
+                        chunks.next().unwrap_or_default(),
+                    ));
+                }
+            }
+            IgvmDirectiveHeader::ParameterInsert(IGVM_VHS_PARAMETER_INSERT { gpa, .. }) => {
+                // As imported by load_igvm, a single unmeasured page.
+                pages.push((*gpa, PAGE_TYPE_UNMEASURED, &[]));
+            }
+            IgvmDirectiveHeader::SnpVpContext { gpa, vmsa, .. } => {
+                pages.push((*gpa, PAGE_TYPE_VMSA, vmsa.as_bytes()));
+            }
+            IgvmDirectiveHeader::SnpIdBlock {
+                author_key_enabled,
+                ld,
+                family_id,
+                image_id,
+                version,
+                guest_svn,
+                id_public_key,
+                author_public_key,
+                ..
+            } => {
+                // ID_BLOCK structure checked by SNP_LAUNCH_FINISH.
+                let mut id_block = Vec::with_capacity(0x60);
+                id_block.extend_from_slice(ld);
+                id_block.extend_from_slice(family_id);
+                id_block.extend_from_slice(image_id);
+                id_block.extend_from_slice(&version.to_le_bytes());
+                id_block.extend_from_slice(&guest_svn.to_le_bytes());
+                id_block.extend_from_slice(&policy.to_le_bytes());
+
+                let author_key_digest = (*author_key_enabled != 0)
+                    .then(|| hex::encode(Sha384::digest(author_public_key.as_bytes())));
+                id_block_digests = Some((
+                    *ld,
+                    hex::encode(Sha384::digest(&id_block)),
+                    hex::encode(Sha384::digest(id_public_key.as_bytes())),
+                    author_key_digest,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    // The sort is stable, the pages sharing a GPA remaining in file order as
+    // for load_igvm. The contents of the pages are digested in parallel, only
+    // the launch digest being extended one page after the other.
+    pages.sort_by_key(|(gpa, _, _)| *gpa);
+    let pages = map_in_parallel(&pages, |(gpa, page_type, data)| {
+        MeasuredPage::new(*gpa, *page_type, data)
+    });
+
+    Ok((launch_digest(&pages), id_block_digests))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igvm::IgvmRevision;
+    use igvm_defs::{IgvmPageDataFlags, IGVM_VHS_SUPPORTED_PLATFORM};
+
+    fn page_data(gpa: u64, flags: IgvmPageDataFlags, data: Vec<u8>) -> IgvmDirectiveHeader {
+        IgvmDirectiveHeader::PageData {
+            gpa,
+            compatibility_mask: 1,
+            flags,
+            data_type: IgvmPageDataType::NORMAL,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_snp_launch_measurement() {
+        let igvm_file = IgvmFile::new(
+            IgvmRevision::V1,
+            vec![IgvmPlatformHeader::SupportedPlatform(
+                IGVM_VHS_SUPPORTED_PLATFORM {
+                    compatibility_mask: 1,
+                    highest_vtl: 0,
+                    platform_type: IgvmPlatformType::SEV_SNP,
+                    platform_version: 1,
+                    shared_gpa_boundary: 0,
+                },
+            )],
+            vec![],
+            vec![
+                page_data(0x2000, IgvmPageDataFlags::new(), vec![0xaa; 16]),
+                page_data(
+                    0x1000,
+                    IgvmPageDataFlags::new().with_unmeasured(true),
+                    vec![0xbb; 16],
+                ),
+            ],
+        )
+        .unwrap();
+        let mut file_contents = Vec::new();
+        igvm_file.serialize(&mut file_contents).unwrap();
+
+        let measurement = measure(&file_contents, false).unwrap();
+
+        // The pages are measured in order of GPA, the content of the
+        // unmeasured one being left out.
+        let digest = launch_digest(&[
+            MeasuredPage::new(0x1000, PAGE_TYPE_UNMEASURED, &[]),
+            MeasuredPage::new(0x2000, PAGE_TYPE_NORMAL, &[0xaa; 16]),
+        ]);
+        assert_eq!(measurement.launch_digest, hex::encode(digest));
+        assert!(measurement.id_block_digest.is_none());
+
+        let page = MeasuredPage::new(0x2000, PAGE_TYPE_NORMAL, &[0xaa; 16]);
+        let page_info = page.page_info(&[0x11; DIGEST_SIZE]);
+        assert_eq!(&page_info[..0x30], &[0x11; DIGEST_SIZE]);
+        assert_eq!(&page_info[0x60..0x63], &[0x70, 0x00, PAGE_TYPE_NORMAL]);
+        assert_eq!(&page_info[0x68..], &0x2000u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_snp_launch_measurement_lazy_unmeasured() {
+        let igvm_file = IgvmFile::new(
+            IgvmRevision::V1,
+            vec![IgvmPlatformHeader::SupportedPlatform(
+                IGVM_VHS_SUPPORTED_PLATFORM {
+                    compatibility_mask: 1,
+                    highest_vtl: 0,
+                    platform_type: IgvmPlatformType::SEV_SNP,
+                    platform_version: 1,
+                    shared_gpa_boundary: 0,
+                },
+            )],
+            vec![],
+            vec![
+                page_data(0x1000, IgvmPageDataFlags::new(), vec![]),
+                page_data(
+                    0x2000,
+                    IgvmPageDataFlags::new().with_unmeasured(true),
+                    vec![0xbb; 16],
+                ),
+                page_data(
+                    0x3000,
+                    IgvmPageDataFlags::new().with_unmeasured(true),
+                    vec![],
+                ),
+            ],
+        )
+        .unwrap();
+        let mut file_contents = Vec::new();
+        igvm_file.serialize(&mut file_contents).unwrap();
+
+        // Only the unmeasured page with no content is left out.
+        let measurement = measure(&file_contents, true).unwrap();
+        let digest = launch_digest(&[
+            MeasuredPage::new(0x1000, PAGE_TYPE_NORMAL, &[]),
+            MeasuredPage::new(0x2000, PAGE_TYPE_UNMEASURED, &[]),
+        ]);
+        assert_eq!(measurement.launch_digest, hex::encode(digest));
+
+        let measurement = measure(&file_contents, false).unwrap();
+        let digest = launch_digest(&[
+            MeasuredPage::new(0x1000, PAGE_TYPE_NORMAL, &[]),
+            MeasuredPage::new(0x2000, PAGE_TYPE_UNMEASURED, &[]),
+            MeasuredPage::new(0x3000, PAGE_TYPE_UNMEASURED, &[]),
+        ]);
+        assert_eq!(measurement.launch_digest, hex::encode(digest));
+    }
+}
@@ -25,18 +25,34 @@
  *  booting a legacy VM, as well as SNP based isolated VM.
  */
 
+#[cfg(target_arch = "x86_64")]
+mod cpuid;
+#[cfg(feature = "sev_snp")]
+pub mod id_block;
 pub mod igvm_loader;
 mod loader;
+mod mapping;
+pub mod measurement;
+pub mod validate;
+pub use mapping::IgvmMapping;
+
 use igvm::snp_defs::SevVmsa;
-use igvm_defs::IGVM_VHS_SNP_ID_BLOCK;
+use igvm_defs::{IgvmPageDataFlags, IgvmPageDataType, IGVM_VHS_SNP_ID_BLOCK};
+use std::collections::BTreeMap;
+use std::thread;
 use zerocopy::FromZeroes;
 
 #[derive(Debug, Clone)]
 pub struct IgvmLoadedInfo {
     pub gpas: Vec<u64>,
     pub vmsa_gpa: u64,
+    /// GPA of the VMSA of each vCPU, per VP index.
+    pub vmsa_gpas: BTreeMap<u32, u64>,
     pub snp_id_block: IGVM_VHS_SNP_ID_BLOCK,
     pub vmsa: SevVmsa,
+    /// Instruction pointer (program counter on aarch64) of the boot vCPU, set
+    /// by a VBS VP context.
+    pub vp_context_rip: u64,
 }
 
 impl Default for IgvmLoadedInfo {
@@ -44,13 +60,59 @@ impl Default for IgvmLoadedInfo {
         IgvmLoadedInfo {
             gpas: Vec::new(),
             vmsa_gpa: 0,
+            vmsa_gpas: BTreeMap::new(),
             snp_id_block: IGVM_VHS_SNP_ID_BLOCK::new_zeroed(),
             vmsa: SevVmsa::new_zeroed(),
+            vp_context_rip: 0,
         }
     }
 }
 
 pub const HV_PAGE_SIZE: u64 = 4096;
+pub const HV_PAGE_SIZE_2MB: u64 = 0x20_0000;
+
+const MAX_PAGE_WORKER_COUNT: usize = 8;
+
+/// Run `f` for each of the `items`, spread across a few threads, keeping the
+/// results in order. A payload of a few GiB adds up to a million pages, the
+/// work done for each of them being significant at this scale.
+fn map_in_parallel<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let num_threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(MAX_PAGE_WORKER_COUNT)
+        .min(items.len());
+    if num_threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = items
+            .chunks(items.len().div_ceil(num_threads))
+            .map(|chunk| s.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+
+        let mut results = Vec::with_capacity(items.len());
+        for handle in handles {
+            results.extend(
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e)),
+            );
+        }
+        results
+    })
+}
+
+/// Whether the page data is left unaccepted when the unmeasured pages are
+/// accepted lazily: a normal unmeasured page with no content, the guest
+/// getting a zeroed page when accepting it.
+fn is_lazy_page(flags: &IgvmPageDataFlags, data_type: IgvmPageDataType, data: &[u8]) -> bool {
+    data_type == IgvmPageDataType::NORMAL
+        && flags.unmeasured()
+        && !flags.shared()
+        && data.iter().all(|b| *b == 0)
+}
 
 /// The page acceptance used for importing pages into the initial launch context of the guest.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
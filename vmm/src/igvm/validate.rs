@@ -0,0 +1,475 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Validation of an IGVM file ahead of its load.
+//!
+//! The directives are checked against the invariants `load_igvm` relies on,
+//! for an invalid file to be reported as a list of problems rather than to
+//! crash the VMM in the middle of the load.
+
+use crate::igvm::{IgvmMapping, HV_PAGE_SIZE, HV_PAGE_SIZE_2MB};
+use igvm::{IgvmDirectiveHeader, IgvmFile, IgvmPlatformHeader, IsolationType};
+use igvm_defs::{IgvmPageDataType, IGVM_VHS_PARAMETER, IGVM_VHS_PARAMETER_INSERT};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to read the IGVM file: {0}")]
+    ReadIgvmFile(#[source] io::Error),
+    #[error("Invalid IGVM file: {0}")]
+    InvalidIgvmFile(#[source] igvm::Error),
+}
+
+/// Problem found in the directives of an IGVM file.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ValidationError {
+    #[error("The IGVM file supports no platform")]
+    NoPlatform,
+    #[error("Parameter area {0} is declared more than once")]
+    DuplicateParameterArea(u32),
+    #[error("Parameter area {0} is not declared")]
+    UndeclaredParameterArea(u32),
+    #[error("Parameter area {0} is used after being inserted")]
+    InsertedParameterArea(u32),
+    #[error("Parameter area {0} of 0x{1:x} bytes is not a whole number of pages")]
+    InvalidParameterAreaSize(u32, u64),
+    #[error("Parameter at offset 0x{1:x} is beyond the parameter area {0}")]
+    InvalidParameterOffset(u32, u32),
+    #[error("Page at 0x{0:x} is not aligned on its size")]
+    UnalignedPage(u64),
+    #[error("Page data at 0x{0:x} is larger than its page")]
+    PageDataTooLarge(u64),
+    #[error("Page at 0x{0:x} overlaps a page imported before")]
+    OverlappingPage(u64),
+    #[error("Required memory at 0x{0:x} of 0x{1:x} bytes is not backed by the guest RAM")]
+    RequiredMemoryUnavailable(u64, u64),
+    #[error("Page data type {0:?} at 0x{1:x} is not supported")]
+    UnsupportedPageDataType(IgvmPageDataType, u64),
+    #[error("{0} directive is not supported")]
+    UnsupportedDirective(String),
+}
+
+#[derive(Clone, Copy)]
+enum ParameterArea {
+    Allocated(u64),
+    Inserted,
+}
+
+// Name of the directive, without its content.
+pub(crate) fn directive_name(header: &IgvmDirectiveHeader) -> String {
+    format!("{header:?}")
+        .split([' ', '(', '{'])
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+// Guest RAM ranges, as (start, end) tuples, for the RAM of `size` bytes laid
+// out around the 32-bit memory hole.
+pub fn ram_ranges(size: u64) -> Vec<(u64, u64)> {
+    #[cfg(target_arch = "x86_64")]
+    let ram_start = 0;
+    #[cfg(target_arch = "aarch64")]
+    let ram_start = arch::layout::RAM_START.raw_value();
+
+    let low_size = size.min(arch::layout::MEM_32BIT_RESERVED_START.raw_value() - ram_start);
+    let mut ranges = vec![(ram_start, ram_start + low_size)];
+    if size > low_size {
+        let start = arch::layout::RAM_64BIT_START.raw_value();
+        ranges.push((start, start + size - low_size));
+    }
+
+    ranges
+}
+
+struct Validator<'a> {
+    ram_ranges: Option<&'a [(u64, u64)]>,
+    parameter_areas: HashMap<u32, ParameterArea>,
+    // Imported pages, end address per start address.
+    pages: BTreeMap<u64, u64>,
+    errors: Vec<ValidationError>,
+}
+
+impl<'a> Validator<'a> {
+    fn new(ram_ranges: Option<&'a [(u64, u64)]>) -> Self {
+        Validator {
+            ram_ranges,
+            parameter_areas: HashMap::new(),
+            pages: BTreeMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn import_pages(&mut self, gpa: u64, size: u64) {
+        if gpa % size.min(HV_PAGE_SIZE_2MB) != 0 {
+            self.errors.push(ValidationError::UnalignedPage(gpa));
+        }
+
+        let end = gpa + size;
+        let overlaps = self
+            .pages
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, page_end)| *page_end > gpa);
+        if overlaps {
+            self.errors.push(ValidationError::OverlappingPage(gpa));
+        } else {
+            self.pages.insert(gpa, end);
+        }
+    }
+
+    fn parameter_area(&mut self, index: u32) -> Option<u64> {
+        match self.parameter_areas.get(&index) {
+            Some(ParameterArea::Allocated(size)) => Some(*size),
+            Some(ParameterArea::Inserted) => {
+                self.errors
+                    .push(ValidationError::InsertedParameterArea(index));
+                None
+            }
+            None => {
+                self.errors
+                    .push(ValidationError::UndeclaredParameterArea(index));
+                None
+            }
+        }
+    }
+
+    fn import_parameter(&mut self, info: &IGVM_VHS_PARAMETER) {
+        if let Some(size) = self.parameter_area(info.parameter_area_index) {
+            if u64::from(info.byte_offset) >= size {
+                self.errors.push(ValidationError::InvalidParameterOffset(
+                    info.parameter_area_index,
+                    info.byte_offset,
+                ));
+            }
+        }
+    }
+
+    fn check(&mut self, header: &IgvmDirectiveHeader) {
+        match header {
+            IgvmDirectiveHeader::PageData {
+                gpa,
+                flags,
+                data_type,
+                data,
+                ..
+            } => {
+                let page_size = if flags.is_2mb_page() {
+                    HV_PAGE_SIZE_2MB
+                } else {
+                    HV_PAGE_SIZE
+                };
+                if data.len() as u64 > page_size {
+                    self.errors.push(ValidationError::PageDataTooLarge(*gpa));
+                }
+                if !matches!(
+                    *data_type,
+                    IgvmPageDataType::NORMAL
+                        | IgvmPageDataType::SECRETS
+                        | IgvmPageDataType::CPUID_DATA
+                ) {
+                    self.errors
+                        .push(ValidationError::UnsupportedPageDataType(*data_type, *gpa));
+                }
+                // As for load_igvm, the zero pages are not tracked.
+                if !data.is_empty() || *data_type == IgvmPageDataType::CPUID_DATA {
+                    self.import_pages(*gpa, page_size);
+                } else if gpa % page_size != 0 {
+                    self.errors.push(ValidationError::UnalignedPage(*gpa));
+                }
+            }
+            IgvmDirectiveHeader::ParameterArea {
+                number_of_bytes,
+                parameter_area_index,
+                ..
+            } => {
+                if number_of_bytes % HV_PAGE_SIZE != 0 {
+                    self.errors.push(ValidationError::InvalidParameterAreaSize(
+                        *parameter_area_index,
+                        *number_of_bytes,
+                    ));
+                }
+                if self
+                    .parameter_areas
+                    .insert(
+                        *parameter_area_index,
+                        ParameterArea::Allocated(*number_of_bytes),
+                    )
+                    .is_some()
+                {
+                    self.errors.push(ValidationError::DuplicateParameterArea(
+                        *parameter_area_index,
+                    ));
+                }
+            }
+            IgvmDirectiveHeader::VpCount(info)
+            | IgvmDirectiveHeader::MmioRanges(info)
+            | IgvmDirectiveHeader::CommandLine(info)
+            | IgvmDirectiveHeader::DeviceTree(info)
+            | IgvmDirectiveHeader::EnvironmentInfo(info) => self.import_parameter(info),
+            #[cfg(any(feature = "sev_snp", feature = "tdx"))]
+            IgvmDirectiveHeader::MemoryMap(info) => self.import_parameter(info),
+            IgvmDirectiveHeader::ParameterInsert(IGVM_VHS_PARAMETER_INSERT {
+                gpa,
+                parameter_area_index,
+                ..
+            }) => {
+                if let Some(size) = self.parameter_area(*parameter_area_index) {
+                    self.parameter_areas
+                        .insert(*parameter_area_index, ParameterArea::Inserted);
+                    self.import_pages(*gpa, size.max(HV_PAGE_SIZE));
+                }
+            }
+            IgvmDirectiveHeader::RequiredMemory {
+                gpa,
+                number_of_bytes,
+                ..
+            } => {
+                let (start, end) = (*gpa, gpa + u64::from(*number_of_bytes));
+                if let Some(ram_ranges) = self.ram_ranges {
+                    if !ram_ranges
+                        .iter()
+                        .any(|(ram_start, ram_end)| start >= *ram_start && end <= *ram_end)
+                    {
+                        self.errors.push(ValidationError::RequiredMemoryUnavailable(
+                            start,
+                            u64::from(*number_of_bytes),
+                        ));
+                    }
+                }
+            }
+            IgvmDirectiveHeader::SnpVpContext { gpa, .. } => self.import_pages(*gpa, HV_PAGE_SIZE),
+            IgvmDirectiveHeader::SnpIdBlock { .. } | IgvmDirectiveHeader::VbsMeasurement { .. } => {
+            }
+            #[cfg(target_arch = "x86_64")]
+            IgvmDirectiveHeader::X64VbsVpContext { .. } => {}
+            #[cfg(target_arch = "aarch64")]
+            IgvmDirectiveHeader::AArch64VbsVpContext { .. } => {}
+            header => self
+                .errors
+                .push(ValidationError::UnsupportedDirective(directive_name(
+                    header,
+                ))),
+        }
+    }
+}
+
+/// Checks the directives of the parsed `igvm_file`, for each of the
+/// platforms it supports. The required memory is checked against the
+/// `ram_ranges` if given.
+pub fn validate(igvm_file: &IgvmFile, ram_ranges: Option<&[(u64, u64)]>) -> Vec<ValidationError> {
+    if igvm_file.platforms().is_empty() {
+        return vec![ValidationError::NoPlatform];
+    }
+
+    let mut errors = Vec::new();
+    for platform in igvm_file.platforms() {
+        let IgvmPlatformHeader::SupportedPlatform(info) = platform;
+        for error in validate_platform(igvm_file, info.compatibility_mask, ram_ranges) {
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+        }
+    }
+
+    errors
+}
+
+/// Checks the directives of the parsed `igvm_file` for the platform of the
+/// compatibility `mask` only.
+pub fn validate_platform(
+    igvm_file: &IgvmFile,
+    mask: u32,
+    ram_ranges: Option<&[(u64, u64)]>,
+) -> Vec<ValidationError> {
+    let mut validator = Validator::new(ram_ranges);
+    for header in igvm_file
+        .directives()
+        .iter()
+        .filter(|header| header.compatibility_mask().unwrap_or(mask) & mask != 0)
+    {
+        validator.check(header);
+    }
+
+    validator.errors
+}
+
+/// Parses the IGVM `file` and checks its directives, returning the problems
+/// found. The file is parsed for the `isolation_type` if given, for all the
+/// platforms it supports otherwise.
+pub fn validate_igvm(
+    file: &File,
+    isolation_type: Option<IsolationType>,
+    ram_size: Option<u64>,
+) -> Result<Vec<ValidationError>, Error> {
+    let mapping = IgvmMapping::new(file).map_err(Error::ReadIgvmFile)?;
+    let igvm_file =
+        IgvmFile::new_from_binary(&mapping, isolation_type).map_err(Error::InvalidIgvmFile)?;
+    let ram_ranges = ram_size.map(ram_ranges);
+
+    Ok(validate(&igvm_file, ram_ranges.as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igvm_defs::IgvmPageDataFlags;
+
+    fn page_data(gpa: u64, data: Vec<u8>) -> IgvmDirectiveHeader {
+        IgvmDirectiveHeader::PageData {
+            gpa,
+            compatibility_mask: 1,
+            flags: IgvmPageDataFlags::new(),
+            data_type: IgvmPageDataType::NORMAL,
+            data,
+        }
+    }
+
+    fn parameter_area(index: u32) -> IgvmDirectiveHeader {
+        IgvmDirectiveHeader::ParameterArea {
+            number_of_bytes: HV_PAGE_SIZE,
+            parameter_area_index: index,
+            initial_data: vec![],
+        }
+    }
+
+    fn parameter_insert(gpa: u64, index: u32) -> IgvmDirectiveHeader {
+        IgvmDirectiveHeader::ParameterInsert(IGVM_VHS_PARAMETER_INSERT {
+            gpa,
+            compatibility_mask: 1,
+            parameter_area_index: index,
+        })
+    }
+
+    fn command_line(index: u32) -> IgvmDirectiveHeader {
+        IgvmDirectiveHeader::CommandLine(IGVM_VHS_PARAMETER {
+            parameter_area_index: index,
+            byte_offset: 0,
+        })
+    }
+
+    fn check(
+        directives: &[IgvmDirectiveHeader],
+        ram_ranges: Option<&[(u64, u64)]>,
+    ) -> Vec<ValidationError> {
+        let mut validator = Validator::new(ram_ranges);
+        for header in directives {
+            validator.check(header);
+        }
+        validator.errors
+    }
+
+    #[test]
+    fn test_validate_igvm() {
+        let ram_start = ram_ranges(1 << 30)[0].0;
+        let valid = [
+            page_data(0x1000, vec![0xaa; 16]),
+            page_data(0x1000, vec![]),
+            parameter_area(0),
+            command_line(0),
+            parameter_insert(0x2000, 0),
+            IgvmDirectiveHeader::RequiredMemory {
+                gpa: ram_start + 0x10_0000,
+                compatibility_mask: 1,
+                number_of_bytes: 0x1000,
+                vtl2_protectable: false,
+            },
+        ];
+        assert!(check(&valid, Some(&ram_ranges(1 << 30))).is_empty());
+        assert_eq!(
+            check(&valid, Some(&ram_ranges(0x10_0000))),
+            vec![ValidationError::RequiredMemoryUnavailable(
+                ram_start + 0x10_0000,
+                0x1000
+            )]
+        );
+
+        let invalid = [
+            page_data(0x1000, vec![0xaa; 16]),
+            page_data(0x1000, vec![0xbb; 16]),
+            page_data(0x3800, vec![0xcc; 16]),
+            parameter_area(0),
+            parameter_area(0),
+            parameter_insert(0x2000, 0),
+            command_line(0),
+            parameter_insert(0x4000, 1),
+        ];
+        assert_eq!(
+            check(&invalid, None),
+            vec![
+                ValidationError::OverlappingPage(0x1000),
+                ValidationError::UnalignedPage(0x3800),
+                ValidationError::DuplicateParameterArea(0),
+                ValidationError::InsertedParameterArea(0),
+                ValidationError::UndeclaredParameterArea(1),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_ram_ranges() {
+        assert_eq!(ram_ranges(1 << 30), vec![(0, 1 << 30)]);
+        assert_eq!(
+            ram_ranges(4 << 30),
+            vec![(0, 0xc000_0000), (0x1_0000_0000, 0x1_4000_0000)]
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_ram_ranges() {
+        assert_eq!(ram_ranges(1 << 30), vec![(0x4000_0000, 0x8000_0000)]);
+        assert_eq!(
+            ram_ranges(4 << 30),
+            vec![(0x4000_0000, 0xfc00_0000), (0x1_0000_0000, 0x1_4400_0000)]
+        );
+    }
+
+    #[test]
+    fn test_validate_device_tree() {
+        let device_tree = IgvmDirectiveHeader::DeviceTree(IGVM_VHS_PARAMETER {
+            parameter_area_index: 0,
+            byte_offset: 0,
+        });
+        assert!(check(
+            &[
+                parameter_area(0),
+                device_tree.clone(),
+                parameter_insert(0x2000, 0)
+            ],
+            None
+        )
+        .is_empty());
+        assert_eq!(
+            check(&[device_tree], None),
+            vec![ValidationError::UndeclaredParameterArea(0)]
+        );
+    }
+
+    #[test]
+    fn test_validate_environment_info() {
+        let environment_info = IgvmDirectiveHeader::EnvironmentInfo(IGVM_VHS_PARAMETER {
+            parameter_area_index: 0,
+            byte_offset: 0,
+        });
+        assert!(check(
+            &[
+                parameter_area(0),
+                environment_info.clone(),
+                parameter_insert(0x2000, 0)
+            ],
+            None
+        )
+        .is_empty());
+        assert_eq!(
+            check(&[environment_info], None),
+            vec![ValidationError::UndeclaredParameterArea(0)]
+        );
+    }
+}
@@ -72,6 +72,7 @@ impl InterruptRoute {
     }
 
     pub fn trigger(&self) -> Result<()> {
+        tracer::usdt!(irq_inject_msi, self.gsi);
         self.irq_fd.write(1)
     }
 
@@ -227,6 +228,7 @@ impl LegacyUserspaceInterruptGroup {
 
 impl InterruptSourceGroup for LegacyUserspaceInterruptGroup {
     fn trigger(&self, _index: InterruptIndex) -> Result<()> {
+        tracer::usdt!(irq_inject_legacy, self.irq);
         self.ioapic
             .lock()
             .unwrap()
@@ -0,0 +1,220 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Release of secrets by a Key Broker Service (KBS).
+//!
+//! Confidential deployments usually keep the keys of the encrypted disks, or
+//! the host data bound to the launch of a SEV-SNP guest, in a KBS which only
+//! releases them once the platform has been attested. The attestation and the
+//! HTTPS exchange with the KBS are left to a key broker client (KBC) agent
+//! listening on a UNIX socket, so that neither TLS nor the attestation
+//! protocols end up in the VMM, and the VMM doesn't need to spawn programs
+//! from within its sandbox.
+//!
+//! Each resource is fetched over its own connection: the VMM writes a single
+//! line holding a JSON [`Request`] and the agent answers with a single line
+//! holding a JSON [`Response`].
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::config::VmConfig;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No key broker service configured")]
+    NotConfigured,
+    #[error("Error connecting to the key broker client: {0}")]
+    Connect(#[source] io::Error),
+    #[error("Error communicating with the key broker client: {0}")]
+    Io(#[source] io::Error),
+    #[error("Invalid answer from the key broker client: {0}")]
+    InvalidResponse(#[source] serde_json::Error),
+    #[error("Key broker client failed to fetch {0}: {1}")]
+    Fetch(String, String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// What the VMM knows about the platform the secrets are released to.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Evidence {
+    /// Type of TEE, as named by the KBS protocol.
+    pub tee: &'static str,
+    /// Host data bound to the launch of a SEV-SNP guest, hex encoded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_data: Option<String>,
+}
+
+impl Evidence {
+    // Only the TEEs the VMM is built with are looked up in the config.
+    #[cfg_attr(
+        not(any(feature = "tdx", feature = "sev_snp")),
+        allow(unused_variables)
+    )]
+    pub fn new(config: &VmConfig) -> Self {
+        #[cfg(feature = "tdx")]
+        if config.is_tdx_enabled() {
+            return Evidence {
+                tee: "tdx",
+                host_data: None,
+            };
+        }
+        #[cfg(feature = "sev_snp")]
+        if config.is_sev_snp_enabled() {
+            return Evidence {
+                tee: "snp",
+                host_data: config.payload.as_ref().and_then(|p| p.host_data.clone()),
+            };
+        }
+        Evidence {
+            tee: "sample",
+            host_data: None,
+        }
+    }
+}
+
+/// Request sent to the KBC agent.
+#[derive(Debug, Serialize)]
+pub struct Request<'a> {
+    pub kbs_uri: &'a str,
+    /// Path of the resource in the KBS, e.g. "default/key/disk0".
+    pub resource: &'a str,
+    pub evidence: &'a Evidence,
+}
+
+/// Answer of the KBC agent, carrying either the hex encoded resource or the
+/// reason it couldn't be released.
+#[derive(Debug, Deserialize)]
+pub struct Response {
+    #[serde(default)]
+    pub data: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// A client fetching resources from a KBS on behalf of the VM.
+pub trait KeyBrokerClient: Send + Sync {
+    fn get_resource(&self, evidence: &Evidence, resource: &str) -> Result<Vec<u8>>;
+}
+
+/// Client relying on a KBC agent listening on a UNIX socket.
+pub struct SocketKbc {
+    socket: PathBuf,
+    kbs_uri: String,
+}
+
+impl SocketKbc {
+    pub fn new(socket: &Path, kbs_uri: &str) -> Self {
+        SocketKbc {
+            socket: socket.to_path_buf(),
+            kbs_uri: kbs_uri.to_owned(),
+        }
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl KeyBrokerClient for SocketKbc {
+    fn get_resource(&self, evidence: &Evidence, resource: &str) -> Result<Vec<u8>> {
+        info!(
+            "Fetching {} from key broker service {}",
+            resource, self.kbs_uri
+        );
+        let mut stream = UnixStream::connect(&self.socket).map_err(Error::Connect)?;
+
+        let mut request = serde_json::to_vec(&Request {
+            kbs_uri: &self.kbs_uri,
+            resource,
+            evidence,
+        })
+        .unwrap();
+        request.push(b'\n');
+        stream.write_all(&request).map_err(Error::Io)?;
+
+        let mut response = String::new();
+        BufReader::new(stream)
+            .read_line(&mut response)
+            .map_err(Error::Io)?;
+        let response: Response = serde_json::from_str(&response).map_err(Error::InvalidResponse)?;
+
+        match (response.data, response.error) {
+            (_, Some(e)) => Err(Error::Fetch(resource.to_owned(), e)),
+            (Some(data), None) => decode_hex(&data)
+                .filter(|d| !d.is_empty())
+                .ok_or_else(|| Error::Fetch(resource.to_owned(), "invalid resource".to_owned())),
+            (None, None) => Err(Error::Fetch(resource.to_owned(), "no resource".to_owned())),
+        }
+    }
+}
+
+/// Fetches a resource from the KBS configured for the VM.
+pub fn fetch(config: &VmConfig, resource: &str) -> Result<Vec<u8>> {
+    let platform = config.platform.as_ref().ok_or(Error::NotConfigured)?;
+    let (Some(kbs_uri), Some(kbc)) = (&platform.kbs_uri, &platform.kbc) else {
+        return Err(Error::NotConfigured);
+    };
+    SocketKbc::new(kbc, kbs_uri).get_resource(&Evidence::new(config), resource)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread::{self, JoinHandle};
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn serve(response: &'static str) -> (TempDir, PathBuf, JoinHandle<String>) {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let socket = dir.as_path().join("kbc.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+            request
+        });
+        (dir, socket, handle)
+    }
+
+    #[test]
+    fn test_socket_kbc() {
+        let evidence = Evidence {
+            tee: "snp",
+            host_data: None,
+        };
+
+        let (_dir, socket, handle) = serve("{\"data\":\"00ff\"}\n");
+        let kbc = SocketKbc::new(&socket, "https://kbs.example:8080");
+        assert_eq!(
+            kbc.get_resource(&evidence, "default/key/disk0").unwrap(),
+            [0x00, 0xff]
+        );
+        assert_eq!(
+            handle.join().unwrap(),
+            "{\"kbs_uri\":\"https://kbs.example:8080\",\"resource\":\"default/key/disk0\",\
+             \"evidence\":{\"tee\":\"snp\"}}\n"
+        );
+
+        let (_dir, socket, _) = serve("{\"error\":\"attestation failed\"}\n");
+        let kbc = SocketKbc::new(&socket, "https://kbs.example:8080");
+        assert!(matches!(
+            kbc.get_resource(&evidence, "default/key/disk0"),
+            Err(Error::Fetch(..))
+        ));
+    }
+}
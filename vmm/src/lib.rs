@@ -9,17 +9,23 @@ extern crate event_monitor;
 extern crate log;
 
 use crate::api::{
-    ApiRequest, ApiResponse, RequestHandler, VmInfoResponse, VmReceiveMigrationData,
-    VmSendMigrationData, VmmPingResponse,
+    ApiRequest, ApiResponse, InjectErrorType, RequestHandler, VmInfoResponse, VmInjectErrorData,
+    VmMigrateMemoryData, VmPauseData, VmReceiveMigrationData, VmSendMigrationData,
+    VmSetZonePolicyData, VmSnpReportData, VmTimeAdjustData, VmValidateConfigResponse,
+    VmmPingResponse,
 };
 use crate::config::{
-    add_to_config, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig,
-    UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig,
+    add_to_config, ConsolePortConfig, DeviceConfig, DiskConfig, FsConfig, NetConfig, OnResetLoop,
+    PmemConfig, RestartPolicy, RestoreConfig, UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig,
+    VsockForwardConfig,
 };
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::GuestDebuggable;
+use crate::device_manager::DeviceManagerError;
 use crate::landlock::Landlock;
-use crate::memory_manager::MemoryManager;
+use crate::log_filter::{set_log_filter, LogFilter, LogFilterError};
+use crate::mce::HwPoisonReceiver;
+use crate::memory_manager::{MemoryManager, MemoryRestoreMode};
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 use crate::migration::get_vm_snapshot;
 use crate::migration::{recv_vm_config, recv_vm_state};
@@ -29,6 +35,7 @@ use anyhow::anyhow;
 #[cfg(feature = "dbus_api")]
 use api::dbus::{DBusApiOptions, DBusApiShutdownChannels};
 use api::http::HttpApiHandle;
+use api::operations;
 use console_devices::{pre_create_console_devices, ConsoleInfo};
 use landlock::LandlockError;
 use libc::{tcsetattr, termios, EFD_NONBLOCK, SIGINT, SIGTERM, TCSANOW};
@@ -38,7 +45,7 @@ use seccompiler::{apply_filter, SeccompAction};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
 use signal_hook::iterator::{Handle, Signals};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::io;
 use std::io::{stdout, Read, Write};
@@ -50,7 +57,7 @@ use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::mpsc::{Receiver, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{result, thread};
 use thiserror::Error;
 use tracer::trace_scoped;
@@ -61,29 +68,48 @@ use vm_migration::{MigratableError, Pausable, Snapshot, Snapshottable, Transport
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::signal::unblock_signal;
 use vmm_sys_util::sock_ctrl_msg::ScmSocket;
+use vmm_sys_util::timerfd::TimerFd;
 
 mod acpi;
+pub mod agent_channel;
 pub mod api;
+mod auto_numa;
+pub mod builder;
 mod clone3;
 pub mod config;
+pub mod config_check;
 pub mod console_devices;
+mod console_log;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 mod coredump;
+mod counters_shm;
 pub mod cpu;
+pub mod crash_report;
 pub mod device_manager;
 pub mod device_tree;
 #[cfg(feature = "guest_debug")]
 mod gdb;
+mod hugetlbfs;
 #[cfg(feature = "igvm")]
-mod igvm;
+pub mod igvm;
 pub mod interrupt;
+pub mod kbs;
 pub mod landlock;
+pub mod log_filter;
+#[cfg(feature = "mdns")]
+mod mce;
+mod mdns;
 pub mod memory_manager;
 pub mod migration;
 mod pci_segment;
 pub mod seccomp_filters;
+pub mod seccomp_report;
+pub mod self_test;
 mod serial_manager;
 mod sigwinch_listener;
+#[cfg(feature = "tdx")]
+pub mod tdx_quote;
+mod userfaultfd;
 pub mod vm;
 pub mod vm_config;
 
@@ -117,6 +143,14 @@ pub enum Error {
     #[error("Error reading from EventFd: {0}")]
     EventFdRead(#[source] io::Error),
 
+    /// Cannot create TimerFd.
+    #[error("Error creating TimerFd: {0}")]
+    TimerFdCreate(#[source] io::Error),
+
+    /// Cannot read from TimerFd.
+    #[error("Error reading from TimerFd: {0}")]
+    TimerFdRead(#[source] io::Error),
+
     /// Cannot create epoll context.
     #[error("Error creating epoll context: {0}")]
     Epoll(#[source] io::Error),
@@ -217,6 +251,10 @@ pub enum EpollDispatch {
     Api = 2,
     ActivateVirtioDevices = 3,
     Debug = 4,
+    Pause = 5,
+    UnplugTimeout = 6,
+    GuestExit = 7,
+    HwPoison = 8,
     Unknown,
 }
 
@@ -229,6 +267,10 @@ impl From<u64> for EpollDispatch {
             2 => Api,
             3 => ActivateVirtioDevices,
             4 => Debug,
+            5 => Pause,
+            6 => UnplugTimeout,
+            7 => GuestExit,
+            8 => HwPoison,
             _ => Unknown,
         }
     }
@@ -312,6 +354,8 @@ impl Serialize for PciDeviceInfo {
 
 pub fn feature_list() -> Vec<String> {
     vec![
+        #[cfg(feature = "balloon")]
+        "balloon".to_string(),
         #[cfg(feature = "dbus_api")]
         "dbus_api".to_string(),
         #[cfg(feature = "dhat-heap")]
@@ -324,14 +368,24 @@ pub fn feature_list() -> Vec<String> {
         "io_uring".to_string(),
         #[cfg(feature = "kvm")]
         "kvm".to_string(),
+        #[cfg(feature = "mdns")]
+        "mdns".to_string(),
         #[cfg(feature = "mshv")]
         "mshv".to_string(),
+        #[cfg(feature = "rng")]
+        "rng".to_string(),
         #[cfg(feature = "sev_snp")]
         "sev_snp".to_string(),
         #[cfg(feature = "tdx")]
         "tdx".to_string(),
         #[cfg(feature = "tracing")]
         "tracing".to_string(),
+        #[cfg(feature = "vdpa")]
+        "vdpa".to_string(),
+        #[cfg(feature = "vsock")]
+        "vsock".to_string(),
+        #[cfg(feature = "watchdog")]
+        "watchdog".to_string(),
     ]
 }
 
@@ -532,7 +586,7 @@ pub fn start_vmm_thread(
 struct VmMigrationConfig {
     vm_config: Arc<Mutex<VmConfig>>,
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-    common_cpuid: Vec<hypervisor::arch::x86::CpuIdEntry>,
+    cpu_features: arch::CpuFeatureManifest,
     memory_manager_data: MemoryManagerSnapshotData,
 }
 
@@ -558,10 +612,74 @@ pub struct VmmThreadHandle {
     pub http_api_handle: Option<HttpApiHandle>,
 }
 
+/// Number of guest resets within [`RESET_LOOP_WINDOW`] considered as a loop.
+const RESET_LOOP_COUNT: usize = 5;
+const RESET_LOOP_WINDOW: Duration = Duration::from_secs(30);
+
+/// Detects a guest stuck in a reset loop, e.g. triple faulting early in the
+/// boot, from the times of its resets.
+#[derive(Default)]
+struct ResetLoopDetector {
+    resets: VecDeque<Instant>,
+}
+
+impl ResetLoopDetector {
+    /// Records a reset, returning the number of recent resets once they
+    /// amount to a loop.
+    fn record(&mut self, now: Instant) -> Option<usize> {
+        while self
+            .resets
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > RESET_LOOP_WINDOW)
+        {
+            self.resets.pop_front();
+        }
+        self.resets.push_back(now);
+
+        if self.resets.len() < RESET_LOOP_COUNT {
+            return None;
+        }
+        // Start over so that a persisting loop is reported periodically.
+        let count = self.resets.len();
+        self.resets.clear();
+        Some(count)
+    }
+}
+
+/// Times of the restarts of the VM, limiting them as set by the restart
+/// policy.
+#[derive(Default)]
+struct RestartHistory {
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartHistory {
+    /// Records a restart, returning whether it is within the limit of
+    /// `max_restarts` per `window`.
+    fn record(&mut self, now: Instant, max_restarts: u32, window: Duration) -> bool {
+        while self
+            .restarts
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > window)
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= max_restarts as usize {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+}
+
 pub struct Vmm {
     epoll: EpollContext,
     exit_evt: EventFd,
+    // Written by the VM as the guest shuts down or fails, distinct from
+    // exit_evt for the restart policy to only apply to the guest.
+    guest_exit_evt: EventFd,
     reset_evt: EventFd,
+    pause_evt: EventFd,
     api_evt: EventFd,
     #[cfg(feature = "guest_debug")]
     debug_evt: EventFd,
@@ -578,6 +696,10 @@ pub struct Vmm {
     original_termios_opt: Arc<Mutex<Option<termios>>>,
     console_resize_pipe: Option<Arc<File>>,
     console_info: Option<ConsoleInfo>,
+    unplug_timer: TimerFd,
+    reset_loop_detector: ResetLoopDetector,
+    restart_history: RestartHistory,
+    hwpoison: Option<HwPoisonReceiver>,
 }
 
 impl Vmm {
@@ -686,17 +808,28 @@ impl Vmm {
         exit_evt: EventFd,
     ) -> Result<Self> {
         let mut epoll = EpollContext::new().map_err(Error::Epoll)?;
+        let guest_exit_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
         let reset_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        let pause_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
         let activate_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        let unplug_timer = TimerFd::new().map_err(Error::TimerFdCreate)?;
 
         epoll
             .add_event(&exit_evt, EpollDispatch::Exit)
             .map_err(Error::Epoll)?;
 
+        epoll
+            .add_event(&guest_exit_evt, EpollDispatch::GuestExit)
+            .map_err(Error::Epoll)?;
+
         epoll
             .add_event(&reset_evt, EpollDispatch::Reset)
             .map_err(Error::Epoll)?;
 
+        epoll
+            .add_event(&pause_evt, EpollDispatch::Pause)
+            .map_err(Error::Epoll)?;
+
         epoll
             .add_event(&activate_evt, EpollDispatch::ActivateVirtioDevices)
             .map_err(Error::Epoll)?;
@@ -705,15 +838,34 @@ impl Vmm {
             .add_event(&api_evt, EpollDispatch::Api)
             .map_err(Error::Epoll)?;
 
+        epoll
+            .add_event(&unplug_timer, EpollDispatch::UnplugTimeout)
+            .map_err(Error::Epoll)?;
+
         #[cfg(feature = "guest_debug")]
         epoll
             .add_event(&debug_evt, EpollDispatch::Debug)
             .map_err(Error::Epoll)?;
 
+        let hwpoison = match HwPoisonReceiver::new() {
+            Ok(hwpoison) => {
+                epoll
+                    .add_event(&hwpoison, EpollDispatch::HwPoison)
+                    .map_err(Error::Epoll)?;
+                Some(hwpoison)
+            }
+            Err(e) => {
+                warn!("Host memory errors won't be reported to the guest: {}", e);
+                None
+            }
+        };
+
         Ok(Vmm {
             epoll,
             exit_evt,
+            guest_exit_evt,
             reset_evt,
+            pause_evt,
             api_evt,
             #[cfg(feature = "guest_debug")]
             debug_evt,
@@ -730,9 +882,53 @@ impl Vmm {
             original_termios_opt: Arc::new(Mutex::new(None)),
             console_resize_pipe: None,
             console_info: None,
+            unplug_timer,
+            reset_loop_detector: ResetLoopDetector::default(),
+            restart_history: RestartHistory::default(),
+            hwpoison,
         })
     }
 
+    // Arm the timer for the earliest pending device removal deadline, if any.
+    fn arm_unplug_timer(&mut self) {
+        let result = match self.vm.as_ref().and_then(|vm| vm.next_unplug_deadline()) {
+            Some(deadline) => {
+                // A zero duration would disarm the timer.
+                let timeout = deadline
+                    .saturating_duration_since(Instant::now())
+                    .max(Duration::from_millis(1));
+                self.unplug_timer.reset(timeout, None)
+            }
+            None => self.unplug_timer.clear(),
+        };
+
+        if let Err(e) = result {
+            error!("Error arming the device unplug timer: {}", e);
+        }
+    }
+
+    // Reads the config or state data following a request, checking the length
+    // sent by the source before allocating the buffer.
+    fn receive_data<T>(req: &Request, socket: &mut T) -> result::Result<Vec<u8>, MigratableError>
+    where
+        T: Read,
+    {
+        if req.length() > MAX_DATA_LENGTH {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "Data too large: {} bytes (maximum {})",
+                req.length(),
+                MAX_DATA_LENGTH
+            )));
+        }
+
+        let mut data = vec![0u8; req.length() as usize];
+        socket
+            .read_exact(&mut data)
+            .map_err(MigratableError::MigrateSocket)?;
+
+        Ok(data)
+    }
+
     fn vm_receive_config<T>(
         &mut self,
         req: &Request,
@@ -743,21 +939,30 @@ impl Vmm {
         T: Read + Write,
     {
         // Read in config data along with memory manager data
-        let mut data: Vec<u8> = Vec::new();
-        data.resize_with(req.length() as usize, Default::default);
-        socket
-            .read_exact(&mut data)
-            .map_err(MigratableError::MigrateSocket)?;
+        let data = Self::receive_data(req, socket)?;
 
         let vm_migration_config: VmMigrationConfig =
             serde_json::from_slice(&data).map_err(|e| {
                 MigratableError::MigrateReceive(anyhow!("Error deserialising config: {}", e))
             })?;
 
+        #[cfg(feature = "sev_snp")]
+        if vm_migration_config
+            .vm_config
+            .lock()
+            .unwrap()
+            .is_sev_snp_enabled()
+        {
+            Response::error().write_to(socket).ok();
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "Live migration of SEV-SNP guests is not supported"
+            )));
+        }
+
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        self.vm_check_cpuid_compatibility(
+        self.vm_check_cpu_compatibility(
             &vm_migration_config.vm_config,
-            &vm_migration_config.common_cpuid,
+            &vm_migration_config.cpu_features,
         )?;
 
         let config = vm_migration_config.vm_config.clone();
@@ -785,6 +990,8 @@ impl Vmm {
             false,
             #[cfg(feature = "sev_snp")]
             false,
+            #[cfg(feature = "sev_snp")]
+            false,
         )
         .map_err(|e| {
             MigratableError::MigrateReceive(anyhow!(
@@ -792,6 +999,9 @@ impl Vmm {
                 e
             ))
         })?;
+        Vm::configure_idle(&vm, &config.lock().unwrap().cpus).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error configuring idle vCPUs: {:?}", e))
+        })?;
 
         let phys_bits =
             vm::physical_bits(&self.hypervisor, config.lock().unwrap().cpus.max_phys_bits);
@@ -830,21 +1040,20 @@ impl Vmm {
         T: Read + Write,
     {
         // Read in state data
-        let mut data: Vec<u8> = Vec::new();
-        data.resize_with(req.length() as usize, Default::default);
-        socket
-            .read_exact(&mut data)
-            .map_err(MigratableError::MigrateSocket)?;
+        let data = Self::receive_data(req, socket)?;
         let snapshot: Snapshot = serde_json::from_slice(&data).map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error deserialising snapshot: {}", e))
         })?;
 
-        let exit_evt = self.exit_evt.try_clone().map_err(|e| {
+        let exit_evt = self.guest_exit_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning exit EventFd: {}", e))
         })?;
         let reset_evt = self.reset_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning reset EventFd: {}", e))
         })?;
+        let pause_evt = self.pause_evt.try_clone().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error cloning pause EventFd: {}", e))
+        })?;
         #[cfg(feature = "guest_debug")]
         let debug_evt = self.vm_debug_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning debug EventFd: {}", e))
@@ -861,6 +1070,7 @@ impl Vmm {
             hypervisor_vm,
             exit_evt,
             reset_evt,
+            pause_evt,
             #[cfg(feature = "guest_debug")]
             debug_evt,
             &self.seccomp_action,
@@ -898,6 +1108,14 @@ impl Vmm {
         T: Read + ReadVolatile + Write,
     {
         // Read table
+        let max_length = memory_manager.max_memory_range_table_length();
+        if req.length() > max_length {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "Memory range table too large: {} bytes (maximum {})",
+                req.length(),
+                max_length
+            )));
+        }
         let table = MemoryRangeTable::read_from(socket, req.length())?;
 
         // And then read the memory itself
@@ -918,6 +1136,20 @@ impl Vmm {
             .map(|s| s.into())
     }
 
+    // Abandons the migration if the API operation running it was cancelled.
+    fn check_migration_cancelled<T>(socket: &mut T) -> result::Result<(), MigratableError>
+    where
+        T: Read + Write,
+    {
+        if operations::cancel_requested() {
+            Request::abandon().write_to(socket)?;
+            Response::read_from(socket)?;
+            return Err(MigratableError::MigrateSend(anyhow!("Migration cancelled")));
+        }
+
+        Ok(())
+    }
+
     // Returns true if there were dirty pages to send
     fn vm_maybe_send_dirty_pages<T>(
         vm: &mut Vm,
@@ -968,7 +1200,7 @@ impl Vmm {
         // Send config
         let vm_config = vm.get_config();
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        let common_cpuid = {
+        let cpu_features = {
             #[cfg(feature = "tdx")]
             if vm_config.lock().unwrap().is_tdx_enabled() {
                 return Err(MigratableError::MigrateSend(anyhow!(
@@ -977,9 +1209,10 @@ impl Vmm {
             };
 
             let amx = vm_config.lock().unwrap().cpus.features.amx;
+            let cet = vm_config.lock().unwrap().cpus.features.cet;
             let phys_bits =
                 vm::physical_bits(&hypervisor, vm_config.lock().unwrap().cpus.max_phys_bits);
-            arch::generate_common_cpuid(
+            arch::CpuFeatureManifest::new(
                 &hypervisor,
                 &arch::CpuidConfig {
                     sgx_epc_sections: None,
@@ -988,10 +1221,12 @@ impl Vmm {
                     #[cfg(feature = "tdx")]
                     tdx: false,
                     amx,
+                    cet,
+                    msi_ext_dest_id: cpu::x2apic_ids_required(&vm_config.lock().unwrap().cpus),
                 },
             )
             .map_err(|e| {
-                MigratableError::MigrateSend(anyhow!("Error generating common cpuid': {:?}", e))
+                MigratableError::MigrateSend(anyhow!("Error generating CPU features: {:?}", e))
             })?
         };
 
@@ -1002,7 +1237,7 @@ impl Vmm {
         let vm_migration_config = VmMigrationConfig {
             vm_config,
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-            common_cpuid,
+            cpu_features,
             memory_manager_data: vm.memory_manager_data(),
         };
         let config_data = serde_json::to_vec(&vm_migration_config).unwrap();
@@ -1014,6 +1249,8 @@ impl Vmm {
             &mut socket,
             MigratableError::MigrateSend(anyhow!("Error during config migration")),
         )?;
+        operations::report_progress(5);
+        Self::check_migration_cancelled(&mut socket)?;
 
         // Let every Migratable object know about the migration being started.
         vm.start_migration()?;
@@ -1037,16 +1274,22 @@ impl Vmm {
                 &mut socket,
                 MigratableError::MigrateSend(anyhow!("Error during dirty memory migration")),
             )?;
+            operations::report_progress(60);
 
             // Try at most 5 passes of dirty memory sending
             const MAX_DIRTY_MIGRATIONS: usize = 5;
             for i in 0..MAX_DIRTY_MIGRATIONS {
+                Self::check_migration_cancelled(&mut socket)?;
                 info!("Dirty memory migration {} of {}", i, MAX_DIRTY_MIGRATIONS);
                 if !Self::vm_maybe_send_dirty_pages(vm, &mut socket)? {
                     break;
                 }
+                operations::report_progress(60 + 6 * (i as u8 + 1));
             }
 
+            // Past this point, the VM is paused until it runs on the destination.
+            Self::check_migration_cancelled(&mut socket)?;
+
             // Now pause VM
             vm.pause()?;
 
@@ -1056,6 +1299,7 @@ impl Vmm {
             // Stop logging dirty pages
             vm.stop_dirty_log()?;
         }
+        operations::report_progress(90);
         // Capture snapshot and send it
         let vm_snapshot = vm.snapshot()?;
         let snapshot_data = serde_json::to_vec(&vm_snapshot).unwrap();
@@ -1067,6 +1311,7 @@ impl Vmm {
             &mut socket,
             MigratableError::MigrateSend(anyhow!("Error during state migration")),
         )?;
+        operations::report_progress(95);
         // Complete the migration
         Request::complete().write_to(&mut socket)?;
         Response::read_from(&mut socket)?.ok_or_abandon(
@@ -1081,10 +1326,10 @@ impl Vmm {
     }
 
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-    fn vm_check_cpuid_compatibility(
+    fn vm_check_cpu_compatibility(
         &self,
         src_vm_config: &Arc<Mutex<VmConfig>>,
-        src_vm_cpuid: &[hypervisor::arch::x86::CpuIdEntry],
+        src_cpu_features: &arch::CpuFeatureManifest,
     ) -> result::Result<(), MigratableError> {
         #[cfg(feature = "tdx")]
         if src_vm_config.lock().unwrap().is_tdx_enabled() {
@@ -1093,13 +1338,13 @@ impl Vmm {
             )));
         };
 
-        // We check the `CPUID` compatibility of between the source vm and destination, which is
-        // mostly about feature compatibility and "topology/sgx" leaves are not relevant.
-        let dest_cpuid = &{
+        // We check the CPU features compatibility of between the source vm and destination, which
+        // is mostly about feature compatibility and "topology/sgx" leaves are not relevant.
+        let dest_cpu_features = &{
             let vm_config = &src_vm_config.lock().unwrap();
 
             let phys_bits = vm::physical_bits(&self.hypervisor, vm_config.cpus.max_phys_bits);
-            arch::generate_common_cpuid(
+            arch::CpuFeatureManifest::new(
                 &self.hypervisor.clone(),
                 &arch::CpuidConfig {
                     sgx_epc_sections: None,
@@ -1108,18 +1353,22 @@ impl Vmm {
                     #[cfg(feature = "tdx")]
                     tdx: false,
                     amx: vm_config.cpus.features.amx,
+                    cet: vm_config.cpus.features.cet,
+                    msi_ext_dest_id: cpu::x2apic_ids_required(&vm_config.cpus),
                 },
             )
             .map_err(|e| {
-                MigratableError::MigrateReceive(anyhow!("Error generating common cpuid: {:?}", e))
+                MigratableError::MigrateReceive(anyhow!("Error generating CPU features: {:?}", e))
             })?
         };
-        arch::CpuidFeatureEntry::check_cpuid_compatibility(src_vm_cpuid, dest_cpuid).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!(
-                "Error checking cpu feature compatibility': {:?}",
-                e
-            ))
-        })
+        src_cpu_features
+            .check_compatibility(dest_cpu_features)
+            .map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!(
+                    "Error checking cpu feature compatibility: {}",
+                    e
+                ))
+            })
     }
 
     fn control_loop(
@@ -1169,8 +1418,36 @@ impl Vmm {
                         info!("VM reset event");
                         // Consume the event.
                         self.reset_evt.read().map_err(Error::EventFdRead)?;
+                        if !self.apply_restart_policy(true) {
+                            self.vmm_shutdown().map_err(Error::VmmShutdown)?;
+
+                            break 'outer;
+                        }
+                        let reset_loop = self.reset_loop_detector.record(Instant::now());
+                        self.vm_reboot().map_err(Error::VmReboot)?;
+                        if let Some(resets) = reset_loop {
+                            self.report_reset_loop(resets);
+                        }
+                    }
+                    EpollDispatch::GuestExit => {
+                        info!("VM guest exit event");
+                        // Consume the event.
+                        self.guest_exit_evt.read().map_err(Error::EventFdRead)?;
+                        if !self.apply_restart_policy(false) {
+                            self.vmm_shutdown().map_err(Error::VmmShutdown)?;
+
+                            break 'outer;
+                        }
                         self.vm_reboot().map_err(Error::VmReboot)?;
                     }
+                    EpollDispatch::Pause => {
+                        info!("VM pause event");
+                        // Consume the event.
+                        self.pause_evt.read().map_err(Error::EventFdRead)?;
+                        if let Err(e) = self.vm_pause(VmPauseData::default()) {
+                            error!("Error pausing the VM: {:?}", e);
+                        }
+                    }
                     EpollDispatch::ActivateVirtioDevices => {
                         if let Some(ref vm) = self.vm {
                             let count = self.activate_evt.read().map_err(Error::EventFdRead)?;
@@ -1182,6 +1459,33 @@ impl Vmm {
                                 .map_err(Error::ActivateVirtioDevices)?;
                         }
                     }
+                    EpollDispatch::UnplugTimeout => {
+                        // Consume the event.
+                        self.unplug_timer.wait().map_err(Error::TimerFdRead)?;
+                        if let Some(ref mut vm) = self.vm {
+                            if let Err(e) = vm.process_unplug_timeouts() {
+                                error!("Error handling device unplug timeouts: {:?}", e);
+                            }
+                        }
+                        self.arm_unplug_timer();
+                    }
+                    EpollDispatch::HwPoison => {
+                        let pages = match self.hwpoison.as_mut().unwrap().pages() {
+                            Ok(pages) => pages,
+                            Err(e) => {
+                                error!("Error reading the host memory errors: {}", e);
+                                continue;
+                            }
+                        };
+                        for page in pages {
+                            warn!("Host memory error at {:#x}", page.address);
+                            if let Some(ref vm) = self.vm {
+                                if let Err(e) = vm.report_host_memory_error(&page) {
+                                    error!("Error reporting the host memory error: {:?}", e);
+                                }
+                            }
+                        }
+                    }
                     EpollDispatch::Api => {
                         // Consume the events.
                         for _ in 0..self.api_evt.read().map_err(Error::EventFdRead)? {
@@ -1283,8 +1587,12 @@ impl RequestHandler for Vmm {
 
             // Create a new VM if we don't have one yet.
             if self.vm.is_none() {
-                let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
+                let exit_evt = self
+                    .guest_exit_evt
+                    .try_clone()
+                    .map_err(VmError::EventFdClone)?;
                 let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
+                let pause_evt = self.pause_evt.try_clone().map_err(VmError::EventFdClone)?;
                 #[cfg(feature = "guest_debug")]
                 let vm_debug_evt = self
                     .vm_debug_evt
@@ -1300,6 +1608,7 @@ impl RequestHandler for Vmm {
                         Arc::clone(vm_config),
                         exit_evt,
                         reset_evt,
+                        pause_evt,
                         #[cfg(feature = "guest_debug")]
                         vm_debug_evt,
                         &self.seccomp_action,
@@ -1311,6 +1620,7 @@ impl RequestHandler for Vmm {
                         None,
                         None,
                         None,
+                        MemoryRestoreMode::Copy,
                     )?;
 
                     self.vm = Some(vm);
@@ -1331,9 +1641,18 @@ impl RequestHandler for Vmm {
         r
     }
 
-    fn vm_pause(&mut self) -> result::Result<(), VmError> {
+    fn vm_pause(&mut self, pause_data: VmPauseData) -> result::Result<Option<Vec<u8>>, VmError> {
         if let Some(ref mut vm) = self.vm {
-            vm.pause().map_err(VmError::Pause)
+            let quiesce_timeout = pause_data
+                .quiesce_timeout
+                .map(Duration::from_millis)
+                .unwrap_or(vm::DEFAULT_QUIESCE_TIMEOUT);
+            let report = vm
+                .pause_with_timeout(quiesce_timeout)
+                .map_err(VmError::Pause)?;
+            serde_json::to_vec(&report)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
         } else {
             Err(VmError::VmNotRunning)
         }
@@ -1354,6 +1673,7 @@ impl RequestHandler for Vmm {
             vm.snapshot()
                 .map_err(VmError::Snapshot)
                 .and_then(|snapshot| {
+                    operations::report_progress(10);
                     vm.send(&snapshot, destination_url)
                         .map_err(VmError::SnapshotSend)
                 })
@@ -1395,13 +1715,28 @@ impl RequestHandler for Vmm {
             }
         }
 
+        let memory_restore_mode = if restore_cfg.lazy {
+            MemoryRestoreMode::OnDemand
+        } else if restore_cfg.clone {
+            MemoryRestoreMode::CopyOnWrite
+        } else {
+            MemoryRestoreMode::Copy
+        };
+
         let snapshot = recv_vm_state(source_url).map_err(VmError::Restore)?;
+        operations::report_progress(10);
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
         let vm_snapshot = get_vm_snapshot(&snapshot).map_err(VmError::Restore)?;
 
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        self.vm_check_cpuid_compatibility(&vm_config, &vm_snapshot.common_cpuid)
-            .map_err(VmError::Restore)?;
+        self.vm_check_cpu_compatibility(
+            &vm_config,
+            &arch::CpuFeatureManifest {
+                cpuid: vm_snapshot.common_cpuid.clone(),
+                msrs: Vec::new(),
+            },
+        )
+        .map_err(VmError::Restore)?;
 
         self.vm_config = Some(Arc::clone(&vm_config));
 
@@ -1411,8 +1746,12 @@ impl RequestHandler for Vmm {
                 Some(pre_create_console_devices(self).map_err(VmError::CreateConsoleDevices)?);
         }
 
-        let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let exit_evt = self
+            .guest_exit_evt
+            .try_clone()
+            .map_err(VmError::EventFdClone)?;
         let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let pause_evt = self.pause_evt.try_clone().map_err(VmError::EventFdClone)?;
         #[cfg(feature = "guest_debug")]
         let debug_evt = self
             .vm_debug_evt
@@ -1427,6 +1766,7 @@ impl RequestHandler for Vmm {
             vm_config,
             exit_evt,
             reset_evt,
+            pause_evt,
             #[cfg(feature = "guest_debug")]
             debug_evt,
             &self.seccomp_action,
@@ -1438,8 +1778,11 @@ impl RequestHandler for Vmm {
             Some(snapshot),
             Some(source_url),
             Some(restore_cfg.prefault),
+            memory_restore_mode,
         )?;
         self.vm = Some(vm);
+        // Creating the VM restored its memory.
+        operations::report_progress(80);
 
         if self
             .vm_config
@@ -1455,7 +1798,15 @@ impl RequestHandler for Vmm {
 
         // Now we can restore the rest of the VM.
         if let Some(ref mut vm) = self.vm {
-            vm.restore()
+            vm.restore()?;
+
+            // Let the guest know it is running as a new instance, since other
+            // VMs might be restored from the same snapshot.
+            if restore_cfg.clone {
+                vm.update_vmgenid()?;
+            }
+
+            Ok(())
         } else {
             Err(VmError::VmNotCreated)
         }
@@ -1503,8 +1854,12 @@ impl RequestHandler for Vmm {
         // so that the closed FD #s are not reused.
         let _ = self.console_info.take();
 
-        let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let exit_evt = self
+            .guest_exit_evt
+            .try_clone()
+            .map_err(VmError::EventFdClone)?;
         let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let pause_evt = self.pause_evt.try_clone().map_err(VmError::EventFdClone)?;
         #[cfg(feature = "guest_debug")]
         let debug_evt = self
             .vm_debug_evt
@@ -1530,6 +1885,7 @@ impl RequestHandler for Vmm {
             config,
             exit_evt,
             reset_evt,
+            pause_evt,
             #[cfg(feature = "guest_debug")]
             debug_evt,
             &self.seccomp_action,
@@ -1541,6 +1897,7 @@ impl RequestHandler for Vmm {
             None,
             None,
             None,
+            MemoryRestoreMode::Copy,
         )?;
 
         // And we boot it
@@ -1553,6 +1910,86 @@ impl RequestHandler for Vmm {
         Ok(())
     }
 
+    /// Decides whether the VM is restarted as the guest resets (`reset`) or
+    /// shuts down. Without a restart policy, a reset restarts the VM while a
+    /// shutdown stops the VMM.
+    fn apply_restart_policy(&mut self, reset: bool) -> bool {
+        let failed = self.vm.as_ref().is_some_and(|vm| vm.failed());
+        let cause = if failed {
+            "failure"
+        } else if reset {
+            "reset"
+        } else {
+            "shutdown"
+        };
+
+        let restart_policy = self
+            .vm_config
+            .as_ref()
+            .and_then(|config| config.lock().unwrap().restart_policy.clone());
+        let mut restart = match restart_policy.as_ref().map(|config| config.policy) {
+            None => reset,
+            Some(RestartPolicy::Always) => true,
+            Some(RestartPolicy::OnFailure) => reset || failed,
+            Some(RestartPolicy::Never) => false,
+        };
+
+        if let Some(max_reboots) = restart_policy
+            .as_ref()
+            .and_then(|config| config.max_reboots)
+        {
+            let window = Duration::from_secs(restart_policy.as_ref().unwrap().window);
+            if restart
+                && !self
+                    .restart_history
+                    .record(Instant::now(), max_reboots, window)
+            {
+                warn!(
+                    "VM restarted {} times within {} seconds, stopping it",
+                    max_reboots,
+                    window.as_secs()
+                );
+                event!(
+                    "vm",
+                    "restart-limit-reached",
+                    "max_reboots",
+                    max_reboots.to_string(),
+                    "window",
+                    window.as_secs().to_string()
+                );
+                restart = false;
+            }
+        }
+
+        let action = if restart { "restart" } else { "stop" };
+        info!("Guest {}, the VM is going to {}", cause, action);
+        event!("vm", "restart-policy", "cause", cause, "action", action);
+
+        restart
+    }
+
+    fn report_reset_loop(&mut self, resets: usize) {
+        warn!(
+            "Guest reset {} times within {} seconds, it may be stuck in a reset loop",
+            resets,
+            RESET_LOOP_WINDOW.as_secs()
+        );
+        event!("vm", "reset-loop", "resets", resets.to_string());
+
+        let on_reset_loop = self
+            .vm_config
+            .as_ref()
+            .and_then(|config| config.lock().unwrap().platform.clone())
+            .map(|platform| platform.on_reset_loop)
+            .unwrap_or_default();
+        if on_reset_loop == OnResetLoop::Pause {
+            info!("Pausing the VM stuck in a reset loop");
+            if let Err(e) = self.vm_pause(VmPauseData::default()) {
+                error!("Error pausing the VM: {:?}", e);
+            }
+        }
+    }
+
     fn vm_info(&self) -> result::Result<VmInfoResponse, VmError> {
         match &self.vm_config {
             Some(config) => {
@@ -1595,6 +2032,12 @@ impl RequestHandler for Vmm {
         }
     }
 
+    fn vm_validate_config(&self, config: &VmConfig) -> VmValidateConfigResponse {
+        VmValidateConfigResponse {
+            problems: config_check::check(config, self.hypervisor.as_ref()),
+        }
+    }
+
     fn vm_delete(&mut self) -> result::Result<(), VmError> {
         if self.vm_config.is_none() {
             return Ok(());
@@ -1618,9 +2061,16 @@ impl RequestHandler for Vmm {
         Ok(())
     }
 
+    fn vmm_set_log_level(&mut self, filter: &str) -> result::Result<(), LogFilterError> {
+        let filter = LogFilter::parse(filter, log::LevelFilter::Warn)?;
+        info!("Setting the log filter to {:?}", filter);
+        set_log_filter(filter);
+        Ok(())
+    }
+
     fn vm_resize(
         &mut self,
-        desired_vcpus: Option<u8>,
+        desired_vcpus: Option<u32>,
         desired_ram: Option<u64>,
         desired_balloon: Option<u64>,
     ) -> result::Result<(), VmError> {
@@ -1736,12 +2186,18 @@ impl RequestHandler for Vmm {
         }
     }
 
-    fn vm_remove_device(&mut self, id: String) -> result::Result<(), VmError> {
+    fn vm_remove_device(
+        &mut self,
+        id: String,
+        timeout: Option<Duration>,
+        surprise_removal: bool,
+    ) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
-            if let Err(e) = vm.remove_device(id) {
+            if let Err(e) = vm.remove_device(id, timeout, surprise_removal) {
                 error!("Error when removing device from the VM: {:?}", e);
                 Err(e)
             } else {
+                self.arm_unplug_timer();
                 Ok(())
             }
         } else if let Some(ref config) = self.vm_config {
@@ -1756,6 +2212,42 @@ impl RequestHandler for Vmm {
         }
     }
 
+    fn vm_unplug_status(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref vm) = self.vm {
+            serde_json::to_vec(&vm.unplug_status())
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_block_trace(&mut self, id: String, path: Option<PathBuf>) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            if let Err(e) = vm.trace_block_device(&id, path) {
+                error!("Error when tracing block device: {:?}", e);
+                Err(e)
+            } else {
+                Ok(())
+            }
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_keep_disk(&mut self, id: String, path: PathBuf) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            if let Err(e) = vm.keep_transient_disk(&id, path) {
+                error!("Error when keeping transient disk: {:?}", e);
+                Err(e)
+            } else {
+                Ok(())
+            }
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_add_disk(&mut self, disk_cfg: DiskConfig) -> result::Result<Option<Vec<u8>>, VmError> {
         self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
 
@@ -1860,6 +2352,47 @@ impl RequestHandler for Vmm {
         }
     }
 
+    fn vm_swap_net(&mut self, mut net_cfg: NetConfig) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            {
+                // Only the backend changes, what the guest sees of the device
+                // is kept from its current configuration.
+                let mut config = self.vm_config.as_ref().unwrap().lock().unwrap().clone();
+                let current_cfg = config
+                    .net
+                    .iter_mut()
+                    .flatten()
+                    .find(|n| n.id.is_some() && n.id == net_cfg.id)
+                    .ok_or_else(|| {
+                        VmError::DeviceManager(DeviceManagerError::UnknownDeviceId(
+                            net_cfg.id.clone().unwrap_or_default(),
+                        ))
+                    })?;
+                net_cfg.mac = current_cfg.mac;
+                net_cfg.mtu = current_cfg.mtu;
+                net_cfg.num_queues = current_cfg.num_queues;
+                net_cfg.queue_size = current_cfg.queue_size;
+                net_cfg.iommu = current_cfg.iommu;
+                net_cfg.pci_segment = current_cfg.pci_segment;
+                net_cfg.offload_tso = current_cfg.offload_tso;
+                net_cfg.offload_ufo = current_cfg.offload_ufo;
+                net_cfg.offload_csum = current_cfg.offload_csum;
+                net_cfg.standby = current_cfg.standby;
+
+                // Validate the configuration change in a cloned configuration
+                *current_cfg = net_cfg.clone();
+                config.validate().map_err(VmError::ConfigValidation)?;
+            }
+
+            vm.swap_net(net_cfg).map_err(|e| {
+                error!("Error when swapping the network device backend: {:?}", e);
+                e
+            })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_add_vdpa(&mut self, vdpa_cfg: VdpaConfig) -> result::Result<Option<Vec<u8>>, VmError> {
         self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
 
@@ -1886,6 +2419,35 @@ impl RequestHandler for Vmm {
         }
     }
 
+    fn vm_add_console(
+        &mut self,
+        console_port_cfg: ConsolePortConfig,
+    ) -> result::Result<Option<Vec<u8>>, VmError> {
+        self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        {
+            // Validate the configuration change in a cloned configuration
+            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap().clone();
+            add_to_config(&mut config.console_ports, console_port_cfg.clone());
+            config.validate().map_err(VmError::ConfigValidation)?;
+        }
+
+        if let Some(ref mut vm) = self.vm {
+            let info = vm.add_console(console_port_cfg).map_err(|e| {
+                error!("Error when adding new console port to the VM: {:?}", e);
+                e
+            })?;
+            serde_json::to_vec(&info)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            // Update VmConfig by adding the new device.
+            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
+            add_to_config(&mut config.console_ports, console_port_cfg);
+            Ok(None)
+        }
+    }
+
     fn vm_add_vsock(&mut self, vsock_cfg: VsockConfig) -> result::Result<Option<Vec<u8>>, VmError> {
         self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
 
@@ -1917,6 +2479,61 @@ impl RequestHandler for Vmm {
         }
     }
 
+    fn vm_add_vsock_forward(&mut self, forward: VsockForwardConfig) -> result::Result<(), VmError> {
+        self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        {
+            // Validate the configuration change in a cloned configuration
+            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap().clone();
+            let vsock = config.vsock.as_mut().ok_or(VmError::NoVsockDevice)?;
+            vsock.forwards.push(forward.clone());
+            config.validate().map_err(VmError::ConfigValidation)?;
+        }
+
+        if let Some(ref mut vm) = self.vm {
+            vm.add_vsock_forward(forward).map_err(|e| {
+                error!("Error when adding vsock forwarding rule: {:?}", e);
+                e
+            })
+        } else {
+            // Update VmConfig by adding the new rule.
+            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
+            config.vsock.as_mut().unwrap().forwards.push(forward);
+            Ok(())
+        }
+    }
+
+    fn vm_remove_vsock_forward(
+        &mut self,
+        forward: VsockForwardConfig,
+    ) -> result::Result<(), VmError> {
+        self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        {
+            let config = self.vm_config.as_ref().unwrap().lock().unwrap();
+            let vsock = config.vsock.as_ref().ok_or(VmError::NoVsockDevice)?;
+            if !vsock.forwards.iter().any(|f| f.conflicts_with(&forward)) {
+                return Err(VmError::UnknownVsockForward(forward.to_string()));
+            }
+        }
+
+        if let Some(ref mut vm) = self.vm {
+            vm.remove_vsock_forward(forward).map_err(|e| {
+                error!("Error when removing vsock forwarding rule: {:?}", e);
+                e
+            })
+        } else {
+            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
+            config
+                .vsock
+                .as_mut()
+                .unwrap()
+                .forwards
+                .retain(|f| !f.conflicts_with(&forward));
+            Ok(())
+        }
+    }
+
     fn vm_counters(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
         if let Some(ref mut vm) = self.vm {
             let info = vm.counters().map_err(|e| {
@@ -1931,6 +2548,81 @@ impl RequestHandler for Vmm {
         }
     }
 
+    fn vm_counters_shm(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref mut vm) = self.vm {
+            let info = vm.counters_shm().map_err(|e| {
+                error!(
+                    "Error publishing the counters through shared memory: {:?}",
+                    e
+                );
+                e
+            })?;
+            serde_json::to_vec(&info)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_launch_measurement(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
+        let vm_config = self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        #[cfg(feature = "igvm")]
+        {
+            let vm_config = vm_config.lock().unwrap();
+            let igvm_path = vm_config
+                .payload
+                .as_ref()
+                .and_then(|payload| payload.igvm.clone())
+                .ok_or(VmError::MissingIgvmPayload)?;
+            #[cfg(feature = "sev_snp")]
+            let lazy_unmeasured = vm_config
+                .sev_snp
+                .as_ref()
+                .is_some_and(|sev_snp| sev_snp.lazy_unmeasured);
+            #[cfg(not(feature = "sev_snp"))]
+            let lazy_unmeasured = false;
+            drop(vm_config);
+            let igvm = File::open(igvm_path).map_err(VmError::IgvmFile)?;
+            let measurement =
+                crate::igvm::measurement::snp_launch_measurement(&igvm, lazy_unmeasured)
+                    .map_err(VmError::LaunchMeasurement)?;
+            serde_json::to_vec(&measurement)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        }
+        #[cfg(not(feature = "igvm"))]
+        {
+            let _ = vm_config;
+            Err(VmError::MissingIgvmPayload)
+        }
+    }
+
+    fn vm_snp_report(
+        &mut self,
+        snp_report_data: VmSnpReportData,
+    ) -> result::Result<Option<Vec<u8>>, VmError> {
+        let hex = snp_report_data.report_data.unwrap_or_default();
+        if hex.len() > 128 || hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(VmError::InvalidReportData);
+        }
+        let mut report_data = [0u8; 64];
+        for (i, byte) in report_data.iter_mut().take(hex.len() / 2).enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                .map_err(|_| VmError::InvalidReportData)?;
+        }
+
+        if let Some(ref vm) = self.vm {
+            let report = vm.snp_report(&report_data, snp_report_data.port.as_deref())?;
+            serde_json::to_vec(&report)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_power_button(&mut self) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
             vm.power_button()
@@ -1947,6 +2639,86 @@ impl RequestHandler for Vmm {
         }
     }
 
+    fn vm_time_info(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref vm) = self.vm {
+            let info = vm.time_info().map_err(|e| {
+                error!("Error when getting time information from the VM: {:?}", e);
+                e
+            })?;
+            serde_json::to_vec(&info)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_time_adjust(
+        &mut self,
+        time_adjust_data: VmTimeAdjustData,
+    ) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            vm.adjust_time(time_adjust_data.delta_ns).map_err(|e| {
+                error!("Error when adjusting the VM clock: {:?}", e);
+                e
+            })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_inject_error(
+        &mut self,
+        inject_error_data: VmInjectErrorData,
+    ) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            match inject_error_data.error_type {
+                InjectErrorType::Memory => vm
+                    .inject_memory_error(inject_error_data.gpa, inject_error_data.severity)
+                    .map_err(|e| {
+                        error!("Error when injecting the memory error: {:?}", e);
+                        e
+                    }),
+            }
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_migrate_memory(
+        &mut self,
+        migrate_memory_data: VmMigrateMemoryData,
+    ) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            vm.migrate_memory(migrate_memory_data.host_numa_node)
+                .map_err(|e| {
+                    error!("Error when moving the guest memory: {:?}", e);
+                    e
+                })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_set_zone_policy(
+        &mut self,
+        set_zone_policy_data: VmSetZonePolicyData,
+    ) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            vm.set_zone_policy(
+                &set_zone_policy_data.id,
+                set_zone_policy_data.host_numa_node,
+                set_zone_policy_data.policy,
+            )
+            .map_err(|e| {
+                error!("Error when changing the memory zone policy: {:?}", e);
+                e
+            })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_receive_migration(
         &mut self,
         receive_data_migration: VmReceiveMigrationData,
@@ -1993,6 +2765,7 @@ impl RequestHandler for Vmm {
                         &mut socket,
                         existing_memory_files.take(),
                     )?);
+                    operations::report_progress(10);
                 }
                 Command::State => {
                     info!("State Command Received");
@@ -2004,6 +2777,7 @@ impl RequestHandler for Vmm {
                     }
                     if let Some(mm) = memory_manager.take() {
                         self.vm_receive_state(&req, &mut socket, mm)?;
+                        operations::report_progress(90);
                     } else {
                         warn!("Configuration not sent yet");
                         Response::error().write_to(&mut socket)?;
@@ -2099,6 +2873,21 @@ impl RequestHandler for Vmm {
             )));
         }
 
+        #[cfg(feature = "sev_snp")]
+        if self
+            .vm_config
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .is_sev_snp_enabled()
+            && !self.hypervisor.sev_snp_migration_supported()
+        {
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Live migration of SEV-SNP guests is not supported by the hypervisor"
+            )));
+        }
+
         if let Some(vm) = self.vm.as_mut() {
             Self::send_migration(
                 vm,
@@ -2165,6 +2954,49 @@ mod unit_tests {
         .unwrap()
     }
 
+    #[test]
+    fn test_restart_history() {
+        let mut history = RestartHistory::default();
+        let start = Instant::now();
+        let window = Duration::from_secs(60);
+
+        for i in 0..3 {
+            assert!(history.record(start + Duration::from_secs(i), 3, window));
+        }
+        // The limit is reached until the first restart leaves the window.
+        assert!(!history.record(start + Duration::from_secs(10), 3, window));
+        assert!(!history.record(start + window, 3, window));
+        assert!(history.record(start + window + Duration::from_secs(1), 3, window));
+
+        // No restart is allowed at all with a limit of 0.
+        assert!(!RestartHistory::default().record(start, 0, window));
+    }
+
+    #[test]
+    fn test_reset_loop_detector() {
+        let mut detector = ResetLoopDetector::default();
+        let start = Instant::now();
+
+        // Resets spread over time aren't a loop.
+        for i in 0..RESET_LOOP_COUNT as u32 * 2 {
+            assert_eq!(detector.record(start + RESET_LOOP_WINDOW * i), None);
+        }
+
+        let start = start + RESET_LOOP_WINDOW * 100;
+        for i in 0..RESET_LOOP_COUNT as u32 - 1 {
+            assert_eq!(detector.record(start + Duration::from_secs(i.into())), None);
+        }
+        assert_eq!(
+            detector.record(start + Duration::from_secs(RESET_LOOP_COUNT as u64)),
+            Some(RESET_LOOP_COUNT)
+        );
+        // The detection starts over once a loop has been reported.
+        assert_eq!(
+            detector.record(start + Duration::from_secs(RESET_LOOP_COUNT as u64 + 1)),
+            None
+        );
+    }
+
     fn create_dummy_vm_config() -> Arc<Mutex<VmConfig>> {
         Arc::new(Mutex::new(VmConfig {
             cpus: CpusConfig {
@@ -2175,6 +3007,9 @@ mod unit_tests {
                 max_phys_bits: 46,
                 affinity: None,
                 features: config::CpuFeatures::default(),
+                halt_poll_ns: None,
+                #[cfg(target_arch = "x86_64")]
+                idle_poll: false,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
@@ -2188,6 +3023,8 @@ mod unit_tests {
                 prefault: false,
                 zones: None,
                 thp: true,
+                auto_numa: false,
+                coredump: false,
             },
             payload: Some(PayloadConfig {
                 kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -2196,6 +3033,8 @@ mod unit_tests {
                 initramfs: None,
                 #[cfg(feature = "igvm")]
                 igvm: None,
+                #[cfg(feature = "igvm")]
+                igvm_compatibility_mask: None,
                 #[cfg(feature = "sev_snp")]
                 host_data: None,
             }),
@@ -2208,19 +3047,25 @@ mod unit_tests {
             },
             balloon: None,
             fs: None,
+            gpu: None,
             pmem: None,
             serial: ConsoleConfig {
                 file: None,
                 mode: ConsoleOutputMode::Null,
                 iommu: false,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             },
             console: ConsoleConfig {
                 file: None,
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
                 socket: None,
+                tcp: None,
+                log_socket: None,
             },
+            console_ports: None,
             #[cfg(target_arch = "x86_64")]
             debug_console: DebugConsoleConfig::default(),
             devices: None,
@@ -2233,13 +3078,20 @@ mod unit_tests {
             iommu: false,
             #[cfg(target_arch = "x86_64")]
             sgx_epc: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays: None,
             numa: None,
             watchdog: false,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             pci_segments: None,
             platform: None,
+            #[cfg(feature = "sev_snp")]
+            sev_snp: None,
             tpm: None,
+            restart_policy: None,
+            #[cfg(feature = "mdns")]
+            mdns: None,
             preserved_fds: None,
             landlock_enable: false,
             landlock_rules: None,
@@ -2591,6 +3443,54 @@ mod unit_tests {
         );
     }
 
+    #[test]
+    fn test_vmm_vm_cold_add_console() {
+        let mut vmm = create_dummy_vmm();
+        let console_port_config = ConsolePortConfig::parse("file=/tmp/console.log").unwrap();
+
+        assert!(matches!(
+            vmm.vm_add_console(console_port_config.clone()),
+            Err(VmError::VmNotCreated)
+        ));
+
+        let _ = vmm.vm_create(create_dummy_vm_config());
+        assert!(vmm
+            .vm_config
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .console_ports
+            .is_none());
+
+        let result = vmm.vm_add_console(console_port_config.clone());
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+        assert_eq!(
+            vmm.vm_config
+                .as_ref()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .console_ports
+                .clone()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            vmm.vm_config
+                .as_ref()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .console_ports
+                .clone()
+                .unwrap()[0],
+            console_port_config
+        );
+    }
+
     #[test]
     fn test_vmm_vm_cold_add_vsock() {
         let mut vmm = create_dummy_vmm();
@@ -0,0 +1,167 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Per module filtering of the log records.
+//!
+//! A filter is a comma separated list of directives, each of them being either
+//! a bare level applying to all the modules, or `<module>=<level>`:
+//!
+//! ```text
+//! info,vmm::device_manager=debug,virtio=warn
+//! ```
+//!
+//! A directive applies to the records whose target starts with its module,
+//! the longest matching module winning. The records not matched by any
+//! directive are filtered with the default level. The filter in use can be
+//! replaced at any time, which lets the verbosity of a running VMM be raised
+//! while investigating an issue.
+
+use log::{LevelFilter, Metadata};
+use std::str::FromStr;
+use std::sync::RwLock;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LogFilterError {
+    #[error("Invalid log level in directive \"{0}\"")]
+    InvalidLevel(String),
+    #[error("Missing module in directive \"{0}\"")]
+    MissingModule(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    // Sorted by decreasing module length, for the longest match to be found first
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    pub const fn new(default: LevelFilter) -> Self {
+        LogFilter {
+            default,
+            directives: Vec::new(),
+        }
+    }
+
+    /// Parses `filter`, the modules without a directive being filtered with
+    /// `default` unless the filter sets another default level.
+    pub fn parse(filter: &str, default: LevelFilter) -> Result<Self, LogFilterError> {
+        let mut log_filter = LogFilter::new(default);
+
+        for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim();
+                    if module.is_empty() {
+                        return Err(LogFilterError::MissingModule(directive.to_string()));
+                    }
+                    let level = LevelFilter::from_str(level.trim())
+                        .map_err(|_| LogFilterError::InvalidLevel(directive.to_string()))?;
+                    log_filter.directives.retain(|(m, _)| m != module);
+                    log_filter.directives.push((module.to_string(), level));
+                }
+                None => {
+                    log_filter.default = LevelFilter::from_str(directive)
+                        .map_err(|_| LogFilterError::InvalidLevel(directive.to_string()))?;
+                }
+            }
+        }
+        log_filter
+            .directives
+            .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+
+        Ok(log_filter)
+    }
+
+    /// Level the records of `target` are filtered with.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|(module, _)| target.starts_with(module.as_str()))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    /// Most verbose level of the filter.
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, LevelFilter::max)
+    }
+
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+}
+
+static LOG_FILTER: RwLock<LogFilter> = RwLock::new(LogFilter::new(LevelFilter::Warn));
+
+/// Replaces the filter applied to the log records.
+pub fn set_log_filter(filter: LogFilter) {
+    log::set_max_level(filter.max_level());
+    *LOG_FILTER.write().unwrap() = filter;
+}
+
+/// Whether the record described by `metadata` passes the filter in use.
+pub fn log_enabled(metadata: &Metadata) -> bool {
+    LOG_FILTER.read().unwrap().enabled(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn enabled(filter: &LogFilter, target: &str, level: Level) -> bool {
+        filter.enabled(&Metadata::builder().target(target).level(level).build())
+    }
+
+    #[test]
+    fn test_log_filter_parse() {
+        let filter = LogFilter::parse("", LevelFilter::Warn).unwrap();
+        assert_eq!(filter, LogFilter::new(LevelFilter::Warn));
+
+        let filter = LogFilter::parse("debug", LevelFilter::Warn).unwrap();
+        assert_eq!(filter, LogFilter::new(LevelFilter::Debug));
+
+        let filter = LogFilter::parse(
+            "virtio=warn, vmm::device_manager=debug,info,virtio=error",
+            LevelFilter::Warn,
+        )
+        .unwrap();
+        assert_eq!(filter.default, LevelFilter::Info);
+        assert_eq!(
+            filter.directives,
+            vec![
+                ("vmm::device_manager".to_string(), LevelFilter::Debug),
+                ("virtio".to_string(), LevelFilter::Error),
+            ]
+        );
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+
+        assert!(LogFilter::parse("verbose", LevelFilter::Warn).is_err());
+        assert!(LogFilter::parse("vmm=loud", LevelFilter::Warn).is_err());
+        assert!(LogFilter::parse("=debug", LevelFilter::Warn).is_err());
+    }
+
+    #[test]
+    fn test_log_filter_enabled() {
+        let filter = LogFilter::parse(
+            "vmm=info,vmm::device_manager=trace,virtio=off",
+            LevelFilter::Warn,
+        )
+        .unwrap();
+
+        assert!(enabled(&filter, "vmm::device_manager", Level::Trace));
+        assert!(enabled(&filter, "vmm::device_manager::pci", Level::Trace));
+        assert!(!enabled(&filter, "vmm::cpu", Level::Debug));
+        assert!(enabled(&filter, "vmm::cpu", Level::Info));
+        assert!(!enabled(&filter, "virtio_devices::net", Level::Error));
+        assert!(!enabled(&filter, "block::qcow", Level::Info));
+        assert!(enabled(&filter, "block::qcow", Level::Warn));
+    }
+}
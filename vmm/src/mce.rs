@@ -0,0 +1,209 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Forwarding of the host memory errors to the guest.
+//!
+//! When the host detects an uncorrected memory error in a page the VMM maps,
+//! e.g. while scrubbing memory, the kernel unmaps the page and sends SIGBUS
+//! with BUS_MCEERR_AO to the processes asking for early kill. When a thread
+//! accesses a poisoned page, it gets SIGBUS with BUS_MCEERR_AR instead. Both
+//! are forwarded to the control loop when the page belongs to the guest
+//! memory, the page being replaced and the error reported to the guest for it
+//! to stop using the page.
+//!
+//! Other SIGBUS, including BUS_MCEERR_AR outside the guest memory or on the
+//! thread running the control loop, keep terminating the VMM.
+
+use crate::GuestMemoryMmap;
+use libc::{c_int, c_short, c_void, siginfo_t};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use vm_memory::{GuestMemory, GuestMemoryRegion};
+use vmm_sys_util::signal::register_signal_handler;
+
+// Missing from the libc crate, see include/uapi/asm-generic/siginfo.h
+const BUS_MCEERR_AR: c_int = 4;
+const BUS_MCEERR_AO: c_int = 5;
+
+// Upper bound of the number of regions of the guest memory.
+const MAX_GUEST_MEMORY_RANGES: usize = 256;
+
+// Write end of the pipe receiving the poisoned pages.
+static HWPOISON_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+// Host virtual address ranges of the guest memory, readable from the signal
+// handler. Unused entries are empty ranges.
+#[allow(clippy::declare_interior_mutable_const)]
+const UNUSED: AtomicU64 = AtomicU64::new(0);
+static GUEST_MEMORY_START: [AtomicU64; MAX_GUEST_MEMORY_RANGES] = [UNUSED; MAX_GUEST_MEMORY_RANGES];
+static GUEST_MEMORY_END: [AtomicU64; MAX_GUEST_MEMORY_RANGES] = [UNUSED; MAX_GUEST_MEMORY_RANGES];
+
+thread_local! {
+    // Whether the thread runs the control loop, which can't wait for the
+    // pages it accesses to be replaced.
+    static CONTROL_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+// Layout of the siginfo of the SIGBUS reporting memory errors, the libc
+// crate doesn't give access to si_addr_lsb.
+#[repr(C)]
+struct SigbusInfo {
+    _si_signo: c_int,
+    _si_errno: c_int,
+    si_code: c_int,
+    _pad: c_int,
+    si_addr: u64,
+    si_addr_lsb: c_short,
+}
+
+/// Poisoned page of the VMM address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoisonedPage {
+    /// Host virtual address of the error
+    pub address: u64,
+    /// Size of the poisoned page, bigger than the base page size for huge
+    /// pages
+    pub size: u64,
+}
+
+/// Publishes the host virtual address ranges of the guest memory, in which
+/// the accesses to poisoned pages can be recovered from.
+pub fn set_guest_memory(guest_memory: &GuestMemoryMmap) {
+    let mut regions = guest_memory.iter();
+    for (start, end) in GUEST_MEMORY_START.iter().zip(GUEST_MEMORY_END.iter()) {
+        // Emptied first, the signal handler never sees a stale range.
+        end.store(0, Ordering::Release);
+        if let Some(region) = regions.next() {
+            let address = region.as_ptr() as u64;
+            start.store(address, Ordering::Release);
+            end.store(address + region.len(), Ordering::Release);
+        }
+    }
+
+    if regions.next().is_some() {
+        warn!(
+            "Accesses to poisoned pages beyond the first {} guest memory regions will terminate the VMM",
+            MAX_GUEST_MEMORY_RANGES
+        );
+    }
+}
+
+fn in_guest_memory(address: u64) -> bool {
+    GUEST_MEMORY_START
+        .iter()
+        .zip(GUEST_MEMORY_END.iter())
+        .any(|(start, end)| {
+            (start.load(Ordering::Acquire)..end.load(Ordering::Acquire)).contains(&address)
+        })
+}
+
+extern "C" fn handle_sigbus(num: c_int, info: *mut siginfo_t, _: *mut c_void) {
+    // SAFETY: the kernel provides a valid siginfo to SA_SIGINFO handlers,
+    // which starts with the fields of SigbusInfo for SIGBUS.
+    let info = unsafe { &*(info as *const SigbusInfo) };
+    let forward = match info.si_code {
+        BUS_MCEERR_AO => true,
+        // The access is retried once the handler returns, until the page is
+        // replaced by the control loop.
+        BUS_MCEERR_AR => {
+            in_guest_memory(info.si_addr) && !CONTROL_THREAD.with(|control| control.get())
+        }
+        _ => false,
+    };
+
+    if forward {
+        let page = [info.si_addr, 1u64 << info.si_addr_lsb];
+        // SAFETY: write(2) is async-signal-safe and the buffer is valid. The
+        // write of a single page to a pipe is atomic.
+        unsafe {
+            libc::write(
+                HWPOISON_PIPE.load(Ordering::Relaxed),
+                page.as_ptr() as *const c_void,
+                std::mem::size_of_val(&page),
+            )
+        };
+        return;
+    }
+
+    // SAFETY: restoring the default action and raising the signal again are
+    // async-signal-safe.
+    unsafe {
+        libc::signal(num, libc::SIG_DFL);
+        libc::raise(num);
+    }
+}
+
+/// Receiver of the poisoned pages.
+pub struct HwPoisonReceiver {
+    pipe: File,
+}
+
+impl HwPoisonReceiver {
+    /// Asks the kernel to report the memory errors as soon as they are
+    /// detected, and starts handling them. The setting is inherited by the
+    /// threads created afterwards.
+    pub fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        // SAFETY: FFI call with a valid array of two file descriptors.
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the file descriptors were just created and are owned by us.
+        let pipe = unsafe { File::from_raw_fd(fds[0]) };
+        HWPOISON_PIPE.store(fds[1], Ordering::Relaxed);
+
+        // SAFETY: FFI call with valid arguments.
+        if unsafe {
+            libc::prctl(
+                libc::PR_MCE_KILL,
+                libc::PR_MCE_KILL_SET,
+                libc::PR_MCE_KILL_EARLY,
+                0,
+                0,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        CONTROL_THREAD.with(|control| control.set(true));
+        register_signal_handler(libc::SIGBUS, handle_sigbus)
+            .map_err(|e| io::Error::from_raw_os_error(e.errno()))?;
+
+        Ok(HwPoisonReceiver { pipe })
+    }
+
+    /// Pages poisoned since the last call.
+    pub fn pages(&mut self) -> io::Result<Vec<PoisonedPage>> {
+        let mut pages = Vec::new();
+        let mut page = [0u8; 16];
+        loop {
+            match self.pipe.read_exact(&mut page) {
+                Ok(()) => {
+                    let (address, size) = page.split_at(8);
+                    let page = PoisonedPage {
+                        address: u64::from_ne_bytes(address.try_into().unwrap()),
+                        size: u64::from_ne_bytes(size.try_into().unwrap()),
+                    };
+                    // An access is retried until the page is replaced.
+                    if !pages.contains(&page) {
+                        pages.push(page);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(pages),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl AsRawFd for HwPoisonReceiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.pipe.as_raw_fd()
+    }
+}
@@ -0,0 +1,429 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Publication of the guest name through multicast DNS.
+//!
+//! Meant for development setups, the VMM answers the mDNS queries (RFC 6762)
+//! for `<hostname>.local` with the IPv4 address of the guest, letting the
+//! guest be reached by name without maintaining `/etc/hosts`. The record is
+//! announced as the VM boots and withdrawn, with a TTL of 0, as it stops.
+//! The port is shared with the other responders of the host, e.g. avahi.
+
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::thread;
+use std::time::Duration;
+use vmm_sys_util::eventfd::EventFd;
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+// TTL of the record, and its maximum for the legacy unicast responses
+const RECORD_TTL: u32 = 120;
+const LEGACY_RECORD_TTL: u32 = 10;
+// Number of unsolicited responses announcing the record, and their interval
+const ANNOUNCEMENTS: u32 = 2;
+const ANNOUNCEMENT_INTERVAL: Duration = Duration::from_secs(1);
+
+const HEADER_SIZE: usize = 12;
+const FLAGS_RESPONSE: u16 = 0x8400;
+const FLAGS_QUERY_MASK: u16 = 0xf800;
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+// Top bit of the class, requesting a unicast response in a question and
+// flushing the caches in an answer.
+const CLASS_UNICAST: u16 = 0x8000;
+const CLASS_CACHE_FLUSH: u16 = 0x8000;
+// Bound on the compression pointers followed, against loops
+const MAX_NAME_POINTERS: usize = 16;
+const MAX_PACKET_SIZE: usize = 9000;
+
+fn read_u16(packet: &[u8], offset: usize) -> Option<u16> {
+    packet
+        .get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+// Reads the lowercase name at `offset`, returning it along with the offset
+// following it.
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+
+    loop {
+        let len = *packet.get(offset)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(offset + 1)));
+        }
+
+        match len & 0xc0 {
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_NAME_POINTERS {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                offset = ((len & 0x3f) << 8) | *packet.get(offset + 1)? as usize;
+            }
+            0 => {
+                let label = packet.get(offset + 1..offset + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).to_lowercase());
+                offset += 1 + len;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Question of a query for the A record of the published name.
+#[derive(Debug, PartialEq, Eq)]
+struct Query {
+    id: u16,
+    unicast: bool,
+}
+
+// Looks for a question about the A record of `name` in `packet`.
+fn parse_query(packet: &[u8], name: &str) -> Option<Query> {
+    let id = read_u16(packet, 0)?;
+    if read_u16(packet, 2)? & FLAGS_QUERY_MASK != 0 {
+        return None;
+    }
+
+    let mut offset = HEADER_SIZE;
+    for _ in 0..read_u16(packet, 4)? {
+        let (qname, next) = read_name(packet, offset)?;
+        let qtype = read_u16(packet, next)?;
+        let qclass = read_u16(packet, next + 2)?;
+        offset = next + 4;
+
+        if qname == name
+            && (qtype == TYPE_A || qtype == TYPE_ANY)
+            && matches!(qclass & !CLASS_UNICAST, CLASS_IN | CLASS_ANY)
+        {
+            return Some(Query {
+                id,
+                unicast: qclass & CLASS_UNICAST != 0,
+            });
+        }
+    }
+
+    None
+}
+
+fn write_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+}
+
+// Response holding the A record of `name`. A legacy unicast response, to a
+// resolver not speaking mDNS, repeats the question and can't flush the
+// caches.
+fn build_response(id: u16, name: &str, ip: Ipv4Addr, ttl: u32, legacy: bool) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_SIZE + 2 * (name.len() + 16));
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&FLAGS_RESPONSE.to_be_bytes());
+    packet.extend_from_slice(&u16::from(legacy).to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    packet.extend_from_slice(&[0; 4]);
+
+    if legacy {
+        write_name(&mut packet, name);
+        packet.extend_from_slice(&TYPE_A.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+
+    let (class, ttl) = if legacy {
+        (CLASS_IN, ttl.min(LEGACY_RECORD_TTL))
+    } else {
+        (CLASS_IN | CLASS_CACHE_FLUSH, ttl)
+    };
+    write_name(&mut packet, name);
+    packet.extend_from_slice(&TYPE_A.to_be_bytes());
+    packet.extend_from_slice(&class.to_be_bytes());
+    packet.extend_from_slice(&ttl.to_be_bytes());
+    packet.extend_from_slice(&4u16.to_be_bytes());
+    packet.extend_from_slice(&ip.octets());
+
+    packet
+}
+
+// Socket bound to the mDNS port, alongside the other responders of the host.
+fn bind_socket() -> io::Result<UdpSocket> {
+    // SAFETY: FFI call, the result is checked
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is valid and owned by nothing else
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+
+    for option in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        let value: libc::c_int = 1;
+        // SAFETY: FFI call with a valid fd and option value, the result is
+        // checked
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &value as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: MDNS_PORT.to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(Ipv4Addr::UNSPECIFIED).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    // SAFETY: FFI call with a valid fd and address, the result is checked
+    let ret = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    socket.join_multicast_v4(&MDNS_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(socket)
+}
+
+/// Responder publishing the name of the guest until dropped.
+pub struct MdnsResponder {
+    kill_evt: EventFd,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl MdnsResponder {
+    pub fn new(hostname: &str, ip: Ipv4Addr) -> io::Result<Self> {
+        let socket = bind_socket()?;
+        let name = format!("{}.local", hostname.to_lowercase());
+        socket.send_to(
+            &build_response(0, &name, ip, RECORD_TTL, false),
+            (MDNS_ADDR, MDNS_PORT),
+        )?;
+        info!("Publishing {} as {} through mDNS", name, ip);
+
+        let kill_evt = EventFd::new(libc::EFD_NONBLOCK)?;
+        let thread_kill_evt = kill_evt.try_clone()?;
+        let handle = thread::Builder::new()
+            .name("mdns".to_string())
+            .spawn(move || Self::respond(socket, thread_kill_evt, name, ip))?;
+
+        Ok(MdnsResponder {
+            kill_evt,
+            handle: Some(handle),
+        })
+    }
+
+    fn respond(socket: UdpSocket, kill_evt: EventFd, name: String, ip: Ipv4Addr) {
+        let group = SocketAddr::from((MDNS_ADDR, MDNS_PORT));
+        let mut fds = [
+            libc::pollfd {
+                fd: kill_evt.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: socket.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        let mut announcements = 1;
+        let mut packet = vec![0u8; MAX_PACKET_SIZE];
+
+        loop {
+            let timeout = if announcements < ANNOUNCEMENTS {
+                ANNOUNCEMENT_INTERVAL.as_millis() as i32
+            } else {
+                -1
+            };
+            // SAFETY: FFI call with a valid array of pollfd
+            let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("Error polling the mDNS socket: {}", e);
+                return;
+            }
+
+            if fds[0].revents & libc::POLLIN != 0 {
+                // Let the caches know the record is gone.
+                let goodbye = build_response(0, &name, ip, 0, false);
+                if let Err(e) = socket.send_to(&goodbye, group) {
+                    warn!("Error withdrawing {} from mDNS: {}", name, e);
+                }
+                return;
+            }
+
+            if ret == 0 {
+                announcements += 1;
+                let announcement = build_response(0, &name, ip, RECORD_TTL, false);
+                if let Err(e) = socket.send_to(&announcement, group) {
+                    warn!("Error announcing {} through mDNS: {}", name, e);
+                }
+                continue;
+            }
+
+            loop {
+                let (len, src) = match socket.recv_from(&mut packet) {
+                    Ok(r) => r,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        warn!("Error receiving an mDNS query: {}", e);
+                        break;
+                    }
+                };
+                let Some(query) = parse_query(&packet[..len], &name) else {
+                    continue;
+                };
+
+                // A query not sent from the mDNS port is a legacy unicast
+                // one, answered as a regular DNS server would.
+                let legacy = src.port() != MDNS_PORT;
+                let response = build_response(query.id, &name, ip, RECORD_TTL, legacy);
+                let dst = if legacy || query.unicast { src } else { group };
+                if let Err(e) = socket.send_to(&response, dst) {
+                    warn!("Error answering an mDNS query from {}: {}", src, e);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for MdnsResponder {
+    fn drop(&mut self) {
+        let _ = self.kill_evt.write(1);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(id: u16, questions: &[(&str, u16, u16)]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0; 6]);
+        for (name, qtype, qclass) in questions {
+            write_name(&mut packet, name);
+            packet.extend_from_slice(&qtype.to_be_bytes());
+            packet.extend_from_slice(&qclass.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn test_mdns_parse_query() {
+        let name = "vm-name.local";
+
+        assert_eq!(
+            parse_query(&query(0x1234, &[("VM-Name.local", TYPE_A, CLASS_IN)]), name),
+            Some(Query {
+                id: 0x1234,
+                unicast: false
+            })
+        );
+        assert_eq!(
+            parse_query(
+                &query(
+                    0,
+                    &[
+                        ("other.local", TYPE_A, CLASS_IN),
+                        (name, TYPE_ANY, CLASS_IN | CLASS_UNICAST)
+                    ]
+                ),
+                name
+            ),
+            Some(Query {
+                id: 0,
+                unicast: true
+            })
+        );
+        // AAAA record
+        assert_eq!(parse_query(&query(0, &[(name, 28, CLASS_IN)]), name), None);
+        assert_eq!(
+            parse_query(&query(0, &[("other.local", TYPE_A, CLASS_IN)]), name),
+            None
+        );
+        // Responses are ignored.
+        let mut response = query(0, &[(name, TYPE_A, CLASS_IN)]);
+        response[2] = 0x84;
+        assert_eq!(parse_query(&response, name), None);
+        assert_eq!(parse_query(&[0; 4], name), None);
+
+        // Compressed name, pointing to the "local" label of the first one
+        let mut packet = query(0, &[("other.local", TYPE_A, CLASS_IN)]);
+        packet[5] = 2;
+        packet.extend_from_slice(&[7]);
+        packet.extend_from_slice(b"vm-name");
+        packet.extend_from_slice(&[0xc0, (HEADER_SIZE + 6) as u8]);
+        packet.extend_from_slice(&TYPE_A.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        assert!(parse_query(&packet, name).is_some());
+
+        // Pointer loop
+        let mut packet = query(0, &[]);
+        packet[5] = 1;
+        packet.extend_from_slice(&[0xc0, HEADER_SIZE as u8, 0, 1, 0, 1]);
+        assert_eq!(parse_query(&packet, name), None);
+    }
+
+    #[test]
+    fn test_mdns_build_response() {
+        let ip = Ipv4Addr::new(192, 168, 249, 2);
+        let response = build_response(0, "vm.local", ip, RECORD_TTL, false);
+        assert_eq!(
+            &response[..HEADER_SIZE],
+            &[0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            &response[HEADER_SIZE..],
+            &[
+                2, b'v', b'm', 5, b'l', b'o', b'c', b'a', b'l', 0, 0, 1, 0x80, 1, 0, 0, 0, 120, 0,
+                4, 192, 168, 249, 2
+            ]
+        );
+
+        // The legacy response repeats the question, with a short TTL.
+        let response = build_response(0x1234, "vm.local", ip, RECORD_TTL, true);
+        assert_eq!(&response[..6], &[0x12, 0x34, 0x84, 0, 0, 1]);
+        assert_eq!(parse_query(&response, "vm.local"), None);
+        assert_eq!(
+            &response[response.len() - 14..],
+            &[0, 1, 0, 1, 0, 0, 0, 10, 0, 4, 192, 168, 249, 2]
+        );
+    }
+}
@@ -2,14 +2,17 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 //
+use crate::api::operations;
 #[cfg(target_arch = "x86_64")]
 use crate::config::SgxEpcConfig;
-use crate::config::{HotplugMethod, MemoryConfig, MemoryZoneConfig};
+use crate::config::{HostNumaPolicy, HotplugMethod, MemoryConfig, MemoryZoneConfig};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::{
     CoredumpMemoryRegion, CoredumpMemoryRegions, DumpState, GuestDebuggableError,
 };
+use crate::hugetlbfs;
 use crate::migration::url_to_path;
+use crate::userfaultfd::{Userfaultfd, UFFDIO_REGISTER_MODE_MISSING};
 use crate::MEMORY_MANAGER_SNAPSHOT_ID;
 use crate::{GuestMemoryMmap, GuestRegionMmap};
 use acpi_tables::{aml, Aml};
@@ -29,14 +32,17 @@ use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::io::{self};
+use std::io::{self, Seek, SeekFrom};
+use std::num::Wrapping;
 use std::ops::{BitAnd, Deref, Not, Sub};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use std::os::fd::AsFd;
+use std::os::unix::fs::FileExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path::PathBuf;
 use std::result;
 use std::sync::{Arc, Barrier, Mutex};
+use std::time::Instant;
 use std::{ffi, thread};
 use tracer::trace_scoped;
 use virtio_devices::BlocksState;
@@ -55,6 +61,7 @@ use vm_migration::{
     protocol::MemoryRange, protocol::MemoryRangeTable, Migratable, MigratableError, Pausable,
     Snapshot, SnapshotData, Snapshottable, Transportable,
 };
+use vmm_sys_util::eventfd::EventFd;
 
 pub const MEMORY_MANAGER_ACPI_SIZE: usize = 0x18;
 
@@ -71,6 +78,7 @@ const SGX_PAGE_SIZE: u64 = 1 << 12;
 const HOTPLUG_COUNT: usize = 8;
 
 // Memory policy constants
+const MPOL_PREFERRED: u32 = 1;
 const MPOL_BIND: u32 = 2;
 const MPOL_MF_STRICT: u32 = 1;
 const MPOL_MF_MOVE: u32 = 1 << 1;
@@ -80,6 +88,222 @@ const PLATFORM_DEVICE_AREA_SIZE: u64 = 1 << 20;
 
 const MAX_PREFAULT_THREAD_COUNT: usize = 16;
 
+// Size of the chunks read from the snapshot file by the lazy restore
+// prefetcher between two checks for pending page faults.
+const PREFETCH_CHUNK_SIZE: u64 = 2 << 20;
+
+// Size of the chunks used when querying the residency of a region, so that
+// the vector filled by mincore(2) stays small regardless of the region size.
+const MINCORE_CHUNK_SIZE: usize = 1 << 30;
+
+/// How the guest memory is populated when restoring from a snapshot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryRestoreMode {
+    /// Copy the whole snapshot before starting the VM.
+    #[default]
+    Copy,
+    /// Populate the memory from the snapshot as the guest touches it.
+    OnDemand,
+    /// Map the snapshot privately, the pages being copied on the first
+    /// write. The snapshot is shared with every other VM restored this way.
+    CopyOnWrite,
+}
+
+// Part of the guest memory populated from the snapshot file on demand.
+struct LazyRange {
+    host_addr: u64,
+    len: u64,
+    file_offset: u64,
+    page_size: u64,
+}
+
+impl LazyRange {
+    fn contains(&self, host_addr: u64) -> bool {
+        host_addr >= self.host_addr && host_addr < self.host_addr + self.len
+    }
+}
+
+// Populates the guest memory from the snapshot file as the guest touches it,
+// while a background prefetcher copies the remaining pages in order. Once
+// everything is copied, the userfaultfd is closed and the guest memory is
+// left as if it had been restored upfront.
+struct LazyRestore {
+    kill_evt: EventFd,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl LazyRestore {
+    fn new(memory_file: File, ranges: Vec<LazyRange>) -> io::Result<Self> {
+        let uffd = Userfaultfd::new()?;
+        for range in ranges.iter() {
+            uffd.register(range.host_addr, range.len, UFFDIO_REGISTER_MODE_MISSING)?;
+        }
+
+        let kill_evt = EventFd::new(libc::EFD_NONBLOCK)?;
+        let thread_kill_evt = kill_evt.try_clone()?;
+        let handle = thread::Builder::new()
+            .name("lazy_restore".to_string())
+            .spawn(move || {
+                if let Err(e) = Self::populate(uffd, thread_kill_evt, memory_file, ranges) {
+                    error!("Error populating guest memory from snapshot: {}", e);
+                }
+            })?;
+
+        Ok(LazyRestore {
+            kill_evt,
+            handle: Some(handle),
+        })
+    }
+
+    fn copy_page(
+        uffd: &Userfaultfd,
+        memory_file: &File,
+        range: &LazyRange,
+        host_addr: u64,
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        let page = host_addr & !(range.page_size - 1);
+        let buf = &mut buf[..range.page_size as usize];
+        memory_file.read_exact_at(buf, range.file_offset + page - range.host_addr)?;
+        uffd.copy(page, buf.as_ptr(), range.page_size)
+    }
+
+    fn populate(
+        uffd: Userfaultfd,
+        kill_evt: EventFd,
+        memory_file: File,
+        ranges: Vec<LazyRange>,
+    ) -> io::Result<()> {
+        let start = Instant::now();
+        let max_page_size = ranges.iter().map(|r| r.page_size).max().unwrap_or(0);
+        let mut buf = vec![0u8; std::cmp::max(max_page_size, PREFETCH_CHUNK_SIZE) as usize];
+        let mut fds = [
+            libc::pollfd {
+                fd: uffd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: kill_evt.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+
+        // Position of the prefetcher, as a range index and an offset in it.
+        let mut prefetch_range = 0;
+        let mut prefetch_offset = 0;
+        let mut faults = 0u64;
+
+        while prefetch_range < ranges.len() {
+            // Only check for page faults without waiting, the prefetcher
+            // having work to do.
+            // SAFETY: FFI call with a valid array of pollfd
+            let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 0) };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            if fds[1].revents & libc::POLLIN != 0 {
+                return Ok(());
+            }
+
+            while let Some(fault) = uffd.read_fault()? {
+                let Some(range) = ranges.iter().find(|r| r.contains(fault.address)) else {
+                    warn!("Unexpected page fault at 0x{:x}", fault.address);
+                    continue;
+                };
+                Self::copy_page(&uffd, &memory_file, range, fault.address, &mut buf)?;
+                faults += 1;
+            }
+
+            // Prefetch the next chunk, page by page since the guest might
+            // have already faulted some of them in.
+            let range = &ranges[prefetch_range];
+            let len = std::cmp::min(
+                std::cmp::max(range.page_size, PREFETCH_CHUNK_SIZE),
+                range.len - prefetch_offset,
+            );
+            memory_file.read_exact_at(
+                &mut buf[..len as usize],
+                range.file_offset + prefetch_offset,
+            )?;
+            for offset in (0..len).step_by(range.page_size as usize) {
+                uffd.copy(
+                    range.host_addr + prefetch_offset + offset,
+                    buf[offset as usize..].as_ptr(),
+                    range.page_size,
+                )?;
+            }
+
+            prefetch_offset += len;
+            if prefetch_offset == range.len {
+                prefetch_range += 1;
+                prefetch_offset = 0;
+            }
+        }
+
+        info!(
+            "Guest memory restored from snapshot in {:?} ({} page faults)",
+            start.elapsed(),
+            faults
+        );
+        event!("vm", "memory-restored");
+
+        Ok(())
+    }
+}
+
+impl Drop for LazyRestore {
+    fn drop(&mut self) {
+        if let Err(e) = self.kill_evt.write(1) {
+            error!("Error stopping lazy restore: {}", e);
+        }
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("Lazy restore thread panicked");
+            }
+        }
+    }
+}
+
+// Number of bytes from the region currently resident in memory.
+fn region_resident_size(region: &GuestRegionMmap) -> io::Result<u64> {
+    // SAFETY: FFI call. Trivially safe.
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+    let mut vec = vec![0u8; MINCORE_CHUNK_SIZE / page_size];
+    let region_len = region.len() as usize;
+    let mut resident = 0;
+    let mut offset = 0;
+
+    while offset < region_len {
+        let len = std::cmp::min(MINCORE_CHUNK_SIZE, region_len - offset);
+        // SAFETY: the range is part of the region mapping and the vector is
+        // large enough to hold one byte per page of the range.
+        let ret = unsafe {
+            libc::mincore(
+                region.as_ptr().add(offset) as *mut libc::c_void,
+                len,
+                vec.as_mut_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        resident += vec[..len.div_ceil(page_size)]
+            .iter()
+            .filter(|v| **v & 1 != 0)
+            .count()
+            * page_size;
+        offset += len;
+    }
+
+    Ok(resident as u64)
+}
+
 #[derive(Clone, Default, Serialize, Deserialize)]
 struct HotPlugState {
     base: u64,
@@ -141,6 +365,173 @@ impl MemoryZone {
 
 pub type MemoryZones = HashMap<String, MemoryZone>;
 
+/// Private memory of a SEV-SNP guest on KVM, backed by the guest_memfd of
+/// the hypervisor while the shared memory is backed by the guest RAM mapped
+/// by the VMM. The guest memory starts private, the ranges the guest
+/// converted to shared memory are tracked, and the backing a range no longer
+/// uses is released on conversion.
+#[cfg(feature = "sev_snp")]
+pub struct PrivateMemory {
+    memory: GuestMemoryAtomic<GuestMemoryMmap>,
+    // Start and end of the shared ranges, sorted and never adjacent.
+    shared: Mutex<BTreeMap<u64, u64>>,
+    // Start and end of the pages of the payload left unaccepted at launch,
+    // until the guest changes their state.
+    unaccepted: Mutex<BTreeMap<u64, u64>>,
+}
+
+#[cfg(feature = "sev_snp")]
+impl PrivateMemory {
+    fn new(memory: GuestMemoryAtomic<GuestMemoryMmap>) -> Self {
+        PrivateMemory {
+            memory,
+            shared: Mutex::new(BTreeMap::new()),
+            unaccepted: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn insert_range(ranges: &mut BTreeMap<u64, u64>, mut start: u64, mut end: u64) {
+        let merged: Vec<(u64, u64)> = ranges
+            .range(..=end)
+            .filter(|(_, e)| **e >= start)
+            .map(|(s, e)| (*s, *e))
+            .collect();
+        for (s, e) in merged {
+            ranges.remove(&s);
+            start = std::cmp::min(start, s);
+            end = std::cmp::max(end, e);
+        }
+        ranges.insert(start, end);
+    }
+
+    // Removes [start, end) from the ranges, returning the size removed.
+    fn remove_range(ranges: &mut BTreeMap<u64, u64>, start: u64, end: u64) -> u64 {
+        let overlapping: Vec<(u64, u64)> = ranges
+            .range(..end)
+            .filter(|(_, e)| **e > start)
+            .map(|(s, e)| (*s, *e))
+            .collect();
+        let mut removed = 0;
+        for (s, e) in overlapping {
+            ranges.remove(&s);
+            if s < start {
+                ranges.insert(s, start);
+            }
+            if e > end {
+                ranges.insert(end, e);
+            }
+            removed += std::cmp::min(e, end) - std::cmp::max(s, start);
+        }
+        removed
+    }
+
+    // Releases the pages of the guest RAM mapping backing [gpa, gpa + size).
+    fn release_shared(&self, gpa: u64, size: u64) -> Result<(), io::Error> {
+        let guest_memory = self.memory.memory();
+        let end = gpa + size;
+        let mut gpa = gpa;
+        while gpa < end {
+            let region = guest_memory
+                .find_region(GuestAddress(gpa))
+                .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+            let offset = gpa - region.start_addr().raw_value();
+            let len = std::cmp::min(end - gpa, region.len() - offset);
+
+            if let Some(file_offset) = region.file_offset() {
+                if region.flags() & MAP_SHARED != 0 {
+                    // SAFETY: FFI call with valid arguments
+                    let ret = unsafe {
+                        libc::fallocate64(
+                            file_offset.file().as_raw_fd(),
+                            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                            (file_offset.start() + offset) as libc::off64_t,
+                            len as libc::off64_t,
+                        )
+                    };
+                    if ret != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+            }
+
+            // SAFETY: FFI call with a range of the guest RAM mapping
+            let ret = unsafe {
+                libc::madvise(
+                    region.as_ptr().add(offset as usize) as *mut libc::c_void,
+                    len as libc::size_t,
+                    libc::MADV_DONTNEED,
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            gpa += len;
+        }
+
+        Ok(())
+    }
+
+    /// Records [gpa, gpa + size) as left unaccepted at launch, the guest
+    /// accepting the pages on demand.
+    pub fn leave_unaccepted(&self, gpa: u64, size: u64) {
+        Self::insert_range(&mut self.unaccepted.lock().unwrap(), gpa, gpa + size);
+    }
+
+    /// Size of the memory left unaccepted at launch the guest has not
+    /// changed the state of yet.
+    pub fn unaccepted_size(&self) -> u64 {
+        self.unaccepted
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(start, end)| end - start)
+            .sum()
+    }
+
+    /// Records the conversion of [gpa, gpa + size) by the guest.
+    pub fn convert(&self, gpa: u64, size: u64, private: bool) {
+        // The page state change precedes the acceptance of the pages the
+        // payload left unaccepted, which are no longer tracked once the
+        // guest requested them in either state.
+        let accepted = Self::remove_range(&mut self.unaccepted.lock().unwrap(), gpa, gpa + size);
+        if accepted > 0 {
+            debug!(
+                "Guest changed the state of 0x{:x} bytes left unaccepted at 0x{:x}-0x{:x}",
+                accepted,
+                gpa,
+                gpa + size - 1
+            );
+        }
+
+        let mut shared = self.shared.lock().unwrap();
+        if private {
+            Self::remove_range(&mut shared, gpa, gpa + size);
+            if let Err(e) = self.release_shared(gpa, size) {
+                warn!(
+                    "Failed to release the shared memory at 0x{:x}-0x{:x}: {}",
+                    gpa,
+                    gpa + size - 1,
+                    e
+                );
+            }
+        } else {
+            Self::insert_range(&mut shared, gpa, gpa + size);
+        }
+    }
+
+    /// Whether the guest memory at `gpa` is private.
+    pub fn is_private(&self, gpa: u64) -> bool {
+        !self
+            .shared
+            .lock()
+            .unwrap()
+            .range(..=gpa)
+            .next_back()
+            .is_some_and(|(_, end)| gpa < *end)
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 struct GuestRamMapping {
     slot: u32,
@@ -169,6 +560,8 @@ pub struct MemoryManager {
     hotplug_slots: Vec<HotPlugState>,
     selected_slot: usize,
     mergeable: bool,
+    // Whether the guest RAM is part of the core dumps of the VMM.
+    coredump: bool,
     allocator: Arc<Mutex<SystemAllocator>>,
     hotplug_method: HotplugMethod,
     boot_ram: u64,
@@ -197,6 +590,15 @@ pub struct MemoryManager {
     pub acpi_address: Option<GuestAddress>,
     #[cfg(target_arch = "aarch64")]
     uefi_flash: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+
+    // Page size of each memory zone
+    zone_page_sizes: HashMap<String, u64>,
+
+    // Pending population of the guest memory from a snapshot
+    lazy_restore: Option<LazyRestore>,
+
+    #[cfg(feature = "sev_snp")]
+    private_memory: Option<Arc<PrivateMemory>>,
 }
 
 #[derive(Debug)]
@@ -318,6 +720,9 @@ pub enum Error {
     /// Guest address overflow
     GuestAddressOverFlow,
 
+    /// RAM and hotplug areas don't leave room for the 64-bit device area
+    DeviceAreaExhausted(GuestAddress, u8),
+
     /// Error opening snapshot file
     SnapshotOpen(io::Error),
 
@@ -339,8 +744,27 @@ pub enum Error {
 
     /// Memory size is misaligned with default page size or its hugepage size
     MisalignedMemorySize,
+
+    /// Failed to set up the lazy restore of the guest memory
+    LazyRestore(io::Error),
+
+    /// Failed to map the snapshot file into the guest memory
+    SnapshotMap(io::Error),
+
+    /// Failed to seek into the snapshot file
+    SnapshotSeek(io::Error),
+
+    /// Failed to create the hugetlbfs file backing the memory
+    HugePages(hugetlbfs::Error),
+
+    /// Failed to replace a poisoned page of the guest memory
+    ReplacePoisonedPage(io::Error),
 }
 
+// Amount of guest memory moved at once to another host NUMA node, a multiple
+// of the huge page sizes.
+const HOST_NUMA_MIGRATION_CHUNK_SIZE: u64 = 1 << 30;
+
 const ENABLE_FLAG: usize = 0;
 const INSERTING_FLAG: usize = 1;
 const REMOVING_FLAG: usize = 2;
@@ -599,7 +1023,9 @@ impl MemoryManager {
                     zone.shared,
                     zone.hugepages,
                     zone.hugepage_size,
+                    true,
                     zone.host_numa_node,
+                    zone.host_numa_policy,
                     None,
                     thp,
                 )?;
@@ -684,7 +1110,9 @@ impl MemoryManager {
                         zone_config.shared,
                         zone_config.hugepages,
                         zone_config.hugepage_size,
+                        !guest_ram_mapping.virtio_mem,
                         zone_config.host_numa_node,
+                        zone_config.host_numa_policy,
                         existing_memory_files.remove(&guest_ram_mapping.slot),
                         thp,
                     )?;
@@ -755,6 +1183,138 @@ impl MemoryManager {
         Ok(())
     }
 
+    // Populate the guest memory from the snapshot file on demand, instead of
+    // copying the whole snapshot before starting the VM.
+    fn lazy_restore_saved_regions(
+        &mut self,
+        file_path: PathBuf,
+        saved_regions: MemoryRangeTable,
+    ) -> Result<(), Error> {
+        if saved_regions.is_empty() {
+            return Ok(());
+        }
+
+        let memory_file = OpenOptions::new()
+            .read(true)
+            .open(file_path)
+            .map_err(Error::SnapshotOpen)?;
+
+        // SAFETY: FFI call. Trivially safe.
+        let default_page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 };
+        let guest_memory = self.guest_memory.memory();
+        let mut ranges = Vec::new();
+        let mut file_offset = 0;
+        for range in saved_regions.regions() {
+            let host_addr = guest_memory
+                .get_host_address(GuestAddress(range.gpa))
+                .map_err(Error::SnapshotCopy)? as u64;
+            let page_size = self
+                .memory_zones
+                .iter()
+                .find(|(_, zone)| {
+                    zone.regions()
+                        .iter()
+                        .chain(zone.virtio_mem_zone().as_ref().map(|z| z.region()))
+                        .any(|r| r.address_in_range(GuestAddress(range.gpa)))
+                })
+                .and_then(|(id, _)| self.zone_page_sizes.get(id).copied())
+                .unwrap_or(default_page_size);
+            ranges.push(LazyRange {
+                host_addr,
+                len: range.length,
+                file_offset,
+                page_size,
+            });
+            file_offset += range.length;
+        }
+
+        self.lazy_restore =
+            Some(LazyRestore::new(memory_file, ranges).map_err(Error::LazyRestore)?);
+
+        Ok(())
+    }
+
+    // Map the snapshot file privately over the guest memory, letting all the
+    // VMs restored from the same snapshot share the pages they don't modify.
+    // Only anonymous private memory can be replaced this way, the regions
+    // backed by a file or shared with another process are copied instead.
+    // So are huge pages, hugetlbfs or transparent ones, which the snapshot
+    // file mapping would silently turn into regular pages.
+    fn map_saved_regions(
+        &mut self,
+        file_path: PathBuf,
+        saved_regions: MemoryRangeTable,
+    ) -> Result<(), Error> {
+        if saved_regions.is_empty() {
+            return Ok(());
+        }
+
+        let mut memory_file = OpenOptions::new()
+            .read(true)
+            .open(file_path)
+            .map_err(Error::SnapshotOpen)?;
+
+        let guest_memory = self.guest_memory.memory();
+        let mut file_offset = 0;
+        for range in saved_regions.regions() {
+            let private = !self.thp
+                && guest_memory
+                    .find_region(GuestAddress(range.gpa))
+                    .map(|r| {
+                        r.file_offset().is_none()
+                            && r.flags() & libc::MAP_PRIVATE == libc::MAP_PRIVATE
+                            && r.flags() & libc::MAP_HUGETLB == 0
+                            && range.gpa + range.length <= r.start_addr().raw_value() + r.len()
+                    })
+                    .unwrap_or(false);
+
+            if private {
+                let host_addr = guest_memory
+                    .get_host_address(GuestAddress(range.gpa))
+                    .map_err(Error::SnapshotCopy)?;
+                // SAFETY: FFI call. The range belongs to the guest memory
+                // mapping, which is replaced in place with the same protection
+                // before the VM starts running.
+                let ret = unsafe {
+                    libc::mmap(
+                        host_addr as *mut libc::c_void,
+                        range.length as usize,
+                        libc::PROT_READ | libc::PROT_WRITE,
+                        libc::MAP_PRIVATE | libc::MAP_FIXED | libc::MAP_NORESERVE,
+                        memory_file.as_raw_fd(),
+                        file_offset as libc::off_t,
+                    )
+                };
+                if ret == libc::MAP_FAILED {
+                    return Err(Error::SnapshotMap(io::Error::last_os_error()));
+                }
+            } else {
+                memory_file
+                    .seek(SeekFrom::Start(file_offset))
+                    .map_err(Error::SnapshotSeek)?;
+                let mut offset: u64 = 0;
+                loop {
+                    let bytes_read = guest_memory
+                        .read_volatile_from(
+                            GuestAddress(range.gpa + offset),
+                            &mut memory_file,
+                            (range.length - offset) as usize,
+                        )
+                        .map_err(Error::SnapshotCopy)?;
+                    offset += bytes_read as u64;
+
+                    if offset == range.length {
+                        break;
+                    }
+                }
+            }
+
+            file_offset += range.length;
+        }
+
+        Ok(())
+    }
+
     fn validate_memory_config(
         config: &MemoryConfig,
         user_provided_zones: bool,
@@ -810,6 +1370,7 @@ impl MemoryManager {
                 hugepages: config.hugepages,
                 hugepage_size: config.hugepage_size,
                 host_numa_node: None,
+                host_numa_policy: HostNumaPolicy::Bind,
                 hotplug_size: config.hotplug_size,
                 hotplugged_size: config.hotplugged_size,
                 prefault: config.prefault,
@@ -1082,8 +1643,10 @@ impl MemoryManager {
                         }
 
                         if !user_provided_zones && config.hotplug_method == HotplugMethod::Acpi {
+                            // Hotplugged DIMMs are multiples of 128MiB, size
+                            // the hole so that the whole hotplug_size fits.
                             start_of_device_area = start_of_device_area
-                                .checked_add(hotplug_size)
+                                .checked_add(hotplug_size.div_ceil(128 << 20) * (128 << 20))
                                 .ok_or(Error::GuestAddressOverFlow)?;
                         } else {
                             // Alignment must be "natural" i.e. same as size of block
@@ -1106,7 +1669,9 @@ impl MemoryManager {
                                 zone.shared,
                                 zone.hugepages,
                                 zone.hugepage_size,
+                                false,
                                 zone.host_numa_node,
+                                zone.host_numa_policy,
                                 None,
                                 config.thp,
                             )?;
@@ -1153,6 +1718,22 @@ impl MemoryManager {
             )
         };
 
+        // Large guests can consume the whole guest physical address space,
+        // leaving nothing for the 64-bit PCI BARs and platform devices.
+        if start_of_device_area >= end_of_device_area {
+            error!(
+                "Guest memory ends at {:#x}, beyond the {} bits of physical address space",
+                start_of_device_area.0, phys_bits
+            );
+            return Err(Error::DeviceAreaExhausted(start_of_device_area, phys_bits));
+        }
+
+        let mut zone_page_sizes = HashMap::new();
+        for zone in zones.iter() {
+            zone_page_sizes.insert(zone.id.clone(), memory_zone_get_align_size(zone)?);
+        }
+
+        crate::mce::set_guest_memory(&guest_memory);
         let guest_memory = GuestMemoryAtomic::new(guest_memory);
 
         // Both MMIO and PIO address spaces start at address 0.
@@ -1213,6 +1794,7 @@ impl MemoryManager {
             hotplug_slots,
             selected_slot,
             mergeable: config.mergeable,
+            coredump: config.coredump,
             allocator,
             hotplug_method: config.hotplug_method,
             boot_ram,
@@ -1236,6 +1818,10 @@ impl MemoryManager {
             #[cfg(target_arch = "aarch64")]
             uefi_flash: None,
             thp: config.thp,
+            zone_page_sizes,
+            lazy_restore: None,
+            #[cfg(feature = "sev_snp")]
+            private_memory: None,
         };
 
         #[cfg(target_arch = "aarch64")]
@@ -1264,6 +1850,7 @@ impl MemoryManager {
         config: &MemoryConfig,
         source_url: Option<&str>,
         prefault: bool,
+        restore_mode: MemoryRestoreMode,
         phys_bits: u8,
     ) -> Result<Arc<Mutex<MemoryManager>>, Error> {
         if let Some(source_url) = source_url {
@@ -1286,9 +1873,20 @@ impl MemoryManager {
                 None,
             )?;
 
-            mm.lock()
-                .unwrap()
-                .fill_saved_regions(memory_file_path, mem_snapshot.memory_ranges)?;
+            match restore_mode {
+                MemoryRestoreMode::Copy => mm
+                    .lock()
+                    .unwrap()
+                    .fill_saved_regions(memory_file_path, mem_snapshot.memory_ranges)?,
+                MemoryRestoreMode::OnDemand => mm
+                    .lock()
+                    .unwrap()
+                    .lazy_restore_saved_regions(memory_file_path, mem_snapshot.memory_ranges)?,
+                MemoryRestoreMode::CopyOnWrite => mm
+                    .lock()
+                    .unwrap()
+                    .map_saved_regions(memory_file_path, mem_snapshot.memory_ranges)?,
+            }
 
             Ok(mm)
         } else {
@@ -1307,6 +1905,59 @@ impl MemoryManager {
         }
     }
 
+    // Applies the policy to the allocations of [addr, addr + len), moving the
+    // pages already allocated to the host NUMA node.
+    fn apply_host_numa_policy(
+        addr: *mut u8,
+        len: u64,
+        node: u32,
+        policy: HostNumaPolicy,
+    ) -> Result<(), io::Error> {
+        // Policies are enforced by using MPOL_MF_MOVE flag as it will
+        // force the kernel to move all pages that might have been already
+        // allocated to the proper set of NUMA nodes. MPOL_MF_STRICT is
+        // used to throw an error if MPOL_MF_MOVE didn't succeed, unless
+        // the allocations are allowed to fall back to other nodes.
+        let flags = match policy {
+            HostNumaPolicy::Bind => MPOL_MF_STRICT | MPOL_MF_MOVE,
+            HostNumaPolicy::Preferred => MPOL_MF_MOVE,
+        };
+        Self::set_host_numa_policy(addr, len, node, policy, flags)
+    }
+
+    fn set_host_numa_policy(
+        addr: *mut u8,
+        len: u64,
+        node: u32,
+        policy: HostNumaPolicy,
+        flags: u32,
+    ) -> Result<(), io::Error> {
+        // MPOL_BIND specifies a strict policy that restricts memory
+        // allocation to the nodes specified in the nodemask, while
+        // MPOL_PREFERRED falls back to the other nodes when the preferred
+        // one is exhausted.
+        let mode = match policy {
+            HostNumaPolicy::Bind => MPOL_BIND,
+            HostNumaPolicy::Preferred => MPOL_PREFERRED,
+        };
+        let mut nodemask: Vec<u64> = Vec::new();
+
+        // Linux is kind of buggy in the way it interprets maxnode as it
+        // will cut off the last node. That's why we have to add 1 to what
+        // we would consider as the proper maxnode value.
+        let maxnode = node as u64 + 1 + 1;
+
+        // Allocate the right size for the vector.
+        nodemask.resize((node as usize / 64) + 1, 0);
+
+        // Fill the global bitmask through the nodemask vector.
+        let idx = (node / 64) as usize;
+        let shift = node % 64;
+        nodemask[idx] |= 1u64 << shift;
+
+        Self::mbind(addr, len, mode, nodemask, maxnode, flags)
+    }
+
     fn mbind(
         addr: *mut u8,
         len: u64,
@@ -1335,37 +1986,55 @@ impl MemoryManager {
         }
     }
 
+    // Moves the pages of [addr, addr + len) to the host NUMA node, returning
+    // the number of allocated pages which couldn't be moved.
+    fn move_pages(addr: *mut u8, len: u64, page_size: u64, node: u32) -> Result<u64, io::Error> {
+        let pages: Vec<*mut libc::c_void> = (0..len)
+            .step_by(page_size as usize)
+            .map(|offset| addr.wrapping_add(offset as usize) as *mut libc::c_void)
+            .collect();
+        let nodes = vec![node as libc::c_int; pages.len()];
+        let mut status = vec![0 as libc::c_int; pages.len()];
+
+        // SAFETY: FFI call with arrays of the same length, moving pages of
+        // the VMM only.
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_move_pages,
+                0,
+                pages.len() as libc::c_ulong,
+                pages.as_ptr(),
+                nodes.as_ptr(),
+                status.as_mut_ptr(),
+                MPOL_MF_MOVE as libc::c_int,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The pages not allocated yet are allocated according to the new
+        // policy once the guest accesses them.
+        Ok(status
+            .iter()
+            .filter(|status| **status < 0 && **status != -libc::ENOENT)
+            .count() as u64)
+    }
+
     fn create_anonymous_file(
         size: usize,
         hugepages: bool,
         hugepage_size: Option<u64>,
+        reserve: bool,
     ) -> Result<FileOffset, Error> {
-        let fd = Self::memfd_create(
-            &ffi::CString::new("ch_ram").unwrap(),
-            libc::MFD_CLOEXEC
-                | if hugepages {
-                    libc::MFD_HUGETLB
-                        | if let Some(hugepage_size) = hugepage_size {
-                            /*
-                             * From the Linux kernel:
-                             * Several system calls take a flag to request "hugetlb" huge pages.
-                             * Without further specification, these system calls will use the
-                             * system's default huge page size.  If a system supports multiple
-                             * huge page sizes, the desired huge page size can be specified in
-                             * bits [26:31] of the flag arguments.  The value in these 6 bits
-                             * will encode the log2 of the huge page size.
-                             */
-
-                            hugepage_size.trailing_zeros() << 26
-                        } else {
-                            // Use the system default huge page size
-                            0
-                        }
-                } else {
-                    0
-                },
-        )
-        .map_err(Error::SharedFileCreate)?;
+        if hugepages {
+            let f = hugetlbfs::create_file(size as u64, hugepage_size, reserve)
+                .map_err(Error::HugePages)?;
+            return Ok(FileOffset::new(f, 0));
+        }
+
+        let fd = Self::memfd_create(&ffi::CString::new("ch_ram").unwrap(), libc::MFD_CLOEXEC)
+            .map_err(Error::SharedFileCreate)?;
 
         // SAFETY: fd is valid
         let f = unsafe { File::from_raw_fd(fd) };
@@ -1398,7 +2067,9 @@ impl MemoryManager {
         shared: bool,
         hugepages: bool,
         hugepage_size: Option<u64>,
+        reserve: bool,
         host_numa_node: Option<u32>,
+        host_numa_policy: HostNumaPolicy,
         existing_memory_file: Option<File>,
         thp: bool,
     ) -> Result<Arc<GuestRegionMmap>, Error> {
@@ -1422,7 +2093,19 @@ impl MemoryManager {
             // because the MAP_PRIVATE will trigger CoW against the backing file with
             // the VFIO pinning
             mmap_flags |= libc::MAP_SHARED;
-            Some(Self::create_anonymous_file(size, hugepages, hugepage_size)?)
+            // Reserve the hugepages when mapping the file, so that the guest
+            // can't be killed by SIGBUS later on if the pool gets exhausted.
+            // Regions backing virtio-mem are populated as memory gets plugged
+            // and are left unreserved.
+            if hugepages && reserve {
+                mmap_flags &= !libc::MAP_NORESERVE;
+            }
+            Some(Self::create_anonymous_file(
+                size,
+                hugepages,
+                hugepage_size,
+                reserve,
+            )?)
         } else {
             mmap_flags |= libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
             None
@@ -1437,34 +2120,13 @@ impl MemoryManager {
 
         // Apply NUMA policy if needed.
         if let Some(node) = host_numa_node {
-            let addr = region.deref().as_ptr();
-            let len = region.deref().size() as u64;
-            let mode = MPOL_BIND;
-            let mut nodemask: Vec<u64> = Vec::new();
-            let flags = MPOL_MF_STRICT | MPOL_MF_MOVE;
-
-            // Linux is kind of buggy in the way it interprets maxnode as it
-            // will cut off the last node. That's why we have to add 1 to what
-            // we would consider as the proper maxnode value.
-            let maxnode = node as u64 + 1 + 1;
-
-            // Allocate the right size for the vector.
-            nodemask.resize((node as usize / 64) + 1, 0);
-
-            // Fill the global bitmask through the nodemask vector.
-            let idx = (node / 64) as usize;
-            let shift = node % 64;
-            nodemask[idx] |= 1u64 << shift;
-
-            // Policies are enforced by using MPOL_MF_MOVE flag as it will
-            // force the kernel to move all pages that might have been already
-            // allocated to the proper set of NUMA nodes. MPOL_MF_STRICT is
-            // used to throw an error if MPOL_MF_MOVE didn't succeed.
-            // MPOL_BIND is the selected mode as it specifies a strict policy
-            // that restricts memory allocation to the nodes specified in the
-            // nodemask.
-            Self::mbind(addr, len, mode, nodemask, maxnode, flags)
-                .map_err(Error::ApplyNumaPolicy)?;
+            Self::apply_host_numa_policy(
+                region.deref().as_ptr(),
+                region.deref().size() as u64,
+                node,
+                host_numa_policy,
+            )
+            .map_err(Error::ApplyNumaPolicy)?;
         }
 
         // Prefault the region if needed, in parallel.
@@ -1588,6 +2250,7 @@ impl MemoryManager {
             .memory()
             .insert_region(region)
             .map_err(Error::GuestMemory)?;
+        crate::mce::set_guest_memory(&guest_memory);
         self.guest_memory.lock().unwrap().replace(guest_memory);
 
         Ok(())
@@ -1633,7 +2296,9 @@ impl MemoryManager {
             self.shared,
             self.hugepages,
             self.hugepage_size,
+            true,
             None,
+            HostNumaPolicy::Bind,
             None,
             self.thp,
         )?;
@@ -1661,6 +2326,45 @@ impl MemoryManager {
         Ok(region)
     }
 
+    /// Adds RAM regions backing the pages of the given GPAs the guest RAM
+    /// doesn't back, such as the firmware an IGVM file loads right below
+    /// 4GiB. The contiguous pages are backed by a single region.
+    #[cfg(feature = "igvm")]
+    pub fn add_ram_regions_for_pages(
+        &mut self,
+        gpas: impl IntoIterator<Item = u64>,
+    ) -> Result<(), Error> {
+        let page_size = 4096;
+        let mut pages: Vec<u64> = {
+            let guest_memory = self.guest_memory.memory();
+            gpas.into_iter()
+                .map(|gpa| gpa & !(page_size - 1))
+                .filter(|gpa| !guest_memory.address_in_range(GuestAddress(*gpa)))
+                .collect()
+        };
+        pages.sort_unstable();
+        pages.dedup();
+
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for gpa in pages {
+            match ranges.last_mut() {
+                Some((start, size)) if *start + *size == gpa => *size += page_size,
+                _ => ranges.push((gpa, page_size)),
+            }
+        }
+
+        for (start, size) in ranges {
+            info!(
+                "Adding RAM region for the pages at 0x{:x}-0x{:x}",
+                start,
+                start + size - 1
+            );
+            self.add_ram_region(GuestAddress(start), size as usize)?;
+        }
+
+        Ok(())
+    }
+
     fn hotplug_ram_region(&mut self, size: usize) -> Result<Arc<GuestRegionMmap>, Error> {
         info!("Hotplugging new RAM: {}", size);
 
@@ -1670,13 +2374,16 @@ impl MemoryManager {
         }
 
         // "Inserted" DIMM must have a size that is a multiple of 128MiB
-        if size % (128 << 20) != 0 {
+        if size == 0 || size % (128 << 20) != 0 {
             return Err(Error::InvalidSize);
         }
 
         let start_addr = MemoryManager::start_addr(self.guest_memory.memory().last_addr(), true)?;
 
-        if start_addr.checked_add(size.try_into().unwrap()).unwrap() >= self.end_of_ram_area {
+        let end_addr = start_addr
+            .checked_add(size as u64 - 1)
+            .ok_or(Error::GuestAddressOverFlow)?;
+        if end_addr > self.end_of_ram_area {
             return Err(Error::InsufficientHotplugRam);
         }
 
@@ -1709,6 +2416,140 @@ impl MemoryManager {
         self.guest_memory.clone()
     }
 
+    /// Tracks the private memory of the SEV-SNP guest.
+    #[cfg(feature = "sev_snp")]
+    pub fn enable_private_memory(&mut self) -> Arc<PrivateMemory> {
+        self.private_memory
+            .get_or_insert_with(|| Arc::new(PrivateMemory::new(self.guest_memory.clone())))
+            .clone()
+    }
+
+    #[cfg(feature = "sev_snp")]
+    pub fn private_memory(&self) -> Option<&Arc<PrivateMemory>> {
+        self.private_memory.as_ref()
+    }
+
+    /// Moves the guest memory to the host NUMA node `node`, and binds it to
+    /// the node. The memory is moved by chunks, the guest only waiting on the
+    /// pages being moved when it accesses them.
+    pub fn migrate_to_host_numa_node(&self, node: u32) -> Result<(), Error> {
+        let guest_memory = self.guest_memory.memory();
+        for region in guest_memory.iter() {
+            let mut offset = 0;
+            while offset < region.len() {
+                let len = std::cmp::min(region.len() - offset, HOST_NUMA_MIGRATION_CHUNK_SIZE);
+                Self::apply_host_numa_policy(
+                    region.as_ptr().wrapping_add(offset as usize),
+                    len,
+                    node,
+                    HostNumaPolicy::Bind,
+                )
+                .map_err(Error::ApplyNumaPolicy)?;
+                offset += len;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Changes the host NUMA node and policy of the memory zone `id`. The
+    /// policy applies to the allocations to come, while the pages already
+    /// allocated are moved to the node by chunks. Returns the number of
+    /// pages which couldn't be moved, e.g. because they are pinned.
+    pub fn set_zone_host_numa_policy(
+        &self,
+        id: &str,
+        node: u32,
+        policy: HostNumaPolicy,
+    ) -> Result<u64, Error> {
+        let memory_zone = self.memory_zones.get(id).ok_or(Error::UnknownMemoryZone)?;
+        let page_size = self.zone_page_sizes.get(id).copied().unwrap_or_else(|| {
+            // SAFETY: FFI call. Trivially safe.
+            unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+        });
+
+        let mut not_moved = 0;
+        let regions = memory_zone.regions.iter().chain(
+            memory_zone
+                .virtio_mem_zone
+                .as_ref()
+                .map(|zone| &zone.region),
+        );
+        for region in regions {
+            let mut offset = 0;
+            while offset < region.len() {
+                let len = std::cmp::min(region.len() - offset, HOST_NUMA_MIGRATION_CHUNK_SIZE);
+                let addr = region.as_ptr().wrapping_add(offset as usize);
+                Self::set_host_numa_policy(addr, len, node, policy, 0)
+                    .map_err(Error::ApplyNumaPolicy)?;
+                not_moved +=
+                    Self::move_pages(addr, len, page_size, node).map_err(Error::ApplyNumaPolicy)?;
+                offset += len;
+            }
+        }
+
+        Ok(not_moved)
+    }
+
+    /// Replaces the poisoned page of `size` bytes containing `gpa` with a
+    /// new zeroed page, for the guest to access it again without hitting
+    /// the memory error.
+    pub fn replace_poisoned_page(&self, gpa: GuestAddress, size: u64) -> Result<(), Error> {
+        let guest_memory = self.guest_memory.memory();
+        let region = guest_memory
+            .find_region(gpa)
+            .ok_or(Error::ReplacePoisonedPage(io::Error::from(
+                io::ErrorKind::InvalidInput,
+            )))?;
+        let offset = align_down(gpa.raw_value() - region.start_addr().raw_value(), size);
+        let host_addr = region.as_ptr() as u64 + offset;
+
+        // The page is dropped from the file backing shared memory, which is
+        // mapped again. Private memory gets an anonymous page.
+        let (flags, fd, file_offset) = match region.file_offset() {
+            Some(file_offset) if region.flags() & MAP_SHARED != 0 => {
+                let fd = file_offset.file().as_raw_fd();
+                let file_offset = file_offset.start() + offset;
+                // SAFETY: FFI call with valid arguments
+                if unsafe {
+                    libc::fallocate64(
+                        fd,
+                        libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                        file_offset as libc::off64_t,
+                        size as libc::off64_t,
+                    )
+                } != 0
+                {
+                    return Err(Error::ReplacePoisonedPage(io::Error::last_os_error()));
+                }
+                (MAP_SHARED, fd, file_offset)
+            }
+            _ => (
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | MAP_NORESERVE,
+                -1,
+                0,
+            ),
+        };
+
+        // SAFETY: the range belongs to the mapping of the guest memory, which
+        // is replaced in place.
+        let ret = unsafe {
+            libc::mmap(
+                host_addr as *mut libc::c_void,
+                size as libc::size_t,
+                PROT_READ | PROT_WRITE,
+                flags | libc::MAP_FIXED,
+                fd,
+                file_offset as libc::off_t,
+            )
+        };
+        if ret == libc::MAP_FAILED {
+            return Err(Error::ReplacePoisonedPage(io::Error::last_os_error()));
+        }
+
+        Ok(())
+    }
+
     pub fn boot_guest_memory(&self) -> GuestMemoryMmap {
         self.boot_guest_memory.clone()
     }
@@ -1759,18 +2600,22 @@ impl MemoryManager {
             .create_user_memory_region(mem_region)
             .map_err(Error::CreateUserMemoryRegion)?;
 
-        // SAFETY: the address and size are valid since the
-        // mmap succeeded.
-        let ret = unsafe {
-            libc::madvise(
-                userspace_addr as *mut libc::c_void,
-                memory_size as libc::size_t,
-                libc::MADV_DONTDUMP,
-            )
-        };
-        if ret != 0 {
-            let e = io::Error::last_os_error();
-            warn!("Failed to mark mapping as MADV_DONTDUMP: {}", e);
+        // Keep the guest memory out of the core dumps unless asked for.
+        if !self.coredump {
+            // SAFETY: the address and size are valid since the
+            // mmap succeeded.
+            let ret = unsafe {
+                libc::madvise(
+                    userspace_addr as *mut libc::c_void,
+                    memory_size as libc::size_t,
+                    libc::MADV_DONTDUMP,
+                )
+            };
+            if ret != 0 {
+                let e = io::Error::last_os_error();
+                warn!("Failed to mark mapping as MADV_DONTDUMP: {}", e);
+            }
+            crate::crash_report::exclude_guest_memory(userspace_addr, memory_size);
         }
 
         // Mark the pages as mergeable if explicitly asked for.
@@ -1827,6 +2672,8 @@ impl MemoryManager {
             .remove_user_memory_region(mem_region)
             .map_err(Error::RemoveUserMemoryRegion)?;
 
+        crate::crash_report::include_guest_memory(userspace_addr);
+
         // Mark the pages as unmergeable if there were previously marked as
         // mergeable.
         if mergeable {
@@ -2059,6 +2906,53 @@ impl MemoryManager {
         unsafe { (*stat.as_ptr()).st_nlink as usize > 0 }
     }
 
+    pub fn counters(&self) -> HashMap<String, HashMap<&'static str, Wrapping<u64>>> {
+        let mut counters = HashMap::new();
+
+        let mut process_counters = HashMap::new();
+        let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+        // SAFETY: FFI call with a valid rusage structure
+        if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } == 0 {
+            // SAFETY: getrusage() succeeded and initialized the structure
+            let usage = unsafe { usage.assume_init() };
+            process_counters.insert("major_faults", Wrapping(usage.ru_majflt as u64));
+            process_counters.insert("minor_faults", Wrapping(usage.ru_minflt as u64));
+        } else {
+            warn!(
+                "Failed to get resource usage: {}",
+                io::Error::last_os_error()
+            );
+        }
+        counters.insert("memory".to_string(), process_counters);
+
+        for (id, memory_zone) in self.memory_zones.iter() {
+            let mut zone_counters = HashMap::new();
+            let mut regions = memory_zone.regions().clone();
+            if let Some(virtio_mem_zone) = memory_zone.virtio_mem_zone() {
+                regions.push(virtio_mem_zone.region().clone());
+            }
+
+            let mut size = 0;
+            let mut resident = 0;
+            for region in regions.iter() {
+                size += region.len();
+                match region_resident_size(region) {
+                    Ok(r) => resident += r,
+                    Err(e) => warn!("Failed to get resident size of memory zone '{}': {}", id, e),
+                }
+            }
+            zone_counters.insert("size_bytes", Wrapping(size));
+            zone_counters.insert("resident_bytes", Wrapping(resident));
+            if let Some(page_size) = self.zone_page_sizes.get(id) {
+                zone_counters.insert("page_size", Wrapping(*page_size));
+            }
+
+            counters.insert(format!("memory_zone_{id}"), zone_counters);
+        }
+
+        counters
+    }
+
     pub fn memory_zones(&self) -> &MemoryZones {
         &self.memory_zones
     }
@@ -2214,6 +3108,13 @@ impl MemoryManager {
         Ok(())
     }
 
+    // Largest memory range table the source can send, as each range covers at
+    // least a 4KiB page of the guest memory.
+    pub fn max_memory_range_table_length(&self) -> u64 {
+        let size: u64 = self.guest_memory.memory().iter().map(|r| r.len()).sum();
+        (size >> 12) * std::mem::size_of::<MemoryRange>() as u64
+    }
+
     pub fn receive_memory_regions<F>(
         &mut self,
         ranges: &MemoryRangeTable,
@@ -2502,6 +3403,16 @@ impl Aml for MemoryMethods {
     }
 }
 
+impl Drop for MemoryManager {
+    fn drop(&mut self) {
+        // The guest memory is unmapped along with the last reference to it,
+        // the crash reports must not advise the addresses it leaves behind.
+        for region in self.guest_memory.memory().iter() {
+            crate::crash_report::include_guest_memory(region.as_ptr() as u64);
+        }
+    }
+}
+
 impl Aml for MemoryManager {
     fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
         if let Some(acpi_address) = self.acpi_address {
@@ -2690,6 +3601,14 @@ impl Transportable for MemoryManager {
             .map_err(|e| MigratableError::MigrateSend(e.into()))?;
 
         let guest_memory = self.guest_memory.memory();
+        let total: u64 = self
+            .snapshot_memory_ranges
+            .regions()
+            .iter()
+            .map(|range| range.length)
+            .sum::<u64>()
+            .max(1);
+        let mut done = 0;
 
         for range in self.snapshot_memory_ranges.regions() {
             let mut offset: u64 = 0;
@@ -2699,6 +3618,10 @@ impl Transportable for MemoryManager {
             // following the correct behavior. For more info about this issue
             // see: https://github.com/rust-vmm/vm-memory/issues/174
             loop {
+                if operations::cancel_requested() {
+                    return Err(MigratableError::MigrateSend(anyhow!("Snapshot cancelled")));
+                }
+
                 let bytes_written = guest_memory
                     .write_volatile_to(
                         GuestAddress(range.gpa + offset),
@@ -2707,6 +3630,9 @@ impl Transportable for MemoryManager {
                     )
                     .map_err(|e| MigratableError::MigrateSend(e.into()))?;
                 offset += bytes_written as u64;
+                done += bytes_written as u64;
+                // Writing the memory is the bulk of the snapshot.
+                operations::report_progress((10 + done * 90 / total) as u8);
 
                 if offset == range.length {
                     break;
@@ -2787,3 +3713,70 @@ impl Migratable for MemoryManager {
         Ok(table)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_memory::{Bytes, MemoryRegionAddress};
+
+    #[test]
+    fn test_region_resident_size() {
+        // SAFETY: FFI call. Trivially safe.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+        let region =
+            GuestRegionMmap::new(MmapRegion::new(4 * page_size).unwrap(), GuestAddress(0)).unwrap();
+        assert_eq!(region_resident_size(&region).unwrap(), 0);
+
+        // Only the pages touched are resident.
+        region.write_obj(1u8, MemoryRegionAddress(0)).unwrap();
+        region
+            .write_obj(1u8, MemoryRegionAddress(2 * page_size as u64 + 8))
+            .unwrap();
+        assert_eq!(region_resident_size(&region).unwrap(), 2 * page_size as u64);
+    }
+
+    #[cfg(feature = "sev_snp")]
+    #[test]
+    fn test_private_memory_conversions() {
+        let private_memory = PrivateMemory::new(GuestMemoryAtomic::new(
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap(),
+        ));
+        assert!(private_memory.is_private(0x1000));
+
+        private_memory.convert(0x1000, 0x2000, false);
+        private_memory.convert(0x3000, 0x1000, false);
+        assert_eq!(
+            *private_memory.shared.lock().unwrap(),
+            BTreeMap::from([(0x1000, 0x4000)])
+        );
+
+        private_memory.convert(0x2000, 0x1000, true);
+        assert_eq!(
+            *private_memory.shared.lock().unwrap(),
+            BTreeMap::from([(0x1000, 0x2000), (0x3000, 0x4000)])
+        );
+        assert!(!private_memory.is_private(0x1fff));
+        assert!(private_memory.is_private(0x2000));
+        assert!(!private_memory.is_private(0x3000));
+        assert!(private_memory.is_private(0x4000));
+    }
+
+    #[cfg(feature = "sev_snp")]
+    #[test]
+    fn test_private_memory_unaccepted() {
+        let private_memory = PrivateMemory::new(GuestMemoryAtomic::new(
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap(),
+        ));
+        private_memory.leave_unaccepted(0x1000, 0x1000);
+        private_memory.leave_unaccepted(0x2000, 0x3000);
+        assert_eq!(private_memory.unaccepted_size(), 0x4000);
+
+        private_memory.convert(0x2000, 0x1000, true);
+        private_memory.convert(0x4000, 0x2000, false);
+        assert_eq!(
+            *private_memory.unaccepted.lock().unwrap(),
+            BTreeMap::from([(0x1000, 0x2000), (0x3000, 0x4000)])
+        );
+        assert_eq!(private_memory.unaccepted_size(), 0x2000);
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Reporting of the seccomp violations.
+//!
+//! A syscall which isn't allowed by the seccomp filter of its thread raises a
+//! SIGSYS, which kills the process right away. When the violations are
+//! reported instead, the SIGSYS handler makes the syscall fail with `ENOSYS`
+//! and only passes the syscall number and the address it was issued from to
+//! a reporting thread, through a pipe, as nothing else is async signal safe.
+//! The reporting thread logs the violation, sends the event and asks the VMM
+//! to exit. The VMM then terminates through its usual path, which gets the
+//! report to the log and to the event monitor.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::thread;
+use vm_memory::ByteValued;
+use vmm_sys_util::eventfd::EventFd;
+
+static VIOLATIONS: AtomicU64 = AtomicU64::new(0);
+// Write end of the pipe to the reporting thread.
+static REPORT_FD: AtomicI32 = AtomicI32::new(-1);
+
+// Layout of the siginfo_t of a SIGSYS, which the libc crate doesn't expose.
+#[repr(C)]
+struct SigsysInfo {
+    _signo: libc::c_int,
+    _errno: libc::c_int,
+    _code: libc::c_int,
+    call_addr: *mut libc::c_void,
+    syscall: libc::c_int,
+    _arch: libc::c_uint,
+}
+
+// What the handler sends to the reporting thread, small enough to be written
+// atomically to the pipe.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Violation {
+    syscall: i64,
+    call_addr: u64,
+}
+
+// SAFETY: Violation only contains integers.
+unsafe impl ByteValued for Violation {}
+
+/// Number of seccomp violations reported so far.
+pub fn violations() -> u64 {
+    VIOLATIONS.load(Ordering::Relaxed)
+}
+
+// Locates `addr` in the mappings of the process, as the path of the mapped
+// file and the offset in that file, which addr2line can resolve.
+pub(crate) fn locate(maps: &str, addr: u64) -> Option<(&str, u64)> {
+    maps.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let (start, end) = fields.next()?.split_once('-')?;
+        let start = u64::from_str_radix(start, 16).ok()?;
+        let end = u64::from_str_radix(end, 16).ok()?;
+        let offset = u64::from_str_radix(fields.nth(1)?, 16).ok()?;
+        let path = fields.nth(2).unwrap_or("[anonymous]");
+        (start..end)
+            .contains(&addr)
+            .then(|| (path, addr - start + offset))
+    })
+}
+
+fn report(violation: &Violation, exit_evt: &EventFd) {
+    let count = VIOLATIONS.fetch_add(1, Ordering::Relaxed) + 1;
+    let maps = fs::read_to_string("/proc/self/maps").unwrap_or_default();
+    let location = match locate(&maps, violation.call_addr) {
+        Some((path, offset)) => format!("{path}+{offset:#x}"),
+        None => format!("{:#x}", violation.call_addr),
+    };
+
+    error!(
+        "Seccomp violation: syscall {} from {}",
+        violation.syscall, location
+    );
+    event!(
+        "vmm",
+        "seccomp-violation",
+        "syscall",
+        violation.syscall.to_string(),
+        "location",
+        location,
+        "count",
+        count.to_string()
+    );
+
+    if let Err(e) = exit_evt.write(1) {
+        error!("Failed to ask the VMM to exit: {}", e);
+    }
+}
+
+/// Reports the seccomp violations rather than being killed by them, the VMM
+/// being asked to exit through `exit_evt`.
+pub fn enable(exit_evt: &EventFd) -> io::Result<()> {
+    let exit_evt = exit_evt.try_clone()?;
+
+    let mut fds = [-1; 2];
+    // SAFETY: FFI call with a valid array of two fds.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: The read end of the pipe was just created and is owned by the
+    // reporting thread. The write end is kept open for the lifetime of the
+    // process.
+    let mut reports = unsafe { File::from_raw_fd(fds[0]) };
+    REPORT_FD.store(fds[1], Ordering::Relaxed);
+
+    thread::Builder::new()
+        .name("seccomp_report".to_string())
+        .spawn(move || {
+            let mut violation = Violation::default();
+            while reports.read_exact(violation.as_mut_slice()).is_ok() {
+                report(&violation, &exit_evt);
+            }
+        })?;
+
+    // SAFETY: Zero-initialized sigaction, filled below.
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handle_sigsys as usize;
+    action.sa_flags = libc::SA_SIGINFO;
+
+    // SAFETY: FFI call with a valid sigaction.
+    if unsafe { libc::sigaction(libc::SIGSYS, &action, std::ptr::null_mut()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// Only async signal safe operations are allowed here: reading the siginfo,
+// updating the context and writing to the pipe, as every thread filter allows
+// write(2). Another violation from within the handler finds SIGSYS blocked,
+// in which case the kernel kills the process as if the violations weren't
+// reported.
+extern "C" fn handle_sigsys(
+    _: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    // SAFETY: The kernel passes the siginfo of the SIGSYS.
+    let info = unsafe { &*(info as *const SigsysInfo) };
+    let violation = Violation {
+        syscall: info.syscall as i64,
+        call_addr: info.call_addr as u64,
+    };
+
+    // SAFETY: The kernel passes the context of the thread, whose return value
+    // register is overwritten.
+    unsafe {
+        let context = &mut *(context as *mut libc::ucontext_t);
+        #[cfg(target_arch = "x86_64")]
+        {
+            context.uc_mcontext.gregs[libc::REG_RAX as usize] = -libc::ENOSYS as i64;
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            context.uc_mcontext.regs[0] = -libc::ENOSYS as u64;
+        }
+    }
+
+    // SAFETY: FFI call writing a buffer of the given size to the pipe, which
+    // is async signal safe.
+    unsafe {
+        libc::write(
+            REPORT_FD.load(Ordering::Relaxed),
+            violation.as_slice().as_ptr() as *const libc::c_void,
+            violation.as_slice().len(),
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+
+    #[test]
+    fn test_locate() {
+        let maps = "55d4c1a00000-55d4c1c00000 r--p 00000000 fd:01 1234 /usr/bin/cloud-hypervisor\n\
+                    55d4c1c00000-55d4c2000000 r-xp 00200000 fd:01 1234 /usr/bin/cloud-hypervisor\n\
+                    7f0000000000-7f0000021000 rw-p 00000000 00:00 0\n";
+        assert_eq!(
+            locate(maps, 0x55d4c1c01234),
+            Some(("/usr/bin/cloud-hypervisor", 0x201234))
+        );
+        assert_eq!(locate(maps, 0x7f0000000010), Some(("[anonymous]", 0x10)));
+        assert_eq!(locate(maps, 0x1000), None);
+    }
+
+    #[test]
+    fn test_report_violation() {
+        let exit_evt = EventFd::new(0).unwrap();
+        enable(&exit_evt).unwrap();
+
+        // Only getppid is denied, to the thread issuing it.
+        let result = thread::spawn(|| {
+            let filter = SeccompFilter::new(
+                vec![(libc::SYS_getppid, vec![])].into_iter().collect(),
+                SeccompAction::Allow,
+                SeccompAction::Trap,
+                std::env::consts::ARCH.try_into().unwrap(),
+            )
+            .unwrap();
+            let filter: BpfProgram = filter.try_into().unwrap();
+            seccompiler::apply_filter(&filter).unwrap();
+
+            // SAFETY: FFI call without arguments.
+            let ret = unsafe { libc::syscall(libc::SYS_getppid) };
+            (ret, io::Error::last_os_error().raw_os_error())
+        })
+        .join()
+        .unwrap();
+
+        // The syscall failed rather than killing the process, and the VMM is
+        // asked to exit once the violation is reported.
+        assert_eq!(result, (-1, Some(libc::ENOSYS)));
+        assert_eq!(exit_evt.read().unwrap(), 1);
+        assert_eq!(violations(), 1);
+    }
+}
@@ -0,0 +1,444 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Self-test of the host, run through `cloud-hypervisor --self-test`.
+//!
+//! The checks cover what the VMM needs from the host to run VMs: access to
+//! the hypervisor, the hugepage pool and the backends of the virtio-blk,
+//! virtio-net and virtio-console devices. A built-in guest is then booted in
+//! a throwaway VM, writing to the serial port and exercising the PIO and MMIO
+//! exit paths, which gives the cost of a VM exit on this host.
+
+use crate::console_devices;
+use crate::hugetlbfs;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+use vmm_sys_util::tempfile::TempFile;
+
+#[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+mod guest {
+    use crate::GuestMemoryMmap;
+    use hypervisor::{HypervisorType, HypervisorVmError, VmExit, VmOps};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use vm_memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryRegion};
+
+    const LOAD_ADDR: u64 = 0x1000;
+    // Number of PIO and MMIO exits triggered by the guest.
+    pub const EXIT_COUNT: u16 = 10000;
+    const SERIAL_PORT: u64 = 0x3f8;
+    const POST_PORT: u64 = 0x80;
+    // Not backed by any memory, so that writing to it is an MMIO exit.
+    const MMIO_ADDR: u64 = 0x8000;
+    pub const GREETING: &[u8] = b"ok\n";
+
+    // Real mode code, run from LOAD_ADDR.
+    #[rustfmt::skip]
+    const CODE: [u8; 28] = [
+        0xba, 0xf8, 0x03, /* mov $0x3f8, %dx */
+        0xb0, b'o', /* mov $'o', %al */
+        0xee, /* out %al, (%dx) */
+        0xb0, b'k', /* mov $'k', %al */
+        0xee, /* out %al, (%dx) */
+        0xb0, b'\n', /* mov $'\n', %al */
+        0xee, /* out %al, (%dx) */
+        0xb9, EXIT_COUNT as u8, (EXIT_COUNT >> 8) as u8, /* mov $EXIT_COUNT, %cx */
+        0xe6, 0x80, /* 1: out %al, $0x80 */
+        0xe2, 0xfc, /* loop 1b */
+        0xb9, EXIT_COUNT as u8, (EXIT_COUNT >> 8) as u8, /* mov $EXIT_COUNT, %cx */
+        0xa2, 0x00, 0x80, /* 2: mov %al, 0x8000 */
+        0xe2, 0xfb, /* loop 2b */
+        0xf4, /* hlt */
+    ];
+
+    #[derive(Default)]
+    struct SelfTestOps {
+        serial: Mutex<Vec<u8>>,
+        pio_exits: AtomicU64,
+        mmio_exits: AtomicU64,
+    }
+
+    impl VmOps for SelfTestOps {
+        fn guest_mem_write(&self, _gpa: u64, _buf: &[u8]) -> Result<usize, HypervisorVmError> {
+            Ok(0)
+        }
+
+        fn guest_mem_read(&self, _gpa: u64, _buf: &mut [u8]) -> Result<usize, HypervisorVmError> {
+            Ok(0)
+        }
+
+        fn mmio_read(&self, _gpa: u64, _data: &mut [u8]) -> Result<(), HypervisorVmError> {
+            Ok(())
+        }
+
+        fn mmio_write(&self, gpa: u64, _data: &[u8]) -> Result<(), HypervisorVmError> {
+            if gpa == MMIO_ADDR {
+                self.mmio_exits.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        }
+
+        fn pio_read(&self, _port: u64, _data: &mut [u8]) -> Result<(), HypervisorVmError> {
+            Ok(())
+        }
+
+        fn pio_write(&self, port: u64, data: &[u8]) -> Result<(), HypervisorVmError> {
+            match port {
+                SERIAL_PORT => self.serial.lock().unwrap().extend_from_slice(data),
+                POST_PORT => {
+                    self.pio_exits.fetch_add(1, Ordering::Relaxed);
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+    }
+
+    /// Measurements of the built-in guest run.
+    pub struct GuestRun {
+        pub vm_creation: Duration,
+        pub run: Duration,
+        pub serial: Vec<u8>,
+        pub pio_exits: u64,
+        pub mmio_exits: u64,
+    }
+
+    pub fn run(hypervisor: &Arc<dyn hypervisor::Hypervisor>) -> Result<GuestRun, String> {
+        if hypervisor.hypervisor_type() != HypervisorType::Kvm {
+            return Err("the built-in guest only runs on KVM".to_string());
+        }
+
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(LOAD_ADDR), 0x1000)])
+            .map_err(|e| format!("Error allocating the guest memory: {e}"))?;
+        mem.write_slice(&CODE, GuestAddress(LOAD_ADDR))
+            .map_err(|e| format!("Error loading the guest code: {e}"))?;
+
+        let start = Instant::now();
+        let vm = hypervisor
+            .create_vm()
+            .map_err(|e| format!("Error creating the VM: {e}"))?;
+        for (index, region) in mem.iter().enumerate() {
+            let mem_region = vm.make_user_memory_region(
+                index as u32,
+                region.start_addr().raw_value(),
+                region.len(),
+                region.as_ptr() as u64,
+                false,
+                false,
+            );
+            vm.create_user_memory_region(mem_region)
+                .map_err(|e| format!("Error mapping the guest memory: {e}"))?;
+        }
+
+        let ops = Arc::new(SelfTestOps::default());
+        let vcpu = vm
+            .create_vcpu(0, Some(ops.clone()))
+            .map_err(|e| format!("Error creating the vCPU: {e}"))?;
+        let vm_creation = start.elapsed();
+
+        let mut sregs = vcpu
+            .get_sregs()
+            .map_err(|e| format!("Error getting the vCPU special registers: {e}"))?;
+        sregs.cs.base = 0;
+        sregs.cs.selector = 0;
+        vcpu.set_sregs(&sregs)
+            .map_err(|e| format!("Error setting the vCPU special registers: {e}"))?;
+        let mut regs = vcpu
+            .get_regs()
+            .map_err(|e| format!("Error getting the vCPU registers: {e}"))?;
+        regs.set_rip(LOAD_ADDR);
+        regs.set_rflags(2);
+        vcpu.set_regs(&regs)
+            .map_err(|e| format!("Error setting the vCPU registers: {e}"))?;
+
+        let start = Instant::now();
+        loop {
+            match vcpu.run() {
+                Ok(VmExit::Reset) => break,
+                Ok(VmExit::Ignore) => {}
+                Ok(exit) => return Err(format!("Unexpected VM exit: {exit:?}")),
+                Err(e) => return Err(format!("Error running the vCPU: {e}")),
+            }
+        }
+        let run = start.elapsed();
+
+        let serial = ops.serial.lock().unwrap().clone();
+        Ok(GuestRun {
+            vm_creation,
+            run,
+            serial,
+            pio_exits: ops.pio_exits.load(Ordering::Relaxed),
+            mmio_exits: ops.mmio_exits.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// The host can run VMs, but not with every feature.
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+            Status::Skip => "skip",
+        };
+        write!(f, "{status}")
+    }
+}
+
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+}
+
+/// Outcome of the self-test.
+pub struct SelfTestReport {
+    pub features: Vec<String>,
+    pub checks: Vec<Check>,
+}
+
+impl SelfTestReport {
+    fn push(&mut self, name: &'static str, status: Status, message: String) {
+        self.checks.push(Check {
+            name,
+            status,
+            message,
+        });
+    }
+
+    /// Whether the host is able to run VMs.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.status != Status::Fail)
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Enabled features: {}", self.features.join(", "))?;
+        for check in &self.checks {
+            writeln!(f, "[{}] {}: {}", check.status, check.name, check.message)?;
+        }
+        if self.passed() {
+            writeln!(f, "The host is able to run VMs")
+        } else {
+            writeln!(f, "The host is not able to run VMs")
+        }
+    }
+}
+
+fn check_hugepages(report: &mut SelfTestReport) {
+    let pages = hugetlbfs::default_page_size().and_then(|page_size| {
+        hugetlbfs::available_pages(page_size).map(|available| (page_size, available))
+    });
+    match pages {
+        Ok((page_size, 0)) => report.push(
+            "hugepages",
+            Status::Warn,
+            format!("No {} KiB hugepage left in the pool", page_size >> 10),
+        ),
+        Ok((page_size, available)) => report.push(
+            "hugepages",
+            Status::Pass,
+            format!("{available} {} KiB hugepages available", page_size >> 10),
+        ),
+        Err(e) => report.push("hugepages", Status::Warn, e.to_string()),
+    }
+}
+
+// A write and read back through a file, like the ones of the disk images.
+fn check_block(report: &mut SelfTestReport) {
+    let result = TempFile::new().map_err(|e| e.to_string()).and_then(|file| {
+        let data = [0xa5u8; 4096];
+        let mut buf = [0u8; 4096];
+        file.as_file()
+            .write_all_at(&data, 0)
+            .and_then(|_| file.as_file().read_exact_at(&mut buf, 0))
+            .map_err(|e| e.to_string())?;
+        if buf != data {
+            return Err("Data read back differs from the data written".to_string());
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) => report.push(
+            "virtio-blk",
+            Status::Pass,
+            format!(
+                "Disk image I/O works, io_uring {}, AIO {}",
+                supported(block::block_io_uring_is_supported()),
+                supported(block::block_aio_is_supported())
+            ),
+        ),
+        Err(e) => report.push("virtio-blk", Status::Fail, e),
+    }
+}
+
+fn check_net(report: &mut SelfTestReport) {
+    match net_util::Tap::new(1) {
+        Ok(_) => report.push(
+            "virtio-net",
+            Status::Pass,
+            "Tap interfaces can be created".into(),
+        ),
+        Err(e) => report.push(
+            "virtio-net",
+            Status::Warn,
+            format!("Tap interfaces can't be created: {e}"),
+        ),
+    }
+
+    match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/vhost-net")
+    {
+        Ok(_) => report.push("vhost-net", Status::Pass, "/dev/vhost-net is usable".into()),
+        Err(e) => report.push(
+            "vhost-net",
+            Status::Warn,
+            format!("Cannot open /dev/vhost-net: {e}"),
+        ),
+    }
+}
+
+fn check_console(report: &mut SelfTestReport) {
+    match console_devices::create_pty() {
+        Ok(_) => report.push("virtio-console", Status::Pass, "PTYs can be created".into()),
+        Err(e) => report.push(
+            "virtio-console",
+            Status::Fail,
+            format!("Cannot create a PTY: {e}"),
+        ),
+    }
+}
+
+fn supported(supported: bool) -> &'static str {
+    if supported {
+        "supported"
+    } else {
+        "not supported"
+    }
+}
+
+#[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+fn check_guest(report: &mut SelfTestReport, hypervisor: &Arc<dyn hypervisor::Hypervisor>) {
+    match guest::run(hypervisor) {
+        Ok(run) if run.serial != guest::GREETING => report.push(
+            "guest",
+            Status::Fail,
+            format!(
+                "Unexpected serial output from the guest: {:?}",
+                String::from_utf8_lossy(&run.serial)
+            ),
+        ),
+        Ok(run)
+            if run.pio_exits != guest::EXIT_COUNT as u64
+                || run.mmio_exits != guest::EXIT_COUNT as u64 =>
+        {
+            report.push(
+                "guest",
+                Status::Fail,
+                format!(
+                    "{} PIO and {} MMIO exits instead of {}",
+                    run.pio_exits,
+                    run.mmio_exits,
+                    guest::EXIT_COUNT
+                ),
+            )
+        }
+        Ok(run) => report.push(
+            "guest",
+            Status::Pass,
+            format!(
+                "Booted in {:?}, {:.2} us per VM exit",
+                run.vm_creation,
+                run.run.as_secs_f64() * 1_000_000.0 / (run.pio_exits + run.mmio_exits) as f64
+            ),
+        ),
+        Err(e) => report.push("guest", Status::Fail, e),
+    }
+}
+
+#[cfg(not(all(feature = "kvm", target_arch = "x86_64")))]
+fn check_guest(report: &mut SelfTestReport, _hypervisor: &Arc<dyn hypervisor::Hypervisor>) {
+    report.push(
+        "guest",
+        Status::Skip,
+        "The built-in guest only runs on KVM and x86_64".into(),
+    );
+}
+
+/// Runs the self-test, which never stops at the first failure.
+pub fn run() -> SelfTestReport {
+    let mut report = SelfTestReport {
+        features: crate::feature_list(),
+        checks: Vec::new(),
+    };
+
+    let hypervisor = match hypervisor::new() {
+        Ok(hypervisor) => {
+            report.push(
+                "hypervisor",
+                Status::Pass,
+                format!("{:?} is usable", hypervisor.hypervisor_type()),
+            );
+            Some(hypervisor)
+        }
+        Err(e) => {
+            report.push(
+                "hypervisor",
+                Status::Fail,
+                format!("Cannot open the hypervisor: {e}"),
+            );
+            None
+        }
+    };
+
+    check_hugepages(&mut report);
+    check_block(&mut report);
+    check_net(&mut report);
+    check_console(&mut report);
+
+    match hypervisor {
+        Some(hypervisor) => check_guest(&mut report, &hypervisor),
+        None => report.push("guest", Status::Skip, "No usable hypervisor".into()),
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_report_passed() {
+        let mut report = SelfTestReport {
+            features: Vec::new(),
+            checks: Vec::new(),
+        };
+        report.push("hugepages", Status::Warn, String::new());
+        report.push("guest", Status::Skip, String::new());
+        assert!(report.passed());
+
+        report.push("hypervisor", Status::Fail, String::new());
+        assert!(!report.passed());
+        assert!(report
+            .to_string()
+            .contains("The host is not able to run VMs"));
+    }
+}
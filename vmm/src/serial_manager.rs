@@ -4,6 +4,8 @@
 //
 
 use crate::config::ConsoleOutputMode;
+use crate::console_devices::socket_listener;
+use crate::console_log::ConsoleLog;
 #[cfg(target_arch = "aarch64")]
 use devices::legacy::Pl011;
 #[cfg(target_arch = "x86_64")]
@@ -12,10 +14,8 @@ use libc::EFD_NONBLOCK;
 use serial_buffer::SerialBuffer;
 use std::fs::File;
 use std::io::Read;
-use std::net::Shutdown;
 use std::os::fd::RawFd;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
-use std::os::unix::net::{UnixListener, UnixStream};
 use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -66,9 +66,9 @@ pub enum Error {
     #[error("Error accepting connection: {0}")]
     AcceptConnection(#[source] io::Error),
 
-    /// Cannot clone the UnixStream
-    #[error("Error cloning UnixStream: {0}")]
-    CloneUnixStream(#[source] io::Error),
+    /// Cannot clone the client stream
+    #[error("Error cloning the client stream: {0}")]
+    CloneStream(#[source] io::Error),
 
     /// Cannot shutdown the connection
     #[error("Error shutting down a connection: {0}")]
@@ -117,6 +117,32 @@ pub struct SerialManager {
     pty_write_out: Option<Arc<AtomicBool>>,
     mode: ConsoleOutputMode,
     socket_path: Option<PathBuf>,
+    log: Option<ConsoleLog>,
+}
+
+fn is_socket(mode: &ConsoleOutputMode) -> bool {
+    matches!(mode, ConsoleOutputMode::Socket | ConsoleOutputMode::Tcp)
+}
+
+// Closes both directions of the client stream, the serial device keeping a
+// clone of it to write the output to.
+fn shutdown(stream: &File) -> io::Result<()> {
+    // SAFETY: FFI call with a valid socket.
+    if unsafe { libc::shutdown(stream.as_raw_fd(), libc::SHUT_RDWR) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Keeps shipping the serial output when its destination changes.
+fn tee(
+    log: Option<&ConsoleLog>,
+    out: Option<Box<dyn io::Write + Send>>,
+) -> Option<Box<dyn io::Write + Send>> {
+    match log {
+        Some(log) => Some(log.writer(out)),
+        None => out,
+    }
 }
 
 impl SerialManager {
@@ -124,17 +150,19 @@ impl SerialManager {
         #[cfg(target_arch = "x86_64")] serial: Arc<Mutex<Serial>>,
         #[cfg(target_arch = "aarch64")] serial: Arc<Mutex<Pl011>>,
         main_fd: Option<RawFd>,
+        input_fd: Option<RawFd>,
         mode: ConsoleOutputMode,
         socket: Option<PathBuf>,
+        log: Option<ConsoleLog>,
     ) -> Result<Option<Self>> {
         let mut socket_path: Option<PathBuf> = None;
 
         let in_file = match mode {
-            ConsoleOutputMode::Pty => {
-                if let Some(pty_main) = main_fd {
-                    // SAFETY: pty_main is guaranteed to be a valid fd from
+            ConsoleOutputMode::Pty | ConsoleOutputMode::Pipe => {
+                if let Some(main) = main_fd {
+                    // SAFETY: main is guaranteed to be a valid fd from
                     // pre_create_console_devices() in vmm/src/console_devices.rs
-                    unsafe { File::from_raw_fd(pty_main) }
+                    unsafe { File::from_raw_fd(main) }
                 } else {
                     return Ok(None);
                 }
@@ -166,7 +194,7 @@ impl SerialManager {
                     return Ok(None);
                 }
             }
-            ConsoleOutputMode::Socket => {
+            ConsoleOutputMode::Socket | ConsoleOutputMode::Tcp => {
                 if let Some(socket_fd) = main_fd {
                     if let Some(path_in_socket) = socket {
                         socket_path = Some(path_in_socket.clone());
@@ -181,6 +209,14 @@ impl SerialManager {
             _ => return Ok(None),
         };
 
+        // The input of a pair of named pipes is read from a separate file.
+        let (in_file, out_file) = match input_fd {
+            // SAFETY: input is guaranteed to be a valid fd from
+            // pre_create_console_devices() in vmm/src/console_devices.rs
+            Some(input) => (unsafe { File::from_raw_fd(input) }, Some(in_file)),
+            None => (in_file, None),
+        };
+
         let epoll_fd = epoll::create(true).map_err(Error::Epoll)?;
         let kill_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFd)?;
 
@@ -192,7 +228,7 @@ impl SerialManager {
         )
         .map_err(Error::Epoll)?;
 
-        let epoll_fd_data = if mode == ConsoleOutputMode::Socket {
+        let epoll_fd_data = if is_socket(&mode) {
             EpollDispatch::Socket
         } else {
             EpollDispatch::File
@@ -216,7 +252,17 @@ impl SerialManager {
                 .as_ref()
                 .lock()
                 .unwrap()
-                .set_out(Some(Box::new(buffer)));
+                .set_out(tee(log.as_ref(), Some(Box::new(buffer))));
+        } else if mode == ConsoleOutputMode::Pipe {
+            let writer = match out_file {
+                Some(out_file) => out_file,
+                None => in_file.try_clone().map_err(Error::FileClone)?,
+            };
+            serial
+                .as_ref()
+                .lock()
+                .unwrap()
+                .set_out(tee(log.as_ref(), Some(Box::new(writer))));
         }
 
         // Use 'File' to enforce closing on 'epoll_fd'
@@ -232,6 +278,7 @@ impl SerialManager {
             pty_write_out,
             mode,
             socket_path,
+            log,
         }))
     }
 
@@ -272,12 +319,17 @@ impl SerialManager {
         let mut in_file = self.in_file.try_clone().map_err(Error::FileClone)?;
         let serial = self.serial.clone();
         let pty_write_out = self.pty_write_out.clone();
-        // SAFETY: from_raw_fd is always called with a valid fd
-        let listener = unsafe {
-            UnixListener::from_raw_fd(in_file.try_clone().map_err(Error::FileClone)?.into_raw_fd())
+        let listener = if is_socket(&self.mode) {
+            Some(socket_listener(
+                &self.mode,
+                in_file.try_clone().map_err(Error::FileClone)?,
+            ))
+        } else {
+            None
         };
-        let mut reader: Option<UnixStream> = None;
+        let mut reader: Option<File> = None;
         let mode = self.mode.clone();
+        let log = self.log.clone();
 
         // In case of PTY, we want to be able to detect a connection on the
         // other end of the PTY. This is done by detecting there's no event
@@ -316,7 +368,7 @@ impl SerialManager {
                             }
                         };
 
-                        if mode != ConsoleOutputMode::Socket && num_events == 0 {
+                        if !is_socket(&mode) && num_events == 0 {
                             // This very specific case happens when the serial is connected
                             // to a PTY. We know EPOLLHUP is always present when there's nothing
                             // connected at the other end of the PTY. That's why getting no event
@@ -336,52 +388,53 @@ impl SerialManager {
                                     // New connection request arrived.
                                     // Shutdown the previous connection, if any
                                     if let Some(previous_reader) = reader {
-                                        previous_reader
-                                            .shutdown(Shutdown::Both)
+                                        shutdown(&previous_reader)
                                             .map_err(Error::AcceptConnection)?;
                                     }
                                     // Events on the listening socket will be connection requests.
                                     // Accept them, create a reader and a writer.
-                                    let (unix_stream, _) =
-                                        listener.accept().map_err(Error::AcceptConnection)?;
-                                    let writer =
-                                        unix_stream.try_clone().map_err(Error::CloneUnixStream)?;
-                                    reader = Some(
-                                        unix_stream.try_clone().map_err(Error::CloneUnixStream)?,
-                                    );
+                                    let stream = listener
+                                        .as_ref()
+                                        .unwrap()
+                                        .accept()
+                                        .map_err(Error::AcceptConnection)?;
+                                    let writer = stream.try_clone().map_err(Error::CloneStream)?;
+                                    reader = Some(stream.try_clone().map_err(Error::CloneStream)?);
 
                                     epoll::ctl(
                                         epoll_fd,
                                         epoll::ControlOptions::EPOLL_CTL_ADD,
-                                        unix_stream.into_raw_fd(),
+                                        stream.into_raw_fd(),
                                         epoll::Event::new(
                                             epoll::Events::EPOLLIN,
                                             EpollDispatch::File as u64,
                                         ),
                                     )
                                     .map_err(Error::Epoll)?;
-                                    serial.lock().unwrap().set_out(Some(Box::new(writer)));
+                                    serial
+                                        .lock()
+                                        .unwrap()
+                                        .set_out(tee(log.as_ref(), Some(Box::new(writer))));
                                 }
                                 EpollDispatch::File => {
                                     if event.events & libc::EPOLLIN as u32 != 0 {
                                         let mut input = [0u8; 64];
                                         let count = match mode {
-                                            ConsoleOutputMode::Socket => {
+                                            ConsoleOutputMode::Socket | ConsoleOutputMode::Tcp => {
                                                 if let Some(mut serial_reader) = reader.as_ref() {
                                                     let count = serial_reader
                                                         .read(&mut input)
                                                         .map_err(Error::ReadInput)?;
                                                     if count == 0 {
                                                         info!("Remote end closed serial socket");
-                                                        serial_reader
-                                                            .shutdown(Shutdown::Both)
+                                                        shutdown(serial_reader)
                                                             .map_err(Error::ShutdownConnection)?;
                                                         reader = None;
                                                         serial
                                                             .as_ref()
                                                             .lock()
                                                             .unwrap()
-                                                            .set_out(None);
+                                                            .set_out(tee(log.as_ref(), None));
                                                     }
                                                     count
                                                 } else {
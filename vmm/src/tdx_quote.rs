@@ -0,0 +1,269 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Generation of the quotes of TD guests.
+//!
+//! A TD guest gets its TDREPORT signed into a quote with the
+//! TDG.VP.VMCALL<GetQuote> hypercall, handing over a shared buffer laid out
+//! as follows, all the fields being little endian:
+//!
+//! ```text
+//! offset  size  field
+//!      0     8  version, 1
+//!      8     8  status, GET_QUOTE_IN_FLIGHT until the quote is generated
+//!     16     4  length of the message to the quote generation service
+//!     20     4  length of the answer of the quote generation service
+//!     24        message, then answer
+//! ```
+//!
+//! The message is forwarded as is to the Quote Generation Service (QGS) of
+//! the host, over its UNIX socket, with no vsock device or agent needed in
+//! the guest. Each message is prefixed with its length, as a 32-bit big
+//! endian integer, and so is the answer. The quotes are generated one after
+//! the other by a dedicated thread, the guest polling the status of the
+//! buffer for the completion of its request.
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use thiserror::Error;
+use vm_memory::{Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic};
+
+use crate::GuestMemoryMmap;
+
+/// Default socket of the Intel QGS.
+pub const DEFAULT_QGS_SOCKET: &str = "/var/run/tdx-qgs/qgs.socket";
+
+const QUOTE_BUFFER_VERSION: u64 = 1;
+const QUOTE_HEADER_SIZE: u64 = 24;
+const PAGE_SIZE: u64 = 4096;
+// Largest message or answer accepted, well above the size of a quote.
+const MAX_QGS_MESSAGE_SIZE: u32 = 1 << 20;
+
+// Status of the quote buffer
+const GET_QUOTE_SUCCESS: u64 = 0;
+const GET_QUOTE_ERROR: u64 = 0x8000_0000_0000_0000;
+const GET_QUOTE_SERVICE_UNAVAILABLE: u64 = 0x8000_0000_0000_0001;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Quote buffer at 0x{0:x} of size 0x{1:x} is not made of pages")]
+    UnalignedBuffer(u64, u64),
+    #[error("Failed to access the quote buffer: {0}")]
+    GuestMemory(#[source] vm_memory::GuestMemoryError),
+    #[error("Unsupported quote buffer version {0}")]
+    UnsupportedVersion(u64),
+    #[error("Message of {0} bytes does not fit in the quote buffer")]
+    MessageTooLarge(u32),
+    #[error("Failed to spawn the quote generation thread: {0}")]
+    SpawnThread(#[source] io::Error),
+    #[error("The quote generation thread is gone")]
+    ThreadGone,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// Quote buffer of a pending request.
+struct QuoteRequest {
+    gpa: u64,
+    size: u64,
+    message: Vec<u8>,
+}
+
+/// Forwards the GetQuote requests of a TD guest to the QGS of the host.
+pub struct TdxQuoteGenerator {
+    memory: GuestMemoryAtomic<GuestMemoryMmap>,
+    // Bit of the GPA telling a shared page apart from a private one.
+    shared_gpa_bit: u64,
+    requests: Mutex<Sender<QuoteRequest>>,
+}
+
+impl TdxQuoteGenerator {
+    /// Spawns the thread generating the quotes with the QGS listening on
+    /// `socket`. The shared bit of the GPAs depends on the `phys_bits` of the
+    /// guest.
+    pub fn new(
+        socket: PathBuf,
+        memory: GuestMemoryAtomic<GuestMemoryMmap>,
+        phys_bits: u8,
+    ) -> Result<Self> {
+        let (sender, receiver) = channel();
+        let thread_memory = memory.clone();
+        thread::Builder::new()
+            .name("tdx_quote".to_string())
+            .spawn(move || generate_quotes(&socket, &thread_memory, receiver))
+            .map_err(Error::SpawnThread)?;
+
+        // The GPA width of the TD is 52 bits when the guest supports more
+        // than 48 bits, the shared bit being the highest one.
+        let shared_gpa_bit = if phys_bits > 48 { 1 << 51 } else { 1 << 47 };
+
+        Ok(TdxQuoteGenerator {
+            memory,
+            shared_gpa_bit,
+            requests: Mutex::new(sender),
+        })
+    }
+
+    /// Queues the generation of a quote for the buffer the guest shared at
+    /// `gpa`, the status of the buffer being updated once it is generated.
+    pub fn get_quote(&self, gpa: u64, size: u64) -> Result<()> {
+        let gpa = gpa & !self.shared_gpa_bit;
+        if gpa % PAGE_SIZE != 0 || size == 0 || size % PAGE_SIZE != 0 {
+            return Err(Error::UnalignedBuffer(gpa, size));
+        }
+
+        let memory = self.memory.memory();
+        let version: u64 = memory
+            .read_obj(GuestAddress(gpa))
+            .map_err(Error::GuestMemory)?;
+        if version != QUOTE_BUFFER_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let in_len: u32 = memory
+            .read_obj(GuestAddress(gpa + 16))
+            .map_err(Error::GuestMemory)?;
+        if u64::from(in_len) > size - QUOTE_HEADER_SIZE {
+            return Err(Error::MessageTooLarge(in_len));
+        }
+        let mut message = vec![0u8; in_len as usize];
+        memory
+            .read_slice(&mut message, GuestAddress(gpa + QUOTE_HEADER_SIZE))
+            .map_err(Error::GuestMemory)?;
+
+        self.requests
+            .lock()
+            .unwrap()
+            .send(QuoteRequest { gpa, size, message })
+            .map_err(|_| Error::ThreadGone)
+    }
+}
+
+// Sends the message to the QGS, returning its answer.
+fn request_quote(socket: &Path, message: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(&(message.len() as u32).to_be_bytes())?;
+    stream.write_all(message)?;
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_QGS_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("answer of {len} bytes from the QGS"),
+        ));
+    }
+    let mut answer = vec![0u8; len as usize];
+    stream.read_exact(&mut answer)?;
+
+    Ok(answer)
+}
+
+// Writes the answer to the buffer of the request, the status being updated
+// last for the guest to find the answer once it completed.
+fn complete_request(
+    memory: &GuestMemoryAtomic<GuestMemoryMmap>,
+    request: &QuoteRequest,
+    answer: io::Result<Vec<u8>>,
+) -> std::result::Result<(), vm_memory::GuestMemoryError> {
+    let memory = memory.memory();
+    let status = match answer {
+        Ok(answer) if answer.len() as u64 <= request.size - QUOTE_HEADER_SIZE => {
+            memory.write_slice(&answer, GuestAddress(request.gpa + QUOTE_HEADER_SIZE))?;
+            memory.write_obj(answer.len() as u32, GuestAddress(request.gpa + 20))?;
+            GET_QUOTE_SUCCESS
+        }
+        Ok(answer) => {
+            warn!(
+                "Quote of {} bytes does not fit in the buffer at 0x{:x}",
+                answer.len(),
+                request.gpa
+            );
+            GET_QUOTE_ERROR
+        }
+        Err(e) => {
+            warn!("Failed to get a quote from the QGS: {}", e);
+            GET_QUOTE_SERVICE_UNAVAILABLE
+        }
+    };
+    std::sync::atomic::fence(std::sync::atomic::Ordering::Release);
+    memory.write_obj(status, GuestAddress(request.gpa + 8))
+}
+
+fn generate_quotes(
+    socket: &Path,
+    memory: &GuestMemoryAtomic<GuestMemoryMmap>,
+    requests: Receiver<QuoteRequest>,
+) {
+    for request in requests.iter() {
+        let answer = request_quote(socket, &request.message);
+        if let Err(e) = complete_request(memory, &request, answer) {
+            error!(
+                "Failed to complete the quote request at 0x{:x}: {}",
+                request.gpa, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_tdx_quote_generation() {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let socket = dir.as_path().join("qgs.socket");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let qgs = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len = [0u8; 4];
+            stream.read_exact(&mut len).unwrap();
+            let mut message = vec![0u8; u32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut message).unwrap();
+            assert_eq!(message, [0xaa; 16]);
+            stream.write_all(&8u32.to_be_bytes()).unwrap();
+            stream.write_all(&[0xbb; 8]).unwrap();
+        });
+
+        let memory = GuestMemoryAtomic::new(
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap(),
+        );
+        let mem = memory.memory();
+        mem.write_obj(QUOTE_BUFFER_VERSION, GuestAddress(0x2000))
+            .unwrap();
+        mem.write_obj(u64::MAX, GuestAddress(0x2008)).unwrap();
+        mem.write_obj(16u32, GuestAddress(0x2010)).unwrap();
+        mem.write_slice(&[0xaa; 16], GuestAddress(0x2018)).unwrap();
+
+        let generator = TdxQuoteGenerator::new(socket, memory.clone(), 46).unwrap();
+        assert!(matches!(
+            generator.get_quote(0x2010, 0x1000),
+            Err(Error::UnalignedBuffer(0x2010, 0x1000))
+        ));
+        generator.get_quote((1 << 47) | 0x2000, 0x1000).unwrap();
+        qgs.join().unwrap();
+
+        let mut status = u64::MAX;
+        for _ in 0..100 {
+            status = mem.read_obj(GuestAddress(0x2008)).unwrap();
+            if status != u64::MAX {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(status, GET_QUOTE_SUCCESS);
+        assert_eq!(mem.read_obj::<u32>(GuestAddress(0x2014)).unwrap(), 8);
+        let mut answer = [0u8; 8];
+        mem.read_slice(&mut answer, GuestAddress(0x2018)).unwrap();
+        assert_eq!(answer, [0xbb; 8]);
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright © 2024 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Minimal userfaultfd(2) support, see include/uapi/linux/userfaultfd.h in
+//! the kernel code for the definitions mirrored here.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+const UFFD_API: u64 = 0xaa;
+
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+
+pub const UFFDIO_REGISTER_MODE_MISSING: u64 = 1 << 0;
+
+const UFFDIO_API: u64 = 0xc018_aa3f;
+const UFFDIO_REGISTER: u64 = 0xc020_aa00;
+const UFFDIO_WAKE: u64 = 0x8010_aa02;
+const UFFDIO_COPY: u64 = 0xc028_aa03;
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+struct uffdio_api {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+struct uffdio_range {
+    start: u64,
+    len: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+struct uffdio_register {
+    range: uffdio_range,
+    mode: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+struct uffdio_copy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    copy: i64,
+}
+
+// The kernel structure is packed, but every field is naturally aligned so
+// the C representation matches its 32 bytes layout.
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+struct uffd_msg {
+    event: u8,
+    reserved1: u8,
+    reserved2: u16,
+    reserved3: u32,
+    arg: [u64; 3],
+}
+
+/// A page fault reported through the userfaultfd.
+#[derive(Clone, Copy, Debug)]
+pub struct PageFault {
+    pub address: u64,
+}
+
+pub struct Userfaultfd {
+    file: File,
+}
+
+impl Userfaultfd {
+    /// Create a non-blocking userfaultfd and complete the API handshake.
+    pub fn new() -> io::Result<Self> {
+        // SAFETY: FFI call with valid flags
+        let fd =
+            unsafe { libc::syscall(libc::SYS_userfaultfd, libc::O_CLOEXEC | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: fd was just created and is owned by nobody else
+        let uffd = Userfaultfd {
+            file: unsafe { File::from_raw_fd(fd as RawFd) },
+        };
+
+        let mut api = uffdio_api {
+            api: UFFD_API,
+            ..Default::default()
+        };
+        uffd.ioctl(UFFDIO_API, &mut api)?;
+
+        Ok(uffd)
+    }
+
+    fn ioctl<T>(&self, request: u64, arg: &mut T) -> io::Result<()> {
+        // SAFETY: the argument matches the layout expected by the request
+        let ret = unsafe { libc::ioctl(self.file.as_raw_fd(), request as _, arg as *mut T) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Register the range `[start, start + len)` with the given mode.
+    pub fn register(&self, start: u64, len: u64, mode: u64) -> io::Result<()> {
+        let mut register = uffdio_register {
+            range: uffdio_range { start, len },
+            mode,
+            ioctls: 0,
+        };
+        self.ioctl(UFFDIO_REGISTER, &mut register)
+    }
+
+    /// Wake up the threads waiting on the range `[start, start + len)`.
+    pub fn wake(&self, start: u64, len: u64) -> io::Result<()> {
+        let mut range = uffdio_range { start, len };
+        self.ioctl(UFFDIO_WAKE, &mut range)
+    }
+
+    /// Resolve a missing fault by copying `len` bytes from `src` to `dst`.
+    ///
+    /// If the page got populated concurrently, the waiting threads are simply
+    /// woken up.
+    pub fn copy(&self, dst: u64, src: *const u8, len: u64) -> io::Result<()> {
+        let mut copy = uffdio_copy {
+            dst,
+            src: src as u64,
+            len,
+            ..Default::default()
+        };
+        match self.ioctl(UFFDIO_COPY, &mut copy) {
+            Err(e) if e.raw_os_error() == Some(libc::EEXIST) => self.wake(dst, len),
+            r => r,
+        }
+    }
+
+    /// Read the next page fault, returning `None` once the queue is empty.
+    /// Events other than page faults are skipped.
+    pub fn read_fault(&self) -> io::Result<Option<PageFault>> {
+        loop {
+            let mut msg = uffd_msg::default();
+            // SAFETY: reading at most the size of the message into it
+            let ret = unsafe {
+                libc::read(
+                    self.file.as_raw_fd(),
+                    &mut msg as *mut uffd_msg as *mut libc::c_void,
+                    std::mem::size_of::<uffd_msg>(),
+                )
+            };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(None);
+                }
+                return Err(e);
+            }
+
+            if msg.event == UFFD_EVENT_PAGEFAULT {
+                return Ok(Some(PageFault {
+                    address: msg.arg[1],
+                }));
+            }
+        }
+    }
+}
+
+impl AsRawFd for Userfaultfd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
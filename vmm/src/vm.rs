@@ -11,26 +11,45 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+#[cfg(feature = "sev_snp")]
+use crate::agent_channel::AgentClient;
+#[cfg(all(feature = "igvm", feature = "sev_snp"))]
+use crate::config::SevSnpConfig;
+#[cfg(feature = "sev_snp")]
+use crate::config::SnpCpuidMode;
 use crate::config::{
-    add_to_config, DeviceConfig, DiskConfig, FsConfig, HotplugMethod, NetConfig, PmemConfig,
-    UserDeviceConfig, ValidationError, VdpaConfig, VmConfig, VsockConfig,
+    add_to_config, ConsolePortConfig, DeviceConfig, DiskConfig, FsConfig, HostNumaPolicy,
+    HotplugMethod, NetConfig, PmemConfig, UserDeviceConfig, ValidationError, VdpaConfig, VmConfig,
+    VsockConfig, VsockForwardConfig,
 };
-use crate::config::{NumaConfig, PayloadConfig};
+use crate::config::{CpusConfig, NumaConfig, PayloadConfig};
 use crate::console_devices::{ConsoleDeviceError, ConsoleInfo};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::{
     CpuElf64Writable, DumpState, Elf64Writable, GuestDebuggable, GuestDebuggableError, NoteDescType,
 };
+use crate::counters_shm::{Counters, CountersShm, CountersShmInfo};
 use crate::cpu;
-use crate::device_manager::{DeviceManager, DeviceManagerError};
+use crate::device_manager::{
+    DeviceManager, DeviceManagerError, DeviceQuiesceReport, DeviceUnplugStatus,
+};
 use crate::device_tree::DeviceTree;
 #[cfg(feature = "guest_debug")]
 use crate::gdb::{Debuggable, DebuggableError, GdbRequestPayload, GdbResponsePayload};
+#[cfg(all(feature = "igvm", feature = "sev_snp"))]
+use crate::igvm::id_block::IdBlockSigner;
 #[cfg(feature = "igvm")]
 use crate::igvm::igvm_loader;
+#[cfg(all(feature = "igvm", feature = "sev_snp"))]
+use crate::kbs;
 use crate::landlock::LandlockError;
+use crate::mce::PoisonedPage;
+#[cfg(feature = "mdns")]
+use crate::mdns::MdnsResponder;
+#[cfg(feature = "sev_snp")]
+use crate::memory_manager::PrivateMemory;
 use crate::memory_manager::{
-    Error as MemoryManagerError, MemoryManager, MemoryManagerSnapshotData,
+    Error as MemoryManagerError, MemoryManager, MemoryManagerSnapshotData, MemoryRestoreMode,
 };
 #[cfg(target_arch = "x86_64")]
 use crate::migration::get_vm_snapshot;
@@ -51,6 +70,7 @@ use arch::EntryPoint;
 #[cfg(target_arch = "aarch64")]
 use arch::PciSpaceInfo;
 use arch::{NumaNode, NumaNodes};
+use devices::ghes::ErrorSeverity;
 #[cfg(target_arch = "aarch64")]
 use devices::interrupt_controller;
 use devices::AcpiNotificationFlags;
@@ -59,9 +79,14 @@ use gdbstub_arch::aarch64::reg::AArch64CoreRegs as CoreRegs;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use gdbstub_arch::x86::reg::X86_64CoreRegs as CoreRegs;
 use hypervisor::{HypervisorVmError, VmOps};
+#[cfg(feature = "igvm")]
+use igvm::IsolationType;
 use libc::{termios, SIGWINCH};
 use linux_loader::cmdline::Cmdline;
-#[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+#[cfg(all(
+    target_arch = "x86_64",
+    any(feature = "guest_debug", feature = "sev_snp")
+))]
 use linux_loader::elf;
 #[cfg(target_arch = "x86_64")]
 use linux_loader::loader::bzimage::BzImage;
@@ -82,17 +107,19 @@ use std::mem::size_of;
 use std::num::Wrapping;
 use std::ops::Deref;
 use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{result, str, thread};
 use thiserror::Error;
 use tracer::trace_scoped;
 use vm_device::Bus;
-#[cfg(feature = "tdx")]
-use vm_memory::{Address, ByteValued, GuestMemoryRegion, ReadVolatile};
 use vm_memory::{
-    Bytes, GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryAtomic, WriteVolatile,
+    Address, Bytes, GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryAtomic,
+    GuestMemoryRegion, WriteVolatile,
 };
+#[cfg(feature = "tdx")]
+use vm_memory::{ByteValued, ReadVolatile};
 use vm_migration::protocol::{Request, Response};
 use vm_migration::{
     protocol::MemoryRangeTable, snapshot_from_id, Migratable, MigratableError, Pausable, Snapshot,
@@ -120,6 +147,10 @@ pub enum Error {
     #[error("Cannot load the initramfs into memory")]
     InitramfsLoad,
 
+    #[cfg(target_arch = "aarch64")]
+    #[error("Cannot read the device tree overlay {0:?}: {1}")]
+    FdtOverlayRead(PathBuf, #[source] io::Error),
+
     #[error("Cannot load the kernel command line in memory: {0}")]
     LoadCmdLine(#[source] linux_loader::loader::Error),
 
@@ -135,6 +166,9 @@ pub enum Error {
     #[error("Cannot configure system: {0}")]
     ConfigureSystem(#[source] arch::Error),
 
+    #[error("Error creating the ACPI tables: {0}")]
+    CreateAcpiTables(#[source] crate::acpi::Error),
+
     #[cfg(target_arch = "aarch64")]
     #[error("Cannot enable interrupt controller: {0:?}")]
     EnableInterruptController(interrupt_controller::Error),
@@ -142,6 +176,13 @@ pub enum Error {
     #[error("VM state is poisoned")]
     PoisonedState,
 
+    #[error("Cannot publish the counters through shared memory: {0}")]
+    CountersShm(#[source] io::Error),
+
+    #[cfg(feature = "mdns")]
+    #[error("Cannot publish the guest name through mDNS: {0}")]
+    Mdns(#[source] io::Error),
+
     #[error("Error from device manager: {0:?}")]
     DeviceManager(DeviceManagerError),
 
@@ -217,12 +258,21 @@ pub enum Error {
     #[error("Too many virtio-vsock devices")]
     TooManyVsockDevices,
 
+    #[error("No virtio-vsock device")]
+    NoVsockDevice,
+
+    #[error("Unknown virtio-vsock forwarding rule: {0}")]
+    UnknownVsockForward(String),
+
     #[error("Failed serializing into JSON: {0}")]
     SerializeJson(#[source] serde_json::Error),
 
     #[error("Invalid NUMA configuration")]
     InvalidNumaConfig,
 
+    #[error("Error generating the automatic NUMA configuration: {0}")]
+    AutoNuma(#[source] crate::auto_numa::Error),
+
     #[error("Cannot create seccomp filter: {0}")]
     CreateSeccompFilter(#[source] seccompiler::Error),
 
@@ -238,6 +288,15 @@ pub enum Error {
     #[error("Error triggering power button: {0:?}")]
     PowerButton(DeviceManagerError),
 
+    #[error("Error updating the VM generation ID: {0:?}")]
+    UpdateVmGenId(DeviceManagerError),
+
+    #[error("Error injecting the hardware error: {0:?}")]
+    InjectError(DeviceManagerError),
+
+    #[error("Address {0:#x} is not part of the guest memory")]
+    InvalidErrorAddress(u64),
+
     #[error("Kernel lacks PVH header")]
     KernelMissingPvhHeader,
 
@@ -257,6 +316,14 @@ pub enum Error {
     #[error("Error enabling SEV-SNP VM: {0}")]
     InitializeSevSnpVm(#[source] hypervisor::HypervisorVmError),
 
+    #[cfg(feature = "sev_snp")]
+    #[error("Error launching SEV-ES VM: {0}")]
+    LaunchSevEsVm(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error assigning hot-added memory to the SEV-SNP guest: {0}")]
+    AssignSevSnpMemory(#[source] hypervisor::HypervisorVmError),
+
     #[cfg(feature = "tdx")]
     #[error("Error performing I/O on TDX firmware file: {0}")]
     LoadTdvf(#[source] std::io::Error),
@@ -277,10 +344,21 @@ pub enum Error {
     #[error("Error allocating TDVF memory: {0:?}")]
     AllocatingTdvfMemory(crate::memory_manager::Error),
 
+    #[error("Error setting the halt polling time: {0}")]
+    SetHaltPollNs(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error disabling HLT exits: {0}")]
+    DisableHltExits(#[source] hypervisor::HypervisorVmError),
+
     #[cfg(feature = "tdx")]
     #[error("Error enabling TDX VM: {0}")]
     InitializeTdxVm(#[source] hypervisor::HypervisorVmError),
 
+    #[cfg(feature = "tdx")]
+    #[error("Error binding the migration TD: {0}")]
+    BindMigrationTd(#[source] hypervisor::HypervisorVmError),
+
     #[cfg(feature = "tdx")]
     #[error("Error enabling TDX memory region: {0}")]
     InitializeTdxMemoryRegion(#[source] hypervisor::HypervisorVmError),
@@ -322,14 +400,86 @@ pub enum Error {
     #[error("Cannot load the igvm into memory: {0}")]
     IgvmLoad(#[source] igvm_loader::Error),
 
+    #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+    #[error("Cannot read the keys signing the ID block: {0}")]
+    IdBlockKeys(#[source] crate::igvm::id_block::Error),
+
+    #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+    #[error("Cannot open the SVSM igvm file: {0}")]
+    SvsmFile(#[source] io::Error),
+
+    #[error("The VM payload is not an igvm file")]
+    MissingIgvmPayload,
+
+    #[cfg(feature = "igvm")]
+    #[error("Error computing the launch measurement: {0}")]
+    LaunchMeasurement(#[source] crate::igvm::measurement::Error),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error fetching the host data from the key broker service: {0}")]
+    FetchHostData(#[source] kbs::Error),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Host data from the key broker service isn't 32 bytes long")]
+    InvalidKbsHostData,
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error reading the host data file: {0}")]
+    ReadHostDataFile(#[source] io::Error),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Host data file isn't 32 bytes long")]
+    InvalidHostDataFile,
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error reading the SEV-SNP certificates: {0}")]
+    ReadSevSnpCertificates(#[source] io::Error),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error setting the SEV-SNP certificates: {0}")]
+    SetSevSnpCertificates(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("No guest agent channel to request the SEV-SNP attestation report from")]
+    NoAgentChannel,
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error connecting to the guest agent channel: {0}")]
+    ConnectAgentChannel(#[source] io::Error),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error requesting the SEV-SNP attestation report: {0}")]
+    SevSnpReport(#[source] crate::agent_channel::Error),
+
+    #[error("SEV-SNP isn't enabled for the VM")]
+    SevSnpNotEnabled,
+
+    #[error("Report data isn't at most 64 hex encoded bytes")]
+    InvalidReportData,
+
     #[error("Error injecting NMI")]
     ErrorNmi,
 
     #[error("Error resuming the VM: {0}")]
     ResumeVm(#[source] hypervisor::HypervisorVmError),
 
+    #[cfg(target_arch = "aarch64")]
+    #[error("Error restoring the guest counter: {0}")]
+    RestoreCounter(#[source] hypervisor::HypervisorVmError),
+
     #[error("Error creating console devices")]
     CreateConsoleDevices(ConsoleDeviceError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error getting the VM clock: {0}")]
+    GetClock(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error setting the VM clock: {0}")]
+    SetClock(#[source] hypervisor::HypervisorVmError),
+
+    #[error("Guest time reporting is not supported on this platform")]
+    TimeNotSupported,
 }
 pub type Result<T> = result::Result<T, Error>;
 
@@ -385,6 +535,8 @@ struct VmOpsHandler {
     #[cfg(target_arch = "x86_64")]
     io_bus: Arc<Bus>,
     mmio_bus: Arc<Bus>,
+    #[cfg(feature = "sev_snp")]
+    private_memory: Option<Arc<PrivateMemory>>,
 }
 
 impl VmOps for VmOpsHandler {
@@ -403,6 +555,7 @@ impl VmOps for VmOpsHandler {
     }
 
     fn mmio_read(&self, gpa: u64, data: &mut [u8]) -> result::Result<(), HypervisorVmError> {
+        tracer::usdt!(mmio_read, gpa, data.len());
         if let Err(vm_device::BusError::MissingAddressRange) = self.mmio_bus.read(gpa, data) {
             info!("Guest MMIO read to unregistered address 0x{:x}", gpa);
         }
@@ -410,6 +563,7 @@ impl VmOps for VmOpsHandler {
     }
 
     fn mmio_write(&self, gpa: u64, data: &[u8]) -> result::Result<(), HypervisorVmError> {
+        tracer::usdt!(mmio_write, gpa, data.len());
         match self.mmio_bus.write(gpa, data) {
             Err(vm_device::BusError::MissingAddressRange) => {
                 info!("Guest MMIO write to unregistered address 0x{:x}", gpa);
@@ -426,6 +580,7 @@ impl VmOps for VmOpsHandler {
 
     #[cfg(target_arch = "x86_64")]
     fn pio_read(&self, port: u64, data: &mut [u8]) -> result::Result<(), HypervisorVmError> {
+        tracer::usdt!(pio_read, port, data.len());
         if let Err(vm_device::BusError::MissingAddressRange) = self.io_bus.read(port, data) {
             info!("Guest PIO read to unregistered address 0x{:x}", port);
         }
@@ -434,6 +589,7 @@ impl VmOps for VmOpsHandler {
 
     #[cfg(target_arch = "x86_64")]
     fn pio_write(&self, port: u64, data: &[u8]) -> result::Result<(), HypervisorVmError> {
+        tracer::usdt!(pio_write, port, data.len());
         match self.io_bus.write(port, data) {
             Err(vm_device::BusError::MissingAddressRange) => {
                 info!("Guest PIO write to unregistered address 0x{:x}", port);
@@ -447,6 +603,19 @@ impl VmOps for VmOpsHandler {
         };
         Ok(())
     }
+
+    #[cfg(feature = "sev_snp")]
+    fn memory_converted(
+        &self,
+        gpa: u64,
+        size: u64,
+        private: bool,
+    ) -> result::Result<(), HypervisorVmError> {
+        if let Some(private_memory) = &self.private_memory {
+            private_memory.convert(gpa, size, private);
+        }
+        Ok(())
+    }
 }
 
 pub fn physical_bits(hypervisor: &Arc<dyn hypervisor::Hypervisor>, max_phys_bits: u8) -> u8 {
@@ -455,6 +624,14 @@ pub fn physical_bits(hypervisor: &Arc<dyn hypervisor::Hypervisor>, max_phys_bits
     cmp::min(host_phys_bits, max_phys_bits)
 }
 
+/// SEV-SNP attestation report of the guest, hex encoded.
+#[derive(Debug, Clone, Serialize)]
+pub struct SnpReport {
+    pub report: String,
+    /// Certificate table returned by the extended guest requests
+    pub certificates: String,
+}
+
 pub struct Vm {
     #[cfg(feature = "tdx")]
     kernel: Option<File>,
@@ -470,11 +647,18 @@ pub struct Vm {
     vm: Arc<dyn hypervisor::Vm>,
     #[cfg(target_arch = "x86_64")]
     saved_clock: Option<hypervisor::ClockData>,
+    #[cfg(target_arch = "aarch64")]
+    saved_counter: Option<u64>,
     numa_nodes: NumaNodes,
     #[cfg_attr(any(not(feature = "kvm"), target_arch = "aarch64"), allow(dead_code))]
     hypervisor: Arc<dyn hypervisor::Hypervisor>,
     stop_on_boot: bool,
     load_payload_handle: Option<thread::JoinHandle<Result<EntryPoint>>>,
+    counters_shm: Option<CountersShm>,
+    #[cfg(feature = "mdns")]
+    mdns_responder: Option<MdnsResponder>,
+    #[cfg(feature = "sev_snp")]
+    sev_snp_certificates: Vec<u8>,
 }
 
 impl Vm {
@@ -487,6 +671,7 @@ impl Vm {
         vm: Arc<dyn hypervisor::Vm>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        pause_evt: EventFd,
         #[cfg(feature = "guest_debug")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
@@ -522,10 +707,12 @@ impl Vm {
         let tdx_enabled = config.lock().unwrap().is_tdx_enabled();
         #[cfg(feature = "sev_snp")]
         let sev_snp_enabled = config.lock().unwrap().is_sev_snp_enabled();
+        #[cfg(feature = "sev_snp")]
+        let sev_es_enabled = config.lock().unwrap().is_sev_es_enabled();
         #[cfg(feature = "tdx")]
         let force_iommu = tdx_enabled;
         #[cfg(feature = "sev_snp")]
-        let force_iommu = sev_snp_enabled;
+        let force_iommu = sev_snp_enabled || sev_es_enabled;
         #[cfg(not(any(feature = "tdx", feature = "sev_snp")))]
         let force_iommu = false;
 
@@ -535,6 +722,9 @@ impl Vm {
         let stop_on_boot = false;
 
         let memory = memory_manager.lock().unwrap().guest_memory();
+        #[cfg(feature = "sev_snp")]
+        let private_memory =
+            sev_snp_enabled.then(|| memory_manager.lock().unwrap().enable_private_memory());
         #[cfg(target_arch = "x86_64")]
         let io_bus = Arc::new(Bus::new());
         let mmio_bus = Arc::new(Bus::new());
@@ -544,6 +734,8 @@ impl Vm {
             #[cfg(target_arch = "x86_64")]
             io_bus: io_bus.clone(),
             mmio_bus: mmio_bus.clone(),
+            #[cfg(feature = "sev_snp")]
+            private_memory,
         });
 
         let cpus_config = { &config.lock().unwrap().cpus.clone() };
@@ -601,9 +793,20 @@ impl Vm {
         #[cfg(feature = "tdx")]
         if tdx_enabled {
             let cpuid = cpu_manager.lock().unwrap().common_cpuid();
-            let max_vcpus = cpu_manager.lock().unwrap().max_vcpus() as u32;
+            let max_vcpus = cpu_manager.lock().unwrap().max_vcpus();
             vm.tdx_init(&cpuid, max_vcpus)
                 .map_err(Error::InitializeTdxVm)?;
+
+            let migtd_pid = config
+                .lock()
+                .unwrap()
+                .platform
+                .as_ref()
+                .and_then(|p| p.migtd_pid);
+            if let Some(pid) = migtd_pid {
+                vm.tdx_bind_migration_td(&hypervisor::TdxMigrationTd { pid })
+                    .map_err(Error::BindMigrationTd)?;
+            }
         }
 
         cpu_manager
@@ -616,10 +819,33 @@ impl Vm {
         // vCPUs are created. As part of this initialization we are
         // transitioning the guest into secure state.
         #[cfg(feature = "sev_snp")]
-        if sev_snp_enabled {
-            vm.sev_snp_init().map_err(Error::InitializeSevSnpVm)?;
+        if sev_snp_enabled || sev_es_enabled {
+            let sev_snp = config.lock().unwrap().sev_snp.clone().unwrap_or_default();
+            vm.sev_snp_init(sev_snp.policy)
+                .map_err(Error::InitializeSevSnpVm)?;
         }
 
+        #[cfg(feature = "sev_snp")]
+        let sev_snp_certificates = match config
+            .lock()
+            .unwrap()
+            .sev_snp
+            .as_ref()
+            .and_then(|s| s.certificates.as_ref())
+        {
+            Some(path) if sev_snp_enabled => {
+                let certs = std::fs::read(path).map_err(Error::ReadSevSnpCertificates)?;
+                match vm.sev_snp_set_certificates(certs.clone()) {
+                    Err(hypervisor::HypervisorVmError::SevSnpCertificatesNotSupported) => {
+                        warn!("SEV-SNP certificates aren't served to the guest by the hypervisor")
+                    }
+                    r => r.map_err(Error::SetSevSnpCertificates)?,
+                }
+                certs
+            }
+            _ => Vec::new(),
+        };
+
         #[cfg(feature = "tdx")]
         let dynamic = !tdx_enabled;
         #[cfg(not(feature = "tdx"))]
@@ -635,6 +861,7 @@ impl Vm {
             cpu_manager.clone(),
             exit_evt.try_clone().map_err(Error::EventFdClone)?,
             reset_evt,
+            pause_evt,
             seccomp_action.clone(),
             numa_nodes.clone(),
             &activate_evt,
@@ -645,6 +872,7 @@ impl Vm {
             dynamic,
         )
         .map_err(Error::DeviceManager)?;
+        crate::crash_report::set_device_tree(&device_manager.lock().unwrap().device_tree());
 
         device_manager
             .lock()
@@ -681,6 +909,14 @@ impl Vm {
             None
         };
 
+        #[cfg(target_arch = "aarch64")]
+        let saved_counter = if let Some(snapshot) = snapshot.as_ref() {
+            let vm_snapshot = get_vm_snapshot(snapshot).map_err(Error::Restore)?;
+            vm_snapshot.counter
+        } else {
+            None
+        };
+
         let vm_state = if snapshot.is_some() {
             VmState::Paused
         } else {
@@ -700,10 +936,17 @@ impl Vm {
             vm,
             #[cfg(target_arch = "x86_64")]
             saved_clock,
+            #[cfg(target_arch = "aarch64")]
+            saved_counter,
             numa_nodes,
             hypervisor,
             stop_on_boot,
             load_payload_handle,
+            counters_shm: None,
+            #[cfg(feature = "mdns")]
+            mdns_responder: None,
+            #[cfg(feature = "sev_snp")]
+            sev_snp_certificates,
         })
     }
 
@@ -796,6 +1039,7 @@ impl Vm {
         vm_config: Arc<Mutex<VmConfig>>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        pause_evt: EventFd,
         #[cfg(feature = "guest_debug")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
@@ -806,6 +1050,7 @@ impl Vm {
         snapshot: Option<Snapshot>,
         source_url: Option<&str>,
         prefault: Option<bool>,
+        memory_restore_mode: MemoryRestoreMode,
     ) -> Result<Self> {
         trace_scoped!("Vm::new");
 
@@ -825,13 +1070,23 @@ impl Vm {
             vm_config.lock().unwrap().is_sev_snp_enabled()
         };
 
+        #[cfg(feature = "sev_snp")]
+        let sev_es_enabled = if snapshot.is_some() {
+            false
+        } else {
+            vm_config.lock().unwrap().is_sev_es_enabled()
+        };
+
         let vm = Self::create_hypervisor_vm(
             &hypervisor,
             #[cfg(feature = "tdx")]
             tdx_enabled,
             #[cfg(feature = "sev_snp")]
             sev_snp_enabled,
+            #[cfg(feature = "sev_snp")]
+            sev_es_enabled,
         )?;
+        Self::configure_idle(&vm, &vm_config.lock().unwrap().cpus)?;
 
         let phys_bits = physical_bits(&hypervisor, vm_config.lock().unwrap().cpus.max_phys_bits);
 
@@ -844,10 +1099,17 @@ impl Vm {
                 &vm_config.lock().unwrap().memory.clone(),
                 source_url,
                 prefault.unwrap(),
+                memory_restore_mode,
                 phys_bits,
             )
             .map_err(Error::MemoryManager)?
         } else {
+            let mut config = vm_config.lock().unwrap();
+            if config.memory.auto_numa {
+                crate::auto_numa::apply(&mut config).map_err(Error::AutoNuma)?;
+            }
+            drop(config);
+
             #[cfg(target_arch = "x86_64")]
             let sgx_epc_config = vm_config.lock().unwrap().sgx_epc.clone();
 
@@ -884,6 +1146,7 @@ impl Vm {
             vm,
             exit_evt,
             reset_evt,
+            pause_evt,
             #[cfg(feature = "guest_debug")]
             vm_debug_evt,
             seccomp_action,
@@ -901,6 +1164,7 @@ impl Vm {
         hypervisor: &Arc<dyn hypervisor::Hypervisor>,
         #[cfg(feature = "tdx")] tdx_enabled: bool,
         #[cfg(feature = "sev_snp")] sev_snp_enabled: bool,
+        #[cfg(feature = "sev_snp")] sev_es_enabled: bool,
     ) -> Result<Arc<dyn hypervisor::Vm>> {
         hypervisor.check_required_extensions().unwrap();
 
@@ -913,12 +1177,17 @@ impl Vm {
                     .create_vm_with_type(u64::from(tdx_enabled))
                     .unwrap();
             } else if #[cfg(feature = "sev_snp")] {
-                // Passing SEV_SNP_ENABLED: 1 if sev_snp_enabled is true
+                // Passing SEV_SNP_ENABLED: 1 if sev_snp_enabled is true,
+                // SEV_ES_ENABLED: 2 if sev_es_enabled is true
                 // Otherwise SEV_SNP_DISABLED: 0
-                // value of sev_snp_enabled is mapped to SEV_SNP_ENABLED for true or SEV_SNP_DISABLED for false
-                let vm = hypervisor
-                    .create_vm_with_type(u64::from(sev_snp_enabled))
-                    .unwrap();
+                let vm_type = if sev_snp_enabled {
+                    1
+                } else if sev_es_enabled {
+                    2
+                } else {
+                    0
+                };
+                let vm = hypervisor.create_vm_with_type(vm_type).unwrap();
             } else {
                 let vm = hypervisor.create_vm().unwrap();
             }
@@ -935,6 +1204,23 @@ impl Vm {
         Ok(vm)
     }
 
+    /// Applies how idle vCPUs wait for a wakeup, before any vCPU is created.
+    pub fn configure_idle(vm: &Arc<dyn hypervisor::Vm>, cpus: &CpusConfig) -> Result<()> {
+        if let Some(ns) = cpus.halt_poll_ns {
+            vm.set_halt_poll_ns(ns).map_err(Error::SetHaltPollNs)?;
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if cpus.idle_poll {
+            if cpus.affinity.is_none() {
+                warn!("Idle vCPUs keep their host CPUs busy, consider setting their affinity");
+            }
+            vm.disable_hlt_exits().map_err(Error::DisableHltExits)?;
+        }
+
+        Ok(())
+    }
+
     fn load_initramfs(&mut self, guest_mem: &GuestMemoryMmap) -> Result<arch::InitramfsConfig> {
         let initramfs = self.initramfs.as_mut().unwrap();
         let size: usize = initramfs
@@ -1020,20 +1306,40 @@ impl Vm {
         Ok(EntryPoint { entry_addr })
     }
 
-    #[cfg(feature = "igvm")]
+    #[cfg(all(target_arch = "x86_64", feature = "igvm"))]
     fn load_igvm(
         igvm: File,
         memory_manager: Arc<Mutex<MemoryManager>>,
         cpu_manager: Arc<Mutex<cpu::CpuManager>>,
+        isolation_type: IsolationType,
         #[cfg(feature = "sev_snp")] host_data: &Option<String>,
+        #[cfg(feature = "sev_snp")] snp_cpuid: SnpCpuidMode,
+        #[cfg(feature = "sev_snp")] snp_policy: Option<u64>,
+        #[cfg(feature = "sev_snp")] id_block_signer: Option<&IdBlockSigner>,
+        #[cfg(feature = "sev_snp")] svsm: Option<File>,
+        #[cfg(feature = "sev_snp")] lazy_unmeasured: bool,
+        compatibility_mask: Option<u32>,
     ) -> Result<EntryPoint> {
         let res = igvm_loader::load_igvm(
             &igvm,
             memory_manager,
             cpu_manager.clone(),
             "",
+            None,
+            isolation_type,
             #[cfg(feature = "sev_snp")]
             host_data,
+            #[cfg(feature = "sev_snp")]
+            snp_cpuid,
+            #[cfg(feature = "sev_snp")]
+            snp_policy,
+            #[cfg(feature = "sev_snp")]
+            id_block_signer,
+            #[cfg(feature = "sev_snp")]
+            svsm.as_ref(),
+            #[cfg(feature = "sev_snp")]
+            lazy_unmeasured,
+            compatibility_mask,
         )
         .map_err(Error::IgvmLoad)?;
 
@@ -1046,18 +1352,60 @@ impl Vm {
             if #[cfg(feature = "sev_snp")] {
                 let entry_point = if cpu_manager.lock().unwrap().sev_snp_enabled() {
                     info!("Using vmsa_gpa as entrypoint");
+                    cpu_manager.lock().unwrap().set_vmsa_gpas(res.vmsa_gpas.clone());
                     EntryPoint { entry_addr: vm_memory::GuestAddress(res.vmsa_gpa), setup_header: None }
                 } else {
-                    info!("Using vmsa_rip as entrypoint");
-                    EntryPoint {entry_addr: vm_memory::GuestAddress(res.vmsa.rip), setup_header: None }
+                    info!("Using the VP context rip as entrypoint");
+                    EntryPoint {entry_addr: vm_memory::GuestAddress(res.vp_context_rip), setup_header: None }
                 };
             } else {
-               let entry_point = EntryPoint { entry_addr: vm_memory::GuestAddress(res.vmsa.rip), setup_header: None };
+               let entry_point = EntryPoint { entry_addr: vm_memory::GuestAddress(res.vp_context_rip), setup_header: None };
             }
         };
         Ok(entry_point)
     }
 
+    // With TDX the IGVM file is loaded once the vCPUs are created, as the
+    // loader initializes them along with the TD memory.
+    #[cfg(all(feature = "tdx", feature = "igvm"))]
+    fn load_tdx_igvm(&mut self) -> Result<bool> {
+        let payload = self.config.lock().unwrap().payload.clone();
+        let Some(PayloadConfig {
+            igvm: Some(igvm_path),
+            igvm_compatibility_mask,
+            ..
+        }) = payload
+        else {
+            return Ok(false);
+        };
+
+        let igvm = File::open(igvm_path).map_err(Error::IgvmFile)?;
+        igvm_loader::load_igvm(
+            &igvm,
+            self.memory_manager.clone(),
+            self.cpu_manager.clone(),
+            "",
+            None,
+            IsolationType::Tdx,
+            #[cfg(feature = "sev_snp")]
+            &None,
+            #[cfg(feature = "sev_snp")]
+            SnpCpuidMode::default(),
+            #[cfg(feature = "sev_snp")]
+            None,
+            #[cfg(feature = "sev_snp")]
+            None,
+            #[cfg(feature = "sev_snp")]
+            None,
+            #[cfg(feature = "sev_snp")]
+            false,
+            igvm_compatibility_mask,
+        )
+        .map_err(Error::IgvmLoad)?;
+
+        Ok(true)
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn load_kernel(
         mut kernel: File,
@@ -1122,18 +1470,48 @@ impl Vm {
         memory_manager: Arc<Mutex<MemoryManager>>,
         #[cfg(feature = "igvm")] cpu_manager: Arc<Mutex<cpu::CpuManager>>,
         #[cfg(feature = "sev_snp")] sev_snp_enabled: bool,
+        #[cfg(feature = "sev_snp")] snp_cpuid: SnpCpuidMode,
+        #[cfg(feature = "sev_snp")] snp_policy: Option<u64>,
+        #[cfg(all(feature = "igvm", feature = "sev_snp"))] id_block_signer: Option<&IdBlockSigner>,
+        #[cfg(all(feature = "igvm", feature = "sev_snp"))] svsm: Option<&PathBuf>,
+        #[cfg(all(feature = "igvm", feature = "sev_snp"))] lazy_unmeasured: bool,
     ) -> Result<EntryPoint> {
         trace_scoped!("load_payload");
         #[cfg(feature = "igvm")]
         {
             if let Some(_igvm_file) = &payload.igvm {
                 let igvm = File::open(_igvm_file).map_err(Error::IgvmFile)?;
+                // Without SEV-SNP the guest is booted from the VBS context of
+                // the file, with no isolation.
                 #[cfg(feature = "sev_snp")]
-                if sev_snp_enabled {
-                    return Self::load_igvm(igvm, memory_manager, cpu_manager, &payload.host_data);
-                }
+                let isolation_type = if sev_snp_enabled {
+                    IsolationType::Snp
+                } else {
+                    IsolationType::Vbs
+                };
                 #[cfg(not(feature = "sev_snp"))]
-                return Self::load_igvm(igvm, memory_manager, cpu_manager);
+                let isolation_type = IsolationType::Vbs;
+                #[cfg(feature = "sev_snp")]
+                let svsm = svsm.map(File::open).transpose().map_err(Error::SvsmFile)?;
+                return Self::load_igvm(
+                    igvm,
+                    memory_manager,
+                    cpu_manager,
+                    isolation_type,
+                    #[cfg(feature = "sev_snp")]
+                    &payload.host_data,
+                    #[cfg(feature = "sev_snp")]
+                    snp_cpuid,
+                    #[cfg(feature = "sev_snp")]
+                    snp_policy,
+                    #[cfg(feature = "sev_snp")]
+                    id_block_signer,
+                    #[cfg(feature = "sev_snp")]
+                    svsm,
+                    #[cfg(feature = "sev_snp")]
+                    lazy_unmeasured,
+                    payload.igvm_compatibility_mask,
+                );
             }
         }
         match (
@@ -1155,11 +1533,64 @@ impl Vm {
         }
     }
 
+    // On aarch64 the IGVM file is loaded along with the device tree, once the
+    // devices are created, the VP context of the file setting the entry point
+    // of the boot vCPU.
+    #[cfg(all(target_arch = "aarch64", feature = "igvm"))]
+    fn load_aarch64_igvm(&self, device_tree: &[u8]) -> Result<()> {
+        let payload = self.config.lock().unwrap().payload.clone();
+        let Some(PayloadConfig {
+            igvm: Some(igvm_path),
+            igvm_compatibility_mask,
+            ..
+        }) = payload
+        else {
+            return Ok(());
+        };
+
+        let igvm = File::open(igvm_path).map_err(Error::IgvmFile)?;
+        let res = igvm_loader::load_igvm(
+            &igvm,
+            self.memory_manager.clone(),
+            self.cpu_manager.clone(),
+            "",
+            Some(device_tree),
+            IsolationType::Vbs,
+            #[cfg(feature = "sev_snp")]
+            &None,
+            #[cfg(feature = "sev_snp")]
+            SnpCpuidMode::default(),
+            #[cfg(feature = "sev_snp")]
+            None,
+            #[cfg(feature = "sev_snp")]
+            None,
+            #[cfg(feature = "sev_snp")]
+            None,
+            #[cfg(feature = "sev_snp")]
+            false,
+            igvm_compatibility_mask,
+        )
+        .map_err(Error::IgvmLoad)?;
+
+        info!("Igvm Loaded: pc: 0x{:x}", res.vp_context_rip);
+        Ok(())
+    }
+
     #[cfg(target_arch = "aarch64")]
     fn load_payload(
         payload: &PayloadConfig,
         memory_manager: Arc<Mutex<MemoryManager>>,
+        #[cfg(feature = "igvm")] _cpu_manager: Arc<Mutex<cpu::CpuManager>>,
     ) -> Result<EntryPoint> {
+        // The IGVM file is loaded when configuring the system, it needs the
+        // device tree. Its VP context overrides this entry point.
+        #[cfg(feature = "igvm")]
+        if payload.igvm.is_some() {
+            return Ok(EntryPoint {
+                entry_addr: arch::layout::UEFI_START,
+            });
+        }
+
         match (&payload.firmware, &payload.kernel) {
             (Some(firmware), None) => {
                 let firmware = File::open(firmware).map_err(Error::FirmwareFile)?;
@@ -1173,6 +1604,58 @@ impl Vm {
         }
     }
 
+    /// Sets the host data of the payload from the KBS, if configured so.
+    /// The host data is only kept in the copy of the payload being loaded.
+    #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+    fn fetch_kbs_host_data(config: &VmConfig, mut payload: PayloadConfig) -> Result<PayloadConfig> {
+        let Some(resource) = config
+            .platform
+            .as_ref()
+            .and_then(|p| p.kbs_host_data.as_deref())
+        else {
+            return Ok(payload);
+        };
+
+        let data = kbs::fetch(config, resource).map_err(Error::FetchHostData)?;
+        payload.host_data = Some(Self::encode_host_data(&data).ok_or(Error::InvalidKbsHostData)?);
+        Ok(payload)
+    }
+
+    /// Sets the host data of the payload from the SEV-SNP parameters, if
+    /// given there. The host data is only kept in the copy of the payload
+    /// being loaded.
+    #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+    fn read_sev_snp_host_data(
+        config: &VmConfig,
+        mut payload: PayloadConfig,
+    ) -> Result<PayloadConfig> {
+        let Some(sev_snp) = &config.sev_snp else {
+            return Ok(payload);
+        };
+
+        if let Some(host_data) = &sev_snp.host_data {
+            payload.host_data = Some(host_data.clone());
+        } else if let Some(path) = &sev_snp.host_data_file {
+            let data = std::fs::read(path).map_err(Error::ReadHostDataFile)?;
+            payload.host_data =
+                Some(Self::encode_host_data(&data).ok_or(Error::InvalidHostDataFile)?);
+        }
+        Ok(payload)
+    }
+
+    /// Hex encodes the host data, accepting both the raw 32 bytes and their
+    /// hex encoding.
+    #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+    fn encode_host_data(data: &[u8]) -> Option<String> {
+        match std::str::from_utf8(data).map(str::trim) {
+            Ok(s) if s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                Some(s.to_owned())
+            }
+            _ if data.len() == 32 => Some(data.iter().map(|b| format!("{b:02x}")).collect()),
+            _ => None,
+        }
+    }
+
     fn load_payload_async(
         memory_manager: &Arc<Mutex<MemoryManager>>,
         config: &Arc<Mutex<VmConfig>>,
@@ -1185,20 +1668,57 @@ impl Vm {
             return Ok(None);
         }
 
+        let config = config.lock().unwrap().clone();
         config
-            .lock()
-            .unwrap()
             .payload
-            .as_ref()
+            .clone()
             .map(|payload| {
                 let memory_manager = memory_manager.clone();
-                let payload = payload.clone();
                 #[cfg(feature = "igvm")]
                 let cpu_manager = cpu_manager.clone();
+                #[cfg(feature = "sev_snp")]
+                let snp_cpuid = config
+                    .platform
+                    .as_ref()
+                    .map(|p| p.snp_cpuid)
+                    .unwrap_or_default();
+                #[cfg(feature = "sev_snp")]
+                let snp_policy = config.sev_snp.as_ref().and_then(|s| s.policy);
+                // The keys are read ahead of loading the payload, the policy
+                // being required along with them.
+                #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+                let id_block_signer = match config.sev_snp.as_ref() {
+                    Some(SevSnpConfig {
+                        id_block_key: Some(id_block_key),
+                        author_key,
+                        policy: Some(policy),
+                        ..
+                    }) if sev_snp_enabled => Some(
+                        IdBlockSigner::new(id_block_key, author_key.as_deref(), *policy)
+                            .map_err(Error::IdBlockKeys)?,
+                    ),
+                    _ => None,
+                };
+                #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+                let svsm = config
+                    .sev_snp
+                    .as_ref()
+                    .and_then(|s| s.svsm.clone())
+                    .filter(|_| sev_snp_enabled);
+                #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+                let lazy_unmeasured =
+                    sev_snp_enabled && config.sev_snp.as_ref().is_some_and(|s| s.lazy_unmeasured);
 
                 std::thread::Builder::new()
                     .name("payload_loader".into())
                     .spawn(move || {
+                        #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+                        let payload = if sev_snp_enabled {
+                            let payload = Self::fetch_kbs_host_data(&config, payload)?;
+                            Self::read_sev_snp_host_data(&config, payload)?
+                        } else {
+                            payload
+                        };
                         Self::load_payload(
                             &payload,
                             memory_manager,
@@ -1206,6 +1726,16 @@ impl Vm {
                             cpu_manager,
                             #[cfg(feature = "sev_snp")]
                             sev_snp_enabled,
+                            #[cfg(feature = "sev_snp")]
+                            snp_cpuid,
+                            #[cfg(feature = "sev_snp")]
+                            snp_policy,
+                            #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+                            id_block_signer.as_ref(),
+                            #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+                            svsm.as_ref(),
+                            #[cfg(all(feature = "igvm", feature = "sev_snp"))]
+                            lazy_unmeasured,
                         )
                     })
                     .map_err(Error::KernelLoadThreadSpawn)
@@ -1355,7 +1885,20 @@ impl Vm {
                 ))
             })?;
 
-        arch::configure_system(
+        let fdt_overlays = self
+            .config
+            .lock()
+            .unwrap()
+            .fdt_overlays
+            .iter()
+            .flatten()
+            .map(|overlay| {
+                std::fs::read(&overlay.path)
+                    .map_err(|e| Error::FdtOverlayRead(overlay.path.clone(), e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let _device_tree = arch::configure_system(
             &mem,
             cmdline.as_cstring().unwrap().to_str().unwrap(),
             vcpu_mpidrs,
@@ -1367,9 +1910,13 @@ impl Vm {
             &vgic,
             &self.numa_nodes,
             pmu_supported,
+            &fdt_overlays,
         )
         .map_err(Error::ConfigureSystem)?;
 
+        #[cfg(feature = "igvm")]
+        self.load_aarch64_igvm(&_device_tree)?;
+
         Ok(())
     }
 
@@ -1383,6 +1930,13 @@ impl Vm {
 
         state.valid_transition(new_state)?;
 
+        // Stop publishing the counters before the devices go away
+        self.counters_shm = None;
+        #[cfg(feature = "mdns")]
+        {
+            self.mdns_responder = None;
+        }
+
         // Wake up the DeviceManager threads so they will get terminated cleanly
         self.device_manager
             .lock()
@@ -1407,7 +1961,7 @@ impl Vm {
 
     pub fn resize(
         &mut self,
-        desired_vcpus: Option<u8>,
+        desired_vcpus: Option<u32>,
         desired_memory: Option<u64>,
         desired_balloon: Option<u64>,
     ) -> Result<()> {
@@ -1438,6 +1992,17 @@ impl Vm {
                 .resize(desired_memory)
                 .map_err(Error::MemoryManager)?;
 
+            // The new range must be owned by the guest, which validates it
+            // when onlining the memory it has been notified about.
+            #[cfg(feature = "sev_snp")]
+            if let Some(new_region) = &new_region {
+                if self.config.lock().unwrap().is_sev_snp_enabled() {
+                    self.vm
+                        .release_page_access(new_region.start_addr().raw_value(), new_region.len())
+                        .map_err(Error::AssignSevSnpMemory)?;
+                }
+            }
+
             let memory_config = &mut self.config.lock().unwrap().memory;
 
             if let Some(new_region) = &new_region {
@@ -1577,11 +2142,16 @@ impl Vm {
         Ok(pci_device_info)
     }
 
-    pub fn remove_device(&mut self, id: String) -> Result<()> {
+    pub fn remove_device(
+        &mut self,
+        id: String,
+        timeout: Option<Duration>,
+        surprise_removal: bool,
+    ) -> Result<()> {
         self.device_manager
             .lock()
             .unwrap()
-            .remove_device(id.clone())
+            .remove_device(id.clone(), timeout, surprise_removal)
             .map_err(Error::DeviceManager)?;
 
         // Update VmConfig by removing the device. This is important to
@@ -1596,6 +2166,38 @@ impl Vm {
         Ok(())
     }
 
+    pub fn unplug_status(&self) -> Vec<DeviceUnplugStatus> {
+        self.device_manager.lock().unwrap().unplug_status()
+    }
+
+    pub fn next_unplug_deadline(&self) -> Option<Instant> {
+        self.device_manager.lock().unwrap().next_unplug_deadline()
+    }
+
+    pub fn process_unplug_timeouts(&mut self) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .process_unplug_timeouts()
+            .map_err(Error::DeviceManager)
+    }
+
+    pub fn trace_block_device(&self, id: &str, path: Option<PathBuf>) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .trace_block_device(id, path)
+            .map_err(Error::DeviceManager)
+    }
+
+    pub fn keep_transient_disk(&self, id: &str, path: PathBuf) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .keep_transient_disk(id, path)
+            .map_err(Error::DeviceManager)
+    }
+
     pub fn add_disk(&mut self, mut disk_cfg: DiskConfig) -> Result<PciDeviceInfo> {
         let pci_device_info = self
             .device_manager
@@ -1692,6 +2294,25 @@ impl Vm {
         Ok(pci_device_info)
     }
 
+    pub fn swap_net(&mut self, mut net_cfg: NetConfig) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .swap_net(&mut net_cfg)
+            .map_err(Error::DeviceManager)?;
+
+        // Update VmConfig with the new backend, for the device to be created
+        // with it in case of a reboot.
+        {
+            let mut config = self.config.lock().unwrap();
+            if let Some(net) = config.net.iter_mut().flatten().find(|n| n.id == net_cfg.id) {
+                *net = net_cfg;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn add_vdpa(&mut self, mut vdpa_cfg: VdpaConfig) -> Result<PciDeviceInfo> {
         let pci_device_info = self
             .device_manager
@@ -1716,6 +2337,33 @@ impl Vm {
         Ok(pci_device_info)
     }
 
+    pub fn add_console(
+        &mut self,
+        mut console_port_cfg: ConsolePortConfig,
+    ) -> Result<PciDeviceInfo> {
+        let pci_device_info = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .add_console(&mut console_port_cfg)
+            .map_err(Error::DeviceManager)?;
+
+        // Update VmConfig by adding the new device. This is important to
+        // ensure the device would be created in case of a reboot.
+        {
+            let mut config = self.config.lock().unwrap();
+            add_to_config(&mut config.console_ports, console_port_cfg);
+        }
+
+        self.device_manager
+            .lock()
+            .unwrap()
+            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+            .map_err(Error::DeviceManager)?;
+
+        Ok(pci_device_info)
+    }
+
     pub fn add_vsock(&mut self, mut vsock_cfg: VsockConfig) -> Result<PciDeviceInfo> {
         let pci_device_info = self
             .device_manager
@@ -1740,8 +2388,78 @@ impl Vm {
         Ok(pci_device_info)
     }
 
-    pub fn counters(&self) -> Result<HashMap<String, HashMap<&'static str, Wrapping<u64>>>> {
-        Ok(self.device_manager.lock().unwrap().counters())
+    pub fn add_vsock_forward(&mut self, forward: VsockForwardConfig) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .update_vsock_forward(&forward, true)
+            .map_err(Error::DeviceManager)?;
+
+        // Update VmConfig by adding the new rule. This is important to
+        // ensure the rule would be applied again in case of a reboot.
+        if let Some(vsock) = self.config.lock().unwrap().vsock.as_mut() {
+            vsock.forwards.push(forward);
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_vsock_forward(&mut self, forward: VsockForwardConfig) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .update_vsock_forward(&forward, false)
+            .map_err(Error::DeviceManager)?;
+
+        if let Some(vsock) = self.config.lock().unwrap().vsock.as_mut() {
+            vsock.forwards.retain(|f| !f.conflicts_with(&forward));
+        }
+
+        Ok(())
+    }
+
+    fn collect_counters(
+        device_manager: &Arc<Mutex<DeviceManager>>,
+        memory_manager: &Arc<Mutex<MemoryManager>>,
+        cpu_manager: &Arc<Mutex<cpu::CpuManager>>,
+    ) -> Counters {
+        let mut counters = device_manager.lock().unwrap().counters();
+        counters.extend(memory_manager.lock().unwrap().counters());
+        counters.extend(cpu_manager.lock().unwrap().counters());
+        counters.insert(
+            "vmm".to_string(),
+            HashMap::from([(
+                "seccomp_violations",
+                Wrapping(crate::seccomp_report::violations()),
+            )]),
+        );
+        counters
+    }
+
+    pub fn counters(&self) -> Result<Counters> {
+        Ok(Self::collect_counters(
+            &self.device_manager,
+            &self.memory_manager,
+            &self.cpu_manager,
+        ))
+    }
+
+    /// Returns where to find the shared memory the counters are published
+    /// to, starting the publication on the first call.
+    pub fn counters_shm(&mut self) -> Result<CountersShmInfo> {
+        if self.counters_shm.is_none() {
+            let device_manager = self.device_manager.clone();
+            let memory_manager = self.memory_manager.clone();
+            let cpu_manager = self.cpu_manager.clone();
+            self.counters_shm = Some(
+                CountersShm::new(move || {
+                    Self::collect_counters(&device_manager, &memory_manager, &cpu_manager)
+                })
+                .map_err(Error::CountersShm)?,
+            );
+        }
+
+        Ok(self.counters_shm.as_ref().unwrap().info())
     }
 
     #[cfg(feature = "tdx")]
@@ -2015,6 +2733,68 @@ impl Vm {
         Ok(hob_offset)
     }
 
+    // Encrypts and measures the segments of the firmware, then the vCPUs
+    // state, completing the launch of a SEV-ES guest.
+    #[cfg(feature = "sev_snp")]
+    fn launch_sev_es(&mut self) -> Result<()> {
+        use std::io::Read;
+
+        let firmware_path = self
+            .config
+            .lock()
+            .unwrap()
+            .payload
+            .as_ref()
+            .and_then(|payload| payload.firmware.clone())
+            .ok_or(Error::InvalidPayload)?;
+        let mut firmware = File::open(firmware_path).map_err(Error::FirmwareFile)?;
+
+        let mut ehdr = elf::Elf64_Ehdr::default();
+        firmware
+            .read_exact(ehdr.as_mut_slice())
+            .map_err(Error::FirmwareFile)?;
+
+        let guest_memory = self.memory_manager.lock().unwrap().guest_memory().memory();
+        for i in 0..u64::from(ehdr.e_phnum) {
+            let mut phdr = elf::Elf64_Phdr::default();
+            firmware
+                .seek(SeekFrom::Start(
+                    ehdr.e_phoff + i * u64::from(ehdr.e_phentsize),
+                ))
+                .map_err(Error::FirmwareFile)?;
+            firmware
+                .read_exact(phdr.as_mut_slice())
+                .map_err(Error::FirmwareFile)?;
+            if phdr.p_type != elf::PT_LOAD || phdr.p_memsz == 0 {
+                continue;
+            }
+
+            let start = phdr.p_paddr & !0xfff;
+            let end = (phdr.p_paddr + phdr.p_memsz + 0xfff) & !0xfff;
+            let uaddr = guest_memory
+                .get_host_address(GuestAddress(start))
+                .map_err(Error::FirmwareLoad)?;
+            info!("Measuring SEV-ES firmware: 0x{:x}-0x{:x}", start, end);
+            self.vm
+                .sev_es_launch_update_data(uaddr as u64, end - start)
+                .map_err(Error::LaunchSevEsVm)?;
+        }
+
+        let measurement = self
+            .vm
+            .sev_es_launch_finish()
+            .map_err(Error::LaunchSevEsVm)?;
+        info!(
+            "SEV-ES launch measurement: {}",
+            measurement
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>()
+        );
+
+        Ok(())
+    }
+
     #[cfg(feature = "tdx")]
     fn init_tdx_memory(&mut self, sections: &[TdvfSection]) -> Result<()> {
         let guest_memory = self.memory_manager.lock().as_ref().unwrap().guest_memory();
@@ -2039,10 +2819,10 @@ impl Vm {
     // In case of TDX being used, this is a no-op since the tables will be
     // created and passed when populating the HOB.
 
-    fn create_acpi_tables(&self) -> Option<GuestAddress> {
+    fn create_acpi_tables(&self) -> Result<Option<GuestAddress>> {
         #[cfg(feature = "tdx")]
         if self.config.lock().unwrap().is_tdx_enabled() {
-            return None;
+            return Ok(None);
         }
         let mem = self.memory_manager.lock().unwrap().guest_memory().memory();
         let tpm_enabled = self.config.lock().unwrap().tpm.is_some();
@@ -2053,10 +2833,11 @@ impl Vm {
             &self.memory_manager,
             &self.numa_nodes,
             tpm_enabled,
-        );
+        )
+        .map_err(Error::CreateAcpiTables)?;
         info!("Created ACPI tables: rsdp_addr = 0x{:x}", rsdp_addr.0);
 
-        Some(rsdp_addr)
+        Ok(Some(rsdp_addr))
     }
 
     fn entry_point(&mut self) -> Result<Option<EntryPoint>> {
@@ -2093,10 +2874,10 @@ impl Vm {
                     // rsdp addr to None.
                     None
                 } else {
-                    self.create_acpi_tables()
+                    self.create_acpi_tables()?
                 };
             } else {
-                let rsdp_addr = self.create_acpi_tables();
+                let rsdp_addr = self.create_acpi_tables()?;
             }
         }
 
@@ -2113,18 +2894,22 @@ impl Vm {
 
         // Configure the vcpus that have been created
         let vcpus = self.cpu_manager.lock().unwrap().vcpus();
-        for vcpu in vcpus {
-            let guest_memory = &self.memory_manager.lock().as_ref().unwrap().guest_memory();
-            let boot_setup = entry_point.map(|e| (e, guest_memory));
-            self.cpu_manager
-                .lock()
-                .unwrap()
-                .configure_vcpu(vcpu, boot_setup)
-                .map_err(Error::CpuManager)?;
-        }
+        let guest_memory = &self.memory_manager.lock().as_ref().unwrap().guest_memory();
+        let boot_setup = entry_point.map(|e| (e, guest_memory));
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .configure_vcpus(&vcpus, boot_setup)
+            .map_err(Error::CpuManager)?;
+
+        // An IGVM file sets up the TD on its own, HOB and vCPUs included.
+        #[cfg(all(feature = "tdx", feature = "igvm"))]
+        let tdx_igvm_loaded = tdx_enabled && self.load_tdx_igvm()?;
+        #[cfg(all(feature = "tdx", not(feature = "igvm")))]
+        let tdx_igvm_loaded = false;
 
         #[cfg(feature = "tdx")]
-        let (sections, guid_found) = if tdx_enabled {
+        let (sections, guid_found) = if tdx_enabled && !tdx_igvm_loaded {
             self.extract_tdvf_sections()?
         } else {
             (Vec::new(), false)
@@ -2132,7 +2917,7 @@ impl Vm {
 
         // Configuring the TDX regions requires that the vCPUs are created.
         #[cfg(feature = "tdx")]
-        let hob_address = if tdx_enabled {
+        let hob_address = if tdx_enabled && !tdx_igvm_loaded {
             // TDX sections are written to memory.
             self.populate_tdx_sections(&sections, guid_found)?
         } else {
@@ -2142,11 +2927,11 @@ impl Vm {
         // On aarch64 the ACPI tables depend on the vCPU mpidr which is only
         // available after they are configured
         #[cfg(target_arch = "aarch64")]
-        let rsdp_addr = self.create_acpi_tables();
+        let rsdp_addr = self.create_acpi_tables()?;
 
         // FIXME: do we create ACPI table for SNP or not?
         #[cfg(all(feature = "kvm", feature = "sev_snp"))]
-        let rsdp_addr = self.create_acpi_tables();
+        let rsdp_addr = self.create_acpi_tables()?;
 
         // Configure shared state based on loaded kernel
         entry_point
@@ -2176,6 +2961,18 @@ impl Vm {
             self.vm.tdx_finalize().map_err(Error::FinalizeTdx)?;
         }
 
+        #[cfg(feature = "tdx")]
+        if tdx_igvm_loaded {
+            self.vm.tdx_finalize().map_err(Error::FinalizeTdx)?;
+        }
+
+        // The vCPUs state is encrypted along with the firmware, nothing can
+        // be changed past this point.
+        #[cfg(feature = "sev_snp")]
+        if self.config.lock().unwrap().is_sev_es_enabled() {
+            self.launch_sev_es()?;
+        }
+
         // Resume the vm for MSHV
         if current_state == VmState::Created {
             self.vm.resume().map_err(Error::ResumeVm)?;
@@ -2187,6 +2984,9 @@ impl Vm {
             .start_boot_vcpus(new_state == VmState::BreakPoint)
             .map_err(Error::CpuManager)?;
 
+        #[cfg(feature = "mdns")]
+        self.start_mdns_responder()?;
+
         let mut state = self.state.try_write().map_err(|_| Error::PoisonedState)?;
         *state = new_state;
         Ok(())
@@ -2207,6 +3007,15 @@ impl Vm {
             .allocate_address_space()
             .map_err(Error::MemoryManager)?;
 
+        // The guest counter can only be set before any vCPU has run, so it is
+        // restored here rather than upon resume.
+        #[cfg(target_arch = "aarch64")]
+        if let Some(counter) = self.saved_counter {
+            self.vm
+                .set_counter(counter)
+                .map_err(Error::RestoreCounter)?;
+        }
+
         // Now we can start all vCPUs from here.
         self.cpu_manager
             .lock()
@@ -2214,10 +3023,24 @@ impl Vm {
             .start_restored_vcpus()
             .map_err(Error::CpuManager)?;
 
+        #[cfg(feature = "mdns")]
+        self.start_mdns_responder()?;
+
         event!("vm", "restored");
         Ok(())
     }
 
+    #[cfg(feature = "mdns")]
+    fn start_mdns_responder(&mut self) -> Result<()> {
+        let mdns = self.config.lock().unwrap().mdns.clone();
+        if let Some(mdns) = mdns {
+            self.mdns_responder =
+                Some(MdnsResponder::new(&mdns.hostname, mdns.ip).map_err(Error::Mdns)?);
+        }
+
+        Ok(())
+    }
+
     /// Gets a thread-safe reference counted pointer to the VM configuration.
     pub fn get_config(&self) -> Arc<Mutex<VmConfig>> {
         Arc::clone(&self.config)
@@ -2231,6 +3054,12 @@ impl Vm {
             .map(|state| *state)
     }
 
+    /// Whether the guest failed, rather than resetting or shutting down on
+    /// its own.
+    pub fn failed(&self) -> bool {
+        self.cpu_manager.lock().unwrap().vcpus_failed()
+    }
+
     /// Gets the actual size of the balloon.
     pub fn balloon_size(&self) -> u64 {
         self.device_manager.lock().unwrap().balloon_size()
@@ -2347,6 +3176,143 @@ impl Vm {
             .map_err(Error::PowerButton)
     }
 
+    pub fn update_vmgenid(&self) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .update_vmgenid()
+            .map_err(Error::UpdateVmGenId)
+    }
+
+    /// Report a memory error at `gpa` to the guest, as if the platform had
+    /// detected it.
+    pub fn inject_memory_error(&self, gpa: u64, severity: ErrorSeverity) -> Result<()> {
+        if !self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .guest_memory()
+            .memory()
+            .address_in_range(GuestAddress(gpa))
+        {
+            return Err(Error::InvalidErrorAddress(gpa));
+        }
+
+        self.device_manager
+            .lock()
+            .unwrap()
+            .inject_memory_error(GuestAddress(gpa), severity)
+            .map_err(Error::InjectError)?;
+        event!("vm", "memory-error", "gpa", format!("{gpa:#x}"));
+        Ok(())
+    }
+
+    /// Report the memory error the host detected in `page` to the guest, if
+    /// the page is part of the guest memory, and replace the page.
+    pub fn report_host_memory_error(&self, page: &PoisonedPage) -> Result<()> {
+        let guest_memory = self.memory_manager.lock().unwrap().guest_memory().memory();
+        let gpa = guest_memory.iter().find_map(|region| {
+            let start = region.as_ptr() as u64;
+            (start..start + region.len())
+                .contains(&page.address)
+                .then(|| region.start_addr().unchecked_add(page.address - start))
+        });
+
+        let Some(gpa) = gpa else {
+            warn!(
+                "Host memory error at {:#x} outside the guest memory",
+                page.address
+            );
+            return Ok(());
+        };
+
+        // The guest is told about the error first, for it to stop using the
+        // page before the accesses retried on the new page succeed.
+        let result = self.inject_memory_error(gpa.raw_value(), ErrorSeverity::Recoverable);
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .replace_poisoned_page(gpa, page.size)
+            .map_err(Error::MemoryManager)?;
+        result
+    }
+
+    /// Move the guest memory to the host NUMA node `host_numa_node`, the
+    /// memory zones being bound to it from now on.
+    pub fn migrate_memory(&self, host_numa_node: u32) -> Result<()> {
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .migrate_to_host_numa_node(host_numa_node)
+            .map_err(Error::MemoryManager)?;
+
+        // Keep the binding across reboots.
+        if let Some(zones) = self.config.lock().unwrap().memory.zones.as_mut() {
+            for zone in zones.iter_mut() {
+                zone.host_numa_node = Some(host_numa_node);
+            }
+        }
+
+        event!(
+            "vm",
+            "memory-migrated",
+            "host_numa_node",
+            host_numa_node.to_string()
+        );
+        Ok(())
+    }
+
+    /// Change the host NUMA node and policy of the memory zone `id`, the
+    /// pages already allocated being moved to the node.
+    pub fn set_zone_policy(
+        &self,
+        id: &str,
+        host_numa_node: u32,
+        policy: HostNumaPolicy,
+    ) -> Result<()> {
+        let mut config = self.config.lock().unwrap();
+        let zone = config
+            .memory
+            .zones
+            .as_mut()
+            .and_then(|zones| zones.iter_mut().find(|zone| zone.id == id))
+            .ok_or(Error::MemoryManager(MemoryManagerError::UnknownMemoryZone))?;
+        if zone.shared && zone.file.is_some() {
+            return Err(Error::MemoryManager(
+                MemoryManagerError::InvalidSharedMemoryZoneWithHostNuma,
+            ));
+        }
+
+        let not_moved = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .set_zone_host_numa_policy(id, host_numa_node, policy)
+            .map_err(Error::MemoryManager)?;
+        if not_moved > 0 {
+            warn!(
+                "{} pages of memory zone '{}' couldn't be moved to host NUMA node {}",
+                not_moved, id, host_numa_node
+            );
+        }
+
+        // Keep the policy across reboots.
+        zone.host_numa_node = Some(host_numa_node);
+        zone.host_numa_policy = policy;
+
+        event!(
+            "vm",
+            "memory-zone-policy-changed",
+            "id",
+            id,
+            "host_numa_node",
+            host_numa_node.to_string(),
+            "pages_not_moved",
+            not_moved.to_string()
+        );
+        Ok(())
+    }
+
     pub fn memory_manager_data(&self) -> MemoryManagerSnapshotData {
         self.memory_manager.lock().unwrap().snapshot_data()
     }
@@ -2405,7 +3371,7 @@ impl Vm {
         &mut self,
         destination_url: &str,
     ) -> std::result::Result<DumpState, GuestDebuggableError> {
-        let nr_cpus = self.config.lock().unwrap().cpus.boot_vcpus as u32;
+        let nr_cpus = self.config.lock().unwrap().cpus.boot_vcpus;
         let elf_note_size = self.get_note_size(NoteDescType::ElfAndVmm, nr_cpus) as isize;
         let mut elf_phdr_num = 1;
         let elf_sh_info = 0;
@@ -2448,6 +3414,50 @@ impl Vm {
             + size_of::<elf::Elf64_Phdr>() as u64 * phdr_num as u64
     }
 
+    /// Requests an attestation report of the guest from the guest agent
+    /// listening on the console port `port`, or on the first port backed by
+    /// a socket, along with the certificates the guest gets with its own
+    /// reports.
+    pub fn snp_report(&self, report_data: &[u8; 64], port: Option<&str>) -> Result<SnpReport> {
+        #[cfg(feature = "sev_snp")]
+        if self.config.lock().unwrap().is_sev_snp_enabled() {
+            let socket = self
+                .config
+                .lock()
+                .unwrap()
+                .console_ports
+                .iter()
+                .flatten()
+                .filter(|p| port.is_none() || p.id.as_deref() == port)
+                .find_map(|p| p.socket.clone())
+                .ok_or(Error::NoAgentChannel)?;
+
+            let stream = UnixStream::connect(socket).map_err(Error::ConnectAgentChannel)?;
+            // The VMM thread can't wait forever for an agent which isn't
+            // running or doesn't answer.
+            stream
+                .set_read_timeout(Some(AGENT_CHANNEL_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(AGENT_CHANNEL_TIMEOUT)))
+                .map_err(Error::ConnectAgentChannel)?;
+            let report = AgentClient::new(stream)
+                .and_then(|mut agent| agent.snp_report(report_data))
+                .map_err(Error::SevSnpReport)?;
+
+            return Ok(SnpReport {
+                report: report.iter().map(|b| format!("{b:02x}")).collect(),
+                certificates: self
+                    .sev_snp_certificates
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect(),
+            });
+        }
+        #[cfg(not(feature = "sev_snp"))]
+        let _ = (report_data, port);
+
+        Err(Error::SevSnpNotEnabled)
+    }
+
     pub fn nmi(&self) -> Result<()> {
         return self
             .cpu_manager
@@ -2456,10 +3466,103 @@ impl Vm {
             .nmi()
             .map_err(|_| Error::ErrorNmi);
     }
+
+    /// Report the guest clock alongside the host time it was sampled at.
+    /// Per-vCPU TSC parameters and the clock saved at pause time are only
+    /// available while the VM is paused, as reading them requires the vCPUs
+    /// not to be running.
+    pub fn time_info(&self) -> Result<VmTimeInfo> {
+        #[cfg(target_arch = "x86_64")]
+        {
+            let clock = self.vm.get_clock().map_err(Error::GetClock)?;
+            let mut info = VmTimeInfo {
+                clock_ns: clock.clock_ns(),
+                realtime_ns: clock.realtime_ns(),
+                host_tsc: clock.host_tsc(),
+                ..Default::default()
+            };
+
+            if self.get_state()? == VmState::Paused {
+                info.paused_clock_ns = self.saved_clock.as_ref().map(|c| c.clock_ns());
+                info.vcpus = Some(
+                    self.cpu_manager
+                        .lock()
+                        .unwrap()
+                        .tsc_info()
+                        .map_err(Error::CpuManager)?,
+                );
+            }
+
+            Ok(info)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            Err(Error::TimeNotSupported)
+        }
+    }
+
+    /// Move the guest clock forward by `delta_ns`. When the VM is paused the
+    /// adjustment is applied to the clock restored on resume, so the guest
+    /// does not observe the time spent paused twice.
+    pub fn adjust_time(&mut self, delta_ns: u64) -> Result<()> {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if self.get_state()? == VmState::Paused {
+                if let Some(clock) = self.saved_clock.as_mut() {
+                    clock.advance(delta_ns);
+                    event!("vm", "time-adjusted", "delta_ns", delta_ns.to_string());
+                    return Ok(());
+                }
+            }
+
+            let mut clock = self.vm.get_clock().map_err(Error::GetClock)?;
+            clock.reset_flags();
+            clock.advance(delta_ns);
+            self.vm.set_clock(&clock).map_err(Error::SetClock)?;
+            event!("vm", "time-adjusted", "delta_ns", delta_ns.to_string());
+            Ok(())
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            let _ = delta_ns;
+            Err(Error::TimeNotSupported)
+        }
+    }
 }
 
-impl Pausable for Vm {
-    fn pause(&mut self) -> std::result::Result<(), MigratableError> {
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct VmTimeInfo {
+    /// Current guest clock (kvmclock) value, in nanoseconds.
+    pub clock_ns: u64,
+    /// Host CLOCK_REALTIME at the time the guest clock was sampled.
+    pub realtime_ns: Option<u64>,
+    /// Host TSC at the time the guest clock was sampled.
+    pub host_tsc: Option<u64>,
+    /// Guest clock saved when the VM was paused, restored on resume.
+    pub paused_clock_ns: Option<u64>,
+    #[cfg(target_arch = "x86_64")]
+    pub vcpus: Option<Vec<cpu::VcpuTscInfo>>,
+}
+
+/// Time the guest agent is given to answer an attestation report request.
+#[cfg(feature = "sev_snp")]
+const AGENT_CHANNEL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time given by default to the devices to quiesce when pausing the VM.
+pub const DEFAULT_QUIESCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PauseReport {
+    pub devices: Vec<DeviceQuiesceReport>,
+}
+
+impl Vm {
+    /// Pause the VM, giving the devices until `quiesce_timeout` elapses to
+    /// complete their in-flight operations once the vCPUs are stopped.
+    pub fn pause_with_timeout(
+        &mut self,
+        quiesce_timeout: Duration,
+    ) -> std::result::Result<PauseReport, MigratableError> {
         event!("vm", "pausing");
         let mut state = self
             .state
@@ -2481,6 +3584,13 @@ impl Pausable for Vm {
             self.saved_clock = Some(clock);
         }
 
+        #[cfg(target_arch = "aarch64")]
+        {
+            self.saved_counter = self.vm.get_counter().map_err(|e| {
+                MigratableError::Pause(anyhow!("Could not get guest counter: {}", e))
+            })?;
+        }
+
         // Before pausing the vCPUs activate any pending virtio devices that might
         // need activation between starting the pause (or e.g. a migration it's part of)
         self.activate_virtio_devices().map_err(|e| {
@@ -2488,6 +3598,23 @@ impl Pausable for Vm {
         })?;
 
         self.cpu_manager.lock().unwrap().pause()?;
+
+        let deadline = Instant::now() + quiesce_timeout;
+        let devices = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .quiesce_devices(deadline)?;
+        for device in devices.iter() {
+            if device.timed_out {
+                warn!(
+                    "Device {} failed to quiesce within {:?}",
+                    device.id, quiesce_timeout
+                );
+            }
+            debug!("Device {} quiesced in {}us", device.id, device.duration_us);
+        }
+
         self.device_manager.lock().unwrap().pause()?;
 
         self.vm
@@ -2497,7 +3624,13 @@ impl Pausable for Vm {
         *state = new_state;
 
         event!("vm", "paused");
-        Ok(())
+        Ok(PauseReport { devices })
+    }
+}
+
+impl Pausable for Vm {
+    fn pause(&mut self) -> std::result::Result<(), MigratableError> {
+        self.pause_with_timeout(DEFAULT_QUIESCE_TIMEOUT).map(|_| ())
     }
 
     fn resume(&mut self) -> std::result::Result<(), MigratableError> {
@@ -2542,6 +3675,9 @@ impl Pausable for Vm {
 pub struct VmSnapshot {
     #[cfg(target_arch = "x86_64")]
     pub clock: Option<hypervisor::ClockData>,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub counter: Option<u64>,
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
     pub common_cpuid: Vec<hypervisor::arch::x86::CpuIdEntry>,
 }
@@ -2564,6 +3700,15 @@ impl Snapshottable for Vm {
             }
         }
 
+        #[cfg(feature = "sev_snp")]
+        {
+            if self.config.lock().unwrap().is_sev_snp_enabled() {
+                return Err(MigratableError::Snapshot(anyhow!(
+                    "Snapshot not possible with SEV-SNP VM"
+                )));
+            }
+        }
+
         let current_state = self.get_state().unwrap();
         if current_state != VmState::Paused {
             return Err(MigratableError::Snapshot(anyhow!(
@@ -2574,6 +3719,7 @@ impl Snapshottable for Vm {
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
         let common_cpuid = {
             let amx = self.config.lock().unwrap().cpus.features.amx;
+            let cet = self.config.lock().unwrap().cpus.features.cet;
             let phys_bits = physical_bits(
                 &self.hypervisor,
                 self.config.lock().unwrap().cpus.max_phys_bits,
@@ -2587,6 +3733,8 @@ impl Snapshottable for Vm {
                     #[cfg(feature = "tdx")]
                     tdx: false,
                     amx,
+                    cet,
+                    msi_ext_dest_id: cpu::x2apic_ids_required(&self.config.lock().unwrap().cpus),
                 },
             )
             .map_err(|e| {
@@ -2597,6 +3745,8 @@ impl Snapshottable for Vm {
         let vm_snapshot_state = VmSnapshot {
             #[cfg(target_arch = "x86_64")]
             clock: self.saved_clock,
+            #[cfg(target_arch = "aarch64")]
+            counter: self.saved_counter,
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
             common_cpuid,
         };
@@ -3210,7 +4360,6 @@ mod tests {
 #[test]
 pub fn test_vm() {
     use hypervisor::VmExit;
-    use vm_memory::{Address, GuestMemory, GuestMemoryRegion};
     // This example based on https://lwn.net/Articles/658511/
     let code = [
         0xba, 0xf8, 0x03, /* mov $0x3f8, %dx */
@@ -3,9 +3,18 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 use crate::{landlock::LandlockError, Landlock};
+use block::qos::{IoMaxConfig, IoPriority};
+use block::ImageType;
 use net_util::MacAddr;
+use pci::VfioResetMethod;
 use serde::{Deserialize, Serialize};
-use std::{fs, net::Ipv4Addr, path::PathBuf, result};
+use std::{
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    result,
+};
+use virtio_devices::OnIoError;
 use virtio_devices::RateLimiterConfig;
 
 pub type LandlockResult<T> = result::Result<T, LandlockError>;
@@ -18,7 +27,7 @@ pub(crate) trait ApplyLandlock {
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct CpuAffinity {
-    pub vcpu: u8,
+    pub vcpu: u32,
     pub host_cpus: Vec<usize>,
 }
 
@@ -27,6 +36,9 @@ pub struct CpuFeatures {
     #[cfg(target_arch = "x86_64")]
     #[serde(default)]
     pub amx: bool,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub cet: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -48,8 +60,8 @@ pub fn default_cpuconfig_max_phys_bits() -> u8 {
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct CpusConfig {
-    pub boot_vcpus: u8,
-    pub max_vcpus: u8,
+    pub boot_vcpus: u32,
+    pub max_vcpus: u32,
     #[serde(default)]
     pub topology: Option<CpuTopology>,
     #[serde(default)]
@@ -60,9 +72,20 @@ pub struct CpusConfig {
     pub affinity: Option<Vec<CpuAffinity>>,
     #[serde(default)]
     pub features: CpuFeatures,
+    /// Time spent polling by a halted vCPU before yielding the host CPU, the
+    /// default of the hypervisor being used if not set.
+    #[serde(default)]
+    pub halt_poll_ns: Option<u32>,
+    /// Let the vCPUs idle in the guest rather than exiting on HLT.
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub idle_poll: bool,
 }
 
-pub const DEFAULT_VCPUS: u8 = 1;
+pub const DEFAULT_VCPUS: u32 = 1;
+// Each vCPU is described by an ACPI device named "CXXX", leaving room for
+// 12 bits worth of identifiers.
+pub const MAX_SUPPORTED_VCPUS: u32 = 4096;
 
 impl Default for CpusConfig {
     fn default() -> Self {
@@ -74,6 +97,9 @@ impl Default for CpusConfig {
             max_phys_bits: DEFAULT_MAX_PHYS_BITS,
             affinity: None,
             features: CpuFeatures::default(),
+            halt_poll_ns: None,
+            #[cfg(target_arch = "x86_64")]
+            idle_poll: false,
         }
     }
 }
@@ -83,6 +109,31 @@ pub fn default_platformconfig_num_pci_segments() -> u16 {
     DEFAULT_NUM_PCI_SEGMENTS
 }
 
+/// Action taken when the guest keeps resetting right after booting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum OnResetLoop {
+    /// Report the reset loop and keep rebooting the VM.
+    #[default]
+    #[serde(rename = "report")]
+    Report,
+    /// Report the reset loop and pause the VM once rebooted.
+    #[serde(rename = "pause")]
+    Pause,
+}
+
+/// Source of the leaves of the CPUID page of an SEV-SNP guest.
+#[cfg(feature = "sev_snp")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SnpCpuidMode {
+    /// The leaves listed by the CPUID page of the IGVM file.
+    #[default]
+    #[serde(rename = "template")]
+    Template,
+    /// All the leaves of the VM.
+    #[serde(rename = "passthrough")]
+    Passthrough,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct PlatformConfig {
     #[serde(default = "default_platformconfig_num_pci_segments")]
@@ -95,12 +146,117 @@ pub struct PlatformConfig {
     pub uuid: Option<String>,
     #[serde(default)]
     pub oem_strings: Option<Vec<String>>,
+    #[serde(default)]
+    pub kbs_uri: Option<String>,
+    /// Socket of the key broker client agent
+    #[serde(default)]
+    pub kbc: Option<PathBuf>,
+    #[serde(default)]
+    pub on_reset_loop: OnResetLoop,
+    /// TOML file describing the quirks of the VFIO devices
+    #[serde(default)]
+    pub vfio_quirks: Option<PathBuf>,
+    /// Expose a VM generation ID to the guest
+    #[serde(default)]
+    pub vmgenid: bool,
     #[cfg(feature = "tdx")]
     #[serde(default)]
     pub tdx: bool,
+    #[cfg(feature = "tdx")]
+    #[serde(default)]
+    pub migtd_pid: Option<u32>,
+    /// Forward the GetQuote requests of the TD to the QGS of the host
+    #[cfg(feature = "tdx")]
+    #[serde(default)]
+    pub quote_generation: bool,
+    /// Socket of the QGS, its default one when unset
+    #[cfg(feature = "tdx")]
+    #[serde(default)]
+    pub qgs_socket: Option<PathBuf>,
     #[cfg(feature = "sev_snp")]
     #[serde(default)]
     pub sev_snp: bool,
+    #[cfg(feature = "sev_snp")]
+    #[serde(default)]
+    pub sev_es: bool,
+    /// KBS resource holding the host data of the SEV-SNP guest
+    #[cfg(feature = "sev_snp")]
+    #[serde(default)]
+    pub kbs_host_data: Option<String>,
+    #[cfg(feature = "sev_snp")]
+    #[serde(default)]
+    pub snp_cpuid: SnpCpuidMode,
+}
+
+impl ApplyLandlock for PlatformConfig {
+    fn apply_landlock(&self, landlock: &mut Landlock) -> LandlockResult<()> {
+        if let Some(kbc) = &self.kbc {
+            landlock.add_rule_with_access(kbc.to_path_buf(), "rw")?;
+        }
+        if let Some(vfio_quirks) = &self.vfio_quirks {
+            landlock.add_rule_with_access(vfio_quirks.to_path_buf(), "r")?;
+        }
+        #[cfg(feature = "tdx")]
+        if self.quote_generation {
+            let qgs_socket = self
+                .qgs_socket
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(crate::tdx_quote::DEFAULT_QGS_SOCKET));
+            landlock.add_rule_with_access(qgs_socket, "rw")?;
+        }
+        Ok(())
+    }
+}
+
+/// Launch parameters of the SEV-SNP guest.
+#[cfg(feature = "sev_snp")]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SevSnpConfig {
+    /// Host data of the launch, hex encoded
+    #[serde(default)]
+    pub host_data: Option<String>,
+    /// File holding the host data, either raw or hex encoded
+    #[serde(default)]
+    pub host_data_file: Option<PathBuf>,
+    /// Guest policy, the hypervisor picking its own when unset
+    #[serde(default)]
+    pub policy: Option<u64>,
+    /// Key signing the ID block
+    #[serde(default)]
+    pub id_block_key: Option<PathBuf>,
+    /// Key signing the ID key
+    #[serde(default)]
+    pub author_key: Option<PathBuf>,
+    /// IGVM file of the SVSM run at VMPL0, along with the firmware
+    #[serde(default)]
+    pub svsm: Option<PathBuf>,
+    /// Leave the unmeasured pages of the firmware with no content
+    /// unaccepted, for the guest to accept them on demand
+    #[serde(default)]
+    pub lazy_unmeasured: bool,
+    /// Certificate table (VCEK, ASK and ARK) returned to the guest along with
+    /// its attestation reports
+    #[serde(default)]
+    pub certificates: Option<PathBuf>,
+}
+
+#[cfg(feature = "sev_snp")]
+impl ApplyLandlock for SevSnpConfig {
+    fn apply_landlock(&self, landlock: &mut Landlock) -> LandlockResult<()> {
+        for path in [
+            &self.host_data_file,
+            &self.id_block_key,
+            &self.author_key,
+            &self.svsm,
+            &self.certificates,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            landlock.add_rule_with_access(path.to_path_buf(), "r")?;
+        }
+        Ok(())
+    }
 }
 
 pub const DEFAULT_PCI_SEGMENT_APERTURE_WEIGHT: u32 = 1;
@@ -119,6 +275,19 @@ pub struct PciSegmentConfig {
     pub mmio64_aperture_weight: u32,
 }
 
+/// NUMA policy binding the memory of a zone to its host NUMA node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum HostNumaPolicy {
+    /// Allocate the memory from the node only, failing when it is exhausted.
+    #[default]
+    #[serde(rename = "bind")]
+    Bind,
+    /// Allocate the memory from the node first, falling back to the other
+    /// nodes when it is exhausted.
+    #[serde(rename = "preferred")]
+    Preferred,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct MemoryZoneConfig {
     pub id: String,
@@ -134,6 +303,8 @@ pub struct MemoryZoneConfig {
     #[serde(default)]
     pub host_numa_node: Option<u32>,
     #[serde(default)]
+    pub host_numa_policy: HostNumaPolicy,
+    #[serde(default)]
     pub hotplug_size: Option<u64>,
     #[serde(default)]
     pub hotplugged_size: Option<u64>,
@@ -184,6 +355,10 @@ pub struct MemoryConfig {
     pub zones: Option<Vec<MemoryZoneConfig>>,
     #[serde(default = "default_memoryconfig_thp")]
     pub thp: bool,
+    #[serde(default)]
+    pub auto_numa: bool,
+    #[serde(default)]
+    pub coredump: bool,
 }
 
 pub const DEFAULT_MEMORY_MB: u64 = 512;
@@ -202,6 +377,8 @@ impl Default for MemoryConfig {
             prefault: false,
             zones: None,
             thp: true,
+            auto_numa: false,
+            coredump: false,
         }
     }
 }
@@ -233,6 +410,15 @@ pub struct DiskConfig {
     #[serde(default)]
     pub readonly: bool,
     #[serde(default)]
+    pub share: bool,
+    #[serde(default)]
+    pub image_type: Option<ImageType>,
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    /// KBS resource holding the encryption key
+    #[serde(default)]
+    pub key_resource: Option<String>,
+    #[serde(default)]
     pub direct: bool,
     #[serde(default)]
     pub iommu: bool,
@@ -261,13 +447,47 @@ pub struct DiskConfig {
     pub serial: Option<String>,
     #[serde(default)]
     pub queue_affinity: Option<Vec<VirtQueueAffinity>>,
+    // Timeout of the requests, in milliseconds.
+    #[serde(default)]
+    pub io_timeout: Option<u64>,
+    #[serde(default)]
+    pub on_io_error: OnIoError,
+    #[serde(default)]
+    pub io_priority: Option<IoPriority>,
+    #[serde(default)]
+    pub io_max: Option<IoMaxConfig>,
+    /// Local cache of the image, when served over HTTP
+    #[serde(default)]
+    pub cache: Option<PathBuf>,
+    /// Writes go to a temporary overlay, leaving the image untouched
+    #[serde(default)]
+    pub transient: bool,
+}
+
+impl DiskConfig {
+    /// Whether the image is served over HTTP rather than being a local file.
+    pub fn is_http(&self) -> bool {
+        self.path
+            .as_ref()
+            .and_then(|path| path.to_str())
+            .is_some_and(block::http::is_url)
+    }
 }
 
 impl ApplyLandlock for DiskConfig {
     fn apply_landlock(&self, landlock: &mut Landlock) -> LandlockResult<()> {
-        if let Some(path) = &self.path {
+        if let Some(path) = self.path.as_ref().filter(|_| !self.is_http()) {
             landlock.add_rule_with_access(path.to_path_buf(), "rw")?;
         }
+        if let Some(cache) = &self.cache {
+            landlock.add_rule_with_access(cache.to_path_buf(), "rw")?;
+        }
+        if self.transient {
+            landlock.add_rule_with_access(std::env::temp_dir(), "rw")?;
+        }
+        if let Some(key_file) = &self.key_file {
+            landlock.add_rule_with_access(key_file.to_path_buf(), "r")?;
+        }
         Ok(())
     }
 }
@@ -327,6 +547,8 @@ pub struct NetConfig {
     pub offload_ufo: bool,
     #[serde(default = "default_netconfig_true")]
     pub offload_csum: bool,
+    #[serde(default)]
+    pub standby: bool,
 }
 
 pub fn default_netconfig_true() -> bool {
@@ -428,10 +650,24 @@ pub struct BalloonConfig {
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
 pub struct PvmemcontrolConfig {}
 
+/// Protocol spoken by a shared file system device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FsProtocol {
+    /// virtio-fs, backed by a vhost-user daemon.
+    #[default]
+    #[serde(rename = "virtiofs")]
+    VirtioFs,
+    /// virtio-9p, backed by a 9P2000.L server.
+    #[serde(rename = "9p")]
+    P9,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct FsConfig {
     pub tag: String,
     pub socket: PathBuf,
+    #[serde(default)]
+    pub protocol: FsProtocol,
     #[serde(default = "default_fsconfig_num_queues")]
     pub num_queues: usize,
     #[serde(default = "default_fsconfig_queue_size")]
@@ -457,6 +693,28 @@ impl ApplyLandlock for FsConfig {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GpuConfig {
+    pub socket: PathBuf,
+    #[serde(default = "default_gpuconfig_queue_size")]
+    pub queue_size: u16,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+}
+
+pub fn default_gpuconfig_queue_size() -> u16 {
+    256
+}
+
+impl ApplyLandlock for GpuConfig {
+    fn apply_landlock(&self, landlock: &mut Landlock) -> LandlockResult<()> {
+        landlock.add_rule_with_access(self.socket.to_path_buf(), "rw")?;
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct PmemConfig {
     pub file: PathBuf,
@@ -487,6 +745,10 @@ pub enum ConsoleOutputMode {
     File,
     Socket,
     Null,
+    /// Listening TCP socket.
+    Tcp,
+    /// Existing host character device or named pipe, read and written.
+    Pipe,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -497,20 +759,90 @@ pub struct ConsoleConfig {
     #[serde(default)]
     pub iommu: bool,
     pub socket: Option<PathBuf>,
+    /// TCP address listened on, a single client at a time being bridged.
+    #[serde(default)]
+    pub tcp: Option<SocketAddr>,
+    /// Log shipper the output is sent to as JSON lines.
+    #[serde(default)]
+    pub log_socket: Option<PathBuf>,
 }
 
 pub fn default_consoleconfig_file() -> Option<PathBuf> {
     None
 }
 
+/// Named pipes the input and output of a device in pipe mode are
+/// respectively read from and written to, when they both exist rather than
+/// `path`.
+pub fn pipe_pair(path: &Path) -> (PathBuf, PathBuf) {
+    let with_suffix = |suffix: &str| {
+        let mut path = path.as_os_str().to_owned();
+        path.push(suffix);
+        PathBuf::from(path)
+    };
+    (with_suffix(".in"), with_suffix(".out"))
+}
+
+// Rules for the paths a device in pipe mode may open.
+fn add_pipe_rules(landlock: &mut Landlock, path: &Path) -> LandlockResult<()> {
+    let (input, output) = pipe_pair(path);
+    landlock.add_rule_with_access(input, "rw")?;
+    landlock.add_rule_with_access(output, "rw")?;
+    Ok(())
+}
+
+/// Additional virtio-console device, each one exposing a single port to the
+/// guest (e.g. /dev/hvc1).
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConsolePortConfig {
+    pub mode: ConsoleOutputMode,
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Unix socket the port is bridged to, e.g. for a guest agent channel.
+    #[serde(default)]
+    pub socket: Option<PathBuf>,
+    /// TCP address the port is bridged to.
+    #[serde(default)]
+    pub tcp: Option<SocketAddr>,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+}
+
+impl ApplyLandlock for ConsolePortConfig {
+    fn apply_landlock(&self, landlock: &mut Landlock) -> LandlockResult<()> {
+        if matches!(self.mode, ConsoleOutputMode::File | ConsoleOutputMode::Pipe) {
+            if let Some(file) = &self.file {
+                landlock.add_rule_with_access(file.to_path_buf(), "rw")?;
+            }
+        }
+        if let (ConsoleOutputMode::Pipe, Some(file)) = (&self.mode, &self.file) {
+            add_pipe_rules(landlock, file)?;
+        }
+        if let Some(socket) = &self.socket {
+            landlock.add_rule_with_access(socket.to_path_buf(), "rw")?;
+        }
+        Ok(())
+    }
+}
+
 impl ApplyLandlock for ConsoleConfig {
     fn apply_landlock(&self, landlock: &mut Landlock) -> LandlockResult<()> {
         if let Some(file) = &self.file {
             landlock.add_rule_with_access(file.to_path_buf(), "rw")?;
         }
+        if let (ConsoleOutputMode::Pipe, Some(file)) = (&self.mode, &self.file) {
+            add_pipe_rules(landlock, file)?;
+        }
         if let Some(socket) = &self.socket {
             landlock.add_rule_with_access(socket.to_path_buf(), "rw")?;
         }
+        if let Some(log_socket) = &self.log_socket {
+            landlock.add_rule_with_access(log_socket.to_path_buf(), "rw")?;
+        }
         Ok(())
     }
 }
@@ -523,6 +855,9 @@ pub struct DebugConsoleConfig {
     pub mode: ConsoleOutputMode,
     /// Optionally dedicated I/O-port, if the default port should not be used.
     pub iobase: Option<u16>,
+    /// Prefix each line with the time elapsed since the VM was created.
+    #[serde(default)]
+    pub timestamps: bool,
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -532,6 +867,7 @@ impl Default for DebugConsoleConfig {
             file: None,
             mode: ConsoleOutputMode::Off,
             iobase: Some(devices::debug_console::DEFAULT_PORT as u16),
+            timestamps: false,
         }
     }
 }
@@ -541,6 +877,9 @@ impl ApplyLandlock for DebugConsoleConfig {
         if let Some(file) = &self.file {
             landlock.add_rule_with_access(file.to_path_buf(), "rw")?;
         }
+        if let (ConsoleOutputMode::Pipe, Some(file)) = (&self.mode, &self.file) {
+            add_pipe_rules(landlock, file)?;
+        }
         Ok(())
     }
 }
@@ -556,6 +895,9 @@ pub struct DeviceConfig {
     pub pci_segment: u16,
     #[serde(default)]
     pub x_nv_gpudirect_clique: Option<u8>,
+    /// Reset method overriding the one of the quirks, if any.
+    #[serde(default)]
+    pub reset: Option<VfioResetMethod>,
 }
 
 impl ApplyLandlock for DeviceConfig {
@@ -570,6 +912,10 @@ impl ApplyLandlock for DeviceConfig {
         let vfio_group_path = "/dev/vfio/".to_owned() + iommu_group_str;
         landlock.add_rule_with_access(vfio_group_path.into(), "rw")?;
 
+        if self.reset.is_some() {
+            landlock.add_rule_with_access(self.path.join("reset_method"), "w")?;
+        }
+
         Ok(())
     }
 }
@@ -614,6 +960,21 @@ impl ApplyLandlock for VdpaConfig {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum VsockForwardDirection {
+    /// Guest connections to `port` are forwarded to the host TCP endpoint `address`.
+    GuestToHost,
+    /// Host connections to the TCP address `address` are forwarded to the guest `port`.
+    HostToGuest,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VsockForwardConfig {
+    pub port: u32,
+    pub address: SocketAddr,
+    pub direction: VsockForwardDirection,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct VsockConfig {
     pub cid: u32,
@@ -624,6 +985,8 @@ pub struct VsockConfig {
     pub id: Option<String>,
     #[serde(default)]
     pub pci_segment: u16,
+    #[serde(default)]
+    pub forwards: Vec<VsockForwardConfig>,
 }
 
 impl ApplyLandlock for VsockConfig {
@@ -643,6 +1006,20 @@ pub struct SgxEpcConfig {
     pub prefault: bool,
 }
 
+#[cfg(target_arch = "aarch64")]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FdtOverlayConfig {
+    pub path: PathBuf,
+}
+
+#[cfg(target_arch = "aarch64")]
+impl ApplyLandlock for FdtOverlayConfig {
+    fn apply_landlock(&self, landlock: &mut Landlock) -> LandlockResult<()> {
+        landlock.add_rule_with_access(self.path.to_path_buf(), "r")?;
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct NumaDistance {
     #[serde(default)]
@@ -656,7 +1033,7 @@ pub struct NumaConfig {
     #[serde(default)]
     pub guest_numa_id: u32,
     #[serde(default)]
-    pub cpus: Option<Vec<u8>>,
+    pub cpus: Option<Vec<u32>>,
     #[serde(default)]
     pub distances: Option<Vec<NumaDistance>>,
     #[serde(default)]
@@ -681,6 +1058,11 @@ pub struct PayloadConfig {
     #[cfg(feature = "igvm")]
     #[serde(default)]
     pub igvm: Option<PathBuf>,
+    /// Compatibility mask of the IGVM platform to load, the first platform
+    /// supporting the isolation of the VM being loaded if unset
+    #[cfg(feature = "igvm")]
+    #[serde(default)]
+    pub igvm_compatibility_mask: Option<u32>,
     #[cfg(feature = "sev_snp")]
     #[serde(default)]
     pub host_data: Option<String>,
@@ -716,6 +1098,8 @@ pub fn default_serial() -> ConsoleConfig {
         mode: ConsoleOutputMode::Null,
         iommu: false,
         socket: None,
+        tcp: None,
+        log_socket: None,
     }
 }
 
@@ -725,6 +1109,8 @@ pub fn default_console() -> ConsoleConfig {
         mode: ConsoleOutputMode::Tty,
         iommu: false,
         socket: None,
+        tcp: None,
+        log_socket: None,
     }
 }
 
@@ -740,6 +1126,50 @@ impl ApplyLandlock for TpmConfig {
     }
 }
 
+/// When the VM is restarted as the guest stops.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RestartPolicy {
+    /// Restart the VM as the guest resets, fails or shuts down.
+    #[serde(rename = "always")]
+    Always,
+    /// Restart the VM as the guest resets or fails, a guest shutting down
+    /// stops the VM.
+    #[default]
+    #[serde(rename = "on-failure")]
+    OnFailure,
+    /// Never restart the VM, a guest resetting stops the VM as well.
+    #[serde(rename = "never")]
+    Never,
+}
+
+pub const DEFAULT_RESTART_POLICY_WINDOW: u64 = 60;
+
+pub fn default_restartpolicyconfig_window() -> u64 {
+    DEFAULT_RESTART_POLICY_WINDOW
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RestartPolicyConfig {
+    #[serde(default)]
+    pub policy: RestartPolicy,
+    /// Number of restarts within the window past which the VM is stopped.
+    #[serde(default)]
+    pub max_reboots: Option<u32>,
+    /// Window of the restart limit, in seconds.
+    #[serde(default = "default_restartpolicyconfig_window")]
+    pub window: u64,
+}
+
+/// Name of the guest published through mDNS.
+#[cfg(feature = "mdns")]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MdnsConfig {
+    /// Host name, published as `<hostname>.local`.
+    pub hostname: String,
+    /// IPv4 address of the guest.
+    pub ip: Ipv4Addr,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct LandlockConfig {
     pub path: PathBuf,
@@ -767,11 +1197,14 @@ pub struct VmConfig {
     pub rng: RngConfig,
     pub balloon: Option<BalloonConfig>,
     pub fs: Option<Vec<FsConfig>>,
+    pub gpu: Option<GpuConfig>,
     pub pmem: Option<Vec<PmemConfig>>,
     #[serde(default = "default_serial")]
     pub serial: ConsoleConfig,
     #[serde(default = "default_console")]
     pub console: ConsoleConfig,
+    #[serde(default)]
+    pub console_ports: Option<Vec<ConsolePortConfig>>,
     #[cfg(target_arch = "x86_64")]
     #[serde(default)]
     pub debug_console: DebugConsoleConfig,
@@ -788,6 +1221,8 @@ pub struct VmConfig {
     pub iommu: bool,
     #[cfg(target_arch = "x86_64")]
     pub sgx_epc: Option<Vec<SgxEpcConfig>>,
+    #[cfg(target_arch = "aarch64")]
+    pub fdt_overlays: Option<Vec<FdtOverlayConfig>>,
     pub numa: Option<Vec<NumaConfig>>,
     #[serde(default)]
     pub watchdog: bool,
@@ -796,7 +1231,15 @@ pub struct VmConfig {
     pub gdb: bool,
     pub pci_segments: Option<Vec<PciSegmentConfig>>,
     pub platform: Option<PlatformConfig>,
+    #[cfg(feature = "sev_snp")]
+    #[serde(default)]
+    pub sev_snp: Option<SevSnpConfig>,
     pub tpm: Option<TpmConfig>,
+    #[serde(default)]
+    pub restart_policy: Option<RestartPolicyConfig>,
+    #[cfg(feature = "mdns")]
+    #[serde(default)]
+    pub mdns: Option<MdnsConfig>,
     // Preserved FDs are the ones that share the same life-time as its holding
     // VmConfig instance, such as FDs for creating TAP devices.
     // Preserved FDs will stay open as long as the holding VmConfig instance is
@@ -833,6 +1276,10 @@ impl VmConfig {
             }
         }
 
+        if let Some(gpu_config) = &self.gpu {
+            gpu_config.apply_landlock(&mut landlock)?;
+        }
+
         if let Some(pmem_configs) = &self.pmem {
             for pmem_config in pmem_configs.iter() {
                 pmem_config.apply_landlock(&mut landlock)?;
@@ -871,6 +1318,12 @@ impl VmConfig {
             vsock_config.apply_landlock(&mut landlock)?;
         }
 
+        if let Some(console_ports) = &self.console_ports {
+            for console_port in console_ports.iter() {
+                console_port.apply_landlock(&mut landlock)?;
+            }
+        }
+
         if let Some(payload) = &self.payload {
             payload.apply_landlock(&mut landlock)?;
         }
@@ -879,6 +1332,22 @@ impl VmConfig {
             tpm_config.apply_landlock(&mut landlock)?;
         }
 
+        #[cfg(target_arch = "aarch64")]
+        if let Some(fdt_overlays) = &self.fdt_overlays {
+            for fdt_overlay in fdt_overlays.iter() {
+                fdt_overlay.apply_landlock(&mut landlock)?;
+            }
+        }
+
+        if let Some(platform_config) = &self.platform {
+            platform_config.apply_landlock(&mut landlock)?;
+        }
+
+        #[cfg(feature = "sev_snp")]
+        if let Some(sev_snp_config) = &self.sev_snp {
+            sev_snp_config.apply_landlock(&mut landlock)?;
+        }
+
         if self.net.is_some() {
             landlock.add_rule_with_access("/dev/net/tun".into(), "rw")?;
         }